@@ -1,6 +1,6 @@
 //! Benchmark on a large video file.
 //!
-//! Expects a copy of [Big Buck Bunny](https://peach.blender.org/download/):
+//! Other than `scan_annexb`, expects a copy of [Big Buck Bunny](https://peach.blender.org/download/):
 //! ```text
 //! $ curl -OL https://download.blender.org/peach/bigbuckbunny_movies/big_buck_bunny_1080p_h264.mov
 //! $ ffmpeg -i big_buck_bunny_1080p_h264.mov -c copy big_buck_bunny_1080p.h264
@@ -23,6 +23,7 @@ use h264_reader::Context;
 use h264_reader::rbsp::RbspDecoder;
 use h264_reader::nal::NalHandler;
 use h264_reader::nal::NalHeader;
+use h264_reader::nal::NalInterest;
 
 struct NullNalHandler {
     start: u64,
@@ -36,8 +37,9 @@ impl NalHandler for NullNalHandler {
         self.start += 1;
     }
 
-    fn push(&mut self, _ctx: &mut Context<Self::Ctx>, _buf: &[u8]) {
+    fn push(&mut self, _ctx: &mut Context<Self::Ctx>, _buf: &[u8]) -> NalInterest {
         self.push += 1;
+        NalInterest::Continue
     }
 
     fn end(&mut self, _ctx: &mut Context<Self::Ctx>) {
@@ -93,7 +95,13 @@ impl NalReader for NullNalReader {
 }
 
 fn h264_reader(c: &mut Criterion) {
-    let mut f = File::open("big_buck_bunny_1080p.h264").expect("file not found");
+    let mut f = match File::open("big_buck_bunny_1080p.h264") {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!("skipping parse_annexb: big_buck_bunny_1080p.h264: {}", e);
+            return;
+        }
+    };
     let len = f.metadata().unwrap().len();
     let mut buf = vec![0; usize::try_from(len).unwrap()];
     f.read(&mut buf[..]).unwrap();
@@ -133,6 +141,40 @@ fn h264_reader(c: &mut Criterion) {
     });
 }
 
+/// Scans synthetic slice data, in which lone zero bytes and `00 00 03` are common, so that the
+/// start code search can be measured without the Big Buck Bunny file.
+fn scan_annexb(c: &mut Criterion) {
+    let mut buf = Vec::new();
+    let mut seed = 1u32;
+    for _ in 0..64 {
+        buf.extend_from_slice(&hex!("00 00 00 01 65"));
+        for _ in 0..64 * 1024 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            match (seed >> 16) % 64 {
+                0..=3 => buf.push(0x00),
+                4 => buf.extend_from_slice(&hex!("00 00 03")),
+                _ => buf.push(0x80 | (seed >> 8) as u8),
+            }
+        }
+        buf.push(0x80);
+    }
+    let mut ctx = Context::default();
+    let mut annexb_reader = AnnexBReader::new(NullNalReader {
+        start: 0,
+        push: 0,
+        end: 0,
+    });
+    let mut group = c.benchmark_group("scan_annexb");
+    group.throughput(Throughput::Bytes(buf.len() as u64));
+    group.bench_function("synthetic", |b| {
+        b.iter(|| {
+            annexb_reader.start(&mut ctx);
+            annexb_reader.push(&mut ctx, &buf[..]);
+            annexb_reader.end_units(&mut ctx);
+        })
+    });
+}
+
 fn parse_nal(c: &mut Criterion) {
     let sps = hex!(
         "64 00 16 AC 1B 1A 80 B0 3D FF FF
@@ -144,5 +186,5 @@ fn parse_nal(c: &mut Criterion) {
     group.bench_function("sps", |b| b.iter(|| SeqParameterSet::from_bytes(&sps[..]).unwrap()));
 }
 
-criterion_group!(benches, h264_reader, parse_nal);
+criterion_group!(benches, h264_reader, scan_annexb, parse_nal);
 criterion_main!(benches);
@@ -20,8 +20,9 @@ impl NalHandler for NalCapture {
         self.buf.clear();
     }
 
-    fn push(&mut self, ctx: &mut h264_reader::Context<Self::Ctx>, buf: &[u8]) {
+    fn push(&mut self, ctx: &mut h264_reader::Context<Self::Ctx>, buf: &[u8]) -> nal::NalInterest {
         self.buf.extend_from_slice(buf);
+        nal::NalInterest::Continue
     }
 
     fn end(&mut self, ctx: &mut h264_reader::Context<Self::Ctx>) {
@@ -97,12 +98,13 @@ impl h264_reader::nal::NalHandler for SliceFuzz {
         });
     }
 
-    fn push(&mut self, ctx: &mut h264_reader::Context<Self::Ctx>, buf: &[u8]) {
+    fn push(&mut self, ctx: &mut h264_reader::Context<Self::Ctx>, buf: &[u8]) -> nal::NalInterest {
         self.current_slice
             .as_mut()
             .unwrap()
             .buf
             .extend_from_slice(buf);
+        nal::NalInterest::Continue
     }
 
     fn end(&mut self, ctx: &mut h264_reader::Context<Self::Ctx>) {
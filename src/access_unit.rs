@@ -0,0 +1,694 @@
+//! Detection of access unit boundaries from the NAL units themselves (_7.4.1.2.3_ and
+//! _7.4.1.2.4_), for streams which don't mark them with access unit delimiters.
+//!
+//! ```
+//! use h264_reader::access_unit::AccessUnitDetector;
+//! use h264_reader::nal::NalHeader;
+//! use h264_reader::Context;
+//! let ctx = Context::default();
+//! let mut detector = AccessUnitDetector::new();
+//! // an access unit delimiter, followed by a filler data NAL unit which belongs to the same
+//! // access unit.
+//! assert!(detector.push(&ctx, NalHeader::new(0x09).unwrap(), &[0xf0]).unwrap());
+//! assert!(!detector.push(&ctx, NalHeader::new(0x0c).unwrap(), &[0xff, 0x80]).unwrap());
+//! ```
+
+use crate::nal::pps::ParamSetId;
+use crate::nal::slice::{PicOrderCountLsb, PictureStructure, SliceHeader, SliceHeaderError};
+use crate::nal::sei::{self, recovery_point::RecoveryPoint, HeaderType};
+use crate::nal::{NalHeader, NalHeaderError, UnitType};
+use crate::poc::{PicOrderCnt, PocError, PocState};
+use crate::rbsp::{decode_nal, BitRead, BitReaderSlice};
+use crate::sprop::SpropError;
+use crate::Context;
+
+/// The values of a primary coded picture's first slice header which distinguish it from the
+/// previous picture, per _7.4.1.2.4_.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PictureKey {
+    frame_num: u16,
+    pic_parameter_set_id: ParamSetId,
+    /// `field_pic_flag` and `bottom_field_flag`.
+    structure: PictureStructure,
+    /// Whether `nal_ref_idc` is `0`; other differences in its value don't start a new picture.
+    non_reference: bool,
+    /// `pic_order_cnt_lsb` and `delta_pic_order_cnt_bottom`, or `delta_pic_order_cnt[0]` and
+    /// `delta_pic_order_cnt[1]`.
+    pic_order_cnt_lsb: Option<PicOrderCountLsb>,
+    /// `IdrPicFlag` and `idr_pic_id`.
+    idr_pic_id: Option<u32>,
+}
+impl PictureKey {
+    fn new(nal_header: NalHeader, header: &SliceHeader) -> Self {
+        PictureKey {
+            frame_num: header.frame_num,
+            pic_parameter_set_id: header.pic_parameter_set_id,
+            structure: header.picture_structure(),
+            non_reference: nal_header.nal_ref_idc() == 0,
+            pic_order_cnt_lsb: header.pic_order_cnt_lsb,
+            idr_pic_id: header.idr_pic_id,
+        }
+    }
+}
+
+/// Groups NAL units into access units, given each NAL unit in decoding order.
+///
+/// The first NAL unit of an access unit is either the first of certain non-VCL NAL units (access
+/// unit delimiters, SPSs, PPSs, SEI and NAL unit types 14 to 18) following the last VCL NAL unit
+/// of a primary coded picture, or the first VCL NAL unit of a new primary coded picture.  The
+/// latter is recognised by comparing its slice header with the first slice header of the previous
+/// primary coded picture.
+///
+/// Slices of redundant coded pictures never start an access unit, and neither do the slices of
+/// MVC and SVC extensions (NAL unit types 20 and 21), which are taken to belong to the access unit
+/// of the preceding base layer or view.
+#[derive(Debug, Default)]
+pub struct AccessUnitDetector {
+    /// Whether any NAL unit has been seen.
+    started: bool,
+    /// Whether the current access unit has a VCL NAL unit of its primary coded picture.
+    has_primary_vcl: bool,
+    /// The first slice of the current (or last) primary coded picture.
+    prev: Option<PictureKey>,
+}
+impl AccessUnitDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns true if the given NAL unit is the first of an access unit, as is the first NAL
+    /// unit given.
+    ///
+    /// `rbsp` is the NAL unit's payload following the header byte, with emulation prevention
+    /// removed; for slices, only enough to include the slice header is needed.  Slice headers
+    /// are parsed with the SPS and PPS from `ctx`, which must be kept up to date with the
+    /// stream's parameter sets.  If a slice header can't be parsed, its error is returned and the
+    /// detector's state is unchanged.
+    pub fn push<Ctx>(&mut self, ctx: &Context<Ctx>, nal_header: NalHeader, rbsp: &[u8]) -> Result<bool, SliceHeaderError> {
+        self.push_header(ctx, nal_header, rbsp).map(|(first, _)| first)
+    }
+
+    /// As for [AccessUnitDetector::push], also returning the slice header of a primary coded
+    /// picture's slice.
+    fn push_header<Ctx>(
+        &mut self,
+        ctx: &Context<Ctx>,
+        nal_header: NalHeader,
+        rbsp: &[u8],
+    ) -> Result<(bool, Option<SliceHeader>), SliceHeaderError> {
+        let mut primary = None;
+        let first = match nal_header.nal_unit_type() {
+            UnitType::AccessUnitDelimiter => {
+                self.has_primary_vcl = false;
+                true
+            },
+            UnitType::SEI
+            | UnitType::SeqParameterSet
+            | UnitType::PicParameterSet
+            | UnitType::PrefixNALUnit
+            | UnitType::SubsetSeqParameterSet
+            | UnitType::DepthParameterSet
+            | UnitType::Reserved(17..=18) => {
+                let first = self.has_primary_vcl || !self.started;
+                self.has_primary_vcl = false;
+                first
+            },
+            UnitType::SliceLayerWithoutPartitioningNonIdr
+            | UnitType::SliceDataPartitionALayer
+            | UnitType::SliceLayerWithoutPartitioningIdr => {
+                let (header, _) = SliceHeader::from_bits(&mut BitReaderSlice::new(rbsp), ctx, nal_header)?;
+                if header.redundant_pic_cnt.map(|c| c > 0).unwrap_or(false) {
+                    !self.started
+                } else {
+                    let key = PictureKey::new(nal_header, &header);
+                    let first = if self.has_primary_vcl {
+                        self.prev.as_ref() != Some(&key)
+                    } else {
+                        // the first VCL NAL unit since a NAL unit which started the access unit.
+                        !self.started
+                    };
+                    self.has_primary_vcl = true;
+                    self.prev = Some(key);
+                    primary = Some(header);
+                    first
+                }
+            },
+            _ => !self.started,
+        };
+        self.started = true;
+        Ok((first, primary))
+    }
+}
+
+#[derive(Debug)]
+pub enum PictureAssemblerError {
+    /// The NAL unit was empty.
+    Empty,
+    NalHeader(NalHeaderError),
+    /// An SPS or PPS NAL unit couldn't be parsed.
+    ParameterSet(SpropError),
+    SliceHeader(SliceHeaderError),
+    Poc(PocError),
+}
+impl From<SliceHeaderError> for PictureAssemblerError {
+    fn from(e: SliceHeaderError) -> Self {
+        PictureAssemblerError::SliceHeader(e)
+    }
+}
+impl From<PocError> for PictureAssemblerError {
+    fn from(e: PocError) -> Self {
+        PictureAssemblerError::Poc(e)
+    }
+}
+
+/// The NAL units of an access unit, along with a summary of its primary coded picture.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodedPicture {
+    /// The access unit's NAL units in decoding order, each the header byte followed by the
+    /// escaped payload.
+    pub nals: Vec<Vec<u8>>,
+    /// The header of the primary coded picture's first slice.
+    pub header: SliceHeader,
+    /// Whether this is an IDR picture.
+    pub idr: bool,
+    /// Whether this is a frame or a single field.
+    pub structure: PictureStructure,
+    pub pic_order_cnt: PicOrderCnt,
+}
+impl CodedPicture {
+    /// Whether the picture is used for reference (its `nal_ref_idc` is non-zero).
+    pub fn is_reference(&self) -> bool {
+        self.header.dec_ref_pic_marking.is_some()
+    }
+
+    /// Classifies the access unit as a place where decoding may (or may not) begin.
+    ///
+    /// SEI and parameter set NAL units which can't be parsed are ignored.
+    pub fn random_access(&self) -> RandomAccess {
+        let mut recovery_point = None;
+        let mut sps_ids = Vec::new();
+        let mut pps_ids = Vec::new();
+        for nal in &self.nals {
+            let nal_header = match nal.first().map(|&b| NalHeader::new(b)) {
+                Some(Ok(h)) => h,
+                _ => continue,
+            };
+            let rbsp = decode_nal(&nal[1..]);
+            match nal_header.nal_unit_type() {
+                UnitType::SEI if recovery_point.is_none() => {
+                    recovery_point = sei::messages(&rbsp)
+                        .map_while(Result::ok)
+                        .filter(|m| m.payload_type == HeaderType::RecoveryPoint)
+                        .find_map(|m| RecoveryPoint::read(m.payload).ok());
+                },
+                UnitType::SeqParameterSet => {
+                    // seq_parameter_set_id follows profile_idc, the constraint flags and
+                    // level_idc.
+                    let mut r = BitReaderSlice::new(rbsp.get(3..).unwrap_or_default());
+                    sps_ids.extend(r.read_ue_named("seq_parameter_set_id").ok());
+                },
+                UnitType::PicParameterSet => {
+                    let mut r = BitReaderSlice::new(&rbsp);
+                    if let (Ok(pps), Ok(sps)) = (r.read_ue_named("pic_parameter_set_id"), r.read_ue_named("seq_parameter_set_id")) {
+                        pps_ids.push((pps, sps));
+                    }
+                },
+                _ => {},
+            }
+        }
+        let kind = if self.idr {
+            RandomAccessKind::Idr
+        } else if let Some(recovery_point) = recovery_point {
+            RandomAccessKind::RecoveryPoint(recovery_point)
+        } else {
+            RandomAccessKind::None
+        };
+        let parameter_sets_present = pps_ids
+            .iter()
+            .any(|&(pps, sps)| pps == u32::from(self.header.pic_parameter_set_id.id()) && sps_ids.contains(&sps));
+        RandomAccess { kind, parameter_sets_present }
+    }
+}
+
+/// Whether decoding may begin at an access unit; see [CodedPicture::random_access].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RandomAccessKind {
+    /// An IDR picture, from which all pictures decode exactly.
+    Idr,
+    /// A picture with a recovery point SEI message, from which pictures are output correctly (or
+    /// approximately, if `exact_match_flag` is unset) after `recovery_frame_cnt` frames.  This is
+    /// how streams with gradual decoding refresh, or open GOPs, mark their random access points.
+    RecoveryPoint(RecoveryPoint),
+    /// Not a random access point.
+    None,
+}
+
+/// The classification of an access unit by [CodedPicture::random_access].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RandomAccess {
+    pub kind: RandomAccessKind,
+    /// Whether the access unit includes the PPS of its slices and the SPS that PPS refers to,
+    /// so that decoding may begin here without parameter sets from elsewhere (as from
+    /// out-of-band `sprop-parameter-sets` or an `avcC` box).
+    pub parameter_sets_present: bool,
+}
+impl RandomAccess {
+    /// Whether decoding may begin here, given the parameter sets.
+    pub fn is_random_access_point(&self) -> bool {
+        self.kind != RandomAccessKind::None
+    }
+}
+
+/// Groups a stream's NAL units into [CodedPicture]s, one per access unit.
+///
+/// SPS and PPS NAL units are installed in the given [Context] as they are pushed, so that the
+/// slices which follow can be parsed.  The NAL units of an access unit without a primary coded
+/// picture (say, one with only parameter sets) are kept with the next picture.
+#[derive(Debug, Default)]
+pub struct PictureAssembler {
+    detector: AccessUnitDetector,
+    poc: PocState,
+    nals: Vec<Vec<u8>>,
+    /// The first slice header and picture order count of the access unit being assembled.
+    primary: Option<(SliceHeader, PicOrderCnt)>,
+    /// Whether the rest of the current access unit is being discarded, its first slice having
+    /// been rejected.
+    skipping: bool,
+}
+impl PictureAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the given NAL unit (the header byte followed by the escaped payload), returning the
+    /// previous picture if this NAL unit starts a new access unit.
+    ///
+    /// A NAL unit which can't be parsed is discarded, and its error returned.  If the first slice
+    /// of a picture is rejected (say, because its picture order count overflows), the rest of its
+    /// access unit is discarded too, and the previous picture is returned once the next access
+    /// unit starts.
+    pub fn push<Ctx>(&mut self, ctx: &mut Context<Ctx>, nal: &[u8]) -> Result<Option<CodedPicture>, PictureAssemblerError> {
+        let nal_header = NalHeader::new(*nal.first().ok_or(PictureAssemblerError::Empty)?)
+            .map_err(PictureAssemblerError::NalHeader)?;
+        let rbsp = decode_nal(&nal[1..]);
+        match nal_header.nal_unit_type() {
+            UnitType::SeqParameterSet | UnitType::PicParameterSet => {
+                ctx.put_param_set_nal(nal).map_err(PictureAssemblerError::ParameterSet)?;
+            },
+            _ => {},
+        }
+        let (first, header) = self.detector.push_header(ctx, nal_header, &rbsp)?;
+        if first {
+            self.skipping = false;
+        } else if self.skipping {
+            return Ok(None);
+        }
+        // a new picture's order count is found before the previous picture is completed, so
+        // that the previous picture isn't lost if it fails.
+        let primary = match header {
+            Some(header) if first || self.primary.is_none() => {
+                match self.pic_order_cnt(ctx, &header) {
+                    Ok(pic_order_cnt) => Some((header, pic_order_cnt)),
+                    Err(e) => {
+                        self.skipping = true;
+                        return Err(e);
+                    },
+                }
+            },
+            _ => None,
+        };
+        let mut completed = None;
+        if first && self.primary.is_some() {
+            completed = self.flush();
+        }
+        if primary.is_some() {
+            self.primary = primary;
+        }
+        self.nals.push(nal.to_vec());
+        Ok(completed)
+    }
+
+    fn pic_order_cnt<Ctx>(&mut self, ctx: &Context<Ctx>, header: &SliceHeader) -> Result<PicOrderCnt, PictureAssemblerError> {
+        let (_, sps) = ctx.activation(header.pic_parameter_set_id).map_err(SliceHeaderError::from)?;
+        self.poc.compute(sps, header).map_err(PictureAssemblerError::Poc)
+    }
+
+    /// Returns the picture being assembled, if any, as at the end of the stream.
+    pub fn flush(&mut self) -> Option<CodedPicture> {
+        let (header, pic_order_cnt) = self.primary.take()?;
+        Some(CodedPicture {
+            nals: std::mem::take(&mut self.nals),
+            idr: header.idr_pic_id.is_some(),
+            structure: header.picture_structure(),
+            header,
+            pic_order_cnt,
+        })
+    }
+}
+
+/// A gap in `frame_num` between a picture and the preceding reference picture (_7.4.3_); see
+/// [FrameNumGapDetector].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameNumGap {
+    /// _PrevRefFrameNum_: the `frame_num` of the preceding reference picture.
+    pub prev_ref_frame_num: u16,
+    /// The `frame_num` of the picture following the gap.
+    pub frame_num: u16,
+    /// The number of frames missing between the two.
+    pub missing_frames: u32,
+    /// The SPS's `gaps_in_frame_num_value_allowed_flag`.  If set, the encoder may have left the
+    /// gap intentionally (as when dropping frames for temporal scalability); otherwise, the
+    /// missing frames must have been lost.
+    pub allowed: bool,
+}
+
+/// Told of each gap in `frame_num`; see [FrameNumGapDetector::set_handler].
+pub type FrameNumGapHandler<Ctx> = Box<dyn FnMut(&mut Context<Ctx>, &FrameNumGap)>;
+
+/// Detects gaps in `frame_num` (_7.4.3_), given the first slice header of each primary coded
+/// picture in decoding order (such as [CodedPicture::header]).
+///
+/// `frame_num` counts reference frames, so that each picture's `frame_num` is either that of the
+/// preceding reference picture (for the second field of a pair) or one more than it, modulo
+/// _MaxFrameNum_.  A larger step shows that reference frames are missing, through loss or (with
+/// `gaps_in_frame_num_value_allowed_flag`) by the encoder's choice.
+pub struct FrameNumGapDetector<Ctx> {
+    handler: Option<FrameNumGapHandler<Ctx>>,
+    /// _PrevRefFrameNum_, or `None` before the first picture.
+    prev_ref_frame_num: Option<u16>,
+}
+impl<Ctx> Default for FrameNumGapDetector<Ctx> {
+    fn default() -> Self {
+        FrameNumGapDetector {
+            handler: None,
+            prev_ref_frame_num: None,
+        }
+    }
+}
+impl<Ctx> FrameNumGapDetector<Ctx> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets a handler to be told of each gap found.
+    pub fn set_handler(&mut self, handler: Option<FrameNumGapHandler<Ctx>>) {
+        self.handler = handler;
+    }
+
+    /// Forgets the preceding reference picture, as when the stream is restarted.
+    pub fn reset(&mut self) {
+        self.prev_ref_frame_num = None;
+    }
+
+    /// Checks the `frame_num` of the given picture's first slice header against that of the
+    /// preceding reference picture, calling the handler if frames are missing, and returns the
+    /// gap found, if any.
+    ///
+    /// The SPS is taken from `ctx` as for [SliceHeader::from_bits].  After a gap, the missing
+    /// frames are taken to have been reference frames, so that the gap is reported only once.
+    pub fn push(&mut self, ctx: &mut Context<Ctx>, header: &SliceHeader) -> Result<Option<FrameNumGap>, SliceHeaderError> {
+        let (_, sps) = ctx.activation(header.pic_parameter_set_id).map_err(SliceHeaderError::from)?;
+        let max_frame_num = sps.max_frame_num()
+            .ok_or(SliceHeaderError::Log2MaxFrameNumMinus4OutOfRange(sps.log2_max_frame_num_minus4))?;
+        let allowed = sps.gaps_in_frame_num_value_allowed_flag;
+        // the frame_num preceding this picture's, which is that of the last missing frame after
+        // a gap.
+        let preceding = ((u32::from(header.frame_num) + max_frame_num - 1) % max_frame_num) as u16;
+        let mut gap = None;
+        if let (Some(prev_ref_frame_num), None) = (self.prev_ref_frame_num, header.idr_pic_id) {
+            let step = (u32::from(header.frame_num) + max_frame_num - u32::from(prev_ref_frame_num)) % max_frame_num;
+            if step > 1 {
+                gap = Some(FrameNumGap {
+                    prev_ref_frame_num,
+                    frame_num: header.frame_num,
+                    missing_frames: step - 1,
+                    allowed,
+                });
+                self.prev_ref_frame_num = Some(preceding);
+            }
+        }
+        self.prev_ref_frame_num = match header.dec_ref_pic_marking {
+            Some(ref marking) if marking.has_mmco5() => Some(0),
+            Some(_) => Some(header.frame_num),
+            None => self.prev_ref_frame_num.or(Some(preceding)),
+        };
+        if let (Some(ref gap), Some(ref mut handler)) = (gap, &mut self.handler) {
+            handler(ctx, gap);
+        }
+        Ok(gap)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nal::pps::PpsBuilder;
+    use crate::nal::sps::{Level, PicOrderCntType, Profile, SpsBuilder};
+    use crate::rbsp::{reencode_nal, BitWrite, BitWriterVec};
+
+    /// An I slice with the given `nal_ref_idc`, `first_mb_in_slice`, `frame_num`, `idr_pic_id`
+    /// and `pic_order_cnt_lsb`, given a PPS with CAVLC and deblocking filter control.
+    fn slice(nal_ref_idc: u8, first_mb_in_slice: u32, frame_num: u32, idr_pic_id: Option<u32>, lsb: u32) -> (NalHeader, Vec<u8>) {
+        slice_with_lsb(nal_ref_idc, first_mb_in_slice, frame_num, idr_pic_id, Some(lsb))
+    }
+
+    /// As for `slice`, but `pic_order_cnt_lsb` is omitted (as with `pic_order_cnt_type` 1 and
+    /// `delta_pic_order_always_zero_flag`) if `None`.
+    fn slice_with_lsb(nal_ref_idc: u8, first_mb_in_slice: u32, frame_num: u32, idr_pic_id: Option<u32>, lsb: Option<u32>) -> (NalHeader, Vec<u8>) {
+        let nal_unit_type = if idr_pic_id.is_some() { 5 } else { 1 };
+        let mut w = BitWriterVec::new();
+        w.write_ue_named("first_mb_in_slice", first_mb_in_slice).unwrap();
+        w.write_ue_named("slice_type", 7).unwrap();
+        w.write_ue_named("pic_parameter_set_id", 0).unwrap();
+        w.write_u_named("frame_num", 4, frame_num).unwrap();
+        if let Some(idr_pic_id) = idr_pic_id {
+            w.write_ue_named("idr_pic_id", idr_pic_id).unwrap();
+        }
+        if let Some(lsb) = lsb {
+            w.write_u_named("pic_order_cnt_lsb", 4, lsb).unwrap();
+        }
+        if nal_ref_idc != 0 {
+            if idr_pic_id.is_some() {
+                w.write_bool_named("no_output_of_prior_pics_flag", false).unwrap();
+                w.write_bool_named("long_term_reference_flag", false).unwrap();
+            } else {
+                w.write_bool_named("adaptive_ref_pic_marking_mode_flag", false).unwrap();
+            }
+        }
+        w.write_se_named("slice_qp_delta", 0).unwrap();
+        w.write_ue_named("disable_deblocking_filter_idc", 1).unwrap();
+        w.write_rbsp_trailing_bits().unwrap();
+        (NalHeader::new(nal_ref_idc << 5 | nal_unit_type).unwrap(), w.into_inner())
+    }
+
+    #[test]
+    fn detect() {
+        let mut sps = SpsBuilder::new(Profile::Main, Level::L3)
+            .dimensions(640, 480)
+            .build()
+            .unwrap();
+        sps.log2_max_frame_num_minus4 = 0;
+        sps.pic_order_cnt = PicOrderCntType::TypeZero { log2_max_pic_order_cnt_lsb_minus4: 0 };
+        let pps = PpsBuilder::for_sps(&sps).cabac(false).build().unwrap();
+        let mut ctx = Context::default();
+        ctx.put_seq_param_set(sps.clone());
+        ctx.put_pic_param_set(pps.clone());
+        let sps_nal = (NalHeader::new(0x67).unwrap(), sps.to_bytes().unwrap());
+        let pps_nal = (NalHeader::new(0x68).unwrap(), pps.to_bytes(&sps).unwrap());
+        let sei_nal = (NalHeader::new(0x06).unwrap(), vec![0x05, 0x00, 0x80]);
+        let stream = [
+            (sps_nal.clone(), true),
+            (pps_nal.clone(), false),
+            (slice(3, 0, 0, Some(0), 0), false),
+            (slice(3, 600, 0, Some(0), 0), false),
+            // a new picture: frame_num differs.
+            (slice(2, 0, 1, None, 4), true),
+            (slice(2, 600, 1, None, 4), false),
+            // non-reference pictures with the same frame_num, differing in pic_order_cnt_lsb.
+            (slice(0, 0, 2, None, 2), true),
+            (slice(0, 0, 2, None, 3), true),
+            // a prefix of non-VCL NAL units starts the access unit.
+            (sei_nal, true),
+            (slice(2, 0, 2, None, 8), false),
+            // nal_ref_idc differs, but both are non-zero.
+            (slice(1, 600, 2, None, 8), false),
+            (sps_nal, true),
+            (pps_nal, false),
+            // consecutive IDR pictures are distinguished by idr_pic_id.
+            (slice(3, 0, 0, Some(1), 0), false),
+            (slice(3, 0, 0, Some(2), 0), true),
+            ((NalHeader::new(0x0a).unwrap(), vec![]), false),
+        ];
+        let mut detector = AccessUnitDetector::new();
+        for (i, &((header, ref rbsp), expected)) in stream.iter().enumerate() {
+            assert_eq!(detector.push(&ctx, header, rbsp).unwrap(), expected, "NAL unit {}", i);
+        }
+    }
+
+    #[test]
+    fn assemble() {
+        let mut sps = SpsBuilder::new(Profile::Main, Level::L3)
+            .dimensions(640, 480)
+            .build()
+            .unwrap();
+        sps.log2_max_frame_num_minus4 = 0;
+        sps.pic_order_cnt = PicOrderCntType::TypeZero { log2_max_pic_order_cnt_lsb_minus4: 0 };
+        let pps = PpsBuilder::for_sps(&sps).cabac(false).build().unwrap();
+        let nal = |(header, rbsp): (NalHeader, Vec<u8>)| reencode_nal(header, &rbsp);
+        let stream = [
+            nal((NalHeader::new(0x67).unwrap(), sps.to_bytes().unwrap())),
+            nal((NalHeader::new(0x68).unwrap(), pps.to_bytes(&sps).unwrap())),
+            nal(slice(3, 0, 0, Some(0), 0)),
+            nal(slice(3, 600, 0, Some(0), 0)),
+            nal((NalHeader::new(0x06).unwrap(), vec![0x05, 0x00, 0x80])),
+            nal(slice(2, 0, 1, None, 4)),
+            nal(slice(0, 0, 2, None, 2)),
+        ];
+        let mut ctx = Context::default();
+        let mut assembler = PictureAssembler::new();
+        let mut pictures = Vec::new();
+        for n in &stream {
+            pictures.extend(assembler.push(&mut ctx, n).unwrap());
+        }
+        pictures.extend(assembler.flush());
+        assert!(assembler.flush().is_none());
+        let summary: Vec<_> = pictures
+            .iter()
+            .map(|p| (p.nals.len(), p.idr, p.is_reference(), p.structure, p.pic_order_cnt.pic_order_cnt()))
+            .collect();
+        assert_eq!(summary, vec![
+            (4, true, true, PictureStructure::Frame, 0),
+            (2, false, true, PictureStructure::Frame, 4),
+            (1, false, false, PictureStructure::Frame, 2),
+        ]);
+        assert_eq!(pictures[0].nals[..2], stream[..2]);
+        assert_eq!(pictures[1].header.frame_num, 1);
+    }
+
+    #[test]
+    fn assemble_poc_error() {
+        let mut sps = SpsBuilder::new(Profile::Main, Level::L3)
+            .dimensions(640, 480)
+            .build()
+            .unwrap();
+        sps.log2_max_frame_num_minus4 = 0;
+        sps.pic_order_cnt = PicOrderCntType::TypeOne {
+            delta_pic_order_always_zero_flag: true,
+            offset_for_non_ref_pic: 0,
+            offset_for_top_to_bottom_field: 0,
+            offsets_for_ref_frame: vec![i32::MAX],
+        };
+        let pps = PpsBuilder::for_sps(&sps).cabac(false).build().unwrap();
+        let mut ctx = Context::default();
+        ctx.put_seq_param_set(sps);
+        ctx.put_pic_param_set(pps);
+        let nal = |(header, rbsp): (NalHeader, Vec<u8>)| reencode_nal(header, &rbsp);
+        let mut assembler = PictureAssembler::new();
+        assert_eq!(assembler.push(&mut ctx, &nal(slice_with_lsb(3, 0, 0, Some(0), None))).unwrap(), None);
+        let idr = assembler.push(&mut ctx, &nal(slice_with_lsb(2, 0, 1, None, None))).unwrap().unwrap();
+        assert!(idr.idr);
+
+        // absFrameNum 2 gives an expected picture order count of 2 * i32::MAX, which overflows.
+        assert!(matches!(
+            assembler.push(&mut ctx, &nal(slice_with_lsb(2, 0, 2, None, None))),
+            Err(PictureAssemblerError::Poc(PocError::Overflow))
+        ));
+        // the rest of that picture is discarded, and the previous picture is kept intact.
+        assert_eq!(assembler.push(&mut ctx, &nal(slice_with_lsb(2, 600, 2, None, None))).unwrap(), None);
+        let previous = assembler.push(&mut ctx, &nal(slice_with_lsb(3, 0, 0, Some(1), None))).unwrap().unwrap();
+        assert_eq!(previous.nals.len(), 1);
+        assert_eq!(previous.header.frame_num, 1);
+        assert_eq!(previous.pic_order_cnt.pic_order_cnt(), i32::MAX);
+        assert_eq!(assembler.flush().unwrap().header.idr_pic_id, Some(1));
+    }
+
+    #[test]
+    fn random_access() {
+        let mut sps = SpsBuilder::new(Profile::Main, Level::L3)
+            .dimensions(640, 480)
+            .build()
+            .unwrap();
+        sps.log2_max_frame_num_minus4 = 0;
+        sps.pic_order_cnt = PicOrderCntType::TypeZero { log2_max_pic_order_cnt_lsb_minus4: 0 };
+        let pps = PpsBuilder::for_sps(&sps).cabac(false).build().unwrap();
+        let nal = |(header, rbsp): (NalHeader, Vec<u8>)| reencode_nal(header, &rbsp);
+        let sps_nal = nal((NalHeader::new(0x67).unwrap(), sps.to_bytes().unwrap()));
+        let pps_nal = nal((NalHeader::new(0x68).unwrap(), pps.to_bytes(&sps).unwrap()));
+        // a recovery point SEI message with recovery_frame_cnt 3 and exact_match_flag set.
+        let recovery_point = nal((NalHeader::new(0x06).unwrap(), vec![0x06, 0x02, 0b0010_0100, 0b0010_0000, 0x80]));
+        let stream = [
+            sps_nal.clone(),
+            pps_nal.clone(),
+            nal(slice(3, 0, 0, Some(0), 0)),
+            recovery_point.clone(),
+            nal(slice(2, 0, 1, None, 4)),
+            nal(slice(2, 0, 2, None, 6)),
+            sps_nal,
+            pps_nal,
+            recovery_point,
+            nal(slice(2, 0, 3, None, 8)),
+        ];
+        let mut ctx = Context::default();
+        let mut assembler = PictureAssembler::new();
+        let mut pictures = Vec::new();
+        for n in &stream {
+            pictures.extend(assembler.push(&mut ctx, n).unwrap());
+        }
+        pictures.extend(assembler.flush());
+        let recovery_point = RandomAccessKind::RecoveryPoint(RecoveryPoint {
+            recovery_frame_cnt: 3,
+            exact_match_flag: true,
+            broken_link_flag: false,
+            changing_slice_group_idc: 0,
+        });
+        let classified: Vec<_> = pictures.iter().map(CodedPicture::random_access).collect();
+        assert_eq!(classified, vec![
+            RandomAccess { kind: RandomAccessKind::Idr, parameter_sets_present: true },
+            RandomAccess { kind: recovery_point, parameter_sets_present: false },
+            RandomAccess { kind: RandomAccessKind::None, parameter_sets_present: false },
+            RandomAccess { kind: recovery_point, parameter_sets_present: true },
+        ]);
+        assert!(classified[1].is_random_access_point());
+        assert!(!classified[2].is_random_access_point());
+    }
+
+    #[test]
+    fn frame_num_gaps() {
+        let mut sps = SpsBuilder::new(Profile::Main, Level::L3)
+            .dimensions(640, 480)
+            .build()
+            .unwrap();
+        sps.log2_max_frame_num_minus4 = 0;
+        sps.pic_order_cnt = PicOrderCntType::TypeZero { log2_max_pic_order_cnt_lsb_minus4: 0 };
+        let pps = PpsBuilder::for_sps(&sps).cabac(false).build().unwrap();
+        let nal = |(header, rbsp): (NalHeader, Vec<u8>)| reencode_nal(header, &rbsp);
+        let mut stream = vec![
+            nal((NalHeader::new(0x67).unwrap(), sps.to_bytes().unwrap())),
+            nal((NalHeader::new(0x68).unwrap(), pps.to_bytes(&sps).unwrap())),
+            nal(slice(3, 0, 0, Some(0), 0)),
+        ];
+        // (nal_ref_idc, frame_num): frames 3 and 4 are missing, then 7 to 14, then (after
+        // wrapping around MaxFrameNum) 0.
+        let pictures = [(2, 1), (0, 2), (2, 2), (2, 5), (0, 6), (2, 6), (2, 15), (2, 1)];
+        for (i, &(nal_ref_idc, frame_num)) in pictures.iter().enumerate() {
+            stream.push(nal(slice(nal_ref_idc, 0, frame_num, None, (2 * i as u32 + 2) % 16)));
+        }
+        let mut ctx = Context::new(Vec::new());
+        let mut assembler = PictureAssembler::new();
+        let mut detector = FrameNumGapDetector::new();
+        detector.set_handler(Some(Box::new(|ctx: &mut Context<Vec<u32>>, gap: &FrameNumGap| {
+            ctx.user_context.push(gap.missing_frames)
+        })));
+        let mut gaps = Vec::new();
+        for n in &stream {
+            if let Some(picture) = assembler.push(&mut ctx, n).unwrap() {
+                gaps.extend(detector.push(&mut ctx, &picture.header).unwrap());
+            }
+        }
+        let picture = assembler.flush().unwrap();
+        gaps.extend(detector.push(&mut ctx, &picture.header).unwrap());
+        assert_eq!(gaps[0], FrameNumGap { prev_ref_frame_num: 2, frame_num: 5, missing_frames: 2, allowed: false });
+        assert_eq!(gaps[2], FrameNumGap { prev_ref_frame_num: 15, frame_num: 1, missing_frames: 1, allowed: false });
+        assert_eq!(ctx.user_context, vec![2, 8, 1]);
+    }
+}
@@ -1,9 +1,14 @@
 //! A reader for the NAL Unit framing format defined in _ITU-T Recommendation H.264 - Annex B_,
 //! as used when H264 data is embedded in an MPEG2 Transport Stream
 
-use crate::Context;
+use crate::{Context, ErrorAction, ErrorKind, Severity};
+use crate::nal::{NalHeader, RefNal, UnitType};
 use memchr;
 use log::*;
+use std::io;
+
+/// Buffer capacity which is reasonable for most uses of [`AnnexBReader::read_from`].
+pub const DEFAULT_READ_CAPACITY: usize = 64 * 1024;
 
 #[derive(Debug)]
 enum ParseState {
@@ -60,14 +65,37 @@ pub trait NalReader {
     fn start(&mut self, ctx: &mut Context<Self::Ctx>);
     fn push(&mut self, ctx: &mut Context<Self::Ctx>, buf: &[u8]);
     fn end(&mut self, ctx: &mut Context<Self::Ctx>);
+
+    /// Called instead of `end()` when the current NAL unit is found to be damaged (see
+    /// [AnnexBReader::set_resync_handler]).  Readers which buffer data should discard it.  The
+    /// default implementation does nothing.
+    fn reset(&mut self, _ctx: &mut Context<Self::Ctx>) {
+    }
 }
 
+/// Told the number of bytes discarded while resynchronising; see
+/// [AnnexBReader::set_resync_handler].
+pub type ResyncHandler<Ctx> = Box<dyn FnMut(&mut Context<Ctx>, usize)>;
+
 pub struct AnnexBReader<R, Ctx>
     where
         R: NalReader<Ctx=Ctx>
 {
     state: ParseState,
     nal_reader: R,
+    resync_handler: Option<ResyncHandler<Ctx>>,
+    /// number of 0x00 bytes seen while looking for a start code
+    start_zeros: usize,
+    /// number of bytes discarded since the last start code
+    discarded: usize,
+    /// offset of the first of the `discarded` bytes
+    discarded_offset: u64,
+    /// number of bytes pushed since `start()`
+    offset: u64,
+    /// offset, length (so far) and start code zero bytes of the current NAL unit
+    unit_offset: u64,
+    unit_len: u64,
+    unit_zeros: usize,
 }
 impl<R, Ctx> AnnexBReader<R, Ctx>
     where
@@ -77,22 +105,67 @@ impl<R, Ctx> AnnexBReader<R, Ctx>
         AnnexBReader {
             state: ParseState::End,
             nal_reader,
+            resync_handler: None,
+            start_zeros: 0,
+            discarded: 0,
+            discarded_offset: 0,
+            offset: 0,
+            unit_offset: 0,
+            unit_len: 0,
+            unit_zeros: 0,
         }
     }
 
+    /// Enables recovery from malformed data, as may result from packet loss.
+    ///
+    /// Without a resync handler, bytes which can't begin a start code are skipped (and reported
+    /// via [Context::report_error](../struct.Context.html#method.report_error) as
+    /// [AnnexBError::DiscardedBytes] once the next start code is found), but malformed data
+    /// within a NAL unit (a run of `0x00` bytes not followed by a start code) stops all further
+    /// parsing until the next call to `start()`.
+    ///
+    /// With a resync handler, the input is instead scanned forward from malformed data to the
+    /// next start code, from which parsing continues.  Once that start code is found (or
+    /// `end_units()` is called), the handler is given the number of bytes skipped since the damage
+    /// was detected.
+    ///
+    /// Either way, the NAL unit containing malformed data is reported as
+    /// [AnnexBError::UnexpectedByte], and is dropped (see [NalReader::reset]) if the error
+    /// handler returns `ErrorAction::SkipNal` (the default), or ended at the damage if it returns
+    /// `ErrorAction::Continue`.
+    pub fn set_resync_handler(&mut self, handler: Option<ResyncHandler<Ctx>>) {
+        self.resync_handler = handler;
+    }
+
+    pub fn into_nal_reader(self) -> R {
+        self.nal_reader
+    }
+
     pub fn start(&mut self, ctx: &mut Context<Ctx>) {
         if self.state.in_unit() {
             // TODO: or reset()?
-            self.nal_reader.end(ctx);
+            self.end_unit(ctx);
         }
         self.to(ParseState::Start);
+        self.start_zeros = 0;
+        self.discarded = 0;
+        self.offset = 0;
     }
 
     pub fn push(&mut self, ctx: &mut Context<Ctx>, buf: &[u8]) {
+        if ctx.is_aborted() {
+            return;
+        }
         let mut unit_start: Option<isize> = self.state.end_backtrack_bytes().map(|v| -(v as isize));
+        let base = self.offset;
+        self.offset += buf.len() as u64;
 
         let mut i = 0;
         while i < buf.len() {
+            if ctx.is_aborted() {
+                // the error handler aborted while this reader or its NalReader was reporting
+                return;
+            }
             let b = buf[i];
             match self.state {
                 ParseState::End => {
@@ -103,40 +176,52 @@ impl<R, Ctx> AnnexBReader<R, Ctx>
                 ParseState::Error => return,
                 ParseState::Start => {
                     match b {
-                        0x00 => self.to(ParseState::StartOneZero),
-                        _ => self.err(b),
+                        0x00 => {
+                            self.start_zeros = 1;
+                            self.to(ParseState::StartOneZero)
+                        },
+                        _ => self.err(base + i as u64),
                     }
                 },
                 ParseState::StartOneZero => {
                     match b {
-                        0x00 => self.to(ParseState::StartTwoZero),
-                        _ => self.err(b),
+                        0x00 => {
+                            self.start_zeros += 1;
+                            self.to(ParseState::StartTwoZero)
+                        },
+                        _ => self.err(base + i as u64),
                     }
                 },
                 ParseState::StartTwoZero => {
                     match b {
-                        0x00 => (),   // keep ignoring further 0x00 bytes
+                        0x00 => self.start_zeros += 1,   // keep ignoring further 0x00 bytes
                         0x01 => {
+                            self.report_discarded(ctx);
+                            if ctx.is_aborted() {
+                                return;
+                            }
+                            self.unit_zeros = self.start_zeros;
+                            self.start_zeros = 0;
                             self.to(ParseState::InUnit);
                             unit_start = Some(i as isize + 1);
-                            self.nal_reader.start(ctx);
+                            self.unit_offset = base + i as u64 + 1;
+                            self.start_unit(ctx);
                         },
-                        _ => self.err(b),
+                        _ => self.err(base + i as u64),
                     }
                 },
                 ParseState::InUnitStart => {
-                    self.nal_reader.start(ctx);
+                    self.start_unit(ctx);
                     match b {
                         0x00 => self.to(ParseState::InUnitOneZero),
                         _ => self.to(ParseState::InUnit),
                     }
                 },
                 ParseState::InUnit => {
-                    let remaining = &buf[i..];
-                    match memchr::memchr(0x00, remaining) {
-                        Some(pos) => {
+                    match find_unit_end(&buf[i..]) {
+                        Some(p) => {
                             self.to(ParseState::InUnitOneZero);
-                            i += pos;
+                            i += p;
                         },
                         None => {
                             // skip to end
@@ -157,11 +242,11 @@ impl<R, Ctx> AnnexBReader<R, Ctx>
                     match b {
                         0x00 => self.to(ParseState::InUnitThreeZero),
                         0x01 => {
-                            if unit_start.is_some() && (unit_start.unwrap() > 0 || i > 2) {
-                                self.emit(ctx, buf, unit_start, i - 2);
-                            }
-                            self.nal_reader.end(ctx);
+                            self.emit(ctx, buf, unit_start, i, 2);
+                            self.end_unit(ctx);
                             unit_start = Some(i as isize + 1);
+                            self.unit_offset = base + i as u64 + 1;
+                            self.unit_zeros = 2;
                             self.to(ParseState::InUnitStart);
                         },
                         _ => {
@@ -176,11 +261,11 @@ impl<R, Ctx> AnnexBReader<R, Ctx>
                             self.to(ParseState::InUnitTrailingZeros)
                         },
                         0x01 => {
-                            if unit_start.is_some() && (unit_start.unwrap() > 0 || i > 3) {
-                                self.emit(ctx, buf, unit_start, i - 3);
-                            }
-                            self.nal_reader.end(ctx);
+                            self.emit(ctx, buf, unit_start, i, 3);
+                            self.end_unit(ctx);
                             unit_start = Some(i as isize + 1);
+                            self.unit_offset = base + i as u64 + 1;
+                            self.unit_zeros = 3;
                             self.to(ParseState::InUnitStart);
                         },
                         _ => {
@@ -207,16 +292,31 @@ impl<R, Ctx> AnnexBReader<R, Ctx>
                             // pairs to make 16 bit elements mentioned above,
                         },
                         0x01 => {
-                            if unit_start.is_some() && (unit_start.unwrap() > 0 || i > 3) {
-                                self.emit(ctx, buf, unit_start, i - 3);
-                            }
-                            self.nal_reader.end(ctx);
+                            self.emit(ctx, buf, unit_start, i, 3);
+                            self.end_unit(ctx);
                             unit_start = Some(i as isize + 1);
+                            self.unit_offset = base + i as u64 + 1;
+                            self.unit_zeros = 3;
                             self.to(ParseState::InUnitStart);
                         },
                         _ => {
-                            error!("Expected either start code byte 0x01 or end_units(), got byte {:#x}", b);
-                            self.to(ParseState::Error);
+                            match ctx.report_error(Severity::Error, ErrorKind::AnnexB(AnnexBError::UnexpectedByte { byte: b, offset: base + i as u64 })) {
+                                ErrorAction::Continue => {
+                                    // end the unit at the damage, keeping the data before it
+                                    self.emit(ctx, buf, unit_start, i, 3);
+                                    self.end_unit(ctx);
+                                },
+                                ErrorAction::SkipNal => self.nal_reader.reset(ctx),
+                                ErrorAction::Abort => return,
+                            }
+                            unit_start = None;
+                            if self.resync_handler.is_some() {
+                                self.start_zeros = 0;
+                                self.discard(1, base + i as u64);
+                                self.to(ParseState::Start);
+                            } else {
+                                self.to(ParseState::Error);
+                            }
                         },
                     }
                 },
@@ -230,11 +330,59 @@ impl<R, Ctx> AnnexBReader<R, Ctx>
                 start as usize
             };
             if buf.len() > backtrack {
-                self.nal_reader.push(ctx, &buf[adjusted_start..buf.len() - backtrack])
+                self.push_unit(ctx, &buf[adjusted_start..buf.len() - backtrack])
+            }
+        }
+    }
+
+    /// Pushes all data from the given `Read` source until end-of-file, using an internally
+    /// allocated buffer of the given capacity (see [`DEFAULT_READ_CAPACITY`]).
+    ///
+    /// As with `push()`, `start()` must have been called beforehand, and the caller should call
+    /// `end_units()` once the source has been exhausted (if that marks the end of the final NAL
+    /// unit).  Errors returned by the source (other than `ErrorKind::Interrupted`, which is
+    /// retried) are returned; all data read prior to the error will already have been pushed.
+    pub fn read_from<Rd: io::Read>(&mut self, ctx: &mut Context<Ctx>, mut rd: Rd, capacity: usize) -> io::Result<()> {
+        let mut buf = vec![0u8; capacity.max(1)];
+        loop {
+            match rd.read(&mut buf[..]) {
+                Ok(0) => return Ok(()),
+                Ok(len) => self.push(ctx, &buf[..len]),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
             }
         }
     }
 
+    /// Pushes all data from the given `BufRead` source until end-of-file, as with `read_from()`
+    /// but without copying: each window returned by `fill_buf()` is pushed directly.
+    ///
+    /// The windows are as large as the source's buffer, so a `BufReader` of generous capacity
+    /// lets multi-megabyte slices be scanned in a few large pushes.
+    pub fn read_from_buf<Rd: io::BufRead>(&mut self, ctx: &mut Context<Ctx>, mut rd: Rd) -> io::Result<()> {
+        loop {
+            let len = match rd.fill_buf() {
+                Ok([]) => return Ok(()),
+                Ok(buf) => {
+                    self.push(ctx, buf);
+                    buf.len()
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            rd.consume(len);
+        }
+    }
+
+    /// Pushes each of the given buffers in turn, as if they had been concatenated, so that
+    /// data gathered by vectored reads (or held in the two halves of a ring buffer) needn't be
+    /// copied into one buffer first.
+    pub fn push_vectored(&mut self, ctx: &mut Context<Ctx>, bufs: &[io::IoSlice<'_>]) {
+        for buf in bufs {
+            self.push(ctx, buf);
+        }
+    }
+
     /// To be invoked when calling code knows that the end of a sequence of NAL Unit data has been
     /// reached.
     ///
@@ -242,19 +390,79 @@ impl<R, Ctx> AnnexBReader<R, Ctx>
     /// Units explicitly, the parser for that structure should call `end_units()` once all data
     /// has been passed to the `push()` function.
     pub fn end_units(&mut self, ctx: &mut Context<Ctx>) {
+        if ctx.is_aborted() {
+            return;
+        }
         if let Some(backtrack) = self.state.end_backtrack_bytes() {
             // if we were in the middle of parsing a sequence of 0x00 bytes that might have become
             // a start-code, but actually reached the end of input, then we will now need to emit
             // those 0x00 bytes that we had been holding back,
             if backtrack > 0 {
                 let tmp = [0u8; 3];
-                self.nal_reader.push(ctx, &tmp[0..backtrack]);
+                self.push_unit(ctx, &tmp[0..backtrack]);
             }
         }
+        let in_unit = self.state.in_unit();
+        self.to(ParseState::End);
+        if in_unit {
+            self.end_unit(ctx);
+        }
+        self.report_discarded(ctx);
+    }
+
+    /// Drops any partially-read NAL unit (see [NalReader::reset]), as when the caller knows that
+    /// some of its data has been lost.  No further data is accepted until the next call to
+    /// `start()`.
+    pub fn reset(&mut self, ctx: &mut Context<Ctx>) {
+        if self.state.in_unit() {
+            self.nal_reader.reset(ctx);
+        }
         self.to(ParseState::End);
+        self.discarded = 0;
+    }
+
+    fn start_unit(&mut self, ctx: &mut Context<Ctx>) {
+        self.unit_len = 0;
+        ctx.set_nal_start(self.unit_offset, self.unit_zeros);
+        self.nal_reader.start(ctx);
+    }
+
+    fn push_unit(&mut self, ctx: &mut Context<Ctx>, buf: &[u8]) {
+        self.unit_len += buf.len() as u64;
+        self.nal_reader.push(ctx, buf);
+    }
+
+    fn end_unit(&mut self, ctx: &mut Context<Ctx>) {
+        ctx.set_nal_len(self.unit_len);
         self.nal_reader.end(ctx);
     }
 
+    fn discard(&mut self, len: usize, offset: u64) {
+        if self.discarded == 0 {
+            self.discarded_offset = offset;
+        }
+        self.discarded += len;
+    }
+
+    /// Reports the run of discarded bytes (if any) once, rather than each byte as it's found.
+    ///
+    /// The discarded bytes belong to no NAL unit, so `ErrorAction::SkipNal` is the same as
+    /// `ErrorAction::Continue` here.
+    fn report_discarded(&mut self, ctx: &mut Context<Ctx>) {
+        if self.discarded > 0 {
+            let action = ctx.report_error(Severity::Error, ErrorKind::AnnexB(AnnexBError::DiscardedBytes {
+                offset: self.discarded_offset,
+                len: self.discarded,
+            }));
+            if action != ErrorAction::Abort {
+                if let Some(ref mut handler) = self.resync_handler {
+                    handler(ctx, self.discarded);
+                }
+            }
+            self.discarded = 0;
+        }
+    }
+
     fn to(&mut self, new_state: ParseState) {
         self.state = new_state;
     }
@@ -262,25 +470,783 @@ impl<R, Ctx> AnnexBReader<R, Ctx>
     /// count must be 4 or less
     fn emit_fake(&mut self, ctx: &mut Context<Ctx>, count: usize) {
         let fake = [0u8; 4];
-        self.nal_reader.push(ctx, &fake[..count]);
+        self.push_unit(ctx, &fake[..count]);
     }
 
-    fn emit(&mut self, ctx: &mut Context<Ctx>, buf:&[u8], start_index: Option<isize>, end_index: usize) {
-        if let Some(start) = start_index {
-            let start = if start < 0 {
-                0usize
-            } else {
-                start as usize
+    /// Emits the remainder of the unit ending at the start code whose `0x01` byte is at
+    /// `buf[one_index]`, excluding the given number of `0x00` bytes preceding the `0x01`.
+    ///
+    /// A negative `start_index` gives the number of `0x00` bytes held back from the previous
+    /// `push()`, which precede `buf`.
+    fn emit(&mut self, ctx: &mut Context<Ctx>, buf:&[u8], start_index: Option<isize>, one_index: usize, zeros: usize) {
+        match start_index {
+            Some(start) => {
+                let start = start.max(0) as usize;
+                if one_index > start + zeros {
+                    self.push_unit(ctx, &buf[start..one_index - zeros])
+                }
+            },
+            None => error!("AnnexBReader: no start_index"),
+        }
+    }
+
+    /// Discards the byte at the given offset, which can't be part of a start code, along with
+    /// the zero bytes preceding it.
+    fn err(&mut self, offset: u64) {
+        self.discard(self.start_zeros + 1, offset - self.start_zeros as u64);
+        self.start_zeros = 0;
+        self.state = ParseState::Start;
+    }
+}
+
+/// Error yielded by [iter_nals], or reported by [AnnexBReader] via
+/// [Context::report_error](../struct.Context.html#method.report_error).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnnexBError {
+    /// The given number of bytes before the first start code were not all `0x00`.
+    DataBeforeStartCode { len: usize },
+    /// `AnnexBReader` found a byte other than the `0x01` of a start code following a run of
+    /// three or more `0x00` bytes within a NAL unit, at the given offset from the start of the
+    /// stream.
+    UnexpectedByte { byte: u8, offset: u64 },
+    /// `AnnexBReader` skipped the given number of bytes, starting at the given offset from the
+    /// start of the stream, which could not be part of a start code (or of the zero bytes
+    /// preceding one).  Reported once the next start code is found, or by `end_units()`.
+    DiscardedBytes { offset: u64, len: usize },
+    /// A NAL unit exceeded the size (in bytes, including the header) set by a reader's
+    /// `set_max_nal_size()`, so was dropped.
+    NalTooLarge { max_size: usize },
+}
+
+/// Finds the start codes within a complete Annex B byte stream, yielding each NAL unit borrowed
+/// from the input; a simpler alternative to [AnnexBReader] when all data is in memory.
+///
+/// A NAL unit can't end with a `0x00` byte, so all the zero bytes preceding a start code
+/// (`zero_byte` and any `trailing_zero_8bits`) are excluded from the preceding NAL unit.  (This
+/// differs from `AnnexBReader`, which passes zero bytes beyond those of the start code to the
+/// preceding unit.)  Empty NAL units are skipped.
+///
+/// ```
+/// # use h264_reader::annexb::iter_nals;
+/// # use h264_reader::nal::{UnitType, sps::SeqParameterSet};
+/// # let data = [0u8; 0];
+/// for nal in iter_nals(&data[..]).filter_map(Result::ok) {
+///     if nal.header().map(|h| h.nal_unit_type()).ok() == Some(UnitType::SeqParameterSet) {
+///         println!("{:?}", SeqParameterSet::from_bits(&mut nal.reader()));
+///     }
+/// }
+/// ```
+pub fn iter_nals(data: &[u8]) -> NalIter<'_> {
+    NalIter {
+        rest: data,
+        first: true,
+    }
+}
+
+/// Returns the offset of the first `0x00` byte within `data` which may begin a start code (or
+/// the forbidden `00 00 00` sequence), for `AnnexBReader` to examine byte by byte: one followed
+/// by `00 00`, `00 01` or the end of `data`.
+///
+/// The other zero bytes, which are common in slice data (e.g. the `00 00 03` of emulation
+/// prevention), are passed over without leaving the `memchr` search, so that scanning a NAL unit
+/// costs little more than `memchr` itself.
+fn find_unit_end(data: &[u8]) -> Option<usize> {
+    let mut i = 0;
+    while let Some(pos) = memchr::memchr(0x00, &data[i..]) {
+        let p = i + pos;
+        match (data.get(p + 1), data.get(p + 2)) {
+            (Some(&b1), _) if b1 != 0x00 => i = p + 2,
+            (Some(_), Some(&b2)) if b2 > 0x01 => i = p + 3,
+            _ => return Some(p),
+        }
+    }
+    None
+}
+
+/// Returns the offset of the first `00 00 01` start code within `data`.
+fn find_start_code(data: &[u8]) -> Option<usize> {
+    let mut i = 2;
+    while i < data.len() {
+        let one = i + memchr::memchr(0x01, &data[i..])?;
+        if data[one-2] == 0x00 && data[one-1] == 0x00 {
+            return Some(one - 2);
+        }
+        i = one + 1;
+    }
+    None
+}
+
+/// Iterator returned by [iter_nals].
+pub struct NalIter<'a> {
+    rest: &'a [u8],
+    first: bool,
+}
+impl<'a> NalIter<'a> {
+    /// Returns the next NAL unit's bytes, including the header byte.
+    pub(crate) fn next_slice(&mut self) -> Option<Result<&'a [u8], AnnexBError>> {
+        if self.first {
+            self.first = false;
+            let start = find_start_code(self.rest);
+            let leading = &self.rest[..start.unwrap_or(self.rest.len())];
+            self.rest = &self.rest[leading.len()..];
+            if leading.iter().any(|&b| b != 0x00) {
+                return Some(Err(AnnexBError::DataBeforeStartCode { len: leading.len() }));
+            }
+        }
+        loop {
+            if self.rest.len() < 3 {
+                return None;
+            }
+            // self.rest begins with a start code.
+            let unit = &self.rest[3..];
+            let (nal, rest) = match find_start_code(unit) {
+                Some(end) => {
+                    // a NAL unit can't end with 0x00, so any zero_byte and trailing_zero_8bits
+                    // preceding the start code aren't part of it.
+                    let nal_end = unit[..end].iter().rposition(|&b| b != 0x00).map_or(0, |p| p + 1);
+                    (&unit[..nal_end], &unit[end..])
+                },
+                None => (unit, &unit[unit.len()..]),
             };
-            self.nal_reader.push(ctx, &buf[start..end_index])
+            self.rest = rest;
+            if !nal.is_empty() {
+                return Some(Ok(nal));
+            }
+        }
+    }
+}
+impl<'a> Iterator for NalIter<'a> {
+    type Item = Result<RefNal<'a>, AnnexBError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_slice().map(|r| r.map(|nal| RefNal::new(nal, &[], true)))
+    }
+}
+
+/// Writes NAL units (header byte followed by the escaped payload, as produced by
+/// [`NalAccumulator`](../nal/struct.NalAccumulator.html)) as an Annex B byte stream; the inverse
+/// of [AnnexBReader].
+///
+/// By default, the 4-byte start code (including `zero_byte`) which _Annex B_ requires is used for
+/// parameter sets and for NAL units which begin an access unit, and the 3-byte start code
+/// otherwise.  The start of an access unit is recognised by the first NAL unit written, an access
+/// unit delimiter, or SEI, prefix NAL units and the other non-VCL types of _Section 7.4.1.2.3_
+/// following a VCL NAL unit.  A VCL NAL unit which begins an access unit without any of these
+/// preceding it can't be recognised without parsing its slice header, so gets the 3-byte start
+/// code; use `set_always_long_start_codes(true)` where that matters.
+pub struct AnnexBWriter<W: io::Write> {
+    writer: W,
+    always_long: bool,
+    /// whether the last NAL unit written was a VCL NAL unit, or `None` if nothing has been
+    /// written (so the next NAL unit begins an access unit).
+    prev_vcl: Option<bool>,
+}
+impl<W: io::Write> AnnexBWriter<W> {
+    pub fn new(writer: W) -> Self {
+        AnnexBWriter {
+            writer,
+            always_long: false,
+            prev_vcl: None,
+        }
+    }
+
+    /// If true, every NAL unit is given a 4-byte start code.
+    pub fn set_always_long_start_codes(&mut self, always_long: bool) {
+        self.always_long = always_long;
+    }
+
+    /// Writes the start code followed by the given NAL unit, which must contain at least the
+    /// header byte, and must not end with a `0x00` byte (which a reader would take to be part of
+    /// the following start code; an escaped payload instead ends with `0x03`).
+    pub fn write_nal(&mut self, nal: &[u8]) -> io::Result<()> {
+        let header = check_nal(nal)?;
+        let unit_type = header.nal_unit_type();
+        let vcl = matches!(unit_type.id(), 1..=5 | 20 | 21);
+        let long = self.always_long || self.prev_vcl.is_none() || match unit_type {
+            UnitType::SeqParameterSet
+            | UnitType::PicParameterSet
+            | UnitType::SubsetSeqParameterSet
+            | UnitType::DepthParameterSet
+            | UnitType::AccessUnitDelimiter => true,
+            UnitType::SEI | UnitType::PrefixNALUnit | UnitType::Reserved(17..=18) => self.prev_vcl == Some(true),
+            _ => false,
+        };
+        self.prev_vcl = Some(vcl);
+        self.write_start_code(if long { 3 } else { 2 })?;
+        self.writer.write_all(nal)
+    }
+
+    /// Writes the given NAL unit preceded by a start code with exactly `zeros` `0x00` bytes
+    /// before its `0x01` (at least 2), as given by
+    /// [`Context::nal_start_code_zeros`](../struct.Context.html#method.nal_start_code_zeros);
+    /// for byte-exact remuxing.
+    pub fn write_nal_with_zeros(&mut self, nal: &[u8], zeros: usize) -> io::Result<()> {
+        if zeros < 2 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "start code needs at least two zero bytes"));
+        }
+        let header = check_nal(nal)?;
+        self.prev_vcl = Some(matches!(header.nal_unit_type().id(), 1..=5 | 20 | 21));
+        self.write_start_code(zeros)?;
+        self.writer.write_all(nal)
+    }
+
+    fn write_start_code(&mut self, zeros: usize) -> io::Result<()> {
+        const ZEROS: [u8; 16] = [0; 16];
+        let mut remaining = zeros;
+        while remaining > 0 {
+            let n = std::cmp::min(remaining, ZEROS.len());
+            self.writer.write_all(&ZEROS[..n])?;
+            remaining -= n;
+        }
+        self.writer.write_all(&[0x01])
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Checks that `nal` is suitable for [AnnexBWriter], returning its header.
+fn check_nal(nal: &[u8]) -> io::Result<NalHeader> {
+    let header = nal.first()
+        .and_then(|&b| NalHeader::new(b).ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "NAL unit lacks a valid header byte"))?;
+    if nal.last() == Some(&0x00) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "NAL unit ends with a zero byte"));
+    }
+    Ok(header)
+}
+
+/// Collects whole NAL units for [SyncNalReader] and [AsyncNalReader].
+#[derive(Default)]
+struct NalCollector {
+    current: Vec<u8>,
+    complete: std::collections::VecDeque<Vec<u8>>,
+    max_nal_size: Option<usize>,
+    /// Whether the current NAL unit exceeded `max_nal_size`, so is being dropped.
+    too_large: bool,
+}
+impl NalReader for NalCollector {
+    type Ctx = ();
+
+    fn start(&mut self, _ctx: &mut Context<Self::Ctx>) {
+        self.current.clear();
+        self.too_large = false;
+    }
+
+    fn push(&mut self, ctx: &mut Context<Self::Ctx>, buf: &[u8]) {
+        if self.too_large {
+            return;
+        }
+        if let Some(max_size) = self.max_nal_size {
+            if self.current.len() + buf.len() > max_size {
+                self.current = Vec::new();
+                self.too_large = true;
+                // the unit is dropped whatever the action; the AnnexBReader stops on an Abort.
+                ctx.report_error(Severity::Error, ErrorKind::AnnexB(AnnexBError::NalTooLarge { max_size }));
+                return;
+            }
+        }
+        self.current.extend_from_slice(buf);
+    }
+
+    fn end(&mut self, _ctx: &mut Context<Self::Ctx>) {
+        if !self.current.is_empty() {
+            self.complete.push_back(std::mem::take(&mut self.current));
+        }
+    }
+
+    fn reset(&mut self, _ctx: &mut Context<Self::Ctx>) {
+        self.current.clear();
+        self.too_large = false;
+    }
+}
+
+/// Reads an Annex B byte stream from a `std::io::Read`, yielding each complete NAL unit (header
+/// byte followed by the escaped payload) in turn.
+///
+/// A simpler alternative to driving an [AnnexBReader] when the caller just wants to loop over the
+/// NAL units of a file or other blocking source:
+///
+/// ```
+/// # use h264_reader::annexb::SyncNalReader;
+/// let data: &[u8] = &[0, 0, 0, 1, 0x09, 0xf0, 0, 0, 1, 0x68, 0xce, 0x3c, 0x80];
+/// let mut reader = SyncNalReader::new(data);
+/// let mut types = vec![];
+/// while let Some(nal) = reader.next_nal()? {
+///     types.push(nal[0] & 0x1f);
+/// }
+/// assert_eq!(types, [9, 8]);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+///
+/// It also implements `Iterator`, yielding owned NAL units.  Like [AnnexBReader], on which it is
+/// built, it holds each NAL unit in memory until the following start code (or end of input) is
+/// found.
+pub struct SyncNalReader<R> {
+    reader: R,
+    annexb: AnnexBReader<NalCollector, ()>,
+    ctx: Context<()>,
+    buf: Vec<u8>,
+    current: Vec<u8>,
+    eof: bool,
+}
+impl<R: io::Read> SyncNalReader<R> {
+    /// Creates a reader which reads from the source in chunks of [`DEFAULT_READ_CAPACITY`] bytes.
+    pub fn new(reader: R) -> Self {
+        Self::with_capacity(reader, DEFAULT_READ_CAPACITY)
+    }
+
+    /// Creates a reader which reads from the source in chunks of (at most) the given size.
+    pub fn with_capacity(reader: R, capacity: usize) -> Self {
+        let mut ctx = Context::default();
+        let mut annexb = AnnexBReader::new(NalCollector::default());
+        annexb.start(&mut ctx);
+        SyncNalReader {
+            reader,
+            annexb,
+            ctx,
+            buf: vec![0u8; capacity.max(1)],
+            current: vec![],
+            eof: false,
+        }
+    }
+
+    /// Limits the size in bytes (including the header) of each NAL unit, bounding the memory
+    /// used on a stream without start codes.  A NAL unit exceeding the limit is dropped, and
+    /// [AnnexBError::NalTooLarge] is reported (and so logged).  By default there is no limit.
+    pub fn set_max_nal_size(&mut self, max_nal_size: Option<usize>) {
+        self.annexb.nal_reader.max_nal_size = max_nal_size;
+    }
+
+    /// Returns the next NAL unit, borrowed from this reader's internal buffer, or `None` once
+    /// the end of the source has been reached.
+    ///
+    /// Errors from the source (other than `ErrorKind::Interrupted`, which is retried) are
+    /// returned; reading may be resumed afterwards if the source allows it.
+    pub fn next_nal(&mut self) -> io::Result<Option<&[u8]>> {
+        match self.fill()? {
+            Some(nal) => {
+                self.current = nal;
+                Ok(Some(&self.current[..]))
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn fill(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            if let Some(nal) = self.annexb.nal_reader.complete.pop_front() {
+                return Ok(Some(nal));
+            }
+            if self.eof {
+                return Ok(None);
+            }
+            match self.reader.read(&mut self.buf[..]) {
+                Ok(0) => {
+                    self.eof = true;
+                    self.annexb.end_units(&mut self.ctx);
+                },
+                Ok(len) => self.annexb.push(&mut self.ctx, &self.buf[..len]),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+impl<R: io::Read> Iterator for SyncNalReader<R> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.fill().transpose()
+    }
+}
+
+/// Feeds the payloads of MPEG-TS PES packets to an [AnnexBReader].
+///
+/// The methods correspond to the callbacks of an elementary stream consumer in a transport stream
+/// demultiplexer (such as `mpeg2ts_reader::pes::ElementaryStreamConsumer`), so that the consumer
+/// for an H.264 stream can simply forward to them.  Payload data may be split at arbitrary points
+/// across calls to `continue_packet()`.
+///
+/// Each PES packet is taken to hold whole NAL units, as produced by muxers which begin every
+/// packet with an access unit delimiter, so the last NAL unit of a packet is passed on as soon as
+/// `end_packet()` is called, rather than when the next packet's start code arrives.
+pub struct PesAdapter<R, Ctx>
+    where
+        R: NalReader<Ctx=Ctx>
+{
+    annexb: AnnexBReader<R, Ctx>,
+    in_packet: bool,
+}
+impl<R, Ctx> PesAdapter<R, Ctx>
+    where
+        R: NalReader<Ctx=Ctx>
+{
+    pub fn new(nal_reader: R) -> Self {
+        PesAdapter {
+            annexb: AnnexBReader::new(nal_reader),
+            in_packet: false,
+        }
+    }
+
+    /// Replaces the [AnnexBReader] used for each packet's payload, e.g. so that one with a
+    /// resync handler can be given.
+    pub fn from_annexb_reader(annexb: AnnexBReader<R, Ctx>) -> Self {
+        PesAdapter {
+            annexb,
+            in_packet: false,
+        }
+    }
+
+    /// To be called when the stream (re)starts, such as when the PID is first seen or a
+    /// demultiplexer is reset.  Any partially-read NAL unit is dropped.
+    pub fn start_stream(&mut self, ctx: &mut Context<Ctx>) {
+        self.annexb.reset(ctx);
+        self.in_packet = false;
+    }
+
+    /// To be called when a new PES packet begins (i.e. on `payload_unit_start_indicator`).  If
+    /// the previous packet was not ended explicitly, it is ended now.
+    pub fn begin_packet(&mut self, ctx: &mut Context<Ctx>) {
+        if self.in_packet {
+            self.annexb.end_units(ctx);
+        }
+        self.annexb.start(ctx);
+        self.in_packet = true;
+    }
+
+    /// Passes on a fragment of the current packet's payload.  Data received outside of a packet
+    /// (before the first `begin_packet()`, or after a continuity error) is ignored.
+    pub fn continue_packet(&mut self, ctx: &mut Context<Ctx>, payload: &[u8]) {
+        if self.in_packet {
+            self.annexb.push(ctx, payload);
+        }
+    }
+
+    /// To be called once the whole payload of the current packet has been passed on, completing
+    /// the packet's final NAL unit.
+    pub fn end_packet(&mut self, ctx: &mut Context<Ctx>) {
+        if self.in_packet {
+            self.annexb.end_units(ctx);
+            self.in_packet = false;
+        }
+    }
+
+    /// To be called when transport stream packets have been lost.  The NAL unit being read is
+    /// dropped (see [NalReader::reset]), as is the rest of the current PES packet.
+    pub fn continuity_error(&mut self, ctx: &mut Context<Ctx>) {
+        self.annexb.reset(ctx);
+        self.in_packet = false;
+    }
+
+    pub fn nal_reader(&mut self) -> &mut R {
+        &mut self.annexb.nal_reader
+    }
+
+    pub fn into_nal_reader(self) -> R {
+        self.annexb.into_nal_reader()
+    }
+}
+
+/// A NAL unit (header byte followed by the escaped payload) held as a sequence of `bytes::Bytes`
+/// chunks sharing the buffers originally passed to [BytesNalReader::push].
+///
+/// Requires the `bytes` feature.
+#[cfg(feature = "bytes")]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BytesNal {
+    chunks: Vec<bytes::Bytes>,
+    len: usize,
+}
+#[cfg(feature = "bytes")]
+impl BytesNal {
+    /// The chunks which make up this NAL unit, in order.  None of them are empty.
+    pub fn chunks(&self) -> &[bytes::Bytes] {
+        &self.chunks[..]
+    }
+
+    /// The total length of the NAL unit in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn header(&self) -> Result<NalHeader, crate::nal::NalHeaderError> {
+        NalHeader::new(self.chunks[0][0])
+    }
+
+    /// Calls the given function with a [RefNal] view of this NAL unit, for parsing without
+    /// copying the data.
+    pub fn with_ref_nal<T>(&self, f: impl FnOnce(&RefNal<'_>) -> T) -> T {
+        let tail: Vec<&[u8]> = self.chunks[1..].iter().map(|c| &c[..]).collect();
+        f(&RefNal::new(&self.chunks[0][..], &tail[..], true))
+    }
+
+    /// Returns the NAL unit as a single contiguous `Bytes`, which only requires a copy if it is
+    /// currently split across more than one chunk.
+    pub fn into_bytes(mut self) -> bytes::Bytes {
+        if self.chunks.len() == 1 {
+            return self.chunks.pop().unwrap();
+        }
+        let mut v = Vec::with_capacity(self.len);
+        for c in &self.chunks {
+            v.extend_from_slice(&c[..]);
+        }
+        bytes::Bytes::from(v)
+    }
+}
+
+/// Collects whole NAL units for [BytesNalReader], referencing the input rather than copying it.
+#[cfg(feature = "bytes")]
+#[derive(Default)]
+struct BytesCollector {
+    input: bytes::Bytes,
+    current: BytesNal,
+    complete: std::collections::VecDeque<BytesNal>,
+    max_nal_size: Option<usize>,
+    /// Whether the current NAL unit exceeded `max_nal_size`, so is being dropped.
+    too_large: bool,
+}
+#[cfg(feature = "bytes")]
+impl NalReader for BytesCollector {
+    type Ctx = ();
+
+    fn start(&mut self, _ctx: &mut Context<Self::Ctx>) {
+        self.current = BytesNal::default();
+        self.too_large = false;
+    }
+
+    fn push(&mut self, ctx: &mut Context<Self::Ctx>, buf: &[u8]) {
+        if buf.is_empty() || self.too_large {
+            return;
+        }
+        if let Some(max_size) = self.max_nal_size {
+            if self.current.len + buf.len() > max_size {
+                self.current = BytesNal::default();
+                self.too_large = true;
+                // the unit is dropped whatever the action; the AnnexBReader stops on an Abort.
+                ctx.report_error(Severity::Error, ErrorKind::AnnexB(AnnexBError::NalTooLarge { max_size }));
+                return;
+            }
+        }
+        let start = self.input.as_ptr() as usize;
+        let p = buf.as_ptr() as usize;
+        let chunk = if p >= start && p + buf.len() <= start + self.input.len() {
+            self.input.slice_ref(buf)
         } else {
-            error!("AnnexBReader: no start_index");
+            // zero bytes held back by AnnexBReader from a previous push() are not part of the
+            // current input,
+            bytes::Bytes::copy_from_slice(buf)
+        };
+        self.current.len += chunk.len();
+        self.current.chunks.push(chunk);
+    }
+
+    fn end(&mut self, _ctx: &mut Context<Self::Ctx>) {
+        if !self.current.is_empty() {
+            self.complete.push_back(std::mem::take(&mut self.current));
         }
     }
 
-    fn err(&mut self, b: u8) {
-        error!("AnnexBReader: state={:?}, invalid byte {:#x}", self.state, b);
-        self.state = ParseState::Start;
+    fn reset(&mut self, _ctx: &mut Context<Self::Ctx>) {
+        self.current = BytesNal::default();
+        self.too_large = false;
+    }
+}
+
+/// Splits an Annex B byte stream supplied as `bytes::Bytes` buffers into NAL units, without
+/// copying the NAL unit data.
+///
+/// Requires the `bytes` feature.  Each [BytesNal] produced references the buffers passed to
+/// `push()`; only NAL units split across several buffers are made up of more than one chunk.
+#[cfg(feature = "bytes")]
+pub struct BytesNalReader {
+    annexb: AnnexBReader<BytesCollector, ()>,
+    ctx: Context<()>,
+}
+#[cfg(feature = "bytes")]
+impl Default for BytesNalReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+#[cfg(feature = "bytes")]
+impl BytesNalReader {
+    pub fn new() -> Self {
+        let mut ctx = Context::default();
+        let mut annexb = AnnexBReader::new(BytesCollector::default());
+        annexb.start(&mut ctx);
+        BytesNalReader {
+            annexb,
+            ctx,
+        }
+    }
+
+    /// Limits the size in bytes (including the header) of each NAL unit, as with
+    /// [SyncNalReader::set_max_nal_size].
+    pub fn set_max_nal_size(&mut self, max_nal_size: Option<usize>) {
+        self.annexb.nal_reader.max_nal_size = max_nal_size;
+    }
+
+    /// Supplies the next buffer of the byte stream.  NAL units completed by this data become
+    /// available from `next_nal()`.
+    pub fn push(&mut self, data: bytes::Bytes) {
+        self.annexb.nal_reader.input = data.clone();
+        self.annexb.push(&mut self.ctx, &data[..]);
+        self.annexb.nal_reader.input = bytes::Bytes::new();
+    }
+
+    /// Indicates the end of the byte stream, so that the final NAL unit becomes available from
+    /// `next_nal()`.
+    pub fn end_units(&mut self) {
+        self.annexb.end_units(&mut self.ctx);
+    }
+
+    /// Returns the next complete NAL unit, if any.
+    pub fn next_nal(&mut self) -> Option<BytesNal> {
+        self.annexb.nal_reader.complete.pop_front()
+    }
+}
+
+/// Adapts a `futures_core::Stream` of `bytes::Bytes` buffers holding an Annex B byte stream (for
+/// example, as received from a network connection) into a `Stream` of the NAL units it contains.
+///
+/// Requires the `stream` feature.  The buffers may be split at arbitrary points; the final NAL
+/// unit is produced once the inner stream ends.  See [BytesNalReader].
+#[cfg(feature = "stream")]
+pub struct BytesNalStream<S> {
+    inner: S,
+    reader: BytesNalReader,
+    done: bool,
+}
+#[cfg(feature = "stream")]
+impl<S: futures_core::Stream<Item = bytes::Bytes> + Unpin> BytesNalStream<S> {
+    pub fn new(inner: S) -> Self {
+        BytesNalStream {
+            inner,
+            reader: BytesNalReader::new(),
+            done: false,
+        }
+    }
+
+    /// Limits the size in bytes (including the header) of each NAL unit, as with
+    /// [SyncNalReader::set_max_nal_size].
+    pub fn set_max_nal_size(&mut self, max_nal_size: Option<usize>) {
+        self.reader.set_max_nal_size(max_nal_size);
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+#[cfg(feature = "stream")]
+impl<S: futures_core::Stream<Item = bytes::Bytes> + Unpin> futures_core::Stream for BytesNalStream<S> {
+    type Item = BytesNal;
+
+    fn poll_next(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Option<Self::Item>> {
+        use std::pin::Pin;
+        use std::task::Poll;
+        let this = self.get_mut();
+        loop {
+            if let Some(nal) = this.reader.next_nal() {
+                return Poll::Ready(Some(nal));
+            }
+            if this.done {
+                return Poll::Ready(None);
+            }
+            match Pin::new(&mut this.inner).poll_next(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Some(data)) => this.reader.push(data),
+                Poll::Ready(None) => {
+                    this.done = true;
+                    this.reader.end_units();
+                },
+            }
+        }
+    }
+}
+
+/// Reads an Annex B byte stream from a `tokio::io::AsyncBufRead`, yielding each complete NAL unit
+/// (header byte followed by the escaped payload) as a `futures_core::Stream`.
+///
+/// Requires the `tokio` feature.  Like [AnnexBReader], on which it is built, it holds each NAL
+/// unit in memory until the following start code (or end of input) is found.
+#[cfg(feature = "tokio")]
+pub struct AsyncNalReader<R> {
+    reader: R,
+    annexb: AnnexBReader<NalCollector, ()>,
+    ctx: Context<()>,
+    eof: bool,
+}
+#[cfg(feature = "tokio")]
+impl<R: tokio::io::AsyncBufRead + Unpin> AsyncNalReader<R> {
+    pub fn new(reader: R) -> Self {
+        let mut ctx = Context::default();
+        let mut annexb = AnnexBReader::new(NalCollector::default());
+        annexb.start(&mut ctx);
+        AsyncNalReader {
+            reader,
+            annexb,
+            ctx,
+            eof: false,
+        }
+    }
+
+    /// Limits the size in bytes (including the header) of each NAL unit, as with
+    /// [SyncNalReader::set_max_nal_size].
+    pub fn set_max_nal_size(&mut self, max_nal_size: Option<usize>) {
+        self.annexb.nal_reader.max_nal_size = max_nal_size;
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+#[cfg(feature = "tokio")]
+impl<R: tokio::io::AsyncBufRead + Unpin> futures_core::Stream for AsyncNalReader<R> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn poll_next(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Option<Self::Item>> {
+        use std::pin::Pin;
+        use std::task::Poll;
+        let this = self.get_mut();
+        loop {
+            if let Some(nal) = this.annexb.nal_reader.complete.pop_front() {
+                return Poll::Ready(Some(Ok(nal)));
+            }
+            if this.eof {
+                return Poll::Ready(None);
+            }
+            let buf = match Pin::new(&mut this.reader).poll_fill_buf(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(Ok(buf)) => buf,
+            };
+            if buf.is_empty() {
+                this.eof = true;
+                this.annexb.end_units(&mut this.ctx);
+                continue;
+            }
+            let len = buf.len();
+            this.annexb.push(&mut this.ctx, buf);
+            Pin::new(&mut this.reader).consume(len);
+        }
     }
 }
 
@@ -289,7 +1255,10 @@ mod tests {
     use super::*;
     use std::rc::Rc;
     use std::cell::RefCell;
+    use std::sync::{Arc, Mutex};
     use hex_literal::*;
+    use crate::nal::UnitType;
+    use crate::rbsp::BitRead;
 
     struct State {
         started: u32,
@@ -322,6 +1291,33 @@ mod tests {
         }
     }
 
+    /// Collects each NAL unit, dropping empty ones and those which are reset.
+    struct Collect(Vec<Vec<u8>>);
+    impl NalReader for Collect {
+        type Ctx = ();
+        fn start(&mut self, _ctx: &mut Context<Self::Ctx>) {
+            self.0.push(Vec::new());
+        }
+        fn push(&mut self, _ctx: &mut Context<Self::Ctx>, buf: &[u8]) {
+            self.0.last_mut().unwrap().extend_from_slice(buf);
+        }
+        fn end(&mut self, _ctx: &mut Context<Self::Ctx>) {
+            if self.0.last().map(Vec::is_empty).unwrap_or(false) {
+                self.0.pop();
+            }
+        }
+        fn reset(&mut self, _ctx: &mut Context<Self::Ctx>) {
+            self.0.pop();
+        }
+    }
+
+    #[cfg(any(feature = "stream", feature = "tokio"))]
+    struct NoopWaker;
+    #[cfg(any(feature = "stream", feature = "tokio"))]
+    impl std::task::Wake for NoopWaker {
+        fn wake(self: std::sync::Arc<Self>) {}
+    }
+
     #[test]
     fn simple_nal() {
         let state = Rc::new(RefCell::new(State {
@@ -658,4 +1654,622 @@ mod tests {
         assert_eq!(3, state.borrow().ended);
         assert_eq!(&state.borrow().data[..], &expected[..]);
     }
+
+    /// Returns at most `chunk` bytes per `read()` call, with an interruption between each.
+    struct ChunkedRead<'a> {
+        data: &'a [u8],
+        chunk: usize,
+        interrupt: bool,
+    }
+    impl<'a> std::io::Read for ChunkedRead<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.interrupt = !self.interrupt;
+            if self.interrupt {
+                return Err(std::io::ErrorKind::Interrupted.into());
+            }
+            let len = self.chunk.min(buf.len()).min(self.data.len());
+            buf[..len].copy_from_slice(&self.data[..len]);
+            self.data = &self.data[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn read_from_buf_and_push_vectored() {
+        let data = hex!(
+            "00 00 00 01 67 64 00 0A AC 72 84 44 26 84 00 00 03
+             00 00 00 01 68 EE 3C B0
+             00 00 01 65 88 00 00");
+        for &(chunk, capacity) in &[(1, 16), (3, 2), (64, DEFAULT_READ_CAPACITY)] {
+            for &vectored in &[false, true] {
+                let state = Rc::new(RefCell::new(State {
+                    started: 0,
+                    ended: 0,
+                    data: Vec::new(),
+                }));
+                let mock = MockReader::new(Rc::clone(&state));
+                let mut r = AnnexBReader::new(mock);
+                let mut ctx = Context::default();
+                r.start(&mut ctx);
+                if vectored {
+                    let bufs: Vec<_> = data.chunks(chunk).map(std::io::IoSlice::new).collect();
+                    r.push_vectored(&mut ctx, &bufs);
+                } else {
+                    let rd = ChunkedRead { data: &data[..], chunk, interrupt: false };
+                    r.read_from_buf(&mut ctx, std::io::BufReader::with_capacity(capacity, rd)).unwrap();
+                }
+                r.end_units(&mut ctx);
+                let s = state.borrow();
+                assert_eq!(3, s.started);
+                assert_eq!(3, s.ended);
+                assert_eq!(&s.data[..], &hex!(
+                    "67 64 00 0A AC 72 84 44 26 84 00 00 03
+                     68 EE 3C B0
+                     65 88 00 00")[..]);
+            }
+        }
+    }
+
+    #[test]
+    fn read_from() {
+        let data = hex!(
+            "00 00 00 01 67 64 00 0A AC 72 84 44 26 84 00 00 03
+             00 00 00 01 68 EE 3C B0
+             00 00 01 65 88 00 00");
+        for &(chunk, capacity) in &[(1, 16), (3, 2), (64, DEFAULT_READ_CAPACITY)] {
+            let state = Rc::new(RefCell::new(State {
+                started: 0,
+                ended: 0,
+                data: Vec::new(),
+            }));
+            let mock = MockReader::new(Rc::clone(&state));
+            let mut r = AnnexBReader::new(mock);
+            let mut ctx = Context::default();
+            r.start(&mut ctx);
+            let rd = ChunkedRead { data: &data[..], chunk, interrupt: false };
+            r.read_from(&mut ctx, rd, capacity).unwrap();
+            r.end_units(&mut ctx);
+            let s = state.borrow();
+            assert_eq!(3, s.started);
+            assert_eq!(3, s.ended);
+            assert_eq!(&s.data[..], &hex!(
+                "67 64 00 0A AC 72 84 44 26 84 00 00 03
+                 68 EE 3C B0
+                 65 88 00 00")[..]);
+        }
+    }
+
+    #[test]
+    fn sync_reader() {
+        let data = hex!(
+            "00 00 00 01 67 64 00 0A AC 72 84 44 26 84 00 00 03
+             00 00 00 01 68 EE 3C B0
+             00 00 01 65 88 00 00");
+        let expected: [&[u8]; 3] = [
+            &hex!("67 64 00 0A AC 72 84 44 26 84 00 00 03"),
+            &hex!("68 EE 3C B0"),
+            &hex!("65 88 00 00"),
+        ];
+        for &(chunk, capacity) in &[(1, 16), (3, 2), (64, DEFAULT_READ_CAPACITY)] {
+            let rd = ChunkedRead { data: &data[..], chunk, interrupt: false };
+            let mut r = SyncNalReader::with_capacity(rd, capacity);
+            let mut nals = vec![];
+            loop {
+                match r.next_nal() {
+                    Ok(Some(nal)) => nals.push(nal.to_vec()),
+                    Ok(None) => break,
+                    Err(e) => panic!("{:?}", e),
+                }
+            }
+            assert_eq!(nals, expected);
+            assert!(r.next_nal().unwrap().is_none());
+
+            let rd = ChunkedRead { data: &data[..], chunk, interrupt: false };
+            let nals: Vec<_> = SyncNalReader::with_capacity(rd, capacity).map(Result::unwrap).collect();
+            assert_eq!(nals, expected);
+
+            // the SPS is too large, and is dropped.
+            let rd = ChunkedRead { data: &data[..], chunk, interrupt: false };
+            let mut r = SyncNalReader::with_capacity(rd, capacity);
+            r.set_max_nal_size(Some(12));
+            let nals: Vec<_> = r.map(Result::unwrap).collect();
+            assert_eq!(nals, &expected[1..]);
+        }
+    }
+
+    #[test]
+    fn pes_adapter() {
+        let packets: [&[u8]; 2] = [
+            &hex!("00 00 00 01 09 F0 00 00 01 65 88 84 00 00"),
+            &hex!("00 00 00 01 09 F0 00 00 01 41 9A 00"),
+        ];
+        let expected: [&[u8]; 4] = [
+            &hex!("09 F0"),
+            &hex!("65 88 84 00 00"),
+            &hex!("09 F0"),
+            &hex!("41 9A 00"),
+        ];
+        for split in 1..14 {
+            let mut ctx = Context::default();
+            let mut pes = PesAdapter::new(NalCollector::default());
+            pes.start_stream(&mut ctx);
+            for packet in &packets {
+                pes.begin_packet(&mut ctx);
+                for chunk in packet.chunks(split) {
+                    pes.continue_packet(&mut ctx, chunk);
+                }
+                pes.end_packet(&mut ctx);
+            }
+            let nals: Vec<_> = pes.nal_reader().complete.drain(..).collect();
+            assert_eq!(nals, expected, "split={}", split);
+        }
+
+        // the final NAL unit is complete at end_packet(), without waiting for the next packet,
+        let mut ctx = Context::default();
+        let mut pes = PesAdapter::new(NalCollector::default());
+        pes.begin_packet(&mut ctx);
+        pes.continue_packet(&mut ctx, packets[0]);
+        assert_eq!(pes.nal_reader().complete.len(), 1);
+        pes.end_packet(&mut ctx);
+        assert_eq!(pes.nal_reader().complete.len(), 2);
+
+        // an empty packet produces nothing,
+        pes.begin_packet(&mut ctx);
+        pes.end_packet(&mut ctx);
+        assert_eq!(pes.nal_reader().complete.len(), 2);
+
+        // a continuity error drops the partial NAL unit and the rest of its packet, and a packet
+        // which isn't explicitly ended is ended by the next one,
+        pes.begin_packet(&mut ctx);
+        pes.continue_packet(&mut ctx, &packets[1][..10]);
+        pes.continuity_error(&mut ctx);
+        pes.continue_packet(&mut ctx, &packets[1][10..]);
+        pes.begin_packet(&mut ctx);
+        pes.continue_packet(&mut ctx, packets[1]);
+        pes.begin_packet(&mut ctx);
+        let nals: Vec<_> = pes.into_nal_reader().complete.into_iter().collect();
+        assert_eq!(nals, [expected[0], expected[1], expected[2], expected[2], expected[3]]);
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn bytes_reader() {
+        let data = bytes::Bytes::from(hex!(
+            "00 00 00 01 67 64 00 0A AC 72 84 44 26 84 00 00 03
+             00 00 00 01 68 EE 3C B0
+             00 00 01 65 88 00 00").to_vec());
+        let expected: [&[u8]; 3] = [
+            &hex!("67 64 00 0A AC 72 84 44 26 84 00 00 03"),
+            &hex!("68 EE 3C B0"),
+            &hex!("65 88 00 00"),
+        ];
+        for chunk in 1..=data.len() {
+            let mut r = BytesNalReader::new();
+            let mut nals = vec![];
+            let mut i = 0;
+            while i < data.len() {
+                let end = (i + chunk).min(data.len());
+                r.push(data.slice(i..end));
+                i = end;
+                while let Some(nal) = r.next_nal() {
+                    nals.push(nal);
+                }
+            }
+            r.end_units();
+            nals.extend(r.next_nal());
+            assert!(r.next_nal().is_none());
+            let flat: Vec<Vec<u8>> = nals.iter().map(|n| n.clone().into_bytes().to_vec()).collect();
+            assert_eq!(flat, expected, "chunk={}", chunk);
+            assert_eq!(nals.iter().map(BytesNal::len).collect::<Vec<_>>(), [13, 4, 4]);
+            assert_eq!(nals[1].header().unwrap().nal_unit_type(), UnitType::PicParameterSet);
+            let payload = nals[1].with_ref_nal(|n| n.reader().read_remaining_payload("pps").unwrap());
+            assert_eq!(payload, hex!("EE 3C B0"));
+        }
+
+        // the SPS is too large, and is dropped.
+        let mut r = BytesNalReader::new();
+        r.set_max_nal_size(Some(12));
+        r.push(data.slice(..10));
+        r.push(data.slice(10..));
+        r.end_units();
+        let sizes: Vec<_> = std::iter::from_fn(|| r.next_nal()).map(|n| n.len()).collect();
+        assert_eq!(sizes, [4, 4]);
+
+        // a single push yields chunks sharing the input buffer,
+        let mut r = BytesNalReader::new();
+        r.push(data.clone());
+        let nal = r.next_nal().unwrap();
+        assert_eq!(nal.chunks().len(), 1);
+        assert_eq!(nal.chunks()[0].as_ptr(), data[4..].as_ptr());
+    }
+
+    #[cfg(feature = "stream")]
+    #[test]
+    fn bytes_stream() {
+        use futures_core::Stream;
+        use std::pin::Pin;
+        use std::task::{Poll, Waker};
+
+        let waker = Waker::from(std::sync::Arc::new(NoopWaker));
+        let mut cx = std::task::Context::from_waker(&waker);
+
+        /// Yields each buffer in turn, returning `Pending` before each.
+        struct Bufs {
+            bufs: std::collections::VecDeque<bytes::Bytes>,
+            ready: bool,
+        }
+        impl Stream for Bufs {
+            type Item = bytes::Bytes;
+
+            fn poll_next(mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Option<Self::Item>> {
+                self.ready = !self.ready;
+                if !self.ready {
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+                Poll::Ready(self.bufs.pop_front())
+            }
+        }
+
+        let bufs = Bufs {
+            bufs: vec![
+                bytes::Bytes::from(hex!("00 00 00 01 09 10 00").to_vec()),
+                bytes::Bytes::from(hex!("00 01 67 64 00 00 03 01 00 00").to_vec()),
+                bytes::Bytes::from(hex!("01 68 ee 00 00").to_vec()),
+            ].into(),
+            ready: true,
+        };
+        let mut s = BytesNalStream::new(bufs);
+        let mut nals = Vec::new();
+        let mut pending = 0;
+        loop {
+            match Pin::new(&mut s).poll_next(&mut cx) {
+                Poll::Ready(Some(nal)) => nals.push(nal.into_bytes().to_vec()),
+                Poll::Ready(None) => break,
+                Poll::Pending => pending += 1,
+            }
+        }
+        assert_eq!(pending, 4);
+        assert_eq!(nals, vec![
+            hex!("09 10").to_vec(),
+            hex!("67 64 00 00 03 01").to_vec(),
+            hex!("68 ee 00 00").to_vec(),
+        ]);
+    }
+
+    #[test]
+    fn iter() {
+        let data = hex!(
+           "00 00 00 01 09 10
+            00 00 01 67 64 00 00 03 01 00 00
+            00 00 01 00 00 01 68 ee
+            00 00 00 00 00 01 65 88 00 00 00 00");
+        let nals: Vec<_> = iter_nals(&data[..]).map(|n| n.unwrap().reader().read_remaining_payload("nal").unwrap()).collect();
+        assert_eq!(nals, vec![
+            vec![0x10],
+            vec![0x64, 0x00, 0x00, 0x01, 0x00],  // emulation prevention removed
+            vec![0xee],  // trailing_zero_8bits dropped
+            vec![0x88, 0x00, 0x00, 0x00, 0x00],
+        ]);
+        assert_eq!(iter_nals(&data[..]).map(|n| n.unwrap().header().unwrap().nal_unit_type()).collect::<Vec<_>>(), vec![
+            UnitType::AccessUnitDelimiter,
+            UnitType::SeqParameterSet,
+            UnitType::PicParameterSet,
+            UnitType::SliceLayerWithoutPartitioningIdr,
+        ]);
+
+        let mut i = iter_nals(&hex!("ff 00 00 01 09 10")[..]);
+        assert!(matches!(i.next(), Some(Err(AnnexBError::DataBeforeStartCode { len: 1 }))));
+        assert!(matches!(i.next(), Some(Ok(_))));
+        assert!(i.next().is_none());
+        assert!(iter_nals(&[]).next().is_none());
+        assert!(matches!(iter_nals(&hex!("01 02")[..]).next(), Some(Err(AnnexBError::DataBeforeStartCode { len: 2 }))));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn async_reader() {
+        use futures_core::Stream;
+        use std::pin::Pin;
+        use std::task::{Poll, Waker};
+
+        let waker = Waker::from(std::sync::Arc::new(NoopWaker));
+        let mut cx = std::task::Context::from_waker(&waker);
+
+        let data = hex!(
+           "00 00 00 01 09 10
+            00 00 01 67 64 00 00 03 01
+            00 00 01 68 ee 00 00");
+        let mut r = AsyncNalReader::new(&data[..]);
+        let mut nals = Vec::new();
+        loop {
+            match Pin::new(&mut r).poll_next(&mut cx) {
+                Poll::Ready(Some(nal)) => nals.push(nal.unwrap()),
+                Poll::Ready(None) => break,
+                Poll::Pending => panic!("slice reader should never be pending"),
+            }
+        }
+        assert_eq!(nals, vec![
+            hex!("09 10").to_vec(),
+            hex!("67 64 00 00 03 01").to_vec(),
+            hex!("68 ee 00 00").to_vec(),
+        ]);
+    }
+
+    #[test]
+    fn scan_matches_iter_nals() {
+        // pseudo-random escaped NAL units in which zeros and start codes are common.
+        let mut seed = 1u32;
+        let mut data = hex!("00 00 00 01 65").to_vec();
+        for _ in 0..200 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let x = 0x40 | ((seed >> 8) as u8 & 0x07);
+            match (seed >> 16) % 8 {
+                0..=2 => data.push(x),
+                3 => data.extend_from_slice(&[0x00, x]),
+                4 => data.extend_from_slice(&[0x00, 0x00, 0x03]),
+                5 => data.extend_from_slice(&[0x00, 0x00, 0x01, x]),
+                6 => data.extend_from_slice(&[0x00, 0x00, 0x00, 0x01, x]),
+                _ => data.push(0x01),
+            }
+        }
+        let expected: Vec<(u8, Vec<u8>)> = iter_nals(&data[..])
+            .map(|n| {
+                let n = n.unwrap();
+                (n.header().unwrap().into(), n.reader().read_remaining_payload("nal").unwrap())
+            })
+            .collect();
+        assert!(expected.len() > 3);
+        let mut ctx = Context::default();
+        for split in 0..data.len() {
+            let mut r = AnnexBReader::new(Collect(Vec::new()));
+            r.start(&mut ctx);
+            r.push(&mut ctx, &data[..split]);
+            r.push(&mut ctx, &data[split..]);
+            r.end_units(&mut ctx);
+            let actual: Vec<(u8, Vec<u8>)> = r.nal_reader.0.iter()
+                .map(|n| (n[0], crate::rbsp::decode_nal(&n[1..]).into_owned()))
+                .collect();
+            assert_eq!(actual, expected, "split={}", split);
+        }
+    }
+
+    #[test]
+    fn writer() {
+        let nals: &[&[u8]] = &[
+            &hex!("09 10"),
+            &hex!("67 64 00 0a"),
+            &hex!("68 ee 06 f2"),
+            &hex!("06 05 01 80"),
+            &hex!("65 88 84"),
+            &hex!("65 00 10"),
+            &hex!("06 05 01 80"),
+            &hex!("41 9a 03"),
+        ];
+        let mut w = AnnexBWriter::new(Vec::new());
+        for nal in nals {
+            w.write_nal(nal).unwrap();
+        }
+        assert!(w.write_nal(&[]).is_err());
+        assert!(w.write_nal(&hex!("80")).is_err());
+        assert_eq!(w.write_nal(&hex!("41 9a 00")).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(w.write_nal_with_zeros(&hex!("41 9a 00"), 2).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        let data = w.into_inner();
+        assert_eq!(&data[..], &hex!(
+           "00 00 00 01 09 10
+            00 00 00 01 67 64 00 0a
+            00 00 00 01 68 ee 06 f2
+            00 00 01 06 05 01 80
+            00 00 01 65 88 84
+            00 00 01 65 00 10
+            00 00 00 01 06 05 01 80
+            00 00 01 41 9a 03")[..]);
+        let read: Vec<_> = iter_nals(&data[..]).map(|n| n.unwrap().header().unwrap().nal_unit_type().id()).collect();
+        assert_eq!(read, vec![9, 7, 8, 6, 5, 5, 6, 1]);
+
+        let mut w = AnnexBWriter::new(Vec::new());
+        w.write_nal(&hex!("65 88")).unwrap();
+        w.write_nal(&hex!("65 88")).unwrap();
+        w.set_always_long_start_codes(true);
+        w.write_nal(&hex!("65 88")).unwrap();
+        assert_eq!(&w.get_ref()[..], &hex!("00 00 00 01 65 88 00 00 01 65 88 00 00 00 01 65 88")[..]);
+    }
+
+    #[test]
+    fn unit_end() {
+        assert_eq!(find_unit_end(&hex!("65 00 88 00 00 03 00 00 02 00 00 01 09")[..]), Some(9));
+        assert_eq!(find_unit_end(&hex!("65 00 88 00 00 00 01")[..]), Some(3));
+        assert_eq!(find_unit_end(&hex!("65 00 88 00 00 03 00")[..]), Some(6));
+        assert_eq!(find_unit_end(&hex!("65 00 88 00 00")[..]), Some(3));
+        assert_eq!(find_unit_end(&hex!("65 00 88 00 00 03")[..]), None);
+        assert_eq!(find_unit_end(&[]), None);
+    }
+
+    #[test]
+    fn damage_independent_of_split() {
+        let data = hex!("00 00 01 65 88 00 ab 00 00 00 00 7f 00 ab 00 00 01 09 30");
+        let mut ctx = Context::default();
+        for split in 0..data.len() {
+            let state = Rc::new(RefCell::new(State { started: 0, ended: 0, data: Vec::new() }));
+            let mut r = AnnexBReader::new(MockReader::new(Rc::clone(&state)));
+            r.start(&mut ctx);
+            r.push(&mut ctx, &data[..split]);
+            r.push(&mut ctx, &data[split..]);
+            r.end_units(&mut ctx);
+            // parsing stops at the forbidden run of zeros, so the second NAL unit isn't seen.
+            assert_eq!(state.borrow().started, 1, "split={}", split);
+        }
+    }
+
+    #[test]
+    fn resync() {
+        let data = hex!(
+           "ff ff 00 00 01 09 10
+            00 00 01 65 88 00 00 00 00 7f 00 ab 00 00 01 09 30
+            00 00 01 41 00 00 00 00 00");
+        let discards = Rc::new(RefCell::new(Vec::new()));
+        let discards2 = Rc::clone(&discards);
+        let mut ctx = Context::default();
+        for &split in &[1, 5, 13, 15, 17] {
+            discards.borrow_mut().clear();
+            let mut r = AnnexBReader::new(Collect(Vec::new()));
+            let discards2 = Rc::clone(&discards2);
+            r.set_resync_handler(Some(Box::new(move |_ctx, len| discards2.borrow_mut().push(len))));
+            r.start(&mut ctx);
+            r.push(&mut ctx, &data[..split]);
+            r.push(&mut ctx, &data[split..]);
+            r.end_units(&mut ctx);
+            assert_eq!(&r.nal_reader.0[..], &[
+                hex!("09 10").to_vec(),
+                hex!("09 30").to_vec(),
+                hex!("41 00 00 00 00 00").to_vec(),
+            ][..], "split={}", split);
+            assert_eq!(&discards.borrow()[..], &[2, 3][..], "split={}", split);
+        }
+
+        // without a handler, parsing stops at the damage, and the damaged NAL unit is dropped.
+        let mut r = AnnexBReader::new(Collect(Vec::new()));
+        r.start(&mut ctx);
+        r.push(&mut ctx, &data[..]);
+        r.end_units(&mut ctx);
+        assert_eq!(&r.nal_reader.0[..], &[hex!("09 10").to_vec()][..]);
+    }
+
+    #[test]
+    fn unexpected_byte_actions() {
+        let data = hex!(
+           "00 00 01 09 10
+            00 00 01 65 88 00 00 00 00 7f 00 00 01 09 30");
+        let run = |action: crate::ErrorAction, split: usize| {
+            let mut ctx = Context::default();
+            ctx.set_error_handler(Some(Box::new(move |e: &crate::ReportedError| {
+                assert!(matches!(e.kind, ErrorKind::AnnexB(AnnexBError::UnexpectedByte { byte: 0x7f, offset: 14 })));
+                action
+            })));
+            let mut r = AnnexBReader::new(Collect(Vec::new()));
+            r.set_resync_handler(Some(Box::new(|_ctx, _len| {})));
+            r.start(&mut ctx);
+            r.push(&mut ctx, &data[..split]);
+            r.push(&mut ctx, &data[split..]);
+            r.end_units(&mut ctx);
+            (r.into_nal_reader().0, ctx.is_aborted())
+        };
+        for &split in &[1, 10, data.len()] {
+            // the damaged NAL unit is ended at the damage.
+            let (nals, aborted) = run(crate::ErrorAction::Continue, split);
+            assert_eq!(&nals[..], &[hex!("09 10").to_vec(), hex!("65 88 00").to_vec(), hex!("09 30").to_vec()][..], "split={}", split);
+            assert!(!aborted);
+
+            // the damaged NAL unit is dropped.
+            let (nals, aborted) = run(crate::ErrorAction::SkipNal, split);
+            assert_eq!(&nals[..], &[hex!("09 10").to_vec(), hex!("09 30").to_vec()][..], "split={}", split);
+            assert!(!aborted);
+
+            // nothing after the damage is parsed.
+            let (nals, aborted) = run(crate::ErrorAction::Abort, split);
+            assert_eq!(nals[0], hex!("09 10").to_vec(), "split={}", split);
+            assert!(!nals.contains(&hex!("09 30").to_vec()), "split={}", split);
+            assert!(aborted);
+        }
+    }
+
+    #[test]
+    fn discarded_bytes_abort() {
+        let data = hex!("ff 00 00 01 09 10");
+        let mut ctx = Context::default();
+        ctx.set_error_handler(Some(Box::new(|_e: &crate::ReportedError| crate::ErrorAction::Abort)));
+        let mut r = AnnexBReader::new(Collect(Vec::new()));
+        r.set_resync_handler(Some(Box::new(|_ctx, _len| panic!("resync handler called after abort"))));
+        r.start(&mut ctx);
+        r.push(&mut ctx, &data[..]);
+        r.end_units(&mut ctx);
+        assert!(ctx.is_aborted());
+        assert!(r.nal_reader.0.is_empty());
+    }
+
+    #[test]
+    fn discarded_bytes_reported_once() {
+        let mut data = vec![0xff; 1000];
+        data.extend_from_slice(&hex!("00 7f 00 00 01 09 10"));
+        let expected = format!("{:?}", ErrorKind::AnnexB(AnnexBError::DiscardedBytes { offset: 0, len: 1002 }));
+        for &chunk_size in &[1, 7, data.len()] {
+            let errors = Arc::new(Mutex::new(Vec::new()));
+            let errors2 = Arc::clone(&errors);
+            let mut ctx = Context::default();
+            ctx.set_error_handler(Some(Box::new(move |e: &crate::ReportedError| {
+                errors2.lock().unwrap().push(format!("{:?}", e.kind));
+                crate::ErrorAction::Continue
+            })));
+            let state = Rc::new(RefCell::new(State { started: 0, ended: 0, data: Vec::new() }));
+            let mut r = AnnexBReader::new(MockReader::new(Rc::clone(&state)));
+            r.start(&mut ctx);
+            for chunk in data.chunks(chunk_size) {
+                r.push(&mut ctx, chunk);
+            }
+            r.end_units(&mut ctx);
+            assert_eq!(&errors.lock().unwrap()[..], &[expected.clone()][..], "chunk_size={}", chunk_size);
+            assert_eq!(&state.borrow().data[..], &[0x09, 0x10][..]);
+        }
+    }
+
+    #[test]
+    fn nal_position() {
+        use crate::nal::{NalAccumulator, NalSwitch};
+        let data = hex!(
+           "00 00 00 01 09 10
+            00 00 01 67 64 00 0a
+            00 00 00 01 68 ee
+            00 00 01 65 88 00 00");
+        let nals = Rc::new(RefCell::new(Vec::new()));
+        let nals2 = Rc::clone(&nals);
+        let mut s = NalSwitch::default();
+        s.set_default_handler(Some(Box::new(RefCell::new(NalAccumulator::new(move |ctx: &mut Context<()>, nal| {
+            nals2.borrow_mut().push((ctx.nal_offset().unwrap(), ctx.nal_len().unwrap(), ctx.nal_start_code_zeros().unwrap(), nal));
+        })))));
+        let mut ctx = Context::default();
+        for split in 0..data.len() {
+            nals.borrow_mut().clear();
+            let mut r = AnnexBReader::new(s);
+            r.start(&mut ctx);
+            r.push(&mut ctx, &data[..split]);
+            r.push(&mut ctx, &data[split..]);
+            r.end_units(&mut ctx);
+            let positions: Vec<_> = nals.borrow().iter().map(|&(offset, len, zeros, _)| (offset, len, zeros)).collect();
+            assert_eq!(positions, vec![(4, 2, 3), (9, 4, 2), (17, 2, 3), (22, 4, 2)], "split={}", split);
+            for (offset, len, _, nal) in nals.borrow().iter() {
+                assert_eq!(&data[*offset as usize..(*offset + *len) as usize], &nal[..]);
+            }
+            s = r.nal_reader;
+        }
+    }
+
+    #[test]
+    fn leading_zeros() {
+        struct Zeros(Vec<usize>);
+        impl NalReader for Zeros {
+            type Ctx = ();
+            fn start(&mut self, ctx: &mut Context<Self::Ctx>) {
+                self.0.push(ctx.nal_start_code_zeros().unwrap());
+            }
+            fn push(&mut self, _ctx: &mut Context<Self::Ctx>, _buf: &[u8]) {}
+            fn end(&mut self, _ctx: &mut Context<Self::Ctx>) {}
+        }
+        let data = hex!("00 00 00 00 00 01 09 10 00 00 01 09 10 00 00 00 01 09 10");
+        let mut ctx = Context::default();
+        for split in 0..data.len() {
+            let mut r = AnnexBReader::new(Zeros(Vec::new()));
+            r.start(&mut ctx);
+            r.push(&mut ctx, &data[..split]);
+            r.push(&mut ctx, &data[split..]);
+            r.end_units(&mut ctx);
+            assert_eq!(r.nal_reader.0, vec![5, 2, 3], "split={}", split);
+        }
+
+        // the zeros allow the stream to be rewritten exactly.
+        let mut w = AnnexBWriter::new(Vec::new());
+        for &zeros in &[5, 2, 3] {
+            w.write_nal_with_zeros(&hex!("09 10"), zeros).unwrap();
+        }
+        assert_eq!(&w.into_inner()[..], &data[..]);
+    }
 }
@@ -2,12 +2,14 @@
 //! File Format_ (AKA MP4), as the specified in _ISO/IEC 14496-15_.
 //!
 
-use crate::nal::{sps, UnitType, NalHeader, NalHeaderError, pps, NalHandler};
+use crate::nal::{sps, UnitType, NalHeader, NalHeaderError, pps};
 use std::convert::TryFrom;
-use crate::nal::sps::{ProfileIdc, Level, ConstraintFlags, SeqParameterSet, SeqParameterSetNalHandler};
+use crate::nal::sps::{ProfileIdc, Level, ConstraintFlags, SeqParameterSet};
 use crate::Context;
-use crate::nal::pps::PicParameterSetNalHandler;
+use crate::nal::pps::PicParameterSet;
 use crate::rbsp;
+use crate::rbsp::BitRead;
+use crate::annexb::NalReader;
 
 #[derive(Debug)]
 pub enum AvccError {
@@ -17,6 +19,27 @@ pub enum AvccError {
     ParamSet(ParamSetError),
     Sps(sps::SpsError),
     Pps(pps::PpsError),
+    /// The NAL unit length size must be 1, 2 or 4 bytes.
+    InvalidLengthSize(usize),
+    /// The sample ended part way through the length prefix at the given offset.
+    TruncatedLength { offset: usize },
+    /// The length prefix at the given offset was larger than the remainder of the sample.
+    NalLengthTooLarge { offset: usize, len: usize, remaining: usize },
+    /// The length prefix at the given offset was zero.
+    EmptyNal { offset: usize },
+    /// A configuration record must contain at least one _sequence parameter set_.
+    MissingSps,
+    /// More parameter sets of the given type were supplied than a configuration record can hold.
+    TooManyParamSets { unit_type: UnitType, count: usize },
+    /// A parameter set longer than the 65535 bytes a configuration record can hold.
+    ParamSetTooLarge { unit_type: UnitType, len: usize },
+    /// The Annex B data to be converted was malformed.
+    AnnexB(crate::annexb::AnnexBError),
+    /// A NAL unit was too long for its length to be represented in the given number of bytes.
+    NalTooLargeForLengthSize { len: usize, length_size: usize },
+    /// A NAL unit lacked a valid header byte, or ended with a `0x00` byte, so could not be
+    /// written with Annex B framing.
+    InvalidNal,
 }
 
 pub struct AvcDecoderConfigurationRecord<'buf> {
@@ -91,7 +114,11 @@ impl<'buf> AvcDecoderConfigurationRecord<'buf> {
         self.data[2].into()
     }
     pub fn avc_level_indication(&self) -> Level {
-        Level::from_constraint_flags_and_level_idc(self.profile_compatibility(), self.data[3])
+        Level::from_profile_idc_constraint_flags_and_level_idc(
+            self.avc_profile_indication(),
+            self.profile_compatibility(),
+            self.data[3],
+        )
     }
     /// Number of bytes used to specify the length of each NAL unit
     /// 0 => 1 byte, 1 => 2 bytes, 2 => 3 bytes, 3 => 4 bytes
@@ -116,30 +143,553 @@ impl<'buf> AvcDecoderConfigurationRecord<'buf> {
     /// this `AvcDecoderConfigurationRecord`.
     ///
     /// In particular, the _sequence parameter set_ and _picture parameter set_ values of this
-    /// configuration record will be inserted into the resulting context.
+    /// configuration record will be inserted into the resulting context (see `insert_into()`).
     pub fn create_context<C>(&self, ctx: C) -> Result<Context<C>, AvccError> {
         let mut ctx = Context::new(ctx);
-        let mut sps_decode = rbsp::RbspDecoder::new(SeqParameterSetNalHandler::default());
-        for sps in self.sequence_parameter_sets() {
-            sps_decode.push(&mut ctx, sps.map_err(AvccError::ParamSet)?);
-            sps_decode.end(&mut ctx);
+        self.insert_into(&mut ctx)?;
+        Ok(ctx)
+    }
+
+    /// Parses the _sequence parameter set_ and _picture parameter set_ values of this
+    /// configuration record, inserting them into the given context (replacing any existing
+    /// parameter sets with the same ids).
+    ///
+    /// Parameter sets which fail to parse are reported as `AvccError::Sps` or `AvccError::Pps`;
+    /// any parameter sets preceding the failure will already have been inserted.
+    pub fn insert_into<C>(&self, ctx: &mut Context<C>) -> Result<(), AvccError> {
+        let sps_nals = LayeredParamSetIter {
+            data: &self.data[Self::MIN_CONF_SIZE..],
+            remaining: self.num_of_sequence_parameter_sets(),
+            types: &[UnitType::SeqParameterSet],
+        };
+        for nal in sps_nals {
+            let nal = nal.map_err(AvccError::ParamSet)?;
+            let sps = SeqParameterSet::from_bytes(&rbsp::decode_nal(&nal[1..])).map_err(AvccError::Sps)?;
+            ctx.put_seq_param_set_with_nal(sps, nal.to_vec());
         }
-        let mut pps_decode = rbsp::RbspDecoder::new(PicParameterSetNalHandler::default());
-        for pps in self.picture_parameter_sets() {
-            pps_decode.push(&mut ctx, pps.map_err(AvccError::ParamSet)?);
-            pps_decode.end(&mut ctx);
+        let offset = self.seq_param_sets_end().unwrap();
+        let pps_nals = LayeredParamSetIter {
+            data: &self.data[offset + 1..],
+            remaining: usize::from(self.data[offset]),
+            types: &[UnitType::PicParameterSet],
+        };
+        for nal in pps_nals {
+            let nal = nal.map_err(AvccError::ParamSet)?;
+            let pps = PicParameterSet::from_bytes(ctx, &rbsp::decode_nal(&nal[1..])).map_err(AvccError::Pps)?;
+            ctx.put_pic_param_set_with_nal(pps, nal.to_vec());
         }
-        Ok(ctx)
+        Ok(())
     }
 }
 
+/// The layout shared by `SVCDecoderConfigurationRecord` and `MVCDecoderConfigurationRecord`,
+/// whose parameter set arrays may hold subset sequence parameter sets, and which give the NAL
+/// header byte of each parameter set (unlike [AvcDecoderConfigurationRecord]'s iterators).
+struct LayeredRecord<'buf> {
+    data: &'buf [u8],
+}
+impl<'buf> LayeredRecord<'buf> {
+    const MIN_CONF_SIZE: usize = 6;
+
+    fn new(data: &'buf [u8]) -> Result<Self, AvccError> {
+        let rec = LayeredRecord { data };
+        rec.ck(Self::MIN_CONF_SIZE)?;
+        if data[0] != 1 {
+            return Err(AvccError::UnsupportedConfigurationVersion(data[0]));
+        }
+        let mut len = Self::MIN_CONF_SIZE;
+        for _ in 0..rec.num_of_sequence_parameter_sets() {
+            len = rec.param_set_end(len)?;
+        }
+        rec.ck(len + 1)?;
+        let num_pps = data[len];
+        len += 1;
+        for _ in 0..num_pps {
+            len = rec.param_set_end(len)?;
+        }
+        Ok(rec)
+    }
+    fn param_set_end(&self, offset: usize) -> Result<usize, AvccError> {
+        self.ck(offset + 2)?;
+        let len = (usize::from(self.data[offset]) << 8) | usize::from(self.data[offset + 1]);
+        self.ck(offset + 2 + len)?;
+        Ok(offset + 2 + len)
+    }
+    fn ck(&self, len: usize) -> Result<(), AvccError> {
+        if self.data.len() < len {
+            Err(AvccError::NotEnoughData { expected: len, actual: self.data.len() })
+        } else {
+            Ok(())
+        }
+    }
+    fn num_of_sequence_parameter_sets(&self) -> usize {
+        (self.data[5] & 0b0111_1111) as usize
+    }
+    fn sequence_parameter_sets(&self) -> LayeredParamSetIter<'buf> {
+        LayeredParamSetIter {
+            data: &self.data[Self::MIN_CONF_SIZE..],
+            remaining: self.num_of_sequence_parameter_sets(),
+            types: &[UnitType::SeqParameterSet, UnitType::SubsetSeqParameterSet],
+        }
+    }
+    fn picture_parameter_sets(&self) -> LayeredParamSetIter<'buf> {
+        let mut offset = Self::MIN_CONF_SIZE;
+        for _ in 0..self.num_of_sequence_parameter_sets() {
+            offset = self.param_set_end(offset).unwrap();
+        }
+        LayeredParamSetIter {
+            data: &self.data[offset + 1..],
+            remaining: usize::from(self.data[offset]),
+            types: &[UnitType::PicParameterSet],
+        }
+    }
+    fn insert_into<C>(&self, ctx: &mut Context<C>) -> Result<(), AvccError> {
+        let mut subset_ids = vec![];
+        for nal in self.sequence_parameter_sets() {
+            let nal = nal.map_err(AvccError::ParamSet)?;
+            let rbsp = rbsp::decode_nal(&nal[1..]);
+            if nal[0] & 0b0001_1111 == UnitType::SubsetSeqParameterSet.id() {
+                // the seq_parameter_set_id follows the profile, constraint flags and level bytes
+                let mut r = rbsp::BitReaderSlice::new(&rbsp[..]);
+                r.read_u32(24).map_err(|e| AvccError::Sps(e.into()))?;
+                subset_ids.push(r.read_ue_named("seq_parameter_set_id").map_err(|e| AvccError::Sps(e.into()))?);
+                continue;
+            }
+            ctx.put_seq_param_set_with_nal(SeqParameterSet::from_bytes(&rbsp).map_err(AvccError::Sps)?, nal.to_vec());
+        }
+        for nal in self.picture_parameter_sets() {
+            let nal = nal.map_err(AvccError::ParamSet)?;
+            match PicParameterSet::from_bytes(ctx, &rbsp::decode_nal(&nal[1..])) {
+                Ok(pps) => ctx.put_pic_param_set_with_nal(pps, nal.to_vec()),
+                Err(pps::PpsError::UnknownSeqParamSetId(id)) if subset_ids.contains(&id.id().into()) => {},
+                Err(e) => return Err(AvccError::Pps(e)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Iterates over the parameter set NAL units (including the header byte) of a configuration
+/// record.
+struct LayeredParamSetIter<'buf> {
+    data: &'buf [u8],
+    remaining: usize,
+    types: &'static [UnitType],
+}
+impl<'buf> Iterator for LayeredParamSetIter<'buf> {
+    type Item = Result<&'buf [u8], ParamSetError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let len = (usize::from(self.data[0]) << 8) | usize::from(self.data[1]);
+        let (nal, rest) = self.data[2..].split_at(len);
+        self.data = rest;
+        Some(match nal.first().map(|&b| NalHeader::new(b)) {
+            None => Err(ParamSetError::Empty),
+            Some(Ok(header)) if self.types.contains(&header.nal_unit_type()) => Ok(nal),
+            Some(Ok(header)) => Err(ParamSetError::IncorrectNalType { expected: self.types[0], actual: header.nal_unit_type() }),
+            Some(Err(e)) => Err(ParamSetError::NalHeader(e)),
+        })
+    }
+}
+
+/// The `SVCDecoderConfigurationRecord` of an MP4 `svcC` box, describing a Scalable Video Coding
+/// stream.
+pub struct SvcDecoderConfigurationRecord<'buf> {
+    rec: LayeredRecord<'buf>,
+}
+impl<'buf> TryFrom<&'buf [u8]> for SvcDecoderConfigurationRecord<'buf> {
+    type Error = AvccError;
+
+    fn try_from(data: &'buf [u8]) -> Result<Self, Self::Error> {
+        Ok(SvcDecoderConfigurationRecord { rec: LayeredRecord::new(data)? })
+    }
+}
+impl<'buf> SvcDecoderConfigurationRecord<'buf> {
+    pub fn configuration_version(&self) -> u8 {
+        self.rec.data[0]
+    }
+    pub fn avc_profile_indication(&self) -> ProfileIdc {
+        self.rec.data[1].into()
+    }
+    pub fn profile_compatibility(&self) -> ConstraintFlags {
+        self.rec.data[2].into()
+    }
+    pub fn avc_level_indication(&self) -> Level {
+        Level::from_profile_idc_constraint_flags_and_level_idc(
+            self.avc_profile_indication(),
+            self.profile_compatibility(),
+            self.rec.data[3],
+        )
+    }
+    /// If true, the track contains every layer of the stream.
+    pub fn complete_representation(&self) -> bool {
+        self.rec.data[4] & 0b1000_0000 != 0
+    }
+    pub fn length_size_minus_one(&self) -> u8 {
+        self.rec.data[4] & 0b0000_0011
+    }
+    /// The sequence parameter set and subset sequence parameter set NAL units, each including
+    /// the NAL header byte.
+    pub fn sequence_parameter_sets(&self) -> impl Iterator<Item = Result<&'buf [u8], ParamSetError>> {
+        self.rec.sequence_parameter_sets()
+    }
+    /// The picture parameter set NAL units, each including the NAL header byte.
+    pub fn picture_parameter_sets(&self) -> impl Iterator<Item = Result<&'buf [u8], ParamSetError>> {
+        self.rec.picture_parameter_sets()
+    }
+    /// Inserts the sequence parameter sets of this record into the given context, along with the
+    /// picture parameter sets which refer to them.
+    ///
+    /// Subset sequence parameter sets (and picture parameter sets referring to them) are
+    /// skipped; they may be parsed with
+    /// [SubsetSeqParameterSet](../nal/subset_sps/struct.SubsetSeqParameterSet.html) and installed
+    /// with `Context::put_subset_seq_param_set`.
+    pub fn insert_into<C>(&self, ctx: &mut Context<C>) -> Result<(), AvccError> {
+        self.rec.insert_into(ctx)
+    }
+}
+
+/// The `MVCDecoderConfigurationRecord` of an MP4 `mvcC` box, describing a Multiview Video Coding
+/// (e.g. stereoscopic) stream.
+pub struct MvcDecoderConfigurationRecord<'buf> {
+    rec: LayeredRecord<'buf>,
+}
+impl<'buf> TryFrom<&'buf [u8]> for MvcDecoderConfigurationRecord<'buf> {
+    type Error = AvccError;
+
+    fn try_from(data: &'buf [u8]) -> Result<Self, Self::Error> {
+        Ok(MvcDecoderConfigurationRecord { rec: LayeredRecord::new(data)? })
+    }
+}
+impl<'buf> MvcDecoderConfigurationRecord<'buf> {
+    pub fn configuration_version(&self) -> u8 {
+        self.rec.data[0]
+    }
+    pub fn avc_profile_indication(&self) -> ProfileIdc {
+        self.rec.data[1].into()
+    }
+    pub fn profile_compatibility(&self) -> ConstraintFlags {
+        self.rec.data[2].into()
+    }
+    pub fn avc_level_indication(&self) -> Level {
+        Level::from_profile_idc_constraint_flags_and_level_idc(
+            self.avc_profile_indication(),
+            self.profile_compatibility(),
+            self.rec.data[3],
+        )
+    }
+    /// If true, the track contains every view of the stream.
+    pub fn complete_representation(&self) -> bool {
+        self.rec.data[4] & 0b1000_0000 != 0
+    }
+    /// If true, samples of the track are complete access units of all the views it contains.
+    pub fn explicit_au_track(&self) -> bool {
+        self.rec.data[4] & 0b0100_0000 != 0
+    }
+    pub fn length_size_minus_one(&self) -> u8 {
+        self.rec.data[4] & 0b0000_0011
+    }
+    /// The sequence parameter set and subset sequence parameter set NAL units, each including
+    /// the NAL header byte.
+    pub fn sequence_parameter_sets(&self) -> impl Iterator<Item = Result<&'buf [u8], ParamSetError>> {
+        self.rec.sequence_parameter_sets()
+    }
+    /// The picture parameter set NAL units, each including the NAL header byte.
+    pub fn picture_parameter_sets(&self) -> impl Iterator<Item = Result<&'buf [u8], ParamSetError>> {
+        self.rec.picture_parameter_sets()
+    }
+    /// Inserts the sequence parameter sets of this record into the given context, along with the
+    /// picture parameter sets which refer to them.
+    ///
+    /// Subset sequence parameter sets (and picture parameter sets referring to them) are
+    /// skipped; they may be parsed with
+    /// [SubsetSeqParameterSet](../nal/subset_sps/struct.SubsetSeqParameterSet.html) and installed
+    /// with `Context::put_subset_seq_param_set`.
+    pub fn insert_into<C>(&self, ctx: &mut Context<C>) -> Result<(), AvccError> {
+        self.rec.insert_into(ctx)
+    }
+}
+
+/// Builds the bytes of an `AVCDecoderConfigurationRecord` from the given _sequence parameter
+/// set_ and _picture parameter set_ NAL units (each including the NAL header byte, and without
+/// any start code or length prefix), with NAL unit lengths of `length_size` bytes.
+///
+/// The profile, compatibility and level bytes are taken from the first SPS, and for the High
+/// profiles the chroma format and bit depths are also given, as _ISO/IEC 14496-15_ requires.
+/// The result can be parsed with `AvcDecoderConfigurationRecord::try_from()`.
+pub fn build_record(sps: &[&[u8]], pps: &[&[u8]], length_size: usize) -> Result<Vec<u8>, AvccError> {
+    if !matches!(length_size, 1 | 2 | 4) {
+        return Err(AvccError::InvalidLengthSize(length_size));
+    }
+    check_param_sets(sps, UnitType::SeqParameterSet, 0b0001_1111)?;
+    check_param_sets(pps, UnitType::PicParameterSet, 0xff)?;
+    let first = sps.first().ok_or(AvccError::MissingSps)?;
+    let first = SeqParameterSet::from_bytes(&rbsp::decode_nal(&first[1..])).map_err(AvccError::Sps)?;
+    let profile_idc = u8::from(first.profile_idc);
+    let mut data = vec![
+        1,  // configurationVersion
+        profile_idc,
+        u8::from(first.constraint_flags),
+        first.level_idc,
+        0b1111_1100 | (length_size - 1) as u8,
+        0b1110_0000 | sps.len() as u8,
+    ];
+    for nal in sps {
+        data.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+        data.extend_from_slice(nal);
+    }
+    data.push(pps.len() as u8);
+    for nal in pps {
+        data.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+        data.extend_from_slice(nal);
+    }
+    if matches!(profile_idc, 100 | 110 | 122 | 144) {
+        let chroma_format_idc = match first.chroma_info.chroma_format {
+            sps::ChromaFormat::Monochrome => 0,
+            sps::ChromaFormat::YUV420 => 1,
+            sps::ChromaFormat::YUV422 => 2,
+            sps::ChromaFormat::YUV444 => 3,
+            sps::ChromaFormat::Invalid(idc) => idc as u8,
+        };
+        data.push(0b1111_1100 | (chroma_format_idc & 0b11));
+        data.push(0b1111_1000 | (first.chroma_info.bit_depth_luma_minus8 & 0b111));
+        data.push(0b1111_1000 | (first.chroma_info.bit_depth_chroma_minus8 & 0b111));
+        data.push(0);  // numOfSequenceParameterSetExt
+    }
+    Ok(data)
+}
+
+/// Builds the bytes of an `AVCDecoderConfigurationRecord` (see [build_record]) from the SPS with
+/// the given id held by `ctx` and the PPSs which refer to it, using the NAL units from which they
+/// were parsed (see `Context::sps_nal_by_id` and `Context::pps_nal_by_id`).
+///
+/// Returns `AvccError::MissingSps` if `ctx` has no NAL unit for the SPS.  PPSs installed without
+/// their NAL units are omitted.
+pub fn build_record_from_context<C>(ctx: &Context<C>, sps_id: pps::ParamSetId, length_size: usize) -> Result<Vec<u8>, AvccError> {
+    let sps = ctx.sps_nal_by_id(sps_id).ok_or(AvccError::MissingSps)?;
+    let pps: Vec<&[u8]> = ctx.pps()
+        .filter(|p| p.seq_parameter_set_id == sps_id)
+        .filter_map(|p| ctx.pps_nal_by_id(p.pic_parameter_set_id))
+        .collect();
+    build_record(&[sps], &pps[..], length_size)
+}
+
+fn check_param_sets(nals: &[&[u8]], unit_type: UnitType, max_count: usize) -> Result<(), AvccError> {
+    if nals.len() > max_count {
+        return Err(AvccError::TooManyParamSets { unit_type, count: nals.len() });
+    }
+    for nal in nals {
+        let header = NalHeader::new(*nal.first().ok_or(AvccError::ParamSet(ParamSetError::Empty))?)
+            .map_err(|e| AvccError::ParamSet(ParamSetError::NalHeader(e)))?;
+        if header.nal_unit_type() != unit_type {
+            return Err(AvccError::ParamSet(ParamSetError::IncorrectNalType { expected: unit_type, actual: header.nal_unit_type() }));
+        }
+        if nal.len() > usize::from(u16::MAX) {
+            return Err(AvccError::ParamSetTooLarge { unit_type, len: nal.len() });
+        }
+    }
+    Ok(())
+}
+
+/// Splits samples holding length-prefixed NAL units (as found in MP4 and Matroska files, rather
+/// than the start-code delimited Annex B format) and passes each NAL unit to the given
+/// [NalReader], which may be the same [NalSwitch](../nal/struct.NalSwitch.html) used with an
+/// [AnnexBReader](../annexb/struct.AnnexBReader.html).
+pub struct AvccReader<R, Ctx>
+    where
+        R: NalReader<Ctx=Ctx>
+{
+    nal_reader: R,
+    length_size: usize,
+}
+impl<R, Ctx> AvccReader<R, Ctx>
+    where
+        R: NalReader<Ctx=Ctx>
+{
+    /// Creates a reader for NAL units prefixed with big-endian lengths of `length_size` bytes,
+    /// which must be 1, 2 or 4.
+    pub fn new(nal_reader: R, length_size: usize) -> Result<Self, AvccError> {
+        match length_size {
+            1 | 2 | 4 => Ok(AvccReader { nal_reader, length_size }),
+            _ => Err(AvccError::InvalidLengthSize(length_size)),
+        }
+    }
+
+    /// Creates a reader using the `lengthSizeMinusOne` of the given configuration record.
+    pub fn for_record(nal_reader: R, record: &AvcDecoderConfigurationRecord<'_>) -> Result<Self, AvccError> {
+        Self::new(nal_reader, usize::from(record.length_size_minus_one()) + 1)
+    }
+
+    /// Passes each NAL unit of the given sample to the `NalReader`, calling `start()`, `push()`
+    /// and `end()` for each.
+    ///
+    /// Each length is checked against the remainder of the sample; on error, the NAL units
+    /// preceding the bad length will already have been read, and the rest of the sample is
+    /// ignored.
+    ///
+    /// Once an error handler has aborted parsing (see
+    /// [Context::report_error](../struct.Context.html#method.report_error)), the remaining NAL
+    /// units are ignored.
+    pub fn push_sample(&mut self, ctx: &mut Context<Ctx>, sample: &[u8]) -> Result<(), AvccError> {
+        for nal in SampleNals::new(sample, self.length_size) {
+            if ctx.is_aborted() {
+                break;
+            }
+            let nal = nal?;
+            self.nal_reader.start(ctx);
+            self.nal_reader.push(ctx, nal);
+            self.nal_reader.end(ctx);
+        }
+        Ok(())
+    }
+
+    pub fn nal_reader(&mut self) -> &mut R {
+        &mut self.nal_reader
+    }
+
+    pub fn into_nal_reader(self) -> R {
+        self.nal_reader
+    }
+}
+
+/// Iterates over the length-prefixed NAL units of a sample, stopping after any error.
+struct SampleNals<'a> {
+    sample: &'a [u8],
+    length_size: usize,
+    offset: usize,
+}
+impl<'a> SampleNals<'a> {
+    fn new(sample: &'a [u8], length_size: usize) -> Self {
+        SampleNals { sample, length_size, offset: 0 }
+    }
+}
+impl<'a> Iterator for SampleNals<'a> {
+    type Item = Result<&'a [u8], AvccError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let offset = self.offset;
+        if offset >= self.sample.len() {
+            return None;
+        }
+        self.offset = self.sample.len();
+        let remaining = self.sample.len() - offset;
+        if remaining < self.length_size {
+            return Some(Err(AvccError::TruncatedLength { offset }));
+        }
+        let len = self.sample[offset..offset + self.length_size]
+            .iter()
+            .fold(0usize, |len, &b| len << 8 | usize::from(b));
+        let remaining = remaining - self.length_size;
+        if len == 0 {
+            return Some(Err(AvccError::EmptyNal { offset }));
+        }
+        if len > remaining {
+            return Some(Err(AvccError::NalLengthTooLarge { offset, len, remaining }));
+        }
+        let start = offset + self.length_size;
+        self.offset = start + len;
+        Some(Ok(&self.sample[start..self.offset]))
+    }
+}
+
+/// _Sequence parameter set_ and _picture parameter set_ NAL units (each including the NAL header
+/// byte) moved out of, or to be inserted into, an access unit by [annexb_to_length_prefixed] and
+/// [length_prefixed_to_annexb].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ParamSets {
+    pub sps: Vec<Vec<u8>>,
+    pub pps: Vec<Vec<u8>>,
+}
+impl ParamSets {
+    pub fn is_empty(&self) -> bool {
+        self.sps.is_empty() && self.pps.is_empty()
+    }
+
+    /// Builds an `AVCDecoderConfigurationRecord` holding these parameter sets; see
+    /// [build_record].
+    pub fn build_record(&self, length_size: usize) -> Result<Vec<u8>, AvccError> {
+        let sps: Vec<&[u8]> = self.sps.iter().map(|n| &n[..]).collect();
+        let pps: Vec<&[u8]> = self.pps.iter().map(|n| &n[..]).collect();
+        build_record(&sps[..], &pps[..], length_size)
+    }
+}
+
+/// Converts a complete access unit from Annex B framing (with start codes) to NAL units prefixed
+/// with big-endian lengths of `length_size` bytes, as used within MP4 samples.
+///
+/// If `param_sets` is given, SPS and PPS NAL units are moved out of the access unit and appended
+/// to it (for example, to be placed in an `AVCDecoderConfigurationRecord` instead); otherwise
+/// they are kept in the sample.
+pub fn annexb_to_length_prefixed(data: &[u8], length_size: usize, mut param_sets: Option<&mut ParamSets>) -> Result<Vec<u8>, AvccError> {
+    if !matches!(length_size, 1 | 2 | 4) {
+        return Err(AvccError::InvalidLengthSize(length_size));
+    }
+    let max_len = if length_size == 4 { u32::MAX as usize } else { (1 << (8 * length_size)) - 1 };
+    let mut out = Vec::with_capacity(data.len());
+    let mut nals = crate::annexb::iter_nals(data);
+    while let Some(nal) = nals.next_slice() {
+        let nal = nal.map_err(AvccError::AnnexB)?;
+        if let Some(ref mut param_sets) = param_sets {
+            match UnitType::for_id(nal[0] & 0b0001_1111) {
+                Ok(UnitType::SeqParameterSet) => { param_sets.sps.push(nal.to_vec()); continue },
+                Ok(UnitType::PicParameterSet) => { param_sets.pps.push(nal.to_vec()); continue },
+                _ => {},
+            }
+        }
+        if nal.len() > max_len {
+            return Err(AvccError::NalTooLargeForLengthSize { len: nal.len(), length_size });
+        }
+        out.extend_from_slice(&(nal.len() as u32).to_be_bytes()[4 - length_size..]);
+        out.extend_from_slice(nal);
+    }
+    Ok(out)
+}
+
+/// Converts a complete access unit of NAL units prefixed with big-endian lengths of
+/// `length_size` bytes (as found in MP4 samples) to Annex B framing.
+///
+/// If `param_sets` is given, its SPS and PPS NAL units are inserted following any access unit
+/// delimiter, ahead of the rest of the access unit (as is needed for a decoder to be able to
+/// start at this access unit, when they were carried in an `AVCDecoderConfigurationRecord`).
+pub fn length_prefixed_to_annexb(sample: &[u8], length_size: usize, param_sets: Option<&ParamSets>) -> Result<Vec<u8>, AvccError> {
+    if !matches!(length_size, 1 | 2 | 4) {
+        return Err(AvccError::InvalidLengthSize(length_size));
+    }
+    let mut w = crate::annexb::AnnexBWriter::new(Vec::with_capacity(sample.len() + 64));
+    let mut pending = param_sets;
+    for nal in SampleNals::new(sample, length_size) {
+        let nal = nal?;
+        let is_aud = nal[0] & 0b0001_1111 == UnitType::AccessUnitDelimiter.id();
+        if !is_aud {
+            if let Some(param_sets) = pending.take() {
+                for ps in param_sets.sps.iter().chain(param_sets.pps.iter()) {
+                    w.write_nal(ps).map_err(|_| AvccError::InvalidNal)?;
+                }
+            }
+        }
+        w.write_nal(nal).map_err(|_| AvccError::InvalidNal)?;
+    }
+    if let Some(param_sets) = pending {
+        for ps in param_sets.sps.iter().chain(param_sets.pps.iter()) {
+            w.write_nal(ps).map_err(|_| AvccError::InvalidNal)?;
+        }
+    }
+    Ok(w.into_inner())
+}
+
 #[derive(Debug)]
 pub enum ParamSetError {
     NalHeader(NalHeaderError),
     IncorrectNalType { expected: UnitType, actual: UnitType },
+    /// A parameter set with a length of zero (i.e. lacking even the NAL header byte).
+    Empty,
     /// A _sequence parameter set_ found within the AVC decoder config was not consistent with the
     /// settings of the decoder config itself
-    IncompatibleSps(SeqParameterSet),
+    IncompatibleSps(Box<SeqParameterSet>),
 }
 
 struct ParamSetIter<'buf>(&'buf[u8], UnitType);
@@ -158,18 +708,18 @@ impl<'buf> Iterator for ParamSetIter<'buf>
             None
         } else {
             let len = u16::from(self.0[0]) << 8 | u16::from(self.0[1]);
-            let data = &self.0[2..];
-            let res = match NalHeader::new(data[0]) {
-                Ok(nal_header) => {
+            let (data, remainder) = self.0[2..].split_at(len as usize);
+            self.0 = remainder;
+            let res = match data.first().map(|&b| NalHeader::new(b)) {
+                None => Err(ParamSetError::Empty),
+                Some(Ok(nal_header)) => {
                     if nal_header.nal_unit_type() == self.1 {
-                        let (data, remainder) = data.split_at(len as usize);
-                        self.0 = remainder;
                         Ok(&data[1..])  // trim off the nal_header byte
                     } else {
                         Err(ParamSetError::IncorrectNalType { expected: self.1, actual: nal_header.nal_unit_type() })
                     }
                 },
-                Err(err) => Err(ParamSetError::NalHeader(err)),
+                Some(Err(err)) => Err(ParamSetError::NalHeader(err)),
             };
             Some(res)
         }
@@ -204,6 +754,8 @@ mod test {
         assert_eq!(ParamSetId::from_u32(0).unwrap(), sps.seq_parameter_set_id);
         let _pps = ctx.pps_by_id(ParamSetId::from_u32(0).unwrap())
             .expect("missing pps");
+        assert_eq!(ctx.sps_nal_by_id(ParamSetId::from_u32(0).unwrap()), Some(&avcc_data[8..40]));
+        assert_eq!(ctx.pps_nal_by_id(ParamSetId::from_u32(0).unwrap()), Some(&avcc_data[43..]));
     }
     #[test]
     fn sps_with_emulation_protection() {
@@ -217,4 +769,200 @@ mod test {
         let _sps = ctx.sps_by_id(ParamSetId::from_u32(0).unwrap())
             .expect("missing sps");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn avcc_reader() {
+        struct Collect(Vec<Vec<u8>>);
+        impl NalReader for Collect {
+            type Ctx = ();
+            fn start(&mut self, _ctx: &mut Context<()>) {
+                self.0.push(vec![]);
+            }
+            fn push(&mut self, _ctx: &mut Context<()>, buf: &[u8]) {
+                self.0.last_mut().unwrap().extend_from_slice(buf);
+            }
+            fn end(&mut self, _ctx: &mut Context<()>) {}
+        }
+        let mut ctx = Context::default();
+        let avcc_data = hex!("0142c01e ffe10020 6742c01e b91061ff 78088000 00030080 00001971 3006d600 daf7bdc0 7c2211a8 01000468 de3c80");
+        let avcc = AvcDecoderConfigurationRecord::try_from(&avcc_data[..]).unwrap();
+        let mut r = AvccReader::for_record(Collect(vec![]), &avcc).unwrap();
+        r.push_sample(&mut ctx, &hex!("00000002 0910 00000003 658880")[..]).unwrap();
+        assert_eq!(r.nal_reader().0, vec![hex!("0910").to_vec(), hex!("658880").to_vec()]);
+
+        for &(size, ref sample) in &[(1, hex!("02 0910 01 06").to_vec()), (2, hex!("0002 0910 0001 06").to_vec())] {
+            let mut r = AvccReader::new(Collect(vec![]), size).unwrap();
+            r.push_sample(&mut ctx, &sample[..]).unwrap();
+            assert_eq!(r.into_nal_reader().0, vec![vec![0x09, 0x10], vec![0x06]]);
+        }
+
+        assert!(matches!(AvccReader::new(Collect(vec![]), 3), Err(AvccError::InvalidLengthSize(3))));
+        let mut r = AvccReader::new(Collect(vec![]), 4).unwrap();
+        assert!(matches!(r.push_sample(&mut ctx, &hex!("00000001 09 0000")[..]), Err(AvccError::TruncatedLength { offset: 5 })));
+        assert!(matches!(r.push_sample(&mut ctx, &hex!("00000003 0910")[..]), Err(AvccError::NalLengthTooLarge { offset: 0, len: 3, remaining: 2 })));
+        assert!(matches!(r.push_sample(&mut ctx, &hex!("00000000")[..]), Err(AvccError::EmptyNal { offset: 0 })));
+        assert_eq!(r.nal_reader().0, vec![vec![0x09]]);
+    }
+
+    #[test]
+    fn insert_into() {
+        let mut ctx = Context::default();
+        let avcc_data = hex!("0142c01e ffe10020 6742c01e b91061ff 78088000 00030080 00001971 3006d600 daf7bdc0 7c2211a8 01000468 de3c80");
+        let avcc = AvcDecoderConfigurationRecord::try_from(&avcc_data[..]).unwrap();
+        avcc.insert_into(&mut ctx).unwrap();
+        assert_eq!(ctx.sps().count(), 1);
+        assert_eq!(ctx.pps().count(), 1);
+
+        // a truncated SPS,
+        let avcc_data = hex!("0142c01e ffe10002 67ff 00");
+        let avcc = AvcDecoderConfigurationRecord::try_from(&avcc_data[..]).unwrap();
+        assert!(matches!(avcc.create_context(()), Err(AvccError::Sps(_))));
+
+        // a PPS referring to an SPS which is not present,
+        let avcc_data = hex!("0142c01e ffe0 01 0004 68de3c80");
+        let avcc = AvcDecoderConfigurationRecord::try_from(&avcc_data[..]).unwrap();
+        assert!(matches!(avcc.create_context(()), Err(AvccError::Pps(_))));
+
+        // an empty parameter set,
+        let avcc_data = hex!("0142c01e ffe1 0000 00");
+        let avcc = AvcDecoderConfigurationRecord::try_from(&avcc_data[..]).unwrap();
+        assert!(matches!(avcc.create_context(()), Err(AvccError::ParamSet(ParamSetError::Empty))));
+    }
+
+    #[test]
+    fn build() {
+        let avcc_data = hex!("0142c01e ffe10020 6742c01e b91061ff 78088000 00030080 00001971 3006d600 daf7bdc0 7c2211a8 01000468 de3c80");
+        let sps = hex!("6742c01e b91061ff 78088000 00030080 00001971 3006d600 daf7bdc0 7c2211a8");
+        let pps = hex!("68de3c80");
+        assert_eq!(build_record(&[&sps[..]], &[&pps[..]], 4).unwrap(), &avcc_data[..]);
+
+        // High profile records have the chroma format and bit depths appended,
+        let sps = hex!("67 64 00 0A AC 72 84 44 26 84 00 00 03 00 04 00 00 03 00 CA 3C 48 96 11 80");
+        let record = build_record(&[&sps[..]], &[&pps[..]], 2).unwrap();
+        assert_eq!(&record[..6], &hex!("01 64 00 0A fd e1")[..]);
+        assert_eq!(&record[record.len() - 4..], &hex!("fd f8 f8 00")[..]);
+        let avcc = AvcDecoderConfigurationRecord::try_from(&record[..]).unwrap();
+        assert_eq!(avcc.length_size_minus_one(), 1);
+        assert_eq!(avcc.create_context(()).unwrap().pps().count(), 1);
+
+        assert!(matches!(build_record(&[], &[&pps[..]], 4), Err(AvccError::MissingSps)));
+        assert!(matches!(build_record(&[&pps[..]], &[], 4), Err(AvccError::ParamSet(ParamSetError::IncorrectNalType { .. }))));
+        assert!(matches!(build_record(&[&sps[..]], &[], 3), Err(AvccError::InvalidLengthSize(3))));
+        let many = vec![&pps[..]; 256];
+        assert!(matches!(build_record(&[&sps[..]], &many[..], 4), Err(AvccError::TooManyParamSets { count: 256, .. })));
+    }
+
+    #[test]
+    fn build_from_context() {
+        use crate::annexb::AnnexBReader;
+        use crate::nal::{pps::PicParameterSetNalHandler, sps::SeqParameterSetNalHandler, NalSwitch};
+        use std::cell::RefCell;
+        let annexb = hex!(
+            "00 00 00 01 67 64 00 0A AC 72 84 44 26 84 00 00 03 00 04 00 00 03 00 CA 3C 48 96 11 80
+             00 00 00 01 68 de 3c 80
+             00 00 01 65 88 84 00");
+        let sps = hex!("67 64 00 0A AC 72 84 44 26 84 00 00 03 00 04 00 00 03 00 CA 3C 48 96 11 80");
+        let pps = hex!("68 de 3c 80");
+        let mut s = NalSwitch::default();
+        s.put_handler(UnitType::SeqParameterSet, Box::new(RefCell::new(rbsp::RbspDecoder::new(SeqParameterSetNalHandler::default()))));
+        s.put_handler(UnitType::PicParameterSet, Box::new(RefCell::new(rbsp::RbspDecoder::new(PicParameterSetNalHandler::default()))));
+        let mut r = AnnexBReader::new(s);
+        let mut ctx = Context::default();
+        r.start(&mut ctx);
+        r.push(&mut ctx, &annexb[..]);
+        r.end_units(&mut ctx);
+
+        let id = ParamSetId::from_u32(0).unwrap();
+        let record = build_record_from_context(&ctx, id, 4).unwrap();
+        assert_eq!(record, build_record(&[&sps[..]], &[&pps[..]], 4).unwrap());
+        let avcc = AvcDecoderConfigurationRecord::try_from(&record[..]).unwrap();
+        assert_eq!(avcc.sequence_parameter_sets().next().unwrap().unwrap(), &sps[..]);
+        assert_eq!(avcc.picture_parameter_sets().next().unwrap().unwrap(), &pps[..]);
+
+        assert!(matches!(build_record_from_context(&ctx, ParamSetId::from_u32(1).unwrap(), 4), Err(AvccError::MissingSps)));
+    }
+
+    #[test]
+    fn convert() {
+        let annexb = hex!(
+            "00 00 00 01 09 10
+             00 00 00 01 67 64 00 0A AC 72 84 44 26 84 00 00 03 00 04 00 00 03 00 CA 3C 48 96 11 80
+             00 00 00 01 68 de 3c 80
+             00 00 01 06 05 01 ff 80
+             00 00 01 65 88 84 00");
+        let avcc = hex!(
+            "00000002 0910
+             00000019 6764000A AC728444 26840000 03000400 000300CA 3C489611 80
+             00000004 68de3c80
+             00000005 0605 01ff80
+             00000004 65888400");
+        assert_eq!(annexb_to_length_prefixed(&annexb[..], 4, None).unwrap(), &avcc[..]);
+        assert_eq!(length_prefixed_to_annexb(&avcc[..], 4, None).unwrap(), &annexb[..]);
+
+        // extracting the parameter sets, and then injecting them again,
+        let mut param_sets = ParamSets::default();
+        let sample = annexb_to_length_prefixed(&annexb[..], 2, Some(&mut param_sets)).unwrap();
+        assert_eq!(sample, hex!("0002 0910 0005 060501ff80 0004 65888400"));
+        assert_eq!(param_sets.sps, vec![avcc[10..35].to_vec()]);
+        assert_eq!(param_sets.pps, vec![hex!("68de3c80").to_vec()]);
+        assert_eq!(length_prefixed_to_annexb(&sample[..], 2, Some(&param_sets)).unwrap(), &annexb[..]);
+        let record = param_sets.build_record(4).unwrap();
+        assert_eq!(AvcDecoderConfigurationRecord::try_from(&record[..]).unwrap().create_context(()).unwrap().sps().count(), 1);
+
+        // without an AUD, the parameter sets come first,
+        let sample = hex!("0004 65888400");
+        assert_eq!(length_prefixed_to_annexb(&sample[..], 2, Some(&param_sets)).unwrap(), &hex!(
+            "00 00 00 01 67 64 00 0A AC 72 84 44 26 84 00 00 03 00 04 00 00 03 00 CA 3C 48 96 11 80
+             00 00 00 01 68 de 3c 80
+             00 00 01 65 88 84 00")[..]);
+
+        assert!(matches!(annexb_to_length_prefixed(&hex!("ff 00 00 01 09 10")[..], 4, None), Err(AvccError::AnnexB(_))));
+        assert!(annexb_to_length_prefixed(&annexb[..], 1, None).is_ok());
+        let big = [&hex!("00 00 01 65")[..], &[0x88; 300][..]].concat();
+        assert!(matches!(annexb_to_length_prefixed(&big[..], 1, None), Err(AvccError::NalTooLargeForLengthSize { len: 301, length_size: 1 })));
+        assert!(matches!(length_prefixed_to_annexb(&hex!("0005 0910")[..], 2, None), Err(AvccError::NalLengthTooLarge { .. })));
+        assert!(matches!(length_prefixed_to_annexb(&hex!("0002 0900")[..], 2, None), Err(AvccError::InvalidNal)));
+    }
+
+    #[test]
+    fn mvc_record() {
+        let sps = hex!("6742c01e b91061ff 78088000 00030080 00001971 3006d600 daf7bdc0 7c2211a8");
+        // a subset SPS with seq_parameter_set_id=1 (only the leading fields matter here),
+        let subset_sps = hex!("6f 76 00 1e 40");
+        let pps0 = hex!("68de3c80");
+        // pic_parameter_set_id=1, seq_parameter_set_id=1,
+        let pps1 = hex!("68 4b 8f 20");
+        let mut data = hex!("01 76 00 1e ff 82").to_vec();
+        for nal in &[&sps[..], &subset_sps[..]] {
+            data.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+            data.extend_from_slice(nal);
+        }
+        data.push(2);
+        for nal in &[&pps0[..], &pps1[..]] {
+            data.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+            data.extend_from_slice(nal);
+        }
+        let mvcc = MvcDecoderConfigurationRecord::try_from(&data[..]).unwrap();
+        assert_eq!(mvcc.configuration_version(), 1);
+        assert_eq!(mvcc.avc_profile_indication(), ProfileIdc::from(118));
+        assert!(mvcc.complete_representation());
+        assert!(mvcc.explicit_au_track());
+        assert_eq!(mvcc.length_size_minus_one(), 3);
+        let sps_nals: Vec<_> = mvcc.sequence_parameter_sets().map(Result::unwrap).collect();
+        assert_eq!(sps_nals, vec![&sps[..], &subset_sps[..]]);
+        assert_eq!(mvcc.picture_parameter_sets().count(), 2);
+        let mut ctx = Context::default();
+        mvcc.insert_into(&mut ctx).unwrap();
+        assert_eq!(ctx.sps().count(), 1);
+        assert_eq!(ctx.pps().count(), 1);
+
+        let svcc = SvcDecoderConfigurationRecord::try_from(&data[..]).unwrap();
+        assert!(svcc.complete_representation());
+        assert_eq!(svcc.sequence_parameter_sets().count(), 2);
+
+        assert!(matches!(MvcDecoderConfigurationRecord::try_from(&data[..data.len() - 1]), Err(AvccError::NotEnoughData { .. })));
+        data[6 + 2 + sps.len() + 2] = 0x68;  // a PPS where an SPS should be
+        let mvcc = MvcDecoderConfigurationRecord::try_from(&data[..]).unwrap();
+        assert!(matches!(mvcc.sequence_parameter_sets().nth(1), Some(Err(ParamSetError::IncorrectNalType { .. }))));
+    }
+}
@@ -0,0 +1,444 @@
+//! Decoding engine for the _Context-based Adaptive Binary Arithmetic Coding_ (CABAC) used for
+//! `ae(v)`-coded syntax elements in slice data, as specified in section 9.3 of the spec.
+//!
+//! This module provides the arithmetic decoding engine (`DecodeDecision`, `DecodeBypass` and
+//! `DecodeTerminate`) and initialisation of context variables.  Binarization of individual
+//! syntax elements and selection of `ctxIdx` values is left to the caller.  Initialisation
+//! values are only tabulated for `ctxIdx` values `0` to `72` (`mb_type`, `mb_skip_flag`,
+//! `sub_mb_type`, `mvd_lX`, `ref_idx_lX`, `mb_qp_delta`, the intra prediction modes and
+//! `mb_field_decoding_flag`); the residual block and `coded_block_pattern` context variables
+//! must be initialised via [ContextModel::new] given their `m` and `n` values from Tables 9-12
+//! to 9-33.  `end_of_slice_flag` is decoded with `DecodeTerminate`, which needs no context
+//! variable.
+
+use crate::rbsp::{BitRead, RbspBitReaderError};
+
+#[derive(Debug)]
+pub enum CabacError {
+    ReaderError(RbspBitReaderError),
+    /// The initial value of `codIOffset` was `510` or `511`, which the spec does not permit.
+    InvalidInitialOffset(u32),
+    /// `cabac_init_idc` must be between 0 and 2 inclusive.
+    InvalidCabacInitIdc(u32),
+}
+impl From<RbspBitReaderError> for CabacError {
+    fn from(e: RbspBitReaderError) -> Self {
+        CabacError::ReaderError(e)
+    }
+}
+
+/// The state of a single context variable, i.e. the probability estimate for one `ctxIdx`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContextModel {
+    p_state_idx: u8,
+    val_mps: bool,
+}
+impl ContextModel {
+    /// Initialises the context variable from its `m` and `n` values, per section 9.3.1.1.
+    pub fn new(m: i32, n: i32, slice_qp_y: i32) -> ContextModel {
+        let qp = clip3(0, 51, slice_qp_y);
+        let pre_ctx_state = clip3(1, 126, ((m * qp) >> 4) + n);
+        if pre_ctx_state <= 63 {
+            ContextModel { p_state_idx: (63 - pre_ctx_state) as u8, val_mps: false }
+        } else {
+            ContextModel { p_state_idx: (pre_ctx_state - 64) as u8, val_mps: true }
+        }
+    }
+
+    /// `pStateIdx`, in the range `0` to `63` inclusive.
+    pub fn p_state_idx(&self) -> u8 {
+        self.p_state_idx
+    }
+
+    /// `valMPS`, the value of the most probable symbol.
+    pub fn val_mps(&self) -> bool {
+        self.val_mps
+    }
+}
+
+fn clip3(x: i32, y: i32, z: i32) -> i32 {
+    if z < x {
+        x
+    } else if z > y {
+        y
+    } else {
+        z
+    }
+}
+
+/// Initialises context variables for `ctxIdx` values `0` to `72` inclusive, returning them
+/// indexed by `ctxIdx`.
+///
+/// `cabac_init_idc` should be `None` for I and SI slices, matching the slice header.  For I and
+/// SI slices, the context variables for `ctxIdx` `11` to `59` are not used, and are returned in
+/// an arbitrary state.
+pub fn init_contexts(cabac_init_idc: Option<u32>, slice_qp_y: i32) -> Result<Vec<ContextModel>, CabacError> {
+    let table = match cabac_init_idc {
+        None => &INIT_I,
+        Some(0) => &INIT_PB[0],
+        Some(1) => &INIT_PB[1],
+        Some(2) => &INIT_PB[2],
+        Some(other) => return Err(CabacError::InvalidCabacInitIdc(other)),
+    };
+    Ok(table.iter()
+        .map(|&(m, n)| ContextModel::new(i32::from(m), i32::from(n), slice_qp_y))
+        .collect())
+}
+
+/// The arithmetic decoding engine of section 9.3.3.2.
+///
+/// Reads from a [BitRead] which must be positioned at the first bit of CABAC-coded data (i.e.
+/// after `cabac_alignment_one_bit`), and which is passed back in to each decoding call.
+#[derive(Debug)]
+pub struct CabacDecoder {
+    cod_i_range: u32,
+    cod_i_offset: u32,
+}
+impl CabacDecoder {
+    /// Initialises the decoding engine, per section 9.3.1.2.
+    pub fn new<R: BitRead + ?Sized>(r: &mut R) -> Result<CabacDecoder, CabacError> {
+        let cod_i_offset = r.read_u32(9)?;
+        if cod_i_offset >= 510 {
+            return Err(CabacError::InvalidInitialOffset(cod_i_offset));
+        }
+        Ok(CabacDecoder {
+            cod_i_range: 510,
+            cod_i_offset,
+        })
+    }
+
+    /// `DecodeDecision`, decoding a bin using (and updating) the given context variable.
+    pub fn decode_decision<R: BitRead + ?Sized>(&mut self, r: &mut R, ctx: &mut ContextModel) -> Result<bool, CabacError> {
+        let q_cod_i_range_idx = ((self.cod_i_range >> 6) & 0b11) as usize;
+        let cod_i_range_lps = u32::from(RANGE_TAB_LPS[ctx.p_state_idx as usize][q_cod_i_range_idx]);
+        self.cod_i_range -= cod_i_range_lps;
+        let bin_val = if self.cod_i_offset >= self.cod_i_range {
+            self.cod_i_offset -= self.cod_i_range;
+            self.cod_i_range = cod_i_range_lps;
+            let bin_val = !ctx.val_mps;
+            if ctx.p_state_idx == 0 {
+                ctx.val_mps = !ctx.val_mps;
+            }
+            ctx.p_state_idx = TRANS_IDX_LPS[ctx.p_state_idx as usize];
+            bin_val
+        } else {
+            if ctx.p_state_idx < 62 {
+                ctx.p_state_idx += 1;
+            }
+            ctx.val_mps
+        };
+        self.renorm(r)?;
+        Ok(bin_val)
+    }
+
+    /// `DecodeBypass`, decoding a bin with equiprobable values.
+    pub fn decode_bypass<R: BitRead + ?Sized>(&mut self, r: &mut R) -> Result<bool, CabacError> {
+        self.cod_i_offset = (self.cod_i_offset << 1) | u32::from(r.read_bool()?);
+        Ok(if self.cod_i_offset >= self.cod_i_range {
+            self.cod_i_offset -= self.cod_i_range;
+            true
+        } else {
+            false
+        })
+    }
+
+    /// `DecodeTerminate`, used for `end_of_slice_flag` and the bin indicating the `I_PCM`
+    /// macroblock type.
+    ///
+    /// After this returns `true`, the decoding engine must not be used further; when decoding
+    /// `end_of_slice_flag`, the next bits of the reader will be the `rbsp_stop_one_bit`.
+    pub fn decode_terminate<R: BitRead + ?Sized>(&mut self, r: &mut R) -> Result<bool, CabacError> {
+        self.cod_i_range -= 2;
+        if self.cod_i_offset >= self.cod_i_range {
+            Ok(true)
+        } else {
+            self.renorm(r)?;
+            Ok(false)
+        }
+    }
+
+    /// `RenormD`
+    fn renorm<R: BitRead + ?Sized>(&mut self, r: &mut R) -> Result<(), CabacError> {
+        while self.cod_i_range < 256 {
+            self.cod_i_range <<= 1;
+            self.cod_i_offset = (self.cod_i_offset << 1) | u32::from(r.read_bool()?);
+        }
+        Ok(())
+    }
+}
+
+// Table 9-44, indexed by pStateIdx and qCodIRangeIdx
+const RANGE_TAB_LPS: [[u8; 4]; 64] = [
+    [128, 176, 208, 240], [128, 167, 197, 227], [128, 158, 187, 216], [123, 150, 178, 205],
+    [116, 142, 169, 195], [111, 135, 160, 185], [105, 128, 152, 175], [100, 122, 144, 166],
+    [ 95, 116, 137, 158], [ 90, 110, 130, 150], [ 85, 104, 123, 142], [ 81,  99, 117, 135],
+    [ 77,  94, 111, 128], [ 73,  89, 105, 122], [ 69,  85, 100, 116], [ 66,  80,  95, 110],
+    [ 62,  76,  90, 104], [ 59,  72,  86,  99], [ 56,  69,  81,  94], [ 53,  65,  77,  89],
+    [ 51,  62,  73,  85], [ 48,  59,  69,  80], [ 46,  56,  66,  76], [ 43,  53,  63,  72],
+    [ 41,  50,  59,  69], [ 39,  48,  56,  65], [ 37,  45,  54,  62], [ 35,  43,  51,  59],
+    [ 33,  41,  48,  56], [ 32,  39,  46,  53], [ 30,  37,  43,  50], [ 29,  35,  41,  48],
+    [ 27,  33,  39,  45], [ 26,  31,  37,  43], [ 24,  30,  35,  41], [ 23,  28,  33,  39],
+    [ 22,  27,  32,  37], [ 21,  26,  30,  35], [ 20,  24,  29,  33], [ 19,  23,  27,  31],
+    [ 18,  22,  26,  30], [ 17,  21,  25,  28], [ 16,  20,  23,  27], [ 15,  19,  22,  25],
+    [ 14,  18,  21,  24], [ 14,  17,  20,  23], [ 13,  16,  19,  22], [ 12,  15,  18,  21],
+    [ 12,  14,  17,  20], [ 11,  14,  16,  19], [ 11,  13,  15,  18], [ 10,  12,  15,  17],
+    [ 10,  12,  14,  16], [  9,  11,  13,  15], [  9,  11,  12,  14], [  8,  10,  12,  14],
+    [  8,   9,  11,  13], [  7,   9,  11,  12], [  7,   9,  10,  12], [  7,   8,  10,  11],
+    [  6,   8,   9,  11], [  6,   7,   9,  10], [  6,   7,   8,   9], [  2,   2,   2,   2],
+];
+
+// Table 9-45 (transIdxMPS is simply pStateIdx + 1, saturating at 62)
+const TRANS_IDX_LPS: [u8; 64] = [
+     0,  0,  1,  2,  2,  4,  4,  5,  6,  7,  8,  9,  9, 11, 11, 12,
+    13, 13, 15, 15, 16, 16, 18, 18, 19, 19, 21, 21, 22, 22, 23, 24,
+    24, 25, 26, 26, 27, 27, 28, 29, 29, 30, 30, 30, 31, 32, 32, 33,
+    33, 33, 34, 34, 35, 35, 35, 36, 36, 36, 37, 37, 37, 38, 38, 63,
+];
+
+// Tables 9-12 to 9-18, giving (m, n) for ctxIdx 0 to 72 in I and SI slices.  Entries 11 to 59
+// aren't used by these slice types.
+const INIT_I: [(i8, i8); 73] = [
+    // 0 - 10
+    (20, -15), (2, 54), (3, 74), (20, -15), (2, 54), (3, 74), (-28, 127), (-23, 104), (-6, 53),
+    (-1, 54), (7, 51),
+    // 11 - 23 (unused)
+    (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0),
+    (0, 0), (0, 0),
+    // 24 - 39 (unused)
+    (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0),
+    (0, 0), (0, 0), (0, 0), (0, 0), (0, 0),
+    // 40 - 53 (unused)
+    (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0),
+    (0, 0), (0, 0), (0, 0),
+    // 54 - 59 (unused)
+    (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0),
+    // 60 - 69
+    (0, 41), (0, 63), (0, 63), (0, 63), (-9, 83), (4, 86), (0, 97), (-7, 72), (13, 41), (3, 62),
+    // 70 - 72
+    (0, 11), (1, 55), (0, 69),
+];
+
+// Tables 9-12 to 9-18, giving (m, n) for ctxIdx 0 to 72 in P, SP and B slices, indexed by
+// cabac_init_idc.
+const INIT_PB: [[(i8, i8); 73]; 3] = [
+    [
+        // 0 - 10
+        (20, -15), (2, 54), (3, 74), (20, -15), (2, 54), (3, 74), (-28, 127), (-23, 104),
+        (-6, 53), (-1, 54), (7, 51),
+        // 11 - 23
+        (23, 33), (23, 2), (21, 0), (1, 9), (0, 49), (-37, 118), (5, 57), (-13, 78), (-11, 65),
+        (1, 62), (12, 49), (-4, 73), (17, 50),
+        // 24 - 39
+        (18, 64), (9, 43), (29, 0), (26, 67), (16, 90), (9, 104), (-46, 127), (-20, 104),
+        (1, 67), (-13, 78), (-11, 65), (1, 62), (-6, 86), (-17, 95), (-6, 61), (9, 45),
+        // 40 - 53
+        (-3, 69), (-6, 81), (-11, 96), (6, 55), (7, 67), (-5, 86), (2, 88), (0, 58), (-3, 76),
+        (-10, 94), (5, 54), (4, 69), (-3, 81), (0, 88),
+        // 54 - 59
+        (-7, 67), (-5, 74), (-4, 74), (-5, 80), (-7, 72), (1, 58),
+        // 60 - 69
+        (0, 41), (0, 63), (0, 63), (0, 63), (-9, 83), (4, 86), (0, 97), (-7, 72), (13, 41),
+        (3, 62),
+        // 70 - 72
+        (0, 45), (-4, 78), (-3, 96),
+    ],
+    [
+        // 0 - 10
+        (20, -15), (2, 54), (3, 74), (20, -15), (2, 54), (3, 74), (-28, 127), (-23, 104),
+        (-6, 53), (-1, 54), (7, 51),
+        // 11 - 23
+        (22, 25), (34, 0), (16, 0), (-2, 9), (4, 41), (-29, 118), (2, 65), (-6, 71), (-13, 79),
+        (5, 52), (9, 50), (-3, 70), (10, 54),
+        // 24 - 39
+        (26, 34), (19, 22), (40, 0), (57, 2), (41, 36), (26, 69), (-45, 127), (-15, 101),
+        (-4, 76), (-6, 71), (-13, 79), (5, 52), (6, 69), (-13, 90), (0, 52), (8, 43),
+        // 40 - 53
+        (-2, 69), (-5, 82), (-10, 96), (2, 59), (2, 75), (-3, 87), (-3, 100), (1, 56), (-3, 74),
+        (-6, 85), (0, 59), (-3, 81), (-7, 86), (-5, 95),
+        // 54 - 59
+        (-1, 66), (-1, 77), (1, 70), (-2, 86), (-5, 72), (0, 61),
+        // 60 - 69
+        (0, 41), (0, 63), (0, 63), (0, 63), (-9, 83), (4, 86), (0, 97), (-7, 72), (13, 41),
+        (3, 62),
+        // 70 - 72
+        (13, 15), (7, 51), (2, 80),
+    ],
+    [
+        // 0 - 10
+        (20, -15), (2, 54), (3, 74), (20, -15), (2, 54), (3, 74), (-28, 127), (-23, 104),
+        (-6, 53), (-1, 54), (7, 51),
+        // 11 - 23
+        (29, 16), (25, 0), (14, 0), (-10, 51), (-3, 62), (-27, 99), (26, 16), (-4, 85),
+        (-24, 102), (5, 57), (6, 57), (-17, 73), (14, 57),
+        // 24 - 39
+        (20, 40), (20, 10), (29, 0), (54, 0), (37, 42), (12, 97), (-32, 127), (-22, 117),
+        (-2, 74), (-4, 85), (-24, 102), (5, 57), (-6, 93), (-14, 88), (-6, 44), (4, 55),
+        // 40 - 53
+        (-11, 89), (-15, 103), (-21, 116), (19, 57), (20, 58), (4, 84), (6, 96), (1, 63),
+        (-5, 85), (-13, 106), (5, 63), (6, 75), (-3, 90), (-1, 101),
+        // 54 - 59
+        (3, 55), (-4, 79), (-2, 75), (-12, 97), (-7, 50), (1, 60),
+        // 60 - 69
+        (0, 41), (0, 63), (0, 63), (0, 63), (-9, 83), (4, 86), (0, 97), (-7, 72), (13, 41),
+        (3, 62),
+        // 70 - 72
+        (7, 34), (-9, 88), (-20, 127),
+    ],
+];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rbsp::BitReaderSlice;
+
+    /// The encoding process of section 9.3.4.2, used to produce test data.
+    struct Encoder {
+        cod_i_low: u32,
+        cod_i_range: u32,
+        first_bit_flag: bool,
+        bits_outstanding: u32,
+        bits: Vec<bool>,
+    }
+    impl Encoder {
+        fn new() -> Encoder {
+            Encoder { cod_i_low: 0, cod_i_range: 510, first_bit_flag: true, bits_outstanding: 0, bits: vec![] }
+        }
+        fn put_bit(&mut self, b: bool) {
+            if self.first_bit_flag {
+                self.first_bit_flag = false;
+            } else {
+                self.bits.push(b);
+            }
+            while self.bits_outstanding > 0 {
+                self.bits.push(!b);
+                self.bits_outstanding -= 1;
+            }
+        }
+        fn renorm(&mut self) {
+            while self.cod_i_range < 256 {
+                if self.cod_i_low < 256 {
+                    self.put_bit(false);
+                } else if self.cod_i_low >= 512 {
+                    self.cod_i_low -= 512;
+                    self.put_bit(true);
+                } else {
+                    self.cod_i_low -= 256;
+                    self.bits_outstanding += 1;
+                }
+                self.cod_i_range <<= 1;
+                self.cod_i_low <<= 1;
+            }
+        }
+        fn encode_decision(&mut self, ctx: &mut ContextModel, bin_val: bool) {
+            let q = ((self.cod_i_range >> 6) & 3) as usize;
+            let lps = u32::from(RANGE_TAB_LPS[ctx.p_state_idx as usize][q]);
+            self.cod_i_range -= lps;
+            if bin_val != ctx.val_mps {
+                self.cod_i_low += self.cod_i_range;
+                self.cod_i_range = lps;
+                if ctx.p_state_idx == 0 {
+                    ctx.val_mps = !ctx.val_mps;
+                }
+                ctx.p_state_idx = TRANS_IDX_LPS[ctx.p_state_idx as usize];
+            } else if ctx.p_state_idx < 62 {
+                ctx.p_state_idx += 1;
+            }
+            self.renorm();
+        }
+        fn encode_bypass(&mut self, bin_val: bool) {
+            self.cod_i_low <<= 1;
+            if bin_val {
+                self.cod_i_low += self.cod_i_range;
+            }
+            if self.cod_i_low >= 1024 {
+                self.put_bit(true);
+                self.cod_i_low -= 1024;
+            } else if self.cod_i_low < 512 {
+                self.put_bit(false);
+            } else {
+                self.cod_i_low -= 512;
+                self.bits_outstanding += 1;
+            }
+        }
+        fn encode_terminate(&mut self, bin_val: bool) {
+            self.cod_i_range -= 2;
+            if bin_val {
+                self.cod_i_low += self.cod_i_range;
+                self.cod_i_range = 2;
+                self.renorm();
+                self.put_bit((self.cod_i_low >> 9) & 1 != 0);
+                self.bits.push((self.cod_i_low >> 8) & 1 != 0);
+                self.bits.push(true); // rbsp_stop_one_bit
+            } else {
+                self.renorm();
+            }
+        }
+        fn finish(self) -> Vec<u8> {
+            let mut bytes = vec![0u8; (self.bits.len() + 7) / 8];
+            for (i, &b) in self.bits.iter().enumerate() {
+                if b {
+                    bytes[i / 8] |= 0x80 >> (i % 8);
+                }
+            }
+            bytes
+        }
+    }
+
+    #[test]
+    fn context_init() {
+        let ctx = ContextModel::new(20, -15, 26);
+        assert_eq!((ctx.p_state_idx(), ctx.val_mps()), (46, false));
+        let ctx = ContextModel::new(0, 97, 26);
+        assert_eq!((ctx.p_state_idx(), ctx.val_mps()), (33, true));
+        // SliceQPY is clipped to 0..=51, and preCtxState to 1..=126
+        let ctx = ContextModel::new(-28, 127, 100);
+        assert_eq!(ctx, ContextModel::new(-28, 127, 51));
+        let ctx = ContextModel::new(64, 127, 51);
+        assert_eq!((ctx.p_state_idx(), ctx.val_mps()), (62, true));
+
+        assert_eq!(init_contexts(None, 26).unwrap().len(), 73);
+        assert_eq!(init_contexts(Some(2), 26).unwrap()[72], ContextModel::new(-20, 127, 26));
+        assert!(matches!(init_contexts(Some(3), 26), Err(CabacError::InvalidCabacInitIdc(3))));
+    }
+
+    #[test]
+    fn round_trip() {
+        // a deterministic mix of bins, biased so that context adaptation matters
+        let mut seed = 0x1234_5678u32;
+        let mut bins = vec![];
+        for i in 0..2000 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let ctx_idx = i % 3;
+            let bin = (seed >> 16) % 8 < [1, 4, 7][ctx_idx];
+            bins.push((ctx_idx, bin));
+        }
+
+        let mut enc = Encoder::new();
+        let mut contexts = init_contexts(Some(0), 30).unwrap();
+        for (i, &(ctx_idx, bin)) in bins.iter().enumerate() {
+            if i % 7 == 0 {
+                enc.encode_bypass(bin);
+            } else {
+                enc.encode_decision(&mut contexts[ctx_idx], bin);
+            }
+            enc.encode_terminate(false);
+        }
+        enc.encode_terminate(true);
+        let data = enc.finish();
+
+        let mut r = BitReaderSlice::new(&data[..]);
+        let mut dec = CabacDecoder::new(&mut r).unwrap();
+        let mut contexts = init_contexts(Some(0), 30).unwrap();
+        for (i, &(ctx_idx, bin)) in bins.iter().enumerate() {
+            let actual = if i % 7 == 0 {
+                dec.decode_bypass(&mut r).unwrap()
+            } else {
+                dec.decode_decision(&mut r, &mut contexts[ctx_idx]).unwrap()
+            };
+            assert_eq!(actual, bin, "bin {}", i);
+            assert!(!dec.decode_terminate(&mut r).unwrap());
+        }
+        assert!(dec.decode_terminate(&mut r).unwrap());
+        assert!(!r.has_more_rbsp_data("end_of_slice").unwrap());
+    }
+}
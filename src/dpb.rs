@@ -0,0 +1,584 @@
+//! A model of the decoded picture buffer (DPB), which determines the order in which decoded
+//! pictures are output without decoding them.
+//!
+//! [Dpb] is fed each picture (frame or field) in decoding order, along with its first slice
+//! header and picture order count (see [crate::poc]).  It marks pictures as used for short-term
+//! or long-term reference by the sliding window or the slice's
+//! `memory_management_control_operation`s (_8.2.5_), and outputs pictures by the "bumping"
+//! process of _C.4.5.3_, with as many frames buffered as the SPS's `max_dec_frame_buffering`
+//! allows.  When the SPS gives `max_num_reorder_frames`, frames are also output as soon as more
+//! than that many are waiting, so that output isn't delayed longer than necessary.
+//!
+//! Operations naming pictures which aren't in the buffer (as after the loss of some pictures)
+//! are ignored, as are gaps in `frame_num` (which
+//! [FrameNumGapDetector](crate::access_unit::FrameNumGapDetector) can report).
+
+use crate::nal::slice::{DecRefPicMarking, MemoryManagementControlOperation, PictureStructure, SliceHeader};
+use crate::nal::sps::SeqParameterSet;
+use crate::poc::PicOrderCnt;
+use crate::ref_pic_list::RefFrame;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Marking {
+    Unused,
+    ShortTerm,
+    LongTerm,
+}
+
+#[derive(Debug)]
+struct Field {
+    pic_order_cnt: i32,
+    marking: Marking,
+}
+
+/// A frame buffer, holding a frame, a complementary field pair or a single field.
+#[derive(Debug)]
+struct FrameStore<T> {
+    frame_num: u16,
+    /// `LongTermFrameIdx`, meaningful while a field is marked as used for long-term reference.
+    long_term_frame_idx: u32,
+    top: Option<Field>,
+    bottom: Option<Field>,
+    /// The decoded pictures, in decoding order.
+    pictures: Vec<T>,
+    needed_for_output: bool,
+    /// Whether this holds the first field of a picture whose second field may follow.
+    awaiting_second_field: bool,
+}
+impl<T> FrameStore<T> {
+    fn field(&self, parity: PictureStructure) -> &Option<Field> {
+        match parity {
+            PictureStructure::TopField => &self.top,
+            PictureStructure::BottomField => &self.bottom,
+            PictureStructure::Frame => unreachable!("not a field"),
+        }
+    }
+
+    fn field_mut(&mut self, parity: PictureStructure) -> &mut Option<Field> {
+        match parity {
+            PictureStructure::TopField => &mut self.top,
+            PictureStructure::BottomField => &mut self.bottom,
+            PictureStructure::Frame => unreachable!("not a field"),
+        }
+    }
+
+    fn fields(&self) -> impl Iterator<Item = &Field> {
+        self.top.iter().chain(self.bottom.iter())
+    }
+
+    fn has_marking(&self, marking: Marking) -> bool {
+        self.fields().any(|f| f.marking == marking)
+    }
+
+    fn is_reference(&self) -> bool {
+        self.fields().any(|f| f.marking != Marking::Unused)
+    }
+
+    fn mark(&mut self, parity: Option<PictureStructure>, marking: Marking) {
+        let fields = match parity {
+            None => vec![&mut self.top, &mut self.bottom],
+            Some(parity) => vec![self.field_mut(parity)],
+        };
+        for f in fields.into_iter().flatten() {
+            f.marking = marking;
+        }
+    }
+
+    /// _PicOrderCnt_ of the frame, complementary field pair or field.
+    fn pic_order_cnt(&self) -> i32 {
+        self.fields().map(|f| f.pic_order_cnt).min().expect("frame store has a field")
+    }
+}
+
+/// A frame, complementary field pair or unpaired field output by the [Dpb].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputFrame<T> {
+    /// The data given for each picture: one for a frame or unpaired field, and two (in decoding
+    /// order) for a field pair.
+    pub pictures: Vec<T>,
+    /// The picture order counts of the fields present.  For a picture with a
+    /// `memory_management_control_operation` of 5, these are reduced as described in _8.2.1_.
+    pub pic_order_cnt: PicOrderCnt,
+}
+
+/// A model of the decoded picture buffer; see the [module documentation](self).
+///
+/// `T` is the caller's data for each picture, such as its NAL units or an identifier, which is
+/// returned in output order.
+#[derive(Debug)]
+pub struct Dpb<T> {
+    frames: Vec<FrameStore<T>>,
+    /// _MaxLongTermFrameIdx_, or `None` for "no long-term frame indices".
+    max_long_term_frame_idx: Option<u32>,
+}
+impl<T> Default for Dpb<T> {
+    fn default() -> Self {
+        Dpb {
+            frames: Vec::new(),
+            max_long_term_frame_idx: None,
+        }
+    }
+}
+impl<T> Dpb<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a decoded picture with the given first slice header, parsed with the given SPS, and
+    /// picture order count.  Returns the frames which are output as a result, in output order.
+    pub fn push(&mut self, sps: &SeqParameterSet, header: &SliceHeader, pic_order_cnt: PicOrderCnt, data: T) -> Vec<OutputFrame<T>> {
+        let mut output = Vec::new();
+        let structure = header.picture_structure();
+        let idr = header.idr_pic_id.is_some();
+        let current = Current {
+            structure,
+            frame_num: header.frame_num,
+            // no header can be parsed with an SPS lacking MaxFrameNum, and any 16-bit frame_num
+            // is below this.
+            max_frame_num: sps.max_frame_num().map_or(1 << 16, i64::from),
+        };
+        // the index of the frame store holding the first field of this picture, if it is the
+        // second field.
+        let first_field = match structure {
+            PictureStructure::Frame => None,
+            parity => self.frames.iter().position(|s| {
+                s.awaiting_second_field
+                    && !idr
+                    && s.frame_num == header.frame_num
+                    && s.field(parity).is_none()
+                    && s.is_reference() == header.dec_ref_pic_marking.is_some()
+            }),
+        };
+        for s in &mut self.frames {
+            s.awaiting_second_field = false;
+        }
+
+        // decoded reference picture marking (8.2.5).
+        let mut long_term_frame_idx = None;
+        let marking = match header.dec_ref_pic_marking {
+            None => Marking::Unused,
+            Some(DecRefPicMarking::Idr { long_term_reference_flag, .. }) => {
+                for s in &mut self.frames {
+                    s.mark(None, Marking::Unused);
+                }
+                if long_term_reference_flag {
+                    self.max_long_term_frame_idx = Some(0);
+                    long_term_frame_idx = Some(0);
+                } else {
+                    self.max_long_term_frame_idx = None;
+                }
+                if long_term_reference_flag { Marking::LongTerm } else { Marking::ShortTerm }
+            },
+            Some(DecRefPicMarking::SlidingWindow) => {
+                let first_field_short_term = first_field.map(|i| self.frames[i].has_marking(Marking::ShortTerm)).unwrap_or(false);
+                if !first_field_short_term {
+                    self.sliding_window(&current, sps.max_num_ref_frames.max(1));
+                }
+                Marking::ShortTerm
+            },
+            Some(DecRefPicMarking::Adaptive(ref ops)) => {
+                for op in ops {
+                    if let Some(idx) = self.apply(&current, first_field, op) {
+                        long_term_frame_idx = Some(idx);
+                    }
+                }
+                if long_term_frame_idx.is_some() { Marking::LongTerm } else { Marking::ShortTerm }
+            },
+        };
+        let mmco5 = header.dec_ref_pic_marking.as_ref().map(DecRefPicMarking::has_mmco5).unwrap_or(false);
+
+        // removal of pictures before insertion of the current picture (C.4.4).
+        if idr || mmco5 {
+            let no_output_of_prior_pics = matches!(
+                header.dec_ref_pic_marking,
+                Some(DecRefPicMarking::Idr { no_output_of_prior_pics_flag: true, .. })
+            );
+            let keep = first_field.map(|i| self.frames.remove(i));
+            if !no_output_of_prior_pics {
+                while self.bump(&mut output, false) {}
+            }
+            self.frames.clear();
+            self.frames.extend(keep);
+        }
+        self.frames.retain(|s| s.needed_for_output || s.is_reference());
+
+        let (pic_order_cnt, frame_num) = if mmco5 {
+            // after its decoding, the picture is ordered relative to tempPicOrderCnt, and is
+            // inferred to have had a frame_num of 0 (8.2.1).
+            let temp = pic_order_cnt.pic_order_cnt();
+            (pic_order_cnt.map(|c| c - temp), 0)
+        } else {
+            (pic_order_cnt, header.frame_num)
+        };
+        let field = |pic_order_cnt: Option<i32>| pic_order_cnt.map(|pic_order_cnt| Field { pic_order_cnt, marking });
+        if let Some(i) = first_field.and_then(|_| self.frames.iter().position(|s| s.frame_num == frame_num && s.field(structure).is_none())) {
+            let s = &mut self.frames[i];
+            *s.field_mut(structure) = field(match structure {
+                PictureStructure::TopField => pic_order_cnt.top(),
+                _ => pic_order_cnt.bottom(),
+            });
+            s.long_term_frame_idx = long_term_frame_idx.unwrap_or(s.long_term_frame_idx);
+            s.pictures.push(data);
+        } else {
+            // storage of the current picture (C.4.5).
+            let capacity = sps.max_dec_frame_buffering().unwrap_or(16).max(sps.max_num_ref_frames).max(1) as usize;
+            while self.frames.len() >= capacity {
+                let lowest = self.frames.iter().filter(|s| s.needed_for_output).map(FrameStore::pic_order_cnt).min();
+                if marking == Marking::Unused && structure == PictureStructure::Frame && lowest.map(|l| pic_order_cnt.pic_order_cnt() < l).unwrap_or(true) {
+                    output.push(OutputFrame { pictures: vec![data], pic_order_cnt });
+                    return output;
+                }
+                if !self.bump(&mut output, false) {
+                    break;
+                }
+            }
+            self.frames.push(FrameStore {
+                frame_num,
+                long_term_frame_idx: long_term_frame_idx.unwrap_or(0),
+                top: field(pic_order_cnt.top()),
+                bottom: field(pic_order_cnt.bottom()),
+                pictures: vec![data],
+                needed_for_output: true,
+                awaiting_second_field: structure != PictureStructure::Frame,
+            });
+        }
+        if let Some(max_num_reorder_frames) = sps.max_num_reorder_frames() {
+            while self.frames.iter().filter(|s| s.needed_for_output).count() > max_num_reorder_frames as usize {
+                if !self.bump(&mut output, true) {
+                    break;
+                }
+            }
+        }
+        output
+    }
+
+    /// Outputs all remaining frames, as at the end of the stream.
+    pub fn flush(&mut self) -> Vec<OutputFrame<T>> {
+        let mut output = Vec::new();
+        while self.bump(&mut output, false) {}
+        self.frames.clear();
+        output
+    }
+
+    /// The frames currently marked as used for reference, as needed to construct the reference
+    /// picture lists of the next picture's slices (see [crate::ref_pic_list::ref_pic_lists]).
+    ///
+    /// These include the first field of the next picture's frame, if it is a second field.
+    pub fn ref_frames(&self) -> Vec<RefFrame> {
+        let reference = |f: &Option<Field>| f.as_ref().filter(|f| f.marking != Marking::Unused).map(|f| f.pic_order_cnt);
+        self.frames
+            .iter()
+            .filter(|s| s.is_reference())
+            .map(|s| RefFrame {
+                frame_num: s.frame_num,
+                long_term_frame_idx: if s.has_marking(Marking::LongTerm) { Some(s.long_term_frame_idx) } else { None },
+                top: reference(&s.top),
+                bottom: reference(&s.bottom),
+            })
+            .collect()
+    }
+
+    /// The "bumping" process (_C.4.5.3_): outputs the frame with the lowest picture order count
+    /// which is waiting for output, emptying its frame buffer if it isn't used for reference.
+    /// Frame buffers awaiting a second field are skipped if `skip_incomplete`.
+    ///
+    /// Returns false if no frame is waiting for output.
+    fn bump(&mut self, output: &mut Vec<OutputFrame<T>>, skip_incomplete: bool) -> bool {
+        let i = match self.frames
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.needed_for_output && !(skip_incomplete && s.awaiting_second_field))
+            .min_by_key(|(_, s)| s.pic_order_cnt())
+        {
+            Some((i, _)) => i,
+            None => return false,
+        };
+        let s = &mut self.frames[i];
+        s.needed_for_output = false;
+        output.push(OutputFrame {
+            pictures: std::mem::take(&mut s.pictures),
+            pic_order_cnt: PicOrderCnt::from_fields(
+                s.top.as_ref().map(|f| f.pic_order_cnt),
+                s.bottom.as_ref().map(|f| f.pic_order_cnt),
+            ).expect("frame store has a field"),
+        });
+        if !s.is_reference() {
+            self.frames.remove(i);
+        }
+        true
+    }
+
+    /// The sliding window marking process (_8.2.5.3_).
+    fn sliding_window(&mut self, current: &Current, max_num_ref_frames: u32) {
+        loop {
+            let num_ref_frames = self.frames.iter().filter(|s| s.is_reference()).count();
+            if num_ref_frames < max_num_ref_frames as usize {
+                return;
+            }
+            let oldest = self.frames
+                .iter_mut()
+                .filter(|s| s.has_marking(Marking::ShortTerm))
+                .min_by_key(|s| current.frame_num_wrap(s.frame_num));
+            match oldest {
+                Some(s) => s.mark(None, Marking::Unused),
+                None => return,
+            }
+        }
+    }
+
+    /// Applies one `memory_management_control_operation` (_8.2.5.4_), returning the
+    /// `LongTermFrameIdx` of the current picture if it is marked as a long-term reference.
+    fn apply(&mut self, current: &Current, first_field: Option<usize>, op: &MemoryManagementControlOperation) -> Option<u32> {
+        let pic_num_x = |difference_of_pic_nums_minus1: u32| current.curr_pic_num() - (i64::from(difference_of_pic_nums_minus1) + 1);
+        match *op {
+            MemoryManagementControlOperation::ShortTermUnusedForRef { difference_of_pic_nums_minus1 } => {
+                if let Some((i, parity)) = self.find(current, Marking::ShortTerm, pic_num_x(difference_of_pic_nums_minus1)) {
+                    self.frames[i].mark(parity, Marking::Unused);
+                }
+            },
+            MemoryManagementControlOperation::LongTermUnusedForRef { long_term_pic_num } => {
+                if let Some((i, parity)) = self.find(current, Marking::LongTerm, i64::from(long_term_pic_num)) {
+                    self.frames[i].mark(parity, Marking::Unused);
+                }
+            },
+            MemoryManagementControlOperation::ShortTermUsedForLongTerm { difference_of_pic_nums_minus1, long_term_frame_idx } => {
+                if let Some((i, parity)) = self.find(current, Marking::ShortTerm, pic_num_x(difference_of_pic_nums_minus1)) {
+                    self.unmark_long_term_frame_idx(long_term_frame_idx, Some(i));
+                    let s = &mut self.frames[i];
+                    s.mark(parity, Marking::LongTerm);
+                    s.long_term_frame_idx = long_term_frame_idx;
+                }
+            },
+            MemoryManagementControlOperation::MaxUsedLongTermFrameRef { max_long_term_frame_idx_plus1 } => {
+                self.max_long_term_frame_idx = max_long_term_frame_idx_plus1.checked_sub(1);
+                let max = self.max_long_term_frame_idx;
+                for s in &mut self.frames {
+                    if s.has_marking(Marking::LongTerm) && max.map(|m| s.long_term_frame_idx > m).unwrap_or(true) {
+                        s.mark(None, Marking::Unused);
+                    }
+                }
+            },
+            MemoryManagementControlOperation::AllRefPicturesUnused => {
+                for s in &mut self.frames {
+                    s.mark(None, Marking::Unused);
+                }
+                self.max_long_term_frame_idx = None;
+            },
+            MemoryManagementControlOperation::CurrentUsedForLongTerm { long_term_frame_idx } => {
+                self.unmark_long_term_frame_idx(long_term_frame_idx, first_field);
+                return Some(long_term_frame_idx);
+            },
+        }
+        None
+    }
+
+    /// Marks as unused for reference any long-term reference with the given `LongTermFrameIdx`,
+    /// other than the frame store `except` (the other field of the picture being assigned it).
+    fn unmark_long_term_frame_idx(&mut self, long_term_frame_idx: u32, except: Option<usize>) {
+        for (i, s) in self.frames.iter_mut().enumerate() {
+            if Some(i) != except && s.has_marking(Marking::LongTerm) && s.long_term_frame_idx == long_term_frame_idx {
+                s.mark(None, Marking::Unused);
+            }
+        }
+    }
+
+    /// Finds the short-term reference with the given _PicNum_ or the long-term reference with
+    /// the given _LongTermPicNum_, returning its frame store and, when decoding a field, which
+    /// field.
+    fn find(&self, current: &Current, marking: Marking, num: i64) -> Option<(usize, Option<PictureStructure>)> {
+        self.frames.iter().enumerate().find_map(|(i, s)| {
+            let frame_num = if marking == Marking::LongTerm {
+                i64::from(s.long_term_frame_idx)
+            } else {
+                current.frame_num_wrap(s.frame_num)
+            };
+            let marked = |f: &Option<Field>| f.as_ref().map(|f| f.marking == marking).unwrap_or(false);
+            match current.structure {
+                PictureStructure::Frame => {
+                    if marked(&s.top) && marked(&s.bottom) && frame_num == num {
+                        Some((i, None))
+                    } else {
+                        None
+                    }
+                },
+                structure => [PictureStructure::TopField, PictureStructure::BottomField].iter().find_map(|&parity| {
+                    let same_parity = if parity == structure { 1 } else { 0 };
+                    if marked(s.field(parity)) && 2 * frame_num + same_parity == num {
+                        Some((i, Some(parity)))
+                    } else {
+                        None
+                    }
+                }),
+            }
+        })
+    }
+}
+
+/// The properties of the current picture used in deriving picture numbers (_8.2.4.1_).
+struct Current {
+    structure: PictureStructure,
+    frame_num: u16,
+    max_frame_num: i64,
+}
+impl Current {
+    /// _FrameNumWrap_ of a short-term reference frame.
+    fn frame_num_wrap(&self, frame_num: u16) -> i64 {
+        if frame_num > self.frame_num {
+            i64::from(frame_num) - self.max_frame_num
+        } else {
+            i64::from(frame_num)
+        }
+    }
+
+    /// _CurrPicNum_.
+    fn curr_pic_num(&self) -> i64 {
+        match self.structure {
+            PictureStructure::Frame => i64::from(self.frame_num),
+            _ => 2 * i64::from(self.frame_num) + 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nal::slice::{PictureCoding, RefPicListModifications};
+    use crate::nal::sps::{Level, Profile, SpsBuilder};
+
+    /// The first slice header of a picture, with the given `dec_ref_pic_marking()` (absent for
+    /// non-reference pictures).
+    fn header(frame_num: u16, picture_coding: PictureCoding, dec_ref_pic_marking: Option<DecRefPicMarking>) -> SliceHeader {
+        let idr = matches!(dec_ref_pic_marking, Some(DecRefPicMarking::Idr { .. }));
+        SliceHeader {
+            idr_pic_id: if idr { Some(0) } else { None },
+            ref_pic_list_modification: Some(RefPicListModifications::I),
+            dec_ref_pic_marking,
+            ..SliceHeader::for_test(7, frame_num, picture_coding)
+        }
+    }
+
+    fn idr(no_output_of_prior_pics_flag: bool) -> Option<DecRefPicMarking> {
+        Some(DecRefPicMarking::Idr { no_output_of_prior_pics_flag, long_term_reference_flag: false })
+    }
+
+    fn frame_poc(poc: i32) -> PicOrderCnt {
+        PicOrderCnt::frame(poc, poc)
+    }
+
+    fn ids(output: Vec<OutputFrame<u32>>) -> Vec<u32> {
+        output.into_iter().flat_map(|f| f.pictures).collect()
+    }
+
+    #[test]
+    fn reorder() {
+        let sps = SpsBuilder::new(Profile::Main, Level::L3)
+            .dimensions(640, 480)
+            .max_num_ref_frames(2)
+            .max_num_reorder_frames(2)
+            .build()
+            .unwrap();
+        let mut dpb = Dpb::new();
+        // (frame_num, reference, picture order count) in decoding order.
+        let pictures = [(0, true, 0), (1, true, 6), (2, false, 2), (2, false, 4), (2, true, 12), (3, false, 8), (3, false, 10)];
+        let mut output = Vec::new();
+        for (id, &(frame_num, reference, poc)) in pictures.iter().enumerate() {
+            let marking = if id == 0 {
+                idr(false)
+            } else if reference {
+                Some(DecRefPicMarking::SlidingWindow)
+            } else {
+                None
+            };
+            output.extend(dpb.push(&sps, &header(frame_num, PictureCoding::Frame, marking), frame_poc(poc), id as u32));
+        }
+        // with both frame buffers holding reference frames, B-frames are output directly.
+        assert_eq!(output.iter().map(|f| f.pic_order_cnt.pic_order_cnt()).collect::<Vec<_>>(), vec![0, 2, 4, 6, 8, 10]);
+        // the sliding window keeps the last two reference frames.
+        let frame_nums: Vec<_> = dpb.ref_frames().iter().map(|f| f.frame_num).collect();
+        assert_eq!(frame_nums, vec![1, 2]);
+        output.extend(dpb.flush());
+        assert_eq!(ids(output), vec![0, 2, 3, 1, 5, 6, 4]);
+        assert!(dpb.flush().is_empty());
+    }
+
+    #[test]
+    fn memory_management() {
+        let sps = SpsBuilder::new(Profile::Main, Level::L3)
+            .dimensions(640, 480)
+            .max_num_ref_frames(3)
+            .max_num_reorder_frames(0)
+            .build()
+            .unwrap();
+        let mut dpb = Dpb::new();
+        let adaptive = |ops: Vec<MemoryManagementControlOperation>| Some(DecRefPicMarking::Adaptive(ops));
+        let summary = |dpb: &Dpb<u32>| dpb.ref_frames().iter().map(|f| (f.frame_num, f.long_term_frame_idx)).collect::<Vec<_>>();
+        assert_eq!(ids(dpb.push(&sps, &header(0, PictureCoding::Frame, idr(false)), frame_poc(0), 0)), vec![0]);
+        dpb.push(&sps, &header(1, PictureCoding::Frame, Some(DecRefPicMarking::SlidingWindow)), frame_poc(2), 1);
+        // the current picture becomes long-term reference 0.
+        let ops = vec![
+            MemoryManagementControlOperation::MaxUsedLongTermFrameRef { max_long_term_frame_idx_plus1: 2 },
+            MemoryManagementControlOperation::CurrentUsedForLongTerm { long_term_frame_idx: 0 },
+        ];
+        dpb.push(&sps, &header(2, PictureCoding::Frame, adaptive(ops)), frame_poc(4), 2);
+        assert_eq!(summary(&dpb), vec![(0, None), (1, None), (2, Some(0))]);
+        // frame_num 0 (PicNum 3 - 3) becomes long-term 1, and frame_num 1 is dropped.
+        let ops = vec![
+            MemoryManagementControlOperation::ShortTermUsedForLongTerm { difference_of_pic_nums_minus1: 2, long_term_frame_idx: 1 },
+            MemoryManagementControlOperation::ShortTermUnusedForRef { difference_of_pic_nums_minus1: 1 },
+        ];
+        dpb.push(&sps, &header(3, PictureCoding::Frame, adaptive(ops)), frame_poc(6), 3);
+        assert_eq!(summary(&dpb), vec![(0, Some(1)), (2, Some(0)), (3, None)]);
+        // reassigning long-term index 1 replaces frame_num 0; then index 0 is released.
+        let ops = vec![
+            MemoryManagementControlOperation::CurrentUsedForLongTerm { long_term_frame_idx: 1 },
+            MemoryManagementControlOperation::LongTermUnusedForRef { long_term_pic_num: 0 },
+        ];
+        dpb.push(&sps, &header(4, PictureCoding::Frame, adaptive(ops)), frame_poc(8), 4);
+        assert_eq!(summary(&dpb), vec![(3, None), (4, Some(1))]);
+
+        // memory_management_control_operation 5 releases all references, and later pictures
+        // are ordered after the reduced picture order count.
+        let output = dpb.push(&sps, &header(5, PictureCoding::Frame, adaptive(vec![MemoryManagementControlOperation::AllRefPicturesUnused])), frame_poc(10), 5);
+        assert_eq!(output, vec![OutputFrame { pictures: vec![5], pic_order_cnt: frame_poc(0) }]);
+        assert_eq!(summary(&dpb), vec![(0, None)]);
+
+        // an IDR picture with no_output_of_prior_pics_flag discards pictures waiting for output.
+        let sps = SpsBuilder::new(Profile::Main, Level::L3)
+            .dimensions(640, 480)
+            .max_num_ref_frames(3)
+            .max_num_reorder_frames(2)
+            .build()
+            .unwrap();
+        let mut dpb = Dpb::new();
+        dpb.push(&sps, &header(0, PictureCoding::Frame, idr(false)), frame_poc(0), 0);
+        dpb.push(&sps, &header(1, PictureCoding::Frame, Some(DecRefPicMarking::SlidingWindow)), frame_poc(2), 1);
+        assert!(dpb.push(&sps, &header(0, PictureCoding::Frame, idr(true)), frame_poc(0), 2).is_empty());
+        assert_eq!(ids(dpb.flush()), vec![2]);
+    }
+
+    #[test]
+    fn fields() {
+        let sps = SpsBuilder::new(Profile::Main, Level::L3)
+            .dimensions(640, 480)
+            .max_num_ref_frames(2)
+            .max_num_reorder_frames(0)
+            .build()
+            .unwrap();
+        let mut dpb = Dpb::new();
+        let top = PicOrderCnt::top_field;
+        let bottom = PicOrderCnt::bottom_field;
+        // the first field is held until its second arrives.
+        assert!(dpb.push(&sps, &header(0, PictureCoding::TopField, idr(false)), top(0), 0).is_empty());
+        let refs = dpb.ref_frames();
+        assert_eq!((refs[0].top, refs[0].bottom), (Some(0), None));
+        let sliding_window = Some(DecRefPicMarking::SlidingWindow);
+        let output = dpb.push(&sps, &header(0, PictureCoding::BottomField, sliding_window.clone()), bottom(1), 1);
+        assert_eq!(output, vec![OutputFrame { pictures: vec![0, 1], pic_order_cnt: PicOrderCnt::frame(0, 1) }]);
+        // an unpaired field is output once the next picture shows it has no second field.
+        assert!(dpb.push(&sps, &header(1, PictureCoding::BottomField, sliding_window.clone()), bottom(5), 2).is_empty());
+        let output = dpb.push(&sps, &header(2, PictureCoding::Frame, sliding_window), frame_poc(8), 3);
+        assert_eq!(ids(output), vec![2, 3]);
+        let refs = dpb.ref_frames();
+        assert_eq!(refs.iter().map(|f| f.frame_num).collect::<Vec<_>>(), vec![1, 2]);
+    }
+}
@@ -0,0 +1,404 @@
+//! A pipeline of stages which drop, modify or insert NAL units, for rewriting a stream.
+//!
+//! Each [NalFilter] stage sees NAL units as a header byte and an RBSP (i.e. with emulation
+//! prevention removed), so may edit the data freely; emulation prevention is added again, and
+//! the stream re-framed, when the units leave the [Pipeline].
+//!
+//! ```
+//! use h264_reader::filter::{filter_annexb, Pipeline, DropUnitTypes};
+//! use h264_reader::nal::UnitType;
+//! let mut pipeline = Pipeline::new();
+//! pipeline.add_stage(Box::new(DropUnitTypes::new(&[UnitType::SEI])));
+//! let input = [0, 0, 0, 1, 0x09, 0xf0, 0, 0, 1, 0x06, 0x05, 0x00, 0x80, 0, 0, 1, 0x65, 0x88];
+//! let output = filter_annexb(&input[..], &mut pipeline).unwrap();
+//! assert_eq!(output, [0, 0, 0, 1, 0x09, 0xf0, 0, 0, 1, 0x65, 0x88]);
+//! ```
+
+use crate::annexb::{self, AnnexBError, AnnexBWriter};
+use crate::nal::UnitType;
+use crate::nal::aud::{AccessUnitDelimiter, PrimaryPicType};
+use crate::rbsp::{self, BitRead};
+use crate::Context;
+use std::io;
+use std::marker;
+
+/// A NAL unit as seen by a [NalFilter].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FilterNal {
+    /// The NAL header byte.
+    pub header: u8,
+    /// The NAL unit's payload, with emulation prevention removed.
+    pub rbsp: Vec<u8>,
+}
+impl FilterNal {
+    pub fn new(header: u8, rbsp: Vec<u8>) -> Self {
+        FilterNal { header, rbsp }
+    }
+
+    /// Creates a `FilterNal` from the bytes of a NAL unit (the header byte followed by the
+    /// escaped payload), or `None` if `nal` is empty.
+    pub fn from_escaped(nal: &[u8]) -> Option<Self> {
+        let (&header, payload) = nal.split_first()?;
+        Some(FilterNal {
+            header,
+            rbsp: rbsp::decode_nal(payload).into_owned(),
+        })
+    }
+
+    /// Returns the bytes of the NAL unit, with emulation prevention added to the payload.
+    pub fn to_escaped(&self) -> Vec<u8> {
+        let payload = rbsp::encode_nal(&self.rbsp[..]);
+        let mut nal = Vec::with_capacity(1 + payload.len());
+        nal.push(self.header);
+        nal.extend_from_slice(&payload[..]);
+        nal
+    }
+
+    pub fn unit_type(&self) -> UnitType {
+        UnitType::for_id(self.header & 0b0001_1111).unwrap()
+    }
+}
+
+/// A stage of a [Pipeline].
+///
+/// Implemented for closures taking the same arguments as [NalFilter::filter].
+pub trait NalFilter {
+    /// Handles a NAL unit, pushing the units to be passed on to the next stage onto `out`: none
+    /// to drop it, the (possibly modified) unit to keep it, or further units to insert them.
+    fn filter(&mut self, nal: FilterNal, out: &mut Vec<FilterNal>);
+
+    /// Called at the end of the stream, so that stages holding back units may emit them.  The
+    /// default implementation does nothing.
+    fn flush(&mut self, _out: &mut Vec<FilterNal>) {
+    }
+}
+impl<F: FnMut(FilterNal, &mut Vec<FilterNal>)> NalFilter for F {
+    fn filter(&mut self, nal: FilterNal, out: &mut Vec<FilterNal>) {
+        self(nal, out)
+    }
+}
+
+/// A [NalFilter] which drops NAL units of the given types.
+pub struct DropUnitTypes {
+    unit_types: Vec<UnitType>,
+}
+impl DropUnitTypes {
+    pub fn new(unit_types: &[UnitType]) -> Self {
+        DropUnitTypes { unit_types: unit_types.to_vec() }
+    }
+}
+impl NalFilter for DropUnitTypes {
+    fn filter(&mut self, nal: FilterNal, out: &mut Vec<FilterNal>) {
+        if !self.unit_types.contains(&nal.unit_type()) {
+            out.push(nal);
+        }
+    }
+}
+
+/// A [NalFilter] which inserts an access unit delimiter at the start of each access unit which
+/// lacks one, as some decoders require.
+///
+/// The start of an access unit is detected as described in _section 7.4.1.2.3_ of the spec, with
+/// the first VCL NAL unit of a new primary coded picture taken to be one with
+/// `first_mb_in_slice` equal to `0` (so arbitrary slice order is not supported).  The inserted
+/// delimiters give `primary_pic_type` `7`, which permits all slice types.
+#[derive(Default)]
+pub struct InsertAud {
+    started: bool,
+    seen_vcl: bool,
+}
+impl InsertAud {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+impl NalFilter for InsertAud {
+    fn filter(&mut self, nal: FilterNal, out: &mut Vec<FilterNal>) {
+        let id = nal.unit_type().id();
+        let is_vcl = (1..=5).contains(&id);
+        let starts_au = if id == UnitType::AccessUnitDelimiter.id() {
+            self.started = true;
+            self.seen_vcl = false;
+            false
+        } else if !self.started {
+            true
+        } else if !self.seen_vcl {
+            false
+        } else if matches!(id, 6..=8 | 14..=18) {
+            true
+        } else if matches!(id, 1 | 2 | 5) {
+            // first_mb_in_slice is the first element of the slice header
+            let mut r = rbsp::BitReaderSlice::new(&nal.rbsp[..]);
+            matches!(r.read_ue_named("first_mb_in_slice"), Ok(0))
+        } else {
+            false
+        };
+        if starts_au {
+            let aud = AccessUnitDelimiter::new(PrimaryPicType::ISIPSPB).to_nal_bytes();
+            out.push(FilterNal::new(aud[0], aud[1..].to_vec()));
+            self.started = true;
+            self.seen_vcl = false;
+        }
+        if is_vcl {
+            self.seen_vcl = true;
+        }
+        out.push(nal);
+    }
+}
+
+/// A sequence of [NalFilter] stages, through which each NAL unit is passed in turn.
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn NalFilter>>,
+}
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a stage, after any already added.
+    pub fn add_stage(&mut self, stage: Box<dyn NalFilter>) {
+        self.stages.push(stage);
+    }
+
+    /// Passes the given NAL unit (the header byte followed by the escaped payload) through each
+    /// stage, calling `out` with each resulting NAL unit (also with its payload escaped).  An
+    /// empty `nal`, having no header byte, is dropped.
+    pub fn process(&mut self, nal: &[u8], out: &mut dyn FnMut(&[u8])) {
+        if let Some(nal) = FilterNal::from_escaped(nal) {
+            self.run(0, vec![nal], out);
+        }
+    }
+
+    /// Flushes each stage in turn (passing any units they emit through the following stages), at
+    /// the end of the stream.
+    pub fn flush(&mut self, out: &mut dyn FnMut(&[u8])) {
+        for i in 0..self.stages.len() {
+            let mut flushed = Vec::new();
+            self.stages[i].flush(&mut flushed);
+            self.run(i + 1, flushed, out);
+        }
+    }
+
+    fn run(&mut self, first_stage: usize, mut nals: Vec<FilterNal>, out: &mut dyn FnMut(&[u8])) {
+        for stage in &mut self.stages[first_stage..] {
+            let mut next = Vec::with_capacity(nals.len());
+            for nal in nals {
+                stage.filter(nal, &mut next);
+            }
+            nals = next;
+        }
+        for nal in nals {
+            out(&nal.to_escaped()[..]);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FilterError {
+    /// The input Annex B byte stream was malformed.
+    AnnexB(AnnexBError),
+    /// A stage produced a NAL unit which `AnnexBWriter` couldn't write (lacking a valid header
+    /// byte, or ending with a `0x00` byte).
+    InvalidNal,
+}
+
+/// Passes an in-memory Annex B byte stream through the given pipeline (including a final
+/// `flush()`), returning the resulting Annex B byte stream.
+pub fn filter_annexb(data: &[u8], pipeline: &mut Pipeline) -> Result<Vec<u8>, FilterError> {
+    let mut w = AnnexBWriter::new(Vec::with_capacity(data.len()));
+    let mut invalid = false;
+    let mut write = |nal: &[u8]| {
+        if !invalid && w.write_nal(nal).is_err() {
+            invalid = true;
+        }
+    };
+    let mut nals = annexb::iter_nals(data);
+    while let Some(nal) = nals.next_slice() {
+        pipeline.process(nal.map_err(FilterError::AnnexB)?, &mut write);
+    }
+    pipeline.flush(&mut write);
+    if invalid {
+        return Err(FilterError::InvalidNal);
+    }
+    Ok(w.into_inner())
+}
+
+/// A [NalReader](../annexb/trait.NalReader.html) which passes each NAL unit read by an
+/// `AnnexBReader` (or `AvccReader`) through a [Pipeline], writing the result as an Annex B byte
+/// stream.
+pub struct AnnexBFilter<W: io::Write, Ctx> {
+    pipeline: Pipeline,
+    writer: AnnexBWriter<W>,
+    buf: Vec<u8>,
+    error: Option<io::Error>,
+    phantom: marker::PhantomData<Ctx>,
+}
+impl<W: io::Write, Ctx> AnnexBFilter<W, Ctx> {
+    pub fn new(pipeline: Pipeline, writer: W) -> Self {
+        AnnexBFilter {
+            pipeline,
+            writer: AnnexBWriter::new(writer),
+            buf: Vec::new(),
+            error: None,
+            phantom: marker::PhantomData,
+        }
+    }
+
+    /// Flushes the pipeline, returning the writer, or the first error encountered while writing
+    /// (after which no further output was written).
+    pub fn finish(mut self) -> io::Result<W> {
+        let writer = &mut self.writer;
+        let error = &mut self.error;
+        self.pipeline.flush(&mut |nal| write_nal(writer, error, nal));
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(self.writer.into_inner()),
+        }
+    }
+}
+fn write_nal<W: io::Write>(writer: &mut AnnexBWriter<W>, error: &mut Option<io::Error>, nal: &[u8]) {
+    if error.is_none() {
+        if let Err(e) = writer.write_nal(nal) {
+            *error = Some(e);
+        }
+    }
+}
+impl<W: io::Write, Ctx> annexb::NalReader for AnnexBFilter<W, Ctx> {
+    type Ctx = Ctx;
+
+    fn start(&mut self, _ctx: &mut Context<Ctx>) {
+        self.buf.clear();
+    }
+
+    fn push(&mut self, _ctx: &mut Context<Ctx>, buf: &[u8]) {
+        self.buf.extend_from_slice(buf);
+    }
+
+    fn end(&mut self, _ctx: &mut Context<Ctx>) {
+        if self.buf.is_empty() {
+            return;
+        }
+        let writer = &mut self.writer;
+        let error = &mut self.error;
+        self.pipeline.process(&self.buf[..], &mut |nal| write_nal(writer, error, nal));
+        self.buf.clear();
+    }
+
+    fn reset(&mut self, _ctx: &mut Context<Ctx>) {
+        self.buf.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::annexb::AnnexBReader;
+    use hex_literal::*;
+
+    #[test]
+    fn pipeline() {
+        let input = hex!(
+           "00 00 00 01 67 64 00 0A AC 72 84 44 26 84 00 00 03 00 04 00 00 03 00 CA 3C 48 96 11 80
+            00 00 00 01 68 de 3c 80
+            00 00 01 06 05 01 ff 80
+            00 00 01 65 88 84 00 00 03 01
+            00 00 01 41 9a 00 00 03 02");
+        let mut pipeline = Pipeline::new();
+        // drop SEI,
+        pipeline.add_stage(Box::new(DropUnitTypes::new(&[UnitType::SEI])));
+        // insert an AUD before each slice,
+        pipeline.add_stage(Box::new(|nal: FilterNal, out: &mut Vec<FilterNal>| {
+            if let UnitType::SliceLayerWithoutPartitioningIdr | UnitType::SliceLayerWithoutPartitioningNonIdr = nal.unit_type() {
+                out.push(FilterNal::new(0x09, vec![0xf0]));
+            }
+            out.push(nal);
+        }));
+        // modify the RBSP of non-IDR slices (so that emulation prevention must be redone),
+        pipeline.add_stage(Box::new(|mut nal: FilterNal, out: &mut Vec<FilterNal>| {
+            if nal.unit_type() == UnitType::SliceLayerWithoutPartitioningNonIdr {
+                assert_eq!(nal.rbsp, hex!("9a 00 00 02"));
+                nal.rbsp = hex!("9a 00 00 00 00").to_vec();
+            }
+            out.push(nal);
+        }));
+        // and append an end of stream unit when flushed,
+        struct EndOfStream;
+        impl NalFilter for EndOfStream {
+            fn filter(&mut self, nal: FilterNal, out: &mut Vec<FilterNal>) {
+                out.push(nal);
+            }
+            fn flush(&mut self, out: &mut Vec<FilterNal>) {
+                out.push(FilterNal::new(0x0b, vec![]));
+            }
+        }
+        pipeline.add_stage(Box::new(EndOfStream));
+        let expected = hex!(
+           "00 00 00 01 67 64 00 0A AC 72 84 44 26 84 00 00 03 00 04 00 00 03 00 CA 3C 48 96 11 80
+            00 00 00 01 68 de 3c 80
+            00 00 00 01 09 f0
+            00 00 01 65 88 84 00 00 03 01
+            00 00 00 01 09 f0
+            00 00 01 41 9a 00 00 03 00 00 03
+            00 00 01 0b");
+        assert_eq!(filter_annexb(&input[..], &mut pipeline).unwrap(), &expected[..]);
+
+        // the same, driven by AnnexBReader,
+        let mut r = AnnexBReader::new(AnnexBFilter::new(pipeline, Vec::new()));
+        let mut ctx = Context::default();
+        r.start(&mut ctx);
+        for chunk in input.chunks(3) {
+            r.push(&mut ctx, chunk);
+        }
+        r.end_units(&mut ctx);
+        assert_eq!(r.into_nal_reader().finish().unwrap(), &expected[..]);
+    }
+
+    #[test]
+    fn insert_aud() {
+        let mut pipeline = Pipeline::new();
+        pipeline.add_stage(Box::new(InsertAud::new()));
+        // two access units without delimiters (the second picture having two slices), followed
+        // by one which already has a delimiter,
+        let input = hex!(
+           "00 00 00 01 67 64 00 0A AC 72 84 44 26 84 00 00 03 00 04 00 00 03 00 CA 3C 48 96 11 80
+            00 00 00 01 68 de 3c 80
+            00 00 01 65 88 84
+            00 00 01 06 05 01 ff 80
+            00 00 01 41 9a 10
+            00 00 01 41 5a 10
+            00 00 00 01 09 30
+            00 00 01 41 9a 10");
+        let expected = hex!(
+           "00 00 00 01 09 f0
+            00 00 00 01 67 64 00 0A AC 72 84 44 26 84 00 00 03 00 04 00 00 03 00 CA 3C 48 96 11 80
+            00 00 00 01 68 de 3c 80
+            00 00 01 65 88 84
+            00 00 00 01 09 f0
+            00 00 01 06 05 01 ff 80
+            00 00 01 41 9a 10
+            00 00 01 41 5a 10
+            00 00 00 01 09 30
+            00 00 01 41 9a 10");
+        assert_eq!(filter_annexb(&input[..], &mut pipeline).unwrap(), &expected[..]);
+    }
+
+    #[test]
+    fn empty_nal() {
+        assert_eq!(FilterNal::from_escaped(&[]), None);
+        let mut pipeline = Pipeline::new();
+        pipeline.add_stage(Box::new(|_: FilterNal, _: &mut Vec<FilterNal>| {
+            panic!("empty NAL unit passed to stage");
+        }));
+        let mut outputs = 0;
+        pipeline.process(&[], &mut |_| outputs += 1);
+        assert_eq!(outputs, 0);
+
+        // a unit with the forbidden_zero_bit set can't be written.
+        let mut pipeline = Pipeline::new();
+        pipeline.add_stage(Box::new(|_: FilterNal, out: &mut Vec<FilterNal>| {
+            out.push(FilterNal::new(0x80, vec![]));
+        }));
+        assert!(matches!(filter_annexb(&hex!("00 00 01 09 f0")[..], &mut pipeline), Err(FilterError::InvalidNal)));
+    }
+}
@@ -5,14 +5,128 @@
 
 pub mod rbsp;
 pub mod annexb;
+pub mod access_unit;
 pub mod nal;
 pub mod avcc;
+pub mod cabac;
+pub mod filter;
+pub mod poc;
+pub mod ref_pic_list;
+pub mod dpb;
+pub mod sprop;
+#[cfg(feature = "rtp")]
+pub mod rtp;
+
+/// How serious a problem reported via [Context::report_error] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// The data does not conform to the spec, but parsing was not affected.
+    Warning,
+    /// Some of the data (usually the remainder of the current NAL unit) could not be parsed.
+    Error,
+}
+
+/// What parsing should do following a reported problem, as decided by an [ErrorHandler].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorAction {
+    /// Carry on as well as possible.  This is the default for `Severity::Warning`.
+    Continue,
+    /// Ignore the remainder of the current NAL unit.  This is the default for `Severity::Error`.
+    SkipNal,
+    /// Stop parsing.  `AnnexBReader`, `AvccReader` and `rtp::Depacketizer` ignore further input
+    /// until [Context::clear_abort] is called.
+    Abort,
+}
+
+/// A problem found in the input, reported by the parser via [Context::report_error].
+#[derive(Debug)]
+pub enum ErrorKind {
+    AnnexB(annexb::AnnexBError),
+    NalHeader(nal::NalHeaderError),
+    NalSwitch(nal::NalSwitchError),
+    Sps(nal::sps::SpsError),
+    SubsetSps(nal::subset_sps::SubsetSpsError),
+    SpsExtension(nal::sps_extension::SpsExtensionError),
+    Pps(nal::pps::PpsError),
+    SliceHeader(nal::slice::SliceHeaderError),
+    Sei(nal::sei::SeiError),
+    PicTiming(nal::sei::pic_timing::PicTimingError),
+    BufferingPeriod(nal::sei::buffering_period::BufferingPeriodError),
+    ItuTT35(nal::sei::user_data_registered_itu_t_t35::ItuTT35Error),
+}
+
+/// A problem passed to an [ErrorHandler].
+#[derive(Debug)]
+pub struct ReportedError {
+    pub severity: Severity,
+    pub kind: ErrorKind,
+    /// The offset of the NAL unit in which the problem was found (see [Context::nal_offset]).
+    pub nal_offset: Option<u64>,
+}
+
+/// Decides how parsing should proceed after a problem is found; see [Context::set_error_handler].
+pub type ErrorHandler = Box<dyn FnMut(&ReportedError) -> ErrorAction + Send>;
+
+/// Observes changes in the format of the stream; see [Context::set_stream_change_handler].
+pub type StreamChangeHandler = Box<dyn FnMut(&nal::sps::StreamChange) + Send>;
+
+/// A parameter set installed in a [Context]; see [Context::set_parameter_set_observer].
+#[derive(Debug)]
+pub enum ParameterSetChange<'a> {
+    Sps {
+        /// The SPS previously held with the same id, if any.
+        old: Option<&'a nal::sps::SeqParameterSet>,
+        new: &'a nal::sps::SeqParameterSet,
+    },
+    SubsetSps {
+        /// The subset SPS previously held with the same id, if any.
+        old: Option<&'a nal::subset_sps::SubsetSeqParameterSet>,
+        new: &'a nal::subset_sps::SubsetSeqParameterSet,
+    },
+    SpsExtension {
+        /// The SPS extension previously held with the same id, if any.
+        old: Option<&'a nal::sps_extension::SeqParameterSetExtension>,
+        new: &'a nal::sps_extension::SeqParameterSetExtension,
+    },
+    Pps {
+        /// The PPS previously held with the same id, if any.
+        old: Option<&'a nal::pps::PicParameterSet>,
+        new: &'a nal::pps::PicParameterSet,
+    },
+}
+
+/// Observes parameter sets as they are installed; see [Context::set_parameter_set_observer].
+pub type ParameterSetObserver = Box<dyn FnMut(&ParameterSetChange<'_>) + Send>;
+
+/// Why [Context::activation] couldn't find the parameter sets for a PPS id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissingParameterSet {
+    /// No PPS with the given id has been seen.
+    Pps(nal::pps::ParamSetId),
+    /// The PPS exists, but refers to an SPS which hasn't been seen.
+    Sps {
+        pps: nal::pps::ParamSetId,
+        sps: nal::pps::ParamSetId,
+    },
+}
 
 /// Contextual data that needs to be tracked between evaluations of different portions of H264
 /// syntax.
 pub struct Context<Ctx> {
     seq_param_sets: Vec<Option<nal::sps::SeqParameterSet>>,
+    seq_param_set_nals: Vec<Option<Vec<u8>>>,
+    subset_seq_param_sets: Vec<Option<nal::subset_sps::SubsetSeqParameterSet>>,
+    sps_extensions: Vec<Option<nal::sps_extension::SeqParameterSetExtension>>,
     pic_param_sets: Vec<Option<nal::pps::PicParameterSet>>,
+    pic_param_set_nals: Vec<Option<Vec<u8>>>,
+    nal_offset: Option<u64>,
+    nal_len: Option<u64>,
+    nal_start_code_zeros: Option<usize>,
+    error_handler: Option<ErrorHandler>,
+    aborted: bool,
+    active_format: Option<nal::sps::StreamFormat>,
+    stream_change_handler: Option<StreamChangeHandler>,
+    parameter_set_observer: Option<ParameterSetObserver>,
     pub user_context: Ctx,
 }
 impl Default for Context<()> {
@@ -24,11 +138,27 @@ impl<Ctx> Context<Ctx> {
     pub fn new(user_context: Ctx) -> Self {
         let mut seq_param_sets = vec!();
         for _ in 0..32 { seq_param_sets.push(None); }
+        let mut subset_seq_param_sets = vec!();
+        for _ in 0..32 { subset_seq_param_sets.push(None); }
+        let mut sps_extensions = vec!();
+        for _ in 0..32 { sps_extensions.push(None); }
         let mut pic_param_sets = vec!();
         for _ in 0..32 { pic_param_sets.push(None); }
         Context {
             seq_param_sets,
+            seq_param_set_nals: vec![None; 32],
+            subset_seq_param_sets,
+            sps_extensions,
             pic_param_sets,
+            pic_param_set_nals: vec![None; 32],
+            nal_offset: None,
+            nal_len: None,
+            nal_start_code_zeros: None,
+            error_handler: None,
+            aborted: false,
+            active_format: None,
+            stream_change_handler: None,
+            parameter_set_observer: None,
             user_context,
         }
     }
@@ -44,9 +174,81 @@ impl<Ctx> Context<Ctx> {
     pub fn sps(&self) -> impl Iterator<Item = &nal::sps::SeqParameterSet> {
         self.seq_param_sets.iter().filter_map(Option::as_ref)
     }
+    /// Installs the given SPS, replacing any with the same id.  Any NAL unit retained for the
+    /// replaced SPS is discarded; see [Context::put_seq_param_set_with_nal].
     pub fn put_seq_param_set(&mut self, sps: nal::sps::SeqParameterSet) {
+        self.install_sps(sps, None);
+    }
+    /// Installs the given SPS along with the NAL unit it was parsed from (the header byte
+    /// followed by the escaped payload), which may be retrieved by [Context::sps_nal_by_id].
+    pub fn put_seq_param_set_with_nal(&mut self, sps: nal::sps::SeqParameterSet, nal: Vec<u8>) {
+        self.install_sps(sps, Some(nal));
+    }
+    fn install_sps(&mut self, sps: nal::sps::SeqParameterSet, nal: Option<Vec<u8>>) {
         let i = sps.seq_parameter_set_id.id() as usize;
-        self.seq_param_sets[i] = Some(sps);
+        let old = self.seq_param_sets[i].replace(sps);
+        self.seq_param_set_nals[i] = nal;
+        if let Some(ref mut observer) = self.parameter_set_observer {
+            observer(&ParameterSetChange::Sps {
+                old: old.as_ref(),
+                new: self.seq_param_sets[i].as_ref().unwrap(),
+            });
+        }
+    }
+    /// The NAL unit (header byte followed by the escaped payload) from which the SPS with the
+    /// given id was parsed, for writing to a container unchanged.
+    ///
+    /// This is available for SPSs parsed by `SeqParameterSetNalHandler`,
+    /// [Context::put_param_set_nal] and the `avcc` module, but not for those installed with
+    /// [Context::put_seq_param_set].  `SeqParameterSetNalHandler` sees only the RBSP, so
+    /// re-applies emulation prevention and omits any trailing zero bytes; for conforming
+    /// streams this gives the original bytes.
+    pub fn sps_nal_by_id(&self, id: nal::pps::ParamSetId) -> Option<&[u8]> {
+        if id.id() > 31 {
+            None
+        } else {
+            self.seq_param_set_nals[id.id() as usize].as_deref()
+        }
+    }
+    /// Looks up a subset SPS, which shares `seq_parameter_set_id` values with plain SPSs but is
+    /// stored separately.
+    pub fn subset_sps_by_id(&self, id: nal::pps::ParamSetId) -> Option<&nal::subset_sps::SubsetSeqParameterSet> {
+        if id.id() > 31 {
+            None
+        } else {
+            self.subset_seq_param_sets[id.id() as usize].as_ref()
+        }
+    }
+    pub fn subset_sps(&self) -> impl Iterator<Item = &nal::subset_sps::SubsetSeqParameterSet> {
+        self.subset_seq_param_sets.iter().filter_map(Option::as_ref)
+    }
+    pub fn put_subset_seq_param_set(&mut self, subset_sps: nal::subset_sps::SubsetSeqParameterSet) {
+        let i = subset_sps.id().id() as usize;
+        let old = self.subset_seq_param_sets[i].replace(subset_sps);
+        if let Some(ref mut observer) = self.parameter_set_observer {
+            observer(&ParameterSetChange::SubsetSps {
+                old: old.as_ref(),
+                new: self.subset_seq_param_sets[i].as_ref().unwrap(),
+            });
+        }
+    }
+    /// Looks up the SPS extension (describing auxiliary pictures) for the SPS with the given id.
+    pub fn sps_extension_by_id(&self, id: nal::pps::ParamSetId) -> Option<&nal::sps_extension::SeqParameterSetExtension> {
+        if id.id() > 31 {
+            None
+        } else {
+            self.sps_extensions[id.id() as usize].as_ref()
+        }
+    }
+    pub fn put_sps_extension(&mut self, ext: nal::sps_extension::SeqParameterSetExtension) {
+        let i = ext.seq_parameter_set_id.id() as usize;
+        let old = self.sps_extensions[i].replace(ext);
+        if let Some(ref mut observer) = self.parameter_set_observer {
+            observer(&ParameterSetChange::SpsExtension {
+                old: old.as_ref(),
+                new: self.sps_extensions[i].as_ref().unwrap(),
+            });
+        }
     }
     pub fn pps_by_id(&self, id: nal::pps::ParamSetId) -> Option<&nal::pps::PicParameterSet> {
         if id.id() > 31 {
@@ -58,8 +260,232 @@ impl<Ctx> Context<Ctx> {
     pub fn pps(&self) -> impl Iterator<Item = &nal::pps::PicParameterSet> {
         self.pic_param_sets.iter().filter_map(Option::as_ref)
     }
+    /// Looks up the PPS with the given id along with the SPS it refers to, as needed to parse a
+    /// slice.
+    pub fn activation(
+        &self,
+        pps_id: nal::pps::ParamSetId,
+    ) -> Result<(&nal::pps::PicParameterSet, &nal::sps::SeqParameterSet), MissingParameterSet> {
+        let pps = self.pps_by_id(pps_id).ok_or(MissingParameterSet::Pps(pps_id))?;
+        let sps = self.sps_by_id(pps.seq_parameter_set_id).ok_or(MissingParameterSet::Sps {
+            pps: pps_id,
+            sps: pps.seq_parameter_set_id,
+        })?;
+        Ok((pps, sps))
+    }
+    /// Installs the given PPS, replacing any with the same id.  Any NAL unit retained for the
+    /// replaced PPS is discarded; see [Context::put_pic_param_set_with_nal].
     pub fn put_pic_param_set(&mut self, pps: nal::pps::PicParameterSet) {
+        self.install_pps(pps, None);
+    }
+    /// Installs the given PPS along with the NAL unit it was parsed from (the header byte
+    /// followed by the escaped payload), which may be retrieved by [Context::pps_nal_by_id].
+    pub fn put_pic_param_set_with_nal(&mut self, pps: nal::pps::PicParameterSet, nal: Vec<u8>) {
+        self.install_pps(pps, Some(nal));
+    }
+    fn install_pps(&mut self, pps: nal::pps::PicParameterSet, nal: Option<Vec<u8>>) {
         let i = pps.pic_parameter_set_id.id() as usize;
-        self.pic_param_sets[i] = Some(pps);
+        let old = self.pic_param_sets[i].replace(pps);
+        self.pic_param_set_nals[i] = nal;
+        if let Some(ref mut observer) = self.parameter_set_observer {
+            observer(&ParameterSetChange::Pps {
+                old: old.as_ref(),
+                new: self.pic_param_sets[i].as_ref().unwrap(),
+            });
+        }
+    }
+    /// The NAL unit from which the PPS with the given id was parsed; as for
+    /// [Context::sps_nal_by_id].
+    pub fn pps_nal_by_id(&self, id: nal::pps::ParamSetId) -> Option<&[u8]> {
+        if id.id() > 31 {
+            None
+        } else {
+            self.pic_param_set_nals[id.id() as usize].as_deref()
+        }
+    }
+
+    /// Parses the given SPS or PPS NAL unit (the header byte followed by the escaped payload, as
+    /// may be delivered out-of-band) and installs it, returning its type.
+    pub fn put_param_set_nal(&mut self, nal: &[u8]) -> Result<nal::UnitType, sprop::SpropError> {
+        let header = nal::NalHeader::new(*nal.first().ok_or(sprop::SpropError::Empty)?)
+            .map_err(sprop::SpropError::NalHeader)?;
+        let rbsp = rbsp::decode_nal(&nal[1..]);
+        match header.nal_unit_type() {
+            nal::UnitType::SeqParameterSet => {
+                let sps = nal::sps::SeqParameterSet::from_bytes(&rbsp).map_err(sprop::SpropError::Sps)?;
+                self.put_seq_param_set_with_nal(sps, nal.to_vec());
+            },
+            nal::UnitType::PicParameterSet => {
+                let pps = nal::pps::PicParameterSet::from_bytes(self, &rbsp).map_err(sprop::SpropError::Pps)?;
+                self.put_pic_param_set_with_nal(pps, nal.to_vec());
+            },
+            other => return Err(sprop::SpropError::UnexpectedUnitType(other)),
+        }
+        Ok(header.nal_unit_type())
+    }
+
+    /// Decodes and installs the parameter sets of an SDP `sprop-parameter-sets` value: a
+    /// comma-separated list of base64-encoded SPS and PPS NAL units.
+    ///
+    /// Returns the outcome for each set, in the order given.  SPSs are installed before PPSs
+    /// (whatever their order in the list), so that PPSs may refer to them.
+    pub fn put_sprop_parameter_sets(&mut self, sprop_parameter_sets: &str) -> Vec<Result<nal::UnitType, sprop::SpropError>> {
+        let nals: Vec<_> = sprop_parameter_sets
+            .split(',')
+            .map(|set| sprop::decode_base64(set.trim()))
+            .collect();
+        let mut outcomes: Vec<Option<Result<nal::UnitType, sprop::SpropError>>> = nals.iter().map(|_| None).collect();
+        let is_sps = |nal: &[u8]| nal.first().map(|&b| b & 0b0001_1111) == Some(nal::UnitType::SeqParameterSet.id());
+        for (nal, outcome) in nals.iter().zip(outcomes.iter_mut()) {
+            match nal {
+                Ok(nal) if is_sps(nal) => *outcome = Some(self.put_param_set_nal(nal)),
+                _ => {},
+            }
+        }
+        for (nal, outcome) in nals.iter().zip(outcomes.iter_mut()) {
+            match nal {
+                Ok(nal) if outcome.is_none() => *outcome = Some(self.put_param_set_nal(nal)),
+                _ => {},
+            }
+        }
+        nals.into_iter()
+            .zip(outcomes)
+            .map(|(nal, outcome)| nal.and_then(|_| outcome.unwrap()))
+            .collect()
+    }
+
+    /// The offset of the current NAL unit's header byte within the Annex B byte stream, counted
+    /// from the first byte pushed after [`AnnexBReader::start`](annexb/struct.AnnexBReader.html#method.start).
+    ///
+    /// Available to handlers from `start()` onwards, when data is being read by an
+    /// `AnnexBReader`.
+    pub fn nal_offset(&self) -> Option<u64> {
+        self.nal_offset
+    }
+    /// The length of the current NAL unit (i.e. excluding the start code and any trailing
+    /// `zero_byte`), once known: from the time `end()` is called on handlers.
+    pub fn nal_len(&self) -> Option<u64> {
+        self.nal_len
+    }
+    /// The number of `0x00` bytes preceding the `0x01` of the current NAL unit's start code, as
+    /// read by `AnnexBReader`: `2` for a 3-byte start code, `3` for a 4-byte start code (i.e.
+    /// with `zero_byte`), or more if there were also `leading_zero_8bits` (before the first NAL
+    /// unit of the stream).
+    ///
+    /// Zero bytes which follow the previous NAL unit's final non-zero byte and are not part of
+    /// the start code are considered `cabac_zero_word`s / `trailing_zero_8bits` of the previous
+    /// unit, and are included in its data.
+    pub fn nal_start_code_zeros(&self) -> Option<usize> {
+        self.nal_start_code_zeros
+    }
+    pub(crate) fn set_nal_start(&mut self, offset: u64, start_code_zeros: usize) {
+        self.nal_offset = Some(offset);
+        self.nal_len = None;
+        self.nal_start_code_zeros = Some(start_code_zeros);
+    }
+    pub(crate) fn set_nal_len(&mut self, len: u64) {
+        self.nal_len = Some(len);
+    }
+
+    /// Sets the function which is passed each problem found by the parser, and decides how
+    /// parsing should proceed.
+    ///
+    /// Without a handler, problems are logged (using the `log` crate) and the default
+    /// [ErrorAction] for their severity is taken.
+    pub fn set_error_handler(&mut self, error_handler: Option<ErrorHandler>) {
+        self.error_handler = error_handler;
+    }
+    /// Reports a problem with the input to the error handler, returning the action which the
+    /// reporting parser should take.
+    ///
+    /// `NalHandler` implementations outside this crate may also use this to report problems.
+    pub fn report_error(&mut self, severity: Severity, kind: ErrorKind) -> ErrorAction {
+        let action = match self.error_handler {
+            Some(ref mut handler) => handler(&ReportedError {
+                severity,
+                kind,
+                nal_offset: self.nal_offset,
+            }),
+            None => match severity {
+                Severity::Warning => {
+                    log::warn!("{:?}", kind);
+                    ErrorAction::Continue
+                },
+                Severity::Error => {
+                    log::error!("{:?}", kind);
+                    ErrorAction::SkipNal
+                },
+            },
+        };
+        if action == ErrorAction::Abort {
+            self.aborted = true;
+        }
+        action
+    }
+    /// Sets the function which is called when an SPS is activated whose dimensions, chroma
+    /// format or bit depth differ from those of the previously active SPS (including when the
+    /// first SPS is activated), so that downstream decoders may be reinitialised.
+    ///
+    /// This happens, for example, when a camera changes resolution and sends a new SPS with the
+    /// same `seq_parameter_set_id`: the change takes effect from the first slice which refers to
+    /// the new SPS.
+    pub fn set_stream_change_handler(&mut self, handler: Option<StreamChangeHandler>) {
+        self.stream_change_handler = handler;
+    }
+    /// Sets the function which is called whenever an SPS, subset SPS, SPS extension or PPS is
+    /// installed, whether parsed from the stream, supplied out-of-band or put directly, with the
+    /// value it replaces.
+    ///
+    /// Encoders commonly repeat parameter sets before each IDR picture, so `old` may be equal
+    /// to `new`; observers interested only in updates (for example to re-announce the stream's
+    /// parameters via SDP) should compare them.
+    pub fn set_parameter_set_observer(&mut self, observer: Option<ParameterSetObserver>) {
+        self.parameter_set_observer = observer;
+    }
+    /// The format of the SPS most recently activated by a slice, if any.
+    pub fn active_format(&self) -> Option<&nal::sps::StreamFormat> {
+        self.active_format.as_ref()
+    }
+    /// Notes that the given SPS is in use by the current picture, notifying the
+    /// [StreamChangeHandler] if the stream's format has changed.
+    ///
+    /// This is called when a slice header is parsed; `NalHandler` implementations outside this
+    /// crate which parse slice headers themselves may also call it.  Undefined SPS ids are
+    /// ignored.
+    pub fn activate_sps(&mut self, id: nal::pps::ParamSetId) {
+        let current = match self.sps_by_id(id) {
+            Some(sps) => nal::sps::StreamFormat::from_sps(sps),
+            None => return,
+        };
+        let previous = self.active_format.replace(current);
+        let changed = match previous {
+            Some(p) => p.differs_from(&current),
+            None => true,
+        };
+        if changed {
+            if let Some(ref mut handler) = self.stream_change_handler {
+                handler(&nal::sps::StreamChange { previous, current });
+            }
+        }
+    }
+    /// True if an error handler has returned `ErrorAction::Abort`.
+    pub fn is_aborted(&self) -> bool {
+        self.aborted
+    }
+    /// Allows parsing to resume after an `ErrorAction::Abort`.  Readers may need to be restarted,
+    /// since they will have ignored input part way through a NAL unit.
+    pub fn clear_abort(&mut self) {
+        self.aborted = false;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn context_is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Context<()>>();
     }
 }
@@ -0,0 +1,116 @@
+//! Access unit delimiter (`nal_unit_type` `9`), which some decoders require at the start of each
+//! access unit.
+
+use crate::rbsp::{self, BitRead, BitReaderSlice};
+
+#[derive(Debug)]
+pub enum AudError {
+    RbspReaderError(rbsp::RbspBitReaderError),
+}
+impl From<rbsp::RbspBitReaderError> for AudError {
+    fn from(e: rbsp::RbspBitReaderError) -> Self {
+        AudError::RbspReaderError(e)
+    }
+}
+
+/// The slice types which may be present in the primary coded picture of the access unit, per
+/// _Table 7-5_ of the spec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PrimaryPicType {
+    /// `0`: I
+    I,
+    /// `1`: I, P
+    IP,
+    /// `2`: I, P, B
+    IPB,
+    /// `3`: SI
+    SI,
+    /// `4`: SI, SP
+    SISP,
+    /// `5`: I, SI
+    ISI,
+    /// `6`: I, SI, P, SP
+    ISIPSP,
+    /// `7`: I, SI, P, SP, B
+    ISIPSPB,
+}
+impl PrimaryPicType {
+    /// Panics if `id` is greater than `7`.
+    pub fn from_id(id: u8) -> PrimaryPicType {
+        match id {
+            0 => PrimaryPicType::I,
+            1 => PrimaryPicType::IP,
+            2 => PrimaryPicType::IPB,
+            3 => PrimaryPicType::SI,
+            4 => PrimaryPicType::SISP,
+            5 => PrimaryPicType::ISI,
+            6 => PrimaryPicType::ISIPSP,
+            7 => PrimaryPicType::ISIPSPB,
+            _ => panic!("primary_pic_type {} out of range", id),
+        }
+    }
+
+    pub fn id(self) -> u8 {
+        match self {
+            PrimaryPicType::I => 0,
+            PrimaryPicType::IP => 1,
+            PrimaryPicType::IPB => 2,
+            PrimaryPicType::SI => 3,
+            PrimaryPicType::SISP => 4,
+            PrimaryPicType::ISI => 5,
+            PrimaryPicType::ISIPSP => 6,
+            PrimaryPicType::ISIPSPB => 7,
+        }
+    }
+}
+
+/// The `access_unit_delimiter_rbsp()` syntax.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessUnitDelimiter {
+    pub primary_pic_type: PrimaryPicType,
+}
+impl AccessUnitDelimiter {
+    pub fn new(primary_pic_type: PrimaryPicType) -> Self {
+        AccessUnitDelimiter { primary_pic_type }
+    }
+
+    /// Parses the RBSP of an access unit delimiter NAL unit (i.e. following the NAL header byte).
+    pub fn from_bytes(buf: &[u8]) -> Result<AccessUnitDelimiter, AudError> {
+        Self::from_bits(&mut BitReaderSlice::new(buf))
+    }
+
+    /// Parses the `access_unit_delimiter_rbsp()` syntax from the given reader, which is expected
+    /// to be positioned just after the NAL header.
+    pub fn from_bits<R: BitRead + ?Sized>(r: &mut R) -> Result<AccessUnitDelimiter, AudError> {
+        Ok(AccessUnitDelimiter {
+            primary_pic_type: PrimaryPicType::from_id(r.read_u8(3)?),
+        })
+    }
+
+    /// Returns the complete NAL unit (header byte, `primary_pic_type` and `rbsp_trailing_bits()`),
+    /// ready to be framed with a start code or length prefix.
+    pub fn to_nal_bytes(&self) -> [u8; 2] {
+        [
+            0x09,  // nal_ref_idc=0, nal_unit_type=9
+            self.primary_pic_type.id() << 5 | 0b0001_0000,
+        ]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for id in 0..8 {
+            let aud = AccessUnitDelimiter::new(PrimaryPicType::from_id(id));
+            let nal = aud.to_nal_bytes();
+            assert_eq!(nal[0] & 0x1f, 9);
+            assert_eq!(AccessUnitDelimiter::from_bytes(&nal[1..]).unwrap(), aud);
+        }
+        assert_eq!(AccessUnitDelimiter::new(PrimaryPicType::IPB).to_nal_bytes(), [0x09, 0x50]);
+        assert_eq!(AccessUnitDelimiter::from_bytes(&[0xf0]).unwrap().primary_pic_type, PrimaryPicType::ISIPSPB);
+        assert!(matches!(AccessUnitDelimiter::from_bytes(&[]), Err(AudError::RbspReaderError(_))));
+    }
+}
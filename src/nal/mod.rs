@@ -8,36 +8,63 @@ pub mod sps;
 pub mod pps;
 pub mod sei;
 pub mod slice;
+pub mod aud;
+pub mod subset_sps;
+pub mod sps_extension;
 
 use crate::annexb::NalReader;
+use crate::rbsp::ChunkedBitReader;
 use std::cell::RefCell;
-use crate::Context;
+use crate::{Context, ErrorAction, ErrorKind, Severity};
 use std::fmt;
 use log::*;
 
-#[derive(PartialEq, Hash, Debug, Copy, Clone)]
+/// The `nal_unit_type` of a NAL unit, covering every value of _Table 7-1_.
+///
+/// Reserved and unspecified values keep their numeric value, so every `nal_unit_type` maps to a
+/// distinct `UnitType` (and may be given its own handler in [NalSwitch]).
+#[derive(PartialEq, Eq, Hash, Debug, Copy, Clone)]
 pub enum UnitType {
     /// The values `0` and `24`-`31` are unspecified in the H264 spec
     Unspecified(u8),
+    /// Coded slice of a non-IDR picture (`1`)
     SliceLayerWithoutPartitioningNonIdr,
+    /// Coded slice data partition A (`2`)
     SliceDataPartitionALayer,
+    /// Coded slice data partition B (`3`)
     SliceDataPartitionBLayer,
+    /// Coded slice data partition C (`4`)
     SliceDataPartitionCLayer,
+    /// Coded slice of an IDR picture (`5`)
     SliceLayerWithoutPartitioningIdr,
-    /// Supplemental enhancement information
+    /// Supplemental enhancement information (`6`)
     SEI,
+    /// Sequence parameter set (`7`)
     SeqParameterSet,
+    /// Picture parameter set (`8`)
     PicParameterSet,
+    /// Access unit delimiter (`9`)
     AccessUnitDelimiter,
+    /// End of sequence (`10`)
     EndOfSeq,
+    /// End of stream (`11`)
     EndOfStream,
+    /// Filler data (`12`)
     FillerData,
+    /// Sequence parameter set extension (`13`)
     SeqParameterSetExtension,
+    /// Prefix NAL unit, preceding a slice in SVC and MVC streams (`14`)
     PrefixNALUnit,
+    /// Subset sequence parameter set, for SVC, MVC and 3D-AVC (`15`)
     SubsetSeqParameterSet,
+    /// Depth parameter set, for 3D-AVC (`16`)
     DepthParameterSet,
+    /// Coded slice of an auxiliary coded picture without partitioning (`19`)
     SliceLayerWithoutPartitioningAux,
+    /// Coded slice extension, for SVC and MVC (`20`)
     SliceExtension,
+    /// Coded slice extension for a depth view component or a 3D-AVC texture view component
+    /// (`21`)
     SliceExtensionViewComponent,
     /// The values `17`, `18`, `22` and `23` are reserved for future use by the H264 spec
     Reserved(u8),
@@ -116,14 +143,44 @@ pub struct NalHeader ( u8 );
 #[derive(Debug)]
 pub enum NalHeaderError {
     ForbiddenZeroBit,
+    /// The NAL unit ended before the extension header required by its `nal_unit_type` (`14`, `20`
+    /// or `21`).
+    TruncatedExtension,
+}
+/// How to treat a NAL header with `forbidden_zero_bit` set, as sometimes delivered by cameras on
+/// lossy links.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForbiddenZeroBitPolicy {
+    /// Fail with `NalHeaderError::ForbiddenZeroBit`.
+    Reject,
+    /// Warn, then accept the header.  [NalSwitch] reports the warning to the [Context]'s error
+    /// handler (which may ask for the NAL unit to be skipped); [NalHeader::with_policy] logs it.
+    Warn,
+    /// Accept the header silently.
+    Accept,
 }
 impl NalHeader {
+    /// Parses the header using [ForbiddenZeroBitPolicy::Reject].
     pub fn new(header_value: u8) -> Result<NalHeader, NalHeaderError> {
+        Self::with_policy(header_value, ForbiddenZeroBitPolicy::Reject)
+    }
+
+    /// Parses the header, treating a set `forbidden_zero_bit` according to `policy`.  If
+    /// accepted, the violation remains visible via [NalHeader::forbidden_zero_bit].
+    pub fn with_policy(header_value: u8, policy: ForbiddenZeroBitPolicy) -> Result<NalHeader, NalHeaderError> {
         if header_value & 0b1000_0000 != 0 {
-            Err(NalHeaderError::ForbiddenZeroBit)
-        } else {
-            Ok(NalHeader(header_value))
+            match policy {
+                ForbiddenZeroBitPolicy::Reject => return Err(NalHeaderError::ForbiddenZeroBit),
+                ForbiddenZeroBitPolicy::Warn => warn!("NAL header {:#04x} has forbidden_zero_bit set", header_value),
+                ForbiddenZeroBitPolicy::Accept => (),
+            }
         }
+        Ok(NalHeader(header_value))
+    }
+
+    /// True if the header was accepted despite having `forbidden_zero_bit` set.
+    pub fn forbidden_zero_bit(self) -> bool {
+        self.0 & 0b1000_0000 != 0
     }
 
     pub fn nal_ref_idc(self) -> u8 {
@@ -149,26 +206,281 @@ impl fmt::Debug for NalHeader {
     }
 }
 
+/// `nal_unit_header_svc_extension()`, from _Annex G_.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SvcExtension {
+    pub idr_flag: bool,
+    pub priority_id: u8,
+    pub no_inter_layer_pred_flag: bool,
+    pub dependency_id: u8,
+    pub quality_id: u8,
+    pub temporal_id: u8,
+    pub use_ref_base_pic_flag: bool,
+    pub discardable_flag: bool,
+    pub output_flag: bool,
+}
+
+/// `nal_unit_header_mvc_extension()`, from _Annex H_.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MvcExtension {
+    pub non_idr_flag: bool,
+    pub priority_id: u8,
+    pub view_id: u16,
+    pub temporal_id: u8,
+    pub anchor_pic_flag: bool,
+    pub inter_view_flag: bool,
+}
+
+/// `nal_unit_header_3davc_extension()`, from _Annex J_.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Avc3dExtension {
+    pub view_idx: u8,
+    pub depth_flag: bool,
+    pub non_idr_flag: bool,
+    pub temporal_id: u8,
+    pub anchor_pic_flag: bool,
+    pub inter_view_flag: bool,
+}
+
+/// The additional NAL unit header bytes carried by prefix NAL units and coded slice extensions
+/// (`nal_unit_type` `14`, `20` and `21`).
+///
+/// Unlike the payload, these bytes are not subject to emulation prevention.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NalHeaderExtension {
+    Svc(SvcExtension),
+    Mvc(MvcExtension),
+    Avc3d(Avc3dExtension),
+}
+impl NalHeaderExtension {
+    /// Returns true if NAL units of the given type have a header extension.
+    pub fn is_present(unit_type: UnitType) -> bool {
+        matches!(unit_type, UnitType::PrefixNALUnit | UnitType::SliceExtension | UnitType::SliceExtensionViewComponent)
+    }
+
+    /// Parses the extension from the bytes following the first NAL header byte, returning `None`
+    /// for unit types which have no extension.
+    pub fn new(unit_type: UnitType, bytes: &[u8]) -> Result<Option<NalHeaderExtension>, NalHeaderError> {
+        if !Self::is_present(unit_type) {
+            return Ok(None);
+        }
+        let len = Self::len_for(unit_type, bytes).ok_or(NalHeaderError::TruncatedExtension)?;
+        if bytes.len() < len {
+            return Err(NalHeaderError::TruncatedExtension);
+        }
+        let v = bytes[..len].iter().fold(0u32, |acc, &b| acc << 8 | u32::from(b)) << (8 * (4 - len));
+        let bits = |offset: u32, count: u32| (v << offset) >> (32 - count);
+        let flag = |offset: u32| bits(offset, 1) != 0;
+        // bit 0 is svc_extension_flag, or avc_3d_extension_flag for nal_unit_type 21.
+        Ok(Some(if unit_type != UnitType::SliceExtensionViewComponent && flag(0) {
+            NalHeaderExtension::Svc(SvcExtension {
+                idr_flag: flag(1),
+                priority_id: bits(2, 6) as u8,
+                no_inter_layer_pred_flag: flag(8),
+                dependency_id: bits(9, 3) as u8,
+                quality_id: bits(12, 4) as u8,
+                temporal_id: bits(16, 3) as u8,
+                use_ref_base_pic_flag: flag(19),
+                discardable_flag: flag(20),
+                output_flag: flag(21),
+                // reserved_three_2bits ignored
+            })
+        } else if unit_type == UnitType::SliceExtensionViewComponent && flag(0) {
+            NalHeaderExtension::Avc3d(Avc3dExtension {
+                view_idx: bits(1, 8) as u8,
+                depth_flag: flag(9),
+                non_idr_flag: flag(10),
+                temporal_id: bits(11, 3) as u8,
+                anchor_pic_flag: flag(14),
+                inter_view_flag: flag(15),
+            })
+        } else {
+            NalHeaderExtension::Mvc(MvcExtension {
+                non_idr_flag: flag(1),
+                priority_id: bits(2, 6) as u8,
+                view_id: bits(8, 10) as u16,
+                temporal_id: bits(18, 3) as u8,
+                anchor_pic_flag: flag(21),
+                inter_view_flag: flag(22),
+                // reserved_one_bit ignored
+            })
+        }))
+    }
+
+    /// The length in bytes of the extension, i.e. excluding the first NAL header byte.
+    pub fn byte_len(&self) -> usize {
+        match self {
+            NalHeaderExtension::Avc3d(_) => 2,
+            NalHeaderExtension::Svc(_) | NalHeaderExtension::Mvc(_) => 3,
+        }
+    }
+
+    /// The extension length for a unit type known to have one, or `None` if `bytes` is too short
+    /// to tell.
+    fn len_for(unit_type: UnitType, bytes: &[u8]) -> Option<usize> {
+        if unit_type == UnitType::SliceExtensionViewComponent {
+            let avc_3d_extension_flag = bytes.first()? & 0b1000_0000 != 0;
+            Some(if avc_3d_extension_flag { 2 } else { 3 })
+        } else {
+            Some(3)
+        }
+    }
+}
+
+/// A NAL unit borrowed from (possibly discontiguous) input chunks, without copying or
+/// removing emulation prevention bytes up front.
+///
+/// The chunks together contain the NAL header byte followed by the escaped payload.  If
+/// `is_complete()` is false, only a prefix of the NAL unit is available, so reads may fail with
+/// `RbspBitReaderError::UnexpectedEnd` and succeed once more data has arrived.
+#[derive(Clone, Debug)]
+pub struct RefNal<'a> {
+    head: &'a [u8],
+    tail: &'a [&'a [u8]],
+    complete: bool,
+}
+impl<'a> RefNal<'a> {
+    /// Panics if `head` is empty; it must contain at least the NAL header byte.
+    pub fn new(head: &'a [u8], tail: &'a [&'a [u8]], complete: bool) -> RefNal<'a> {
+        assert!(!head.is_empty(), "RefNal head must contain the NAL header byte");
+        RefNal {
+            head,
+            tail,
+            complete,
+        }
+    }
+
+    pub fn header(&self) -> Result<NalHeader, NalHeaderError> {
+        NalHeader::new(self.head[0])
+    }
+
+    /// Parses the header extension of prefix NAL units and coded slice extensions, returning
+    /// `None` for other unit types.
+    pub fn header_extension(&self) -> Result<Option<NalHeaderExtension>, NalHeaderError> {
+        let unit_type = self.header()?.nal_unit_type();
+        if !NalHeaderExtension::is_present(unit_type) {
+            return Ok(None);
+        }
+        let mut bytes = [0u8; 3];
+        let len = self.copy_raw(1, &mut bytes[..]);
+        NalHeaderExtension::new(unit_type, &bytes[..len])
+    }
+
+    /// True if the chunks contain the whole NAL unit.
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Returns a reader over the RBSP (i.e. the payload following the NAL header, including any
+    /// header extension, with emulation prevention removed as it is read).
+    pub fn reader(&self) -> ChunkedBitReader<'a> {
+        let mut skip = 1;
+        let unit_type = UnitType::for_id(self.head[0] & 0b0001_1111).unwrap();
+        if NalHeaderExtension::is_present(unit_type) {
+            let mut first = [0u8; 1];
+            let len = self.copy_raw(1, &mut first[..]);
+            skip += NalHeaderExtension::len_for(unit_type, &first[..len]).unwrap_or(0);
+        }
+        if skip <= self.head.len() {
+            return ChunkedBitReader::new(&self.head[skip..], self.tail);
+        }
+        skip -= self.head.len();
+        for (i, chunk) in self.tail.iter().enumerate() {
+            if skip <= chunk.len() {
+                return ChunkedBitReader::new(&chunk[skip..], &self.tail[i+1..]);
+            }
+            skip -= chunk.len();
+        }
+        ChunkedBitReader::new(&[], &[])
+    }
+
+    /// Copies raw (still escaped) bytes starting at `offset` into `buf`, returning the number
+    /// copied.
+    fn copy_raw(&self, mut offset: usize, buf: &mut [u8]) -> usize {
+        let mut len = 0;
+        for chunk in std::iter::once(&self.head).chain(self.tail.iter()) {
+            if offset >= chunk.len() {
+                offset -= chunk.len();
+                continue;
+            }
+            let n = std::cmp::min(buf.len() - len, chunk.len() - offset);
+            buf[len..len+n].copy_from_slice(&chunk[offset..offset+n]);
+            len += n;
+            offset = 0;
+            if len == buf.len() {
+                break;
+            }
+        }
+        len
+    }
+}
+
 #[derive(Debug)]
 enum NalSwitchState {
     Start,
     Handling(UnitType),
+    /// The handler for the given type returned `NalInterest::Stop`, so will get no more data for
+    /// this NAL unit, but is still to be told of its end.
+    Stopped(UnitType),
     Ignoring,
 }
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NalSwitchError {
+    /// The NAL unit (of the given type, if its header had been seen) exceeded the size set by
+    /// [NalSwitch::set_max_nal_size].
+    NalTooLarge { unit_type: Option<UnitType>, max_size: usize },
+}
+
 // TODO: generate enum at compile time rather than Vec<Box<>>
 pub struct NalSwitch<Ctx> {
     readers_by_id: Vec<Option<Box<RefCell<dyn NalHandler<Ctx=Ctx>>>>>,
+    default_handler: Option<Box<RefCell<dyn NalHandler<Ctx=Ctx>>>>,
     state: NalSwitchState,
+    forbidden_zero_bit_policy: ForbiddenZeroBitPolicy,
+    max_nal_size: Option<usize>,
+    /// number of bytes (including the header) seen so far in the current NAL unit
+    nal_size: usize,
+    error: Option<NalSwitchError>,
 }
 impl<Ctx> Default for NalSwitch<Ctx> {
     fn default() -> Self {
         NalSwitch {
             readers_by_id: Vec::new(),
+            default_handler: None,
             state: NalSwitchState::Start,
+            forbidden_zero_bit_policy: ForbiddenZeroBitPolicy::Reject,
+            max_nal_size: None,
+            nal_size: 0,
+            error: None,
         }
     }
 }
 impl<Ctx> NalSwitch<Ctx> {
+    /// Sets how NAL units with `forbidden_zero_bit` set are treated.  By default they are
+    /// rejected (logged and not passed to any handler).  Otherwise they are passed on, and
+    /// handlers can check [NalHeader::forbidden_zero_bit].
+    pub fn set_forbidden_zero_bit_policy(&mut self, policy: ForbiddenZeroBitPolicy) {
+        self.forbidden_zero_bit_policy = policy;
+    }
+
+    /// Limits the size in bytes (including the header) of each NAL unit, bounding the memory
+    /// used by handlers which accumulate whole NAL units.  A NAL unit exceeding the limit is
+    /// abandoned: its handler is `reset()` rather than `end()`ed, the error is recorded (see
+    /// [NalSwitch::error]), and the rest of its data is ignored until the next NAL unit starts.
+    /// By default there is no limit.
+    pub fn set_max_nal_size(&mut self, max_nal_size: Option<usize>) {
+        self.max_nal_size = max_nal_size;
+    }
+
+    /// Returns the error (if any) which caused the current NAL unit to be abandoned.
+    ///
+    /// The error is retained until the next call to `start()`.
+    pub fn error(&self) -> Option<&NalSwitchError> {
+        self.error.as_ref()
+    }
+
+    /// Registers the handler for NAL units of the given type, replacing any existing handler
+    /// for that type.
     pub fn put_handler(&mut self, unit_type: UnitType, handler: Box<RefCell<dyn NalHandler<Ctx=Ctx>>>) {
         let i = unit_type.id() as usize;
         while i >= self.readers_by_id.len() {
@@ -177,13 +489,42 @@ impl<Ctx> NalSwitch<Ctx> {
         self.readers_by_id[i] = Some(handler);
     }
 
-    fn get_handler(&self, unit_type: UnitType) -> &Option<Box<RefCell<dyn NalHandler<Ctx=Ctx>>>> {
-        let i = unit_type.id() as usize;
-        if i < self.readers_by_id.len() {
-            &self.readers_by_id[i]
-        } else {
-            &None
-        }
+    /// Unregisters and returns the handler for the given type, if any.
+    pub fn remove_handler(&mut self, unit_type: UnitType) -> Option<Box<RefCell<dyn NalHandler<Ctx=Ctx>>>> {
+        self.readers_by_id.get_mut(unit_type.id() as usize).and_then(Option::take)
+    }
+
+    /// Registers a catch-all handler for NAL units of any type which has no handler of its own
+    /// (see [NalSwitch::put_handler]).  Such units are ignored if there is no default handler.
+    pub fn set_default_handler(&mut self, handler: Option<Box<RefCell<dyn NalHandler<Ctx=Ctx>>>>) {
+        self.default_handler = handler;
+    }
+
+    fn get_handler(&self, unit_type: UnitType) -> Option<&RefCell<dyn NalHandler<Ctx=Ctx>>> {
+        self.readers_by_id.get(unit_type.id() as usize)
+            .and_then(Option::as_ref)
+            .or(self.default_handler.as_ref())
+            .map(|h| &**h)
+    }
+}
+impl<Ctx> NalSwitch<Ctx> {
+    fn abandon(&mut self, ctx: &mut Context<Ctx>, max_size: usize) {
+        let unit_type = match self.state {
+            NalSwitchState::Handling(unit_type) | NalSwitchState::Stopped(unit_type) => {
+                if let Some(handler) = self.get_handler(unit_type) {
+                    handler.borrow_mut().reset(ctx);
+                }
+                Some(unit_type)
+            },
+            NalSwitchState::Start => None,
+            NalSwitchState::Ignoring => return,
+        };
+        let error = NalSwitchError::NalTooLarge { unit_type, max_size };
+        // the unit is ignored whatever the action, as keeping it would defeat the limit; the
+        // reader feeding this switch stops on an Abort.
+        ctx.report_error(Severity::Error, ErrorKind::NalSwitch(error.clone()));
+        self.error = Some(error);
+        self.state = NalSwitchState::Ignoring;
     }
 }
 impl<Ctx> NalReader for NalSwitch<Ctx> {
@@ -191,48 +532,91 @@ impl<Ctx> NalReader for NalSwitch<Ctx> {
 
     fn start(&mut self, _ctx: &mut Context<Ctx>) {
         self.state = NalSwitchState::Start;
+        self.nal_size = 0;
+        self.error = None;
     }
 
     fn push(&mut self, ctx: &mut Context<Ctx>, buf: &[u8]) {
         if buf.is_empty() {
             return;
         }
+        self.nal_size = self.nal_size.saturating_add(buf.len());
+        if let Some(max_size) = self.max_nal_size {
+            if self.nal_size > max_size {
+                self.abandon(ctx, max_size);
+                return;
+            }
+        }
         match self.state {
             NalSwitchState::Start => {
-                self.state = match NalHeader::new(buf[0]) {
+                // a Warn policy's warning goes to the error handler rather than the log.
+                let policy = match self.forbidden_zero_bit_policy {
+                    ForbiddenZeroBitPolicy::Warn => ForbiddenZeroBitPolicy::Accept,
+                    policy => policy,
+                };
+                self.state = match NalHeader::with_policy(buf[0], policy) {
                     Ok(header) => {
-                        if let Some(ref handler) = self.get_handler(header.nal_unit_type()) {
+                        let skip = header.forbidden_zero_bit()
+                            && self.forbidden_zero_bit_policy == ForbiddenZeroBitPolicy::Warn
+                            && ctx.report_error(Severity::Warning, ErrorKind::NalHeader(NalHeaderError::ForbiddenZeroBit)) != ErrorAction::Continue;
+                        if skip {
+                            NalSwitchState::Ignoring
+                        } else if let Some(handler) = self.get_handler(header.nal_unit_type()) {
                             handler.borrow_mut().start(ctx, header);
-                            handler.borrow_mut().push(ctx, &buf[1..]);
-                            NalSwitchState::Handling(header.nal_unit_type())
+                            match handler.borrow_mut().push(ctx, &buf[1..]) {
+                                NalInterest::Continue => NalSwitchState::Handling(header.nal_unit_type()),
+                                NalInterest::Stop => NalSwitchState::Stopped(header.nal_unit_type()),
+                            }
                         } else {
                             NalSwitchState::Ignoring
                         }
                     },
                     Err(e) => {
-                        // TODO: proper error propagation
-                        error!("Bad NAL header: {:?}", e);
+                        // without a header, the unit can't be handled whatever the action.
+                        ctx.report_error(Severity::Error, ErrorKind::NalHeader(e));
                         NalSwitchState::Ignoring
                     }
                 };
             },
-            NalSwitchState::Ignoring => (),
+            NalSwitchState::Ignoring | NalSwitchState::Stopped(_) => (),
             NalSwitchState::Handling(unit_type) => {
-                if let Some(ref handler) = self.get_handler(unit_type) {
-                    handler.borrow_mut().push(ctx, buf);
+                if let Some(handler) = self.get_handler(unit_type) {
+                    if handler.borrow_mut().push(ctx, buf) == NalInterest::Stop {
+                        self.state = NalSwitchState::Stopped(unit_type);
+                    }
                 }
             }
         }
     }
 
     fn end(&mut self, ctx: &mut Context<Ctx>) {
-        if let NalSwitchState::Handling(unit_type) = self.state {
-            if let Some(ref handler) = self.get_handler(unit_type) {
+        if let NalSwitchState::Handling(unit_type) | NalSwitchState::Stopped(unit_type) = self.state {
+            if let Some(handler) = self.get_handler(unit_type) {
                 handler.borrow_mut().end(ctx);
             }
         }
         self.state = NalSwitchState::Ignoring
     }
+
+    fn reset(&mut self, ctx: &mut Context<Ctx>) {
+        if let NalSwitchState::Handling(unit_type) | NalSwitchState::Stopped(unit_type) = self.state {
+            if let Some(handler) = self.get_handler(unit_type) {
+                handler.borrow_mut().reset(ctx);
+            }
+        }
+        self.state = NalSwitchState::Ignoring
+    }
+}
+
+/// Returned by [NalHandler::push] to indicate whether the handler wants the rest of the current
+/// NAL unit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NalInterest {
+    /// Continue pushing data for this NAL unit.
+    Continue,
+    /// Push no further data for this NAL unit, e.g. because the handler has all it needs or has
+    /// hit an unrecoverable error.  `end()` will still be called.
+    Stop,
 }
 
 // TODO: rename to 'RbspHandler' or something, to indicate it's only for post-emulation-prevention-bytes data
@@ -240,8 +624,66 @@ pub trait NalHandler {
     type Ctx;
 
     fn start(&mut self, ctx: &mut Context<Self::Ctx>, header: NalHeader);
-    fn push(&mut self, ctx: &mut Context<Self::Ctx>, buf: &[u8]);
+    fn push(&mut self, ctx: &mut Context<Self::Ctx>, buf: &[u8]) -> NalInterest;
     fn end(&mut self, ctx: &mut Context<Self::Ctx>);
+
+    /// Called instead of `end()` when the current NAL unit is abandoned (e.g. for exceeding
+    /// [NalSwitch::set_max_nal_size]).  Handlers which buffer data should discard it.  The
+    /// default implementation does nothing.
+    fn reset(&mut self, _ctx: &mut Context<Self::Ctx>) {
+    }
+}
+
+/// A [NalHandler] which buffers each whole NAL unit (header byte followed by the payload, as
+/// pushed) and passes it to the given callback once `end()` is reached.
+///
+/// Registered directly with a [NalSwitch], the payload is still escaped (containing emulation
+/// prevention bytes), ready to be written to another stream.  Wrapped in an
+/// [`RbspDecoder`](../rbsp/struct.RbspDecoder.html), it receives the RBSP instead.
+pub struct NalAccumulator<Ctx, F>
+where
+    F: FnMut(&mut Context<Ctx>, Vec<u8>)
+{
+    buf: Vec<u8>,
+    callback: F,
+    phantom: std::marker::PhantomData<fn(&mut Context<Ctx>)>,
+}
+impl<Ctx, F> NalAccumulator<Ctx, F>
+where
+    F: FnMut(&mut Context<Ctx>, Vec<u8>)
+{
+    pub fn new(callback: F) -> Self {
+        NalAccumulator {
+            buf: Vec::new(),
+            callback,
+            phantom: std::marker::PhantomData,
+        }
+    }
+}
+impl<Ctx, F> NalHandler for NalAccumulator<Ctx, F>
+where
+    F: FnMut(&mut Context<Ctx>, Vec<u8>)
+{
+    type Ctx = Ctx;
+
+    fn start(&mut self, _ctx: &mut Context<Self::Ctx>, header: NalHeader) {
+        self.buf.clear();
+        self.buf.push(header.into());
+    }
+
+    fn push(&mut self, _ctx: &mut Context<Self::Ctx>, buf: &[u8]) -> NalInterest {
+        self.buf.extend_from_slice(buf);
+        NalInterest::Continue
+    }
+
+    fn end(&mut self, ctx: &mut Context<Self::Ctx>) {
+        let nal = std::mem::take(&mut self.buf);
+        (self.callback)(ctx, nal);
+    }
+
+    fn reset(&mut self, _ctx: &mut Context<Self::Ctx>) {
+        self.buf.clear();
+    }
 }
 
 #[cfg(test)]
@@ -264,11 +706,12 @@ mod test {
             assert_eq!(header.nal_unit_type(), UnitType::SeqParameterSet);
         }
 
-        fn push(&mut self, _ctx: &mut Context<Self::Ctx>, buf: &[u8]) {
+        fn push(&mut self, _ctx: &mut Context<Self::Ctx>, buf: &[u8]) -> NalInterest {
             let expected = hex!(
                "64 00 0A AC 72 84 44 26 84 00 00
                 00 04 00 00 00 CA 3C 48 96 11 80");
-            assert_eq!(buf, &expected[..])
+            assert_eq!(buf, &expected[..]);
+            NalInterest::Continue
         }
 
         fn end(&mut self, _ctx: &mut Context<Self::Ctx>) {
@@ -286,4 +729,349 @@ mod test {
         let mut ctx = Context::default();
         s.push(&mut ctx, &data[..]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn ref_nal() {
+        use crate::nal::sps::SeqParameterSet;
+        use crate::rbsp::BitRead;
+        let data = hex!(
+           "67 64 00 0A AC 72 84 44 26 84 00 00 03
+            00 04 00 00 03 00 CA 3C 48 96 11 80");
+        let expected = SeqParameterSet::from_bytes(&crate::rbsp::decode_nal(&data[1..])).unwrap();
+        for i in 1..data.len() {
+            for j in i..data.len() {
+                let tail = [&data[i..j], &data[j..]];
+                let nal = RefNal::new(&data[..i], &tail[..], true);
+                assert!(nal.is_complete());
+                assert_eq!(nal.header().unwrap().nal_unit_type(), UnitType::SeqParameterSet);
+                let sps = SeqParameterSet::from_bits(&mut nal.reader()).unwrap();
+                assert_eq!(format!("{:?}", sps), format!("{:?}", expected));
+                assert_eq!(nal.reader().read_remaining_payload("rbsp").unwrap(), &crate::rbsp::decode_nal(&data[1..])[..]);
+            }
+        }
+
+        let nal = RefNal::new(&data[..5], &[], false);
+        assert!(!nal.is_complete());
+        assert!(SeqParameterSet::from_bits(&mut nal.reader()).is_err());
+    }
+
+    #[test]
+    fn accumulator() {
+        use crate::annexb::AnnexBReader;
+        let nals = std::rc::Rc::new(RefCell::new(Vec::new()));
+        let nals2 = std::rc::Rc::clone(&nals);
+        let mut s = NalSwitch::default();
+        s.put_handler(UnitType::SeqParameterSet, Box::new(RefCell::new(NalAccumulator::new(move |_ctx: &mut Context<()>, nal| {
+            nals2.borrow_mut().push(nal);
+        }))));
+        let mut r = AnnexBReader::new(s);
+        let mut ctx = Context::default();
+        let data = hex!(
+           "00 00 00 01 67 64 00 0A AC 72 84 44 26 84 00 00 03
+            00 04 00 00 03 00 CA 3C 48 96 11 80
+            00 00 01 68 EE 06 F2 C0
+            00 00 01 67 42");
+        r.start(&mut ctx);
+        r.push(&mut ctx, &data[..10]);
+        r.push(&mut ctx, &data[10..]);
+        r.end_units(&mut ctx);
+        assert_eq!(&nals.borrow()[..], &[data[4..29].to_vec(), data[40..].to_vec()][..]);
+    }
+
+    #[test]
+    fn all_unit_types() {
+        for id in 0..=31 {
+            assert_eq!(UnitType::for_id(id).unwrap().id(), id);
+        }
+        assert!(UnitType::for_id(32).is_err());
+        assert_eq!(UnitType::for_id(23).unwrap(), UnitType::Reserved(23));
+        assert_eq!(UnitType::for_id(24).unwrap(), UnitType::Unspecified(24));
+        let distinct: std::collections::HashSet<_> = (0..=31).map(|id| UnitType::for_id(id).unwrap()).collect();
+        assert_eq!(distinct.len(), 32);
+
+        use crate::annexb::AnnexBReader;
+        let nals = std::rc::Rc::new(RefCell::new(Vec::new()));
+        let mut s = NalSwitch::default();
+        for id in 0..=31 {
+            let nals = std::rc::Rc::clone(&nals);
+            s.put_handler(UnitType::for_id(id).unwrap(), Box::new(RefCell::new(NalAccumulator::new(move |_ctx: &mut Context<()>, nal| {
+                nals.borrow_mut().push(nal);
+            }))));
+        }
+        let mut r = AnnexBReader::new(s);
+        let mut ctx = Context::default();
+        // AUD, filler data, end of sequence, end of stream, SPS extension, prefix NAL unit, slice
+        // extension, and reserved / unspecified types.
+        let data = hex!(
+           "00 00 00 01 09 10
+            00 00 01 0c ff ff 80
+            00 00 01 0a
+            00 00 01 0b
+            00 00 01 0d 80
+            00 00 01 6e 40 00 00
+            00 00 01 74 40 00 00
+            00 00 01 17 80
+            00 00 01 1f 80");
+        r.start(&mut ctx);
+        r.push(&mut ctx, &data[..]);
+        r.end_units(&mut ctx);
+        let types: Vec<_> = nals.borrow().iter().map(|n| NalHeader::new(n[0]).unwrap().nal_unit_type()).collect();
+        assert_eq!(types, vec![
+            UnitType::AccessUnitDelimiter,
+            UnitType::FillerData,
+            UnitType::EndOfSeq,
+            UnitType::EndOfStream,
+            UnitType::SeqParameterSetExtension,
+            UnitType::PrefixNALUnit,
+            UnitType::SliceExtension,
+            UnitType::Reserved(23),
+            UnitType::Unspecified(31),
+        ]);
+    }
+
+    #[test]
+    fn header_extension() {
+        // prefix NAL unit with svc_extension_flag set
+        let svc = hex!("0e c1 23 45 ab");
+        assert_eq!(NalHeaderExtension::new(UnitType::PrefixNALUnit, &svc[1..]).unwrap(), Some(NalHeaderExtension::Svc(SvcExtension {
+            idr_flag: true,
+            priority_id: 1,
+            no_inter_layer_pred_flag: false,
+            dependency_id: 2,
+            quality_id: 3,
+            temporal_id: 2,
+            use_ref_base_pic_flag: false,
+            discardable_flag: false,
+            output_flag: true,
+        })));
+        // coded slice extension with svc_extension_flag clear (MVC)
+        let mvc = hex!("14 41 81 23 ab");
+        let ext = NalHeaderExtension::new(UnitType::SliceExtension, &mvc[1..]).unwrap().unwrap();
+        assert_eq!(ext, NalHeaderExtension::Mvc(MvcExtension {
+            non_idr_flag: true,
+            priority_id: 1,
+            view_id: 0b10_0000_0100,
+            temporal_id: 0b100,
+            anchor_pic_flag: false,
+            inter_view_flag: true,
+        }));
+        assert_eq!(ext.byte_len(), 3);
+        // 3D-AVC extension, only two bytes long
+        let avc3d = hex!("15 81 7f ab");
+        let ext = NalHeaderExtension::new(UnitType::SliceExtensionViewComponent, &avc3d[1..]).unwrap().unwrap();
+        assert_eq!(ext, NalHeaderExtension::Avc3d(Avc3dExtension {
+            view_idx: 0b0000_0010,
+            depth_flag: true,
+            non_idr_flag: true,
+            temporal_id: 0b111,
+            anchor_pic_flag: true,
+            inter_view_flag: true,
+        }));
+        assert_eq!(ext.byte_len(), 2);
+
+        assert_eq!(NalHeaderExtension::new(UnitType::SeqParameterSet, &[]).unwrap(), None);
+        assert!(matches!(NalHeaderExtension::new(UnitType::PrefixNALUnit, &svc[1..3]), Err(NalHeaderError::TruncatedExtension)));
+
+        // RefNal skips the extension, even when split across chunks
+        use crate::rbsp::BitRead;
+        let tail = [&svc[2..3], &svc[3..]];
+        let nal = RefNal::new(&svc[..2], &tail[..], true);
+        assert!(matches!(nal.header_extension().unwrap(), Some(NalHeaderExtension::Svc(_))));
+        assert_eq!(nal.reader().read_remaining_payload("rbsp").unwrap(), &[0xab][..]);
+        let tail = [&avc3d[3..]];
+        let nal = RefNal::new(&avc3d[..3], &tail[..], true);
+        assert!(matches!(nal.header_extension().unwrap(), Some(NalHeaderExtension::Avc3d(_))));
+        assert_eq!(nal.reader().read_remaining_payload("rbsp").unwrap(), &[0xab][..]);
+    }
+
+    #[test]
+    fn forbidden_zero_bit() {
+        assert!(matches!(NalHeader::new(0xe7), Err(NalHeaderError::ForbiddenZeroBit)));
+        let h = NalHeader::with_policy(0xe7, ForbiddenZeroBitPolicy::Warn).unwrap();
+        assert!(h.forbidden_zero_bit());
+        assert_eq!(h.nal_unit_type(), UnitType::SeqParameterSet);
+        assert_eq!(h.nal_ref_idc(), 3);
+        assert!(!NalHeader::new(0x67).unwrap().forbidden_zero_bit());
+
+        let seen = std::rc::Rc::new(RefCell::new(Vec::new()));
+        let seen2 = std::rc::Rc::clone(&seen);
+        let mut s = NalSwitch::default();
+        s.put_handler(UnitType::AccessUnitDelimiter, Box::new(RefCell::new(NalAccumulator::new(move |_ctx: &mut Context<()>, nal| {
+            seen2.borrow_mut().push(nal);
+        }))));
+        let mut ctx = Context::default();
+        for &policy in &[ForbiddenZeroBitPolicy::Reject, ForbiddenZeroBitPolicy::Accept] {
+            s.set_forbidden_zero_bit_policy(policy);
+            s.start(&mut ctx);
+            s.push(&mut ctx, &hex!("89 10")[..]);
+            s.end(&mut ctx);
+        }
+        assert_eq!(&seen.borrow()[..], &[vec![0x89, 0x10]][..]);
+
+        // with Warn, the error handler is told, and decides whether the NAL unit is handled.
+        let reported = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let reported2 = std::sync::Arc::clone(&reported);
+        ctx.set_error_handler(Some(Box::new(move |e: &crate::ReportedError| {
+            assert!(matches!(e.kind, ErrorKind::NalHeader(NalHeaderError::ForbiddenZeroBit)));
+            assert_eq!(e.severity, Severity::Warning);
+            let n = reported2.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1;
+            if n == 1 { ErrorAction::Continue } else { ErrorAction::SkipNal }
+        })));
+        s.set_forbidden_zero_bit_policy(ForbiddenZeroBitPolicy::Warn);
+        for _ in 0..2 {
+            s.start(&mut ctx);
+            s.push(&mut ctx, &hex!("89 20")[..]);
+            s.end(&mut ctx);
+        }
+        assert_eq!(reported.load(std::sync::atomic::Ordering::Relaxed), 2);
+        assert_eq!(&seen.borrow()[..], &[vec![0x89, 0x10], vec![0x89, 0x20]][..]);
+    }
+
+    #[test]
+    fn default_handler() {
+        let nals = std::rc::Rc::new(RefCell::new(Vec::new()));
+        let mut s = NalSwitch::default();
+        let handler = |tag: u8| {
+            let nals = std::rc::Rc::clone(&nals);
+            Box::new(RefCell::new(NalAccumulator::new(move |_ctx: &mut Context<()>, nal| {
+                nals.borrow_mut().push((tag, nal));
+            })))
+        };
+        s.put_handler(UnitType::AccessUnitDelimiter, handler(1));
+        s.set_default_handler(Some(handler(2)));
+        let mut ctx = Context::default();
+        let mut push = |s: &mut NalSwitch<()>, data: &[u8]| {
+            s.start(&mut ctx);
+            s.push(&mut ctx, data);
+            s.end(&mut ctx);
+        };
+        push(&mut s, &hex!("09 10")[..]);
+        push(&mut s, &hex!("0c ff 80")[..]);
+        assert!(s.remove_handler(UnitType::AccessUnitDelimiter).is_some());
+        assert!(s.remove_handler(UnitType::AccessUnitDelimiter).is_none());
+        push(&mut s, &hex!("09 30")[..]);
+        s.set_default_handler(None);
+        push(&mut s, &hex!("0b")[..]);
+        assert_eq!(&nals.borrow()[..], &[
+            (1, vec![0x09, 0x10]),
+            (2, vec![0x0c, 0xff, 0x80]),
+            (2, vec![0x09, 0x30]),
+        ][..]);
+    }
+
+    #[test]
+    fn max_nal_size() {
+        use crate::annexb::AnnexBReader;
+        let nals = std::rc::Rc::new(RefCell::new(Vec::new()));
+        let nals2 = std::rc::Rc::clone(&nals);
+        let mut s = NalSwitch::default();
+        s.set_default_handler(Some(Box::new(RefCell::new(NalAccumulator::new(move |_ctx: &mut Context<()>, nal| {
+            nals2.borrow_mut().push(nal);
+        })))));
+        s.set_max_nal_size(Some(4));
+        let mut r = AnnexBReader::new(s);
+        let mut ctx = Context::default();
+        let data = hex!(
+           "00 00 01 09 10
+            00 00 01 0c ff ff ff ff ff ff 80
+            00 00 01 09 30");
+        r.start(&mut ctx);
+        for chunk in data.chunks(2) {
+            r.push(&mut ctx, chunk);
+        }
+        r.end_units(&mut ctx);
+        assert_eq!(&nals.borrow()[..], &[vec![0x09, 0x10], vec![0x09, 0x30]][..]);
+
+        let mut s = NalSwitch::<()>::default();
+        s.set_max_nal_size(Some(2));
+        s.start(&mut ctx);
+        s.push(&mut ctx, &hex!("0c ff ff")[..]);
+        assert_eq!(s.error(), Some(&NalSwitchError::NalTooLarge { unit_type: None, max_size: 2 }));
+        s.end(&mut ctx);
+        s.start(&mut ctx);
+        assert_eq!(s.error(), None);
+    }
+
+    #[test]
+    fn switch_reset() {
+        let nals = std::rc::Rc::new(RefCell::new(Vec::new()));
+        let nals2 = std::rc::Rc::clone(&nals);
+        let mut s = NalSwitch::default();
+        s.set_default_handler(Some(Box::new(RefCell::new(NalAccumulator::new(move |_ctx: &mut Context<()>, nal| {
+            nals2.borrow_mut().push(nal);
+        })))));
+        let mut ctx = Context::default();
+        s.start(&mut ctx);
+        s.push(&mut ctx, &hex!("65 88")[..]);
+        s.reset(&mut ctx);
+        s.end(&mut ctx);
+        s.start(&mut ctx);
+        s.push(&mut ctx, &hex!("09 10")[..]);
+        s.end(&mut ctx);
+        assert_eq!(&nals.borrow()[..], &[vec![0x09, 0x10]][..]);
+    }
+
+    #[test]
+    fn error_handler() {
+        use crate::annexb::AnnexBReader;
+        use crate::{ErrorAction, ErrorKind, Severity};
+        use std::rc::Rc;
+        use std::sync::{Arc, Mutex};
+        let data = hex!(
+           "00 00 01 80 10
+            00 00 01 67 ff
+            00 00 01 09 10");
+        let run = |action: ErrorAction, chunk_size: usize| {
+            let nals = Rc::new(RefCell::new(Vec::new()));
+            let nals2 = Rc::clone(&nals);
+            let mut s = NalSwitch::default();
+            s.put_handler(UnitType::SeqParameterSet, Box::new(RefCell::new(crate::rbsp::RbspDecoder::new(sps::SeqParameterSetNalHandler::default()))));
+            s.set_default_handler(Some(Box::new(RefCell::new(NalAccumulator::new(move |_ctx: &mut Context<()>, nal| {
+                nals2.borrow_mut().push(nal);
+            })))));
+            let errors = Arc::new(Mutex::new(Vec::new()));
+            let errors2 = Arc::clone(&errors);
+            let mut ctx = Context::default();
+            ctx.set_error_handler(Some(Box::new(move |e| {
+                errors2.lock().unwrap().push((e.severity, e.nal_offset, format!("{:?}", e.kind)));
+                action
+            })));
+            let mut r = AnnexBReader::new(s);
+            r.start(&mut ctx);
+            for chunk in data.chunks(chunk_size) {
+                r.push(&mut ctx, chunk);
+            }
+            r.end_units(&mut ctx);
+            let errors = errors.lock().unwrap().clone();
+            let nals = nals.borrow().clone();
+            (errors, nals, ctx.is_aborted())
+        };
+
+        for &chunk_size in &[1, data.len()] {
+            // neither the NAL unit with a bad header nor the SPS can be used, so Continue
+            // skips them just as SkipNal does.
+            for &action in &[ErrorAction::Continue, ErrorAction::SkipNal] {
+                let (errors, nals, aborted) = run(action, chunk_size);
+                assert_eq!(errors.len(), 2);
+                assert_eq!((errors[0].0, errors[0].1), (Severity::Error, Some(3)));
+                assert!(errors[0].2.starts_with("NalHeader("));
+                assert_eq!((errors[1].0, errors[1].1), (Severity::Error, Some(8)));
+                assert!(errors[1].2.starts_with("Sps("));
+                assert_eq!(nals, vec![vec![0x09, 0x10]]);
+                assert!(!aborted);
+            }
+
+            // the rest of the pushed data is ignored, even within the same push().
+            let (errors, nals, aborted) = run(ErrorAction::Abort, chunk_size);
+            assert_eq!(errors.len(), 1, "chunk_size={}", chunk_size);
+            assert!(nals.is_empty());
+            assert!(aborted);
+        }
+
+        // without a handler, errors are logged and parsing continues,
+        let mut ctx = Context::default();
+        assert_eq!(ctx.report_error(Severity::Warning, ErrorKind::NalHeader(NalHeaderError::ForbiddenZeroBit)), ErrorAction::Continue);
+        assert_eq!(ctx.report_error(Severity::Error, ErrorKind::NalHeader(NalHeaderError::ForbiddenZeroBit)), ErrorAction::SkipNal);
+        assert!(!ctx.is_aborted());
+    }
+}
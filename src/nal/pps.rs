@@ -1,10 +1,9 @@
-use super::NalHandler;
-use super::NalHeader;
+use super::{NalHandler, NalInterest};
+use super::{NalHeader, NalHeaderError, UnitType};
 use super::sps;
 use std::marker;
-use crate::{rbsp, Context};
-use crate::rbsp::RbspBitReader;
-use log::*;
+use crate::{rbsp, Context, ErrorKind, Severity};
+use crate::rbsp::{BitRead, BitReaderSlice, BitWrite, BitWriterError, BitWriterVec};
 
 #[derive(Debug)]
 pub enum PpsError {
@@ -15,6 +14,20 @@ pub enum PpsError {
     BadPicParamSetId(ParamSetIdError),
     BadSeqParamSetId(ParamSetIdError),
     ScalingMatrix(sps::ScalingMatrixError),
+    /// `num_slice_groups_minus1` was greater than the maximum of `7` permitted by any profile.
+    InvalidNumSliceGroupsMinus1(u32),
+    /// The named `num_ref_idx_lX_default_active_minus1` value was greater than `31`.
+    InvalidNumRefIdx(&'static str, u32),
+    /// The named slice group map value was outside the range permitted by the SPS's picture
+    /// size and `num_slice_groups_minus1`.
+    InvalidSliceGroupMap { name: &'static str, value: u32 },
+    /// The NAL unit given to [PicParameterSet::from_nal] was empty, lacking even the header
+    /// byte.
+    EmptyNal,
+    /// The NAL unit given to [PicParameterSet::from_nal] had an invalid header.
+    NalHeader(NalHeaderError),
+    /// The NAL unit given to [PicParameterSet::from_nal] was not a PPS.
+    IncorrectNalType(UnitType),
 }
 
 impl From<rbsp::RbspBitReaderError> for PpsError {
@@ -23,7 +36,9 @@ impl From<rbsp::RbspBitReaderError> for PpsError {
     }
 }
 
-#[derive(Debug, Clone)]
+/// The pattern in which the slice groups of `slice_group_map_type` 3 to 5 evolve.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SliceGroupChangeType {
     BoxOut,
     RasterScan,
@@ -38,151 +53,281 @@ impl SliceGroupChangeType {
             _ => Err(PpsError::InvalidSliceGroupChangeType(id))
         }
     }
+
+    fn id(self) -> u32 {
+        match self {
+            SliceGroupChangeType::BoxOut => 3,
+            SliceGroupChangeType::RasterScan => 4,
+            SliceGroupChangeType::WipeOut => 5,
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+/// A foreground slice group of `slice_group_map_type` 2, given as the raster-scan addresses of
+/// its top-left and bottom-right slice group map units.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SliceRect {
-    top_left: u32,
-    bottom_right: u32,
+    pub top_left: u32,
+    pub bottom_right: u32,
 }
 impl SliceRect {
-    fn read(r: &mut RbspBitReader<'_>) -> Result<SliceRect,PpsError> {
-        Ok(SliceRect {
-            top_left: r.read_ue_named("top_left")?,
-            bottom_right: r.read_ue_named("bottom_right")?,
-        })
+    fn read<R: BitRead + ?Sized>(r: &mut R, sps: &sps::SeqParameterSet) -> Result<SliceRect,PpsError> {
+        let top_left = r.read_ue_named("top_left")?;
+        let bottom_right = r.read_ue_named("bottom_right")?;
+        let width = sps.pic_width_in_mbs();
+        if u64::from(bottom_right) >= sps.pic_size_in_map_units() {
+            return Err(PpsError::InvalidSliceGroupMap { name: "bottom_right", value: bottom_right });
+        }
+        if top_left > bottom_right || u64::from(top_left) % width > u64::from(bottom_right) % width {
+            return Err(PpsError::InvalidSliceGroupMap { name: "top_left", value: top_left });
+        }
+        Ok(SliceRect { top_left, bottom_right })
+    }
+
+    fn write<W: BitWrite + ?Sized>(&self, w: &mut W) -> Result<(), BitWriterError> {
+        w.write_ue_named("top_left", self.top_left)?;
+        w.write_ue_named("bottom_right", self.bottom_right)
     }
 }
 
-#[derive(Debug, Clone)]
+/// The assignment of macroblocks to slice groups (_flexible macroblock ordering_), given by
+/// `slice_group_map_type` and its parameters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SliceGroup {
+    /// Type 0: runs of the given lengths are assigned to each slice group in turn.
     Interleaved {
+        /// One entry per slice group.
         run_length_minus1: Vec<u32>,
     },
+    /// Type 1: macroblocks are spread across the slice groups in a checkerboard-like pattern.
     Dispersed {
         num_slice_groups_minus1: u32,
     },
+    /// Type 2: each slice group but the last is a rectangle; the last holds the remainder.
     ForegroundAndLeftover {
+        /// One entry per slice group other than the leftover group, in priority order.
         rectangles: Vec<SliceRect>,
     },
+    /// Types 3 to 5: two slice groups whose boundary moves from picture to picture.
     Changing {
         change_type: SliceGroupChangeType,
         num_slice_groups_minus1: u32,
         slice_group_change_direction_flag: bool,
         slice_group_change_rate_minus1: u32,
     },
+    /// Type 6: each slice group map unit's slice group is given explicitly.
     ExplicitAssignment {
         num_slice_groups_minus1: u32,
+        /// One entry per slice group map unit, in raster scan order.
         slice_group_id: Vec<u32>
     },
 }
 impl SliceGroup {
-    fn read(r: &mut RbspBitReader<'_>, num_slice_groups_minus1: u32) -> Result<SliceGroup,PpsError> {
+    /// `num_slice_groups_minus1 + 1`: the number of slice groups.
+    pub fn num_slice_groups(&self) -> u32 {
+        match self {
+            SliceGroup::Interleaved { run_length_minus1 } => run_length_minus1.len() as u32,
+            SliceGroup::ForegroundAndLeftover { rectangles } => rectangles.len() as u32 + 1,
+            SliceGroup::Dispersed { num_slice_groups_minus1 }
+            | SliceGroup::Changing { num_slice_groups_minus1, .. }
+            | SliceGroup::ExplicitAssignment { num_slice_groups_minus1, .. } => num_slice_groups_minus1 + 1,
+        }
+    }
+
+    fn read<R: BitRead + ?Sized>(r: &mut R, sps: &sps::SeqParameterSet, num_slice_groups_minus1: u32) -> Result<SliceGroup,PpsError> {
         let slice_group_map_type = r.read_ue_named("slice_group_map_type")?;
         match slice_group_map_type {
             0 => Ok(SliceGroup::Interleaved {
-                run_length_minus1: Self::read_run_lengths(r, num_slice_groups_minus1)?,
+                run_length_minus1: Self::read_run_lengths(r, sps, num_slice_groups_minus1)?,
             }),
             1 => Ok(SliceGroup::Dispersed {
                 num_slice_groups_minus1,
             }),
             2 => Ok(SliceGroup::ForegroundAndLeftover {
-                rectangles: Self::read_rectangles(r, num_slice_groups_minus1)?,
+                rectangles: Self::read_rectangles(r, sps, num_slice_groups_minus1)?,
             }),
             3|4|5 => Ok(SliceGroup::Changing {
                 change_type: SliceGroupChangeType::from_id(slice_group_map_type)?,
                 num_slice_groups_minus1,
-                slice_group_change_direction_flag: r.read_bool()?,
-                slice_group_change_rate_minus1: r.read_ue_named("slice_group_change_rate_minus1")?,
+                slice_group_change_direction_flag: r.read_bool_named("slice_group_change_direction_flag")?,
+                slice_group_change_rate_minus1: Self::read_map_units_minus1(r, sps, "slice_group_change_rate_minus1")?,
             }),
             6 => Ok(SliceGroup::ExplicitAssignment {
                 num_slice_groups_minus1,
-                slice_group_id: Self::read_group_ids(r, num_slice_groups_minus1)?,
+                slice_group_id: Self::read_group_ids(r, sps, num_slice_groups_minus1)?,
             }),
             _ => Err(PpsError::InvalidSliceGroupMapType(slice_group_map_type))
         }
     }
 
-    fn read_run_lengths(r: &mut RbspBitReader<'_>, num_slice_groups_minus1: u32) -> Result<Vec<u32>,PpsError> {
-        let mut run_length_minus1 = Vec::with_capacity(num_slice_groups_minus1 as usize + 1);
-        for _ in 0..num_slice_groups_minus1+1 {
-            run_length_minus1.push(r.read_ue_named("run_length_minus1")?);
+    fn write<W: BitWrite + ?Sized>(this: Option<&Self>, w: &mut W) -> Result<(), BitWriterError> {
+        let groups = match this {
+            None => return w.write_ue_named("num_slice_groups_minus1", 0),
+            Some(groups) => groups,
+        };
+        let num_slice_groups_minus1 = groups.num_slice_groups().wrapping_sub(1);
+        if num_slice_groups_minus1 == 0 || num_slice_groups_minus1 > 7 {
+            return Err(BitWriterError::ValueOutOfRange {
+                name: "num_slice_groups_minus1",
+                value: i64::from(groups.num_slice_groups()) - 1,
+            });
         }
-        Ok(run_length_minus1)
+        w.write_ue_named("num_slice_groups_minus1", num_slice_groups_minus1)?;
+        match groups {
+            SliceGroup::Interleaved { run_length_minus1 } => {
+                w.write_ue_named("slice_group_map_type", 0)?;
+                for &run_length_minus1 in run_length_minus1 {
+                    w.write_ue_named("run_length_minus1", run_length_minus1)?;
+                }
+            },
+            SliceGroup::Dispersed { .. } => w.write_ue_named("slice_group_map_type", 1)?,
+            SliceGroup::ForegroundAndLeftover { rectangles } => {
+                w.write_ue_named("slice_group_map_type", 2)?;
+                for rect in rectangles {
+                    rect.write(w)?;
+                }
+            },
+            SliceGroup::Changing { change_type, slice_group_change_direction_flag, slice_group_change_rate_minus1, .. } => {
+                w.write_ue_named("slice_group_map_type", change_type.id())?;
+                w.write_bool_named("slice_group_change_direction_flag", *slice_group_change_direction_flag)?;
+                w.write_ue_named("slice_group_change_rate_minus1", *slice_group_change_rate_minus1)?;
+            },
+            SliceGroup::ExplicitAssignment { slice_group_id, .. } => {
+                w.write_ue_named("slice_group_map_type", 6)?;
+                if slice_group_id.is_empty() || slice_group_id.len() > u32::MAX as usize {
+                    return Err(BitWriterError::ValueOutOfRange {
+                        name: "pic_size_in_map_units_minus1",
+                        value: slice_group_id.len() as i64 - 1,
+                    });
+                }
+                w.write_ue_named("pic_size_in_map_units_minus1", slice_group_id.len() as u32 - 1)?;
+                let size = 32 - num_slice_groups_minus1.leading_zeros();
+                for &id in slice_group_id {
+                    w.write_u_named("slice_group_id", size, id)?;
+                }
+            },
+        }
+        Ok(())
     }
 
-    fn read_rectangles(r: &mut RbspBitReader<'_>, num_slice_groups_minus1: u32) -> Result<Vec<SliceRect>,PpsError> {
-        let mut run_length_minus1 = Vec::with_capacity(num_slice_groups_minus1 as usize + 1);
-        for _ in 0..num_slice_groups_minus1+1 {
-            run_length_minus1.push(SliceRect::read(r)?);
+    /// Reads a value which must be less than _PicSizeInMapUnits_.
+    fn read_map_units_minus1<R: BitRead + ?Sized>(r: &mut R, sps: &sps::SeqParameterSet, name: &'static str) -> Result<u32,PpsError> {
+        let value = r.read_ue_named(name)?;
+        if u64::from(value) >= sps.pic_size_in_map_units() {
+            return Err(PpsError::InvalidSliceGroupMap { name, value });
         }
-        Ok(run_length_minus1)
+        Ok(value)
     }
 
-    fn read_group_ids(r: &mut RbspBitReader<'_>, num_slice_groups_minus1: u32) -> Result<Vec<u32>,PpsError> {
-        let pic_size_in_map_units_minus1 = r.read_ue_named("pic_size_in_map_units_minus1")?;
-        // TODO: avoid any panics due to failed conversions
-        let size = ((1f64+f64::from(pic_size_in_map_units_minus1)).log2()) as u8;
+    fn read_run_lengths<R: BitRead + ?Sized>(r: &mut R, sps: &sps::SeqParameterSet, num_slice_groups_minus1: u32) -> Result<Vec<u32>,PpsError> {
         let mut run_length_minus1 = Vec::with_capacity(num_slice_groups_minus1 as usize + 1);
         for _ in 0..num_slice_groups_minus1+1 {
-            run_length_minus1.push(r.read_u32(size)?);
+            run_length_minus1.push(Self::read_map_units_minus1(r, sps, "run_length_minus1")?);
         }
         Ok(run_length_minus1)
     }
-}
 
-#[derive(Debug, Clone)]
-struct PicScalingMatrix {
-    // TODO
-}
-impl PicScalingMatrix {
-    fn read(r: &mut RbspBitReader<'_>, sps: &sps::SeqParameterSet, transform_8x8_mode_flag: bool) -> Result<Option<PicScalingMatrix>,PpsError> {
-        let pic_scaling_matrix_present_flag = r.read_bool()?;
-        Ok(if pic_scaling_matrix_present_flag {
-            let mut scaling_list4x4 = vec!();
-            let mut scaling_list8x8 = vec!();
-
-            let count = if transform_8x8_mode_flag {
-                if sps.chroma_info.chroma_format == sps::ChromaFormat::YUV444 { 12 } else { 8 }
-            } else {
-                0
-            };
-            for i in 0..6+count {
-                let seq_scaling_list_present_flag = r.read_bool()?;
-                if seq_scaling_list_present_flag {
-                    if i < 6 {
-                        scaling_list4x4.push(sps::ScalingList::read(r, 16).map_err(PpsError::ScalingMatrix)?);
-                    } else {
-                        scaling_list8x8.push(sps::ScalingList::read(r, 64).map_err(PpsError::ScalingMatrix)?);
-                    }
-                }
+    fn read_rectangles<R: BitRead + ?Sized>(r: &mut R, sps: &sps::SeqParameterSet, num_slice_groups_minus1: u32) -> Result<Vec<SliceRect>,PpsError> {
+        // the last slice group is the leftover, which has no rectangle.
+        let mut rectangles = Vec::with_capacity(num_slice_groups_minus1 as usize);
+        for _ in 0..num_slice_groups_minus1 {
+            rectangles.push(SliceRect::read(r, sps)?);
+        }
+        Ok(rectangles)
+    }
+
+    fn read_group_ids<R: BitRead + ?Sized>(r: &mut R, sps: &sps::SeqParameterSet, num_slice_groups_minus1: u32) -> Result<Vec<u32>,PpsError> {
+        let pic_size_in_map_units_minus1 = r.read_ue_named("pic_size_in_map_units_minus1")?;
+        // must equal PicSizeInMapUnits - 1, which is bounded by the SPS parser's limits.
+        if u64::from(pic_size_in_map_units_minus1) + 1 != sps.pic_size_in_map_units() {
+            return Err(PpsError::InvalidSliceGroupMap {
+                name: "pic_size_in_map_units_minus1",
+                value: pic_size_in_map_units_minus1,
+            });
+        }
+        // Ceil( Log2( num_slice_groups_minus1 + 1 ) ) bits, per section 7.4.2.2
+        let size = (32 - num_slice_groups_minus1.leading_zeros()) as u8;
+        let mut slice_group_id = Vec::with_capacity(pic_size_in_map_units_minus1 as usize + 1);
+        for _ in 0..=pic_size_in_map_units_minus1 {
+            let id = r.read_u32(size)?;
+            if id > num_slice_groups_minus1 {
+                return Err(PpsError::InvalidSliceGroupMap { name: "slice_group_id", value: id });
             }
-            Some(PicScalingMatrix { })
-        } else {
-            None
-        })
+            slice_group_id.push(id);
+        }
+        Ok(slice_group_id)
     }
 }
 
+/// The trailing fields of a PPS, present (as a whole) only when `more_rbsp_data()` follows
+/// `redundant_pic_cnt_present_flag`, as is typical for the High profiles.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PicParameterSetExtra {
-    transform_8x8_mode_flag: bool,
-    pic_scaling_matrix: Option<PicScalingMatrix>,
-    second_chroma_qp_index_offset: i32,
+    pub transform_8x8_mode_flag: bool,
+    /// The picture-level scaling matrix, if `pic_scaling_matrix_present_flag` is set.  Lists
+    /// which are not signalled are derived by _fall-back rule set A_ of _Table 7-2_ if the SPS
+    /// has no `seq_scaling_matrix_present_flag`, or by _fall-back rule set B_ (from the SPS's
+    /// lists) if it does.
+    /// See [PicParameterSet::scaling_matrix].
+    pub pic_scaling_matrix: Option<sps::SeqScalingMatrix>,
+    pub second_chroma_qp_index_offset: i32,
 }
 impl PicParameterSetExtra {
-    fn read(r: &mut RbspBitReader<'_>, sps: &sps::SeqParameterSet) -> Result<Option<PicParameterSetExtra>,PpsError> {
+    fn read<R: BitRead + ?Sized>(r: &mut R, sps: &sps::SeqParameterSet) -> Result<Option<PicParameterSetExtra>,PpsError> {
         Ok(if r.has_more_rbsp_data("pic_parameter_set_extra")? {
-            let transform_8x8_mode_flag = r.read_bool()?;
+            let transform_8x8_mode_flag = r.read_bool_named("transform_8x8_mode_flag")?;
             Some(PicParameterSetExtra {
                 transform_8x8_mode_flag,
-                pic_scaling_matrix: PicScalingMatrix::read(r, sps, transform_8x8_mode_flag)?,
+                pic_scaling_matrix: Self::read_scaling_matrix(r, sps, transform_8x8_mode_flag)?,
                 second_chroma_qp_index_offset: r.read_se_named("second_chroma_qp_index_offset")?,
             })
         } else {
             None
         })
     }
+
+    fn write<W: BitWrite + ?Sized>(&self, sps: &sps::SeqParameterSet, w: &mut W) -> Result<(), BitWriterError> {
+        w.write_bool_named("transform_8x8_mode_flag", self.transform_8x8_mode_flag)?;
+        w.write_bool_named("pic_scaling_matrix_present_flag", self.pic_scaling_matrix.is_some())?;
+        if let Some(ref m) = self.pic_scaling_matrix {
+            m.write_lists(w, Self::scaling_list_count(sps, self.transform_8x8_mode_flag), Self::fall_back(sps))?;
+        }
+        w.write_se_named("second_chroma_qp_index_offset", self.second_chroma_qp_index_offset)
+    }
+
+    fn read_scaling_matrix<R: BitRead + ?Sized>(r: &mut R, sps: &sps::SeqParameterSet, transform_8x8_mode_flag: bool) -> Result<Option<sps::SeqScalingMatrix>,PpsError> {
+        if !r.read_bool_named("pic_scaling_matrix_present_flag")? {
+            return Ok(None);
+        }
+        let count = Self::scaling_list_count(sps, transform_8x8_mode_flag);
+        sps::SeqScalingMatrix::read_lists(r, count, Self::fall_back(sps))
+            .map(Some)
+            .map_err(PpsError::ScalingMatrix)
+    }
+
+    /// The number of lists of `pic_scaling_matrix()`: six 4x4 lists, then (with
+    /// `transform_8x8_mode_flag`) two 8x8 lists, or six for 4:4:4.
+    fn scaling_list_count(sps: &sps::SeqParameterSet, transform_8x8_mode_flag: bool) -> usize {
+        6 + if !transform_8x8_mode_flag {
+            0
+        } else if sps.chroma_info.chroma_format == sps::ChromaFormat::YUV444 {
+            6
+        } else {
+            2
+        }
+    }
+
+    /// The SPS matrix for fall-back rule set B, or `None` for rule set A.
+    fn fall_back(sps: &sps::SeqParameterSet) -> Option<&sps::SeqScalingMatrix> {
+        if sps.chroma_info.seq_scaling_matrix_present_flag {
+            Some(&sps.chroma_info.scaling_matrix)
+        } else {
+            None
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -204,8 +349,85 @@ impl ParamSetId {
         self.0
     }
 }
+/// Serialized as a plain integer; deserialization rejects out-of-range ids just as
+/// [`ParamSetId::from_u32`] does.
+#[cfg(feature = "serde")]
+impl serde::Serialize for ParamSetId {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_u8(self.0)
+    }
+}
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for ParamSetId {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let id = u8::deserialize(d)?;
+        ParamSetId::from_u32(u32::from(id))
+            .map_err(|e| serde::de::Error::custom(format!("bad parameter set id: {:?}", e)))
+    }
+}
+
+/// A way in which a PPS is inconsistent with the SPS it refers to; see [PicParameterSet::check].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PpsViolation {
+    /// The PPS refers to an SPS with a different `seq_parameter_set_id` than the one given.
+    SeqParamSetId(ParamSetId),
+    /// The default number of active references in the given list (`0` or `1`) exceeds the
+    /// maximum for a slice: 16 when the SPS permits only frames, otherwise 32.
+    NumRefIdx { list: u8, count: u32, max: u32 },
+    /// The default number of active references in the given list exceeds the number of
+    /// reference frames (or, when the SPS permits field coding, fields) available under
+    /// `max_num_ref_frames`, so some indices can never refer to a picture.  The spec doesn't
+    /// forbid this, but it suggests a misconfigured encoder.
+    NumRefIdxExceedsRefFrames { list: u8, count: u32, max: u32 },
+    /// `pic_init_qp_minus26` is outside the range permitted by the SPS's luma bit depth.
+    PicInitQp(i32),
+    /// `pic_init_qs_minus26` is outside the range `-26..=25`.
+    PicInitQs(i32),
+    /// The named chroma QP index offset is outside the range `-12..=12`.
+    ChromaQpIndexOffset(&'static str, i32),
+    /// `weighted_bipred_idc` has the reserved value `3`.
+    WeightedBipredIdc(u8),
+    /// CABAC is used, which the SPS's profile doesn't permit.
+    Cabac(sps::Profile),
+    /// Slice groups are used, which the SPS's profile doesn't permit.
+    SliceGroups(sps::Profile),
+    /// Weighted prediction is used, which the SPS's profile doesn't permit.
+    WeightedPrediction(sps::Profile),
+    /// `redundant_pic_cnt_present_flag` is set, which the SPS's profile doesn't permit.
+    RedundantPictures(sps::Profile),
+    /// `transform_8x8_mode_flag` is set, which the SPS's profile doesn't permit.
+    Transform8x8(sps::Profile),
+    /// The PPS has its own scaling matrix, which the SPS's profile doesn't permit.
+    ScalingMatrix(sps::Profile),
+}
+
+/// Whether the profile permits `entropy_coding_mode_flag`.
+fn cabac_permitted(profile: sps::Profile) -> bool {
+    !matches!(profile, sps::Profile::Baseline | sps::Profile::ConstrainedBaseline
+        | sps::Profile::Extended | sps::Profile::CAVLC444Intra
+        | sps::Profile::ScalableBase | sps::Profile::ScalableConstrainedBaseline)
+}
+
+/// Whether the profile permits the PPS extension's 8x8 transform and scaling matrix.
+fn high_tools_permitted(profile: sps::Profile) -> bool {
+    !matches!(profile, sps::Profile::Baseline | sps::Profile::ConstrainedBaseline
+        | sps::Profile::Main | sps::Profile::Extended
+        | sps::Profile::ScalableBase | sps::Profile::ScalableConstrainedBaseline)
+}
+
+/// Whether the profile permits slice groups and redundant pictures.
+fn error_resilience_tools_permitted(profile: sps::Profile) -> bool {
+    matches!(profile, sps::Profile::Baseline | sps::Profile::Extended | sps::Profile::Unknown(_))
+}
+
+/// Whether the profile permits weighted prediction.
+fn weighted_prediction_permitted(profile: sps::Profile) -> bool {
+    !matches!(profile, sps::Profile::Baseline | sps::Profile::ConstrainedBaseline
+        | sps::Profile::ScalableBase | sps::Profile::ScalableConstrainedBaseline)
+}
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PicParameterSet {
     pub pic_parameter_set_id: ParamSetId,
     pub seq_parameter_set_id: ParamSetId,
@@ -225,8 +447,29 @@ pub struct PicParameterSet {
     pub extension: Option<PicParameterSetExtra>,
 }
 impl PicParameterSet {
+    /// Parses the given RBSP: the payload following the NAL header byte, with emulation
+    /// prevention already removed.  The referenced SPS must already be present in `ctx`.  See
+    /// [PicParameterSet::from_nal] to parse a whole NAL unit.
     pub fn from_bytes<Ctx>(ctx: &Context<Ctx>, buf: &[u8]) -> Result<PicParameterSet, PpsError> {
-        let mut r = RbspBitReader::new(buf);
+        Self::from_bits(ctx, &mut BitReaderSlice::new(buf))
+    }
+
+    /// Parses a whole PPS NAL unit: the header byte followed by the escaped payload, as found in
+    /// an `avcC` record or a `sprop-parameter-sets` value.  The referenced SPS must already be
+    /// present in `ctx`.
+    pub fn from_nal<Ctx>(ctx: &Context<Ctx>, nal: &[u8]) -> Result<PicParameterSet, PpsError> {
+        let (&header, payload) = nal.split_first().ok_or(PpsError::EmptyNal)?;
+        let header = NalHeader::new(header).map_err(PpsError::NalHeader)?;
+        if header.nal_unit_type() != UnitType::PicParameterSet {
+            return Err(PpsError::IncorrectNalType(header.nal_unit_type()));
+        }
+        Self::from_bytes(ctx, &rbsp::decode_nal(payload))
+    }
+
+    /// Parses the `pic_parameter_set_rbsp()` syntax from the given reader, which is expected to
+    /// be positioned just after the NAL header.  The referenced SPS must already be present in
+    /// `ctx`.
+    pub fn from_bits<Ctx, R: BitRead + ?Sized>(ctx: &Context<Ctx>, r: &mut R) -> Result<PicParameterSet, PpsError> {
         let pic_parameter_set_id = ParamSetId::from_u32(r.read_ue_named("pic_parameter_set_id")?)
             .map_err(PpsError::BadPicParamSetId)?;
         let seq_parameter_set_id = ParamSetId::from_u32(r.read_ue_named("seq_parameter_set_id")?)
@@ -238,9 +481,9 @@ impl PicParameterSet {
             seq_parameter_set_id,
             entropy_coding_mode_flag: r.read_bool()?,
             bottom_field_pic_order_in_frame_present_flag: r.read_bool()?,
-            slice_groups: Self::read_slice_groups(&mut r)?,
-            num_ref_idx_l0_default_active_minus1: r.read_ue_named("num_ref_idx_l0_default_active_minus1")?,
-            num_ref_idx_l1_default_active_minus1: r.read_ue_named("num_ref_idx_l1_default_active_minus1")?,
+            slice_groups: Self::read_slice_groups(r, seq_parameter_set)?,
+            num_ref_idx_l0_default_active_minus1: Self::read_num_ref_idx(r, "num_ref_idx_l0_default_active_minus1")?,
+            num_ref_idx_l1_default_active_minus1: Self::read_num_ref_idx(r, "num_ref_idx_l1_default_active_minus1")?,
             weighted_pred_flag: r.read_bool()?,
             weighted_bipred_idc: r.read_u8(2)?,
             pic_init_qp_minus26: r.read_se_named("pic_init_qp_minus26")?,
@@ -249,14 +492,158 @@ impl PicParameterSet {
             deblocking_filter_control_present_flag: r.read_bool()?,
             constrained_intra_pred_flag: r.read_bool()?,
             redundant_pic_cnt_present_flag: r.read_bool()?,
-            extension: PicParameterSetExtra::read(&mut r, seq_parameter_set)?,
+            extension: PicParameterSetExtra::read(r, seq_parameter_set)?,
         })
     }
 
-    fn read_slice_groups(r: &mut RbspBitReader<'_>) -> Result<Option<SliceGroup>,PpsError> {
+    /// Writes this PPS as a `pic_parameter_set_rbsp()` (the inverse of
+    /// [PicParameterSet::from_bits]), including the extension if present and the
+    /// `rbsp_trailing_bits()`.
+    ///
+    /// The given SPS (which should be the one this refers to) determines the number of
+    /// scaling lists and how they may be abbreviated by the fall-back rules.  As with
+    /// [SeqParameterSet::write](../sps/struct.SeqParameterSet.html#method.write), the NAL header
+    /// is not written and emulation prevention must be added to the result.
+    pub fn write<W: BitWrite + ?Sized>(&self, sps: &sps::SeqParameterSet, w: &mut W) -> Result<(), BitWriterError> {
+        w.write_ue_named("pic_parameter_set_id", u32::from(self.pic_parameter_set_id.id()))?;
+        w.write_ue_named("seq_parameter_set_id", u32::from(self.seq_parameter_set_id.id()))?;
+        w.write_bool_named("entropy_coding_mode_flag", self.entropy_coding_mode_flag)?;
+        w.write_bool_named("bottom_field_pic_order_in_frame_present_flag", self.bottom_field_pic_order_in_frame_present_flag)?;
+        SliceGroup::write(self.slice_groups.as_ref(), w)?;
+        w.write_ue_named("num_ref_idx_l0_default_active_minus1", self.num_ref_idx_l0_default_active_minus1)?;
+        w.write_ue_named("num_ref_idx_l1_default_active_minus1", self.num_ref_idx_l1_default_active_minus1)?;
+        w.write_bool_named("weighted_pred_flag", self.weighted_pred_flag)?;
+        w.write_u_named("weighted_bipred_idc", 2, u32::from(self.weighted_bipred_idc))?;
+        w.write_se_named("pic_init_qp_minus26", self.pic_init_qp_minus26)?;
+        w.write_se_named("pic_init_qs_minus26", self.pic_init_qs_minus26)?;
+        w.write_se_named("chroma_qp_index_offset", self.chroma_qp_index_offset)?;
+        w.write_bool_named("deblocking_filter_control_present_flag", self.deblocking_filter_control_present_flag)?;
+        w.write_bool_named("constrained_intra_pred_flag", self.constrained_intra_pred_flag)?;
+        w.write_bool_named("redundant_pic_cnt_present_flag", self.redundant_pic_cnt_present_flag)?;
+        if let Some(ref extension) = self.extension {
+            extension.write(sps, w)?;
+        }
+        w.write_rbsp_trailing_bits()
+    }
+
+    /// Returns the RBSP written by [PicParameterSet::write]; the inverse of
+    /// [PicParameterSet::from_bytes].
+    pub fn to_bytes(&self, sps: &sps::SeqParameterSet) -> Result<Vec<u8>, BitWriterError> {
+        let mut w = BitWriterVec::new();
+        self.write(sps, &mut w)?;
+        Ok(w.into_inner())
+    }
+
+    /// `transform_8x8_mode_flag`, which is `false` when the PPS has no extension.
+    pub fn transform_8x8_mode_flag(&self) -> bool {
+        self.extension.as_ref().map(|e| e.transform_8x8_mode_flag).unwrap_or(false)
+    }
+
+    /// `second_chroma_qp_index_offset`, the offset for the Cr component, which is inferred to
+    /// equal `chroma_qp_index_offset` when the PPS has no extension.
+    pub fn second_chroma_qp_index_offset(&self) -> i32 {
+        self.extension.as_ref()
+            .map(|e| e.second_chroma_qp_index_offset)
+            .unwrap_or(self.chroma_qp_index_offset)
+    }
+
+    /// The scaling matrix in effect for pictures using this PPS: its own if present, and
+    /// otherwise that of the given SPS (which should be the one it refers to).
+    pub fn scaling_matrix<'a>(&'a self, sps: &'a sps::SeqParameterSet) -> &'a sps::SeqScalingMatrix {
+        self.extension.as_ref()
+            .and_then(|e| e.pic_scaling_matrix.as_ref())
+            .unwrap_or(&sps.chroma_info.scaling_matrix)
+    }
+
+    /// Checks the PPS against the SPS it refers to, returning every inconsistency found.
+    ///
+    /// This covers value ranges which depend on the SPS or which parsing doesn't enforce, and the
+    /// PPS-level constraints of the SPS's profile (_A.2_).  No constraints are known for
+    /// `Profile::Unknown`.  The level limits of _Annex A_ don't constrain the PPS.
+    pub fn check(&self, sps: &sps::SeqParameterSet) -> Result<(), Vec<PpsViolation>> {
+        let mut violations = Vec::new();
+        if self.seq_parameter_set_id != sps.seq_parameter_set_id {
+            violations.push(PpsViolation::SeqParamSetId(sps.seq_parameter_set_id));
+        }
+        let frames_only = sps.frame_mbs_flags.frame_mbs_only_flag();
+        let (max, max_refs) = if frames_only {
+            (16, sps.max_num_ref_frames)
+        } else {
+            (32, sps.max_num_ref_frames.saturating_mul(2))
+        };
+        let lists = [
+            (0, self.num_ref_idx_l0_default_active_minus1 + 1),
+            (1, self.num_ref_idx_l1_default_active_minus1 + 1),
+        ];
+        for &(list, count) in &lists {
+            if count > max {
+                violations.push(PpsViolation::NumRefIdx { list, count, max });
+            } else if count > max_refs.max(1) {
+                violations.push(PpsViolation::NumRefIdxExceedsRefFrames { list, count, max: max_refs });
+            }
+        }
+        let qp_bd_offset = 6 * i32::from(sps.chroma_info.bit_depth_luma_minus8);
+        if self.pic_init_qp_minus26 < -(26 + qp_bd_offset) || self.pic_init_qp_minus26 > 25 {
+            violations.push(PpsViolation::PicInitQp(self.pic_init_qp_minus26));
+        }
+        if self.pic_init_qs_minus26 < -26 || self.pic_init_qs_minus26 > 25 {
+            violations.push(PpsViolation::PicInitQs(self.pic_init_qs_minus26));
+        }
+        let offsets = [
+            ("chroma_qp_index_offset", self.chroma_qp_index_offset),
+            ("second_chroma_qp_index_offset", self.second_chroma_qp_index_offset()),
+        ];
+        for &(name, offset) in &offsets {
+            if !(-12..=12).contains(&offset) {
+                violations.push(PpsViolation::ChromaQpIndexOffset(name, offset));
+            }
+        }
+        if self.weighted_bipred_idc > 2 {
+            violations.push(PpsViolation::WeightedBipredIdc(self.weighted_bipred_idc));
+        }
+        let profile = sps.profile();
+        if self.entropy_coding_mode_flag && !cabac_permitted(profile) {
+            violations.push(PpsViolation::Cabac(profile));
+        }
+        if self.slice_groups.is_some() && !error_resilience_tools_permitted(profile) {
+            violations.push(PpsViolation::SliceGroups(profile));
+        }
+        if (self.weighted_pred_flag || self.weighted_bipred_idc != 0) && !weighted_prediction_permitted(profile) {
+            violations.push(PpsViolation::WeightedPrediction(profile));
+        }
+        if self.redundant_pic_cnt_present_flag && !error_resilience_tools_permitted(profile) {
+            violations.push(PpsViolation::RedundantPictures(profile));
+        }
+        if let Some(ref extension) = self.extension {
+            if extension.transform_8x8_mode_flag && !high_tools_permitted(profile) {
+                violations.push(PpsViolation::Transform8x8(profile));
+            }
+            if extension.pic_scaling_matrix.is_some() && !high_tools_permitted(profile) {
+                violations.push(PpsViolation::ScalingMatrix(profile));
+            }
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    fn read_num_ref_idx<R: BitRead + ?Sized>(r: &mut R, name: &'static str) -> Result<u32,PpsError> {
+        let val = r.read_ue_named(name)?;
+        if val > 31 {
+            return Err(PpsError::InvalidNumRefIdx(name, val));
+        }
+        Ok(val)
+    }
+
+    fn read_slice_groups<R: BitRead + ?Sized>(r: &mut R, sps: &sps::SeqParameterSet) -> Result<Option<SliceGroup>,PpsError> {
         let num_slice_groups_minus1 = r.read_ue_named("num_slice_groups_minus1")?;
+        if num_slice_groups_minus1 > 7 {
+            return Err(PpsError::InvalidNumSliceGroupsMinus1(num_slice_groups_minus1));
+        }
         Ok(if num_slice_groups_minus1 > 0 {
-            Some(SliceGroup::read(r, num_slice_groups_minus1)?)
+            Some(SliceGroup::read(r, sps, num_slice_groups_minus1)?)
         } else {
             None
         })
@@ -264,6 +651,7 @@ impl PicParameterSet {
 }
 
 pub struct PicParameterSetNalHandler<Ctx> {
+    header: Option<NalHeader>,
     buf: Vec<u8>,
     phantom: marker::PhantomData<Ctx>
 }
@@ -271,6 +659,7 @@ pub struct PicParameterSetNalHandler<Ctx> {
 impl<Ctx> Default for PicParameterSetNalHandler<Ctx> {
     fn default() -> Self {
         PicParameterSetNalHandler {
+            header: None,
             buf: Vec::new(),
             phantom: marker::PhantomData,
         }
@@ -281,23 +670,192 @@ impl<Ctx> NalHandler for PicParameterSetNalHandler<Ctx> {
 
     fn start(&mut self, _ctx: &mut Context<Ctx>, header: NalHeader) {
         assert_eq!(header.nal_unit_type(), super::UnitType::PicParameterSet);
+        self.header = Some(header);
     }
 
-    fn push(&mut self, _ctx: &mut Context<Ctx>, buf: &[u8]) {
+    fn push(&mut self, _ctx: &mut Context<Ctx>, buf: &[u8]) -> NalInterest {
         self.buf.extend_from_slice(buf);
+        NalInterest::Continue
     }
 
     fn end(&mut self, ctx: &mut Context<Ctx>) {
         let pps = PicParameterSet::from_bytes(ctx, &self.buf[..]);
-        self.buf.clear();
-        match pps {
-            Ok(pps) => {
+        match (pps, self.header.take()) {
+            (Ok(pps), Some(header)) => {
+                ctx.put_pic_param_set_with_nal(pps, rbsp::reencode_nal(header, &self.buf));
+            },
+            (Ok(pps), None) => {
                 ctx.put_pic_param_set(pps);
             },
-            Err(e) => {
-                error!("pps: {:?}", e);
+            (Err(e), _) => {
+                ctx.report_error(Severity::Error, ErrorKind::Pps(e));
             },
         }
+        self.buf.clear();
+    }
+
+    fn reset(&mut self, _ctx: &mut Context<Ctx>) {
+        self.header = None;
+        self.buf.clear();
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PpsBuilderError {
+    /// CABAC (`entropy_coding_mode_flag`) was requested, but the SPS's profile doesn't permit it.
+    CabacNotPermitted(sps::Profile),
+    /// `transform_8x8_mode_flag` was requested, but the SPS's profile doesn't permit it.
+    Transform8x8NotPermitted(sps::Profile),
+    /// The default number of active reference indices for the given list (`0` or `1`) must be
+    /// between 1 and 32 inclusive.
+    InvalidNumRefIdx { list: u8, count: u32 },
+    /// The initial QP is outside the range permitted by the SPS's luma bit depth.
+    InvalidQp(i32),
+    /// `chroma_qp_index_offset` must be between -12 and 12 inclusive.
+    InvalidChromaQpIndexOffset(i32),
+}
+
+/// Constructs a [PicParameterSet] for a given SPS, with defaults suited to that SPS.
+///
+/// ```
+/// # use h264_reader::nal::sps::{SpsBuilder, Profile, Level};
+/// # use h264_reader::nal::pps::PpsBuilder;
+/// let sps = SpsBuilder::new(Profile::High, Level::L4).dimensions(1920, 1080).build().unwrap();
+/// let pps = PpsBuilder::for_sps(&sps)
+///     .transform_8x8_mode(true)
+///     .build()
+///     .unwrap();
+/// assert!(pps.entropy_coding_mode_flag);
+/// assert!(pps.transform_8x8_mode_flag());
+/// ```
+///
+/// By default the PPS has id `0`, uses CABAC if the profile permits it, has one active
+/// reference index in each list, an initial QP of `26` and no chroma QP offset, and lets slices
+/// control the deblocking filter.  It has no slice groups, weighted prediction or scaling
+/// matrix.
+#[derive(Debug, Clone)]
+pub struct PpsBuilder {
+    profile: sps::Profile,
+    seq_parameter_set_id: ParamSetId,
+    bit_depth_luma_minus8: u8,
+    pic_parameter_set_id: ParamSetId,
+    cabac: bool,
+    num_ref_idx_default_active: (u32, u32),
+    pic_init_qp: i32,
+    chroma_qp_index_offset: i32,
+    deblocking_filter_control_present: bool,
+    transform_8x8_mode: bool,
+}
+impl PpsBuilder {
+    pub fn for_sps(sps: &sps::SeqParameterSet) -> Self {
+        let profile = sps.profile();
+        PpsBuilder {
+            profile,
+            seq_parameter_set_id: sps.seq_parameter_set_id,
+            bit_depth_luma_minus8: sps.chroma_info.bit_depth_luma_minus8,
+            pic_parameter_set_id: ParamSetId::from_u32(0).unwrap(),
+            cabac: cabac_permitted(profile),
+            num_ref_idx_default_active: (1, 1),
+            pic_init_qp: 26,
+            chroma_qp_index_offset: 0,
+            deblocking_filter_control_present: true,
+            transform_8x8_mode: false,
+        }
+    }
+
+    pub fn pic_parameter_set_id(mut self, id: ParamSetId) -> Self {
+        self.pic_parameter_set_id = id;
+        self
+    }
+
+    /// Whether to use CABAC rather than CAVLC entropy coding (`entropy_coding_mode_flag`).
+    pub fn cabac(mut self, cabac: bool) -> Self {
+        self.cabac = cabac;
+        self
+    }
+
+    /// The default number of active reference indices in lists 0 and 1, each from 1 to 32.
+    pub fn num_ref_idx_default_active(mut self, l0: u32, l1: u32) -> Self {
+        self.num_ref_idx_default_active = (l0, l1);
+        self
+    }
+
+    /// The initial luma QP of each slice, before `slice_qp_delta`.  This may be negative for bit
+    /// depths above 8.
+    pub fn pic_init_qp(mut self, qp: i32) -> Self {
+        self.pic_init_qp = qp;
+        self
+    }
+
+    /// The offset of the chroma QP from the luma QP.
+    pub fn chroma_qp_index_offset(mut self, offset: i32) -> Self {
+        self.chroma_qp_index_offset = offset;
+        self
+    }
+
+    /// Whether slice headers may control the deblocking filter
+    /// (`deblocking_filter_control_present_flag`).
+    pub fn deblocking_filter_control_present(mut self, present: bool) -> Self {
+        self.deblocking_filter_control_present = present;
+        self
+    }
+
+    /// Whether the 8x8 transform may be used (`transform_8x8_mode_flag`), which requires one
+    /// of the High profiles.
+    pub fn transform_8x8_mode(mut self, transform_8x8_mode: bool) -> Self {
+        self.transform_8x8_mode = transform_8x8_mode;
+        self
+    }
+
+    pub fn build(&self) -> Result<PicParameterSet, PpsBuilderError> {
+        if self.cabac && !cabac_permitted(self.profile) {
+            return Err(PpsBuilderError::CabacNotPermitted(self.profile));
+        }
+        if self.transform_8x8_mode && !high_tools_permitted(self.profile) {
+            return Err(PpsBuilderError::Transform8x8NotPermitted(self.profile));
+        }
+        let (l0, l1) = self.num_ref_idx_default_active;
+        for &(list, count) in &[(0, l0), (1, l1)] {
+            if count == 0 || count > 32 {
+                return Err(PpsBuilderError::InvalidNumRefIdx { list, count });
+            }
+        }
+        // pic_init_qp_minus26 ranges from -(26 + QpBdOffsetY) to +25.
+        let qp_bd_offset = 6 * i32::from(self.bit_depth_luma_minus8);
+        if self.pic_init_qp < -qp_bd_offset || self.pic_init_qp > 51 {
+            return Err(PpsBuilderError::InvalidQp(self.pic_init_qp));
+        }
+        if self.chroma_qp_index_offset < -12 || self.chroma_qp_index_offset > 12 {
+            return Err(PpsBuilderError::InvalidChromaQpIndexOffset(
+                self.chroma_qp_index_offset,
+            ));
+        }
+        Ok(PicParameterSet {
+            pic_parameter_set_id: self.pic_parameter_set_id,
+            seq_parameter_set_id: self.seq_parameter_set_id,
+            entropy_coding_mode_flag: self.cabac,
+            bottom_field_pic_order_in_frame_present_flag: false,
+            slice_groups: None,
+            num_ref_idx_l0_default_active_minus1: l0 - 1,
+            num_ref_idx_l1_default_active_minus1: l1 - 1,
+            weighted_pred_flag: false,
+            weighted_bipred_idc: 0,
+            pic_init_qp_minus26: self.pic_init_qp - 26,
+            pic_init_qs_minus26: 0,
+            chroma_qp_index_offset: self.chroma_qp_index_offset,
+            deblocking_filter_control_present_flag: self.deblocking_filter_control_present,
+            constrained_intra_pred_flag: false,
+            redundant_pic_cnt_present_flag: false,
+            extension: if self.transform_8x8_mode {
+                Some(PicParameterSetExtra {
+                    transform_8x8_mode_flag: true,
+                    pic_scaling_matrix: None,
+                    second_chroma_qp_index_offset: self.chroma_qp_index_offset,
+                })
+            } else {
+                None
+            },
+        })
     }
 }
 
@@ -321,7 +879,464 @@ mod test {
                 println!("pps: {:#?}", pps);
                 assert_eq!(pps.pic_parameter_set_id.id(), 0);
                 assert_eq!(pps.seq_parameter_set_id.id(), 0);
+                assert_eq!(pps.to_bytes(ctx.sps_by_id(pps.seq_parameter_set_id).unwrap()).unwrap(), &data[..]);
             }
         }
     }
+
+    #[test]
+    fn from_nal() {
+        let sps_data = hex!(
+           "64 00 0A AC 72 84 44 26 84 00 00
+            00 04 00 00 00 CA 3C 48 96 11 80");
+        let mut sps_nal = vec![0x67];
+        sps_nal.extend_from_slice(&rbsp::encode_nal(&sps_data[..]));
+        let sps = super::sps::SeqParameterSet::from_nal(&sps_nal).unwrap();
+        assert_eq!(sps, super::sps::SeqParameterSet::from_bytes(&sps_data[..]).unwrap());
+        let mut ctx = Context::default();
+        ctx.put_seq_param_set(sps);
+        let pps = PicParameterSet::from_nal(&ctx, &hex!("68 E8 43 8F 13 21 30")).unwrap();
+        assert_eq!(pps.pic_parameter_set_id.id(), 0);
+        assert!(matches!(PicParameterSet::from_nal(&ctx, &[]), Err(PpsError::EmptyNal)));
+        assert!(matches!(
+            PicParameterSet::from_nal(&ctx, &sps_nal),
+            Err(PpsError::IncorrectNalType(UnitType::SeqParameterSet))
+        ));
+        assert!(matches!(
+            PicParameterSet::from_nal(&Context::<()>::default(), &hex!("68 E8 43 8F 13 21 30")),
+            Err(PpsError::UnknownSeqParamSetId(_))
+        ));
+    }
+
+    /// Parses a PPS with the given slice group syntax, referring to a QCIF (11x9 macroblock) SPS.
+    fn read_slice_groups(write: impl FnOnce(&mut BitWriterVec)) -> Result<PicParameterSet, PpsError> {
+        let sps = sps::SpsBuilder::new(sps::Profile::Baseline, sps::Level::L1)
+            .dimensions(176, 144)
+            .build()
+            .unwrap();
+        read_pps(sps, write, |_| {})
+    }
+
+    /// Parses a PPS referring to the given SPS, with the given slice group syntax (which must
+    /// start with `num_slice_groups_minus1`) and syntax following `redundant_pic_cnt_present_flag`,
+    /// checking that a successfully parsed PPS is written back identically.
+    fn read_pps(
+        sps: sps::SeqParameterSet,
+        write_slice_groups: impl FnOnce(&mut BitWriterVec),
+        write_extension: impl FnOnce(&mut BitWriterVec),
+    ) -> Result<PicParameterSet, PpsError> {
+        let mut ctx = Context::default();
+        ctx.put_seq_param_set(sps.clone());
+        let mut w = BitWriterVec::new();
+        w.write_ue_named("pic_parameter_set_id", 0).unwrap();
+        w.write_ue_named("seq_parameter_set_id", 0).unwrap();
+        w.write_bool_named("entropy_coding_mode_flag", false).unwrap();
+        w.write_bool_named("bottom_field_pic_order_in_frame_present_flag", false).unwrap();
+        write_slice_groups(&mut w);
+        w.write_ue_named("num_ref_idx_l0_default_active_minus1", 0).unwrap();
+        w.write_ue_named("num_ref_idx_l1_default_active_minus1", 0).unwrap();
+        w.write_bool_named("weighted_pred_flag", false).unwrap();
+        w.write_u_named("weighted_bipred_idc", 2, 0).unwrap();
+        w.write_se_named("pic_init_qp_minus26", 0).unwrap();
+        w.write_se_named("pic_init_qs_minus26", 0).unwrap();
+        w.write_se_named("chroma_qp_index_offset", 0).unwrap();
+        w.write_bool_named("deblocking_filter_control_present_flag", false).unwrap();
+        w.write_bool_named("constrained_intra_pred_flag", false).unwrap();
+        w.write_bool_named("redundant_pic_cnt_present_flag", false).unwrap();
+        write_extension(&mut w);
+        w.write_rbsp_trailing_bits().unwrap();
+        let data = w.into_inner();
+        let pps = PicParameterSet::from_bytes(&ctx, &data)?;
+        assert_eq!(pps.to_bytes(&sps).unwrap(), data);
+        Ok(pps)
+    }
+
+    #[test]
+    fn slice_groups() {
+        let pps = read_slice_groups(|w| {
+            w.write_ue_named("num_slice_groups_minus1", 1).unwrap();
+            w.write_ue_named("slice_group_map_type", 0).unwrap();
+            w.write_ue_named("run_length_minus1", 10).unwrap();
+            w.write_ue_named("run_length_minus1", 21).unwrap();
+        }).unwrap();
+        assert_eq!(pps.slice_groups, Some(SliceGroup::Interleaved { run_length_minus1: vec![10, 21] }));
+
+        // three slice groups: two rectangles and the leftover.
+        let pps = read_slice_groups(|w| {
+            w.write_ue_named("num_slice_groups_minus1", 2).unwrap();
+            w.write_ue_named("slice_group_map_type", 2).unwrap();
+            for &(top_left, bottom_right) in &[(12, 36), (0, 98)] {
+                w.write_ue_named("top_left", top_left).unwrap();
+                w.write_ue_named("bottom_right", bottom_right).unwrap();
+            }
+        }).unwrap();
+        let slice_groups = pps.slice_groups.unwrap();
+        assert_eq!(slice_groups, SliceGroup::ForegroundAndLeftover { rectangles: vec![
+            SliceRect { top_left: 12, bottom_right: 36 },
+            SliceRect { top_left: 0, bottom_right: 98 },
+        ]});
+        assert_eq!(slice_groups.num_slice_groups(), 3);
+
+        let pps = read_slice_groups(|w| {
+            w.write_ue_named("num_slice_groups_minus1", 1).unwrap();
+            w.write_ue_named("slice_group_map_type", 4).unwrap();
+            w.write_bool_named("slice_group_change_direction_flag", true).unwrap();
+            w.write_ue_named("slice_group_change_rate_minus1", 98).unwrap();
+        }).unwrap();
+        assert_eq!(pps.slice_groups, Some(SliceGroup::Changing {
+            change_type: SliceGroupChangeType::RasterScan,
+            num_slice_groups_minus1: 1,
+            slice_group_change_direction_flag: true,
+            slice_group_change_rate_minus1: 98,
+        }));
+
+        let ids: Vec<u32> = (0..99).map(|i| i % 3).collect();
+        let pps = read_slice_groups(|w| {
+            w.write_ue_named("num_slice_groups_minus1", 2).unwrap();
+            w.write_ue_named("slice_group_map_type", 6).unwrap();
+            w.write_ue_named("pic_size_in_map_units_minus1", 98).unwrap();
+            for &id in &ids {
+                w.write_u_named("slice_group_id", 2, id).unwrap();
+            }
+        }).unwrap();
+        assert_eq!(pps.slice_groups, Some(SliceGroup::ExplicitAssignment { num_slice_groups_minus1: 2, slice_group_id: ids }));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let pps = read_slice_groups(|w| {
+            w.write_ue_named("num_slice_groups_minus1", 2).unwrap();
+            w.write_ue_named("slice_group_map_type", 2).unwrap();
+            for &(top_left, bottom_right) in &[(12, 36), (0, 98)] {
+                w.write_ue_named("top_left", top_left).unwrap();
+                w.write_ue_named("bottom_right", bottom_right).unwrap();
+            }
+        }).unwrap();
+        let json = serde_json::to_string(&pps).unwrap();
+        let pps2: PicParameterSet = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&pps2).unwrap(), json);
+    }
+
+    #[test]
+    fn invalid_slice_groups() {
+        let invalid = |write: fn(&mut BitWriterVec)| match read_slice_groups(write) {
+            Err(PpsError::InvalidSliceGroupMap { name, value }) => (name, value),
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(invalid(|w| {
+            w.write_ue_named("num_slice_groups_minus1", 1).unwrap();
+            w.write_ue_named("slice_group_map_type", 6).unwrap();
+            w.write_ue_named("pic_size_in_map_units_minus1", 1_000_000).unwrap();
+        }), ("pic_size_in_map_units_minus1", 1_000_000));
+        assert_eq!(invalid(|w| {
+            w.write_ue_named("num_slice_groups_minus1", 2).unwrap();
+            w.write_ue_named("slice_group_map_type", 6).unwrap();
+            w.write_ue_named("pic_size_in_map_units_minus1", 98).unwrap();
+            w.write_u_named("slice_group_id", 2, 3).unwrap();
+        }), ("slice_group_id", 3));
+        // the top-left corner is to the right of the bottom-right corner.
+        assert_eq!(invalid(|w| {
+            w.write_ue_named("num_slice_groups_minus1", 1).unwrap();
+            w.write_ue_named("slice_group_map_type", 2).unwrap();
+            w.write_ue_named("top_left", 5).unwrap();
+            w.write_ue_named("bottom_right", 14).unwrap();
+        }), ("top_left", 5));
+        assert_eq!(invalid(|w| {
+            w.write_ue_named("num_slice_groups_minus1", 1).unwrap();
+            w.write_ue_named("slice_group_map_type", 0).unwrap();
+            w.write_ue_named("run_length_minus1", 99).unwrap();
+        }), ("run_length_minus1", 99));
+    }
+
+    #[test]
+    fn extension() {
+        let high = || sps::SpsBuilder::new(sps::Profile::High, sps::Level::L4)
+            .dimensions(1920, 1080)
+            .build()
+            .unwrap();
+        let no_slice_groups = |w: &mut BitWriterVec| w.write_ue_named("num_slice_groups_minus1", 0).unwrap();
+
+        let pps = read_pps(high(), no_slice_groups, |_| {}).unwrap();
+        assert!(pps.extension.is_none());
+        assert!(!pps.transform_8x8_mode_flag());
+        assert_eq!(pps.second_chroma_qp_index_offset(), pps.chroma_qp_index_offset);
+
+        // Two 8x8 lists follow the six 4x4 lists for 4:2:0; none signalled uses rule set A.
+        let pps = read_pps(high(), no_slice_groups, |w| {
+            w.write_bool_named("transform_8x8_mode_flag", true).unwrap();
+            w.write_bool_named("pic_scaling_matrix_present_flag", true).unwrap();
+            for _ in 0..8 {
+                w.write_bool_named("pic_scaling_list_present_flag", false).unwrap();
+            }
+            w.write_se_named("second_chroma_qp_index_offset", -2).unwrap();
+        }).unwrap();
+        assert!(pps.transform_8x8_mode_flag());
+        assert_eq!(pps.second_chroma_qp_index_offset(), -2);
+        let sps = high();
+        let m = pps.scaling_matrix(&sps);
+        assert_eq!(m.scaling_list4x4[0], sps::DEFAULT_4X4_INTRA);
+        assert_eq!(m.scaling_list4x4[5], sps::DEFAULT_4X4_INTER);
+        assert_eq!(m.scaling_list8x8[1], sps::DEFAULT_8X8_INTER);
+
+        // With an SPS matrix, unsignalled lists fall back to the SPS's (rule set B).
+        let mut sps = high();
+        sps.chroma_info.seq_scaling_matrix_present_flag = true;
+        sps.chroma_info.scaling_matrix.scaling_list4x4[3] = [20; 16];
+        sps.chroma_info.scaling_matrix.scaling_list8x8[0] = [24; 64];
+        let mut expected = sps.chroma_info.scaling_matrix.clone();
+        expected.scaling_list4x4[0] = [8; 16];
+        expected.scaling_list4x4[1] = [8; 16];
+        expected.scaling_list4x4[2] = [8; 16];
+        expected.scaling_list8x8[2] = [24; 64];
+        expected.scaling_list8x8[4] = [24; 64];
+        let pps = read_pps(sps.clone(), no_slice_groups, |w| {
+            w.write_bool_named("transform_8x8_mode_flag", true).unwrap();
+            w.write_bool_named("pic_scaling_matrix_present_flag", true).unwrap();
+            expected.write_lists(w, 8, Some(&sps.chroma_info.scaling_matrix)).unwrap();
+            w.write_se_named("second_chroma_qp_index_offset", 3).unwrap();
+        }).unwrap();
+        assert_eq!(pps.scaling_matrix(&sps), &expected);
+        assert_eq!(pps.second_chroma_qp_index_offset(), 3);
+
+        // An SPS which explicitly signals flat lists still selects rule set B.
+        let mut sps = high();
+        sps.chroma_info.seq_scaling_matrix_present_flag = true;
+        let pps = read_pps(sps.clone(), no_slice_groups, |w| {
+            w.write_bool_named("transform_8x8_mode_flag", true).unwrap();
+            w.write_bool_named("pic_scaling_matrix_present_flag", true).unwrap();
+            for _ in 0..8 {
+                w.write_bool_named("pic_scaling_list_present_flag", false).unwrap();
+            }
+            w.write_se_named("second_chroma_qp_index_offset", 0).unwrap();
+        }).unwrap();
+        assert_eq!(pps.scaling_matrix(&sps), &sps::SeqScalingMatrix::default());
+
+        // Without transform_8x8_mode_flag, only the six 4x4 lists are signalled.
+        let pps = read_pps(high(), no_slice_groups, |w| {
+            w.write_bool_named("transform_8x8_mode_flag", false).unwrap();
+            w.write_bool_named("pic_scaling_matrix_present_flag", true).unwrap();
+            for _ in 0..6 {
+                w.write_bool_named("pic_scaling_list_present_flag", false).unwrap();
+            }
+            w.write_se_named("second_chroma_qp_index_offset", 1).unwrap();
+        }).unwrap();
+        assert_eq!(pps.second_chroma_qp_index_offset(), 1);
+    }
+
+    #[test]
+    fn write_errors() {
+        let sps = sps::SpsBuilder::new(sps::Profile::Baseline, sps::Level::L1)
+            .dimensions(176, 144)
+            .build()
+            .unwrap();
+        let mut pps = read_slice_groups(|w| w.write_ue_named("num_slice_groups_minus1", 0).unwrap()).unwrap();
+        pps.slice_groups = Some(SliceGroup::Interleaved { run_length_minus1: vec![0] });
+        assert!(matches!(pps.to_bytes(&sps), Err(BitWriterError::ValueOutOfRange { name: "num_slice_groups_minus1", value: 0 })));
+        pps.slice_groups = Some(SliceGroup::ExplicitAssignment { num_slice_groups_minus1: 1, slice_group_id: vec![0, 2] });
+        assert!(matches!(pps.to_bytes(&sps), Err(BitWriterError::ValueTooLarge { name: "slice_group_id", .. })));
+    }
+
+    #[test]
+    fn builder() {
+        let sps = sps::SpsBuilder::new(sps::Profile::High10, sps::Level::L4)
+            .dimensions(1920, 1080)
+            .bit_depth(10)
+            .build()
+            .unwrap();
+        let pps = PpsBuilder::for_sps(&sps)
+            .pic_parameter_set_id(ParamSetId::from_u32(3).unwrap())
+            .num_ref_idx_default_active(4, 2)
+            .pic_init_qp(-12)
+            .chroma_qp_index_offset(-2)
+            .transform_8x8_mode(true)
+            .build()
+            .unwrap();
+        assert!(pps.entropy_coding_mode_flag);
+        assert_eq!(pps.num_ref_idx_l0_default_active_minus1, 3);
+        assert_eq!(pps.pic_init_qp_minus26, -38);
+        assert_eq!(pps.second_chroma_qp_index_offset(), -2);
+        let mut ctx = Context::default();
+        ctx.put_seq_param_set(sps.clone());
+        let pps2 = PicParameterSet::from_bytes(&ctx, &pps.to_bytes(&sps).unwrap()).unwrap();
+        assert_eq!(format!("{:?}", pps), format!("{:?}", pps2));
+
+        assert_eq!(PpsBuilder::for_sps(&sps).pic_init_qp(-13).build().unwrap_err(),
+                   PpsBuilderError::InvalidQp(-13));
+        assert_eq!(PpsBuilder::for_sps(&sps).num_ref_idx_default_active(1, 33).build().unwrap_err(),
+                   PpsBuilderError::InvalidNumRefIdx { list: 1, count: 33 });
+
+        let sps = sps::SpsBuilder::new(sps::Profile::ConstrainedBaseline, sps::Level::L3)
+            .dimensions(640, 480)
+            .build()
+            .unwrap();
+        let pps = PpsBuilder::for_sps(&sps).build().unwrap();
+        assert!(!pps.entropy_coding_mode_flag);
+        assert!(pps.extension.is_none());
+        assert_eq!(PpsBuilder::for_sps(&sps).cabac(true).build().unwrap_err(),
+                   PpsBuilderError::CabacNotPermitted(sps::Profile::ConstrainedBaseline));
+        assert_eq!(PpsBuilder::for_sps(&sps).transform_8x8_mode(true).build().unwrap_err(),
+                   PpsBuilderError::Transform8x8NotPermitted(sps::Profile::ConstrainedBaseline));
+    }
+
+    #[test]
+    fn activation() {
+        let sps = sps::SpsBuilder::new(sps::Profile::Main, sps::Level::L3)
+            .dimensions(640, 480)
+            .build()
+            .unwrap();
+        let pps = PpsBuilder::for_sps(&sps).build().unwrap();
+        let id = pps.pic_parameter_set_id;
+        let mut ctx = Context::default();
+        assert_eq!(ctx.activation(id).unwrap_err(), crate::MissingParameterSet::Pps(id));
+        ctx.put_pic_param_set(pps);
+        assert_eq!(ctx.activation(id).unwrap_err(), crate::MissingParameterSet::Sps {
+            pps: id,
+            sps: sps.seq_parameter_set_id,
+        });
+        ctx.put_seq_param_set(sps);
+        let (pps, sps) = ctx.activation(id).unwrap();
+        assert_eq!(pps.pic_parameter_set_id, id);
+        assert_eq!(sps.seq_parameter_set_id, pps.seq_parameter_set_id);
+    }
+
+    #[test]
+    fn parameter_set_observer() {
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut ctx = Context::default();
+        let s = seen.clone();
+        ctx.set_parameter_set_observer(Some(Box::new(move |change: &crate::ParameterSetChange<'_>| {
+            s.lock().unwrap().push(match change {
+                crate::ParameterSetChange::Sps { old, new } => {
+                    ("sps", old.map(|o| i32::from(o.level_idc)), i32::from(new.level_idc))
+                },
+                crate::ParameterSetChange::SubsetSps { old, new } => {
+                    ("subset_sps", old.map(|o| i32::from(o.sps.level_idc)), i32::from(new.sps.level_idc))
+                },
+                crate::ParameterSetChange::SpsExtension { old, new } => {
+                    ("sps_extension", old.map(|o| i32::from(o.additional_extension_flag)), i32::from(new.additional_extension_flag))
+                },
+                crate::ParameterSetChange::Pps { old, new } => {
+                    ("pps", old.map(|o| o.pic_init_qp_minus26), new.pic_init_qp_minus26)
+                },
+            });
+        })));
+        let sps = sps::SpsBuilder::new(sps::Profile::Main, sps::Level::L3)
+            .dimensions(640, 480)
+            .build()
+            .unwrap();
+        ctx.put_seq_param_set(sps.clone());
+        ctx.put_pic_param_set(PpsBuilder::for_sps(&sps).pic_init_qp(30).build().unwrap());
+        let sps2 = sps::SpsBuilder::new(sps::Profile::Main, sps::Level::L4)
+            .dimensions(1920, 1080)
+            .build()
+            .unwrap();
+        ctx.put_seq_param_set_with_nal(sps2.clone(), vec![]);
+        ctx.put_pic_param_set(PpsBuilder::for_sps(&sps2).pic_init_qp(32).build().unwrap());
+        ctx.put_subset_seq_param_set(super::super::subset_sps::SubsetSeqParameterSet {
+            sps: sps2.clone(),
+            extension: super::super::subset_sps::SubsetSpsExtension::Unsupported,
+        });
+        // seq_parameter_set_id=0, aux_format_idc=0, additional_extension_flag=0
+        let ext = super::super::sps_extension::SeqParameterSetExtension::from_bytes(&hex!("D0")[..]).unwrap();
+        ctx.put_sps_extension(ext.clone());
+        ctx.put_sps_extension(ext);
+        assert_eq!(&seen.lock().unwrap()[..], &[
+            ("sps", None, 30),
+            ("pps", None, 4),
+            ("sps", Some(30), 40),
+            ("pps", Some(4), 6),
+            ("subset_sps", None, 40),
+            ("sps_extension", None, 0),
+            ("sps_extension", Some(0), 0),
+        ]);
+    }
+
+    #[test]
+    fn retains_nal() {
+        use crate::annexb::AnnexBReader;
+        use crate::nal::NalSwitch;
+        use std::cell::RefCell;
+        let sps = sps::SpsBuilder::new(sps::Profile::High, sps::Level::L4)
+            .dimensions(1920, 1080)
+            .build()
+            .unwrap();
+        let pps = PpsBuilder::for_sps(&sps).transform_8x8_mode(true).build().unwrap();
+        let mut pps_nal = vec![0x68];
+        pps_nal.extend_from_slice(&rbsp::encode_nal(&pps.to_bytes(&sps).unwrap()));
+        let mut data = vec![0, 0, 0, 1];
+        data.extend_from_slice(&pps_nal[..]);
+        data.extend_from_slice(&hex!("00 00 00 01 09 10")[..]);
+        let mut s = NalSwitch::default();
+        s.put_handler(UnitType::PicParameterSet, Box::new(RefCell::new(rbsp::RbspDecoder::new(PicParameterSetNalHandler::default()))));
+        let mut ctx = Context::default();
+        ctx.put_seq_param_set(sps);
+        let mut r = AnnexBReader::new(s);
+        r.start(&mut ctx);
+        r.push(&mut ctx, &data[..]);
+        r.end_units(&mut ctx);
+        let id = pps.pic_parameter_set_id;
+        assert!(ctx.pps_by_id(id).unwrap().transform_8x8_mode_flag());
+        assert_eq!(ctx.pps_nal_by_id(id), Some(&pps_nal[..]));
+
+        // the NAL is forgotten when the PPS is replaced without one.
+        ctx.put_pic_param_set(pps);
+        assert_eq!(ctx.pps_nal_by_id(id), None);
+    }
+
+    #[test]
+    fn check() {
+        let sps = sps::SpsBuilder::new(sps::Profile::High, sps::Level::L4)
+            .dimensions(1920, 1080)
+            .max_num_ref_frames(4)
+            .build()
+            .unwrap();
+        let pps = PpsBuilder::for_sps(&sps)
+            .num_ref_idx_default_active(4, 2)
+            .transform_8x8_mode(true)
+            .build()
+            .unwrap();
+        assert_eq!(pps.check(&sps), Ok(()));
+
+        let mut bad = pps.clone();
+        bad.num_ref_idx_l0_default_active_minus1 = 4;
+        bad.num_ref_idx_l1_default_active_minus1 = 16;
+        bad.pic_init_qp_minus26 = -27;
+        bad.weighted_bipred_idc = 3;
+        bad.extension.as_mut().unwrap().second_chroma_qp_index_offset = 13;
+        assert_eq!(bad.check(&sps), Err(vec![
+            PpsViolation::NumRefIdxExceedsRefFrames { list: 0, count: 5, max: 4 },
+            PpsViolation::NumRefIdx { list: 1, count: 17, max: 16 },
+            PpsViolation::PicInitQp(-27),
+            PpsViolation::ChromaQpIndexOffset("second_chroma_qp_index_offset", 13),
+            PpsViolation::WeightedBipredIdc(3),
+        ]));
+
+        // the same PPS used with a Main profile SPS.
+        let main = sps::SpsBuilder::new(sps::Profile::Main, sps::Level::L4)
+            .dimensions(1920, 1080)
+            .max_num_ref_frames(4)
+            .build()
+            .unwrap();
+        let mut bad = pps;
+        bad.redundant_pic_cnt_present_flag = true;
+        bad.slice_groups = Some(SliceGroup::Dispersed { num_slice_groups_minus1: 1 });
+        assert_eq!(bad.check(&main), Err(vec![
+            PpsViolation::SliceGroups(sps::Profile::Main),
+            PpsViolation::RedundantPictures(sps::Profile::Main),
+            PpsViolation::Transform8x8(sps::Profile::Main),
+        ]));
+
+        let baseline = sps::SpsBuilder::new(sps::Profile::ConstrainedBaseline, sps::Level::L3)
+            .dimensions(640, 480)
+            .build()
+            .unwrap();
+        let mut bad = PpsBuilder::for_sps(&baseline).build().unwrap();
+        bad.entropy_coding_mode_flag = true;
+        bad.weighted_pred_flag = true;
+        assert_eq!(bad.check(&baseline), Err(vec![
+            PpsViolation::Cabac(sps::Profile::ConstrainedBaseline),
+            PpsViolation::WeightedPrediction(sps::Profile::ConstrainedBaseline),
+        ]));
+    }
 }
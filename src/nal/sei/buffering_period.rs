@@ -2,13 +2,13 @@ use super::SeiCompletePayloadReader;
 use std::marker;
 use crate::nal::{sps, pps};
 use crate::rbsp::RbspBitReader;
-use crate::Context;
+use crate::{Context, ErrorKind, Severity};
 use crate::nal::sei::HeaderType;
 use crate::rbsp::RbspBitReaderError;
 use log::*;
 
 #[derive(Debug)]
-enum BufferingPeriodError {
+pub enum BufferingPeriodError {
     ReaderError(RbspBitReaderError),
     UndefinedSeqParamSetId(pps::ParamSetId),
     InvalidSeqParamSetId(pps::ParamSetIdError),
@@ -56,7 +56,7 @@ impl BufferingPeriod {
         let mut read = |p: &sps::HrdParameters| read_cpb_removal_delay_list(
             &mut r,
             p.cpb_specs.len(),
-            p.initial_cpb_removal_delay_length_minus1 + 1,
+            p.initial_cpb_removal_delay_length(),
         );
         let nal_hrd_bp = vui.and_then(|v| v.nal_hrd_parameters.as_ref()).map(&mut read).transpose()?;
         let vcl_hrd_bp = vui.and_then(|v| v.vcl_hrd_parameters.as_ref()).map(&mut read).transpose()?;
@@ -82,7 +82,7 @@ impl<Ctx> SeiCompletePayloadReader for BufferingPeriodPayloadReader<Ctx> {
     fn header(&mut self, ctx: &mut Context<Ctx>, payload_type: HeaderType, buf: &[u8]) {
         assert_eq!(payload_type, HeaderType::BufferingPeriod);
         match BufferingPeriod::read(ctx, buf) {
-            Err(e) => error!("Failure reading buffering_period: {:?}", e),
+            Err(e) => { ctx.report_error(Severity::Error, ErrorKind::BufferingPeriod(e)); },
             Ok(buffering_period) => {
                 info!("TODO: expose buffering_period {:#?}", buffering_period);
             }
@@ -1,12 +1,30 @@
 pub mod buffering_period;
 pub mod user_data_registered_itu_t_t35;
 pub mod pic_timing;
+pub mod recovery_point;
 
 use crate::Context;
-use crate::nal::NalHandler;
+use crate::nal::{NalHandler, NalInterest};
 use crate::nal::NalHeader;
 use crate::rbsp::RbspDecoder;
-use log::*;
+use crate::{ErrorKind, Severity};
+
+/// Problems with the framing of SEI messages within an SEI NAL unit.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SeiError {
+    /// The `payloadType` value exceeded `u32::MAX`.
+    PayloadTypeOverflow,
+    /// The `payloadSize` value exceeded `u32::MAX`.
+    PayloadSizeOverflow,
+    /// The NAL unit ended after an SEI message, without `rbsp_trailing_bits()`.
+    MissingTrailingBits,
+    /// The NAL unit ended part way through a `payloadType`.
+    TruncatedPayloadType,
+    /// The NAL unit ended part way through a `payloadSize`.
+    TruncatedPayloadSize,
+    /// The NAL unit ended having read only `consumed_size` bytes of the payload.
+    TruncatedPayload { payload_type: HeaderType, payload_size: u32, consumed_size: u32 },
+}
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum HeaderType {
@@ -286,7 +304,7 @@ impl<R: SeiIncrementalPayloadReader> NalHandler for SeiHeaderReader<R> {
         self.state = SeiHeaderState::Begin;
     }
 
-    fn push(&mut self, ctx: &mut Context<Self::Ctx>, buf: &[u8]) {
+    fn push(&mut self, ctx: &mut Context<Self::Ctx>, buf: &[u8]) -> NalInterest {
         assert!(!buf.is_empty());
         let mut input = &buf[..];
         loop {
@@ -310,7 +328,13 @@ impl<R: SeiIncrementalPayloadReader> NalHandler for SeiHeaderReader<R> {
                     }
                 },
                 SeiHeaderState::PayloadType { payload_type } => {
-                    let new_type = b as u32 + payload_type;
+                    let new_type = match payload_type.checked_add(b as u32) {
+                        Some(t) => t,
+                        None => {
+                            ctx.report_error(Severity::Error, ErrorKind::Sei(SeiError::PayloadTypeOverflow));
+                            return NalInterest::Stop;
+                        },
+                    };
                     match b {
                         0xff => {
                             SeiHeaderState::PayloadType { payload_type: new_type }
@@ -321,7 +345,13 @@ impl<R: SeiIncrementalPayloadReader> NalHandler for SeiHeaderReader<R> {
                     }
                 },
                 SeiHeaderState::PayloadSize { payload_type, payload_size } => {
-                    let new_size = b as u32 + payload_size;
+                    let new_size = match payload_size.checked_add(b as u32) {
+                        Some(s) => s,
+                        None => {
+                            ctx.report_error(Severity::Error, ErrorKind::Sei(SeiError::PayloadSizeOverflow));
+                            return NalInterest::Stop;
+                        },
+                    };
                     match b {
                         0xff => {
                             SeiHeaderState::PayloadSize { payload_type, payload_size: new_size }
@@ -360,17 +390,18 @@ impl<R: SeiIncrementalPayloadReader> NalHandler for SeiHeaderReader<R> {
                 input = &input[1..];
             }
         }
+        NalInterest::Continue
     }
 
     fn end(&mut self, ctx: &mut Context<Self::Ctx>) {
         match self.state {
             SeiHeaderState::Begin => {
-                error!("End of SEI data without rbsp_trailing_bits");
+                ctx.report_error(Severity::Warning, ErrorKind::Sei(SeiError::MissingTrailingBits));
                 self.reader.reset(ctx);
             },
             SeiHeaderState::End => panic!("SeiHeaderReader already ended and end() called again"),
             SeiHeaderState::PayloadType { .. } => {
-                error!("End of SEI data encountered while reading SEI payloadType");
+                ctx.report_error(Severity::Error, ErrorKind::Sei(SeiError::TruncatedPayloadType));
                 self.reader.reset(ctx);
             },
             SeiHeaderState::PayloadSize { payload_type: HeaderType::ReservedSeiMessage(0x80), payload_size: 0 } => {
@@ -378,16 +409,96 @@ impl<R: SeiIncrementalPayloadReader> NalHandler for SeiHeaderReader<R> {
                 //       be 0b10000000 in an SEI payload since SEI messages are byte-aligned).
             },
             SeiHeaderState::PayloadSize { .. } => {
-                error!("End of SEI data encountered while reading SEI payloadSize");
+                ctx.report_error(Severity::Error, ErrorKind::Sei(SeiError::TruncatedPayloadSize));
                 self.reader.reset(ctx);
             },
             SeiHeaderState::Payload { payload_type, payload_size, consumed_size } => {
-                error!("End of SEI data encountered having read {} bytes of payloadSize={} for header type {:?}", consumed_size, payload_size, payload_type);
+                ctx.report_error(Severity::Error, ErrorKind::Sei(SeiError::TruncatedPayload { payload_type, payload_size, consumed_size }));
                 self.reader.reset(ctx);
             },
         }
         self.state = SeiHeaderState::End;
     }
+
+    fn reset(&mut self, ctx: &mut Context<Self::Ctx>) {
+        if let SeiHeaderState::Payload { .. } = self.state {
+            self.reader.reset(ctx);
+        }
+        self.state = SeiHeaderState::End;
+    }
+}
+
+/// An SEI message within an SEI NAL unit's RBSP.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SeiMessage<'a> {
+    pub payload_type: HeaderType,
+    pub payload: &'a [u8],
+}
+
+/// Iterates over the SEI messages of a complete SEI RBSP (with emulation prevention removed);
+/// see [messages].
+pub struct SeiMessages<'a> {
+    rbsp: &'a [u8],
+    done: bool,
+}
+impl<'a> SeiMessages<'a> {
+    fn read_varint(&mut self, truncated: SeiError, overflow: SeiError) -> Result<u32, SeiError> {
+        let mut value = 0u32;
+        loop {
+            let (&b, rest) = self.rbsp.split_first().ok_or_else(|| truncated.clone())?;
+            self.rbsp = rest;
+            value = value.checked_add(u32::from(b)).ok_or_else(|| overflow.clone())?;
+            if b != 0xff {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn read_message(&mut self) -> Result<SeiMessage<'a>, SeiError> {
+        let payload_type = HeaderType::from_id(
+            self.read_varint(SeiError::TruncatedPayloadType, SeiError::PayloadTypeOverflow)?,
+        );
+        let payload_size = self.read_varint(SeiError::TruncatedPayloadSize, SeiError::PayloadSizeOverflow)?;
+        if payload_size as usize > self.rbsp.len() {
+            return Err(SeiError::TruncatedPayload {
+                payload_type,
+                payload_size,
+                consumed_size: self.rbsp.len() as u32,
+            });
+        }
+        let (payload, rest) = self.rbsp.split_at(payload_size as usize);
+        self.rbsp = rest;
+        Ok(SeiMessage { payload_type, payload })
+    }
+}
+impl<'a> Iterator for SeiMessages<'a> {
+    type Item = Result<SeiMessage<'a>, SeiError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.rbsp.split_first() {
+            None => {
+                self.done = true;
+                return Some(Err(SeiError::MissingTrailingBits));
+            },
+            Some((0x80, rest)) if rest.iter().all(|&b| b == 0) => {
+                self.done = true;
+                return None;
+            },
+            Some(_) => {},
+        }
+        let message = self.read_message();
+        self.done = message.is_err();
+        Some(message)
+    }
+}
+
+/// Returns the SEI messages of a complete SEI RBSP (with emulation prevention removed), as an
+/// alternative to the incremental [SeiNalHandler].  Iteration ends after the first error.
+pub fn messages(rbsp: &[u8]) -> SeiMessages<'_> {
+    SeiMessages { rbsp, done: false }
 }
 
 pub struct SeiNalHandler<R: SeiIncrementalPayloadReader> {
@@ -409,13 +520,17 @@ impl<R: SeiIncrementalPayloadReader> NalHandler for SeiNalHandler<R> {
         self.reader.start(ctx, header);
     }
 
-    fn push(&mut self, ctx: &mut Context<Self::Ctx>, buf: &[u8]) {
-        self.reader.push(ctx, buf);
+    fn push(&mut self, ctx: &mut Context<Self::Ctx>, buf: &[u8]) -> NalInterest {
+        self.reader.push(ctx, buf)
     }
 
     fn end(&mut self, ctx: &mut Context<Self::Ctx>) {
         self.reader.end(ctx);
     }
+
+    fn reset(&mut self, ctx: &mut Context<Self::Ctx>) {
+        self.reader.reset(ctx);
+    }
 }
 
 #[cfg(test)]
@@ -504,4 +619,23 @@ mod test {
         assert_eq!(&st.data[..], [0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
         assert_eq!(st.ended, 1);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn complete_messages() {
+        let rbsp = [0x06, 0x01, 0xaa, 0xff, 0x01, 0x02, 0xbb, 0xcc, 0x80];
+        let all: Vec<_> = messages(&rbsp[..]).collect();
+        assert_eq!(all, vec![
+            Ok(SeiMessage { payload_type: HeaderType::RecoveryPoint, payload: &[0xaa][..] }),
+            Ok(SeiMessage { payload_type: HeaderType::ReservedSeiMessage(256), payload: &[0xbb, 0xcc][..] }),
+        ]);
+        let all: Vec<_> = messages(&rbsp[..3]).collect();
+        assert_eq!(all[1], Err(SeiError::MissingTrailingBits));
+        let all: Vec<_> = messages(&rbsp[..7]).collect();
+        assert_eq!(all[1], Err(SeiError::TruncatedPayload {
+            payload_type: HeaderType::ReservedSeiMessage(256),
+            payload_size: 2,
+            consumed_size: 1,
+        }));
+        assert_eq!(all.len(), 2);
+    }
+}
@@ -1,11 +1,10 @@
 use crate::nal::sei::SeiCompletePayloadReader;
-use crate::Context;
+use crate::{Context, ErrorKind, Severity};
 use crate::nal::sei::HeaderType;
 use crate::nal::pps::ParamSetId;
 use crate::rbsp::RbspBitReader;
 use crate::nal::sps;
 use crate::rbsp::RbspBitReaderError;
-use log::*;
 
 // FIXME: SPS selection
 //      We should really wait until we know what SPS is in use by the frame which follows the
@@ -262,10 +261,10 @@ impl PicTiming {
 
     fn read_delays(r: &mut RbspBitReader<'_>, sps: &sps::SeqParameterSet) -> Result<Option<Delays>,PicTimingError> {
         Ok(if let Some(ref vui_params) = sps.vui_parameters {
-            if let Some(ref hrd) = vui_params.nal_hrd_parameters.as_ref().or_else(|| vui_params.nal_hrd_parameters.as_ref() ) {
+            if let Some(ref hrd) = vui_params.nal_hrd_parameters.as_ref().or(vui_params.vcl_hrd_parameters.as_ref()) {
                 Some(Delays {
-                    cpb_removal_delay: r.read_u32(hrd.cpb_removal_delay_length_minus1+1)?,
-                    dpb_output_delay: r.read_u32(hrd.dpb_output_delay_length_minus1+1)?,
+                    cpb_removal_delay: r.read_u32(hrd.cpb_removal_delay_length())?,
+                    dpb_output_delay: r.read_u32(hrd.dpb_output_delay_length())?,
                 })
             } else {
                 None
@@ -325,7 +324,7 @@ impl<H: PicTimingHandler> SeiCompletePayloadReader for PicTimingReader<H> {
     fn header(&mut self, ctx: &mut Context<Self::Ctx>, payload_type: HeaderType, buf: &[u8]) {
         assert_eq!(payload_type, HeaderType::PicTiming);
         match PicTiming::read(ctx, buf) {
-            Err(e) => error!("Failure reading pic_timing: {:?}", e),
+            Err(e) => { ctx.report_error(Severity::Error, ErrorKind::PicTiming(e)); },
             Ok(pic_timing) => {
                 self.handler.handle(ctx, pic_timing);
             }
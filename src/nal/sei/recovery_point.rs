@@ -0,0 +1,57 @@
+use crate::rbsp::RbspBitReader;
+use crate::rbsp::RbspBitReaderError;
+
+#[derive(Debug)]
+pub enum RecoveryPointError {
+    ReaderError(RbspBitReaderError),
+}
+impl From<RbspBitReaderError> for RecoveryPointError {
+    fn from(e: RbspBitReaderError) -> Self {
+        RecoveryPointError::ReaderError(e)
+    }
+}
+
+/// A recovery point SEI message (_D.1.8_), marking a picture from which decoding may begin,
+/// with pictures being output correctly (or approximately so) after `recovery_frame_cnt` frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecoveryPoint {
+    /// The number of frames in output order, after the picture with this message, until the
+    /// recovery point.
+    pub recovery_frame_cnt: u32,
+    /// Whether pictures from the recovery point are an exact match for those which would be
+    /// output had decoding begun at the previous IDR picture.
+    pub exact_match_flag: bool,
+    /// Whether some pictures following this one may be severely degraded, as when the stream
+    /// was spliced here.
+    pub broken_link_flag: bool,
+    pub changing_slice_group_idc: u8,
+}
+impl RecoveryPoint {
+    pub fn read(buf: &[u8]) -> Result<RecoveryPoint, RecoveryPointError> {
+        let mut r = RbspBitReader::new(buf);
+        Ok(RecoveryPoint {
+            recovery_frame_cnt: r.read_ue_named("recovery_frame_cnt")?,
+            exact_match_flag: r.read_bool_named("exact_match_flag")?,
+            broken_link_flag: r.read_bool_named("broken_link_flag")?,
+            changing_slice_group_idc: r.read_u8(2)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        // recovery_frame_cnt=3 (ue 00100), exact_match_flag=1, broken_link_flag=0,
+        // changing_slice_group_idc=0, then payload padding.
+        assert_eq!(RecoveryPoint::read(&[0b0010_0100, 0b0010_0000][..]).unwrap(), RecoveryPoint {
+            recovery_frame_cnt: 3,
+            exact_match_flag: true,
+            broken_link_flag: false,
+            changing_slice_group_idc: 0,
+        });
+        assert!(matches!(RecoveryPoint::read(&[0b0010_0100][..]), Err(RecoveryPointError::ReaderError(_))));
+    }
+}
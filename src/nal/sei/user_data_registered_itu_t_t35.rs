@@ -1,7 +1,6 @@
 use crate::nal::sei::HeaderType;
-use crate::Context;
+use crate::{Context, ErrorKind, Severity};
 use crate::nal::sei::SeiCompletePayloadReader;
-use log::*;
 
 #[derive(Debug)]
 pub enum ItuTT35Error {
@@ -448,7 +447,7 @@ impl<R: Register> SeiCompletePayloadReader for UserDataRegisteredItuTT35Reader<R
                 self.register.handle(ctx, country_code, payload);
             },
             Err(e) => {
-                error!("Failed to read user_data_registered_itu_t_t35 header: {:?}", e);
+                ctx.report_error(Severity::Error, ErrorKind::ItuTT35(e));
             }
         }
     }
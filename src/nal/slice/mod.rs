@@ -1,13 +1,15 @@
+pub mod partition;
 
-use crate::Context;
-use crate::rbsp::RbspBitReader;
+use crate::{Context, ErrorKind, Severity};
+use crate::rbsp::{BitRead, BitWrite, BitWriterError, RbspBitReader};
 use crate::rbsp::RbspBitReaderError;
 use crate::nal::pps::{ParamSetId, PicParameterSet};
 use crate::nal::pps;
 use crate::nal::sps;
+use std::convert::TryFrom;
 use std::marker;
 use crate::nal::sps::SeqParameterSet;
-use crate::nal::NalHeader;
+use crate::nal::{NalHeader, NalInterest};
 use log::*;
 
 enum ParseState {
@@ -16,28 +18,43 @@ enum ParseState {
     Continue(NalHeader),
 }
 
-#[derive(Debug,PartialEq)]
-enum SliceFamily {
+/// The coding type of a slice, from `slice_type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SliceFamily {
+    /// Predicted from at most one reference picture per block.
     P,
+    /// Bi-predicted from up to two reference pictures per block.
     B,
+    /// Intra-coded.
     I,
+    /// A switching P slice, used to switch between streams.
     SP,
+    /// A switching I slice, used to switch between streams.
     SI
 }
-#[derive(Debug,PartialEq)]
-enum SliceExclusive {
+impl SliceFamily {
+    /// True for I and SI slices, which don't refer to other pictures.
+    pub fn is_intra(self) -> bool {
+        matches!(self, SliceFamily::I | SliceFamily::SI)
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SliceExclusive {
     /// All slices in the picture have the same type
     Exclusive,
     /// Other slices in the picture may have a different type than the current slice
     NonExclusive,
 }
-#[derive(Debug, PartialEq)]
-struct SliceType {
-    family: SliceFamily,
-    exclusive: SliceExclusive,
+/// `slice_type`: values `5` to `9` are `Exclusive` versions of `0` to `4`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SliceType {
+    /// The coding type, regardless of whether it is shared by the whole picture.
+    pub family: SliceFamily,
+    pub exclusive: SliceExclusive,
 }
 impl SliceType {
-    fn from_id(id: u32) -> Result<SliceType, SliceHeaderError> {
+    /// Interprets a `slice_type` value from `0` to `9`.
+    pub fn from_id(id: u32) -> Result<SliceType, SliceHeaderError> {
         match id {
             0 => Ok(SliceType { family: SliceFamily::P, exclusive: SliceExclusive::NonExclusive }),
             1 => Ok(SliceType { family: SliceFamily::B, exclusive: SliceExclusive::NonExclusive }),
@@ -52,6 +69,36 @@ impl SliceType {
             _ => Err(SliceHeaderError::InvalidSliceType(id))
         }
     }
+
+    /// The `slice_type` value, from `0` to `4`, or `5` to `9` if `Exclusive`.
+    pub fn id(self) -> u32 {
+        let family = match self.family {
+            SliceFamily::P => 0,
+            SliceFamily::B => 1,
+            SliceFamily::I => 2,
+            SliceFamily::SP => 3,
+            SliceFamily::SI => 4,
+        };
+        match self.exclusive {
+            SliceExclusive::NonExclusive => family,
+            SliceExclusive::Exclusive => family + 5,
+        }
+    }
+
+    /// True if every slice of the picture has this slice's type (`slice_type` `5` to `9`).
+    pub fn is_exclusive(self) -> bool {
+        self.exclusive == SliceExclusive::Exclusive
+    }
+
+    /// True for I and SI slices, which don't refer to other pictures.
+    pub fn is_intra(self) -> bool {
+        self.family.is_intra()
+    }
+
+    /// True for P, SP and B slices, which may refer to other pictures.
+    pub fn is_inter(self) -> bool {
+        !self.is_intra()
+    }
 }
 
 #[derive(Debug)]
@@ -63,14 +110,41 @@ pub enum SliceHeaderError {
     UndefinedSeqParamSetId(pps::ParamSetId),
     ColourPlaneError(ColourPlaneError),
     InvalidModificationOfPicNumIdc(u32),
+    /// `abs_diff_pic_num_minus1` was not less than `MaxPicNum`
+    InvalidAbsDiffPicNumMinus1(u32),
+    /// The given reference picture list (`0` or `1`) had more modification operations than its
+    /// `max` active entries
+    TooManyRefPicListModifications { list: u8, max: u32 },
     InvalidMemoryManagementControlOperation(u32),
+    /// A `memory_management_control_operation` which may only appear once per slice header was
+    /// repeated
+    RepeatedMemoryManagementControlOperation(MemoryManagementControlOperation),
     InvalidSliceQpDelta(i32),
     InvalidSliceQsDelta(i32),
+    /// `delta_pic_order_cnt_bottom` was too large to be added to `pic_order_cnt_lsb`
+    InvalidDeltaPicOrderCntBottom(i32),
+    /// The named `num_ref_idx_lX_active_minus1` value was greater than `31`
+    InvalidNumRefIdx(&'static str, u32),
     InvalidDisableDeblockingFilterIdc(u32),
     /// `slice_alpha_c0_offset_div2` was outside the expected range of `-6` to `+6`
     InvalidSliceAlphaC0OffsetDiv2(i32),
+    /// `slice_beta_offset_div2` was outside the expected range of `-6` to `+6`
+    InvalidSliceBetaOffsetDiv2(i32),
+    /// `first_mb_in_slice` was beyond the end of the picture
+    InvalidFirstMbInSlice(u32),
+    /// `slice_group_change_cycle` was greater than the number of slice group change cycles in a
+    /// picture
+    InvalidSliceGroupChangeCycle(u32),
+    /// The named `luma_log2_weight_denom` or `chroma_log2_weight_denom` value was greater than `7`
+    InvalidLog2WeightDenom(&'static str, u32),
+    /// The named weight or offset of the `pred_weight_table` was outside the range `-128` to
+    /// `+127`
+    InvalidPredWeight(&'static str, i32),
     /// The header contained syntax elements that the parser isn't able to handle yet
     UnsupportedSyntax(&'static str),
+    /// The SPS's `log2_max_frame_num_minus4` was greater than `12` (as is possible only for a
+    /// constructed SPS), so `frame_num` couldn't be read
+    Log2MaxFrameNumMinus4OutOfRange(u8),
 }
 impl From<RbspBitReaderError> for SliceHeaderError {
     fn from(e: RbspBitReaderError) -> Self {
@@ -82,15 +156,25 @@ impl From<pps::ParamSetIdError> for SliceHeaderError {
         SliceHeaderError::InvalidSeqParamSetId(e)
     }
 }
+impl From<crate::MissingParameterSet> for SliceHeaderError {
+    fn from(e: crate::MissingParameterSet) -> Self {
+        match e {
+            crate::MissingParameterSet::Pps(id) => SliceHeaderError::UndefinedPicParamSetId(id),
+            crate::MissingParameterSet::Sps { sps, .. } => SliceHeaderError::UndefinedSeqParamSetId(sps),
+        }
+    }
+}
 impl From<ColourPlaneError> for SliceHeaderError {
     fn from(e: ColourPlaneError) -> Self {
         SliceHeaderError::ColourPlaneError(e)
     }
 }
 
-#[derive(Debug)]
-enum ColourPlane {
-    /// Indicates the _chroma_ colour plane
+/// Which colour plane a slice belongs to, when the SPS's `separate_colour_plane_flag` is set and
+/// the three planes of a 4:4:4 picture are each coded as if monochrome.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColourPlane {
+    /// Indicates the _luma_ colour plane
     Y,
     /// Indicates the _blue-difference_ colour plane
     Cb,
@@ -110,73 +194,219 @@ impl ColourPlane {
             _ => Err(ColourPlaneError::InvalidId(id))
         }
     }
+
+    fn id(self) -> u8 {
+        match self {
+            ColourPlane::Y => 0,
+            ColourPlane::Cb => 1,
+            ColourPlane::Cr => 2,
+        }
+    }
 }
 
-#[derive(Debug,PartialEq)]
-enum Field {
-    Top,
-    Bottom,
+/// Whether a picture is a frame or a single field; see [PictureCoding::structure].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PictureStructure {
+    Frame,
+    TopField,
+    BottomField,
 }
 
-#[derive(Debug,PartialEq)]
-enum FieldPic {
+/// How the picture to which a slice belongs is coded: the combination of the SPS's
+/// `frame_mbs_only_flag` and `mb_adaptive_frame_field_flag` with the slice's `field_pic_flag`
+/// and `bottom_field_flag`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PictureCoding {
+    /// A frame in which every macroblock is frame-coded.  Every picture of a stream with
+    /// `frame_mbs_only_flag` set is coded this way.
     Frame,
-    Field(Field),
+    /// A frame using macroblock-adaptive frame/field coding (_MbaffFrameFlag_ is 1), in which
+    /// each vertical pair of macroblocks may be frame- or field-coded.
+    MbaffFrame,
+    /// A field comprising the even-numbered lines of the frame.
+    TopField,
+    /// A field comprising the odd-numbered lines of the frame.
+    BottomField,
+}
+impl PictureCoding {
+    /// Whether the picture is a frame or a single field, regardless of MBAFF coding.
+    pub fn structure(self) -> PictureStructure {
+        match self {
+            PictureCoding::Frame | PictureCoding::MbaffFrame => PictureStructure::Frame,
+            PictureCoding::TopField => PictureStructure::TopField,
+            PictureCoding::BottomField => PictureStructure::BottomField,
+        }
+    }
+    /// `field_pic_flag`: true if the picture is a single field.
+    pub fn field_pic_flag(self) -> bool {
+        matches!(self, PictureCoding::TopField | PictureCoding::BottomField)
+    }
+    /// `bottom_field_flag`: true if the picture is a bottom field.
+    pub fn bottom_field_flag(self) -> bool {
+        self == PictureCoding::BottomField
+    }
+    /// _MbaffFrameFlag_.
+    pub fn mbaff_frame_flag(self) -> bool {
+        self == PictureCoding::MbaffFrame
+    }
+    /// _PicHeightInMbs_: the height of the picture in macroblocks, which for a field is half the
+    /// SPS's _FrameHeightInMbs_.
+    pub fn pic_height_in_mbs(self, sps: &SeqParameterSet) -> u64 {
+        if self.field_pic_flag() {
+            sps.frame_height_in_mbs() / 2
+        } else {
+            sps.frame_height_in_mbs()
+        }
+    }
+    /// _PicSizeInMbs_: the number of macroblocks in the picture.
+    pub fn pic_size_in_mbs(self, sps: &SeqParameterSet) -> u64 {
+        sps.pic_size_in_mbs(self.field_pic_flag())
+    }
 }
 
-#[derive(Debug,PartialEq)]
-enum PicOrderCountLsb {
+/// The picture order count syntax of a slice, which depends on the SPS's `pic_order_cnt_type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PicOrderCountLsb {
+    /// `pic_order_cnt_lsb`, for `pic_order_cnt_type` 0 when there's no separate bottom field
+    /// value.
     Frame(u32),
+    /// `pic_order_cnt_lsb` and, for frames when the PPS's
+    /// `bottom_field_pic_order_in_frame_present_flag` is set, `pic_order_cnt_lsb +
+    /// delta_pic_order_cnt_bottom`.
     FieldsAbsolute { top: u32, bottom_delta: i32 },
+    /// `delta_pic_order_cnt[0]` and `delta_pic_order_cnt[1]`, for `pic_order_cnt_type` 1.  The
+    /// second is `0` when not present.
     FieldsDelta([i32; 2]),
 }
 
-#[derive(Debug)]
-enum NumRefIdxActive {
+/// The slice's override of the PPS's default number of active reference indices
+/// (`num_ref_idx_active_override_flag`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumRefIdxActive {
     P{ num_ref_idx_l0_active_minus1: u32 },
     B{ num_ref_idx_l0_active_minus1: u32, num_ref_idx_l1_active_minus1: u32 }
 }
 impl NumRefIdxActive {
-    fn num_ref_idx_l0_active_minus1(&self) -> u32 {
-        match *self {
-            NumRefIdxActive::P { num_ref_idx_l0_active_minus1 } => num_ref_idx_l0_active_minus1,
-            NumRefIdxActive::B { num_ref_idx_l0_active_minus1, .. } => num_ref_idx_l0_active_minus1,
+    /// The `num_ref_idx_l0_active_minus1` and `num_ref_idx_l1_active_minus1` in effect for a
+    /// slice, given the slice's override (if any).  Without one, the PPS defaults apply to frame
+    /// and field slices alike.
+    fn resolve(active: &Option<NumRefIdxActive>, pps: &pps::PicParameterSet) -> (u32, u32) {
+        match *active {
+            Some(NumRefIdxActive::P { num_ref_idx_l0_active_minus1 }) => (num_ref_idx_l0_active_minus1, 0),
+            Some(NumRefIdxActive::B { num_ref_idx_l0_active_minus1, num_ref_idx_l1_active_minus1 }) => {
+                (num_ref_idx_l0_active_minus1, num_ref_idx_l1_active_minus1)
+            },
+            None => (pps.num_ref_idx_l0_default_active_minus1, pps.num_ref_idx_l1_default_active_minus1),
         }
     }
 }
 
-#[derive(Debug)]
-enum ModificationOfPicNums {
+/// One `modification_of_pic_nums_idc` operation, which moves the given picture to the next
+/// position in a reference picture list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModificationOfPicNums {
+    /// `modification_of_pic_nums_idc` of `0`: the short-term picture with the predicted picture
+    /// number minus `abs_diff_pic_num_minus1 + 1`.
     Subtract(u32),
+    /// `modification_of_pic_nums_idc` of `1`: the short-term picture with the predicted picture
+    /// number plus `abs_diff_pic_num_minus1 + 1`.
     Add(u32),
+    /// `modification_of_pic_nums_idc` of `2`: the long-term picture with the given
+    /// `long_term_pic_num`.
     LongTermRef(u32),
 }
-#[derive(Debug)]
-enum RefPicListModifications {
+
+/// The `ref_pic_list_modification()` syntax, giving the operations applied to each initial
+/// reference picture list.  Lists without modifications have no operations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefPicListModifications {
+    /// I and SI slices, which have no reference picture lists.
     I,
+    /// P and SP slices.
     P {
         ref_pic_list_modification_l0: Vec<ModificationOfPicNums>,
     },
+    /// B slices.
     B {
         ref_pic_list_modification_l0: Vec<ModificationOfPicNums>,
         ref_pic_list_modification_l1: Vec<ModificationOfPicNums>,
     },
 }
 impl RefPicListModifications {
-    fn read(slice_family: &SliceFamily, r: &mut RbspBitReader<'_>) -> Result<RefPicListModifications, SliceHeaderError> {
+    /// The operations applied to reference picture list 0.
+    pub fn l0(&self) -> &[ModificationOfPicNums] {
+        match self {
+            RefPicListModifications::I => &[],
+            RefPicListModifications::P { ref_pic_list_modification_l0 } => ref_pic_list_modification_l0,
+            RefPicListModifications::B { ref_pic_list_modification_l0, .. } => ref_pic_list_modification_l0,
+        }
+    }
+
+    /// The operations applied to reference picture list 1.
+    pub fn l1(&self) -> &[ModificationOfPicNums] {
+        match self {
+            RefPicListModifications::B { ref_pic_list_modification_l1, .. } => ref_pic_list_modification_l1,
+            _ => &[],
+        }
+    }
+
+    /// Reads the syntax, given the slice's `num_ref_idx_lX_active_minus1` values and
+    /// `MaxPicNum`.
+    fn read<R: BitRead + ?Sized>(slice_family: &SliceFamily, r: &mut R, num_ref_idx_active_minus1: (u32, u32), max_pic_num: u32) -> Result<RefPicListModifications, SliceHeaderError> {
         Ok(match slice_family {
             SliceFamily::I | SliceFamily::SI => RefPicListModifications::I,
             SliceFamily::B => RefPicListModifications::B {
-                ref_pic_list_modification_l0: Self::read_list(r)?,
-                ref_pic_list_modification_l1: Self::read_list(r)?,
+                ref_pic_list_modification_l0: Self::read_list(r, 0, num_ref_idx_active_minus1.0, max_pic_num)?,
+                ref_pic_list_modification_l1: Self::read_list(r, 1, num_ref_idx_active_minus1.1, max_pic_num)?,
             },
             SliceFamily::P | SliceFamily::SP => RefPicListModifications::P {
-                ref_pic_list_modification_l0: Self::read_list(r)?,
+                ref_pic_list_modification_l0: Self::read_list(r, 0, num_ref_idx_active_minus1.0, max_pic_num)?,
             }
         })
     }
 
-    fn read_list(r: &mut RbspBitReader<'_>) -> Result<Vec<ModificationOfPicNums>, SliceHeaderError> {
+    fn write<W: BitWrite + ?Sized>(&self, slice_family: &SliceFamily, w: &mut W, num_ref_idx_active_minus1: (u32, u32)) -> Result<(), BitWriterError> {
+        match (slice_family, self) {
+            (SliceFamily::I | SliceFamily::SI, RefPicListModifications::I) => Ok(()),
+            (SliceFamily::P | SliceFamily::SP, RefPicListModifications::P { ref_pic_list_modification_l0 }) => {
+                Self::write_list(w, ref_pic_list_modification_l0, num_ref_idx_active_minus1.0)
+            },
+            (SliceFamily::B, RefPicListModifications::B { ref_pic_list_modification_l0, ref_pic_list_modification_l1 }) => {
+                Self::write_list(w, ref_pic_list_modification_l0, num_ref_idx_active_minus1.0)?;
+                Self::write_list(w, ref_pic_list_modification_l1, num_ref_idx_active_minus1.1)
+            },
+            _ => Err(BitWriterError::Inconsistent { name: "ref_pic_list_modification" }),
+        }
+    }
+
+    fn write_list<W: BitWrite + ?Sized>(w: &mut W, ops: &[ModificationOfPicNums], num_ref_idx_active_minus1: u32) -> Result<(), BitWriterError> {
+        if ops.len() > num_ref_idx_active_minus1 as usize + 1 {
+            return Err(BitWriterError::ValueOutOfRange { name: "modification_of_pic_nums_idc", value: ops.len() as i64 });
+        }
+        w.write_bool_named("ref_pic_list_modification_flag", !ops.is_empty())?;
+        if ops.is_empty() {
+            return Ok(());
+        }
+        for op in ops {
+            match *op {
+                ModificationOfPicNums::Subtract(abs_diff_pic_num_minus1) => {
+                    w.write_ue_named("modification_of_pic_nums_idc", 0)?;
+                    w.write_ue_named("abs_diff_pic_num_minus1", abs_diff_pic_num_minus1)?;
+                },
+                ModificationOfPicNums::Add(abs_diff_pic_num_minus1) => {
+                    w.write_ue_named("modification_of_pic_nums_idc", 1)?;
+                    w.write_ue_named("abs_diff_pic_num_minus1", abs_diff_pic_num_minus1)?;
+                },
+                ModificationOfPicNums::LongTermRef(long_term_pic_num) => {
+                    w.write_ue_named("modification_of_pic_nums_idc", 2)?;
+                    w.write_ue_named("long_term_pic_num", long_term_pic_num)?;
+                },
+            }
+        }
+        w.write_ue_named("modification_of_pic_nums_idc", 3)
+    }
+
+    fn read_list<R: BitRead + ?Sized>(r: &mut R, list: u8, num_ref_idx_active_minus1: u32, max_pic_num: u32) -> Result<Vec<ModificationOfPicNums>, SliceHeaderError> {
         let mut result = vec![];
         // either ref_pic_list_modification_flag_l0 or ref_pic_list_modification_flag_l1 depending
         // on call-site,
@@ -184,90 +414,177 @@ impl RefPicListModifications {
             return Ok(result);
         }
         loop {
-            match r.read_ue_named("modification_of_pic_nums_idc")? {
-                0 => result.push(ModificationOfPicNums::Subtract(r.read_ue_named("abs_diff_pic_num_minus1")?)),
-                1 => result.push(ModificationOfPicNums::Add(r.read_ue_named("abs_diff_pic_num_minus1")?)),
-                2 => result.push(ModificationOfPicNums::LongTermRef(r.read_ue_named("long_term_pic_num")?)),
+            let op = match r.read_ue_named("modification_of_pic_nums_idc")? {
+                idc @ 0..=1 => {
+                    let abs_diff_pic_num_minus1 = r.read_ue_named("abs_diff_pic_num_minus1")?;
+                    if abs_diff_pic_num_minus1 >= max_pic_num {
+                        return Err(SliceHeaderError::InvalidAbsDiffPicNumMinus1(abs_diff_pic_num_minus1));
+                    }
+                    if idc == 0 {
+                        ModificationOfPicNums::Subtract(abs_diff_pic_num_minus1)
+                    } else {
+                        ModificationOfPicNums::Add(abs_diff_pic_num_minus1)
+                    }
+                },
+                2 => ModificationOfPicNums::LongTermRef(r.read_ue_named("long_term_pic_num")?),
                 3 => break,
                 v => return Err(SliceHeaderError::InvalidModificationOfPicNumIdc(v)),
+            };
+            // each operation fills one entry of the list.
+            if result.len() > num_ref_idx_active_minus1 as usize {
+                return Err(SliceHeaderError::TooManyRefPicListModifications { list, max: num_ref_idx_active_minus1 + 1 });
             }
+            result.push(op);
         }
         Ok(result)
     }
 }
 
-#[derive(Debug)]
-struct PredWeight {
-    weight: i32,
-    offset: i32,
+/// An explicit weighting factor and offset for weighted prediction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PredWeight {
+    /// The weight, to be divided by 2 to the power of the relevant `log2_weight_denom`.
+    pub weight: i32,
+    /// The offset, in units of the 8-bit sample range (i.e. to be scaled by `1 << (BitDepth -
+    /// 8)`).
+    pub offset: i32,
 }
-#[derive(Debug)]
-struct PredWeightTable {
-    luma_log2_weight_denom: u32,
-    chroma_log2_weight_denom: Option<u32>,
-    luma_weights: Vec<Option<PredWeight>>,
-    chroma_weights: Vec<Vec<PredWeight>>,
+
+/// The weights for prediction from one entry in a reference picture list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefPicWeights {
+    /// The luma weight, or `None` if the default (a weight of `1 << luma_log2_weight_denom` and
+    /// an offset of `0`) applies.
+    pub luma: Option<PredWeight>,
+    /// The Cb and Cr weights, or `None` if the default applies.  Always `None` for monochrome
+    /// content.
+    pub chroma: Option<[PredWeight; 2]>,
+}
+
+/// The `pred_weight_table()` syntax of P and SP slices when `weighted_pred_flag` is set, and of
+/// B slices when `weighted_bipred_idc` is `1`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PredWeightTable {
+    pub luma_log2_weight_denom: u32,
+    /// Absent for monochrome content (when `ChromaArrayType` is `0`).
+    pub chroma_log2_weight_denom: Option<u32>,
+    /// The weights for each active entry of reference picture list 0.
+    pub l0: Vec<RefPicWeights>,
+    /// The weights for each active entry of reference picture list 1; empty except in B slices.
+    pub l1: Vec<RefPicWeights>,
 }
 impl PredWeightTable {
-    fn read(r: &mut RbspBitReader<'_>, slice_type: &SliceType, pps: &pps::PicParameterSet, sps: &sps::SeqParameterSet, num_ref_active: &Option<NumRefIdxActive>) -> Result<PredWeightTable, SliceHeaderError> {
-        let chroma_array_type = if sps.chroma_info.separate_colour_plane_flag {
-            // TODO: "Otherwise (separate_colour_plane_flag is equal to 1), ChromaArrayType is
-            //       set equal to 0."  ...does this mean ChromaFormat::Monochrome then?
-            sps::ChromaFormat::Monochrome
+    fn read<R: BitRead + ?Sized>(r: &mut R, slice_type: &SliceType, sps: &sps::SeqParameterSet, num_ref_idx_active_minus1: (u32, u32)) -> Result<PredWeightTable, SliceHeaderError> {
+        let chroma = sps.chroma_array_type() != sps::ChromaFormat::Monochrome;
+        let luma_log2_weight_denom = Self::read_denom(r, "luma_log2_weight_denom")?;
+        let chroma_log2_weight_denom = if chroma {
+            Some(Self::read_denom(r, "chroma_log2_weight_denom")?)
         } else {
-            sps.chroma_info.chroma_format
+            None
         };
-        let luma_log2_weight_denom = r.read_ue_named("luma_log2_weight_denom")?;
-        let chroma_log2_weight_denom = if chroma_array_type != sps::ChromaFormat::Monochrome {
-            Some(r.read_ue_named("chroma_log2_weight_denom")?)
+        let l0 = Self::read_list(r, chroma, num_ref_idx_active_minus1.0)?;
+        let l1 = if slice_type.family == SliceFamily::B {
+            Self::read_list(r, chroma, num_ref_idx_active_minus1.1)?
         } else {
-            None
+            Vec::new()
         };
-        let num_ref_idx_l0_active_minus1 = num_ref_active
-            .as_ref()
-            .map(|n| n.num_ref_idx_l0_active_minus1() )
-            .unwrap_or_else(|| {
-                pps.num_ref_idx_l0_default_active_minus1
-            });
-        let mut luma_weights = Vec::with_capacity((num_ref_idx_l0_active_minus1 + 1) as usize);
-        let mut chroma_weights = Vec::with_capacity((num_ref_idx_l0_active_minus1 + 1) as usize);
-        for _ in 0..=num_ref_idx_l0_active_minus1 {
-            if r.read_bool_named("luma_weight_l0_flag")? {
-                luma_weights.push(Some(PredWeight {
-                    weight: r.read_se_named("luma_weight_l0")?,
-                    offset: r.read_se_named("luma_offset_l0")?,
-                }));
-            } else {
-                luma_weights.push(None);
-            }
-            if chroma_array_type != sps::ChromaFormat::Monochrome {
-                let mut weights = Vec::with_capacity(2); // TODO: just an array?
-                if r.read_bool_named("chroma_weight_l0_flag")? {
-                    for _j in 0..2 {
-                        weights.push(PredWeight {
-                            weight: r.read_se_named("chroma_weight_l0")?,
-                            offset: r.read_se_named("chroma_offset_l0")?,
-                        });
+        Ok(PredWeightTable {
+            luma_log2_weight_denom,
+            chroma_log2_weight_denom,
+            l0,
+            l1,
+        })
+    }
 
-                    }
+    fn write<W: BitWrite + ?Sized>(&self, w: &mut W, slice_type: &SliceType, sps: &sps::SeqParameterSet, num_ref_idx_active_minus1: (u32, u32)) -> Result<(), BitWriterError> {
+        let chroma = sps.chroma_array_type() != sps::ChromaFormat::Monochrome;
+        if self.chroma_log2_weight_denom.is_some() != chroma {
+            return Err(BitWriterError::Inconsistent { name: "chroma_log2_weight_denom" });
+        }
+        w.write_ue_named("luma_log2_weight_denom", self.luma_log2_weight_denom)?;
+        if let Some(chroma_log2_weight_denom) = self.chroma_log2_weight_denom {
+            w.write_ue_named("chroma_log2_weight_denom", chroma_log2_weight_denom)?;
+        }
+        Self::write_list(w, chroma, &self.l0, "num_ref_idx_l0_active_minus1", num_ref_idx_active_minus1.0)?;
+        if slice_type.family == SliceFamily::B {
+            Self::write_list(w, chroma, &self.l1, "num_ref_idx_l1_active_minus1", num_ref_idx_active_minus1.1)?;
+        } else if !self.l1.is_empty() {
+            return Err(BitWriterError::Inconsistent { name: "luma_weight_l1_flag" });
+        }
+        Ok(())
+    }
+
+    // as with read_list, the element names of list 0 are given for both lists.
+    fn write_list<W: BitWrite + ?Sized>(w: &mut W, chroma: bool, list: &[RefPicWeights], count_name: &'static str, num_ref_idx_active_minus1: u32) -> Result<(), BitWriterError> {
+        if list.len() != num_ref_idx_active_minus1 as usize + 1 {
+            return Err(BitWriterError::ValueOutOfRange { name: count_name, value: list.len() as i64 - 1 });
+        }
+        for weights in list {
+            w.write_bool_named("luma_weight_l0_flag", weights.luma.is_some())?;
+            if let Some(luma) = weights.luma {
+                w.write_se_named("luma_weight_l0", luma.weight)?;
+                w.write_se_named("luma_offset_l0", luma.offset)?;
+            }
+            if chroma {
+                w.write_bool_named("chroma_weight_l0_flag", weights.chroma.is_some())?;
+                for c in weights.chroma.iter().flatten() {
+                    w.write_se_named("chroma_weight_l0", c.weight)?;
+                    w.write_se_named("chroma_offset_l0", c.offset)?;
                 }
-                chroma_weights.push(weights);
+            } else if weights.chroma.is_some() {
+                return Err(BitWriterError::Inconsistent { name: "chroma_weight_l0_flag" });
             }
         }
-        if slice_type.family == SliceFamily::B {
-            unimplemented!("B Frame")
+        Ok(())
+    }
+
+    fn read_denom<R: BitRead + ?Sized>(r: &mut R, name: &'static str) -> Result<u32, SliceHeaderError> {
+        let denom = r.read_ue_named(name)?;
+        if denom > 7 {
+            return Err(SliceHeaderError::InvalidLog2WeightDenom(name, denom));
         }
-        Ok(PredWeightTable {
-            luma_log2_weight_denom,
-            chroma_log2_weight_denom,
-            luma_weights,
-            chroma_weights,
-        })
+        Ok(denom)
+    }
+
+    // the element names of list 0 are given for both lists, as for ref_pic_list_modification.
+    fn read_list<R: BitRead + ?Sized>(r: &mut R, chroma: bool, num_ref_idx_active_minus1: u32) -> Result<Vec<RefPicWeights>, SliceHeaderError> {
+        let mut list = Vec::with_capacity(num_ref_idx_active_minus1 as usize + 1);
+        for _ in 0..=num_ref_idx_active_minus1 {
+            let luma = if r.read_bool_named("luma_weight_l0_flag")? {
+                Some(Self::read_weight(r, "luma_weight_l0", "luma_offset_l0")?)
+            } else {
+                None
+            };
+            let chroma = if chroma && r.read_bool_named("chroma_weight_l0_flag")? {
+                Some([
+                    Self::read_weight(r, "chroma_weight_l0", "chroma_offset_l0")?,
+                    Self::read_weight(r, "chroma_weight_l0", "chroma_offset_l0")?,
+                ])
+            } else {
+                None
+            };
+            list.push(RefPicWeights { luma, chroma });
+        }
+        Ok(list)
+    }
+
+    fn read_weight<R: BitRead + ?Sized>(r: &mut R, weight_name: &'static str, offset_name: &'static str) -> Result<PredWeight, SliceHeaderError> {
+        let weight = r.read_se_named(weight_name)?;
+        if !(-128..=127).contains(&weight) {
+            return Err(SliceHeaderError::InvalidPredWeight(weight_name, weight));
+        }
+        let offset = r.read_se_named(offset_name)?;
+        if !(-128..=127).contains(&offset) {
+            return Err(SliceHeaderError::InvalidPredWeight(offset_name, offset));
+        }
+        Ok(PredWeight { weight, offset })
     }
 }
 
-#[derive(Debug)]
-enum MemoryManagementControlOperation {
+/// An operation on the reference pictures of the decoded picture buffer, applied after the current
+/// picture is decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryManagementControlOperation {
     /// `memory_management_control_operation` value of `1`
     ShortTermUnusedForRef { difference_of_pic_nums_minus1: u32 },
     /// `memory_management_control_operation` value of `2`
@@ -282,11 +599,14 @@ enum MemoryManagementControlOperation {
     CurrentUsedForLongTerm { long_term_frame_idx: u32 },
 }
 
-/// Decoded reference picture marking
-#[derive(Debug)]
-enum DecRefPicMarking {
+/// Decoded reference picture marking, present in the headers of slices of reference pictures.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecRefPicMarking {
     Idr {
+        /// Whether pictures decoded before the IDR picture but not yet output should be discarded.
         no_output_of_prior_pics_flag: bool,
+        /// Whether the IDR picture is marked as a long-term reference with `LongTermFrameIdx` 0,
+        /// rather than a short-term reference.
         long_term_reference_flag: bool,
     },
     /// `adaptive_ref_pic_marking_mode_flag` equal to `0`
@@ -295,7 +615,61 @@ enum DecRefPicMarking {
     Adaptive(Vec<MemoryManagementControlOperation>),
 }
 impl DecRefPicMarking {
-    fn read(r: &mut RbspBitReader<'_>, header: NalHeader) -> Result<DecRefPicMarking, SliceHeaderError> {
+    /// Whether the picture has a `memory_management_control_operation` of `5`, marking all
+    /// reference pictures unused and resetting `frame_num` and the picture order count as
+    /// though for an IDR picture.
+    pub fn has_mmco5(&self) -> bool {
+        match self {
+            DecRefPicMarking::Adaptive(ops) => ops.contains(&MemoryManagementControlOperation::AllRefPicturesUnused),
+            _ => false,
+        }
+    }
+
+    fn write<W: BitWrite + ?Sized>(&self, w: &mut W, header: NalHeader) -> Result<(), BitWriterError> {
+        let idr = header.nal_unit_type() == crate::nal::UnitType::SliceLayerWithoutPartitioningIdr;
+        match self {
+            DecRefPicMarking::Idr { no_output_of_prior_pics_flag, long_term_reference_flag } if idr => {
+                w.write_bool_named("no_output_of_prior_pics_flag", *no_output_of_prior_pics_flag)?;
+                w.write_bool_named("long_term_reference_flag", *long_term_reference_flag)
+            },
+            DecRefPicMarking::SlidingWindow if !idr => w.write_bool_named("adaptive_ref_pic_marking_mode_flag", false),
+            DecRefPicMarking::Adaptive(ops) if !idr => {
+                w.write_bool_named("adaptive_ref_pic_marking_mode_flag", true)?;
+                for op in ops {
+                    match *op {
+                        MemoryManagementControlOperation::ShortTermUnusedForRef { difference_of_pic_nums_minus1 } => {
+                            w.write_ue_named("memory_management_control_operation", 1)?;
+                            w.write_ue_named("difference_of_pic_nums_minus1", difference_of_pic_nums_minus1)?;
+                        },
+                        MemoryManagementControlOperation::LongTermUnusedForRef { long_term_pic_num } => {
+                            w.write_ue_named("memory_management_control_operation", 2)?;
+                            w.write_ue_named("long_term_pic_num", long_term_pic_num)?;
+                        },
+                        MemoryManagementControlOperation::ShortTermUsedForLongTerm { difference_of_pic_nums_minus1, long_term_frame_idx } => {
+                            w.write_ue_named("memory_management_control_operation", 3)?;
+                            w.write_ue_named("difference_of_pic_nums_minus1", difference_of_pic_nums_minus1)?;
+                            w.write_ue_named("long_term_frame_idx", long_term_frame_idx)?;
+                        },
+                        MemoryManagementControlOperation::MaxUsedLongTermFrameRef { max_long_term_frame_idx_plus1 } => {
+                            w.write_ue_named("memory_management_control_operation", 4)?;
+                            w.write_ue_named("max_long_term_frame_idx_plus1", max_long_term_frame_idx_plus1)?;
+                        },
+                        MemoryManagementControlOperation::AllRefPicturesUnused => {
+                            w.write_ue_named("memory_management_control_operation", 5)?;
+                        },
+                        MemoryManagementControlOperation::CurrentUsedForLongTerm { long_term_frame_idx } => {
+                            w.write_ue_named("memory_management_control_operation", 6)?;
+                            w.write_ue_named("long_term_frame_idx", long_term_frame_idx)?;
+                        },
+                    }
+                }
+                w.write_ue_named("memory_management_control_operation", 0)
+            },
+            _ => Err(BitWriterError::Inconsistent { name: "dec_ref_pic_marking" }),
+        }
+    }
+
+    fn read<R: BitRead + ?Sized>(r: &mut R, header: NalHeader) -> Result<DecRefPicMarking, SliceHeaderError> {
         Ok(if header.nal_unit_type() == crate::nal::UnitType::SliceLayerWithoutPartitioningIdr {
             DecRefPicMarking::Idr {
                 no_output_of_prior_pics_flag: r.read_bool_named("no_output_of_prior_pics_flag")?,
@@ -332,6 +706,12 @@ impl DecRefPicMarking {
                     },
                     other => return Err(SliceHeaderError::InvalidMemoryManagementControlOperation(other))
                 };
+                // no more than one of each of operations 4 and 5 may be present.
+                let unique = matches!(op, MemoryManagementControlOperation::MaxUsedLongTermFrameRef { .. }
+                    | MemoryManagementControlOperation::AllRefPicturesUnused);
+                if unique && ctl.iter().any(|o| std::mem::discriminant(o) == std::mem::discriminant(&op)) {
+                    return Err(SliceHeaderError::RepeatedMemoryManagementControlOperation(op));
+                }
                 ctl.push(op);
             }
             DecRefPicMarking::Adaptive(ctl)
@@ -341,55 +721,106 @@ impl DecRefPicMarking {
     }
 }
 
-#[derive(Debug)]
+/// The `slice_header()` syntax.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SliceHeader {
-    first_mb_in_slice: u32,
-    slice_type: SliceType,
-    colour_plane: Option<ColourPlane>,
-    frame_num: u16,
-    field_pic: FieldPic,
-    idr_pic_id: Option<u32>,
-    pic_order_cnt_lsb: Option<PicOrderCountLsb>,
-    redundant_pic_cnt: Option<u32>,
-    direct_spatial_mv_pred_flag: Option<bool>,
-    num_ref_idx_active: Option<NumRefIdxActive>,
-    ref_pic_list_modification: Option<RefPicListModifications>,  // may become an enum rather than Option in future (for ref_pic_list_mvc_modification)
-    pred_weight_table: Option<PredWeightTable>,
-    dec_ref_pic_marking: Option<DecRefPicMarking>,
-    cabac_init_idc: Option<u32>,
-    slice_qp_delta: i32,
-    sp_for_switch_flag: Option<bool>,
-    slice_qs: Option<u32>,
-    disable_deblocking_filter_idc: u8,
+    /// The address of the first macroblock (or, for MBAFF frames, macroblock pair) in the slice.
+    pub first_mb_in_slice: u32,
+    pub slice_type: SliceType,
+    pub pic_parameter_set_id: ParamSetId,
+    /// The colour plane this slice belongs to (`colour_plane_id`), present only when the SPS's
+    /// `separate_colour_plane_flag` is set.
+    pub colour_plane: Option<ColourPlane>,
+    pub frame_num: u16,
+    pub picture_coding: PictureCoding,
+    /// Present for IDR pictures.
+    pub idr_pic_id: Option<u32>,
+    /// Absent for `pic_order_cnt_type` 2, and for type 1 when `delta_pic_order_always_zero_flag`
+    /// is set.
+    pub pic_order_cnt_lsb: Option<PicOrderCountLsb>,
+    /// Present when the PPS's `redundant_pic_cnt_present_flag` is set.
+    pub redundant_pic_cnt: Option<u32>,
+    /// Present for B slices.
+    pub direct_spatial_mv_pred_flag: Option<bool>,
+    /// The slice's override of the PPS's default number of active reference indices, if any;
+    /// see [SliceHeader::num_ref_idx_active_minus1].
+    pub num_ref_idx_active: Option<NumRefIdxActive>,
+    /// The reference picture list modifications.  Currently always present, as slices of NAL
+    /// unit types 20 and 21 (with `ref_pic_list_mvc_modification()`) aren't yet supported.
+    pub ref_pic_list_modification: Option<RefPicListModifications>,  // may become an enum rather than Option in future (for ref_pic_list_mvc_modification)
+    /// The explicit weighted prediction table, present in P and SP slices when the PPS's
+    /// `weighted_pred_flag` is set, and in B slices when its `weighted_bipred_idc` is `1`.
+    pub pred_weight_table: Option<PredWeightTable>,
+    /// The decoded reference picture marking, present when the NAL header's `nal_ref_idc` is
+    /// non-zero.
+    pub dec_ref_pic_marking: Option<DecRefPicMarking>,
+    /// Present for non-I slices when the PPS's `entropy_coding_mode_flag` is set.
+    pub cabac_init_idc: Option<u32>,
+    pub slice_qp_delta: i32,
+    /// Present for SP slices.
+    pub sp_for_switch_flag: Option<bool>,
+    /// _QSY_ (`26 + pic_init_qs_minus26 + slice_qs_delta`), present for SP and SI slices.
+    pub slice_qs: Option<u32>,
+    /// `0` (the default) to enable the deblocking filter, `1` to disable it, or `2` to disable
+    /// it across slice boundaries.  Values up to `6` are used by the SVC extension.
+    pub disable_deblocking_filter_idc: u8,
+    /// `0` unless the PPS's `deblocking_filter_control_present_flag` is set.
+    pub slice_alpha_c0_offset_div2: i32,
+    /// `0` unless the PPS's `deblocking_filter_control_present_flag` is set.
+    pub slice_beta_offset_div2: i32,
+    /// Present when the PPS has a `slice_group_map_type` of 3 to 5.
+    pub slice_group_change_cycle: Option<u32>,
 }
 impl SliceHeader {
+    /// Parses a slice header from the given RBSP reader, activating the SPS it refers to (see
+    /// [Context::activate_sps]) and returning it and the PPS along with the header.  See
+    /// [SliceHeader::from_bits] to parse without activation.
     pub fn read<'a, Ctx>(ctx: &'a mut Context<Ctx>, r: &mut RbspBitReader<'_>, header: NalHeader) -> Result<(SliceHeader, &'a SeqParameterSet, &'a PicParameterSet), SliceHeaderError> {
+        let (slice_header, _) = Self::from_bits(r, ctx, header)?;
+        let seq_parameter_set_id = ctx.activation(slice_header.pic_parameter_set_id)?.0.seq_parameter_set_id;
+        ctx.activate_sps(seq_parameter_set_id);
+        let (pps, sps) = ctx.activation(slice_header.pic_parameter_set_id)?;
+        Ok((slice_header, sps, pps))
+    }
+
+    /// Parses the `slice_header()` syntax of a slice NAL unit with the given header, using the
+    /// PPS and SPS it refers to from `ctx`.
+    ///
+    /// Returns the header and the reader's [BitRead::bit_position] following it, where
+    /// `slice_data()` begins.
+    pub fn from_bits<Ctx, R: BitRead + ?Sized>(r: &mut R, ctx: &Context<Ctx>, header: NalHeader) -> Result<(SliceHeader, u64), SliceHeaderError> {
         let first_mb_in_slice = r.read_ue_named("first_mb_in_slice")?;
         let slice_type = SliceType::from_id(r.read_ue_named("slice_type")?)?;
         let pic_parameter_set_id = ParamSetId::from_u32(r.read_ue_named("pic_parameter_set_id")?)?;
-        let pps = ctx.pps_by_id(pic_parameter_set_id)
-            .ok_or(SliceHeaderError::UndefinedPicParamSetId(pic_parameter_set_id))?;
-        let sps = ctx.sps_by_id(pps.seq_parameter_set_id)
-            .ok_or(SliceHeaderError::UndefinedSeqParamSetId(pps.seq_parameter_set_id))?;
+        let (pps, sps) = ctx.activation(pic_parameter_set_id)?;
         let colour_plane = if sps.chroma_info.separate_colour_plane_flag {
             Some(ColourPlane::from_id(r.read_u8(2)?)?)
         } else {
             None
         };
+        let max_frame_num = sps.max_frame_num()
+            .ok_or(SliceHeaderError::Log2MaxFrameNumMinus4OutOfRange(sps.log2_max_frame_num_minus4))?;
         let frame_num = r.read_u16(sps.log2_max_frame_num())?;
-        let field_pic = if let sps::FrameMbsFlags::Fields { .. } = sps.frame_mbs_flags {
+        let picture_coding = if let sps::FrameMbsFlags::Fields { mb_adaptive_frame_field_flag } = sps.frame_mbs_flags {
             if r.read_bool_named("field_pic_flag")? {
                 if r.read_bool_named("bottom_field_flag")? {
-                    FieldPic::Field(Field::Bottom)
+                    PictureCoding::BottomField
                 } else {
-                    FieldPic::Field(Field::Top)
+                    PictureCoding::TopField
                 }
+            } else if mb_adaptive_frame_field_flag {
+                PictureCoding::MbaffFrame
             } else {
-                FieldPic::Frame
+                PictureCoding::Frame
             }
         } else {
-            FieldPic::Frame
+            PictureCoding::Frame
         };
+        let pic_size_in_mbs = picture_coding.pic_size_in_mbs(sps);
+        let mbs_per_address = if picture_coding.mbaff_frame_flag() { 2 } else { 1 };
+        if u64::from(first_mb_in_slice) * mbs_per_address >= pic_size_in_mbs {
+            return Err(SliceHeaderError::InvalidFirstMbInSlice(first_mb_in_slice));
+        }
         let idr_pic_id = if header.nal_unit_type() == crate::nal::UnitType::SliceLayerWithoutPartitioningIdr {
             Some(r.read_ue_named("idr_pic_id")?)
         } else {
@@ -398,11 +829,14 @@ impl SliceHeader {
         let pic_order_cnt_lsb = match sps.pic_order_cnt {
             sps::PicOrderCntType::TypeZero { log2_max_pic_order_cnt_lsb_minus4 } => {
                 let pic_order_cnt_lsb = r.read_u32(log2_max_pic_order_cnt_lsb_minus4 + 4)?;
-                Some(if pps.bottom_field_pic_order_in_frame_present_flag && field_pic == FieldPic::Frame {
+                Some(if pps.bottom_field_pic_order_in_frame_present_flag && !picture_coding.field_pic_flag() {
                     let delta_pic_order_cnt_bottom = r.read_se_named("delta_pic_order_cnt_bottom")?;
+                    // pic_order_cnt_lsb has at most 16 bits, so always fits in an i32
+                    let bottom_delta = (pic_order_cnt_lsb as i32).checked_add(delta_pic_order_cnt_bottom)
+                        .ok_or(SliceHeaderError::InvalidDeltaPicOrderCntBottom(delta_pic_order_cnt_bottom))?;
                     PicOrderCountLsb::FieldsAbsolute {
                         top: pic_order_cnt_lsb,
-                        bottom_delta: pic_order_cnt_lsb as i32 + delta_pic_order_cnt_bottom,
+                        bottom_delta,
                     }
                 } else {
                     PicOrderCountLsb::Frame(pic_order_cnt_lsb)
@@ -412,17 +846,20 @@ impl SliceHeader {
                 if delta_pic_order_always_zero_flag {
                     None
                 } else {
-                    Some(PicOrderCountLsb::FieldsDelta([
-                        // TODO: can't remember what field names these are in the spec, to give for debugging
-                        r.read_se_named("FieldsDelta[0]")?,
-                        r.read_se_named("FieldsDelta[1]")?
-                    ]))
+                    let delta_pic_order_cnt_0 = r.read_se_named("delta_pic_order_cnt[0]")?;
+                    // the bottom field's delta is only present for frames.
+                    let delta_pic_order_cnt_1 = if pps.bottom_field_pic_order_in_frame_present_flag && !picture_coding.field_pic_flag() {
+                        r.read_se_named("delta_pic_order_cnt[1]")?
+                    } else {
+                        0
+                    };
+                    Some(PicOrderCountLsb::FieldsDelta([delta_pic_order_cnt_0, delta_pic_order_cnt_1]))
                 }
             },
             sps::PicOrderCntType::TypeTwo => None,
         };
         let redundant_pic_cnt = if pps.redundant_pic_cnt_present_flag {
-            Some(r.read_ue_named("redundant_pic_cnt ")?)
+            Some(r.read_ue_named("redundant_pic_cnt")?)
         } else {
             None
         };
@@ -431,13 +868,13 @@ impl SliceHeader {
         } else {
             None
         };
-        let num_ref_idx_active = if slice_type.family == SliceFamily::P || slice_type.family == SliceFamily::SP || slice_type.family == SliceFamily::B {
+        let num_ref_idx_active = if slice_type.is_inter() {
             if r.read_bool_named("num_ref_idx_active_override_flag")? {
-                let num_ref_idx_l0_active_minus1 = r.read_ue_named("num_ref_idx_l0_active_minus1")?;
+                let num_ref_idx_l0_active_minus1 = read_num_ref_idx(r, "num_ref_idx_l0_active_minus1")?;
                 Some(if slice_type.family == SliceFamily::B {
                     NumRefIdxActive::B {
                         num_ref_idx_l0_active_minus1,
-                        num_ref_idx_l1_active_minus1: r.read_ue_named("num_ref_idx_l1_active_minus1")?,
+                        num_ref_idx_l1_active_minus1: read_num_ref_idx(r, "num_ref_idx_l1_active_minus1")?,
                     }
                 } else {
                     NumRefIdxActive::P { num_ref_idx_l0_active_minus1 }
@@ -448,14 +885,20 @@ impl SliceHeader {
         } else {
             None
         };
+        let num_ref_idx_active_minus1 = NumRefIdxActive::resolve(&num_ref_idx_active, pps);
         let ref_pic_list_modification = if header.nal_unit_type() == crate::nal::UnitType::SliceExtension || header.nal_unit_type() == crate::nal::UnitType::SliceExtensionViewComponent {
             return Err(SliceHeaderError::UnsupportedSyntax("NALU types 20 and 21 not yet supported"));
         } else {
-            RefPicListModifications::read(&slice_type.family, r)?
+            let max_pic_num = if picture_coding.field_pic_flag() {
+                max_frame_num * 2
+            } else {
+                max_frame_num
+            };
+            RefPicListModifications::read(&slice_type.family, r, num_ref_idx_active_minus1, max_pic_num)?
         };
-        let pred_weight_table = if (pps.weighted_pred_flag && slice_type.family == SliceFamily::P || slice_type.family == SliceFamily::SP)
+        let pred_weight_table = if (pps.weighted_pred_flag && (slice_type.family == SliceFamily::P || slice_type.family == SliceFamily::SP))
             || (pps.weighted_bipred_idc == 1 && slice_type.family == SliceFamily::B) {
-            Some(PredWeightTable::read(r, &slice_type, pps, sps, &num_ref_idx_active)?)
+            Some(PredWeightTable::read(r, &slice_type, sps, num_ref_idx_active_minus1)?)
         } else {
             None
         };
@@ -464,13 +907,16 @@ impl SliceHeader {
         } else {
             Some(DecRefPicMarking::read(r, header)?)
         };
-        let cabac_init_idc = if pps.entropy_coding_mode_flag && slice_type.family != SliceFamily::I && slice_type.family != SliceFamily::SI {
+        let cabac_init_idc = if pps.entropy_coding_mode_flag && slice_type.is_inter() {
             Some(r.read_ue_named("cabac_init_idc")?)
         } else {
             None
         };
         let slice_qp_delta = r.read_se_named("slice_qp_delta")?;
-        if slice_qp_delta > 51 {  // TODO: or less than -qp_bd_offset
+        // SliceQPY must be in the range -QpBdOffsetY to +51.
+        let qp_bd_offset = 6 * i64::from(sps.chroma_info.bit_depth_luma_minus8);
+        let slice_qp = slice_qp_y(pps, slice_qp_delta);
+        if slice_qp < -qp_bd_offset || slice_qp > 51 {
             return Err(SliceHeaderError::InvalidSliceQpDelta(slice_qp_delta))
         }
         let mut sp_for_switch_flag = None;
@@ -479,15 +925,17 @@ impl SliceHeader {
                 sp_for_switch_flag = Some(r.read_bool_named("sp_for_switch_flag")?);
             }
             let slice_qs_delta = r.read_se_named("slice_qs_delta")?;
-            let qs_y = 26 + pps.pic_init_qs_minus26 + slice_qs_delta;
-            if qs_y < 0 || 51 < qs_y {
-                return Err(SliceHeaderError::InvalidSliceQsDelta(slice_qs_delta))
+            let qs_y = pps.pic_init_qs_minus26.checked_add(26).and_then(|v| v.checked_add(slice_qs_delta));
+            match qs_y {
+                Some(qs_y) if (0..=51).contains(&qs_y) => Some(qs_y as u32),
+                _ => return Err(SliceHeaderError::InvalidSliceQsDelta(slice_qs_delta)),
             }
-            Some(qs_y as u32)
         } else {
             None
         };
         let mut disable_deblocking_filter_idc = 0;
+        let mut slice_alpha_c0_offset_div2 = 0;
+        let mut slice_beta_offset_div2 = 0;
         if pps.deblocking_filter_control_present_flag {
             disable_deblocking_filter_idc = {
                 let v = r.read_ue_named("disable_deblocking_filter_idc")?;
@@ -497,19 +945,34 @@ impl SliceHeader {
                 v as u8
             };
             if disable_deblocking_filter_idc != 1 {
-                let slice_alpha_c0_offset_div2 = r.read_se_named("slice_alpha_c0_offset_div2")?;
+                slice_alpha_c0_offset_div2 = r.read_se_named("slice_alpha_c0_offset_div2")?;
                 if slice_alpha_c0_offset_div2 < -6 || 6 < slice_alpha_c0_offset_div2 {
                     return Err(SliceHeaderError::InvalidSliceAlphaC0OffsetDiv2(slice_alpha_c0_offset_div2));
                 }
-                let _slice_beta_offset_div2 = r.read_se_named("slice_beta_offset_div2")?;
+                slice_beta_offset_div2 = r.read_se_named("slice_beta_offset_div2")?;
+                if !(-6..=6).contains(&slice_beta_offset_div2) {
+                    return Err(SliceHeaderError::InvalidSliceBetaOffsetDiv2(slice_beta_offset_div2));
+                }
             }
         }
+        let slice_group_change_cycle = match pps.slice_groups {
+            Some(pps::SliceGroup::Changing { slice_group_change_rate_minus1, .. }) => {
+                let (bits, max) = slice_group_change_cycle_range(sps, slice_group_change_rate_minus1);
+                let slice_group_change_cycle = r.read_u32(bits)?;
+                if u64::from(slice_group_change_cycle) > max {
+                    return Err(SliceHeaderError::InvalidSliceGroupChangeCycle(slice_group_change_cycle));
+                }
+                Some(slice_group_change_cycle)
+            },
+            _ => None,
+        };
         let header = SliceHeader {
             first_mb_in_slice,
             slice_type,
+            pic_parameter_set_id,
             colour_plane,
             frame_num,
-            field_pic,
+            picture_coding,
             idr_pic_id,
             pic_order_cnt_lsb,
             redundant_pic_cnt,
@@ -523,11 +986,273 @@ impl SliceHeader {
             sp_for_switch_flag,
             slice_qs,
             disable_deblocking_filter_idc,
+            slice_alpha_c0_offset_div2,
+            slice_beta_offset_div2,
+            slice_group_change_cycle,
         };
-        Ok((header, sps, pps))
+        Ok((header, r.bit_position()))
+    }
+
+    /// Writes the `slice_header()` syntax of a slice NAL unit with the given header, given the
+    /// PPS the slice refers to and the SPS that refers to; the inverse of
+    /// [SliceHeader::from_bits].
+    ///
+    /// The `slice_data()` should follow.  Note that for CABAC slices it begins with alignment
+    /// bits, whose number depends on the length of the header.  Fails if the header's optional
+    /// fields aren't present exactly when the parameter sets and NAL header require them.
+    pub fn write<W: BitWrite + ?Sized>(
+        &self,
+        sps: &SeqParameterSet,
+        pps: &PicParameterSet,
+        header: NalHeader,
+        w: &mut W,
+    ) -> Result<(), BitWriterError> {
+        w.write_ue_named("first_mb_in_slice", self.first_mb_in_slice)?;
+        w.write_ue_named("slice_type", self.slice_type.id())?;
+        w.write_ue_named("pic_parameter_set_id", u32::from(self.pic_parameter_set_id.id()))?;
+        check_presence("colour_plane_id", &self.colour_plane, sps.chroma_info.separate_colour_plane_flag)?;
+        if let Some(colour_plane) = self.colour_plane {
+            w.write_u_named("colour_plane_id", 2, u32::from(colour_plane.id()))?;
+        }
+        w.write_u_named("frame_num", u32::from(sps.log2_max_frame_num()), u32::from(self.frame_num))?;
+        match sps.frame_mbs_flags {
+            sps::FrameMbsFlags::Fields { mb_adaptive_frame_field_flag } => {
+                if !self.picture_coding.field_pic_flag() && self.picture_coding.mbaff_frame_flag() != mb_adaptive_frame_field_flag {
+                    return Err(BitWriterError::Inconsistent { name: "field_pic_flag" });
+                }
+                w.write_bool_named("field_pic_flag", self.picture_coding.field_pic_flag())?;
+                if self.picture_coding.field_pic_flag() {
+                    w.write_bool_named("bottom_field_flag", self.picture_coding.bottom_field_flag())?;
+                }
+            },
+            sps::FrameMbsFlags::Frames => if self.picture_coding != PictureCoding::Frame {
+                return Err(BitWriterError::Inconsistent { name: "field_pic_flag" });
+            },
+        }
+        let idr = header.nal_unit_type() == crate::nal::UnitType::SliceLayerWithoutPartitioningIdr;
+        check_presence("idr_pic_id", &self.idr_pic_id, idr)?;
+        if let Some(idr_pic_id) = self.idr_pic_id {
+            w.write_ue_named("idr_pic_id", idr_pic_id)?;
+        }
+        let bottom_present = pps.bottom_field_pic_order_in_frame_present_flag && !self.picture_coding.field_pic_flag();
+        match (&sps.pic_order_cnt, self.pic_order_cnt_lsb) {
+            (sps::PicOrderCntType::TypeZero { log2_max_pic_order_cnt_lsb_minus4 }, Some(PicOrderCountLsb::Frame(lsb))) if !bottom_present => {
+                w.write_u_named("pic_order_cnt_lsb", u32::from(log2_max_pic_order_cnt_lsb_minus4 + 4), lsb)?;
+            },
+            (sps::PicOrderCntType::TypeZero { log2_max_pic_order_cnt_lsb_minus4 }, Some(PicOrderCountLsb::FieldsAbsolute { top, bottom_delta })) if bottom_present => {
+                w.write_u_named("pic_order_cnt_lsb", u32::from(log2_max_pic_order_cnt_lsb_minus4 + 4), top)?;
+                let delta_pic_order_cnt_bottom = i64::from(bottom_delta) - i64::from(top);
+                let delta_pic_order_cnt_bottom = i32::try_from(delta_pic_order_cnt_bottom)
+                    .map_err(|_| BitWriterError::ValueOutOfRange { name: "delta_pic_order_cnt_bottom", value: delta_pic_order_cnt_bottom })?;
+                w.write_se_named("delta_pic_order_cnt_bottom", delta_pic_order_cnt_bottom)?;
+            },
+            (sps::PicOrderCntType::TypeOne { delta_pic_order_always_zero_flag: true, .. }, None) => {},
+            (sps::PicOrderCntType::TypeOne { delta_pic_order_always_zero_flag: false, .. }, Some(PicOrderCountLsb::FieldsDelta([delta0, delta1]))) => {
+                w.write_se_named("delta_pic_order_cnt[0]", delta0)?;
+                if bottom_present {
+                    w.write_se_named("delta_pic_order_cnt[1]", delta1)?;
+                } else if delta1 != 0 {
+                    return Err(BitWriterError::Inconsistent { name: "delta_pic_order_cnt[1]" });
+                }
+            },
+            (sps::PicOrderCntType::TypeTwo, None) => {},
+            _ => return Err(BitWriterError::Inconsistent { name: "pic_order_cnt_lsb" }),
+        }
+        check_presence("redundant_pic_cnt", &self.redundant_pic_cnt, pps.redundant_pic_cnt_present_flag)?;
+        if let Some(redundant_pic_cnt) = self.redundant_pic_cnt {
+            w.write_ue_named("redundant_pic_cnt", redundant_pic_cnt)?;
+        }
+        let family = self.slice_type.family;
+        check_presence("direct_spatial_mv_pred_flag", &self.direct_spatial_mv_pred_flag, family == SliceFamily::B)?;
+        if let Some(direct_spatial_mv_pred_flag) = self.direct_spatial_mv_pred_flag {
+            w.write_bool_named("direct_spatial_mv_pred_flag", direct_spatial_mv_pred_flag)?;
+        }
+        if self.slice_type.is_inter() {
+            w.write_bool_named("num_ref_idx_active_override_flag", self.num_ref_idx_active.is_some())?;
+        }
+        match (family, self.num_ref_idx_active) {
+            (_, None) => {},
+            (SliceFamily::P | SliceFamily::SP, Some(NumRefIdxActive::P { num_ref_idx_l0_active_minus1 })) => {
+                write_num_ref_idx(w, "num_ref_idx_l0_active_minus1", num_ref_idx_l0_active_minus1)?;
+            },
+            (SliceFamily::B, Some(NumRefIdxActive::B { num_ref_idx_l0_active_minus1, num_ref_idx_l1_active_minus1 })) => {
+                write_num_ref_idx(w, "num_ref_idx_l0_active_minus1", num_ref_idx_l0_active_minus1)?;
+                write_num_ref_idx(w, "num_ref_idx_l1_active_minus1", num_ref_idx_l1_active_minus1)?;
+            },
+            _ => return Err(BitWriterError::Inconsistent { name: "num_ref_idx_active_override_flag" }),
+        }
+        let num_ref_idx_active_minus1 = NumRefIdxActive::resolve(&self.num_ref_idx_active, pps);
+        self.ref_pic_list_modification
+            .as_ref()
+            .ok_or(BitWriterError::Inconsistent { name: "ref_pic_list_modification" })?
+            .write(&family, w, num_ref_idx_active_minus1)?;
+        let weighted = (pps.weighted_pred_flag && (family == SliceFamily::P || family == SliceFamily::SP))
+            || (pps.weighted_bipred_idc == 1 && family == SliceFamily::B);
+        check_presence("pred_weight_table", &self.pred_weight_table, weighted)?;
+        if let Some(ref pred_weight_table) = self.pred_weight_table {
+            pred_weight_table.write(w, &self.slice_type, sps, num_ref_idx_active_minus1)?;
+        }
+        check_presence("dec_ref_pic_marking", &self.dec_ref_pic_marking, header.nal_ref_idc() != 0)?;
+        if let Some(ref dec_ref_pic_marking) = self.dec_ref_pic_marking {
+            dec_ref_pic_marking.write(w, header)?;
+        }
+        check_presence("cabac_init_idc", &self.cabac_init_idc, pps.entropy_coding_mode_flag && self.slice_type.is_inter())?;
+        if let Some(cabac_init_idc) = self.cabac_init_idc {
+            w.write_ue_named("cabac_init_idc", cabac_init_idc)?;
+        }
+        w.write_se_named("slice_qp_delta", self.slice_qp_delta)?;
+        check_presence("sp_for_switch_flag", &self.sp_for_switch_flag, family == SliceFamily::SP)?;
+        if let Some(sp_for_switch_flag) = self.sp_for_switch_flag {
+            w.write_bool_named("sp_for_switch_flag", sp_for_switch_flag)?;
+        }
+        check_presence("slice_qs_delta", &self.slice_qs, family == SliceFamily::SP || family == SliceFamily::SI)?;
+        if let Some(slice_qs) = self.slice_qs {
+            let slice_qs_delta = i64::from(slice_qs) - 26 - i64::from(pps.pic_init_qs_minus26);
+            let slice_qs_delta = i32::try_from(slice_qs_delta)
+                .map_err(|_| BitWriterError::ValueOutOfRange { name: "slice_qs_delta", value: slice_qs_delta })?;
+            w.write_se_named("slice_qs_delta", slice_qs_delta)?;
+        }
+        if pps.deblocking_filter_control_present_flag {
+            w.write_ue_named("disable_deblocking_filter_idc", u32::from(self.disable_deblocking_filter_idc))?;
+            if self.disable_deblocking_filter_idc != 1 {
+                w.write_se_named("slice_alpha_c0_offset_div2", self.slice_alpha_c0_offset_div2)?;
+                w.write_se_named("slice_beta_offset_div2", self.slice_beta_offset_div2)?;
+            }
+        } else if self.disable_deblocking_filter_idc != 0 || self.slice_alpha_c0_offset_div2 != 0 || self.slice_beta_offset_div2 != 0 {
+            return Err(BitWriterError::Inconsistent { name: "disable_deblocking_filter_idc" });
+        }
+        match pps.slice_groups {
+            Some(pps::SliceGroup::Changing { slice_group_change_rate_minus1, .. }) => {
+                let slice_group_change_cycle = self.slice_group_change_cycle
+                    .ok_or(BitWriterError::Inconsistent { name: "slice_group_change_cycle" })?;
+                let (bits, max) = slice_group_change_cycle_range(sps, slice_group_change_rate_minus1);
+                if u64::from(slice_group_change_cycle) > max {
+                    return Err(BitWriterError::ValueOutOfRange {
+                        name: "slice_group_change_cycle",
+                        value: i64::from(slice_group_change_cycle),
+                    });
+                }
+                w.write_u_named("slice_group_change_cycle", u32::from(bits), slice_group_change_cycle)?;
+            },
+            _ => check_presence("slice_group_change_cycle", &self.slice_group_change_cycle, false)?,
+        }
+        Ok(())
+    }
+
+    /// The `num_ref_idx_l0_active_minus1` and `num_ref_idx_l1_active_minus1` in effect for this
+    /// slice, which come from the PPS (given) unless overridden by the slice.  Both are `0` for
+    /// lists the slice doesn't use.
+    pub fn num_ref_idx_active_minus1(&self, pps: &PicParameterSet) -> (u32, u32) {
+        match self.slice_type.family {
+            SliceFamily::I | SliceFamily::SI => (0, 0),
+            SliceFamily::P | SliceFamily::SP => (NumRefIdxActive::resolve(&self.num_ref_idx_active, pps).0, 0),
+            SliceFamily::B => NumRefIdxActive::resolve(&self.num_ref_idx_active, pps),
+        }
+    }
+
+    /// _SliceQPY_, the luma quantisation parameter of the slice's first macroblock:
+    /// `26 + pic_init_qp_minus26 + slice_qp_delta`, given the PPS the slice refers to.
+    ///
+    /// Returns [SliceHeaderError::InvalidSliceQpDelta] if this is outside the range `-36..=51`
+    /// permitted for any bit depth, as when the header is paired with the wrong PPS.  (Parsing
+    /// checks the narrower range for the SPS's luma bit depth, from `-QpBdOffsetY`.)  For SP and SI
+    /// slices, _QSY_ is [SliceHeader::slice_qs].
+    pub fn qp_y(&self, pps: &PicParameterSet) -> Result<i32, SliceHeaderError> {
+        let qp_y = slice_qp_y(pps, self.slice_qp_delta);
+        if !(-MAX_QP_BD_OFFSET..=51).contains(&qp_y) {
+            return Err(SliceHeaderError::InvalidSliceQpDelta(self.slice_qp_delta));
+        }
+        Ok(qp_y as i32)
+    }
+
+    /// Whether this slice belongs to a frame, an MBAFF frame or a field.
+    pub fn picture_coding(&self) -> PictureCoding {
+        self.picture_coding
+    }
+
+    /// Whether this slice belongs to a frame or a single field.
+    pub fn picture_structure(&self) -> PictureStructure {
+        self.picture_coding.structure()
+    }
+}
+#[cfg(test)]
+impl SliceHeader {
+    /// A non-IDR, non-reference slice header of the given `slice_type` with no optional syntax,
+    /// for tests to adjust via struct update syntax.
+    pub(crate) fn for_test(slice_type: u32, frame_num: u16, picture_coding: PictureCoding) -> SliceHeader {
+        let slice_type = SliceType::from_id(slice_type).unwrap();
+        let b = slice_type.family == SliceFamily::B;
+        SliceHeader {
+            first_mb_in_slice: 0,
+            slice_type,
+            pic_parameter_set_id: ParamSetId::from_u32(0).unwrap(),
+            colour_plane: None,
+            frame_num,
+            picture_coding,
+            idr_pic_id: None,
+            pic_order_cnt_lsb: None,
+            redundant_pic_cnt: None,
+            direct_spatial_mv_pred_flag: if b { Some(true) } else { None },
+            num_ref_idx_active: None,
+            ref_pic_list_modification: None,
+            pred_weight_table: None,
+            dec_ref_pic_marking: None,
+            cabac_init_idc: None,
+            slice_qp_delta: 0,
+            sp_for_switch_flag: None,
+            slice_qs: None,
+            disable_deblocking_filter_idc: 0,
+            slice_alpha_c0_offset_div2: 0,
+            slice_beta_offset_div2: 0,
+            slice_group_change_cycle: None,
+        }
     }
 }
 
+/// _QpBdOffsetY_ for the greatest luma bit depth, 14.
+const MAX_QP_BD_OFFSET: i64 = 6 * 6;
+
+/// _SliceQPY_, which may be out of range.
+fn slice_qp_y(pps: &PicParameterSet, slice_qp_delta: i32) -> i64 {
+    26 + i64::from(pps.pic_init_qp_minus26) + i64::from(slice_qp_delta)
+}
+
+/// The number of bits of `slice_group_change_cycle`, and its maximum value.
+fn slice_group_change_cycle_range(sps: &SeqParameterSet, slice_group_change_rate_minus1: u32) -> (u8, u64) {
+    // Ceil( Log2( PicSizeInMapUnits ÷ SliceGroupChangeRate + 1 ) ) bits, and a value of at most
+    // Ceil( PicSizeInMapUnits ÷ SliceGroupChangeRate ).
+    let pic_size_in_map_units = sps.pic_size_in_map_units();
+    let rate = u64::from(slice_group_change_rate_minus1) + 1;
+    let mut bits = 0;
+    while (rate << bits) < pic_size_in_map_units + rate {
+        bits += 1;
+    }
+    (bits, (pic_size_in_map_units - 1) / rate + 1)
+}
+
+/// Checks that an optional syntax element is present exactly when required.
+fn check_presence<T>(name: &'static str, value: &Option<T>, required: bool) -> Result<(), BitWriterError> {
+    if value.is_some() != required {
+        return Err(BitWriterError::Inconsistent { name });
+    }
+    Ok(())
+}
+
+fn write_num_ref_idx<W: BitWrite + ?Sized>(w: &mut W, name: &'static str, value: u32) -> Result<(), BitWriterError> {
+    if value > 31 {
+        return Err(BitWriterError::ValueOutOfRange { name, value: i64::from(value) });
+    }
+    w.write_ue_named(name, value)
+}
+
+fn read_num_ref_idx<R: BitRead + ?Sized>(r: &mut R, name: &'static str) -> Result<u32, SliceHeaderError> {
+    let val = r.read_ue_named(name)?;
+    if val > 31 {
+        return Err(SliceHeaderError::InvalidNumRefIdx(name, val));
+    }
+    Ok(val)
+}
+
 pub struct SliceLayerWithoutPartitioningRbsp<Ctx> {
     state: ParseState,
     phantom: marker::PhantomData<Ctx>
@@ -539,19 +1264,22 @@ impl<Ctx> super::NalHandler for SliceLayerWithoutPartitioningRbsp<Ctx> {
         self.state = ParseState::Start(header);
     }
 
-    fn push(&mut self, ctx: &mut Context<Ctx>, buf: &[u8]) {
+    fn push(&mut self, ctx: &mut Context<Ctx>, buf: &[u8]) -> NalInterest {
         match self.state {
             ParseState::Unstarted => panic!("start() not yet called"),
             ParseState::Start(header) => {
                 let mut r = RbspBitReader::new(buf);
                 match SliceHeader::read(ctx, &mut r, header) {
                     Ok(header) => info!("TODO: expose to caller: {:#?}", header),
-                    Err(e) => error!("slice_header() error: SliceHeaderError::{:?}", e),
+                    Err(e) => { ctx.report_error(Severity::Error, ErrorKind::SliceHeader(e)); },
                 }
                 self.state = ParseState::Continue(header);
+                // slice_data() isn't parsed yet, so there's no point being given it
+                NalInterest::Stop
             },
             ParseState::Continue(_header) => {
                 // TODO
+                NalInterest::Stop
             }
         }
     }
@@ -567,4 +1295,580 @@ impl<Ctx> Default for SliceLayerWithoutPartitioningRbsp<Ctx> {
             phantom: marker::PhantomData,
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nal::sps::{ChromaFormat, FrameCropping, FrameMbsFlags, Level, Profile, SpsBuilder};
+    use crate::rbsp::{BitWrite, BitWriterVec};
+
+    /// A 1080i SPS and a CAVLC PPS referring to it.
+    fn interlaced_context(mb_adaptive_frame_field_flag: bool) -> Context<()> {
+        let mut sps = SpsBuilder::new(Profile::High, Level::L4)
+            .dimensions(1920, 1088)
+            .build()
+            .unwrap();
+        sps.frame_mbs_flags = FrameMbsFlags::Fields { mb_adaptive_frame_field_flag };
+        sps.pic_height_in_map_units_minus1 = 33;
+        sps.frame_cropping = Some(FrameCropping { left_offset: 0, right_offset: 0, top_offset: 0, bottom_offset: 2 });
+        let mut ctx = Context::default();
+        ctx.put_seq_param_set(sps);
+        put_cavlc_pps(&mut ctx, false);
+        ctx
+    }
+
+    /// Adds a CAVLC PPS with id 0, referring to SPS 0.
+    fn put_cavlc_pps(ctx: &mut Context<()>, weighted_pred_flag: bool) {
+        let mut w = BitWriterVec::new();
+        w.write_ue_named("pic_parameter_set_id", 0).unwrap();
+        w.write_ue_named("seq_parameter_set_id", 0).unwrap();
+        w.write_bool_named("entropy_coding_mode_flag", false).unwrap();
+        w.write_bool_named("bottom_field_pic_order_in_frame_present_flag", false).unwrap();
+        w.write_ue_named("num_slice_groups_minus1", 0).unwrap();
+        w.write_ue_named("num_ref_idx_l0_default_active_minus1", 0).unwrap();
+        w.write_ue_named("num_ref_idx_l1_default_active_minus1", 0).unwrap();
+        w.write_bool_named("weighted_pred_flag", weighted_pred_flag).unwrap();
+        w.write_u_named("weighted_bipred_idc", 2, 0).unwrap();
+        w.write_se_named("pic_init_qp_minus26", 0).unwrap();
+        w.write_se_named("pic_init_qs_minus26", 0).unwrap();
+        w.write_se_named("chroma_qp_index_offset", 0).unwrap();
+        w.write_bool_named("deblocking_filter_control_present_flag", false).unwrap();
+        w.write_bool_named("constrained_intra_pred_flag", false).unwrap();
+        w.write_bool_named("redundant_pic_cnt_present_flag", false).unwrap();
+        w.write_rbsp_trailing_bits().unwrap();
+        let pps = PicParameterSet::from_bytes(ctx, &w.into_inner()).unwrap();
+        ctx.put_pic_param_set(pps);
+    }
+
+    /// Checks that [SliceHeader::write] reproduces the given slice header, which is followed
+    /// directly by `rbsp_trailing_bits()`.
+    fn assert_rewrites(ctx: &Context<()>, data: &[u8], nal_header: u8) {
+        let nal_header = NalHeader::new(nal_header).unwrap();
+        let (header, _) = SliceHeader::from_bits(&mut RbspBitReader::new(data), ctx, nal_header).unwrap();
+        let (pps, sps) = ctx.activation(header.pic_parameter_set_id).unwrap();
+        let mut w = BitWriterVec::new();
+        header.write(sps, pps, nal_header, &mut w).unwrap();
+        w.write_rbsp_trailing_bits().unwrap();
+        assert_eq!(w.into_inner(), data);
+    }
+
+    /// Reads the header of a non-reference I slice with the given field flags.
+    fn read_i_slice(ctx: &mut Context<()>, field_pic_flag: bool, bottom_field_flag: bool) -> SliceHeader {
+        let log2_max_frame_num = ctx.sps().next().unwrap().log2_max_frame_num();
+        let mut w = BitWriterVec::new();
+        w.write_ue_named("first_mb_in_slice", 0).unwrap();
+        w.write_ue_named("slice_type", 7).unwrap();
+        w.write_ue_named("pic_parameter_set_id", 0).unwrap();
+        w.write_u_named("frame_num", u32::from(log2_max_frame_num), 0).unwrap();
+        w.write_bool_named("field_pic_flag", field_pic_flag).unwrap();
+        if field_pic_flag {
+            w.write_bool_named("bottom_field_flag", bottom_field_flag).unwrap();
+        }
+        w.write_se_named("slice_qp_delta", 0).unwrap();
+        w.write_rbsp_trailing_bits().unwrap();
+        let data = w.into_inner();
+        let mut r = RbspBitReader::new(&data);
+        let (header, _, _) = SliceHeader::read(ctx, &mut r, NalHeader::new(0x01).unwrap()).unwrap();
+        header
+    }
+
+    #[test]
+    fn picture_coding() {
+        let mut ctx = interlaced_context(true);
+        let sps = ctx.sps().next().unwrap().clone();
+        assert_eq!(sps.pixel_dimensions().unwrap(), (1920, 1080));
+        assert_eq!(sps.frame_height_in_mbs(), 68);
+
+        let frame = read_i_slice(&mut ctx, false, false).picture_coding();
+        assert_eq!(frame, PictureCoding::MbaffFrame);
+        assert!(frame.mbaff_frame_flag() && !frame.field_pic_flag());
+        assert_eq!(frame.structure(), PictureStructure::Frame);
+        assert_eq!(frame.pic_height_in_mbs(&sps), 68);
+
+        let top = read_i_slice(&mut ctx, true, false).picture_coding();
+        assert_eq!(top, PictureCoding::TopField);
+        assert_eq!(top.pic_height_in_mbs(&sps), 34);
+        assert_eq!(top.pic_size_in_mbs(&sps), 120 * 34);
+
+        let bottom = read_i_slice(&mut ctx, true, true).picture_coding();
+        assert_eq!(bottom, PictureCoding::BottomField);
+        assert!(bottom.bottom_field_flag() && !bottom.mbaff_frame_flag());
+        assert_eq!(bottom.structure(), PictureStructure::BottomField);
+        assert_eq!(top.structure(), PictureStructure::TopField);
+
+        let mut ctx = interlaced_context(false);
+        assert_eq!(read_i_slice(&mut ctx, false, false).picture_coding(), PictureCoding::Frame);
+    }
+
+    #[test]
+    fn separate_colour_planes() {
+        let sps = SpsBuilder::new(Profile::High444, Level::L4)
+            .dimensions(1920, 1080)
+            .chroma_format(ChromaFormat::YUV444)
+            .separate_colour_planes(true)
+            .build()
+            .unwrap();
+        assert_eq!(sps.chroma_array_type(), ChromaFormat::Monochrome);
+        let log2_max_frame_num = sps.log2_max_frame_num();
+        let mut ctx = Context::default();
+        ctx.put_seq_param_set(sps);
+        put_cavlc_pps(&mut ctx, true);
+
+        // A P slice of the Cr plane, whose pred_weight_table() has no chroma fields.
+        let mut w = BitWriterVec::new();
+        w.write_ue_named("first_mb_in_slice", 0).unwrap();
+        w.write_ue_named("slice_type", 5).unwrap();
+        w.write_ue_named("pic_parameter_set_id", 0).unwrap();
+        w.write_u_named("colour_plane_id", 2, 2).unwrap();
+        w.write_u_named("frame_num", u32::from(log2_max_frame_num), 1).unwrap();
+        w.write_bool_named("num_ref_idx_active_override_flag", false).unwrap();
+        w.write_bool_named("ref_pic_list_modification_flag_l0", false).unwrap();
+        w.write_ue_named("luma_log2_weight_denom", 5).unwrap();
+        w.write_bool_named("luma_weight_l0_flag", true).unwrap();
+        w.write_se_named("luma_weight_l0", 33).unwrap();
+        w.write_se_named("luma_offset_l0", -1).unwrap();
+        w.write_se_named("slice_qp_delta", -3).unwrap();
+        w.write_rbsp_trailing_bits().unwrap();
+        let data = w.into_inner();
+        let mut r = RbspBitReader::new(&data);
+        let (header, _, _) = SliceHeader::read(&mut ctx, &mut r, NalHeader::new(0x01).unwrap()).unwrap();
+        assert_eq!(header.colour_plane, Some(ColourPlane::Cr));
+        assert_eq!(header.frame_num, 1);
+        assert_rewrites(&ctx, &data, 0x01);
+        let pred_weight_table = header.pred_weight_table.as_ref().unwrap();
+        assert_eq!(pred_weight_table.luma_log2_weight_denom, 5);
+        assert_eq!(pred_weight_table.chroma_log2_weight_denom, None);
+        assert_eq!(pred_weight_table.l0, vec![RefPicWeights {
+            luma: Some(PredWeight { weight: 33, offset: -1 }),
+            chroma: None,
+        }]);
+        assert_eq!(header.slice_qp_delta, -3);
+
+        let mut w = BitWriterVec::new();
+        w.write_ue_named("first_mb_in_slice", 0).unwrap();
+        w.write_ue_named("slice_type", 7).unwrap();
+        w.write_ue_named("pic_parameter_set_id", 0).unwrap();
+        w.write_u_named("colour_plane_id", 2, 3).unwrap();
+        w.write_rbsp_trailing_bits().unwrap();
+        let data = w.into_inner();
+        let mut r = RbspBitReader::new(&data);
+        assert!(matches!(
+            SliceHeader::read(&mut ctx, &mut r, NalHeader::new(0x01).unwrap()),
+            Err(SliceHeaderError::ColourPlaneError(ColourPlaneError::InvalidId(3)))
+        ));
+    }
+
+    #[test]
+    fn pred_weight_table() {
+        let mut ctx = interlaced_context(false);
+        let mut pps = ctx.pps().next().unwrap().clone();
+        pps.num_ref_idx_l1_default_active_minus1 = 1;
+        pps.weighted_bipred_idc = 1;
+        ctx.put_pic_param_set(pps);
+        let log2_max_frame_num = ctx.sps().next().unwrap().log2_max_frame_num();
+
+        // A B field, which uses the PPS's default list sizes of 1 and 2 references.
+        let mut w = BitWriterVec::new();
+        w.write_ue_named("first_mb_in_slice", 0).unwrap();
+        w.write_ue_named("slice_type", 6).unwrap();
+        w.write_ue_named("pic_parameter_set_id", 0).unwrap();
+        w.write_u_named("frame_num", u32::from(log2_max_frame_num), 1).unwrap();
+        w.write_bool_named("field_pic_flag", true).unwrap();
+        w.write_bool_named("bottom_field_flag", false).unwrap();
+        w.write_bool_named("direct_spatial_mv_pred_flag", true).unwrap();
+        w.write_bool_named("num_ref_idx_active_override_flag", false).unwrap();
+        w.write_bool_named("ref_pic_list_modification_flag_l0", false).unwrap();
+        w.write_bool_named("ref_pic_list_modification_flag_l1", false).unwrap();
+        w.write_ue_named("luma_log2_weight_denom", 6).unwrap();
+        w.write_ue_named("chroma_log2_weight_denom", 2).unwrap();
+        for i in 0..3 {
+            w.write_bool_named("luma_weight_flag", i == 0).unwrap();
+            if i == 0 {
+                w.write_se_named("luma_weight", 70).unwrap();
+                w.write_se_named("luma_offset", 2).unwrap();
+            }
+            w.write_bool_named("chroma_weight_flag", i == 2).unwrap();
+            if i == 2 {
+                for &(weight, offset) in &[(3, -4), (5, 6)] {
+                    w.write_se_named("chroma_weight", weight).unwrap();
+                    w.write_se_named("chroma_offset", offset).unwrap();
+                }
+            }
+        }
+        w.write_se_named("slice_qp_delta", 0).unwrap();
+        w.write_rbsp_trailing_bits().unwrap();
+        let data = w.into_inner();
+        let mut r = RbspBitReader::new(&data);
+        let (header, _, _) = SliceHeader::read(&mut ctx, &mut r, NalHeader::new(0x01).unwrap()).unwrap();
+        let unweighted = RefPicWeights { luma: None, chroma: None };
+        assert_eq!(header.pred_weight_table, Some(PredWeightTable {
+            luma_log2_weight_denom: 6,
+            chroma_log2_weight_denom: Some(2),
+            l0: vec![
+                RefPicWeights { luma: Some(PredWeight { weight: 70, offset: 2 }), chroma: None },
+            ],
+            l1: vec![
+                unweighted,
+                RefPicWeights {
+                    luma: None,
+                    chroma: Some([PredWeight { weight: 3, offset: -4 }, PredWeight { weight: 5, offset: 6 }]),
+                },
+            ],
+        }));
+        assert_eq!(header.slice_qp_delta, 0);
+        assert_rewrites(&ctx, &data, 0x01);
+
+        // An out-of-range denominator.
+        let mut w = BitWriterVec::new();
+        w.write_ue_named("first_mb_in_slice", 0).unwrap();
+        w.write_ue_named("slice_type", 6).unwrap();
+        w.write_ue_named("pic_parameter_set_id", 0).unwrap();
+        w.write_u_named("frame_num", u32::from(log2_max_frame_num), 1).unwrap();
+        w.write_bool_named("field_pic_flag", false).unwrap();
+        w.write_bool_named("direct_spatial_mv_pred_flag", true).unwrap();
+        w.write_bool_named("num_ref_idx_active_override_flag", false).unwrap();
+        w.write_bool_named("ref_pic_list_modification_flag_l0", false).unwrap();
+        w.write_bool_named("ref_pic_list_modification_flag_l1", false).unwrap();
+        w.write_ue_named("luma_log2_weight_denom", 8).unwrap();
+        w.write_rbsp_trailing_bits().unwrap();
+        let data = w.into_inner();
+        let mut r = RbspBitReader::new(&data);
+        assert!(matches!(
+            SliceHeader::read(&mut ctx, &mut r, NalHeader::new(0x01).unwrap()),
+            Err(SliceHeaderError::InvalidLog2WeightDenom("luma_log2_weight_denom", 8))
+        ));
+    }
+
+    #[test]
+    fn dec_ref_pic_marking() {
+        let sps = SpsBuilder::new(Profile::Main, Level::L3)
+            .dimensions(640, 480)
+            .max_num_ref_frames(2)
+            .build()
+            .unwrap();
+        let log2_max_frame_num = sps.log2_max_frame_num();
+        let mut ctx = Context::default();
+        ctx.put_seq_param_set(sps);
+        put_cavlc_pps(&mut ctx, false);
+        let mut read = |nal_header, marking: &dyn Fn(&mut BitWriterVec)| {
+            let mut w = BitWriterVec::new();
+            w.write_ue_named("first_mb_in_slice", 0).unwrap();
+            w.write_ue_named("slice_type", 7).unwrap();
+            w.write_ue_named("pic_parameter_set_id", 0).unwrap();
+            w.write_u_named("frame_num", u32::from(log2_max_frame_num), 0).unwrap();
+            if nal_header == 0x65 {
+                w.write_ue_named("idr_pic_id", 0).unwrap();
+            }
+            marking(&mut w);
+            w.write_se_named("slice_qp_delta", 0).unwrap();
+            w.write_rbsp_trailing_bits().unwrap();
+            let data = w.into_inner();
+            let mut r = RbspBitReader::new(&data);
+            let marking = SliceHeader::read(&mut ctx, &mut r, NalHeader::new(nal_header).unwrap())
+                .map(|(header, _, _)| header.dec_ref_pic_marking);
+            if marking.is_ok() {
+                assert_rewrites(&ctx, &data, nal_header);
+            }
+            marking
+        };
+
+        let idr = read(0x65, &|w| {
+            w.write_bool_named("no_output_of_prior_pics_flag", true).unwrap();
+            w.write_bool_named("long_term_reference_flag", false).unwrap();
+        });
+        assert_eq!(idr.unwrap(), Some(DecRefPicMarking::Idr {
+            no_output_of_prior_pics_flag: true,
+            long_term_reference_flag: false,
+        }));
+        assert_eq!(read(0x01, &|_| {}).unwrap(), None);
+        let sliding = read(0x21, &|w| w.write_bool_named("adaptive_ref_pic_marking_mode_flag", false).unwrap());
+        assert_eq!(sliding.unwrap(), Some(DecRefPicMarking::SlidingWindow));
+
+        let adaptive = |ops: &'static [u32]| move |w: &mut BitWriterVec| {
+            w.write_bool_named("adaptive_ref_pic_marking_mode_flag", true).unwrap();
+            for &op in ops {
+                w.write_ue_named("memory_management_control_operation", op).unwrap();
+                w.write_ue_named("value", match op { 0 | 5 => continue, v => v + 10 }).unwrap();
+                if op == 3 {
+                    w.write_ue_named("long_term_frame_idx", 1).unwrap();
+                }
+            }
+        };
+        let marking = read(0x21, &adaptive(&[1, 3, 4, 5, 2, 6, 0])).unwrap().unwrap();
+        assert!(marking.has_mmco5());
+        assert_eq!(marking, DecRefPicMarking::Adaptive(vec![
+            MemoryManagementControlOperation::ShortTermUnusedForRef { difference_of_pic_nums_minus1: 11 },
+            MemoryManagementControlOperation::ShortTermUsedForLongTerm { difference_of_pic_nums_minus1: 13, long_term_frame_idx: 1 },
+            MemoryManagementControlOperation::MaxUsedLongTermFrameRef { max_long_term_frame_idx_plus1: 14 },
+            MemoryManagementControlOperation::AllRefPicturesUnused,
+            MemoryManagementControlOperation::LongTermUnusedForRef { long_term_pic_num: 12 },
+            MemoryManagementControlOperation::CurrentUsedForLongTerm { long_term_frame_idx: 16 },
+        ]));
+        assert!(matches!(
+            read(0x21, &adaptive(&[5, 5, 0])),
+            Err(SliceHeaderError::RepeatedMemoryManagementControlOperation(MemoryManagementControlOperation::AllRefPicturesUnused))
+        ));
+        assert!(matches!(
+            read(0x21, &adaptive(&[7, 0])),
+            Err(SliceHeaderError::InvalidMemoryManagementControlOperation(7))
+        ));
+    }
+
+    #[test]
+    fn ref_pic_list_modification() {
+        let sps = SpsBuilder::new(Profile::Main, Level::L3)
+            .dimensions(640, 480)
+            .max_num_ref_frames(2)
+            .build()
+            .unwrap();
+        let log2_max_frame_num = sps.log2_max_frame_num();
+        let max_frame_num = sps.max_frame_num().unwrap();
+        let mut ctx = Context::default();
+        ctx.put_seq_param_set(sps);
+        put_cavlc_pps(&mut ctx, false);
+        // A B slice overriding the list sizes to 2 and 1, with the given operations for each.
+        let mut read = |l0: &[(u32, u32)], l1: &[(u32, u32)]| {
+            let mut w = BitWriterVec::new();
+            w.write_ue_named("first_mb_in_slice", 0).unwrap();
+            w.write_ue_named("slice_type", 6).unwrap();
+            w.write_ue_named("pic_parameter_set_id", 0).unwrap();
+            w.write_u_named("frame_num", u32::from(log2_max_frame_num), 0).unwrap();
+            w.write_bool_named("direct_spatial_mv_pred_flag", true).unwrap();
+            w.write_bool_named("num_ref_idx_active_override_flag", true).unwrap();
+            w.write_ue_named("num_ref_idx_l0_active_minus1", 1).unwrap();
+            w.write_ue_named("num_ref_idx_l1_active_minus1", 0).unwrap();
+            for ops in &[l0, l1] {
+                w.write_bool_named("ref_pic_list_modification_flag", !ops.is_empty()).unwrap();
+                if !ops.is_empty() {
+                    for &(idc, value) in ops.iter() {
+                        w.write_ue_named("modification_of_pic_nums_idc", idc).unwrap();
+                        w.write_ue_named("value", value).unwrap();
+                    }
+                    w.write_ue_named("modification_of_pic_nums_idc", 3).unwrap();
+                }
+            }
+            w.write_se_named("slice_qp_delta", 0).unwrap();
+            w.write_rbsp_trailing_bits().unwrap();
+            let data = w.into_inner();
+            let mut r = RbspBitReader::new(&data);
+            let modifications = SliceHeader::read(&mut ctx, &mut r, NalHeader::new(0x01).unwrap())
+                .map(|(header, _, _)| header.ref_pic_list_modification.unwrap());
+            if modifications.is_ok() {
+                assert_rewrites(&ctx, &data, 0x01);
+            }
+            modifications
+        };
+
+        let modifications = read(&[(1, 0), (2, 4)], &[(0, 2)]).unwrap();
+        assert_eq!(modifications.l0(), &[ModificationOfPicNums::Add(0), ModificationOfPicNums::LongTermRef(4)]);
+        assert_eq!(modifications.l1(), &[ModificationOfPicNums::Subtract(2)]);
+        let modifications = read(&[], &[]).unwrap();
+        assert!(modifications.l0().is_empty() && modifications.l1().is_empty());
+
+        assert!(matches!(
+            read(&[], &[(0, 1), (0, 1)]),
+            Err(SliceHeaderError::TooManyRefPicListModifications { list: 1, max: 1 })
+        ));
+        assert!(matches!(
+            read(&[(0, max_frame_num)], &[]),
+            Err(SliceHeaderError::InvalidAbsDiffPicNumMinus1(v)) if v == max_frame_num
+        ));
+        assert!(matches!(read(&[(4, 0)], &[]), Err(SliceHeaderError::InvalidModificationOfPicNumIdc(4))));
+    }
+
+    #[test]
+    fn from_bits() {
+        use crate::nal::pps::{PpsBuilder, SliceGroup, SliceGroupChangeType};
+        use crate::rbsp::BitReaderSlice;
+        let sps = SpsBuilder::new(Profile::Baseline, Level::L3)
+            .dimensions(640, 480)
+            .max_num_ref_frames(1)
+            .build()
+            .unwrap();
+        let log2_max_frame_num = sps.log2_max_frame_num();
+        let mut pps = PpsBuilder::for_sps(&sps).pic_init_qp(30).build().unwrap();
+        pps.slice_groups = Some(SliceGroup::Changing {
+            change_type: SliceGroupChangeType::RasterScan,
+            num_slice_groups_minus1: 1,
+            slice_group_change_direction_flag: false,
+            slice_group_change_rate_minus1: 9,
+        });
+        let mut ctx = Context::default();
+        ctx.put_seq_param_set(sps);
+        ctx.put_pic_param_set(pps.clone());
+        let write = |first_mb_in_slice, slice_qp_delta, slice_group_change_cycle| {
+            let mut w = BitWriterVec::new();
+            w.write_ue_named("first_mb_in_slice", first_mb_in_slice).unwrap();
+            w.write_ue_named("slice_type", 0).unwrap();
+            w.write_ue_named("pic_parameter_set_id", 0).unwrap();
+            w.write_u_named("frame_num", u32::from(log2_max_frame_num), 3).unwrap();
+            w.write_bool_named("num_ref_idx_active_override_flag", false).unwrap();
+            w.write_bool_named("ref_pic_list_modification_flag_l0", false).unwrap();
+            w.write_bool_named("adaptive_ref_pic_marking_mode_flag", false).unwrap();
+            w.write_se_named("slice_qp_delta", slice_qp_delta).unwrap();
+            w.write_ue_named("disable_deblocking_filter_idc", 2).unwrap();
+            w.write_se_named("slice_alpha_c0_offset_div2", -2).unwrap();
+            w.write_se_named("slice_beta_offset_div2", 3).unwrap();
+            // 1200 map units at a rate of 10 per cycle need 7 bits.
+            w.write_u_named("slice_group_change_cycle", 7, slice_group_change_cycle).unwrap();
+            // the start of slice_data().
+            w.write_u_named("mb_skip_run", 8, 0xff).unwrap();
+            w.into_inner()
+        };
+
+        let data = write(40, -4, 120);
+        let mut r = BitReaderSlice::new(&data);
+        let (header, offset) = SliceHeader::from_bits(&mut r, &ctx, NalHeader::new(0x21).unwrap()).unwrap();
+        assert_eq!(header, SliceHeader {
+            first_mb_in_slice: 40,
+            slice_type: SliceType { family: SliceFamily::P, exclusive: SliceExclusive::NonExclusive },
+            pic_parameter_set_id: ParamSetId::from_u32(0).unwrap(),
+            colour_plane: None,
+            frame_num: 3,
+            picture_coding: PictureCoding::Frame,
+            idr_pic_id: None,
+            pic_order_cnt_lsb: None,
+            redundant_pic_cnt: None,
+            direct_spatial_mv_pred_flag: None,
+            num_ref_idx_active: None,
+            ref_pic_list_modification: Some(RefPicListModifications::P { ref_pic_list_modification_l0: vec![] }),
+            pred_weight_table: None,
+            dec_ref_pic_marking: Some(DecRefPicMarking::SlidingWindow),
+            cabac_init_idc: None,
+            slice_qp_delta: -4,
+            sp_for_switch_flag: None,
+            slice_qs: None,
+            disable_deblocking_filter_idc: 2,
+            slice_alpha_c0_offset_div2: -2,
+            slice_beta_offset_div2: 3,
+            slice_group_change_cycle: Some(120),
+        });
+        assert_eq!(header.num_ref_idx_active_minus1(&pps), (0, 0));
+        assert_eq!(header.qp_y(&pps).unwrap(), 26);
+        let mut other_pps = pps.clone();
+        other_pps.pic_init_qp_minus26 = 30;
+        assert!(matches!(header.qp_y(&other_pps), Err(SliceHeaderError::InvalidSliceQpDelta(-4))));
+        assert_eq!(offset, r.bit_position());
+        assert_eq!(r.read_u8(8).unwrap(), 0xff);
+
+        let data = write(1200, 0, 0);
+        assert!(matches!(
+            SliceHeader::from_bits(&mut BitReaderSlice::new(&data), &ctx, NalHeader::new(0x21).unwrap()),
+            Err(SliceHeaderError::InvalidFirstMbInSlice(1200))
+        ));
+        // SliceQPY = 26 + 4 + 22 is beyond 51.
+        let data = write(0, 22, 0);
+        assert!(matches!(
+            SliceHeader::from_bits(&mut BitReaderSlice::new(&data), &ctx, NalHeader::new(0x21).unwrap()),
+            Err(SliceHeaderError::InvalidSliceQpDelta(22))
+        ));
+        let data = write(0, 0, 121);
+        assert!(matches!(
+            SliceHeader::from_bits(&mut BitReaderSlice::new(&data), &ctx, NalHeader::new(0x21).unwrap()),
+            Err(SliceHeaderError::InvalidSliceGroupChangeCycle(121))
+        ));
+
+        // rewriting the header, with its frame_num renumbered.
+        let (_, sps) = ctx.activation(header.pic_parameter_set_id).unwrap();
+        let nal_header = NalHeader::new(0x21).unwrap();
+        let mut renumbered = header.clone();
+        renumbered.frame_num = 9;
+        let mut w = BitWriterVec::new();
+        renumbered.write(sps, &pps, nal_header, &mut w).unwrap();
+        assert_eq!(w.bit_position(), offset);
+        w.write_u_named("mb_skip_run", 8, 0xff).unwrap();
+        let data = w.into_inner();
+        assert_eq!(SliceHeader::from_bits(&mut BitReaderSlice::new(&data), &ctx, nal_header).unwrap().0, renumbered);
+
+        let write_err = |header: &SliceHeader, nal_header| header.write(sps, &pps, nal_header, &mut BitWriterVec::new()).unwrap_err();
+        let mut bad = header.clone();
+        bad.frame_num = 1 << log2_max_frame_num;
+        assert!(matches!(write_err(&bad, nal_header), BitWriterError::ValueTooLarge { name: "frame_num", .. }));
+        let mut bad = header.clone();
+        bad.cabac_init_idc = Some(0);
+        assert_eq!(write_err(&bad, nal_header), BitWriterError::Inconsistent { name: "cabac_init_idc" });
+        let mut bad = header.clone();
+        bad.num_ref_idx_active = Some(NumRefIdxActive::P { num_ref_idx_l0_active_minus1: 32 });
+        assert_eq!(write_err(&bad, nal_header), BitWriterError::ValueOutOfRange { name: "num_ref_idx_l0_active_minus1", value: 32 });
+        // a non-reference picture has no dec_ref_pic_marking().
+        assert_eq!(write_err(&header, NalHeader::new(0x01).unwrap()), BitWriterError::Inconsistent { name: "dec_ref_pic_marking" });
+        let mut bad = header;
+        bad.slice_group_change_cycle = Some(121);
+        assert_eq!(write_err(&bad, nal_header), BitWriterError::ValueOutOfRange { name: "slice_group_change_cycle", value: 121 });
+    }
+
+    #[test]
+    fn slice_type() {
+        for id in 0..10 {
+            let t = SliceType::from_id(id).unwrap();
+            assert_eq!(t.id(), id);
+            assert_eq!(t.is_exclusive(), id >= 5);
+            assert_eq!(t.family, SliceType::from_id(id % 5).unwrap().family);
+        }
+        let i = SliceType::from_id(7).unwrap();
+        assert!(i.is_intra() && !i.is_inter());
+        assert_eq!(i.family, SliceFamily::I);
+        let si = SliceType::from_id(4).unwrap();
+        assert!(si.is_intra() && !si.is_exclusive());
+        for &id in &[0, 1, 3, 5, 6, 8] {
+            assert!(SliceType::from_id(id).unwrap().is_inter());
+        }
+        assert!(matches!(SliceType::from_id(10), Err(SliceHeaderError::InvalidSliceType(10))));
+    }
+
+    #[test]
+    fn interlaced_pic_order_cnt() {
+        let mut ctx = interlaced_context(false);
+        let mut pps = ctx.pps().next().unwrap().clone();
+        pps.bottom_field_pic_order_in_frame_present_flag = true;
+        ctx.put_pic_param_set(pps);
+        let mut sps = ctx.sps().next().unwrap().clone();
+        let log2_max_frame_num = sps.log2_max_frame_num();
+        let read = |ctx: &mut Context<()>, field_pic_flag: bool, poc: &dyn Fn(&mut BitWriterVec)| {
+            let mut w = BitWriterVec::new();
+            w.write_ue_named("first_mb_in_slice", 0).unwrap();
+            w.write_ue_named("slice_type", 7).unwrap();
+            w.write_ue_named("pic_parameter_set_id", 0).unwrap();
+            w.write_u_named("frame_num", u32::from(log2_max_frame_num), 2).unwrap();
+            w.write_bool_named("field_pic_flag", field_pic_flag).unwrap();
+            if field_pic_flag {
+                w.write_bool_named("bottom_field_flag", true).unwrap();
+            }
+            poc(&mut w);
+            w.write_se_named("slice_qp_delta", 5).unwrap();
+            w.write_rbsp_trailing_bits().unwrap();
+            let data = w.into_inner();
+            let mut r = RbspBitReader::new(&data);
+            let (header, _, _) = SliceHeader::read(ctx, &mut r, NalHeader::new(0x01).unwrap()).unwrap();
+            assert_eq!(header.frame_num, 2);
+            assert_eq!(header.slice_qp_delta, 5);
+            assert_rewrites(ctx, &data, 0x01);
+            (header.picture_structure(), header.pic_order_cnt_lsb)
+        };
+
+        sps.pic_order_cnt = sps::PicOrderCntType::TypeZero { log2_max_pic_order_cnt_lsb_minus4: 0 };
+        ctx.put_seq_param_set(sps.clone());
+        // delta_pic_order_cnt_bottom is present only for frames.
+        assert_eq!(read(&mut ctx, false, &|w| {
+            w.write_u_named("pic_order_cnt_lsb", 4, 6).unwrap();
+            w.write_se_named("delta_pic_order_cnt_bottom", 1).unwrap();
+        }), (PictureStructure::Frame, Some(PicOrderCountLsb::FieldsAbsolute { top: 6, bottom_delta: 7 })));
+        assert_eq!(read(&mut ctx, true, &|w| w.write_u_named("pic_order_cnt_lsb", 4, 7).unwrap()),
+                   (PictureStructure::BottomField, Some(PicOrderCountLsb::Frame(7))));
+
+        sps.pic_order_cnt = sps::PicOrderCntType::TypeOne {
+            delta_pic_order_always_zero_flag: false,
+            offset_for_non_ref_pic: 0,
+            offset_for_top_to_bottom_field: 1,
+            offsets_for_ref_frame: vec![2],
+        };
+        ctx.put_seq_param_set(sps);
+        // as is delta_pic_order_cnt[1].
+        assert_eq!(read(&mut ctx, false, &|w| {
+            w.write_se_named("delta_pic_order_cnt[0]", -1).unwrap();
+            w.write_se_named("delta_pic_order_cnt[1]", 3).unwrap();
+        }), (PictureStructure::Frame, Some(PicOrderCountLsb::FieldsDelta([-1, 3]))));
+        assert_eq!(read(&mut ctx, true, &|w| w.write_se_named("delta_pic_order_cnt[0]", 4).unwrap()),
+                   (PictureStructure::BottomField, Some(PicOrderCountLsb::FieldsDelta([4, 0]))));
+    }
+}
@@ -0,0 +1,178 @@
+//! Coded slice data partitions (_7.3.2.9_ and _7.3.2.10_), NAL unit types `2` to `4`, as used
+//! by the Extended profile.
+//!
+//! Partition A carries the slice header and macroblock headers; partitions B and C carry the
+//! intra and inter residual data respectively.  B and C have no slice header of their own, but
+//! refer to the partition A of the same slice by `slice_id`, and follow it in decoding order.
+
+use super::{ColourPlane, ColourPlaneError, SliceHeader, SliceHeaderError};
+use crate::nal::NalHeader;
+use crate::rbsp::{BitRead, RbspBitReaderError};
+use crate::Context;
+
+#[derive(Debug)]
+pub enum SliceDataPartitionError {
+    RbspError(RbspBitReaderError),
+    SliceHeader(SliceHeaderError),
+    ColourPlaneError(ColourPlaneError),
+    /// `slice_id` was beyond the number of slices a picture may have
+    InvalidSliceId(u32),
+    /// A partition B or C didn't match the partition A it was parsed against, differing in
+    /// `slice_id`, `colour_plane_id` or `redundant_pic_cnt`
+    UnassociatedPartition { slice_id: u32 },
+}
+impl From<RbspBitReaderError> for SliceDataPartitionError {
+    fn from(e: RbspBitReaderError) -> Self {
+        SliceDataPartitionError::RbspError(e)
+    }
+}
+impl From<SliceHeaderError> for SliceDataPartitionError {
+    fn from(e: SliceHeaderError) -> Self {
+        SliceDataPartitionError::SliceHeader(e)
+    }
+}
+impl From<ColourPlaneError> for SliceDataPartitionError {
+    fn from(e: ColourPlaneError) -> Self {
+        SliceDataPartitionError::ColourPlaneError(e)
+    }
+}
+
+/// The header of a partition A NAL unit (`slice_data_partition_a_layer_rbsp()`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SliceDataPartitionA {
+    pub header: SliceHeader,
+    /// Identifies the slice within its picture, so that its partitions B and C may refer to it.
+    pub slice_id: u32,
+}
+impl SliceDataPartitionA {
+    /// Parses the slice header and `slice_id`, using the PPS and SPS the header refers to from
+    /// `ctx`.
+    ///
+    /// Returns the reader's [BitRead::bit_position] following them, where the category 2
+    /// `slice_data()` begins.
+    pub fn from_bits<Ctx, R: BitRead + ?Sized>(
+        r: &mut R,
+        ctx: &Context<Ctx>,
+        nal_header: NalHeader,
+    ) -> Result<(SliceDataPartitionA, u64), SliceDataPartitionError> {
+        let (header, _) = SliceHeader::from_bits(r, ctx, nal_header)?;
+        let (_, sps) = ctx.activation(header.pic_parameter_set_id).map_err(SliceHeaderError::from)?;
+        let slice_id = r.read_ue_named("slice_id")?;
+        // one slice per macroblock (or macroblock pair, in MBAFF frames) at most.
+        let mbs_per_address = if header.picture_coding.mbaff_frame_flag() { 2 } else { 1 };
+        if u64::from(slice_id) * mbs_per_address >= header.picture_coding.pic_size_in_mbs(sps) {
+            return Err(SliceDataPartitionError::InvalidSliceId(slice_id));
+        }
+        Ok((SliceDataPartitionA { header, slice_id }, r.bit_position()))
+    }
+}
+
+/// The header of a partition B or C NAL unit (`slice_data_partition_b_layer_rbsp()` or
+/// `slice_data_partition_c_layer_rbsp()`), which have the same syntax.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SliceDataPartitionBOrC {
+    pub slice_id: u32,
+    /// Present when the SPS's `separate_colour_plane_flag` is set.
+    pub colour_plane: Option<ColourPlane>,
+    /// Present when the PPS's `redundant_pic_cnt_present_flag` is set.
+    pub redundant_pic_cnt: Option<u32>,
+}
+impl SliceDataPartitionBOrC {
+    /// Parses the header of a partition B or C belonging to the given partition A, whose PPS
+    /// and SPS (from `ctx`) determine which syntax elements are present.
+    ///
+    /// Returns the reader's [BitRead::bit_position] following the header, where the category 3
+    /// or 4 `slice_data()` begins.  A partition which doesn't belong to `partition_a` (as when
+    /// that of its own slice was lost) is an error.
+    pub fn from_bits<Ctx, R: BitRead + ?Sized>(
+        r: &mut R,
+        ctx: &Context<Ctx>,
+        partition_a: &SliceDataPartitionA,
+    ) -> Result<(SliceDataPartitionBOrC, u64), SliceDataPartitionError> {
+        let (pps, sps) = ctx
+            .activation(partition_a.header.pic_parameter_set_id)
+            .map_err(SliceHeaderError::from)?;
+        let slice_id = r.read_ue_named("slice_id")?;
+        let colour_plane = if sps.chroma_info.separate_colour_plane_flag {
+            Some(ColourPlane::from_id(r.read_u8(2)?)?)
+        } else {
+            None
+        };
+        let redundant_pic_cnt = if pps.redundant_pic_cnt_present_flag {
+            Some(r.read_ue_named("redundant_pic_cnt")?)
+        } else {
+            None
+        };
+        let partition = SliceDataPartitionBOrC { slice_id, colour_plane, redundant_pic_cnt };
+        if !partition.belongs_to(partition_a) {
+            return Err(SliceDataPartitionError::UnassociatedPartition { slice_id });
+        }
+        Ok((partition, r.bit_position()))
+    }
+
+    /// Whether this partition belongs to the same slice as the given partition A.
+    pub fn belongs_to(&self, partition_a: &SliceDataPartitionA) -> bool {
+        self.slice_id == partition_a.slice_id
+            && self.colour_plane == partition_a.header.colour_plane
+            && self.redundant_pic_cnt == partition_a.header.redundant_pic_cnt
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nal::pps::PpsBuilder;
+    use crate::nal::sps::{Level, PicOrderCntType, Profile, SpsBuilder};
+    use crate::rbsp::{BitReaderSlice, BitWrite, BitWriterVec};
+
+    #[test]
+    fn partitions() {
+        let mut sps = SpsBuilder::new(Profile::Extended, Level::L3)
+            .dimensions(640, 480)
+            .build()
+            .unwrap();
+        sps.log2_max_frame_num_minus4 = 0;
+        sps.pic_order_cnt = PicOrderCntType::TypeTwo;
+        let mut pps = PpsBuilder::for_sps(&sps).build().unwrap();
+        pps.redundant_pic_cnt_present_flag = true;
+        let mut ctx = Context::default();
+        ctx.put_seq_param_set(sps);
+        ctx.put_pic_param_set(pps);
+
+        let mut w = BitWriterVec::new();
+        w.write_ue_named("first_mb_in_slice", 40).unwrap();
+        w.write_ue_named("slice_type", 7).unwrap();
+        w.write_ue_named("pic_parameter_set_id", 0).unwrap();
+        w.write_u_named("frame_num", 4, 1).unwrap();
+        w.write_ue_named("redundant_pic_cnt", 0).unwrap();
+        w.write_bool_named("adaptive_ref_pic_marking_mode_flag", false).unwrap();
+        w.write_se_named("slice_qp_delta", 0).unwrap();
+        w.write_ue_named("disable_deblocking_filter_idc", 1).unwrap();
+        w.write_ue_named("slice_id", 2).unwrap();
+        w.write_rbsp_trailing_bits().unwrap();
+        let data = w.into_inner();
+        let mut r = BitReaderSlice::new(&data);
+        let (a, offset) = SliceDataPartitionA::from_bits(&mut r, &ctx, NalHeader::new(0x42).unwrap()).unwrap();
+        assert_eq!(a.header.first_mb_in_slice, 40);
+        assert_eq!(a.header.redundant_pic_cnt, Some(0));
+        assert_eq!(a.slice_id, 2);
+        assert_eq!(offset, r.bit_position());
+        assert!(r.read_bool().unwrap());
+
+        let partition = |slice_id, redundant_pic_cnt| {
+            let mut w = BitWriterVec::new();
+            w.write_ue_named("slice_id", slice_id).unwrap();
+            w.write_ue_named("redundant_pic_cnt", redundant_pic_cnt).unwrap();
+            w.write_rbsp_trailing_bits().unwrap();
+            let data = w.into_inner();
+            SliceDataPartitionBOrC::from_bits(&mut BitReaderSlice::new(&data), &ctx, &a).map(|(p, _)| p)
+        };
+        assert_eq!(partition(2, 0).unwrap(), SliceDataPartitionBOrC {
+            slice_id: 2,
+            colour_plane: None,
+            redundant_pic_cnt: Some(0),
+        });
+        assert!(matches!(partition(3, 0), Err(SliceDataPartitionError::UnassociatedPartition { slice_id: 3 })));
+        assert!(matches!(partition(2, 1), Err(SliceDataPartitionError::UnassociatedPartition { slice_id: 2 })));
+    }
+}
@@ -1,8 +1,8 @@
 
-use crate::rbsp::RbspBitReader;
-use super::NalHandler;
-use super::NalHeader;
-use crate::Context;
+use crate::rbsp::{self, BitRead, BitReaderSlice, BitWrite, BitWriterError, BitWriterVec, ErrorContext, ErrorLocation};
+use super::{NalHandler, NalInterest};
+use super::{NalHeader, NalHeaderError, UnitType};
+use crate::{Context, ErrorKind, Severity};
 use crate::rbsp::RbspBitReaderError;
 use std::{marker, fmt};
 use crate::nal::pps::ParamSetId;
@@ -19,12 +19,31 @@ pub enum SpsError {
     /// log2_max_frame_num_minus4 must be between 0 and 12
     Log2MaxFrameNumMinus4OutOfRange(u32),
     BadSeqParamSetId(ParamSetIdError),
-    /// A field in the bitstream had a value too large for a subsequent calculation
+    /// A field in the bitstream had a value beyond the limit allowed by the spec, or too large
+    /// for a subsequent calculation
     FieldValueTooLarge { name: &'static str, value: u32 },
     /// The frame-cropping values are too large vs. the coded picture size,
     CroppingError(FrameCropping),
     /// The `cpb_cnt_minus1` field must be between 0 and 31 inclusive.
     CpbCountOutOfRange(u32),
+    /// The wrapped error occurred at the given location within the SPS.
+    InContext(ErrorLocation, Box<SpsError>),
+    /// The NAL unit given to [SeqParameterSet::from_nal] was empty, lacking even the header
+    /// byte.
+    EmptyNal,
+    /// The NAL unit given to [SeqParameterSet::from_nal] had an invalid header.
+    NalHeader(NalHeaderError),
+    /// The NAL unit given to [SeqParameterSet::from_nal] was not an SPS.
+    IncorrectNalType(UnitType),
+}
+impl SpsError {
+    /// Where the error occurred, if known.
+    pub fn location(&self) -> Option<&ErrorLocation> {
+        match self {
+            SpsError::InContext(loc, _) => Some(loc),
+            _ => None,
+        }
+    }
 }
 
 impl From<RbspBitReaderError> for SpsError {
@@ -32,8 +51,48 @@ impl From<RbspBitReaderError> for SpsError {
         SpsError::RbspReaderError(e)
     }
 }
+impl ErrorContext for SpsError {
+    fn in_scope(self, scope: &'static str, bit_offset: u64) -> Self {
+        match self {
+            SpsError::InContext(loc, e) => SpsError::InContext(loc.within(scope), e),
+            e => SpsError::InContext(ErrorLocation::new(scope, bit_offset), Box::new(e)),
+        }
+    }
+}
+
+/// The largest _MaxDpbFrames_ of any level, which bounds `max_num_ref_frames` and the VUI's
+/// `max_dec_frame_buffering` and `max_num_reorder_frames`.
+const MAX_DPB_FRAMES: u32 = 16;
+
+/// The largest _MaxFS_ of any level in _Table A-1_ (that of levels 6, 6.1 and 6.2), bounding
+/// _FrameSizeInMbs_.
+const MAX_FRAME_SIZE_MBS: u64 = 139_264;
+
+/// `Sqrt(MaxFS * 8)` for the largest _MaxFS_, which bounds _PicWidthInMbs_ and
+/// _FrameHeightInMbs_ (_A.3.1_ item f).
+const MAX_DIMENSION_MBS: u32 = 1055;
+
+/// Reads a `ue(v)` field, failing with [SpsError::FieldValueTooLarge] if it exceeds `max`.
+fn read_ue_max<R: BitRead + ?Sized>(r: &mut R, name: &'static str, max: u32) -> Result<u32, SpsError> {
+    let value = r.read_ue_named(name)?;
+    if value > max {
+        Err(SpsError::FieldValueTooLarge { name, value })
+    } else {
+        Ok(value)
+    }
+}
+
+/// An error from [SeqParameterSet::rewrite_vui].
+#[derive(Debug)]
+pub enum VuiRewriteError {
+    /// The given SPS could not be parsed.
+    Sps(SpsError),
+    /// The modified VUI could not be written.
+    Write(BitWriterError),
+}
 
 pub struct SeqParameterSetNalHandler<Ctx> {
+    header: Option<NalHeader>,
     buf: Vec<u8>,
     phantom: marker::PhantomData<Ctx>
 }
@@ -41,6 +100,7 @@ pub struct SeqParameterSetNalHandler<Ctx> {
 impl<Ctx> Default for SeqParameterSetNalHandler<Ctx> {
     fn default() -> Self {
         SeqParameterSetNalHandler {
+            header: None,
             buf: Vec::new(),
             phantom: marker::PhantomData,
         }
@@ -51,35 +111,71 @@ impl<Ctx> NalHandler for SeqParameterSetNalHandler<Ctx> {
 
     fn start(&mut self, _ctx: &mut Context<Ctx>, header: NalHeader) {
         assert_eq!(header.nal_unit_type(), super::UnitType::SeqParameterSet);
+        self.header = Some(header);
     }
 
-    fn push(&mut self, _ctx: &mut Context<Ctx>, buf: &[u8]) {
+    fn push(&mut self, _ctx: &mut Context<Ctx>, buf: &[u8]) -> NalInterest {
         self.buf.extend_from_slice(buf);
+        NalInterest::Continue
     }
 
     fn end(&mut self, ctx: &mut Context<Ctx>) {
         let sps = SeqParameterSet::from_bytes(&self.buf[..]);
-        self.buf.clear();
-        if let Ok(sps) = sps {
-            ctx.put_seq_param_set(sps);
+        match (sps, self.header.take()) {
+            (Ok(sps), Some(header)) => ctx.put_seq_param_set_with_nal(sps, rbsp::reencode_nal(header, &self.buf)),
+            (Ok(sps), None) => ctx.put_seq_param_set(sps),
+            (Err(e), _) => { ctx.report_error(Severity::Error, ErrorKind::Sps(e)); },
         }
+        self.buf.clear();
+    }
+
+    fn reset(&mut self, _ctx: &mut Context<Ctx>) {
+        self.header = None;
+        self.buf.clear();
     }
 }
 
-#[derive(Debug)]
+/// A profile of _Annex A_, _Annex G_ or _Annex H_.
+///
+/// `from_profile_idc` distinguishes profiles by `profile_idc` alone, while
+/// `from_profile_idc_and_constraint_flags` also recognises the profiles which are signalled by
+/// constraint flags, such as Constrained Baseline or the Intra profiles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Profile {
     Unknown(u8),
     Baseline,
+    /// Baseline with `constraint_set1_flag` (_A.2.1.1_)
+    ConstrainedBaseline,
     Main,
+    Extended,
     High,
-    High422,
+    /// High with `constraint_set4_flag` (_A.2.4.1_)
+    ProgressiveHigh,
+    /// High with `constraint_set4_flag` and `constraint_set5_flag` (_A.2.4.2_)
+    ConstrainedHigh,
     High10,
+    /// High 10 with `constraint_set3_flag` (_A.2.8_)
+    High10Intra,
+    High422,
+    /// High 4:2:2 with `constraint_set3_flag` (_A.2.9_)
+    High422Intra,
+    /// High 4:4:4 Predictive
     High444,
-    Extended,
+    /// High 4:4:4 Predictive with `constraint_set3_flag` (_A.2.10_)
+    High444Intra,
+    CAVLC444Intra,
     ScalableBase,
+    /// Scalable Baseline with `constraint_set5_flag` (_G.10.1.1.1_)
+    ScalableConstrainedBaseline,
     ScalableHigh,
+    /// Scalable High with `constraint_set5_flag` (_G.10.1.3.1_)
+    ScalableConstrainedHigh,
+    /// Scalable High with `constraint_set3_flag` (_G.10.1.3.2_)
+    ScalableHighIntra,
     MultiviewHigh,
     StereoHigh,
+    MFCHigh,
     MFCDepthHigh,
     MultiviewDepthHigh,
     EnhancedMultiviewDepthHigh,
@@ -87,7 +183,6 @@ pub enum Profile {
 
 impl Profile {
     pub fn from_profile_idc(profile_idc: ProfileIdc) -> Profile {
-        // TODO: accept constraint_flags too, as Level does?
         match profile_idc.0 {
             66  => Profile::Baseline,
             77  => Profile::Main,
@@ -95,39 +190,189 @@ impl Profile {
             122 => Profile::High422,
             110 => Profile::High10,
             244 => Profile::High444,
+            44  => Profile::CAVLC444Intra,
             88  => Profile::Extended,
             83  => Profile::ScalableBase,
             86  => Profile::ScalableHigh,
             118 => Profile::MultiviewHigh,
             128 => Profile::StereoHigh,
+            134 => Profile::MFCHigh,
             135 => Profile::MFCDepthHigh,
             138 => Profile::MultiviewDepthHigh,
             139 => Profile::EnhancedMultiviewDepthHigh,
             other   => Profile::Unknown(other),
         }
     }
+
+    /// Identifies the profile from `profile_idc` and the constraint flags whose meaning depends
+    /// upon it.
+    pub fn from_profile_idc_and_constraint_flags(profile_idc: ProfileIdc, constraint_flags: ConstraintFlags) -> Profile {
+        let c = constraint_flags;
+        match Profile::from_profile_idc(profile_idc) {
+            Profile::Baseline if c.flag1() => Profile::ConstrainedBaseline,
+            Profile::High if c.flag4() && c.flag5() => Profile::ConstrainedHigh,
+            Profile::High if c.flag4() => Profile::ProgressiveHigh,
+            Profile::High10 if c.flag3() => Profile::High10Intra,
+            Profile::High422 if c.flag3() => Profile::High422Intra,
+            Profile::High444 if c.flag3() => Profile::High444Intra,
+            Profile::ScalableBase if c.flag5() => Profile::ScalableConstrainedBaseline,
+            Profile::ScalableHigh if c.flag3() => Profile::ScalableHighIntra,
+            Profile::ScalableHigh if c.flag5() => Profile::ScalableConstrainedHigh,
+            other => other,
+        }
+    }
+
     pub fn profile_idc(&self) -> u8 {
         match *self {
-            Profile::Baseline                   => 66,
+            Profile::Baseline
+            | Profile::ConstrainedBaseline      => 66,
             Profile::Main                       => 77,
-            Profile::High                       => 100,
-            Profile::High422                    => 122,
-            Profile::High10                     => 110,
-            Profile::High444                    => 144,
+            Profile::High
+            | Profile::ProgressiveHigh
+            | Profile::ConstrainedHigh          => 100,
+            Profile::High422
+            | Profile::High422Intra             => 122,
+            Profile::High10
+            | Profile::High10Intra              => 110,
+            Profile::High444
+            | Profile::High444Intra             => 244,
+            Profile::CAVLC444Intra              => 44,
             Profile::Extended                   => 88,
-            Profile::ScalableBase               => 83,
-            Profile::ScalableHigh               => 86,
+            Profile::ScalableBase
+            | Profile::ScalableConstrainedBaseline => 83,
+            Profile::ScalableHigh
+            | Profile::ScalableConstrainedHigh
+            | Profile::ScalableHighIntra        => 86,
             Profile::MultiviewHigh              => 118,
             Profile::StereoHigh                 => 128,
+            Profile::MFCHigh                    => 134,
             Profile::MFCDepthHigh               => 135,
             Profile::MultiviewDepthHigh         => 138,
             Profile::EnhancedMultiviewDepthHigh => 139,
             Profile::Unknown(profile_idc)       => profile_idc,
         }
     }
+
+    /// True for the profiles which only permit IDR pictures.
+    pub fn is_intra(&self) -> bool {
+        matches!(self, Profile::High10Intra | Profile::High422Intra | Profile::High444Intra
+            | Profile::CAVLC444Intra | Profile::ScalableHighIntra)
+    }
+
+    /// Checks the given SPS against the constraints this profile places on its fields,
+    /// returning every constraint violated.
+    ///
+    /// These are the permitted `chroma_format_idc` values and bit depths, whether
+    /// `qpprime_y_zero_transform_bypass_flag` may be set, and whether `frame_mbs_only_flag` must
+    /// be set.  Typically called as `sps.profile().check(&sps)`, though another profile may be
+    /// given to see whether the SPS also satisfies it.
+    pub fn check(&self, sps: &SeqParameterSet) -> Result<(), ProfileError> {
+        // (max chroma_format_idc, max bit depth, transform bypass allowed, frame_mbs_only_flag required)
+        let (max_chroma_format_idc, max_bit_depth, bypass_allowed, frames_only) = match *self {
+            Profile::Baseline | Profile::ConstrainedBaseline => (1, 8, false, true),
+            Profile::Main | Profile::Extended | Profile::High => (1, 8, false, false),
+            Profile::ProgressiveHigh | Profile::ConstrainedHigh => (1, 8, false, true),
+            Profile::High10 | Profile::High10Intra => (1, 10, false, false),
+            Profile::High422 | Profile::High422Intra => (2, 10, false, false),
+            Profile::High444 | Profile::High444Intra | Profile::CAVLC444Intra => (3, 14, true, false),
+            Profile::ScalableBase | Profile::ScalableConstrainedBaseline | Profile::ScalableHigh
+            | Profile::ScalableConstrainedHigh | Profile::ScalableHighIntra
+            | Profile::MultiviewHigh | Profile::StereoHigh | Profile::MFCHigh | Profile::MFCDepthHigh
+            | Profile::MultiviewDepthHigh | Profile::EnhancedMultiviewDepthHigh => (1, 8, false, false),
+            Profile::Unknown(profile_idc) => return Err(ProfileError::UnknownProfile(profile_idc)),
+        };
+        let chroma = &sps.chroma_info;
+        let mut violations = Vec::new();
+        match chroma.chroma_format {
+            ChromaFormat::Invalid(_) => violations.push(ProfileViolation::ChromaFormat(chroma.chroma_format)),
+            f if f.chroma_format_idc() > max_chroma_format_idc => violations.push(ProfileViolation::ChromaFormat(f)),
+            _ => {},
+        }
+        let bit_depth_luma = u32::from(chroma.bit_depth_luma_minus8) + 8;
+        if bit_depth_luma > max_bit_depth {
+            violations.push(ProfileViolation::BitDepthLuma { actual: bit_depth_luma, max: max_bit_depth });
+        }
+        let bit_depth_chroma = u32::from(chroma.bit_depth_chroma_minus8) + 8;
+        if bit_depth_chroma > max_bit_depth {
+            violations.push(ProfileViolation::BitDepthChroma { actual: bit_depth_chroma, max: max_bit_depth });
+        }
+        if chroma.qpprime_y_zero_transform_bypass_flag && !bypass_allowed {
+            violations.push(ProfileViolation::TransformBypass);
+        }
+        if frames_only && !sps.frame_mbs_flags.frame_mbs_only_flag() {
+            violations.push(ProfileViolation::FieldCoding);
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(ProfileError::ConstraintsViolated(violations))
+        }
+    }
+
+    /// Whether a decoder conforming to this profile must be able to decode a stream with the
+    /// given `profile_idc` and constraint flags, per the decoder conformance rules of _A.2_.
+    /// `None` for an unknown profile.
+    ///
+    /// Decoders of the scalable and multiview profiles are taken to decode only the base layer
+    /// or view described by an SPS, per the corresponding single-layer profile.
+    fn decodes(&self, profile_idc: ProfileIdc, c: ConstraintFlags) -> Option<bool> {
+        let idc = profile_idc.0;
+        let constrained_baseline = (idc == 66 && c.flag1()) || (c.flag0() && c.flag1());
+        let baseline = idc == 66 || c.flag0();
+        let main = idc == 77 || c.flag1();
+        let high = main || idc == 100;
+        let high10 = high || idc == 110;
+        let high422 = high10 || idc == 122;
+        let progressive = (main || idc == 100) && c.flag4();
+        Some(match *self {
+            Profile::Baseline => baseline,
+            Profile::ConstrainedBaseline | Profile::ScalableBase
+            | Profile::ScalableConstrainedBaseline => constrained_baseline,
+            Profile::Main => main,
+            Profile::Extended => constrained_baseline || idc == 88 || c.flag2(),
+            Profile::High | Profile::ScalableHigh | Profile::ScalableConstrainedHigh
+            | Profile::ScalableHighIntra | Profile::MultiviewHigh | Profile::StereoHigh
+            | Profile::MFCHigh | Profile::MFCDepthHigh | Profile::MultiviewDepthHigh
+            | Profile::EnhancedMultiviewDepthHigh => high,
+            Profile::ProgressiveHigh => constrained_baseline || progressive,
+            Profile::ConstrainedHigh => constrained_baseline || (progressive && c.flag5()),
+            Profile::High10 => high10,
+            Profile::High422 => high422,
+            Profile::High444 => high422 || idc == 244 || idc == 44,
+            Profile::High10Intra => idc == 110 && c.flag3(),
+            Profile::High422Intra => matches!(idc, 110 | 122) && c.flag3(),
+            Profile::High444Intra => (matches!(idc, 110 | 122 | 244) && c.flag3()) || idc == 44,
+            Profile::CAVLC444Intra => idc == 44,
+            Profile::Unknown(_) => return None,
+        })
+    }
+}
+
+/// A constraint of a profile which an SPS fails to meet; see [Profile::check].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileViolation {
+    /// The chroma format is not permitted.
+    ChromaFormat(ChromaFormat),
+    /// The luma bit depth exceeds the maximum permitted.
+    BitDepthLuma { actual: u32, max: u32 },
+    /// The chroma bit depth exceeds the maximum permitted.
+    BitDepthChroma { actual: u32, max: u32 },
+    /// `qpprime_y_zero_transform_bypass_flag` is set, which only the High 4:4:4 profiles permit.
+    TransformBypass,
+    /// `frame_mbs_only_flag` is not set, but the profile permits only frame coding.
+    FieldCoding,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Debug, PartialEq)]
+pub enum ProfileError {
+    /// The profile has no known constraints.
+    UnknownProfile(u8),
+    /// The SPS violates the given constraints of the profile.
+    ConstraintsViolated(Vec<ProfileViolation>),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ConstraintFlags(u8);
 impl From<u8> for ConstraintFlags {
     fn from(v: u8) -> Self {
@@ -140,11 +385,22 @@ impl From<ConstraintFlags> for u8 {
     }
 }
 impl ConstraintFlags {
+    /// `constraint_set0_flag`: the stream obeys the Baseline profile constraints (_A.2.1_).
     pub fn flag0(self) -> bool { self.0 & 0b1000_0000 != 0 }
+    /// `constraint_set1_flag`: the stream obeys the Main profile constraints (_A.2.2_); with
+    /// Baseline, this signals Constrained Baseline.
     pub fn flag1(self) -> bool { self.0 & 0b0100_0000 != 0 }
+    /// `constraint_set2_flag`: the stream obeys the Extended profile constraints (_A.2.3_).
     pub fn flag2(self) -> bool { self.0 & 0b0010_0000 != 0 }
+    /// `constraint_set3_flag`: with Baseline, Main or Extended and `level_idc` 11, this signals
+    /// level 1b; with the High 10, High 4:2:2, High 4:4:4 and Scalable High profiles it signals
+    /// the Intra variant.
     pub fn flag3(self) -> bool { self.0 & 0b0001_0000 != 0 }
+    /// `constraint_set4_flag`: with the Main, High and High 10 profiles, `frame_mbs_only_flag`
+    /// is 1 (eg Progressive High).  Has other meanings for the Multiview profiles.
     pub fn flag4(self) -> bool { self.0 & 0b0000_1000 != 0 }
+    /// `constraint_set5_flag`: with the Main and High profiles there are no B slices (eg
+    /// Constrained High).  Has other meanings for the Scalable and Multiview profiles.
     pub fn flag5(self) -> bool { self.0 & 0b0000_0100 != 0 }
     pub fn reserved_zero_two_bits(self) -> u8 { self.0 & 0b0000_0011 }
 }
@@ -162,8 +418,9 @@ impl Debug for ConstraintFlags {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[allow(non_camel_case_types)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Level {
     Unknown(u8),
     L1,
@@ -183,13 +440,31 @@ pub enum Level {
     L5,
     L5_1,
     L5_2,
+    L6,
+    L6_1,
+    L6_2,
 }
 impl Level {
+    /// The level, treating `level_idc` `11` with `constraint_set3_flag` as level 1b whatever the
+    /// profile.  That's only correct for the Baseline, Main and Extended profiles; prefer
+    /// `from_profile_idc_constraint_flags_and_level_idc`.
     pub fn from_constraint_flags_and_level_idc(constraint_flags: ConstraintFlags, level_idc: u8) -> Level {
+        Level::from_profile_idc_constraint_flags_and_level_idc(ProfileIdc(66), constraint_flags, level_idc)
+    }
+    /// The level, for which `level_idc` `11` with `constraint_set3_flag` means level 1b only
+    /// with the Baseline, Main and Extended profiles (_A.3.1_, _A.3.2_); other profiles use the
+    /// flag to signal an Intra profile, and signal level 1b with `level_idc` `9`.
+    pub fn from_profile_idc_constraint_flags_and_level_idc(
+        profile_idc: ProfileIdc,
+        constraint_flags: ConstraintFlags,
+        level_idc: u8,
+    ) -> Level {
         match level_idc {
+            // level 1b of profiles other than Baseline, Main and Extended
+            9 => Level::L1_b,
             10 => Level::L1,
             11 => {
-                if constraint_flags.flag3() {
+                if constraint_flags.flag3() && matches!(profile_idc.0, 66 | 77 | 88) {
                     Level::L1_b
                 } else {
                     Level::L1_1
@@ -209,6 +484,9 @@ impl Level {
             50 => Level::L5,
             51 => Level::L5_1,
             52 => Level::L5_2,
+            60 => Level::L6,
+            61 => Level::L6_1,
+            62 => Level::L6_2,
             _  => Level::Unknown(level_idc)
         }
     }
@@ -230,12 +508,209 @@ impl Level {
             Level::L5    => 50,
             Level::L5_1  => 51,
             Level::L5_2  => 52,
+            Level::L6    => 60,
+            Level::L6_1  => 61,
+            Level::L6_2  => 62,
             Level::Unknown(level_idc) => level_idc,
         }
     }
+
+    /// The position of this level in increasing order of capability, or `None` for
+    /// `Level::Unknown`.
+    fn ordinal(&self) -> Option<u8> {
+        Some(match *self {
+            Level::L1   => 0,
+            Level::L1_b => 1,
+            Level::L1_1 => 2,
+            Level::L1_2 => 3,
+            Level::L1_3 => 4,
+            Level::L2   => 5,
+            Level::L2_1 => 6,
+            Level::L2_2 => 7,
+            Level::L3   => 8,
+            Level::L3_1 => 9,
+            Level::L3_2 => 10,
+            Level::L4   => 11,
+            Level::L4_1 => 12,
+            Level::L4_2 => 13,
+            Level::L5   => 14,
+            Level::L5_1 => 15,
+            Level::L5_2 => 16,
+            Level::L6   => 17,
+            Level::L6_1 => 18,
+            Level::L6_2 => 19,
+            Level::Unknown(_) => return None,
+        })
+    }
+
+    /// The limits of _Table A-1_ for this level, or `None` for `Level::Unknown`.
+    pub fn limits(&self) -> Option<LevelLimits> {
+        let (max_mbps, max_fs, max_dpb_mbs, max_br, max_cpb) = match *self {
+            Level::L1   => (1485, 99, 396, 64, 175),
+            Level::L1_b => (1485, 99, 396, 128, 350),
+            Level::L1_1 => (3000, 396, 900, 192, 500),
+            Level::L1_2 => (6000, 396, 2376, 384, 1000),
+            Level::L1_3 => (11880, 396, 2376, 768, 2000),
+            Level::L2   => (11880, 396, 2376, 2000, 2000),
+            Level::L2_1 => (19800, 792, 4752, 4000, 4000),
+            Level::L2_2 => (20250, 1620, 8100, 4000, 4000),
+            Level::L3   => (40500, 1620, 8100, 10000, 10000),
+            Level::L3_1 => (108000, 3600, 18000, 14000, 14000),
+            Level::L3_2 => (216000, 5120, 20480, 20000, 20000),
+            Level::L4   => (245760, 8192, 32768, 20000, 25000),
+            Level::L4_1 => (245760, 8192, 32768, 50000, 62500),
+            Level::L4_2 => (522240, 8704, 34816, 50000, 62500),
+            Level::L5   => (589824, 22080, 110400, 135000, 135000),
+            Level::L5_1 => (983040, 36864, 184320, 240000, 240000),
+            Level::L5_2 => (2073600, 36864, 184320, 240000, 240000),
+            Level::L6   => (4177920, 139264, 696320, 240000, 240000),
+            Level::L6_1 => (8355840, 139264, 696320, 480000, 480000),
+            Level::L6_2 => (16711680, 139264, 696320, 800000, 800000),
+            Level::Unknown(_) => return None,
+        };
+        Some(LevelLimits { max_mbps, max_fs, max_dpb_mbs, max_br, max_cpb })
+    }
+
+    /// Checks the given SPS against the limits of this level, returning every limit exceeded.
+    ///
+    /// The macroblock processing rate can only be checked when the frame rate is known; callers
+    /// may supply `sps.fps().map(|f| f.as_f64())` or a rate measured from the stream.  The bit
+    /// rate and CPB size are checked for each CPB specification of the VUI HRD parameters.
+    pub fn check(&self, sps: &SeqParameterSet, fps: Option<f64>) -> Result<(), LevelError> {
+        let limits = self.limits().ok_or_else(|| LevelError::UnknownLevel(self.level_idc()))?;
+        let mut violations = Vec::new();
+        let mut check = |limit, actual: u64, max: u64| {
+            if actual > max {
+                violations.push(LevelViolation { limit, actual, max });
+            }
+        };
+        let width_mbs = sps.pic_width_in_mbs();
+        let height_mbs = sps.frame_height_in_mbs();
+        let frame_size = sps.frame_size_in_mbs();
+        let max_fs = u64::from(limits.max_fs);
+        check(LevelLimit::MaxFs, frame_size, max_fs);
+        // equations A-1 and A-2, comparing squares rather than taking Sqrt( MaxFS * 8 ).
+        check(LevelLimit::FrameWidth, width_mbs * width_mbs, max_fs * 8);
+        check(LevelLimit::FrameHeight, height_mbs * height_mbs, max_fs * 8);
+        if let Some(fps) = fps {
+            let mbps = (frame_size as f64 * fps).ceil() as u64;
+            check(LevelLimit::MaxMbps, mbps, u64::from(limits.max_mbps));
+        }
+        let dpb_frames = sps.bitstream_restrictions()
+            .map(|r| r.max_dec_frame_buffering)
+            .unwrap_or(sps.max_num_ref_frames);
+        check(LevelLimit::MaxDpbFrames, u64::from(dpb_frames), u64::from(limits.max_dpb_frames(frame_size)));
+        if let Some(ref vui) = sps.vui_parameters {
+            let vcl_factor = limits.cpb_br_vcl_factor(sps.profile_idc);
+            let hrds = [
+                (&vui.vcl_hrd_parameters, vcl_factor),
+                (&vui.nal_hrd_parameters, vcl_factor * 6 / 5),
+            ];
+            for (hrd, factor) in hrds.iter() {
+                if let Some(hrd) = hrd {
+                    for idx in 0..hrd.cpb_cnt() {
+                        check(LevelLimit::MaxBr, hrd.bit_rate(idx).unwrap(), u64::from(limits.max_br) * factor);
+                        check(LevelLimit::MaxCpb, hrd.cpb_size(idx).unwrap(), u64::from(limits.max_cpb) * factor);
+                    }
+                }
+            }
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(LevelError::LimitsExceeded(violations))
+        }
+    }
+}
+
+/// The per-level limits of _Table A-1_.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LevelLimits {
+    /// Max macroblock processing rate _MaxMBPS_ (MB/s)
+    pub max_mbps: u32,
+    /// Max frame size _MaxFS_ (MBs)
+    pub max_fs: u32,
+    /// Max decoded picture buffer size _MaxDpbMbs_ (MBs)
+    pub max_dpb_mbs: u32,
+    /// Max video bit rate _MaxBR_, in units of `cpbBrVclFactor` bits/s for the VCL HRD
+    pub max_br: u32,
+    /// Max CPB size _MaxCPB_, in units of `cpbBrVclFactor` bits for the VCL HRD
+    pub max_cpb: u32,
+}
+impl LevelLimits {
+    /// _MaxDpbFrames_ (per _A.3.1_ item h): the number of frames of the given size in
+    /// macroblocks which fit in _MaxDpbMbs_, up to 16.
+    pub fn max_dpb_frames(&self, frame_size_in_mbs: u64) -> u32 {
+        std::cmp::min(u64::from(self.max_dpb_mbs) / std::cmp::max(frame_size_in_mbs, 1), 16) as u32
+    }
+
+    /// _cpbBrVclFactor_ of _Table A-2_ for the given profile; the _cpbBrNalFactor_ is 1.2 times
+    /// this.
+    pub fn cpb_br_vcl_factor(&self, profile_idc: ProfileIdc) -> u64 {
+        match profile_idc.0 {
+            100 => 1250,
+            110 => 3000,
+            122 | 244 | 44 => 4000,
+            _ => 1000,
+        }
+    }
+}
+
+/// A limit checked by `Level::check()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LevelLimit {
+    /// the frame size in macroblocks
+    MaxFs,
+    /// the square of the frame width in macroblocks, against `8 * MaxFS`
+    FrameWidth,
+    /// the square of the frame height in macroblocks, against `8 * MaxFS`
+    FrameHeight,
+    /// the macroblock processing rate, in macroblocks per second
+    MaxMbps,
+    /// `max_dec_frame_buffering` (or `max_num_ref_frames`), against _MaxDpbFrames_
+    MaxDpbFrames,
+    /// the HRD bit rate, in bits per second
+    MaxBr,
+    /// the HRD CPB size, in bits
+    MaxCpb,
+}
+
+/// A level limit exceeded by a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LevelViolation {
+    pub limit: LevelLimit,
+    pub actual: u64,
+    pub max: u64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LevelError {
+    /// The level has no known limits.
+    UnknownLevel(u8),
+    /// The stream exceeds the given limits of the level.
+    LimitsExceeded(Vec<LevelViolation>),
 }
 
-#[derive(Debug,PartialEq,Clone,Copy)]
+/// A reason a decoder may be unable to decode a stream; see [SeqParameterSet::decodable_by].
+#[derive(Debug, PartialEq)]
+pub enum Incompatibility {
+    /// The decoder's profile (given as its `profile_idc`) is unknown.
+    UnknownProfile(u8),
+    /// The stream's `profile_idc` and constraint flags, giving the stream's profile, don't
+    /// indicate conformance to any profile the decoder must support.
+    Profile(Profile),
+    /// The SPS violates a constraint of the decoder's profile.
+    ProfileConstraint(ProfileViolation),
+    /// The decoder's level (given as its `level_idc`) is unknown.
+    UnknownLevel(u8),
+    /// The stream's level is higher than the decoder's.
+    Level(Level),
+    /// The SPS exceeds a limit of the decoder's level.
+    LevelLimit(LevelViolation),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ChromaFormat {
     Monochrome,
     YUV420,
@@ -253,15 +728,25 @@ impl ChromaFormat {
             _ => ChromaFormat::Invalid(chroma_format_idc)
         }
     }
+    fn chroma_format_idc(self) -> u32 {
+        match self {
+            ChromaFormat::Monochrome => 0,
+            ChromaFormat::YUV420 => 1,
+            ChromaFormat::YUV422 => 2,
+            ChromaFormat::YUV444 => 3,
+            ChromaFormat::Invalid(chroma_format_idc) => chroma_format_idc,
+        }
+    }
 }
 
 // _Profile Indication_ value
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ProfileIdc(u8);
 impl ProfileIdc {
     pub fn has_chroma_info(self) -> bool {
         match self.0 {
-            100 | 110 | 122 | 244 | 44 | 83 | 86 => true,
+            100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 134 | 135 | 138 | 139 => true,
             _ => false,
         }
     }
@@ -275,15 +760,40 @@ impl From<ProfileIdc> for u8 {
     fn from(v: ProfileIdc) -> Self { v.0 }
 }
 
-pub struct ScalingList {
-    // TODO
+/// The `Default_4x4_Intra` scaling list of _Table 7-3_, in zig-zag scan order.
+pub const DEFAULT_4X4_INTRA: [u8; 16] = [6, 13, 13, 20, 20, 20, 28, 28, 28, 28, 32, 32, 32, 37, 37, 42];
+/// The `Default_4x4_Inter` scaling list of _Table 7-3_, in zig-zag scan order.
+pub const DEFAULT_4X4_INTER: [u8; 16] = [10, 14, 14, 20, 20, 20, 24, 24, 24, 24, 27, 27, 27, 30, 30, 34];
+/// The `Default_8x8_Intra` scaling list of _Table 7-4_, in 8x8 zig-zag scan order.
+pub const DEFAULT_8X8_INTRA: [u8; 64] = [
+     6, 10, 10, 13, 11, 13, 16, 16, 16, 16, 18, 18, 18, 18, 18, 23,
+    23, 23, 23, 23, 23, 25, 25, 25, 25, 25, 25, 25, 27, 27, 27, 27,
+    27, 27, 27, 27, 29, 29, 29, 29, 29, 29, 29, 31, 31, 31, 31, 31,
+    31, 33, 33, 33, 33, 33, 36, 36, 36, 36, 38, 38, 38, 40, 40, 42,
+];
+/// The `Default_8x8_Inter` scaling list of _Table 7-4_, in 8x8 zig-zag scan order.
+pub const DEFAULT_8X8_INTER: [u8; 64] = [
+     9, 13, 13, 15, 13, 15, 17, 17, 17, 17, 19, 19, 19, 19, 19, 21,
+    21, 21, 21, 21, 21, 22, 22, 22, 22, 22, 22, 22, 24, 24, 24, 24,
+    24, 24, 24, 24, 25, 25, 25, 25, 25, 25, 25, 27, 27, 27, 27, 27,
+    27, 28, 28, 28, 28, 28, 30, 30, 30, 30, 32, 32, 32, 33, 33, 35,
+];
+
+/// The outcome of reading one `scaling_list()` syntax structure.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ScalingList {
+    /// `useDefaultScalingMatrixFlag` was set, so the appropriate table from _Table 7-3_ or
+    /// _Table 7-4_ applies.
+    UseDefault,
+    /// The 16 or 64 values of the list, in zig-zag scan order.
+    List(Vec<u8>),
 }
 impl ScalingList {
-    pub fn read(r: &mut RbspBitReader<'_>, size: u8) -> Result<ScalingList,ScalingMatrixError> {
-        let mut scaling_list = vec!();
+    pub fn read<R: BitRead + ?Sized>(r: &mut R, size: u8) -> Result<ScalingList,ScalingMatrixError> {
+        let mut scaling_list = Vec::with_capacity(usize::from(size));
         let mut last_scale = 8;
         let mut next_scale = 8;
-        let mut _use_default_scaling_matrix_flag = false;
         for j in 0..size {
             if next_scale != 0 {
                 let delta_scale = r.read_se_named("delta_scale")?;
@@ -291,13 +801,43 @@ impl ScalingList {
                     return Err(ScalingMatrixError::DeltaScaleOutOfRange(delta_scale));
                 }
                 next_scale = (last_scale + delta_scale + 256) % 256;
-                _use_default_scaling_matrix_flag = j == 0 && next_scale == 0;
+                if j == 0 && next_scale == 0 {
+                    return Ok(ScalingList::UseDefault);
+                }
             }
             let new_value = if next_scale == 0 { last_scale } else { next_scale };
-            scaling_list.push(new_value);
+            scaling_list.push(new_value as u8);
             last_scale = new_value;
         }
-        Ok(ScalingList { })
+        Ok(ScalingList::List(scaling_list))
+    }
+
+    pub fn write<W: BitWrite + ?Sized>(&self, w: &mut W) -> Result<(), BitWriterError> {
+        let list = match self {
+            // a delta_scale giving nextScale of 0 for the first entry
+            ScalingList::UseDefault => return w.write_se_named("delta_scale", -8),
+            ScalingList::List(list) => list,
+        };
+        let mut last_scale = 8;
+        for (j, &value) in list.iter().enumerate() {
+            let value = i32::from(value);
+            // a nextScale of 0 repeats lastScale for the rest of the list.
+            let next_scale = if j > 0 && list[j..].iter().all(|&v| i32::from(v) == last_scale) {
+                0
+            } else {
+                value
+            };
+            let mut delta_scale = (next_scale - last_scale + 256) % 256;
+            if delta_scale > 127 {
+                delta_scale -= 256;
+            }
+            w.write_se_named("delta_scale", delta_scale)?;
+            if next_scale == 0 {
+                break;
+            }
+            last_scale = value;
+        }
+        Ok(())
     }
 }
 
@@ -314,56 +854,182 @@ impl From<RbspBitReaderError> for ScalingMatrixError {
     }
 }
 
-#[derive(Debug, Clone)]
+/// The scaling matrices derived from `seq_scaling_matrix_present_flag` and the SPS's
+/// `scaling_list()` structures, per _7.4.2.1.1_.
+///
+/// When no matrix is present, all lists are `Flat_4x4_16` / `Flat_8x8_16` (every entry `16`).
+/// Lists which are not present, or which signal `useDefaultScalingMatrixFlag`, are derived by
+/// _fall-back rule set A_ of _Table 7-2_.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SeqScalingMatrix {
-    // TODO
+    /// `ScalingList4x4[i]`, each in zig-zag scan order, for `i` being Intra Y, Intra Cb,
+    /// Intra Cr, Inter Y, Inter Cb and Inter Cr respectively.
+    pub scaling_list4x4: [[u8; 16]; 6],
+    /// `ScalingList8x8[i]`, each in 8x8 zig-zag scan order, for `i` being Intra Y, Inter Y,
+    /// Intra Cb, Inter Cb, Intra Cr and Inter Cr respectively.  Only the first two are signalled
+    /// unless `chroma_format_idc` is `3`; the chroma lists are nonetheless derived by the
+    /// fall-back rule.
+    #[cfg_attr(feature = "serde", serde(with = "serde_scaling_list8x8"))]
+    pub scaling_list8x8: [[u8; 64]; 6],
 }
 impl Default for SeqScalingMatrix {
     fn default() -> Self {
-        SeqScalingMatrix { }
+        SeqScalingMatrix {
+            scaling_list4x4: [[16; 16]; 6],
+            scaling_list8x8: [[16; 64]; 6],
+        }
     }
 }
-impl SeqScalingMatrix {
-    fn read(r: &mut RbspBitReader<'_>, chroma_format_idc: u32) -> Result<SeqScalingMatrix,ScalingMatrixError> {
-        let mut scaling_list4x4 = vec!();
-        let mut scaling_list8x8 = vec!();
 
+/// serde doesn't support arrays longer than 32 elements, so `scaling_list8x8` goes through a
+/// sequence of slices.
+#[cfg(feature = "serde")]
+mod serde_scaling_list8x8 {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(lists: &[[u8; 64]; 6], s: S) -> Result<S::Ok, S::Error> {
+        s.collect_seq(lists.iter().map(|l| &l[..]))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<[[u8; 64]; 6], D::Error> {
+        let lists = Vec::<Vec<u8>>::deserialize(d)?;
+        if lists.len() != 6 || lists.iter().any(|l| l.len() != 64) {
+            return Err(serde::de::Error::custom("expected 6 scaling lists of 64 entries"));
+        }
+        let mut out = [[0; 64]; 6];
+        for (o, l) in out.iter_mut().zip(&lists) {
+            o.copy_from_slice(l);
+        }
+        Ok(out)
+    }
+}
+impl SeqScalingMatrix {
+    fn read<R: BitRead + ?Sized>(r: &mut R, chroma_format_idc: u32) -> Result<SeqScalingMatrix,ScalingMatrixError> {
         let count = if chroma_format_idc == 3 { 12 } else { 8 };
+        Self::read_lists(r, count, None)
+    }
+
+    /// Reads `count` optional scaling lists (the first 6 being 4x4 and the remainder 8x8),
+    /// applying fall-back rule set A, or rule set B if `fall_back` gives the SPS's matrix.
+    pub(crate) fn read_lists<R: BitRead + ?Sized>(r: &mut R, count: usize, fall_back: Option<&SeqScalingMatrix>) -> Result<SeqScalingMatrix,ScalingMatrixError> {
+        let mut m = SeqScalingMatrix::default();
+        for i in 0..count.max(8) {
+            let list = if i < count && r.read_bool_named("scaling_list_present_flag")? {
+                Some(ScalingList::read(r, if i < 6 { 16 } else { 64 })?)
+            } else {
+                None
+            };
+            if i < 6 {
+                m.scaling_list4x4[i] = match list {
+                    Some(ScalingList::List(l)) => {
+                        let mut a = [0; 16];
+                        a.copy_from_slice(&l);
+                        a
+                    },
+                    Some(ScalingList::UseDefault) if i < 3 => DEFAULT_4X4_INTRA,
+                    Some(ScalingList::UseDefault) => DEFAULT_4X4_INTER,
+                    None if i == 1 || i == 2 || i == 4 || i == 5 => m.scaling_list4x4[i - 1],
+                    None => match fall_back {
+                        Some(sps) => sps.scaling_list4x4[i],
+                        None if i == 0 => DEFAULT_4X4_INTRA,
+                        None => DEFAULT_4X4_INTER,
+                    },
+                };
+            } else {
+                let k = i - 6;
+                m.scaling_list8x8[k] = match list {
+                    Some(ScalingList::List(l)) => {
+                        let mut a = [0; 64];
+                        a.copy_from_slice(&l);
+                        a
+                    },
+                    Some(ScalingList::UseDefault) if k % 2 == 0 => DEFAULT_8X8_INTRA,
+                    Some(ScalingList::UseDefault) => DEFAULT_8X8_INTER,
+                    None if k >= 2 => m.scaling_list8x8[k - 2],
+                    None => match fall_back {
+                        Some(sps) => sps.scaling_list8x8[k],
+                        None if k == 0 => DEFAULT_8X8_INTRA,
+                        None => DEFAULT_8X8_INTER,
+                    },
+                };
+            }
+        }
+        // chroma 8x8 lists which aren't signalled at all follow the same fall-back
+        for k in count.max(8) - 6..6 {
+            m.scaling_list8x8[k] = m.scaling_list8x8[k - 2];
+        }
+        Ok(m)
+    }
+
+    /// Writes `count` optional scaling lists, the inverse of `read_lists`.  Each list is omitted
+    /// if fall-back rule set A (or B, with `fall_back`) would derive it, signalled as
+    /// `useDefaultScalingMatrixFlag` if it is the default list, and otherwise written in full.
+    pub(crate) fn write_lists<W: BitWrite + ?Sized>(&self, w: &mut W, count: usize, fall_back: Option<&SeqScalingMatrix>) -> Result<(), BitWriterError> {
         for i in 0..count {
-            let seq_scaling_list_present_flag = r.read_bool()?;
-            if seq_scaling_list_present_flag {
-                if i < 6 {
-                    scaling_list4x4.push(ScalingList::read(r, 16)?);
+            let (list, default, derived): (&[u8], &[u8], &[u8]) = if i < 6 {
+                let default = if i < 3 { &DEFAULT_4X4_INTRA } else { &DEFAULT_4X4_INTER };
+                let derived = match (i, fall_back) {
+                    (1, _) | (2, _) | (4, _) | (5, _) => &self.scaling_list4x4[i - 1],
+                    (_, Some(sps)) => &sps.scaling_list4x4[i],
+                    (_, None) => default,
+                };
+                (&self.scaling_list4x4[i], default, derived)
+            } else {
+                let k = i - 6;
+                let default = if k % 2 == 0 { &DEFAULT_8X8_INTRA } else { &DEFAULT_8X8_INTER };
+                let derived = match (k, fall_back) {
+                    (0, Some(sps)) | (1, Some(sps)) => &sps.scaling_list8x8[k],
+                    (0, None) | (1, None) => default,
+                    _ => &self.scaling_list8x8[k - 2],
+                };
+                (&self.scaling_list8x8[k], default, derived)
+            };
+            let present = list != derived;
+            w.write_bool_named("scaling_list_present_flag", present)?;
+            if present {
+                if list == default {
+                    ScalingList::UseDefault.write(w)?;
                 } else {
-                    scaling_list8x8.push(ScalingList::read(r, 64)?);
+                    ScalingList::List(list.to_vec()).write(w)?;
                 }
             }
         }
-        Ok(SeqScalingMatrix { })
+        Ok(())
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ChromaInfo {
     pub chroma_format: ChromaFormat,
     pub separate_colour_plane_flag: bool,
     pub bit_depth_luma_minus8: u8,
     pub bit_depth_chroma_minus8: u8,
     pub qpprime_y_zero_transform_bypass_flag: bool,
+    /// Whether the SPS signals its own `scaling_matrix`.  This selects _fall-back rule set B_
+    /// for a PPS scaling matrix, even if the signalled lists happen to be flat.
+    pub seq_scaling_matrix_present_flag: bool,
+    /// The SPS's scaling matrix; flat unless `seq_scaling_matrix_present_flag` is set.
     pub scaling_matrix: SeqScalingMatrix,
 }
 impl ChromaInfo {
-    pub fn read(r: &mut RbspBitReader<'_>, profile_idc: ProfileIdc) -> Result<ChromaInfo, SpsError> {
+    pub fn read<R: BitRead + ?Sized>(r: &mut R, profile_idc: ProfileIdc) -> Result<ChromaInfo, SpsError> {
         if profile_idc.has_chroma_info() {
             let chroma_format_idc = r.read_ue_named("chroma_format_idc")?;
-            Ok(ChromaInfo {
+            let mut info = ChromaInfo {
                 chroma_format: ChromaFormat::from_chroma_format_idc(chroma_format_idc),
                 separate_colour_plane_flag: if chroma_format_idc == 3 { r.read_bool()? } else { false },
                 bit_depth_luma_minus8: Self::read_bit_depth_minus8(r)?,
                 bit_depth_chroma_minus8: Self::read_bit_depth_minus8(r)?,
                 qpprime_y_zero_transform_bypass_flag: r.read_bool()?,
-                scaling_matrix: Self::read_scaling_matrix(r, chroma_format_idc)?
-            })
+                seq_scaling_matrix_present_flag: r.read_bool_named("seq_scaling_matrix_present_flag")?,
+                scaling_matrix: SeqScalingMatrix::default(),
+            };
+            if info.seq_scaling_matrix_present_flag {
+                info.scaling_matrix = SeqScalingMatrix::read(r, chroma_format_idc).map_err(SpsError::ScalingMatrix)?;
+            }
+            Ok(info)
         } else {
             Ok(ChromaInfo {
                 chroma_format: ChromaFormat::YUV420,
@@ -371,11 +1037,33 @@ impl ChromaInfo {
                 bit_depth_luma_minus8: 0,
                 bit_depth_chroma_minus8: 0,
                 qpprime_y_zero_transform_bypass_flag: false,
+                seq_scaling_matrix_present_flag: false,
                 scaling_matrix: SeqScalingMatrix::default(),
             })
         }
     }
-    fn read_bit_depth_minus8(r: &mut RbspBitReader<'_>) -> Result<u8, SpsError> {
+    /// Writes the chroma fields, which are only present for profiles where
+    /// `ProfileIdc::has_chroma_info` is true.
+    fn write<W: BitWrite + ?Sized>(&self, w: &mut W, profile_idc: ProfileIdc) -> Result<(), BitWriterError> {
+        if !profile_idc.has_chroma_info() {
+            return Ok(());
+        }
+        let chroma_format_idc = self.chroma_format.chroma_format_idc();
+        w.write_ue_named("chroma_format_idc", chroma_format_idc)?;
+        if chroma_format_idc == 3 {
+            w.write_bool_named("separate_colour_plane_flag", self.separate_colour_plane_flag)?;
+        }
+        w.write_ue_named("bit_depth_luma_minus8", u32::from(self.bit_depth_luma_minus8))?;
+        w.write_ue_named("bit_depth_chroma_minus8", u32::from(self.bit_depth_chroma_minus8))?;
+        w.write_bool_named("qpprime_y_zero_transform_bypass_flag", self.qpprime_y_zero_transform_bypass_flag)?;
+        w.write_bool_named("seq_scaling_matrix_present_flag", self.seq_scaling_matrix_present_flag)?;
+        if self.seq_scaling_matrix_present_flag {
+            let count = if chroma_format_idc == 3 { 12 } else { 8 };
+            self.scaling_matrix.write_lists(w, count, None)?;
+        }
+        Ok(())
+    }
+    fn read_bit_depth_minus8<R: BitRead + ?Sized>(r: &mut R) -> Result<u8, SpsError> {
         let value = r.read_ue_named("read_bit_depth_minus8")?;
         if value > 6 {
             Err(SpsError::BitDepthOutOfRange(value))
@@ -383,14 +1071,6 @@ impl ChromaInfo {
             Ok(value as u8)
         }
     }
-    fn read_scaling_matrix(r: &mut RbspBitReader<'_>, chroma_format_idc: u32) -> Result<SeqScalingMatrix, SpsError> {
-        let scaling_matrix_present_flag = r.read_bool()?;
-        if scaling_matrix_present_flag {
-            SeqScalingMatrix::read(r, chroma_format_idc).map_err(SpsError::ScalingMatrix)
-        } else {
-            Ok(SeqScalingMatrix::default())
-        }
-    }
 }
 
 #[derive(Debug)]
@@ -409,7 +1089,8 @@ impl From<RbspBitReaderError> for PicOrderCntError {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PicOrderCntType {
     TypeZero {
         log2_max_pic_order_cnt_lsb_minus4: u8
@@ -423,7 +1104,7 @@ pub enum PicOrderCntType {
     TypeTwo
 }
 impl PicOrderCntType {
-    fn read(r: &mut RbspBitReader<'_>) -> Result<PicOrderCntType, PicOrderCntError> {
+    fn read<R: BitRead + ?Sized>(r: &mut R) -> Result<PicOrderCntType, PicOrderCntError> {
         let pic_order_cnt_type = r.read_ue_named("pic_order_cnt_type")?;
         match pic_order_cnt_type {
             0 => {
@@ -448,7 +1129,39 @@ impl PicOrderCntType {
         }
     }
 
-    fn read_log2_max_pic_order_cnt_lsb_minus4(r: &mut RbspBitReader<'_>) -> Result<u8, PicOrderCntError> {
+    fn write<W: BitWrite + ?Sized>(&self, w: &mut W) -> Result<(), BitWriterError> {
+        match self {
+            PicOrderCntType::TypeZero { log2_max_pic_order_cnt_lsb_minus4 } => {
+                w.write_ue_named("pic_order_cnt_type", 0)?;
+                w.write_ue_named("log2_max_pic_order_cnt_lsb_minus4", u32::from(*log2_max_pic_order_cnt_lsb_minus4))
+            },
+            PicOrderCntType::TypeOne {
+                delta_pic_order_always_zero_flag,
+                offset_for_non_ref_pic,
+                offset_for_top_to_bottom_field,
+                offsets_for_ref_frame,
+            } => {
+                w.write_ue_named("pic_order_cnt_type", 1)?;
+                w.write_bool_named("delta_pic_order_always_zero_flag", *delta_pic_order_always_zero_flag)?;
+                w.write_se_named("offset_for_non_ref_pic", *offset_for_non_ref_pic)?;
+                w.write_se_named("offset_for_top_to_bottom_field", *offset_for_top_to_bottom_field)?;
+                if offsets_for_ref_frame.len() > 255 {
+                    return Err(BitWriterError::ValueOutOfRange {
+                        name: "num_ref_frames_in_pic_order_cnt_cycle",
+                        value: offsets_for_ref_frame.len() as i64,
+                    });
+                }
+                w.write_ue_named("num_ref_frames_in_pic_order_cnt_cycle", offsets_for_ref_frame.len() as u32)?;
+                for &offset in offsets_for_ref_frame {
+                    w.write_se_named("offset_for_ref_frame", offset)?;
+                }
+                Ok(())
+            },
+            PicOrderCntType::TypeTwo => w.write_ue_named("pic_order_cnt_type", 2),
+        }
+    }
+
+    fn read_log2_max_pic_order_cnt_lsb_minus4<R: BitRead + ?Sized>(r: &mut R) -> Result<u8, PicOrderCntError> {
         let val = r.read_ue_named("log2_max_pic_order_cnt_lsb_minus4")?;
         if val > 12 {
             Err(PicOrderCntError::Log2MaxPicOrderCntLsbMinus4OutOfRange(val))
@@ -457,7 +1170,7 @@ impl PicOrderCntType {
         }
     }
 
-    fn read_offsets_for_ref_frame(r: &mut RbspBitReader<'_>) -> Result<Vec<i32>, PicOrderCntError> {
+    fn read_offsets_for_ref_frame<R: BitRead + ?Sized>(r: &mut R) -> Result<Vec<i32>, PicOrderCntError> {
         let num_ref_frames_in_pic_order_cnt_cycle = r.read_ue_named("num_ref_frames_in_pic_order_cnt_cycle")?;
         if num_ref_frames_in_pic_order_cnt_cycle > 255 {
             return Err(PicOrderCntError::NumRefFramesInPicOrderCntCycleOutOfRange(num_ref_frames_in_pic_order_cnt_cycle));
@@ -468,9 +1181,24 @@ impl PicOrderCntType {
         }
         Ok(offsets)
     }
+
+    /// _ExpectedDeltaPerPicOrderCntCycle_ (_equation 7-12_): the sum of `offsets_for_ref_frame`,
+    /// by which the expected picture order count advances over each cycle of reference frames.
+    /// `None` unless `pic_order_cnt_type` is `1`.
+    ///
+    /// This is an `i64` because the sum of up to 255 `i32` offsets may not fit in an `i32`.
+    pub fn expected_delta_per_pic_order_cnt_cycle(&self) -> Option<i64> {
+        match self {
+            PicOrderCntType::TypeOne { offsets_for_ref_frame, .. } => {
+                Some(offsets_for_ref_frame.iter().map(|&o| i64::from(o)).sum())
+            },
+            _ => None,
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FrameMbsFlags {
     Frames,
     Fields {
@@ -478,7 +1206,7 @@ pub enum FrameMbsFlags {
     }
 }
 impl FrameMbsFlags {
-    fn read(r: &mut RbspBitReader<'_>) -> Result<FrameMbsFlags, RbspBitReaderError> {
+    fn read<R: BitRead + ?Sized>(r: &mut R) -> Result<FrameMbsFlags, RbspBitReaderError> {
         let frame_mbs_only_flag = r.read_bool()?;
         if frame_mbs_only_flag {
             Ok(FrameMbsFlags::Frames)
@@ -488,9 +1216,31 @@ impl FrameMbsFlags {
             })
         }
     }
+    /// `frame_mbs_only_flag`: true if every picture is a frame of frame-coded macroblocks.
+    pub fn frame_mbs_only_flag(&self) -> bool {
+        *self == FrameMbsFlags::Frames
+    }
+    /// `mb_adaptive_frame_field_flag`, which is inferred to be false when
+    /// `frame_mbs_only_flag` is set.
+    pub fn mb_adaptive_frame_field_flag(&self) -> bool {
+        match *self {
+            FrameMbsFlags::Frames => false,
+            FrameMbsFlags::Fields { mb_adaptive_frame_field_flag } => mb_adaptive_frame_field_flag,
+        }
+    }
+    fn write<W: BitWrite + ?Sized>(&self, w: &mut W) -> Result<(), BitWriterError> {
+        match self {
+            FrameMbsFlags::Frames => w.write_bool_named("frame_mbs_only_flag", true),
+            FrameMbsFlags::Fields { mb_adaptive_frame_field_flag } => {
+                w.write_bool_named("frame_mbs_only_flag", false)?;
+                w.write_bool_named("mb_adaptive_frame_field_flag", *mb_adaptive_frame_field_flag)
+            },
+        }
+    }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FrameCropping {
     pub left_offset: u32,
     pub right_offset: u32,
@@ -498,7 +1248,7 @@ pub struct FrameCropping {
     pub bottom_offset: u32,
 }
 impl FrameCropping {
-    fn read(r: &mut RbspBitReader<'_>) -> Result<Option<FrameCropping>, RbspBitReaderError> {
+    fn read<R: BitRead + ?Sized>(r: &mut R) -> Result<Option<FrameCropping>, RbspBitReaderError> {
         let frame_cropping_flag = r.read_bool()?;
         Ok(if frame_cropping_flag {
             Some(FrameCropping {
@@ -511,9 +1261,20 @@ impl FrameCropping {
             None
         })
     }
+    fn write<W: BitWrite + ?Sized>(this: Option<&Self>, w: &mut W) -> Result<(), BitWriterError> {
+        w.write_bool_named("frame_cropping_flag", this.is_some())?;
+        if let Some(c) = this {
+            w.write_ue_named("left_offset", c.left_offset)?;
+            w.write_ue_named("right_offset", c.right_offset)?;
+            w.write_ue_named("top_offset", c.top_offset)?;
+            w.write_ue_named("bottom_offset", c.bottom_offset)?;
+        }
+        Ok(())
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AspectRatioInfo {
     Unspecified,
     Ratio1_1,
@@ -537,36 +1298,83 @@ pub enum AspectRatioInfo {
 
 }
 impl AspectRatioInfo {
-    fn read(r: &mut RbspBitReader<'_>) -> Result<Option<AspectRatioInfo>, RbspBitReaderError> {
+    fn read<R: BitRead + ?Sized>(r: &mut R) -> Result<Option<AspectRatioInfo>, RbspBitReaderError> {
         let aspect_ratio_info_present_flag = r.read_bool()?;
         Ok(if aspect_ratio_info_present_flag {
             let aspect_ratio_idc = r.read_u8(8)?;
             Some(match aspect_ratio_idc {
-                0 => AspectRatioInfo::Unspecified,
-                1 => AspectRatioInfo::Ratio1_1,
-                2 => AspectRatioInfo::Ratio12_11,
-                3 => AspectRatioInfo::Ratio10_11,
-                4 => AspectRatioInfo::Ratio16_11,
-                5 => AspectRatioInfo::Ratio40_33,
-                6 => AspectRatioInfo::Ratio24_11,
-                7 => AspectRatioInfo::Ratio20_11,
-                8 => AspectRatioInfo::Ratio32_11,
-                9 => AspectRatioInfo::Ratio80_33,
-                10 => AspectRatioInfo::Ratio18_11,
-                11 => AspectRatioInfo::Ratio15_11,
-                12 => AspectRatioInfo::Ratio64_33,
-                13 => AspectRatioInfo::Ratio160_99,
-                14 => AspectRatioInfo::Ratio4_3,
-                15 => AspectRatioInfo::Ratio3_2,
-                16 => AspectRatioInfo::Ratio2_1,
                 255 => AspectRatioInfo::Extended(r.read_u16(16)?, r.read_u16(16)?),
-                _ => AspectRatioInfo::Reserved(aspect_ratio_idc),
+                _ => Self::from_aspect_ratio_idc(aspect_ratio_idc),
             })
         } else {
             None
         })
     }
 
+    /// The entry of _Table E-1_ for an `aspect_ratio_idc` other than `Extended_SAR` (255).
+    fn from_aspect_ratio_idc(aspect_ratio_idc: u8) -> AspectRatioInfo {
+        match aspect_ratio_idc {
+            0 => AspectRatioInfo::Unspecified,
+            1 => AspectRatioInfo::Ratio1_1,
+            2 => AspectRatioInfo::Ratio12_11,
+            3 => AspectRatioInfo::Ratio10_11,
+            4 => AspectRatioInfo::Ratio16_11,
+            5 => AspectRatioInfo::Ratio40_33,
+            6 => AspectRatioInfo::Ratio24_11,
+            7 => AspectRatioInfo::Ratio20_11,
+            8 => AspectRatioInfo::Ratio32_11,
+            9 => AspectRatioInfo::Ratio80_33,
+            10 => AspectRatioInfo::Ratio18_11,
+            11 => AspectRatioInfo::Ratio15_11,
+            12 => AspectRatioInfo::Ratio64_33,
+            13 => AspectRatioInfo::Ratio160_99,
+            14 => AspectRatioInfo::Ratio4_3,
+            15 => AspectRatioInfo::Ratio3_2,
+            16 => AspectRatioInfo::Ratio2_1,
+            _ => AspectRatioInfo::Reserved(aspect_ratio_idc),
+        }
+    }
+
+    /// The entry of _Table E-1_ with the given sample aspect ratio, or `Extended` if there is
+    /// none.
+    pub fn from_ratio(width: u16, height: u16) -> AspectRatioInfo {
+        (1..=16)
+            .map(Self::from_aspect_ratio_idc)
+            .find(|a| a.get() == Some((width, height)))
+            .unwrap_or(AspectRatioInfo::Extended(width, height))
+    }
+
+    fn write<W: BitWrite + ?Sized>(this: Option<&Self>, w: &mut W) -> Result<(), BitWriterError> {
+        w.write_bool_named("aspect_ratio_info_present_flag", this.is_some())?;
+        let aspect_ratio_idc = match this {
+            None => return Ok(()),
+            Some(AspectRatioInfo::Unspecified) => 0,
+            Some(AspectRatioInfo::Ratio1_1) => 1,
+            Some(AspectRatioInfo::Ratio12_11) => 2,
+            Some(AspectRatioInfo::Ratio10_11) => 3,
+            Some(AspectRatioInfo::Ratio16_11) => 4,
+            Some(AspectRatioInfo::Ratio40_33) => 5,
+            Some(AspectRatioInfo::Ratio24_11) => 6,
+            Some(AspectRatioInfo::Ratio20_11) => 7,
+            Some(AspectRatioInfo::Ratio32_11) => 8,
+            Some(AspectRatioInfo::Ratio80_33) => 9,
+            Some(AspectRatioInfo::Ratio18_11) => 10,
+            Some(AspectRatioInfo::Ratio15_11) => 11,
+            Some(AspectRatioInfo::Ratio64_33) => 12,
+            Some(AspectRatioInfo::Ratio160_99) => 13,
+            Some(AspectRatioInfo::Ratio4_3) => 14,
+            Some(AspectRatioInfo::Ratio3_2) => 15,
+            Some(AspectRatioInfo::Ratio2_1) => 16,
+            Some(&AspectRatioInfo::Reserved(aspect_ratio_idc)) => aspect_ratio_idc,
+            Some(&AspectRatioInfo::Extended(sar_width, sar_height)) => {
+                w.write_u_named("aspect_ratio_idc", 8, 255)?;
+                w.write_u_named("sar_width", 16, u32::from(sar_width))?;
+                return w.write_u_named("sar_height", 16, u32::from(sar_height));
+            },
+        };
+        w.write_u_named("aspect_ratio_idc", 8, u32::from(aspect_ratio_idc))
+    }
+
     /// Returns the aspect ratio as `(width, height)`, if specified.
     pub fn get(&self) -> Option<(u16, u16)> {
         match self {
@@ -602,14 +1410,24 @@ impl AspectRatioInfo {
     }
 }
 
-#[derive(Debug, Clone)]
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        let t = a % b;
+        a = b;
+        b = t;
+    }
+    a
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum OverscanAppropriate {
     Unspecified,
     Appropriate,
     Inappropriate,
 }
 impl OverscanAppropriate {
-    fn read(r: &mut RbspBitReader<'_>) -> Result<OverscanAppropriate, RbspBitReaderError> {
+    fn read<R: BitRead + ?Sized>(r: &mut R) -> Result<OverscanAppropriate, RbspBitReaderError> {
         let overscan_info_present_flag = r.read_bool()?;
         Ok(if overscan_info_present_flag {
             let overscan_appropriate_flag = r.read_bool()?;
@@ -622,9 +1440,19 @@ impl OverscanAppropriate {
             OverscanAppropriate::Unspecified
         })
     }
+    fn write<W: BitWrite + ?Sized>(&self, w: &mut W) -> Result<(), BitWriterError> {
+        match self {
+            OverscanAppropriate::Unspecified => w.write_bool_named("overscan_info_present_flag", false),
+            OverscanAppropriate::Appropriate | OverscanAppropriate::Inappropriate => {
+                w.write_bool_named("overscan_info_present_flag", true)?;
+                w.write_bool_named("overscan_appropriate_flag", matches!(self, OverscanAppropriate::Appropriate))
+            },
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum VideoFormat {
     Component,
     PAL,
@@ -647,16 +1475,175 @@ impl VideoFormat {
             _ => panic!("unsupported video_format value {}", video_format),
         }
     }
+    fn video_format(&self) -> u8 {
+        match *self {
+            VideoFormat::Component => 0,
+            VideoFormat::PAL => 1,
+            VideoFormat::NTSC => 2,
+            VideoFormat::SECAM => 3,
+            VideoFormat::MAC => 4,
+            VideoFormat::Unspecified => 5,
+            VideoFormat::Reserved(video_format) => video_format,
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+/// Defines an enum for one of the code points of _ISO/IEC 23091-2_ (_Tables E-3_ to _E-5_),
+/// with `From<u8>` and `as_u8`; values not named are held by the `Reserved` variant.
+macro_rules! code_point_enum {
+    (
+        $(#[$attr:meta])*
+        pub enum $name:ident {
+            $( $(#[$vattr:meta])* $variant:ident = $value:literal, )*
+        }
+    ) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+        pub enum $name {
+            $( $(#[$vattr])* $variant, )*
+            /// A value reserved for future use by ITU-T | ISO/IEC.
+            Reserved(u8),
+        }
+        impl From<u8> for $name {
+            fn from(value: u8) -> Self {
+                match value {
+                    $( $value => $name::$variant, )*
+                    _ => $name::Reserved(value),
+                }
+            }
+        }
+        impl $name {
+            /// The value as signalled in the bitstream.
+            pub fn as_u8(self) -> u8 {
+                match self {
+                    $( $name::$variant => $value, )*
+                    $name::Reserved(value) => value,
+                }
+            }
+        }
+    };
+}
+
+code_point_enum! {
+    /// `colour_primaries` (_Table E-3_): the chromaticity coordinates of the source primaries.
+    pub enum ColourPrimaries {
+        /// Rec. ITU-R BT.709, also sRGB.
+        Bt709 = 1,
+        Unspecified = 2,
+        /// Rec. ITU-R BT.470 System M.
+        Bt470M = 4,
+        /// Rec. ITU-R BT.470 System B, G (625-line PAL/SECAM), also BT.601 625.
+        Bt470Bg = 5,
+        /// SMPTE 170M (525-line NTSC), also BT.601 525.
+        Smpte170M = 6,
+        Smpte240M = 7,
+        /// Generic film (colour filters using Illuminant C).
+        Film = 8,
+        /// Rec. ITU-R BT.2020 and BT.2100.
+        Bt2020 = 9,
+        /// SMPTE ST 428-1 (CIE 1931 XYZ).
+        Smpte428 = 10,
+        /// SMPTE RP 431-2 (DCI-P3).
+        Smpte431 = 11,
+        /// SMPTE EG 432-1 (Display P3).
+        Smpte432 = 12,
+        /// EBU Tech. 3213-E.
+        Ebu3213 = 22,
+    }
+}
+
+code_point_enum! {
+    /// `transfer_characteristics` (_Table E-4_): the opto-electronic transfer characteristic of
+    /// the source picture.
+    pub enum TransferCharacteristics {
+        /// Rec. ITU-R BT.709.
+        Bt709 = 1,
+        Unspecified = 2,
+        /// Assumed display gamma 2.2 (Rec. ITU-R BT.470 System M).
+        Gamma22 = 4,
+        /// Assumed display gamma 2.8 (Rec. ITU-R BT.470 System B, G).
+        Gamma28 = 5,
+        /// SMPTE 170M, also BT.601.
+        Smpte170M = 6,
+        Smpte240M = 7,
+        Linear = 8,
+        /// Logarithmic, 100:1 range.
+        Log100 = 9,
+        /// Logarithmic, 100 * Sqrt( 10 ):1 range.
+        Log316 = 10,
+        /// IEC 61966-2-4 (xvYCC).
+        Iec61966_2_4 = 11,
+        /// Rec. ITU-R BT.1361 extended colour gamut.
+        Bt1361 = 12,
+        /// IEC 61966-2-1 (sRGB or sYCC).
+        Srgb = 13,
+        /// Rec. ITU-R BT.2020 for 10-bit systems.
+        Bt2020_10 = 14,
+        /// Rec. ITU-R BT.2020 for 12-bit systems.
+        Bt2020_12 = 15,
+        /// SMPTE ST 2084 perceptual quantizer (PQ), as used for HDR10.
+        Smpte2084 = 16,
+        /// SMPTE ST 428-1.
+        Smpte428 = 17,
+        /// ARIB STD-B67 hybrid log-gamma (HLG).
+        Hlg = 18,
+    }
+}
+
+code_point_enum! {
+    /// `matrix_coefficients` (_Table E-5_): how luma and chroma are derived from the red, green
+    /// and blue primaries.
+    pub enum MatrixCoefficients {
+        /// The identity matrix, i.e. GBR (as used with 4:4:4 RGB coding).
+        Identity = 0,
+        /// Rec. ITU-R BT.709.
+        Bt709 = 1,
+        Unspecified = 2,
+        /// United States Federal Communications Commission Title 47.
+        Fcc = 4,
+        /// Rec. ITU-R BT.470 System B, G, also BT.601 625.
+        Bt470Bg = 5,
+        /// SMPTE 170M, also BT.601 525.
+        Smpte170M = 6,
+        Smpte240M = 7,
+        YCgCo = 8,
+        /// Rec. ITU-R BT.2020 non-constant luminance.
+        Bt2020Ncl = 9,
+        /// Rec. ITU-R BT.2020 constant luminance.
+        Bt2020Cl = 10,
+        /// SMPTE ST 2085 (Y'D'zD'x).
+        Smpte2085 = 11,
+        /// Chromaticity-derived non-constant luminance.
+        ChromaticityNcl = 12,
+        /// Chromaticity-derived constant luminance.
+        ChromaticityCl = 13,
+        /// Rec. ITU-R BT.2100 ICtCp.
+        ICtCp = 14,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ColourDescription {
-    colour_primaries: u8,
-    transfer_characteristics: u8,
-    matrix_coefficients: u8,
+    pub colour_primaries: u8,
+    pub transfer_characteristics: u8,
+    pub matrix_coefficients: u8,
 }
 impl ColourDescription {
-    fn read(r: &mut RbspBitReader<'_>) -> Result<Option<ColourDescription>, RbspBitReaderError> {
+    /// `colour_primaries`, as an enum.
+    pub fn primaries(&self) -> ColourPrimaries {
+        ColourPrimaries::from(self.colour_primaries)
+    }
+    /// `transfer_characteristics`, as an enum.
+    pub fn transfer(&self) -> TransferCharacteristics {
+        TransferCharacteristics::from(self.transfer_characteristics)
+    }
+    /// `matrix_coefficients`, as an enum.
+    pub fn matrix(&self) -> MatrixCoefficients {
+        MatrixCoefficients::from(self.matrix_coefficients)
+    }
+    fn read<R: BitRead + ?Sized>(r: &mut R) -> Result<Option<ColourDescription>, RbspBitReaderError> {
         let colour_description_present_flag = r.read_bool()?;
         Ok(if colour_description_present_flag {
             Some(ColourDescription {
@@ -668,16 +1655,26 @@ impl ColourDescription {
             None
         })
     }
+    fn write<W: BitWrite + ?Sized>(this: Option<&Self>, w: &mut W) -> Result<(), BitWriterError> {
+        w.write_bool_named("colour_description_present_flag", this.is_some())?;
+        if let Some(c) = this {
+            w.write_u_named("colour_primaries", 8, u32::from(c.colour_primaries))?;
+            w.write_u_named("transfer_characteristics", 8, u32::from(c.transfer_characteristics))?;
+            w.write_u_named("matrix_coefficients", 8, u32::from(c.matrix_coefficients))?;
+        }
+        Ok(())
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct VideoSignalType {
-    video_format: VideoFormat,
-    video_full_range_flag: bool,
-    colour_description: Option<ColourDescription>,
+    pub video_format: VideoFormat,
+    pub video_full_range_flag: bool,
+    pub colour_description: Option<ColourDescription>,
 }
 impl VideoSignalType {
-    fn read(r: &mut RbspBitReader<'_>) -> Result<Option<VideoSignalType>, RbspBitReaderError> {
+    fn read<R: BitRead + ?Sized>(r: &mut R) -> Result<Option<VideoSignalType>, RbspBitReaderError> {
         let video_signal_type_present_flag = r.read_bool()?;
         Ok(if video_signal_type_present_flag {
             Some(VideoSignalType {
@@ -689,15 +1686,25 @@ impl VideoSignalType {
             None
         })
     }
+    fn write<W: BitWrite + ?Sized>(this: Option<&Self>, w: &mut W) -> Result<(), BitWriterError> {
+        w.write_bool_named("video_signal_type_present_flag", this.is_some())?;
+        if let Some(v) = this {
+            w.write_u_named("video_format", 3, u32::from(v.video_format.video_format()))?;
+            w.write_bool_named("video_full_range_flag", v.video_full_range_flag)?;
+            ColourDescription::write(v.colour_description.as_ref(), w)?;
+        }
+        Ok(())
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ChromaLocInfo {
-    chroma_sample_loc_type_top_field: u32,
-    chroma_sample_loc_type_bottom_field: u32,
+    pub chroma_sample_loc_type_top_field: u32,
+    pub chroma_sample_loc_type_bottom_field: u32,
 }
 impl ChromaLocInfo {
-    fn read(r: &mut RbspBitReader<'_>) -> Result<Option<ChromaLocInfo>, RbspBitReaderError> {
+    fn read<R: BitRead + ?Sized>(r: &mut R) -> Result<Option<ChromaLocInfo>, RbspBitReaderError> {
         let chroma_loc_info_present_flag = r.read_bool()?;
         Ok(if chroma_loc_info_present_flag {
             Some(ChromaLocInfo {
@@ -708,16 +1715,25 @@ impl ChromaLocInfo {
             None
         })
     }
+    fn write<W: BitWrite + ?Sized>(this: Option<&Self>, w: &mut W) -> Result<(), BitWriterError> {
+        w.write_bool_named("chroma_loc_info_present_flag", this.is_some())?;
+        if let Some(c) = this {
+            w.write_ue_named("chroma_sample_loc_type_top_field", c.chroma_sample_loc_type_top_field)?;
+            w.write_ue_named("chroma_sample_loc_type_bottom_field", c.chroma_sample_loc_type_bottom_field)?;
+        }
+        Ok(())
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TimingInfo {
     pub num_units_in_tick: u32,
     pub time_scale: u32,
     pub fixed_frame_rate_flag: bool,
 }
 impl TimingInfo {
-    fn read(r: &mut RbspBitReader<'_>) -> Result<Option<TimingInfo>, RbspBitReaderError> {
+    pub(crate) fn read<R: BitRead + ?Sized>(r: &mut R) -> Result<Option<TimingInfo>, RbspBitReaderError> {
         let timing_info_present_flag = r.read_bool()?;
         Ok(if timing_info_present_flag {
             Some(TimingInfo {
@@ -729,16 +1745,71 @@ impl TimingInfo {
             None
         })
     }
+    pub(crate) fn write<W: BitWrite + ?Sized>(this: Option<&Self>, w: &mut W) -> Result<(), BitWriterError> {
+        w.write_bool_named("timing_info_present_flag", this.is_some())?;
+        if let Some(t) = this {
+            w.write_u_named("num_units_in_tick", 32, t.num_units_in_tick)?;
+            w.write_u_named("time_scale", 32, t.time_scale)?;
+            w.write_bool_named("fixed_frame_rate_flag", t.fixed_frame_rate_flag)?;
+        }
+        Ok(())
+    }
+
+    /// The nominal frame rate implied by this timing information, or `None` if either
+    /// `num_units_in_tick` or `time_scale` is zero.
+    ///
+    /// A frame normally lasts two clock ticks (one per field), so this is
+    /// `time_scale / (2 * num_units_in_tick)`.  Pictures may actually last a different number of
+    /// ticks, as signalled by `pic_struct` in the pic timing SEI (eg field repetition or frame
+    /// doubling); see `FrameRate::tick_rate()` for the raw clock tick rate.
+    pub fn fps(&self) -> Option<FrameRate> {
+        if self.num_units_in_tick == 0 || self.time_scale == 0 {
+            return None;
+        }
+        Some(FrameRate {
+            num: self.time_scale,
+            den: 2 * u64::from(self.num_units_in_tick),
+            fixed_frame_rate: self.fixed_frame_rate_flag,
+        })
+    }
 }
 
-#[derive(Debug, Clone)]
+/// A frame rate, as an exact fraction of frames per second, derived from `TimingInfo`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FrameRate {
+    /// the numerator, which is `time_scale`
+    pub num: u32,
+    /// the denominator, which is `2 * num_units_in_tick`
+    pub den: u64,
+    /// `fixed_frame_rate_flag`: if false, the stream may vary its frame rate and this value is
+    /// only an upper bound
+    pub fixed_frame_rate: bool,
+}
+impl FrameRate {
+    /// The frame rate as a floating-point number of frames per second.
+    pub fn as_f64(&self) -> f64 {
+        f64::from(self.num) / self.den as f64
+    }
+
+    /// The clock tick rate, `time_scale / num_units_in_tick`, as a `(num, den)` fraction.  This is
+    /// the field rate of interlaced content, or twice the frame rate of progressive content.
+    pub fn tick_rate(&self) -> (u32, u64) {
+        (self.num, self.den / 2)
+    }
+}
+
+/// The parameters of one of the CPB specifications of `hrd_parameters()`, indexed by
+/// `SchedSelIdx`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CpbSpec {
-    bit_rate_value_minus1: u32,
-    cpb_size_value_minus1: u32,
-    cbr_flag: bool,
+    pub bit_rate_value_minus1: u32,
+    pub cpb_size_value_minus1: u32,
+    pub cbr_flag: bool,
 }
 impl CpbSpec {
-    fn read(r: &mut RbspBitReader<'_>) -> Result<CpbSpec,RbspBitReaderError> {
+    fn read<R: BitRead + ?Sized>(r: &mut R) -> Result<CpbSpec,RbspBitReaderError> {
         Ok(CpbSpec {
             bit_rate_value_minus1: r.read_ue_named("bit_rate_value_minus1")?,
             cpb_size_value_minus1: r.read_ue_named("cpb_size_value_minus1")?,
@@ -748,10 +1819,12 @@ impl CpbSpec {
 }
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct HrdParameters {
     pub bit_rate_scale: u8,
     pub cpb_size_scale: u8,
+    /// One entry for each `SchedSelIdx` from `0` to `cpb_cnt_minus1`.
     pub cpb_specs: Vec<CpbSpec>,
     pub initial_cpb_removal_delay_length_minus1: u8,
     pub cpb_removal_delay_length_minus1: u8,
@@ -759,7 +1832,7 @@ pub struct HrdParameters {
     pub time_offset_length: u8,
 }
 impl HrdParameters {
-    fn read(r: &mut RbspBitReader<'_>, hrd_parameters_present: &mut bool) -> Result<Option<HrdParameters>, SpsError> {
+    pub(crate) fn read<R: BitRead + ?Sized>(r: &mut R, hrd_parameters_present: &mut bool) -> Result<Option<HrdParameters>, SpsError> {
         let hrd_parameters_present_flag = r.read_bool_named("hrd_parameters_present_flag")?;
         *hrd_parameters_present |= hrd_parameters_present_flag;
         Ok(if hrd_parameters_present_flag {
@@ -781,45 +1854,130 @@ impl HrdParameters {
             None
         })
     }
-    fn read_cpb_specs(r: &mut RbspBitReader<'_>, cpb_cnt: u32) -> Result<Vec<CpbSpec>,RbspBitReaderError> {
+    pub(crate) fn write<W: BitWrite + ?Sized>(this: Option<&Self>, w: &mut W) -> Result<(), BitWriterError> {
+        w.write_bool_named("hrd_parameters_present_flag", this.is_some())?;
+        let hrd = match this {
+            Some(hrd) => hrd,
+            None => return Ok(()),
+        };
+        if hrd.cpb_specs.is_empty() || hrd.cpb_specs.len() > 32 {
+            return Err(BitWriterError::ValueOutOfRange { name: "cpb_cnt_minus1", value: hrd.cpb_specs.len() as i64 - 1 });
+        }
+        w.write_ue_named("cpb_cnt_minus1", hrd.cpb_specs.len() as u32 - 1)?;
+        w.write_u_named("bit_rate_scale", 4, u32::from(hrd.bit_rate_scale))?;
+        w.write_u_named("cpb_size_scale", 4, u32::from(hrd.cpb_size_scale))?;
+        for spec in &hrd.cpb_specs {
+            w.write_ue_named("bit_rate_value_minus1", spec.bit_rate_value_minus1)?;
+            w.write_ue_named("cpb_size_value_minus1", spec.cpb_size_value_minus1)?;
+            w.write_bool_named("cbr_flag", spec.cbr_flag)?;
+        }
+        w.write_u_named("initial_cpb_removal_delay_length_minus1", 5, u32::from(hrd.initial_cpb_removal_delay_length_minus1))?;
+        w.write_u_named("cpb_removal_delay_length_minus1", 5, u32::from(hrd.cpb_removal_delay_length_minus1))?;
+        w.write_u_named("dpb_output_delay_length_minus1", 5, u32::from(hrd.dpb_output_delay_length_minus1))?;
+        w.write_u_named("time_offset_length", 5, u32::from(hrd.time_offset_length))
+    }
+    fn read_cpb_specs<R: BitRead + ?Sized>(r: &mut R, cpb_cnt: u32) -> Result<Vec<CpbSpec>,RbspBitReaderError> {
         let mut cpb_specs = Vec::with_capacity(cpb_cnt as usize);
         for _ in 0..cpb_cnt {
             cpb_specs.push(CpbSpec::read(r)?);
         }
         Ok(cpb_specs)
     }
+
+    /// `cpb_cnt_minus1 + 1`: the number of alternative CPB specifications.
+    pub fn cpb_cnt(&self) -> usize {
+        self.cpb_specs.len()
+    }
+
+    /// The maximum input bit rate, in bits per second, of the CPB specification with the given
+    /// `SchedSelIdx`, per _equation E-37_.
+    pub fn bit_rate(&self, sched_sel_idx: usize) -> Option<u64> {
+        self.cpb_specs.get(sched_sel_idx)
+            .map(|spec| (u64::from(spec.bit_rate_value_minus1) + 1) << (6 + self.bit_rate_scale))
+    }
+
+    /// The CPB size, in bits, of the CPB specification with the given `SchedSelIdx`, per
+    /// _equation E-38_.
+    pub fn cpb_size(&self, sched_sel_idx: usize) -> Option<u64> {
+        self.cpb_specs.get(sched_sel_idx)
+            .map(|spec| (u64::from(spec.cpb_size_value_minus1) + 1) << (4 + self.cpb_size_scale))
+    }
+
+    /// Whether the CPB specification with the given `SchedSelIdx` is operated in constant bit
+    /// rate mode.
+    pub fn cbr(&self, sched_sel_idx: usize) -> Option<bool> {
+        self.cpb_specs.get(sched_sel_idx).map(|spec| spec.cbr_flag)
+    }
+
+    /// The length in bits of the buffering period SEI's `initial_cpb_removal_delay` and
+    /// `initial_cpb_removal_delay_offset`.
+    pub fn initial_cpb_removal_delay_length(&self) -> u8 {
+        self.initial_cpb_removal_delay_length_minus1 + 1
+    }
+
+    /// The length in bits of the picture timing SEI's `cpb_removal_delay`.
+    pub fn cpb_removal_delay_length(&self) -> u8 {
+        self.cpb_removal_delay_length_minus1 + 1
+    }
+
+    /// The length in bits of the picture timing SEI's `dpb_output_delay`.
+    pub fn dpb_output_delay_length(&self) -> u8 {
+        self.dpb_output_delay_length_minus1 + 1
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BitstreamRestrictions {
-    motion_vectors_over_pic_boundaries_flag: bool,
-    max_bytes_per_pic_denom: u32,
-    max_bits_per_mb_denom: u32,
-    log2_max_mv_length_horizontal: u32,
-    log2_max_mv_length_vertical: u32,
-    max_num_reorder_frames: u32,
-    max_dec_frame_buffering: u32,
+    /// If false, no sample outside the picture boundaries is used for inter prediction.
+    pub motion_vectors_over_pic_boundaries_flag: bool,
+    /// Bounds the size of each picture's VCL NAL units; `0` means no limit is indicated.
+    pub max_bytes_per_pic_denom: u32,
+    /// Bounds the size of each macroblock's coded data; `0` means no limit is indicated.
+    pub max_bits_per_mb_denom: u32,
+    /// The base-2 log of the maximum horizontal motion vector component, in quarter samples.
+    pub log2_max_mv_length_horizontal: u32,
+    /// The base-2 log of the maximum vertical motion vector component, in quarter samples.
+    pub log2_max_mv_length_vertical: u32,
+    /// See [SeqParameterSet::max_num_reorder_frames], which also covers the inferred value.
+    pub max_num_reorder_frames: u32,
+    /// See [SeqParameterSet::max_dec_frame_buffering], which also covers the inferred value.
+    pub max_dec_frame_buffering: u32,
 }
 impl BitstreamRestrictions {
-    fn read(r: &mut RbspBitReader<'_>) -> Result<Option<BitstreamRestrictions>,RbspBitReaderError> {
+    fn read<R: BitRead + ?Sized>(r: &mut R) -> Result<Option<BitstreamRestrictions>,SpsError> {
         let bitstream_restriction_flag = r.read_bool()?;
         Ok(if bitstream_restriction_flag {
             Some(BitstreamRestrictions {
                 motion_vectors_over_pic_boundaries_flag: r.read_bool_named("motion_vectors_over_pic_boundaries_flag")?,
-                max_bytes_per_pic_denom: r.read_ue_named("max_bytes_per_pic_denom")?,
-                max_bits_per_mb_denom: r.read_ue_named("max_bits_per_mb_denom")?,
-                log2_max_mv_length_horizontal: r.read_ue_named("log2_max_mv_length_horizontal")?,
-                log2_max_mv_length_vertical: r.read_ue_named("log2_max_mv_length_vertical")?,
-                max_num_reorder_frames: r.read_ue_named("max_num_reorder_frames")?,
-                max_dec_frame_buffering: r.read_ue_named("max_dec_frame_buffering")?,
+                max_bytes_per_pic_denom: read_ue_max(r, "max_bytes_per_pic_denom", 16)?,
+                max_bits_per_mb_denom: read_ue_max(r, "max_bits_per_mb_denom", 16)?,
+                log2_max_mv_length_horizontal: read_ue_max(r, "log2_max_mv_length_horizontal", 16)?,
+                log2_max_mv_length_vertical: read_ue_max(r, "log2_max_mv_length_vertical", 16)?,
+                max_num_reorder_frames: read_ue_max(r, "max_num_reorder_frames", MAX_DPB_FRAMES)?,
+                max_dec_frame_buffering: read_ue_max(r, "max_dec_frame_buffering", MAX_DPB_FRAMES)?,
             })
         } else {
             None
         })
     }
+    fn write<W: BitWrite + ?Sized>(this: Option<&Self>, w: &mut W) -> Result<(), BitWriterError> {
+        w.write_bool_named("bitstream_restriction_flag", this.is_some())?;
+        if let Some(b) = this {
+            w.write_bool_named("motion_vectors_over_pic_boundaries_flag", b.motion_vectors_over_pic_boundaries_flag)?;
+            w.write_ue_named("max_bytes_per_pic_denom", b.max_bytes_per_pic_denom)?;
+            w.write_ue_named("max_bits_per_mb_denom", b.max_bits_per_mb_denom)?;
+            w.write_ue_named("log2_max_mv_length_horizontal", b.log2_max_mv_length_horizontal)?;
+            w.write_ue_named("log2_max_mv_length_vertical", b.log2_max_mv_length_vertical)?;
+            w.write_ue_named("max_num_reorder_frames", b.max_num_reorder_frames)?;
+            w.write_ue_named("max_dec_frame_buffering", b.max_dec_frame_buffering)?;
+        }
+        Ok(())
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct VuiParameters {
     pub aspect_ratio_info: Option<AspectRatioInfo>,
     pub overscan_appropriate: OverscanAppropriate,
@@ -833,7 +1991,7 @@ pub struct VuiParameters {
     pub bitstream_restrictions: Option<BitstreamRestrictions>,
 }
 impl VuiParameters {
-    fn read(r: &mut RbspBitReader<'_>) -> Result<Option<VuiParameters>, SpsError> {
+    fn read<R: BitRead + ?Sized>(r: &mut R) -> Result<Option<VuiParameters>, SpsError> {
         let vui_parameters_present_flag = r.read_bool()?;
         Ok(if vui_parameters_present_flag {
             let mut hrd_parameters_present = false;
@@ -843,8 +2001,8 @@ impl VuiParameters {
                 video_signal_type: VideoSignalType::read(r)?,
                 chroma_loc_info: ChromaLocInfo::read(r)?,
                 timing_info: TimingInfo::read(r)?,
-                nal_hrd_parameters: HrdParameters::read(r, &mut hrd_parameters_present)?,
-                vcl_hrd_parameters: HrdParameters::read(r, &mut hrd_parameters_present)?,
+                nal_hrd_parameters: rbsp::scoped(r, "nal_hrd", |r| HrdParameters::read(r, &mut hrd_parameters_present))?,
+                vcl_hrd_parameters: rbsp::scoped(r, "vcl_hrd", |r| HrdParameters::read(r, &mut hrd_parameters_present))?,
                 low_delay_hrd_flag: if hrd_parameters_present { Some(r.read_bool_named("low_delay_hrd_flag")?) } else { None },
                 pic_struct_present_flag: r.read_bool_named("pic_struct_present_flag")?,
                 bitstream_restrictions: BitstreamRestrictions::read(r)?,
@@ -853,9 +2011,31 @@ impl VuiParameters {
             None
         })
     }
+    /// Writes the VUI.  `low_delay_hrd_flag` is written (as `false` if `None`) whenever HRD
+    /// parameters are present, and otherwise ignored.
+    fn write<W: BitWrite + ?Sized>(this: Option<&Self>, w: &mut W) -> Result<(), BitWriterError> {
+        w.write_bool_named("vui_parameters_present_flag", this.is_some())?;
+        let vui = match this {
+            Some(vui) => vui,
+            None => return Ok(()),
+        };
+        AspectRatioInfo::write(vui.aspect_ratio_info.as_ref(), w)?;
+        vui.overscan_appropriate.write(w)?;
+        VideoSignalType::write(vui.video_signal_type.as_ref(), w)?;
+        ChromaLocInfo::write(vui.chroma_loc_info.as_ref(), w)?;
+        TimingInfo::write(vui.timing_info.as_ref(), w)?;
+        HrdParameters::write(vui.nal_hrd_parameters.as_ref(), w)?;
+        HrdParameters::write(vui.vcl_hrd_parameters.as_ref(), w)?;
+        if vui.nal_hrd_parameters.is_some() || vui.vcl_hrd_parameters.is_some() {
+            w.write_bool_named("low_delay_hrd_flag", vui.low_delay_hrd_flag.unwrap_or(false))?;
+        }
+        w.write_bool_named("pic_struct_present_flag", vui.pic_struct_present_flag)?;
+        BitstreamRestrictions::write(vui.bitstream_restrictions.as_ref(), w)
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SeqParameterSet {
     pub profile_idc: ProfileIdc,
     pub constraint_flags: ConstraintFlags,
@@ -874,30 +2054,131 @@ pub struct SeqParameterSet {
     pub vui_parameters: Option<VuiParameters>,
 }
 impl SeqParameterSet {
+    /// Parses the given RBSP: the payload following the NAL header byte, with emulation
+    /// prevention already removed.  See [SeqParameterSet::from_nal] to parse a whole NAL unit.
     pub fn from_bytes(buf: &[u8]) -> Result<SeqParameterSet, SpsError> {
-        let mut r = RbspBitReader::new(buf);
-        let profile_idc = r.read_u8(8)?.into();
-        let sps = SeqParameterSet {
-            profile_idc,
-            constraint_flags: r.read_u8(8)?.into(),
+        Self::from_bits(&mut BitReaderSlice::new(buf))
+    }
+
+    /// Parses a whole SPS NAL unit: the header byte followed by the escaped payload, as found in
+    /// an `avcC` record or a `sprop-parameter-sets` value.
+    pub fn from_nal(nal: &[u8]) -> Result<SeqParameterSet, SpsError> {
+        let (&header, payload) = nal.split_first().ok_or(SpsError::EmptyNal)?;
+        let header = NalHeader::new(header).map_err(SpsError::NalHeader)?;
+        if header.nal_unit_type() != UnitType::SeqParameterSet {
+            return Err(SpsError::IncorrectNalType(header.nal_unit_type()));
+        }
+        Self::from_bytes(&rbsp::decode_nal(payload))
+    }
+
+    /// Parses the `seq_parameter_set_data()` syntax from the given reader, which is expected to
+    /// be positioned just after the NAL header.
+    ///
+    /// Errors are annotated with their location (see [SpsError::location]).
+    pub fn from_bits<R: BitRead + ?Sized>(r: &mut R) -> Result<SeqParameterSet, SpsError> {
+        rbsp::scoped(r, "sps", Self::read_fields)
+    }
+
+    fn read_fields<R: BitRead + ?Sized>(r: &mut R) -> Result<SeqParameterSet, SpsError> {
+        Self::read_fields_and_vui_position(r).map(|(sps, _)| sps)
+    }
+
+    /// As `read_fields`, also returning the bit position of `vui_parameters_present_flag`.
+    fn read_fields_and_vui_position<R: BitRead + ?Sized>(r: &mut R) -> Result<(SeqParameterSet, u64), SpsError> {
+        let profile_idc = r.read_u8(8)?.into();
+        let mut sps = SeqParameterSet {
+            profile_idc,
+            constraint_flags: r.read_u8(8)?.into(),
             level_idc: r.read_u8(8)?,
             seq_parameter_set_id: ParamSetId::from_u32(r.read_ue_named("seq_parameter_set_id")?).map_err(SpsError::BadSeqParamSetId)?,
-            chroma_info: ChromaInfo::read(&mut r, profile_idc)?,
-            log2_max_frame_num_minus4: Self::read_log2_max_frame_num_minus4(&mut r)?,
-            pic_order_cnt: PicOrderCntType::read(&mut r).map_err(SpsError::PicOrderCnt)?,
-            max_num_ref_frames: r.read_ue_named("max_num_ref_frames")?,
+            chroma_info: rbsp::scoped(r, "chroma_info", |r| ChromaInfo::read(r, profile_idc))?,
+            log2_max_frame_num_minus4: Self::read_log2_max_frame_num_minus4(r)?,
+            pic_order_cnt: rbsp::scoped(r, "pic_order_cnt", |r| PicOrderCntType::read(r).map_err(SpsError::PicOrderCnt))?,
+            max_num_ref_frames: read_ue_max(r, "max_num_ref_frames", MAX_DPB_FRAMES)?,
             gaps_in_frame_num_value_allowed_flag: r.read_bool()?,
-            pic_width_in_mbs_minus1: r.read_ue_named("pic_width_in_mbs_minus1")?,
-            pic_height_in_map_units_minus1: r.read_ue_named("pic_height_in_map_units_minus1")?,
-            frame_mbs_flags: FrameMbsFlags::read(&mut r)?,
+            pic_width_in_mbs_minus1: read_ue_max(r, "pic_width_in_mbs_minus1", MAX_DIMENSION_MBS - 1)?,
+            pic_height_in_map_units_minus1: read_ue_max(r, "pic_height_in_map_units_minus1", MAX_DIMENSION_MBS - 1)?,
+            frame_mbs_flags: FrameMbsFlags::read(r)?,
             direct_8x8_inference_flag: r.read_bool()?,
-            frame_cropping: FrameCropping::read(&mut r)?,
-            vui_parameters: VuiParameters::read(&mut r)?,
+            frame_cropping: rbsp::scoped(r, "frame_cropping", |r| FrameCropping::read(r).map_err(SpsError::from))?,
+            vui_parameters: None,
         };
-        Ok(sps)
+        let vui_position = r.bit_position();
+        sps.vui_parameters = rbsp::scoped(r, "vui", VuiParameters::read)?;
+        // With field coding, FrameHeightInMbs is twice the map units checked above.
+        if sps.frame_height_in_mbs() > u64::from(MAX_DIMENSION_MBS) || sps.frame_size_in_mbs() > MAX_FRAME_SIZE_MBS {
+            return Err(SpsError::FieldValueTooLarge {
+                name: "pic_height_in_map_units_minus1",
+                value: sps.pic_height_in_map_units_minus1,
+            });
+        }
+        Ok((sps, vui_position))
+    }
+
+    /// Writes this SPS as a `seq_parameter_set_rbsp()`: the `seq_parameter_set_data()` syntax
+    /// (the inverse of [SeqParameterSet::from_bits]) followed by `rbsp_trailing_bits()`.
+    ///
+    /// The NAL header is not written, and emulation prevention must be added to the result
+    /// (e.g. with [rbsp::encode_nal]).  The output is equivalent to, but not necessarily
+    /// bit-identical to, the SPS this was parsed from: for example, scaling lists are written in
+    /// the shortest form this encoder finds.
+    pub fn write<W: BitWrite + ?Sized>(&self, w: &mut W) -> Result<(), BitWriterError> {
+        w.write_u_named("profile_idc", 8, u32::from(u8::from(self.profile_idc)))?;
+        w.write_u_named("constraint_flags", 8, u32::from(u8::from(self.constraint_flags)))?;
+        w.write_u_named("level_idc", 8, u32::from(self.level_idc))?;
+        w.write_ue_named("seq_parameter_set_id", u32::from(self.seq_parameter_set_id.id()))?;
+        self.chroma_info.write(w, self.profile_idc)?;
+        w.write_ue_named("log2_max_frame_num_minus4", u32::from(self.log2_max_frame_num_minus4))?;
+        self.pic_order_cnt.write(w)?;
+        w.write_ue_named("max_num_ref_frames", self.max_num_ref_frames)?;
+        w.write_bool_named("gaps_in_frame_num_value_allowed_flag", self.gaps_in_frame_num_value_allowed_flag)?;
+        w.write_ue_named("pic_width_in_mbs_minus1", self.pic_width_in_mbs_minus1)?;
+        w.write_ue_named("pic_height_in_map_units_minus1", self.pic_height_in_map_units_minus1)?;
+        self.frame_mbs_flags.write(w)?;
+        w.write_bool_named("direct_8x8_inference_flag", self.direct_8x8_inference_flag)?;
+        FrameCropping::write(self.frame_cropping.as_ref(), w)?;
+        VuiParameters::write(self.vui_parameters.as_ref(), w)?;
+        w.write_rbsp_trailing_bits()
+    }
+
+    /// Returns the RBSP written by [SeqParameterSet::write]; the inverse of
+    /// [SeqParameterSet::from_bytes].
+    pub fn to_bytes(&self) -> Result<Vec<u8>, BitWriterError> {
+        let mut w = BitWriterVec::new();
+        self.write(&mut w)?;
+        Ok(w.into_inner())
     }
 
-    fn read_log2_max_frame_num_minus4(r: &mut RbspBitReader<'_>) -> Result<u8, SpsError> {
+    /// Modifies the VUI of the given SPS RBSP (as passed to [SeqParameterSet::from_bytes]),
+    /// returning the new RBSP; for example, to correct the sample aspect ratio signalled by a
+    /// broken encoder, or to add `timing_info`.
+    ///
+    /// `edit` is passed the parsed SPS along with its VUI (`None` if absent) to modify.  Unlike
+    /// [SeqParameterSet::to_bytes], the syntax preceding the VUI is copied bit-for-bit from
+    /// `rbsp`, and the VUI fields which `edit` leaves alone are written just as they were read,
+    /// so that an unmodified VUI gives back the original RBSP.  As with `to_bytes`, emulation
+    /// prevention must be added to the result.
+    pub fn rewrite_vui(rbsp: &[u8], edit: impl FnOnce(&SeqParameterSet, &mut Option<VuiParameters>)) -> Result<Vec<u8>, VuiRewriteError> {
+        let mut r = BitReaderSlice::new(rbsp);
+        let (sps, vui_position) = rbsp::scoped(&mut r, "sps", Self::read_fields_and_vui_position)
+            .map_err(VuiRewriteError::Sps)?;
+        let mut vui = sps.vui_parameters.clone();
+        edit(&sps, &mut vui);
+        let mut w = BitWriterVec::new();
+        let whole_bytes = (vui_position / 8) as usize;
+        for &b in &rbsp[..whole_bytes] {
+            w.write_bits(8, u64::from(b));
+        }
+        let remaining_bits = (vui_position % 8) as u32;
+        if remaining_bits > 0 {
+            w.write_bits(remaining_bits, u64::from(rbsp[whole_bytes] >> (8 - remaining_bits)));
+        }
+        VuiParameters::write(vui.as_ref(), &mut w).map_err(VuiRewriteError::Write)?;
+        w.write_rbsp_trailing_bits().map_err(VuiRewriteError::Write)?;
+        Ok(w.into_inner())
+    }
+
+    fn read_log2_max_frame_num_minus4<R: BitRead + ?Sized>(r: &mut R) -> Result<u8, SpsError> {
         let val = r.read_ue_named("log2_max_frame_num_minus4")?;
         if val > 12 {
             Err(SpsError::Log2MaxFrameNumMinus4OutOfRange(val))
@@ -906,20 +2187,272 @@ impl SeqParameterSet {
         }
     }
 
+    /// The profile, including those signalled by the constraint flags.
     pub fn profile(&self) -> Profile {
-        Profile::from_profile_idc(self.profile_idc)
+        Profile::from_profile_idc_and_constraint_flags(self.profile_idc, self.constraint_flags)
     }
 
     pub fn level(&self) -> Level {
-        Level::from_constraint_flags_and_level_idc(self.constraint_flags, self.level_idc)
+        Level::from_profile_idc_constraint_flags_and_level_idc(
+            self.profile_idc, self.constraint_flags, self.level_idc)
+    }
+    /// The nominal frame rate from the VUI `timing_info`, if present.  See `TimingInfo::fps()`.
+    pub fn fps(&self) -> Option<FrameRate> {
+        self.vui_parameters.as_ref()
+            .and_then(|vui| vui.timing_info.as_ref())
+            .and_then(TimingInfo::fps)
+    }
+    /// The VUI `colour_description`, if present.  When absent, the primaries, transfer
+    /// characteristics and matrix coefficients are all `Unspecified`.
+    pub fn colour_description(&self) -> Option<&ColourDescription> {
+        self.video_signal_type().and_then(|v| v.colour_description.as_ref())
+    }
+    /// The VUI `video_full_range_flag`, which is inferred to be `false` when absent.
+    pub fn video_full_range(&self) -> bool {
+        self.video_signal_type().map(|v| v.video_full_range_flag).unwrap_or(false)
+    }
+    fn video_signal_type(&self) -> Option<&VideoSignalType> {
+        self.vui_parameters.as_ref().and_then(|vui| vui.video_signal_type.as_ref())
+    }
+
+    /// _PicWidthInMbs_: the width of every picture in macroblocks.
+    pub fn pic_width_in_mbs(&self) -> u64 {
+        u64::from(self.pic_width_in_mbs_minus1) + 1
+    }
+
+    /// _FrameHeightInMbs_: the height of a frame in macroblocks.
+    ///
+    /// Unless `frame_mbs_only_flag` is set, `pic_height_in_map_units_minus1` counts macroblock
+    /// pairs (or equivalently the height of a field), so the frame is twice as high.  The
+    /// height of an individual picture, which may be a field, is given by
+    /// [PictureCoding::pic_height_in_mbs](../slice/enum.PictureCoding.html#method.pic_height_in_mbs).
+    pub fn frame_height_in_mbs(&self) -> u64 {
+        let height_in_map_units = u64::from(self.pic_height_in_map_units_minus1) + 1;
+        if self.frame_mbs_flags.frame_mbs_only_flag() {
+            height_in_map_units
+        } else {
+            2 * height_in_map_units
+        }
+    }
+
+    /// _FrameSizeInMbs_: the number of macroblocks in a frame, which is `PicWidthInMbs *
+    /// FrameHeightInMbs`.
+    pub fn frame_size_in_mbs(&self) -> u64 {
+        self.pic_width_in_mbs() * self.frame_height_in_mbs()
+    }
+
+    /// _PicSizeInMapUnits_: the number of slice group map units in a picture, which is the
+    /// number of macroblocks in a frame or (unless `frame_mbs_only_flag` is set) in a field.
+    /// The number of macroblocks in a particular picture, _PicSizeInMbs_, depends on the
+    /// slice's `field_pic_flag` and is given by [SeqParameterSet::pic_size_in_mbs].
+    pub fn pic_size_in_map_units(&self) -> u64 {
+        self.pic_width_in_mbs() * (u64::from(self.pic_height_in_map_units_minus1) + 1)
+    }
+
+    /// _PicSizeInMbs_: the number of macroblocks in a picture, which is a field if
+    /// `field_pic_flag` is set (as it may be only when `frame_mbs_only_flag` is not), and
+    /// otherwise a frame.
+    pub fn pic_size_in_mbs(&self, field_pic_flag: bool) -> u64 {
+        if field_pic_flag {
+            self.pic_width_in_mbs() * (self.frame_height_in_mbs() / 2)
+        } else {
+            self.frame_size_in_mbs()
+        }
+    }
+
+    /// _MaxFrameNum_: `frame_num` values wrap around to `0` on reaching this.
+    ///
+    /// In the range `16` to `65536` inclusive, or `None` if `log2_max_frame_num_minus4` is
+    /// beyond the spec's limit of `12` (as is possible only for a constructed SPS).
+    pub fn max_frame_num(&self) -> Option<u32> {
+        if self.log2_max_frame_num_minus4 > 12 {
+            return None;
+        }
+        Some(1 << (u32::from(self.log2_max_frame_num_minus4) + 4))
+    }
+
+    /// _MaxPicOrderCntLsb_: `pic_order_cnt_lsb` values wrap around to `0` on reaching this.
+    /// `None` unless `pic_order_cnt_type` is `0`, the only type which signals the LSBs.
+    ///
+    /// In the range `16` to `65536` inclusive; also `None` if
+    /// `log2_max_pic_order_cnt_lsb_minus4` is beyond the spec's limit of `12` (as is possible
+    /// only for a constructed SPS).
+    pub fn max_pic_order_cnt_lsb(&self) -> Option<u32> {
+        match self.pic_order_cnt {
+            PicOrderCntType::TypeZero { log2_max_pic_order_cnt_lsb_minus4 } if log2_max_pic_order_cnt_lsb_minus4 <= 12 => {
+                Some(1 << (u32::from(log2_max_pic_order_cnt_lsb_minus4) + 4))
+            },
+            _ => None,
+        }
     }
+
+    fn bitstream_restrictions(&self) -> Option<&BitstreamRestrictions> {
+        self.vui_parameters.as_ref().and_then(|vui| vui.bitstream_restrictions.as_ref())
+    }
+
+    /// _MaxDpbFrames_: the size of the decoded picture buffer in frames, as implied by the level
+    /// and picture dimensions.  `None` if the level is unknown.
+    pub fn max_dpb_frames(&self) -> Option<u32> {
+        self.level().limits().map(|limits| limits.max_dpb_frames(self.frame_size_in_mbs()))
+    }
+
+    /// True if this SPS's profile only allows IDR pictures, in which case the DPB is not used
+    /// for reordering (see the inference rules for `max_dec_frame_buffering` in _E.2.1_).
+    fn intra_only(&self) -> bool {
+        self.profile().is_intra()
+    }
+
+    /// The number of frames a decoder must be able to hold in its DPB: `max_dec_frame_buffering`
+    /// from the VUI bitstream restrictions if present, and otherwise the value inferred by
+    /// _E.2.1_ (i.e. `max_dpb_frames()`, or `0` for intra-only profiles).
+    ///
+    /// `None` if the value is absent and the level is unknown.
+    pub fn max_dec_frame_buffering(&self) -> Option<u32> {
+        if let Some(r) = self.bitstream_restrictions() {
+            return Some(r.max_dec_frame_buffering);
+        }
+        if self.intra_only() {
+            return Some(0);
+        }
+        self.max_dpb_frames()
+    }
+
+    /// True if a decoder configured for `other` can continue with this SPS without being reset:
+    /// the two agree on the fields which determine the size and format of decoded pictures,
+    /// the DPB size and the reference picture and picture order count arithmetic.
+    ///
+    /// These are the chroma format (including `separate_colour_plane_flag`), bit depths, coded
+    /// dimensions, `frame_mbs_flags`, cropping, `max_num_ref_frames`, the DPB size given by
+    /// [SeqParameterSet::max_dec_frame_buffering], `log2_max_frame_num_minus4` and the picture
+    /// order count type with its parameters.  Other fields, including the id, profile, level,
+    /// scaling matrices and the rest of the VUI, are ignored.
+    pub fn compatible_with(&self, other: &SeqParameterSet) -> bool {
+        self.chroma_info.chroma_format == other.chroma_info.chroma_format
+            && self.chroma_info.separate_colour_plane_flag == other.chroma_info.separate_colour_plane_flag
+            && self.chroma_info.bit_depth_luma_minus8 == other.chroma_info.bit_depth_luma_minus8
+            && self.chroma_info.bit_depth_chroma_minus8 == other.chroma_info.bit_depth_chroma_minus8
+            && self.pic_width_in_mbs_minus1 == other.pic_width_in_mbs_minus1
+            && self.pic_height_in_map_units_minus1 == other.pic_height_in_map_units_minus1
+            && self.frame_mbs_flags == other.frame_mbs_flags
+            && self.frame_cropping == other.frame_cropping
+            && self.max_num_ref_frames == other.max_num_ref_frames
+            && self.max_dec_frame_buffering() == other.max_dec_frame_buffering()
+            && self.log2_max_frame_num_minus4 == other.log2_max_frame_num_minus4
+            && self.pic_order_cnt == other.pic_order_cnt
+    }
+
+    /// Checks whether a decoder conforming to the given profile and level can decode the stream
+    /// described by this SPS, returning every incompatibility found.
+    ///
+    /// The stream's `profile_idc` and constraint flags must indicate conformance to a profile the
+    /// decoder supports (_A.2_), the SPS must meet the constraints of `profile` (see
+    /// [Profile::check]) and the limits of `level` (see [Level::check], using the VUI frame rate
+    /// if any), and the stream's level must not be higher than `level`.
+    pub fn decodable_by(&self, profile: Profile, level: Level) -> Result<(), Vec<Incompatibility>> {
+        let mut incompatibilities = Vec::new();
+        match profile.decodes(self.profile_idc, self.constraint_flags) {
+            None => incompatibilities.push(Incompatibility::UnknownProfile(profile.profile_idc())),
+            Some(false) => incompatibilities.push(Incompatibility::Profile(self.profile())),
+            Some(true) => {},
+        }
+        if let Err(ProfileError::ConstraintsViolated(violations)) = profile.check(self) {
+            incompatibilities.extend(violations.into_iter().map(Incompatibility::ProfileConstraint));
+        }
+        match (level.ordinal(), self.level().ordinal()) {
+            (None, _) => incompatibilities.push(Incompatibility::UnknownLevel(level.level_idc())),
+            (Some(decoder), Some(stream)) if stream > decoder => {
+                incompatibilities.push(Incompatibility::Level(self.level()));
+            },
+            _ => {},
+        }
+        if let Err(LevelError::LimitsExceeded(violations)) = level.check(self, self.fps().map(|f| f.as_f64())) {
+            incompatibilities.extend(violations.into_iter().map(Incompatibility::LevelLimit));
+        }
+        if incompatibilities.is_empty() {
+            Ok(())
+        } else {
+            Err(incompatibilities)
+        }
+    }
+
+    /// The maximum number of frames which may precede any frame in decoding order and follow it
+    /// in output order, which bounds the size of a player's reorder queue.  This is
+    /// `max_num_reorder_frames` from the VUI bitstream restrictions if present, and otherwise the
+    /// value inferred by _E.2.1_ (equal to `max_dec_frame_buffering()`).
+    ///
+    /// `None` if the value is absent and the level is unknown.
+    pub fn max_num_reorder_frames(&self) -> Option<u32> {
+        match self.bitstream_restrictions() {
+            Some(r) => Some(r.max_num_reorder_frames),
+            None => self.max_dec_frame_buffering(),
+        }
+    }
+
+    /// The number of frames a player should budget for holding back before output to restore
+    /// display order: `0` if `pic_order_cnt_type` is `2` (which requires output order to match
+    /// decoding order), and otherwise [SeqParameterSet::max_num_reorder_frames].
+    ///
+    /// A stream for which this is `Some(0)` can be displayed as soon as each frame is decoded.
+    pub fn reorder_delay_frames(&self) -> Option<u32> {
+        match self.pic_order_cnt {
+            PicOrderCntType::TypeTwo => Some(0),
+            _ => self.max_num_reorder_frames(),
+        }
+    }
+
+    /// The display aspect ratio as a reduced `(width, height)` fraction, combining the VUI sample
+    /// aspect ratio with the cropped `pixel_dimensions()`.
+    ///
+    /// Returns `Ok(None)` if the sample aspect ratio is absent or unspecified; callers may
+    /// choose to assume square samples in that case.
+    pub fn display_aspect_ratio(&self) -> Result<Option<(u64, u64)>, SpsError> {
+        let (width, height) = self.pixel_dimensions()?;
+        let sar = self.vui_parameters.as_ref()
+            .and_then(|vui| vui.aspect_ratio_info.as_ref())
+            .and_then(AspectRatioInfo::get);
+        Ok(sar.map(|(sar_width, sar_height)| {
+            let w = u64::from(width) * u64::from(sar_width);
+            let h = u64::from(height) * u64::from(sar_height);
+            let d = gcd(w, h);
+            (w / d, h / d)
+        }))
+    }
+
     /// returned value will be in the range 4 to 16 inclusive
     pub fn log2_max_frame_num(&self) -> u8 {
         self.log2_max_frame_num_minus4 + 4
     }
 
+    /// The _ChromaArrayType_ variable: the chroma format, or `Monochrome` when the colour planes
+    /// are coded separately (`separate_colour_plane_flag`).
+    pub fn chroma_array_type(&self) -> ChromaFormat {
+        if self.chroma_info.separate_colour_plane_flag {
+            ChromaFormat::Monochrome
+        } else {
+            self.chroma_info.chroma_format
+        }
+    }
+
+    /// The _CropUnitX_ and _CropUnitY_ variables (_equations 7-19 to 7-22_): the number of luma
+    /// samples represented by each unit of the horizontal and vertical `frame_crop_*_offset`s.
+    pub fn crop_unit(&self) -> (u32, u32) {
+        let frame_height_mul = match self.frame_mbs_flags {
+            FrameMbsFlags::Fields { .. } => 2,
+            FrameMbsFlags::Frames => 1,
+        };
+        let (sub_width_c, sub_height_c) = match self.chroma_array_type() {
+            ChromaFormat::YUV420 => (2, 2),
+            ChromaFormat::YUV422 => (2, 1),
+            _ => (1, 1),
+        };
+        (sub_width_c, sub_height_c * frame_height_mul)
+    }
+
     /// Helper to calculate the pixel-dimensions of the video image specified by this SPS, taking
     /// into account sample-format, interlacing and cropping.
+    ///
+    /// Cropping which would leave no pixels in either dimension is reported as
+    /// `SpsError::CroppingError`.
     pub fn pixel_dimensions(&self) -> Result<(u32, u32), SpsError> {
         let width = self.pic_width_in_mbs_minus1.checked_add(1).and_then(|w| w.checked_mul(16))
             .ok_or_else(|| SpsError::FieldValueTooLarge { name:"pic_width_in_mbs_minus1", value: self.pic_width_in_mbs_minus1 })?;
@@ -927,26 +2460,11 @@ impl SeqParameterSet {
             FrameMbsFlags::Fields { .. } => 2,
             FrameMbsFlags::Frames => 1,
         };
-        let vsub = if self.chroma_info.chroma_format == ChromaFormat::YUV420 {
-            1
-        } else {
-            0
-        };
-        let hsub = if self.chroma_info.chroma_format == ChromaFormat::YUV420
-            || self.chroma_info.chroma_format == ChromaFormat::YUV422
-        {
-            1
-        } else {
-            0
-        };
-
-        let step_x = 1 << hsub;
-        let step_y = mul << vsub;
-
-        let height = (self.pic_height_in_map_units_minus1 + 1)
-            .checked_mul(mul * 16)
+        let height = self.pic_height_in_map_units_minus1.checked_add(1)
+            .and_then(|h| h.checked_mul(mul * 16))
             .ok_or_else(|| SpsError::FieldValueTooLarge { name:"pic_height_in_map_units_minus1", value: self.pic_height_in_map_units_minus1 })?;
         if let Some(ref crop) = self.frame_cropping {
+            let (step_x, step_y) = self.crop_unit();
             let left_offset = crop.left_offset.checked_mul(step_x)
                 .ok_or_else(|| SpsError::FieldValueTooLarge { name:"left_offset", value: crop.left_offset })?;
             let right_offset = crop.right_offset.checked_mul(step_x)
@@ -961,10 +2479,9 @@ impl SeqParameterSet {
             let height = height
                 .checked_sub(top_offset)
                 .and_then(|w| w.checked_sub(bottom_offset) );
-            if let (Some(width), Some(height)) = (width, height) {
-                Ok((width, height))
-            } else {
-                Err(SpsError::CroppingError(crop.clone()))
+            match (width, height) {
+                (Some(width), Some(height)) if width > 0 && height > 0 => Ok((width, height)),
+                _ => Err(SpsError::CroppingError(crop.clone())),
             }
         } else {
             Ok((width, height))
@@ -976,6 +2493,362 @@ impl SeqParameterSet {
     }
 }
 
+/// The properties of a stream which a decoder is typically initialised with, as given by the
+/// active SPS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StreamFormat {
+    pub seq_parameter_set_id: ParamSetId,
+    /// The cropped dimensions (see [SeqParameterSet::pixel_dimensions]), or `None` if the
+    /// SPS's cropping is invalid.
+    pub dimensions: Option<(u32, u32)>,
+    pub chroma_format: ChromaFormat,
+    pub bit_depth_luma: u8,
+    pub bit_depth_chroma: u8,
+}
+impl StreamFormat {
+    pub fn from_sps(sps: &SeqParameterSet) -> StreamFormat {
+        StreamFormat {
+            seq_parameter_set_id: sps.seq_parameter_set_id,
+            dimensions: sps.pixel_dimensions().ok(),
+            chroma_format: sps.chroma_info.chroma_format,
+            bit_depth_luma: sps.chroma_info.bit_depth_luma_minus8 + 8,
+            bit_depth_chroma: sps.chroma_info.bit_depth_chroma_minus8 + 8,
+        }
+    }
+
+    /// True if the other format differs in dimensions, chroma format or bit depth, and so would
+    /// need a decoder to be reinitialised.  The SPS id alone doesn't matter.
+    pub fn differs_from(&self, other: &StreamFormat) -> bool {
+        self.dimensions != other.dimensions
+            || self.chroma_format != other.chroma_format
+            || self.bit_depth_luma != other.bit_depth_luma
+            || self.bit_depth_chroma != other.bit_depth_chroma
+    }
+}
+
+/// A change in the [StreamFormat] of the active SPS, passed to a
+/// [StreamChangeHandler](crate::StreamChangeHandler).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StreamChange {
+    /// The format before the change, or `None` when the first SPS is activated.
+    pub previous: Option<StreamFormat>,
+    pub current: StreamFormat,
+}
+impl StreamChange {
+    pub fn dimensions_changed(&self) -> bool {
+        match self.previous {
+            Some(p) => p.dimensions != self.current.dimensions,
+            None => true,
+        }
+    }
+    pub fn chroma_format_changed(&self) -> bool {
+        match self.previous {
+            Some(p) => p.chroma_format != self.current.chroma_format,
+            None => true,
+        }
+    }
+    pub fn bit_depth_changed(&self) -> bool {
+        match self.previous {
+            Some(p) => p.bit_depth_luma != self.current.bit_depth_luma
+                || p.bit_depth_chroma != self.current.bit_depth_chroma,
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SpsBuilderError {
+    /// `SpsBuilder::dimensions` wasn't called.
+    MissingDimensions,
+    /// The width or height is zero, too large, or not a multiple of the crop unit of the chroma
+    /// format (e.g. odd dimensions for 4:2:0).
+    InvalidDimensions { width: u32, height: u32 },
+    /// The frame rate has a zero numerator or denominator, or can't be represented as a
+    /// `time_scale` of `2 * num`.
+    InvalidFrameRate { num: u32, den: u32 },
+    /// The profile can't be described by an SPS alone (the Scalable and Multiview profiles use
+    /// a subset SPS).
+    UnsupportedProfile(Profile),
+    /// The chroma format or bit depth isn't permitted by the profile, or separate colour planes
+    /// were requested with a chroma format other than 4:4:4.
+    UnsupportedChromaFormat { chroma_format: ChromaFormat, bit_depth: u8 },
+    /// The resulting SPS exceeds the limits of the level.
+    Level(LevelError),
+}
+
+/// Constructs a progressive-scan [SeqParameterSet] from a few high-level parameters.
+///
+/// ```
+/// # use h264_reader::nal::sps::{SpsBuilder, Profile, Level};
+/// let sps = SpsBuilder::new(Profile::High, Level::L4)
+///     .dimensions(1920, 1080)
+///     .frame_rate(30, 1)
+///     .build()
+///     .unwrap();
+/// assert_eq!(sps.pixel_dimensions().unwrap(), (1920, 1080));
+/// ```
+///
+/// The remaining fields are derived: the macroblock dimensions and cropping from the pixel
+/// dimensions, `log2_max_frame_num` from the number of reference frames, and the picture order
+/// count type (`2` unless frames are reordered, `0` otherwise).  The VUI holds the timing info,
+/// sample aspect ratio and bitstream restrictions, if any were given.
+#[derive(Debug, Clone)]
+pub struct SpsBuilder {
+    profile: Profile,
+    level: Level,
+    seq_parameter_set_id: ParamSetId,
+    dimensions: Option<(u32, u32)>,
+    frame_rate: Option<(u32, u32)>,
+    chroma_format: ChromaFormat,
+    separate_colour_planes: bool,
+    bit_depth: u8,
+    max_num_ref_frames: u32,
+    max_num_reorder_frames: Option<u32>,
+    sample_aspect_ratio: Option<(u16, u16)>,
+    video_full_range: Option<bool>,
+}
+impl SpsBuilder {
+    pub fn new(profile: Profile, level: Level) -> Self {
+        SpsBuilder {
+            profile,
+            level,
+            seq_parameter_set_id: ParamSetId::from_u32(0).unwrap(),
+            dimensions: None,
+            frame_rate: None,
+            chroma_format: ChromaFormat::YUV420,
+            separate_colour_planes: false,
+            bit_depth: 8,
+            max_num_ref_frames: 1,
+            max_num_reorder_frames: None,
+            sample_aspect_ratio: None,
+            video_full_range: None,
+        }
+    }
+
+    pub fn seq_parameter_set_id(mut self, id: ParamSetId) -> Self {
+        self.seq_parameter_set_id = id;
+        self
+    }
+
+    /// The displayed width and height in pixels.
+    pub fn dimensions(mut self, width: u32, height: u32) -> Self {
+        self.dimensions = Some((width, height));
+        self
+    }
+
+    /// The frame rate, as `num / den` frames per second.
+    pub fn frame_rate(mut self, num: u32, den: u32) -> Self {
+        self.frame_rate = Some((num, den));
+        self
+    }
+
+    /// The chroma format, defaulting to 4:2:0.  Formats other than 4:2:0 need a profile which
+    /// signals `chroma_format_idc`, such as High 4:2:2.
+    pub fn chroma_format(mut self, chroma_format: ChromaFormat) -> Self {
+        self.chroma_format = chroma_format;
+        self
+    }
+
+    /// Whether the three colour planes of a 4:4:4 picture are coded separately
+    /// (`separate_colour_plane_flag`), defaulting to `false`.  Requires `ChromaFormat::YUV444`.
+    pub fn separate_colour_planes(mut self, separate_colour_planes: bool) -> Self {
+        self.separate_colour_planes = separate_colour_planes;
+        self
+    }
+
+    /// The bit depth of both luma and chroma samples, defaulting to 8.
+    pub fn bit_depth(mut self, bit_depth: u8) -> Self {
+        self.bit_depth = bit_depth;
+        self
+    }
+
+    /// The number of reference frames, defaulting to 1.
+    pub fn max_num_ref_frames(mut self, max_num_ref_frames: u32) -> Self {
+        self.max_num_ref_frames = max_num_ref_frames;
+        self
+    }
+
+    /// The maximum number of frames which precede any frame in decoding order but follow it in
+    /// output order (i.e. `0` if there are no B-frames).  If given, this is recorded in the VUI
+    /// bitstream restrictions.
+    pub fn max_num_reorder_frames(mut self, max_num_reorder_frames: u32) -> Self {
+        self.max_num_reorder_frames = Some(max_num_reorder_frames);
+        self
+    }
+
+    /// The sample aspect ratio, as `(width, height)`.
+    pub fn sample_aspect_ratio(mut self, width: u16, height: u16) -> Self {
+        self.sample_aspect_ratio = Some((width, height));
+        self
+    }
+
+    /// Whether samples use the full range of values, rather than the "studio" range.
+    pub fn video_full_range(mut self, video_full_range: bool) -> Self {
+        self.video_full_range = Some(video_full_range);
+        self
+    }
+
+    pub fn build(&self) -> Result<SeqParameterSet, SpsBuilderError> {
+        let profile_idc = self.profile.profile_idc();
+        let constraint_flags = match self.profile {
+            Profile::ConstrainedBaseline => 0b1100_0000,
+            Profile::ProgressiveHigh => 0b0000_1000,
+            Profile::ConstrainedHigh => 0b0000_1100,
+            Profile::High10Intra | Profile::High422Intra | Profile::High444Intra => 0b0001_0000,
+            Profile::ScalableBase | Profile::ScalableConstrainedBaseline | Profile::ScalableHigh
+            | Profile::ScalableConstrainedHigh | Profile::ScalableHighIntra | Profile::MultiviewHigh
+            | Profile::StereoHigh | Profile::MFCHigh | Profile::MFCDepthHigh
+            | Profile::MultiviewDepthHigh | Profile::EnhancedMultiviewDepthHigh => {
+                return Err(SpsBuilderError::UnsupportedProfile(self.profile));
+            },
+            _ => 0,
+        };
+        // Only Baseline, Main and Extended signal level 1b with constraint_set3_flag; the
+        // other profiles use level_idc 9, as the flag may already mark an Intra profile.
+        let (constraint_flags, level_idc) = match self.level {
+            Level::L1_b if profile_idc == 66 || profile_idc == 77 || profile_idc == 88 => {
+                (constraint_flags | 0b0001_0000, 11)
+            },
+            Level::L1_b => (constraint_flags, 9),
+            ref level => (constraint_flags, level.level_idc()),
+        };
+        let profile_idc = ProfileIdc(profile_idc);
+        let bit_depth_minus8 = self.bit_depth.wrapping_sub(8);
+        // the chroma format and bit depth must be expressible in the SPS; the profile's own
+        // limits are checked once it's built.
+        let chroma_ok = if profile_idc.has_chroma_info() {
+            bit_depth_minus8 <= 6 && !matches!(self.chroma_format, ChromaFormat::Invalid(_))
+        } else {
+            bit_depth_minus8 == 0 && self.chroma_format == ChromaFormat::YUV420
+        };
+        let chroma_ok = chroma_ok
+            && (!self.separate_colour_planes || self.chroma_format == ChromaFormat::YUV444);
+        if !chroma_ok {
+            return Err(SpsBuilderError::UnsupportedChromaFormat {
+                chroma_format: self.chroma_format,
+                bit_depth: self.bit_depth,
+            });
+        }
+
+        let (width, height) = self.dimensions.ok_or(SpsBuilderError::MissingDimensions)?;
+        let invalid_dimensions = SpsBuilderError::InvalidDimensions { width, height };
+        let (crop_unit_x, crop_unit_y) = match self.chroma_format {
+            ChromaFormat::YUV420 => (2, 2),
+            ChromaFormat::YUV422 => (2, 1),
+            _ => (1, 1),
+        };
+        if width == 0 || height == 0 || width % crop_unit_x != 0 || height % crop_unit_y != 0
+            || width > u32::MAX - 15 || height > u32::MAX - 15 {
+            return Err(invalid_dimensions);
+        }
+        let width_mbs = width / 16 + u32::from(width % 16 != 0);
+        let height_mbs = height / 16 + u32::from(height % 16 != 0);
+        let frame_cropping = if width_mbs * 16 != width || height_mbs * 16 != height {
+            Some(FrameCropping {
+                left_offset: 0,
+                right_offset: (width_mbs * 16 - width) / crop_unit_x,
+                top_offset: 0,
+                bottom_offset: (height_mbs * 16 - height) / crop_unit_y,
+            })
+        } else {
+            None
+        };
+
+        let timing_info = match self.frame_rate {
+            Some((num, den)) if num == 0 || den == 0 || num > u32::MAX / 2 => {
+                return Err(SpsBuilderError::InvalidFrameRate { num, den });
+            },
+            Some((num, den)) => Some(TimingInfo {
+                num_units_in_tick: den,
+                time_scale: 2 * num,
+                fixed_frame_rate_flag: true,
+            }),
+            None => None,
+        };
+
+        // frame_num must be able to distinguish all the reference frames; allow plenty of room.
+        let mut log2_max_frame_num = 4;
+        while log2_max_frame_num < 16 && (1u32 << log2_max_frame_num) < 2 * self.max_num_ref_frames {
+            log2_max_frame_num += 1;
+        }
+        let pic_order_cnt = match self.max_num_reorder_frames {
+            Some(n) if n > 0 => PicOrderCntType::TypeZero {
+                log2_max_pic_order_cnt_lsb_minus4: std::cmp::min(log2_max_frame_num + 1, 16) - 4,
+            },
+            _ => PicOrderCntType::TypeTwo,
+        };
+        let bitstream_restrictions = self.max_num_reorder_frames.map(|max_num_reorder_frames| BitstreamRestrictions {
+            motion_vectors_over_pic_boundaries_flag: true,
+            max_bytes_per_pic_denom: 0,
+            max_bits_per_mb_denom: 0,
+            log2_max_mv_length_horizontal: 15,
+            log2_max_mv_length_vertical: 15,
+            max_num_reorder_frames,
+            max_dec_frame_buffering: std::cmp::max(self.max_num_ref_frames, max_num_reorder_frames),
+        });
+        let aspect_ratio_info = self.sample_aspect_ratio.map(|(w, h)| AspectRatioInfo::from_ratio(w, h));
+        let video_signal_type = self.video_full_range.map(|video_full_range_flag| VideoSignalType {
+            video_format: VideoFormat::Unspecified,
+            video_full_range_flag,
+            colour_description: None,
+        });
+        let vui_parameters = if timing_info.is_some() || bitstream_restrictions.is_some()
+            || aspect_ratio_info.is_some() || video_signal_type.is_some() {
+            Some(VuiParameters {
+                aspect_ratio_info,
+                overscan_appropriate: OverscanAppropriate::Unspecified,
+                video_signal_type,
+                chroma_loc_info: None,
+                timing_info,
+                nal_hrd_parameters: None,
+                vcl_hrd_parameters: None,
+                low_delay_hrd_flag: None,
+                pic_struct_present_flag: false,
+                bitstream_restrictions,
+            })
+        } else {
+            None
+        };
+
+        let sps = SeqParameterSet {
+            profile_idc,
+            constraint_flags: ConstraintFlags(constraint_flags),
+            level_idc,
+            seq_parameter_set_id: self.seq_parameter_set_id,
+            chroma_info: ChromaInfo {
+                chroma_format: self.chroma_format,
+                separate_colour_plane_flag: self.separate_colour_planes,
+                bit_depth_luma_minus8: bit_depth_minus8,
+                bit_depth_chroma_minus8: bit_depth_minus8,
+                qpprime_y_zero_transform_bypass_flag: false,
+                seq_scaling_matrix_present_flag: false,
+                scaling_matrix: SeqScalingMatrix::default(),
+            },
+            log2_max_frame_num_minus4: log2_max_frame_num - 4,
+            pic_order_cnt,
+            max_num_ref_frames: self.max_num_ref_frames,
+            gaps_in_frame_num_value_allowed_flag: false,
+            pic_width_in_mbs_minus1: width_mbs - 1,
+            pic_height_in_map_units_minus1: height_mbs - 1,
+            frame_mbs_flags: FrameMbsFlags::Frames,
+            direct_8x8_inference_flag: true,
+            frame_cropping,
+            vui_parameters,
+        };
+        if let Err(ProfileError::ConstraintsViolated(_)) = self.profile.check(&sps) {
+            return Err(SpsBuilderError::UnsupportedChromaFormat {
+                chroma_format: self.chroma_format,
+                bit_depth: self.bit_depth,
+            });
+        }
+        let fps = self.frame_rate.map(|(num, den)| f64::from(num) / f64::from(den));
+        self.level.check(&sps, fps).map_err(SpsBuilderError::Level)?;
+        Ok(sps)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -1005,9 +2878,489 @@ mod test {
            4B BC B8 50");
         let sps = SeqParameterSet::from_bytes(&data[..]).unwrap();
         println!("sps: {:#?}", sps);
+        assert_eq!(sps.pixel_dimensions().unwrap(), (704, 480));
+        assert_eq!(sps.display_aspect_ratio().unwrap(), Some((16, 9)));
         assert_eq!(sps.vui_parameters.unwrap().aspect_ratio_info.unwrap().get(), Some((40, 33)));
     }
 
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        // the Dahua SPS has VUI parameters; also give it a scaling matrix, whose 8x8 lists are
+        // serialized by hand.
+        let data = hex!(
+          "64 00 16 AC 1B 1A 80 B0 3D FF FF
+           00 28 00 21 6E 0C 0C 0C 80 00 01
+           F4 00 00 27 10 74 30 07 D0 00 07
+           A1 25 DE 5C 68 60 0F A0 00 0F 42
+           4B BC B8 50");
+        let mut sps = SeqParameterSet::from_bytes(&data[..]).unwrap();
+        sps.chroma_info.seq_scaling_matrix_present_flag = true;
+        sps.chroma_info.scaling_matrix = SeqScalingMatrix::read(&mut BitReaderSlice::new(&hex!("84 50 80 21 08 44")[..]), 1).unwrap();
+        let json = serde_json::to_string(&sps).unwrap();
+        assert_eq!(serde_json::from_str::<SeqParameterSet>(&json).unwrap(), sps);
+    }
+
+    #[test]
+    fn scaling_matrix() {
+        // Intra Y 4x4 uses the default, Intra Cb falls back to it, Intra Cr is flat 16, the
+        // inter lists and Intra Y 8x8 are absent, and Inter Y 8x8 uses the default.
+        let data = hex!("84 50 80 21 08 44");
+        let m = SeqScalingMatrix::read(&mut BitReaderSlice::new(&data[..]), 1).unwrap();
+        assert_eq!(m.scaling_list4x4, [
+            DEFAULT_4X4_INTRA,
+            DEFAULT_4X4_INTRA,
+            [16; 16],
+            DEFAULT_4X4_INTER,
+            DEFAULT_4X4_INTER,
+            DEFAULT_4X4_INTER,
+        ]);
+        assert_eq!(m.scaling_list8x8[0], DEFAULT_8X8_INTRA);
+        assert_eq!(m.scaling_list8x8[1], DEFAULT_8X8_INTER);
+        assert_eq!(m.scaling_list8x8[4], DEFAULT_8X8_INTRA);
+        assert_eq!(m.scaling_list8x8[5], DEFAULT_8X8_INTER);
+
+        // explicit values, with a zero delta repeating the last value to the end
+        let list = ScalingList::read(&mut BitReaderSlice::new(&hex!("48 2A")[..]), 16).unwrap();
+        assert_eq!(list, ScalingList::List(vec![9, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10]));
+
+        assert_eq!(SeqScalingMatrix::default().scaling_list8x8[3], [16; 64]);
+    }
+
+    #[test]
+    fn write() {
+        let inputs: [&[u8]; 2] = [
+            &hex!(
+               "64 00 0A AC 72 84 44 26 84 00 00
+                00 04 00 00 00 CA 3C 48 96 11 80")[..],
+            &hex!(
+              "64 00 16 AC 1B 1A 80 B0 3D FF FF
+               00 28 00 21 6E 0C 0C 0C 80 00 01
+               F4 00 00 27 10 74 30 07 D0 00 07
+               A1 25 DE 5C 68 60 0F A0 00 0F 42
+               4B BC B8 50")[..],
+        ];
+        for data in inputs.iter() {
+            let sps = SeqParameterSet::from_bytes(data).unwrap();
+            assert_eq!(&sps.to_bytes().unwrap()[..], *data);
+        }
+
+        // edit the aspect ratio, and read it back.
+        let mut sps = SeqParameterSet::from_bytes(inputs[1]).unwrap();
+        sps.vui_parameters.as_mut().unwrap().aspect_ratio_info = Some(AspectRatioInfo::Extended(4, 3));
+        let sps2 = SeqParameterSet::from_bytes(&sps.to_bytes().unwrap()).unwrap();
+        assert_eq!(format!("{:?}", sps), format!("{:?}", sps2));
+        assert_eq!(sps2.display_aspect_ratio().unwrap(), Some((88, 45)));
+
+        // the various field types, and an SPS without VUI.
+        let mut sps = sps_for_crop(ChromaFormat::YUV444, FrameMbsFlags::Fields { mb_adaptive_frame_field_flag: true },
+                                   33, FrameCropping { left_offset: 1, right_offset: 2, top_offset: 3, bottom_offset: 4 });
+        sps.profile_idc = ProfileIdc(244);
+        sps.chroma_info.separate_colour_plane_flag = true;
+        sps.chroma_info.bit_depth_luma_minus8 = 2;
+        sps.chroma_info.seq_scaling_matrix_present_flag = true;
+        sps.chroma_info.scaling_matrix.scaling_list4x4[2] = DEFAULT_4X4_INTRA;
+        sps.chroma_info.scaling_matrix.scaling_list8x8[5][63] = 200;
+        sps.pic_order_cnt = PicOrderCntType::TypeOne {
+            delta_pic_order_always_zero_flag: true,
+            offset_for_non_ref_pic: -5,
+            offset_for_top_to_bottom_field: 7,
+            offsets_for_ref_frame: vec![1, -1, 0],
+        };
+        let sps2 = SeqParameterSet::from_bytes(&sps.to_bytes().unwrap()).unwrap();
+        assert_eq!(format!("{:?}", sps), format!("{:?}", sps2));
+
+        let mut sps = sps2;
+        sps.pic_order_cnt = PicOrderCntType::TypeOne {
+            delta_pic_order_always_zero_flag: false,
+            offset_for_non_ref_pic: 0,
+            offset_for_top_to_bottom_field: 0,
+            offsets_for_ref_frame: vec![0; 256],
+        };
+        assert!(matches!(sps.to_bytes(), Err(BitWriterError::ValueOutOfRange { .. })));
+    }
+
+    #[test]
+    fn builder() {
+        let sps = SpsBuilder::new(Profile::High, Level::L4)
+            .dimensions(1920, 1080)
+            .frame_rate(30000, 1001)
+            .max_num_ref_frames(4)
+            .max_num_reorder_frames(2)
+            .sample_aspect_ratio(1, 1)
+            .build()
+            .unwrap();
+        let sps = SeqParameterSet::from_bytes(&sps.to_bytes().unwrap()).unwrap();
+        assert_eq!(sps.profile(), Profile::High);
+        assert_eq!(sps.level(), Level::L4);
+        assert_eq!(sps.pixel_dimensions().unwrap(), (1920, 1080));
+        assert_eq!(sps.frame_cropping, Some(FrameCropping { left_offset: 0, right_offset: 0, top_offset: 0, bottom_offset: 4 }));
+        assert!((sps.fps().unwrap().as_f64() - 29.97).abs() < 0.001);
+        assert_eq!(sps.log2_max_frame_num(), 4);
+        assert!(matches!(sps.pic_order_cnt, PicOrderCntType::TypeZero { log2_max_pic_order_cnt_lsb_minus4: 1 }));
+        assert_eq!(sps.display_aspect_ratio().unwrap(), Some((16, 9)));
+        let vui = sps.vui_parameters.unwrap();
+        assert!(matches!(vui.aspect_ratio_info, Some(AspectRatioInfo::Ratio1_1)));
+        assert_eq!(vui.bitstream_restrictions.unwrap().max_dec_frame_buffering, 4);
+
+        let sps = SpsBuilder::new(Profile::ConstrainedBaseline, Level::L1_b)
+            .dimensions(176, 144)
+            .build()
+            .unwrap();
+        assert_eq!(sps.rfc6381().to_string(), "avc1.42D00B");
+        assert!(sps.vui_parameters.is_none());
+        assert!(sps.frame_cropping.is_none());
+        assert!(matches!(sps.pic_order_cnt, PicOrderCntType::TypeTwo));
+        let sps = SpsBuilder::new(Profile::High, Level::L1_b).dimensions(176, 144).build().unwrap();
+        assert_eq!((sps.level_idc, sps.level()), (9, Level::L1_b));
+
+        // constraint_set3_flag signals the Intra profiles, so their level 1b is level_idc 9.
+        for &profile in &[Profile::High10Intra, Profile::High422Intra, Profile::High444Intra,
+                          Profile::CAVLC444Intra] {
+            for &level in &[Level::L1_1, Level::L1_b] {
+                let sps = SpsBuilder::new(profile, level).dimensions(176, 144).build().unwrap();
+                let parsed = SeqParameterSet::from_bytes(&sps.to_bytes().unwrap()).unwrap();
+                assert_eq!((parsed.profile(), parsed.level()), (profile, level));
+            }
+        }
+
+        let sps = SpsBuilder::new(Profile::High422, Level::L3)
+            .dimensions(720, 575)
+            .chroma_format(ChromaFormat::YUV422)
+            .bit_depth(10)
+            .build()
+            .unwrap();
+        let sps = SeqParameterSet::from_bytes(&sps.to_bytes().unwrap()).unwrap();
+        assert_eq!(sps.pixel_dimensions().unwrap(), (720, 575));
+        assert_eq!(sps.chroma_info.bit_depth_luma_minus8, 2);
+
+        assert_eq!(SpsBuilder::new(Profile::High, Level::L4).build().unwrap_err(), SpsBuilderError::MissingDimensions);
+        assert_eq!(SpsBuilder::new(Profile::High, Level::L4).dimensions(1919, 1080).build().unwrap_err(),
+                   SpsBuilderError::InvalidDimensions { width: 1919, height: 1080 });
+        assert_eq!(SpsBuilder::new(Profile::High, Level::L4).dimensions(1920, 1080).frame_rate(30, 0).build().unwrap_err(),
+                   SpsBuilderError::InvalidFrameRate { num: 30, den: 0 });
+        assert_eq!(SpsBuilder::new(Profile::Main, Level::L4).dimensions(1920, 1080).bit_depth(10).build().unwrap_err(),
+                   SpsBuilderError::UnsupportedChromaFormat { chroma_format: ChromaFormat::YUV420, bit_depth: 10 });
+        assert_eq!(SpsBuilder::new(Profile::ScalableHigh, Level::L4).dimensions(1920, 1080).build().unwrap_err(),
+                   SpsBuilderError::UnsupportedProfile(Profile::ScalableHigh));
+        // High has chroma_format_idc in its SPS, but permits only 4:2:0 (and monochrome).
+        assert_eq!(SpsBuilder::new(Profile::High, Level::L4).dimensions(1920, 1080).chroma_format(ChromaFormat::YUV444).build().unwrap_err(),
+                   SpsBuilderError::UnsupportedChromaFormat { chroma_format: ChromaFormat::YUV444, bit_depth: 8 });
+        assert_eq!(SpsBuilder::new(Profile::High10, Level::L4).dimensions(1920, 1080).bit_depth(12).build().unwrap_err(),
+                   SpsBuilderError::UnsupportedChromaFormat { chroma_format: ChromaFormat::YUV420, bit_depth: 12 });
+        assert!(SpsBuilder::new(Profile::High444, Level::L4).dimensions(1920, 1080).chroma_format(ChromaFormat::YUV444).build().is_ok());
+        assert!(matches!(
+            SpsBuilder::new(Profile::High, Level::L3).dimensions(1920, 1080).build(),
+            Err(SpsBuilderError::Level(LevelError::LimitsExceeded(_)))
+        ));
+        assert!(matches!(AspectRatioInfo::from_ratio(40, 33), AspectRatioInfo::Ratio40_33));
+        assert!(matches!(AspectRatioInfo::from_ratio(5, 4), AspectRatioInfo::Extended(5, 4)));
+    }
+
+    #[test]
+    fn write_scaling_matrix() {
+        let data = hex!("84 50 80 21 08 44");
+        let m = SeqScalingMatrix::read(&mut BitReaderSlice::new(&data[..]), 1).unwrap();
+        let mut w = BitWriterVec::new();
+        m.write_lists(&mut w, 8, None).unwrap();
+        let m2 = SeqScalingMatrix::read_lists(&mut BitReaderSlice::new(&w.into_inner()), 8, None).unwrap();
+        assert_eq!(m, m2);
+
+        // rule B falls back to the SPS matrix.
+        let mut pps = m.clone();
+        pps.scaling_list4x4[3] = [20; 16];
+        let mut w = BitWriterVec::new();
+        pps.write_lists(&mut w, 6, Some(&m)).unwrap();
+        let pps2 = SeqScalingMatrix::read_lists(&mut BitReaderSlice::new(&w.into_inner()), 6, Some(&m)).unwrap();
+        assert_eq!(pps, pps2);
+
+        let mut w = BitWriterVec::new();
+        ScalingList::List(vec![9, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10]).write(&mut w).unwrap();
+        assert_eq!(w.into_inner(), hex!("48 2A"));
+    }
+
+    #[test]
+    fn hrd() {
+        let data = hex!(
+          "64 00 16 AC 1B 1A 80 B0 3D FF FF
+           00 28 00 21 6E 0C 0C 0C 80 00 01
+           F4 00 00 27 10 74 30 07 D0 00 07
+           A1 25 DE 5C 68 60 0F A0 00 0F 42
+           4B BC B8 50");
+        let sps = SeqParameterSet::from_bytes(&data[..]).unwrap();
+        let vui = sps.vui_parameters.unwrap();
+        let hrd = vui.nal_hrd_parameters.unwrap();
+        assert_eq!(hrd.cpb_cnt(), 1);
+        assert_eq!(hrd.cpb_specs[0].bit_rate_value_minus1, 999);
+        assert_eq!(hrd.bit_rate(0), Some(1_024_000));
+        assert_eq!(hrd.cpb_size(0), Some(4_000_000));
+        assert_eq!(hrd.cbr(0), Some(false));
+        assert_eq!(hrd.bit_rate(1), None);
+        assert_eq!(hrd.initial_cpb_removal_delay_length(), 24);
+        assert_eq!(hrd.cpb_removal_delay_length(), 16);
+        assert_eq!(hrd.dpb_output_delay_length(), 6);
+        assert!(vui.vcl_hrd_parameters.is_some());
+        assert_eq!(vui.low_delay_hrd_flag, Some(false));
+    }
+
+    #[test]
+    fn fps() {
+        let timing = TimingInfo { num_units_in_tick: 1001, time_scale: 60000, fixed_frame_rate_flag: true };
+        let fps = timing.fps().unwrap();
+        assert_eq!(fps, FrameRate { num: 60000, den: 2002, fixed_frame_rate: true });
+        assert!((fps.as_f64() - 29.97).abs() < 0.001);
+        assert_eq!(fps.tick_rate(), (60000, 1001));
+        assert_eq!(TimingInfo { num_units_in_tick: 0, ..timing }.fps(), None);
+
+        let data = hex!(
+          "64 00 16 AC 1B 1A 80 B0 3D FF FF
+           00 28 00 21 6E 0C 0C 0C 80 00 01
+           F4 00 00 27 10 74 30 07 D0 00 07
+           A1 25 DE 5C 68 60 0F A0 00 0F 42
+           4B BC B8 50");
+        let sps = SeqParameterSet::from_bytes(&data[..]).unwrap();
+        let timing = sps.vui_parameters.as_ref().unwrap().timing_info.as_ref().unwrap();
+        assert_eq!(sps.fps(), timing.fps());
+        assert!(sps.fps().is_some());
+    }
+
+    #[test]
+    fn dyn_bit_read() {
+        let data = hex!(
+           "64 00 0A AC 72 84 44 26 84 00 00
+            00 04 00 00 00 CA 3C 48 96 11 80");
+        let mut readers: Vec<Box<dyn BitRead>> = vec![
+            Box::new(BitReaderSlice::new(&data[..])),
+            Box::new(crate::rbsp::RbspBitReader::new(&data[..])),
+        ];
+        for r in &mut readers {
+            let sps = SeqParameterSet::from_bits(&mut **r).unwrap();
+            assert_eq!((64, 64), sps.pixel_dimensions().unwrap());
+        }
+    }
+
+    #[test]
+    fn error_location() {
+        let data = hex!(
+          "64 00 16 AC 1B 1A 80 B0 3D FF FF
+           00 28 00 21 6E 0C 0C 0C 80 00 01
+           F4 00 00 27 10 74 30 07 D0 00 07
+           A1 25 DE 5C 68 60 0F A0 00 0F 42
+           4B BC B8 50");
+        let e = SeqParameterSet::from_bytes(&data[..30]).unwrap_err();
+        let loc = e.location().unwrap();
+        assert_eq!(loc.scopes(), &["sps", "vui", "nal_hrd"]);
+        assert_eq!(loc.bit_offset(), 238);
+        assert_eq!(loc.to_string(), "sps.vui.nal_hrd at bit 238");
+        assert!(matches!(e, SpsError::InContext(_, ref inner) if matches!(**inner, SpsError::RbspReaderError(_))));
+
+        // every truncation gets a location, with "sps" outermost
+        for len in 0..data.len() {
+            let e = SeqParameterSet::from_bytes(&data[..len]).unwrap_err();
+            assert_eq!(e.location().unwrap().scopes()[0], "sps");
+        }
+    }
+
+    fn sps_for_crop(chroma_format: ChromaFormat, frame_mbs_flags: FrameMbsFlags, height_minus1: u32, frame_cropping: FrameCropping) -> SeqParameterSet {
+        SeqParameterSet {
+            profile_idc: ProfileIdc(0),
+            constraint_flags: ConstraintFlags(0),
+            level_idc: 0,
+            seq_parameter_set_id: ParamSetId::from_u32(0).unwrap(),
+            chroma_info: ChromaInfo {
+                chroma_format,
+                separate_colour_plane_flag: false,
+                bit_depth_luma_minus8: 0,
+                bit_depth_chroma_minus8: 0,
+                qpprime_y_zero_transform_bypass_flag: false,
+                seq_scaling_matrix_present_flag: false,
+                scaling_matrix: Default::default()
+            },
+            log2_max_frame_num_minus4: 0,
+            pic_order_cnt: PicOrderCntType::TypeTwo,
+            max_num_ref_frames: 0,
+            frame_cropping: Some(frame_cropping),
+            pic_width_in_mbs_minus1: 119,
+            pic_height_in_map_units_minus1: height_minus1,
+            frame_mbs_flags,
+            gaps_in_frame_num_value_allowed_flag: false,
+            direct_8x8_inference_flag: false,
+            vui_parameters: None
+        }
+    }
+
+    #[test]
+    fn crop_units() {
+        let fields = FrameMbsFlags::Fields { mb_adaptive_frame_field_flag: false };
+        let crop = FrameCropping { left_offset: 0, right_offset: 2, top_offset: 0, bottom_offset: 2 };
+        // a 1920x1088 picture, with the actual cropping depending on the crop units,
+        let cases = [
+            (ChromaFormat::YUV420, FrameMbsFlags::Frames, 67, (2, 2), (1916, 1084)),
+            (ChromaFormat::YUV420, fields.clone(), 33, (2, 4), (1916, 1080)),
+            (ChromaFormat::YUV422, fields.clone(), 33, (2, 2), (1916, 1084)),
+            (ChromaFormat::YUV444, fields.clone(), 33, (1, 2), (1918, 1084)),
+            (ChromaFormat::Monochrome, fields, 33, (1, 2), (1918, 1084)),
+        ];
+        for (chroma_format, frame_mbs_flags, height_minus1, unit, dims) in cases.iter().cloned() {
+            let sps = sps_for_crop(chroma_format, frame_mbs_flags, height_minus1, crop.clone());
+            assert_eq!(sps.crop_unit(), unit, "{:?}", chroma_format);
+            assert_eq!(sps.pixel_dimensions().unwrap(), dims, "{:?}", chroma_format);
+        }
+
+        // separately coded colour planes crop in units of luma samples,
+        let mut sps = sps_for_crop(ChromaFormat::YUV444, FrameMbsFlags::Frames, 67, crop);
+        sps.chroma_info.separate_colour_plane_flag = true;
+        assert_eq!(sps.chroma_array_type(), ChromaFormat::Monochrome);
+        assert_eq!(sps.crop_unit(), (1, 1));
+
+        // cropping away exactly all the pixels is an error too,
+        let crop = FrameCropping { left_offset: 480, right_offset: 480, top_offset: 0, bottom_offset: 0 };
+        let sps = sps_for_crop(ChromaFormat::YUV420, FrameMbsFlags::Frames, 67, crop);
+        assert!(matches!(sps.pixel_dimensions(), Err(SpsError::CroppingError(_))));
+    }
+
+    #[test]
+    fn profiles() {
+        let p = |idc: u8, flags: u8| Profile::from_profile_idc_and_constraint_flags(
+            ProfileIdc(idc), ConstraintFlags(flags));
+        assert_eq!(p(66, 0xc0), Profile::ConstrainedBaseline);
+        assert_eq!(p(66, 0x80), Profile::Baseline);
+        assert_eq!(p(77, 0x40), Profile::Main);
+        assert_eq!(p(100, 0x08), Profile::ProgressiveHigh);
+        assert_eq!(p(100, 0x0c), Profile::ConstrainedHigh);
+        assert_eq!(p(110, 0x10), Profile::High10Intra);
+        assert_eq!(p(122, 0x10), Profile::High422Intra);
+        assert_eq!(p(244, 0x00), Profile::High444);
+        assert_eq!(p(244, 0x10), Profile::High444Intra);
+        assert_eq!(p(44, 0x10), Profile::CAVLC444Intra);
+        assert_eq!(p(83, 0x04), Profile::ScalableConstrainedBaseline);
+        assert_eq!(p(86, 0x10), Profile::ScalableHighIntra);
+        assert_eq!(p(86, 0x04), Profile::ScalableConstrainedHigh);
+        assert_eq!(p(134, 0x00), Profile::MFCHigh);
+        assert_eq!(p(1, 0x00), Profile::Unknown(1));
+        for &idc in &[66, 77, 88, 100, 110, 122, 244, 44, 83, 86, 118, 128, 134, 135, 138, 139] {
+            for &flags in &[0x00, 0x10, 0x0c, 0x44] {
+                assert_eq!(p(idc, flags).profile_idc(), idc);
+            }
+        }
+        assert!(p(110, 0x10).is_intra());
+        assert!(!p(110, 0x00).is_intra());
+    }
+
+    #[test]
+    fn level_1b() {
+        let l = |idc: u8, flags: u8, level_idc: u8| Level::from_profile_idc_constraint_flags_and_level_idc(
+            ProfileIdc(idc), ConstraintFlags(flags), level_idc);
+        for &idc in &[66, 77, 88] {
+            assert_eq!(l(idc, 0x10, 11), Level::L1_b);
+            assert_eq!(l(idc, 0x00, 11), Level::L1_1);
+        }
+        // With these profiles constraint_set3_flag signals the Intra profile instead.
+        for &idc in &[110, 122, 244, 44] {
+            assert_eq!(l(idc, 0x10, 11), Level::L1_1);
+            assert_eq!(l(idc, 0x10, 9), Level::L1_b);
+        }
+        assert_eq!(l(100, 0x00, 9), Level::L1_b);
+    }
+    }
+
+    #[test]
+    fn level_limits() {
+        let crop = FrameCropping { left_offset: 0, right_offset: 0, top_offset: 0, bottom_offset: 4 };
+        let mut sps = sps_for_crop(ChromaFormat::YUV420, FrameMbsFlags::Frames, 67, crop);
+        sps.max_num_ref_frames = 4;
+        assert_eq!(Level::L4.check(&sps, Some(30.0)), Ok(()));
+        assert_eq!(Level::L4.check(&sps, Some(60.0)), Err(LevelError::LimitsExceeded(vec![
+            LevelViolation { limit: LevelLimit::MaxMbps, actual: 489600, max: 245760 },
+        ])));
+        assert_eq!(Level::L3_1.check(&sps, None), Err(LevelError::LimitsExceeded(vec![
+            LevelViolation { limit: LevelLimit::MaxFs, actual: 8160, max: 3600 },
+            LevelViolation { limit: LevelLimit::MaxDpbFrames, actual: 4, max: 2 },
+        ])));
+        assert_eq!(Level::Unknown(7).check(&sps, None), Err(LevelError::UnknownLevel(7)));
+        assert_eq!(Level::L1_b.limits().unwrap().max_br, 128);
+
+        // 8K at 60 frames per second requires level 6.1.
+        let sps = SpsBuilder::new(Profile::High, Level::L6_1)
+            .dimensions(7680, 4320)
+            .frame_rate(60, 1)
+            .build()
+            .unwrap();
+        let sps = SeqParameterSet::from_bytes(&sps.to_bytes().unwrap()).unwrap();
+        assert_eq!((sps.level_idc, sps.level()), (61, Level::L6_1));
+        assert_eq!(sps.max_dpb_frames(), Some(5));
+        assert_eq!(Level::L6.check(&sps, Some(60.0)), Err(LevelError::LimitsExceeded(vec![
+            LevelViolation { limit: LevelLimit::MaxMbps, actual: 7776000, max: 4177920 },
+        ])));
+        assert_eq!(Level::L5_2.check(&sps, Some(60.0)).map_err(|_| ()), Err(()));
+    }
+
+    #[test]
+    fn dpb_size() {
+        let crop = FrameCropping { left_offset: 0, right_offset: 0, top_offset: 0, bottom_offset: 4 };
+        let mut sps = sps_for_crop(ChromaFormat::YUV420, FrameMbsFlags::Frames, 67, crop);
+        assert_eq!(sps.frame_size_in_mbs(), 8160);
+        assert_eq!(sps.max_dpb_frames(), None);
+        assert_eq!(sps.max_num_reorder_frames(), None);
+        sps.level_idc = 40;
+        assert_eq!(sps.max_dpb_frames(), Some(4));
+        assert_eq!(sps.max_dec_frame_buffering(), Some(4));
+        assert_eq!(sps.max_num_reorder_frames(), Some(4));
+        sps.level_idc = 51;
+        assert_eq!(sps.max_dpb_frames(), Some(16));
+
+        // intra-only profiles need no DPB
+        sps.profile_idc = ProfileIdc(110);
+        sps.constraint_flags = ConstraintFlags(0x10);
+        assert_eq!(sps.max_num_reorder_frames(), Some(0));
+
+        let sps = SpsBuilder::new(Profile::High, Level::L4)
+            .dimensions(1920, 1080)
+            .max_num_ref_frames(3)
+            .max_num_reorder_frames(1)
+            .build()
+            .unwrap();
+        assert_eq!(sps.max_dec_frame_buffering(), Some(3));
+        assert_eq!(sps.max_num_reorder_frames(), Some(1));
+    }
+
+    #[test]
+    fn stream_change() {
+        use std::sync::{Arc, Mutex};
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let mut ctx = Context::default();
+        let c = changes.clone();
+        ctx.set_stream_change_handler(Some(Box::new(move |change: &StreamChange| c.lock().unwrap().push(change.clone()))));
+        let id = ParamSetId::from_u32(0).unwrap();
+        let build = |w, h| SpsBuilder::new(Profile::High, Level::L4).dimensions(w, h).build().unwrap();
+
+        ctx.put_seq_param_set(build(1920, 1080));
+        assert!(ctx.active_format().is_none());
+        ctx.activate_sps(id);
+        ctx.activate_sps(id);
+        // an identical SPS isn't a change, even once reactivated.
+        ctx.put_seq_param_set(build(1920, 1080));
+        ctx.activate_sps(id);
+        assert_eq!(changes.lock().unwrap().len(), 1);
+        assert_eq!(changes.lock().unwrap()[0].previous, None);
+
+        // a new SPS with the same id only takes effect when activated.
+        ctx.put_seq_param_set(build(1280, 720));
+        assert_eq!(changes.lock().unwrap().len(), 1);
+        ctx.activate_sps(id);
+        let change = changes.lock().unwrap()[1].clone();
+        assert!(change.dimensions_changed());
+        assert!(!change.chroma_format_changed());
+        assert!(!change.bit_depth_changed());
+        assert_eq!(change.previous.unwrap().dimensions, Some((1920, 1080)));
+        assert_eq!(change.current.dimensions, Some((1280, 720)));
+        assert_eq!(ctx.active_format(), Some(&change.current));
+
+        // activating an undefined SPS does nothing.
+        ctx.activate_sps(ParamSetId::from_u32(1).unwrap());
+        assert_eq!(changes.lock().unwrap().len(), 2);
+    }
+
     #[test]
     fn crop_removes_all_pixels() {
         let sps = SeqParameterSet {
@@ -1021,6 +3374,7 @@ mod test {
                 bit_depth_luma_minus8: 0,
                 bit_depth_chroma_minus8: 0,
                 qpprime_y_zero_transform_bypass_flag: false,
+                seq_scaling_matrix_present_flag: false,
                 scaling_matrix: Default::default()
             },
             log2_max_frame_num_minus4: 0,
@@ -1043,4 +3397,380 @@ mod test {
         let dim = sps.pixel_dimensions();
         assert!(matches!(dim, Err(SpsError::CroppingError(_))));
     }
+
+    #[test]
+    fn retains_nal() {
+        use crate::annexb::AnnexBReader;
+        use crate::nal::{NalSwitch, UnitType};
+        use std::cell::RefCell;
+        // includes an emulation_prevention_three_byte, and is followed by trailing_zero_8bits.
+        let sps_nal = hex!(
+           "67 42 c0 1e b9 10 61 ff 78 08 80 00 00 03 00 80
+            00 00 19 71 30 06 d6 00 da f7 bd c0 7c 22 11 a8");
+        let mut data = vec![0, 0, 0, 1];
+        data.extend_from_slice(&sps_nal[..]);
+        data.extend_from_slice(&hex!("00 00 00 00 00 01 09 10")[..]);
+        let mut s = NalSwitch::default();
+        s.put_handler(UnitType::SeqParameterSet, Box::new(RefCell::new(rbsp::RbspDecoder::new(SeqParameterSetNalHandler::default()))));
+        let mut ctx = Context::default();
+        let mut r = AnnexBReader::new(s);
+        r.start(&mut ctx);
+        r.push(&mut ctx, &data[..]);
+        r.end_units(&mut ctx);
+        let id = ParamSetId::from_u32(0).unwrap();
+        assert_eq!(ctx.sps_nal_by_id(id), Some(&sps_nal[..]));
+
+        // the NAL is forgotten when the SPS is replaced without one.
+        let sps = ctx.sps_by_id(id).unwrap().clone();
+        ctx.put_seq_param_set(sps);
+        assert_eq!(ctx.sps_nal_by_id(id), None);
+    }
+
+    #[test]
+    fn from_nal() {
+        let sps_nal = hex!("67 42 c0 1e b9 10 61 ff 78 08 80 00 00 03 00 80 00 00 19 71 30 06 d6 00 da f7 bd c0 7c 22 11 a8");
+        let sps = SeqParameterSet::from_nal(&sps_nal[..]).unwrap();
+        assert_eq!(sps, SeqParameterSet::from_bytes(&rbsp::decode_nal(&sps_nal[1..])).unwrap());
+        assert!(matches!(SeqParameterSet::from_nal(&[]), Err(SpsError::EmptyNal)));
+        assert!(matches!(SeqParameterSet::from_nal(&hex!("e7 42")), Err(SpsError::NalHeader(NalHeaderError::ForbiddenZeroBit))));
+        assert!(matches!(
+            SeqParameterSet::from_nal(&hex!("68 E8 43 8F 13 21 30")),
+            Err(SpsError::IncorrectNalType(UnitType::PicParameterSet))
+        ));
+    }
+
+    #[test]
+    fn bounds() {
+        fn innermost(e: SpsError) -> SpsError {
+            match e {
+                SpsError::InContext(_, e) => innermost(*e),
+                e => e,
+            }
+        }
+        let sps = SpsBuilder::new(Profile::High, Level::L5_1)
+            .dimensions(1920, 1080)
+            .max_num_reorder_frames(2)
+            .build()
+            .unwrap();
+        let reparse = |edit: &dyn Fn(&mut SeqParameterSet)| {
+            let mut sps = sps.clone();
+            edit(&mut sps);
+            SeqParameterSet::from_bytes(&sps.to_bytes().unwrap()).map_err(innermost)
+        };
+        assert!(reparse(&|_| {}).is_ok());
+        assert!(reparse(&|sps| sps.max_num_ref_frames = 16).is_ok());
+        assert!(matches!(
+            reparse(&|sps| sps.max_num_ref_frames = 17),
+            Err(SpsError::FieldValueTooLarge { name: "max_num_ref_frames", value: 17 })
+        ));
+        assert!(matches!(
+            reparse(&|sps| sps.pic_width_in_mbs_minus1 = u32::MAX - 1),
+            Err(SpsError::FieldValueTooLarge { name: "pic_width_in_mbs_minus1", .. })
+        ));
+        // 1055 x 1055 macroblocks is within each dimension's limit, but not MaxFS.
+        assert!(matches!(
+            reparse(&|sps| {
+                sps.pic_width_in_mbs_minus1 = 1054;
+                sps.pic_height_in_map_units_minus1 = 1054;
+            }),
+            Err(SpsError::FieldValueTooLarge { name: "pic_height_in_map_units_minus1", .. })
+        ));
+        // with field coding, the frame is twice the height of the map units.
+        assert!(matches!(
+            reparse(&|sps| {
+                sps.pic_width_in_mbs_minus1 = 9;
+                sps.pic_height_in_map_units_minus1 = 599;
+                sps.frame_mbs_flags = FrameMbsFlags::Fields { mb_adaptive_frame_field_flag: false };
+            }),
+            Err(SpsError::FieldValueTooLarge { name: "pic_height_in_map_units_minus1", .. })
+        ));
+        assert!(matches!(
+            reparse(&|sps| sps.vui_parameters.as_mut().unwrap().bitstream_restrictions.as_mut().unwrap().max_dec_frame_buffering = 1000),
+            Err(SpsError::FieldValueTooLarge { name: "max_dec_frame_buffering", value: 1000 })
+        ));
+    }
+
+    #[test]
+    fn colour_description() {
+        let mut sps = SpsBuilder::new(Profile::High10, Level::L5_1)
+            .dimensions(3840, 2160)
+            .bit_depth(10)
+            .video_full_range(true)
+            .build()
+            .unwrap();
+        assert!(sps.colour_description().is_none());
+        assert!(sps.video_full_range());
+        sps.vui_parameters.as_mut().unwrap().video_signal_type.as_mut().unwrap().colour_description = Some(ColourDescription {
+            colour_primaries: 9,
+            transfer_characteristics: 16,
+            matrix_coefficients: 9,
+        });
+        let sps = SeqParameterSet::from_bytes(&sps.to_bytes().unwrap()).unwrap();
+        let c = sps.colour_description().unwrap();
+        assert_eq!(c.primaries(), ColourPrimaries::Bt2020);
+        assert_eq!(c.transfer(), TransferCharacteristics::Smpte2084);
+        assert_eq!(c.matrix(), MatrixCoefficients::Bt2020Ncl);
+        assert_eq!(TransferCharacteristics::from(18), TransferCharacteristics::Hlg);
+        assert_eq!(ColourPrimaries::from(3), ColourPrimaries::Reserved(3));
+        for v in 0..=255 {
+            assert_eq!(ColourPrimaries::from(v).as_u8(), v);
+            assert_eq!(TransferCharacteristics::from(v).as_u8(), v);
+            assert_eq!(MatrixCoefficients::from(v).as_u8(), v);
+        }
+    }
+
+    #[test]
+    fn compatible_with() {
+        use std::collections::HashSet;
+        let sps = SpsBuilder::new(Profile::High, Level::L4)
+            .dimensions(1920, 1080)
+            .frame_rate(30, 1)
+            .build()
+            .unwrap();
+        let mut cosmetic = sps.clone();
+        cosmetic.seq_parameter_set_id = ParamSetId::from_u32(1).unwrap();
+        cosmetic.vui_parameters.as_mut().unwrap().timing_info = None;
+        cosmetic.vui_parameters.as_mut().unwrap().aspect_ratio_info = Some(AspectRatioInfo::Ratio1_1);
+        assert!(cosmetic.compatible_with(&sps));
+        assert_ne!(cosmetic, sps);
+
+        let mut refs = sps.clone();
+        refs.max_num_ref_frames += 1;
+        assert!(!refs.compatible_with(&sps));
+        let mut poc = sps.clone();
+        poc.pic_order_cnt = PicOrderCntType::TypeZero { log2_max_pic_order_cnt_lsb_minus4: 4 };
+        assert!(!poc.compatible_with(&sps));
+        let mut depth = sps.clone();
+        depth.chroma_info.bit_depth_luma_minus8 = 2;
+        assert!(!depth.compatible_with(&sps));
+        let mut crop = sps.clone();
+        crop.frame_cropping = None;
+        assert!(!crop.compatible_with(&sps));
+
+        let mut set = HashSet::new();
+        set.insert(sps.clone());
+        assert!(set.contains(&SeqParameterSet::from_bytes(&sps.to_bytes().unwrap()).unwrap()));
+        assert!(!set.contains(&cosmetic));
+    }
+
+    #[test]
+    fn rewrite_vui() {
+        // an SPS which signals Default_4x4_Intra explicitly, which SeqParameterSet::to_bytes
+        // would shorten to useDefaultScalingMatrixFlag.
+        let mut w = BitWriterVec::new();
+        w.write_u_named("profile_idc", 8, 100).unwrap();
+        w.write_u_named("constraint_flags", 8, 0).unwrap();
+        w.write_u_named("level_idc", 8, 40).unwrap();
+        w.write_ue_named("seq_parameter_set_id", 0).unwrap();
+        w.write_ue_named("chroma_format_idc", 1).unwrap();
+        w.write_ue_named("bit_depth_luma_minus8", 0).unwrap();
+        w.write_ue_named("bit_depth_chroma_minus8", 0).unwrap();
+        w.write_bool_named("qpprime_y_zero_transform_bypass_flag", false).unwrap();
+        w.write_bool_named("seq_scaling_matrix_present_flag", true).unwrap();
+        w.write_bool_named("seq_scaling_list_present_flag", true).unwrap();
+        let mut last_scale = 8;
+        for &v in DEFAULT_4X4_INTRA.iter() {
+            w.write_se_named("delta_scale", i32::from(v) - last_scale).unwrap();
+            last_scale = i32::from(v);
+        }
+        for _ in 1..8 {
+            w.write_bool_named("seq_scaling_list_present_flag", false).unwrap();
+        }
+        w.write_ue_named("log2_max_frame_num_minus4", 0).unwrap();
+        w.write_ue_named("pic_order_cnt_type", 2).unwrap();
+        w.write_ue_named("max_num_ref_frames", 1).unwrap();
+        w.write_bool_named("gaps_in_frame_num_value_allowed_flag", false).unwrap();
+        w.write_ue_named("pic_width_in_mbs_minus1", 119).unwrap();
+        w.write_ue_named("pic_height_in_map_units_minus1", 67).unwrap();
+        w.write_bool_named("frame_mbs_only_flag", true).unwrap();
+        w.write_bool_named("direct_8x8_inference_flag", true).unwrap();
+        w.write_bool_named("frame_cropping_flag", false).unwrap();
+        w.write_bool_named("vui_parameters_present_flag", false).unwrap();
+        w.write_rbsp_trailing_bits().unwrap();
+        let rbsp = w.into_inner();
+        let sps = SeqParameterSet::from_bytes(&rbsp).unwrap();
+        assert_ne!(sps.to_bytes().unwrap(), rbsp);
+        assert_eq!(SeqParameterSet::rewrite_vui(&rbsp, |_, _| {}).unwrap(), rbsp);
+
+        // add a VUI; everything before it is unchanged.
+        let sar = SeqParameterSet::rewrite_vui(&rbsp, |_, vui| {
+            *vui = Some(VuiParameters {
+                aspect_ratio_info: Some(AspectRatioInfo::Ratio4_3),
+                overscan_appropriate: OverscanAppropriate::Unspecified,
+                video_signal_type: None,
+                chroma_loc_info: None,
+                timing_info: None,
+                nal_hrd_parameters: None,
+                vcl_hrd_parameters: None,
+                low_delay_hrd_flag: None,
+                pic_struct_present_flag: false,
+                bitstream_restrictions: None,
+            });
+        }).unwrap();
+        let sps2 = SeqParameterSet::from_bytes(&sar).unwrap();
+        assert_eq!(sps2.chroma_info, sps.chroma_info);
+        assert_eq!(sps2.vui_parameters.as_ref().unwrap().aspect_ratio_info, Some(AspectRatioInfo::Ratio4_3));
+        assert_eq!(SeqParameterSet::rewrite_vui(&sar, |_, vui| *vui = None).unwrap(), rbsp);
+
+        // fix the aspect ratio of an SPS with timing and HRD parameters.
+        let data = hex!(
+          "64 00 16 AC 1B 1A 80 B0 3D FF FF
+           00 28 00 21 6E 0C 0C 0C 80 00 01
+           F4 00 00 27 10 74 30 07 D0 00 07
+           A1 25 DE 5C 68 60 0F A0 00 0F 42
+           4B BC B8 50");
+        assert_eq!(SeqParameterSet::rewrite_vui(&data, |_, _| {}).unwrap(), &data[..]);
+        let fixed = SeqParameterSet::rewrite_vui(&data, |sps, vui| {
+            assert_eq!(sps.pixel_dimensions().unwrap(), (704, 480));
+            vui.as_mut().unwrap().aspect_ratio_info = Some(AspectRatioInfo::from_ratio(10, 11));
+        }).unwrap();
+        let mut expected = SeqParameterSet::from_bytes(&data).unwrap();
+        expected.vui_parameters.as_mut().unwrap().aspect_ratio_info = Some(AspectRatioInfo::Ratio10_11);
+        assert_eq!(SeqParameterSet::from_bytes(&fixed).unwrap(), expected);
+    }
+
+    #[test]
+    fn profile_check() {
+        let sps = SpsBuilder::new(Profile::High, Level::L4).dimensions(1920, 1080).build().unwrap();
+        assert_eq!(sps.profile().check(&sps), Ok(()));
+        assert_eq!(Profile::ConstrainedBaseline.check(&sps), Ok(()));
+
+        let mut bad = sps.clone();
+        bad.chroma_info.chroma_format = ChromaFormat::YUV422;
+        bad.chroma_info.bit_depth_luma_minus8 = 2;
+        bad.chroma_info.qpprime_y_zero_transform_bypass_flag = true;
+        bad.frame_mbs_flags = FrameMbsFlags::Fields { mb_adaptive_frame_field_flag: false };
+        assert_eq!(bad.profile().check(&bad), Err(ProfileError::ConstraintsViolated(vec![
+            ProfileViolation::ChromaFormat(ChromaFormat::YUV422),
+            ProfileViolation::BitDepthLuma { actual: 10, max: 8 },
+            ProfileViolation::TransformBypass,
+        ])));
+        assert_eq!(Profile::ProgressiveHigh.check(&bad).unwrap_err(), ProfileError::ConstraintsViolated(vec![
+            ProfileViolation::ChromaFormat(ChromaFormat::YUV422),
+            ProfileViolation::BitDepthLuma { actual: 10, max: 8 },
+            ProfileViolation::TransformBypass,
+            ProfileViolation::FieldCoding,
+        ]));
+        assert_eq!(Profile::High422.check(&bad), Err(ProfileError::ConstraintsViolated(vec![
+            ProfileViolation::TransformBypass,
+        ])));
+        assert_eq!(Profile::High444.check(&bad), Ok(()));
+        bad.chroma_info.bit_depth_chroma_minus8 = 8;
+        assert_eq!(Profile::High444.check(&bad), Err(ProfileError::ConstraintsViolated(vec![
+            ProfileViolation::BitDepthChroma { actual: 16, max: 14 },
+        ])));
+        assert_eq!(Profile::Unknown(1).check(&sps), Err(ProfileError::UnknownProfile(1)));
+    }
+
+    #[test]
+    fn derived_values() {
+        let mut sps = SpsBuilder::new(Profile::High, Level::L4)
+            .dimensions(1920, 1080)
+            .max_num_reorder_frames(2)
+            .build()
+            .unwrap();
+        sps.log2_max_frame_num_minus4 = 0;
+        sps.pic_order_cnt = PicOrderCntType::TypeZero { log2_max_pic_order_cnt_lsb_minus4: 12 };
+        assert_eq!(sps.max_frame_num(), Some(16));
+        assert_eq!(sps.max_pic_order_cnt_lsb(), Some(65536));
+        assert_eq!(sps.pic_size_in_map_units(), 120 * 68);
+        assert_eq!(sps.pic_size_in_mbs(false), 120 * 68);
+
+        sps.frame_mbs_flags = FrameMbsFlags::Fields { mb_adaptive_frame_field_flag: false };
+        sps.pic_height_in_map_units_minus1 = 33;
+        assert_eq!(sps.pic_size_in_map_units(), 120 * 34);
+        assert_eq!(sps.frame_size_in_mbs(), 120 * 68);
+        assert_eq!(sps.pic_size_in_mbs(true), 120 * 34);
+
+        sps.log2_max_frame_num_minus4 = 12;
+        assert_eq!(sps.max_frame_num(), Some(65536));
+        sps.log2_max_frame_num_minus4 = 28;
+        sps.pic_order_cnt = PicOrderCntType::TypeTwo;
+        assert_eq!(sps.max_frame_num(), None);
+        assert_eq!(sps.max_pic_order_cnt_lsb(), None);
+    }
+
+    #[test]
+    fn poc_type_one() {
+        let mut sps = SpsBuilder::new(Profile::Main, Level::L4).dimensions(1920, 1080).build().unwrap();
+        let offsets_for_ref_frame = vec![i32::MAX, i32::MAX, -3, i32::MIN + 1];
+        sps.pic_order_cnt = PicOrderCntType::TypeOne {
+            delta_pic_order_always_zero_flag: false,
+            offset_for_non_ref_pic: -2,
+            offset_for_top_to_bottom_field: 1,
+            offsets_for_ref_frame: offsets_for_ref_frame.clone(),
+        };
+        let sps = SeqParameterSet::from_bytes(&sps.to_bytes().unwrap()).unwrap();
+        match &sps.pic_order_cnt {
+            PicOrderCntType::TypeOne { offsets_for_ref_frame: parsed, .. } => assert_eq!(parsed, &offsets_for_ref_frame),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(sps.pic_order_cnt.expected_delta_per_pic_order_cnt_cycle(), Some(i64::from(i32::MAX) - 3));
+        assert_eq!(PicOrderCntType::TypeTwo.expected_delta_per_pic_order_cnt_cycle(), None);
+    }
+
+    #[test]
+    fn reorder_delay_frames() {
+        // without bitstream restrictions, POC type 2 still implies no reordering.
+        let sps = SpsBuilder::new(Profile::Main, Level::L4).dimensions(1920, 1080).build().unwrap();
+        assert_eq!(sps.pic_order_cnt, PicOrderCntType::TypeTwo);
+        assert_eq!(sps.max_num_reorder_frames(), Some(4));
+        assert_eq!(sps.reorder_delay_frames(), Some(0));
+
+        let mut sps = SpsBuilder::new(Profile::Main, Level::L4)
+            .dimensions(1920, 1080)
+            .max_num_reorder_frames(2)
+            .build()
+            .unwrap();
+        assert_eq!(sps.reorder_delay_frames(), Some(2));
+        sps.vui_parameters.as_mut().unwrap().bitstream_restrictions = None;
+        assert_eq!(sps.reorder_delay_frames(), Some(4));
+        sps.level_idc = 99;
+        assert_eq!(sps.reorder_delay_frames(), None);
+    }
+
+    #[test]
+    fn decodable_by() {
+        let sps = SpsBuilder::new(Profile::Main, Level::L3_1)
+            .dimensions(1280, 720)
+            .frame_rate(30, 1)
+            .build()
+            .unwrap();
+        assert_eq!(sps.decodable_by(Profile::Main, Level::L3_1), Ok(()));
+        assert_eq!(sps.decodable_by(Profile::High10, Level::L4), Ok(()));
+        assert_eq!(sps.decodable_by(Profile::ConstrainedBaseline, Level::L3_1),
+                   Err(vec![Incompatibility::Profile(Profile::Main)]));
+        assert_eq!(sps.decodable_by(Profile::High10Intra, Level::L3_1),
+                   Err(vec![Incompatibility::Profile(Profile::Main)]));
+        assert_eq!(sps.decodable_by(Profile::Main, Level::L3), Err(vec![
+            Incompatibility::Level(Level::L3_1),
+            Incompatibility::LevelLimit(LevelViolation { limit: LevelLimit::MaxFs, actual: 3600, max: 1620 }),
+            Incompatibility::LevelLimit(LevelViolation { limit: LevelLimit::MaxMbps, actual: 108000, max: 40500 }),
+        ]));
+        assert_eq!(sps.decodable_by(Profile::Unknown(1), Level::Unknown(2)), Err(vec![
+            Incompatibility::UnknownProfile(1),
+            Incompatibility::UnknownLevel(2),
+        ]));
+
+        // Constrained Baseline is decodable by Main and High decoders, but High 10 isn't
+        // decodable by a High decoder.
+        let sps = SpsBuilder::new(Profile::ConstrainedBaseline, Level::L3)
+            .dimensions(640, 480)
+            .build()
+            .unwrap();
+        assert_eq!(sps.decodable_by(Profile::Main, Level::L3), Ok(()));
+        assert_eq!(sps.decodable_by(Profile::Baseline, Level::L3), Ok(()));
+        assert_eq!(sps.decodable_by(Profile::ProgressiveHigh, Level::L3), Ok(()));
+        let sps = SpsBuilder::new(Profile::High10, Level::L4)
+            .dimensions(1920, 1080)
+            .bit_depth(10)
+            .build()
+            .unwrap();
+        assert_eq!(sps.decodable_by(Profile::High, Level::L4), Err(vec![
+            Incompatibility::Profile(Profile::High10),
+            Incompatibility::ProfileConstraint(ProfileViolation::BitDepthLuma { actual: 10, max: 8 }),
+            Incompatibility::ProfileConstraint(ProfileViolation::BitDepthChroma { actual: 10, max: 8 }),
+        ]));
+        assert_eq!(sps.decodable_by(Profile::High422, Level::L4), Ok(()));
+    }
 }
@@ -0,0 +1,181 @@
+//! Sequence parameter set extension (`nal_unit_type` `13`), which describes the auxiliary coded
+//! pictures (such as alpha planes) accompanying the primary coded pictures of a stream.
+
+use super::{NalHandler, NalHeader, NalInterest, UnitType};
+use super::pps::{ParamSetId, ParamSetIdError};
+use crate::rbsp::{BitRead, BitReaderSlice, RbspBitReaderError};
+use crate::{Context, ErrorKind, Severity};
+use std::marker;
+
+#[derive(Debug)]
+pub enum SpsExtensionError {
+    RbspReaderError(RbspBitReaderError),
+    BadSeqParamSetId(ParamSetIdError),
+    /// `aux_format_idc` must be between 0 and 3 inclusive.
+    AuxFormatIdcOutOfRange(u32),
+    /// `bit_depth_aux_minus8` must be between 0 and 4 inclusive.
+    BitDepthAuxOutOfRange(u32),
+}
+impl From<RbspBitReaderError> for SpsExtensionError {
+    fn from(e: RbspBitReaderError) -> Self {
+        SpsExtensionError::RbspReaderError(e)
+    }
+}
+
+/// How the samples of auxiliary pictures are to be interpreted, per `aux_format_idc`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AuxFormat {
+    /// `1`: alpha, to be multiplied with the primary picture's samples (straight alpha).
+    Alpha,
+    /// `2`: alpha, where the primary picture's samples are already multiplied (premultiplied).
+    PremultipliedAlpha,
+    /// `3`: unspecified.
+    Unspecified,
+}
+
+/// The auxiliary picture parameters present when `aux_format_idc` is non-zero.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AuxPictureInfo {
+    pub aux_format: AuxFormat,
+    pub bit_depth_aux_minus8: u8,
+    pub alpha_incr_flag: bool,
+    pub alpha_opaque_value: u16,
+    pub alpha_transparent_value: u16,
+}
+impl AuxPictureInfo {
+    /// The bit depth of auxiliary picture samples, from 8 to 12.
+    pub fn bit_depth_aux(&self) -> u8 {
+        self.bit_depth_aux_minus8 + 8
+    }
+}
+
+/// The `seq_parameter_set_extension_rbsp()` syntax.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SeqParameterSetExtension {
+    pub seq_parameter_set_id: ParamSetId,
+    /// `None` when `aux_format_idc` is `0`, meaning there are no auxiliary pictures.
+    pub aux_picture_info: Option<AuxPictureInfo>,
+    pub additional_extension_flag: bool,
+}
+impl SeqParameterSetExtension {
+    /// Parses the RBSP of an SPS extension NAL unit (i.e. following the NAL header byte).
+    pub fn from_bytes(buf: &[u8]) -> Result<SeqParameterSetExtension, SpsExtensionError> {
+        Self::from_bits(&mut BitReaderSlice::new(buf))
+    }
+
+    /// Parses the `seq_parameter_set_extension_rbsp()` syntax from the given reader, which is
+    /// expected to be positioned just after the NAL header.
+    pub fn from_bits<R: BitRead + ?Sized>(r: &mut R) -> Result<SeqParameterSetExtension, SpsExtensionError> {
+        let seq_parameter_set_id = ParamSetId::from_u32(r.read_ue_named("seq_parameter_set_id")?)
+            .map_err(SpsExtensionError::BadSeqParamSetId)?;
+        let aux_format = match r.read_ue_named("aux_format_idc")? {
+            0 => None,
+            1 => Some(AuxFormat::Alpha),
+            2 => Some(AuxFormat::PremultipliedAlpha),
+            3 => Some(AuxFormat::Unspecified),
+            other => return Err(SpsExtensionError::AuxFormatIdcOutOfRange(other)),
+        };
+        let aux_picture_info = match aux_format {
+            None => None,
+            Some(aux_format) => {
+                let bit_depth_aux_minus8 = r.read_ue_named("bit_depth_aux_minus8")?;
+                if bit_depth_aux_minus8 > 4 {
+                    return Err(SpsExtensionError::BitDepthAuxOutOfRange(bit_depth_aux_minus8));
+                }
+                let alpha_bits = bit_depth_aux_minus8 as u8 + 9;
+                Some(AuxPictureInfo {
+                    aux_format,
+                    bit_depth_aux_minus8: bit_depth_aux_minus8 as u8,
+                    alpha_incr_flag: r.read_bool_named("alpha_incr_flag")?,
+                    alpha_opaque_value: r.read_u16(alpha_bits)?,
+                    alpha_transparent_value: r.read_u16(alpha_bits)?,
+                })
+            },
+        };
+        Ok(SeqParameterSetExtension {
+            seq_parameter_set_id,
+            aux_picture_info,
+            additional_extension_flag: r.read_bool_named("additional_extension_flag")?,
+        })
+    }
+}
+
+/// Parses SPS extension NAL units into the `Context` (see
+/// [Context::put_sps_extension](../../struct.Context.html#method.put_sps_extension)).
+pub struct SpsExtensionNalHandler<Ctx> {
+    buf: Vec<u8>,
+    phantom: marker::PhantomData<Ctx>
+}
+impl<Ctx> Default for SpsExtensionNalHandler<Ctx> {
+    fn default() -> Self {
+        SpsExtensionNalHandler {
+            buf: Vec::new(),
+            phantom: marker::PhantomData,
+        }
+    }
+}
+impl<Ctx> NalHandler for SpsExtensionNalHandler<Ctx> {
+    type Ctx = Ctx;
+
+    fn start(&mut self, _ctx: &mut Context<Ctx>, header: NalHeader) {
+        assert_eq!(header.nal_unit_type(), UnitType::SeqParameterSetExtension);
+    }
+
+    fn push(&mut self, _ctx: &mut Context<Ctx>, buf: &[u8]) -> NalInterest {
+        self.buf.extend_from_slice(buf);
+        NalInterest::Continue
+    }
+
+    fn end(&mut self, ctx: &mut Context<Ctx>) {
+        let ext = SeqParameterSetExtension::from_bytes(&self.buf[..]);
+        self.buf.clear();
+        match ext {
+            Ok(ext) => ctx.put_sps_extension(ext),
+            Err(e) => { ctx.report_error(Severity::Error, ErrorKind::SpsExtension(e)); },
+        }
+    }
+
+    fn reset(&mut self, _ctx: &mut Context<Ctx>) {
+        self.buf.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hex_literal::*;
+
+    #[test]
+    fn parse() {
+        // seq_parameter_set_id=2, aux_format_idc=1, bit_depth_aux_minus8=0, alpha_incr_flag=1,
+        // alpha_opaque_value=255, alpha_transparent_value=0, additional_extension_flag=0
+        let data = hex!("6B 7F 80 10");
+        let ext = SeqParameterSetExtension::from_bytes(&data[..]).unwrap();
+        assert_eq!(ext, SeqParameterSetExtension {
+            seq_parameter_set_id: ParamSetId::from_u32(2).unwrap(),
+            aux_picture_info: Some(AuxPictureInfo {
+                aux_format: AuxFormat::Alpha,
+                bit_depth_aux_minus8: 0,
+                alpha_incr_flag: true,
+                alpha_opaque_value: 255,
+                alpha_transparent_value: 0,
+            }),
+            additional_extension_flag: false,
+        });
+        assert_eq!(ext.aux_picture_info.as_ref().unwrap().bit_depth_aux(), 8);
+
+        // aux_format_idc=0
+        let ext = SeqParameterSetExtension::from_bytes(&hex!("D0")[..]).unwrap();
+        assert!(ext.aux_picture_info.is_none());
+        // aux_format_idc=4
+        assert!(matches!(SeqParameterSetExtension::from_bytes(&hex!("96")[..]), Err(SpsExtensionError::AuxFormatIdcOutOfRange(4))));
+
+        let mut ctx = Context::default();
+        ctx.put_sps_extension(SeqParameterSetExtension::from_bytes(&data[..]).unwrap());
+        assert!(ctx.sps_extension_by_id(ParamSetId::from_u32(2).unwrap()).is_some());
+        assert!(ctx.sps_extension_by_id(ParamSetId::from_u32(0).unwrap()).is_none());
+    }
+}
@@ -0,0 +1,476 @@
+//! Subset sequence parameter set (`nal_unit_type` `15`), used by the SVC and MVC extensions of
+//! _Annex G_ and _Annex H_ in place of the plain SPS for the enhancement layers or non-base views.
+
+use super::{NalHandler, NalHeader, NalInterest, UnitType};
+use super::sps::{self, HrdParameters, SeqParameterSet, SpsError, TimingInfo};
+use super::pps::ParamSetId;
+use crate::rbsp::{BitRead, BitReaderSlice, RbspBitReaderError};
+use crate::{Context, ErrorKind, Severity};
+use std::marker;
+
+#[derive(Debug)]
+pub enum SubsetSpsError {
+    /// A problem in the leading `seq_parameter_set_data()`.
+    Sps(SpsError),
+    RbspReaderError(RbspBitReaderError),
+    /// `vui_ext_num_entries_minus1` must be between 0 and 1023 inclusive.
+    VuiExtNumEntriesOutOfRange(u32),
+    /// The `bit_equal_to_one` preceding the MVC extension was zero.
+    InvalidBitEqualToOne,
+    /// A count in the MVC extension (or its VUI extension) exceeded the maximum the spec allows.
+    MvcCountOutOfRange { name: &'static str, value: u32 },
+}
+impl From<RbspBitReaderError> for SubsetSpsError {
+    fn from(e: RbspBitReaderError) -> Self {
+        SubsetSpsError::RbspReaderError(e)
+    }
+}
+impl From<SpsError> for SubsetSpsError {
+    fn from(e: SpsError) -> Self {
+        SubsetSpsError::Sps(e)
+    }
+}
+
+/// The `seq_parameter_set_svc_extension()` syntax, along with the SVC VUI extension which follows
+/// it.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpsSvcExtension {
+    pub inter_layer_deblocking_filter_control_present_flag: bool,
+    pub extended_spatial_scalability_idc: u8,
+    /// Inferred as `true` when not present.
+    pub chroma_phase_x_plus1_flag: bool,
+    /// Inferred as `1` when not present.
+    pub chroma_phase_y_plus1: u8,
+    /// Present when `extended_spatial_scalability_idc` is `1`.
+    pub seq_ref_layer: Option<SeqRefLayer>,
+    pub seq_tcoeff_level_prediction_flag: bool,
+    pub adaptive_tcoeff_level_prediction_flag: bool,
+    pub slice_header_restriction_flag: bool,
+    /// The entries of `svc_vui_parameters_extension()`, if present.
+    pub vui_parameters_extension: Option<Vec<SvcVuiEntry>>,
+}
+impl SpsSvcExtension {
+    fn read<R: BitRead + ?Sized>(r: &mut R, sps: &SeqParameterSet) -> Result<SpsSvcExtension, SubsetSpsError> {
+        let chroma_array_type = sps.chroma_array_type();
+        let inter_layer_deblocking_filter_control_present_flag = r.read_bool_named("inter_layer_deblocking_filter_control_present_flag")?;
+        let extended_spatial_scalability_idc = r.read_u8(2)?;
+        let chroma_phase_x_plus1_flag = match chroma_array_type {
+            sps::ChromaFormat::YUV420 | sps::ChromaFormat::YUV422 => r.read_bool_named("chroma_phase_x_plus1_flag")?,
+            _ => true,
+        };
+        let chroma_phase_y_plus1 = if chroma_array_type == sps::ChromaFormat::YUV420 { r.read_u8(2)? } else { 1 };
+        let seq_ref_layer = if extended_spatial_scalability_idc == 1 {
+            let (ref_x, ref_y) = if chroma_array_type != sps::ChromaFormat::Monochrome {
+                (r.read_bool_named("seq_ref_layer_chroma_phase_x_plus1_flag")?, r.read_u8(2)?)
+            } else {
+                (chroma_phase_x_plus1_flag, chroma_phase_y_plus1)
+            };
+            Some(SeqRefLayer {
+                chroma_phase_x_plus1_flag: ref_x,
+                chroma_phase_y_plus1: ref_y,
+                scaled_ref_layer_left_offset: r.read_se_named("seq_scaled_ref_layer_left_offset")?,
+                scaled_ref_layer_top_offset: r.read_se_named("seq_scaled_ref_layer_top_offset")?,
+                scaled_ref_layer_right_offset: r.read_se_named("seq_scaled_ref_layer_right_offset")?,
+                scaled_ref_layer_bottom_offset: r.read_se_named("seq_scaled_ref_layer_bottom_offset")?,
+            })
+        } else {
+            None
+        };
+        let seq_tcoeff_level_prediction_flag = r.read_bool_named("seq_tcoeff_level_prediction_flag")?;
+        let adaptive_tcoeff_level_prediction_flag = if seq_tcoeff_level_prediction_flag {
+            r.read_bool_named("adaptive_tcoeff_level_prediction_flag")?
+        } else {
+            false
+        };
+        let slice_header_restriction_flag = r.read_bool_named("slice_header_restriction_flag")?;
+        let vui_parameters_extension = if r.read_bool_named("svc_vui_parameters_present_flag")? {
+            Some(SvcVuiEntry::read_all(r)?)
+        } else {
+            None
+        };
+        Ok(SpsSvcExtension {
+            inter_layer_deblocking_filter_control_present_flag,
+            extended_spatial_scalability_idc,
+            chroma_phase_x_plus1_flag,
+            chroma_phase_y_plus1,
+            seq_ref_layer,
+            seq_tcoeff_level_prediction_flag,
+            adaptive_tcoeff_level_prediction_flag,
+            slice_header_restriction_flag,
+            vui_parameters_extension,
+        })
+    }
+}
+
+/// The reference layer chroma phase and scaled reference layer offsets of the SVC extension.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SeqRefLayer {
+    pub chroma_phase_x_plus1_flag: bool,
+    pub chroma_phase_y_plus1: u8,
+    pub scaled_ref_layer_left_offset: i32,
+    pub scaled_ref_layer_top_offset: i32,
+    pub scaled_ref_layer_right_offset: i32,
+    pub scaled_ref_layer_bottom_offset: i32,
+}
+
+/// One entry of `svc_vui_parameters_extension()`, giving the timing and HRD parameters of the
+/// sub-bitstream with the given `dependency_id`, `quality_id` and `temporal_id`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SvcVuiEntry {
+    pub dependency_id: u8,
+    pub quality_id: u8,
+    pub temporal_id: u8,
+    pub timing_info: Option<TimingInfo>,
+    pub nal_hrd_parameters: Option<HrdParameters>,
+    pub vcl_hrd_parameters: Option<HrdParameters>,
+    pub low_delay_hrd_flag: Option<bool>,
+    pub pic_struct_present_flag: bool,
+}
+impl SvcVuiEntry {
+    fn read_all<R: BitRead + ?Sized>(r: &mut R) -> Result<Vec<SvcVuiEntry>, SubsetSpsError> {
+        let num_entries_minus1 = r.read_ue_named("vui_ext_num_entries_minus1")?;
+        if num_entries_minus1 > 1023 {
+            return Err(SubsetSpsError::VuiExtNumEntriesOutOfRange(num_entries_minus1));
+        }
+        let mut entries = Vec::with_capacity(num_entries_minus1 as usize + 1);
+        for _ in 0..=num_entries_minus1 {
+            let mut hrd_parameters_present = false;
+            entries.push(SvcVuiEntry {
+                dependency_id: r.read_u8(3)?,
+                quality_id: r.read_u8(4)?,
+                temporal_id: r.read_u8(3)?,
+                timing_info: TimingInfo::read(r)?,
+                nal_hrd_parameters: HrdParameters::read(r, &mut hrd_parameters_present)?,
+                vcl_hrd_parameters: HrdParameters::read(r, &mut hrd_parameters_present)?,
+                low_delay_hrd_flag: if hrd_parameters_present { Some(r.read_bool_named("vui_ext_low_delay_hrd_flag")?) } else { None },
+                pic_struct_present_flag: r.read_bool_named("vui_ext_pic_struct_present_flag")?,
+            });
+        }
+        Ok(entries)
+    }
+}
+
+/// Reads a count from the MVC extension, checking it against its maximum.
+fn read_count<R: BitRead + ?Sized>(r: &mut R, name: &'static str, max: u32) -> Result<u32, SubsetSpsError> {
+    let value = r.read_ue_named(name)?;
+    if value > max {
+        return Err(SubsetSpsError::MvcCountOutOfRange { name, value });
+    }
+    Ok(value)
+}
+
+fn read_view_ids<R: BitRead + ?Sized>(r: &mut R, name: &'static str, count: u32) -> Result<Vec<u32>, SubsetSpsError> {
+    let mut ids = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        ids.push(r.read_ue_named(name)?);
+    }
+    Ok(ids)
+}
+
+/// The `seq_parameter_set_mvc_extension()` syntax, along with the MVC VUI extension which follows
+/// it.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpsMvcExtension {
+    /// The views in view order index order; the first is the base view, which has no
+    /// inter-view references.
+    pub views: Vec<MvcView>,
+    pub level_values: Vec<MvcLevelValue>,
+    /// The entries of `mvc_vui_parameters_extension()`, if present.
+    pub vui_parameters_extension: Option<Vec<MvcVuiEntry>>,
+}
+impl SpsMvcExtension {
+    fn read<R: BitRead + ?Sized>(r: &mut R) -> Result<SpsMvcExtension, SubsetSpsError> {
+        let num_views = read_count(r, "num_views_minus1", 1023)? + 1;
+        let mut views = Vec::with_capacity(num_views as usize);
+        for _ in 0..num_views {
+            views.push(MvcView {
+                view_id: r.read_ue_named("view_id")?,
+                anchor_refs_l0: vec![],
+                anchor_refs_l1: vec![],
+                non_anchor_refs_l0: vec![],
+                non_anchor_refs_l1: vec![],
+            });
+        }
+        let max_refs = (num_views - 1).min(15);
+        for view in views.iter_mut().skip(1) {
+            let n = read_count(r, "num_anchor_refs_l0", max_refs)?;
+            view.anchor_refs_l0 = read_view_ids(r, "anchor_ref_l0", n)?;
+            let n = read_count(r, "num_anchor_refs_l1", max_refs)?;
+            view.anchor_refs_l1 = read_view_ids(r, "anchor_ref_l1", n)?;
+        }
+        for view in views.iter_mut().skip(1) {
+            let n = read_count(r, "num_non_anchor_refs_l0", max_refs)?;
+            view.non_anchor_refs_l0 = read_view_ids(r, "non_anchor_ref_l0", n)?;
+            let n = read_count(r, "num_non_anchor_refs_l1", max_refs)?;
+            view.non_anchor_refs_l1 = read_view_ids(r, "non_anchor_ref_l1", n)?;
+        }
+        let num_level_values = read_count(r, "num_level_values_signalled_minus1", 63)? + 1;
+        let mut level_values = Vec::with_capacity(num_level_values as usize);
+        for _ in 0..num_level_values {
+            let level_idc = r.read_u8(8)?;
+            let num_ops = read_count(r, "num_applicable_ops_minus1", 1023)? + 1;
+            let mut applicable_ops = Vec::with_capacity(num_ops as usize);
+            for _ in 0..num_ops {
+                let temporal_id = r.read_u8(3)?;
+                let num_target_views = read_count(r, "applicable_op_num_target_views_minus1", 1023)? + 1;
+                applicable_ops.push(MvcOperationPoint {
+                    temporal_id,
+                    target_view_ids: read_view_ids(r, "applicable_op_target_view_id", num_target_views)?,
+                    num_views_minus1: read_count(r, "applicable_op_num_views_minus1", 1023)?,
+                });
+            }
+            level_values.push(MvcLevelValue { level_idc, applicable_ops });
+        }
+        let vui_parameters_extension = if r.read_bool_named("mvc_vui_parameters_present_flag")? {
+            Some(MvcVuiEntry::read_all(r)?)
+        } else {
+            None
+        };
+        Ok(SpsMvcExtension { views, level_values, vui_parameters_extension })
+    }
+
+    /// Looks up a view by its `view_id`.
+    pub fn view(&self, view_id: u32) -> Option<&MvcView> {
+        self.views.iter().find(|v| v.view_id == view_id)
+    }
+}
+
+/// A view of an MVC stream, with the `view_id`s of the views it may reference for inter-view
+/// prediction, in anchor and non-anchor access units respectively.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MvcView {
+    pub view_id: u32,
+    pub anchor_refs_l0: Vec<u32>,
+    pub anchor_refs_l1: Vec<u32>,
+    pub non_anchor_refs_l0: Vec<u32>,
+    pub non_anchor_refs_l1: Vec<u32>,
+}
+
+/// A `level_idc` signalled for a set of operation points of an MVC stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MvcLevelValue {
+    pub level_idc: u8,
+    pub applicable_ops: Vec<MvcOperationPoint>,
+}
+
+/// An operation point to which an [MvcLevelValue] applies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MvcOperationPoint {
+    pub temporal_id: u8,
+    /// The views to be output for this operation point.
+    pub target_view_ids: Vec<u32>,
+    /// `applicable_op_num_views_minus1`: one less than the number of views required to decode
+    /// the target views, including those they depend upon.
+    pub num_views_minus1: u32,
+}
+
+/// One entry of `mvc_vui_parameters_extension()`, giving the timing and HRD parameters of an
+/// operation point.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MvcVuiEntry {
+    pub temporal_id: u8,
+    pub target_output_view_ids: Vec<u32>,
+    pub timing_info: Option<TimingInfo>,
+    pub nal_hrd_parameters: Option<HrdParameters>,
+    pub vcl_hrd_parameters: Option<HrdParameters>,
+    pub low_delay_hrd_flag: Option<bool>,
+    pub pic_struct_present_flag: bool,
+}
+impl MvcVuiEntry {
+    fn read_all<R: BitRead + ?Sized>(r: &mut R) -> Result<Vec<MvcVuiEntry>, SubsetSpsError> {
+        let num_ops = read_count(r, "vui_mvc_num_ops_minus1", 1023)? + 1;
+        let mut entries = Vec::with_capacity(num_ops as usize);
+        for _ in 0..num_ops {
+            let temporal_id = r.read_u8(3)?;
+            let num_views = read_count(r, "vui_mvc_num_target_output_views_minus1", 1023)? + 1;
+            let target_output_view_ids = read_view_ids(r, "vui_mvc_view_id", num_views)?;
+            let mut hrd_parameters_present = false;
+            entries.push(MvcVuiEntry {
+                temporal_id,
+                target_output_view_ids,
+                timing_info: TimingInfo::read(r)?,
+                nal_hrd_parameters: HrdParameters::read(r, &mut hrd_parameters_present)?,
+                vcl_hrd_parameters: HrdParameters::read(r, &mut hrd_parameters_present)?,
+                low_delay_hrd_flag: if hrd_parameters_present { Some(r.read_bool_named("vui_mvc_low_delay_hrd_flag")?) } else { None },
+                pic_struct_present_flag: r.read_bool_named("vui_mvc_pic_struct_present_flag")?,
+            });
+        }
+        Ok(entries)
+    }
+}
+
+/// The extension following the `seq_parameter_set_data()` of a subset SPS, which depends on its
+/// `profile_idc`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SubsetSpsExtension {
+    /// For the Scalable Baseline, Scalable High and Scalable High Intra profiles (`profile_idc`
+    /// `83` or `86`).
+    Svc(SpsSvcExtension),
+    /// For the Multiview High and Stereo High profiles (`profile_idc` `118` or `128`).
+    Mvc(SpsMvcExtension),
+    /// Any other profile, whose extension is not parsed.
+    Unsupported,
+}
+
+/// The `subset_seq_parameter_set_rbsp()` syntax.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SubsetSeqParameterSet {
+    pub sps: SeqParameterSet,
+    pub extension: SubsetSpsExtension,
+}
+impl SubsetSeqParameterSet {
+    /// Parses the RBSP of a subset SPS NAL unit (i.e. following the NAL header byte).
+    pub fn from_bytes(buf: &[u8]) -> Result<SubsetSeqParameterSet, SubsetSpsError> {
+        Self::from_bits(&mut BitReaderSlice::new(buf))
+    }
+
+    /// Parses the `subset_seq_parameter_set_rbsp()` syntax from the given reader, which is
+    /// expected to be positioned just after the NAL header.
+    pub fn from_bits<R: BitRead + ?Sized>(r: &mut R) -> Result<SubsetSeqParameterSet, SubsetSpsError> {
+        let sps = SeqParameterSet::from_bits(r)?;
+        let extension = match u8::from(sps.profile_idc) {
+            83 | 86 => SubsetSpsExtension::Svc(SpsSvcExtension::read(r, &sps)?),
+            118 | 128 => {
+                if !r.read_bool_named("bit_equal_to_one")? {
+                    return Err(SubsetSpsError::InvalidBitEqualToOne);
+                }
+                SubsetSpsExtension::Mvc(SpsMvcExtension::read(r)?)
+            },
+            _ => SubsetSpsExtension::Unsupported,
+        };
+        Ok(SubsetSeqParameterSet { sps, extension })
+    }
+
+    pub fn id(&self) -> ParamSetId {
+        self.sps.seq_parameter_set_id
+    }
+}
+
+/// Parses subset SPS NAL units into the `Context` (see
+/// [Context::put_subset_seq_param_set](../../struct.Context.html#method.put_subset_seq_param_set)).
+pub struct SubsetSpsNalHandler<Ctx> {
+    buf: Vec<u8>,
+    phantom: marker::PhantomData<Ctx>
+}
+impl<Ctx> Default for SubsetSpsNalHandler<Ctx> {
+    fn default() -> Self {
+        SubsetSpsNalHandler {
+            buf: Vec::new(),
+            phantom: marker::PhantomData,
+        }
+    }
+}
+impl<Ctx> NalHandler for SubsetSpsNalHandler<Ctx> {
+    type Ctx = Ctx;
+
+    fn start(&mut self, _ctx: &mut Context<Ctx>, header: NalHeader) {
+        assert_eq!(header.nal_unit_type(), UnitType::SubsetSeqParameterSet);
+    }
+
+    fn push(&mut self, _ctx: &mut Context<Ctx>, buf: &[u8]) -> NalInterest {
+        self.buf.extend_from_slice(buf);
+        NalInterest::Continue
+    }
+
+    fn end(&mut self, ctx: &mut Context<Ctx>) {
+        let subset_sps = SubsetSeqParameterSet::from_bytes(&self.buf[..]);
+        self.buf.clear();
+        match subset_sps {
+            Ok(subset_sps) => ctx.put_subset_seq_param_set(subset_sps),
+            Err(e) => { ctx.report_error(Severity::Error, ErrorKind::SubsetSps(e)); },
+        }
+    }
+
+    fn reset(&mut self, _ctx: &mut Context<Ctx>) {
+        self.buf.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hex_literal::*;
+
+    #[test]
+    fn svc() {
+        let data = hex!("53 00 1E AC B4 0A 0F CA AC A6 DC 82 80 00 01 F4 80 00 75 30 42");
+        let subset = SubsetSeqParameterSet::from_bytes(&data[..]).unwrap();
+        assert_eq!(subset.id().id(), 0);
+        assert_eq!(subset.sps.pixel_dimensions().unwrap(), (320, 240));
+        let svc = match subset.extension {
+            SubsetSpsExtension::Svc(svc) => svc,
+            e => panic!("unexpected extension {:?}", e),
+        };
+        assert!(svc.inter_layer_deblocking_filter_control_present_flag);
+        assert_eq!(svc.extended_spatial_scalability_idc, 1);
+        assert!(!svc.chroma_phase_x_plus1_flag);
+        assert_eq!(svc.chroma_phase_y_plus1, 2);
+        let ref_layer = svc.seq_ref_layer.unwrap();
+        assert!(ref_layer.chroma_phase_x_plus1_flag);
+        assert_eq!(ref_layer.chroma_phase_y_plus1, 1);
+        assert_eq!(
+            (ref_layer.scaled_ref_layer_left_offset, ref_layer.scaled_ref_layer_top_offset,
+             ref_layer.scaled_ref_layer_right_offset, ref_layer.scaled_ref_layer_bottom_offset),
+            (0, -2, 3, 0));
+        assert!(svc.seq_tcoeff_level_prediction_flag);
+        assert!(!svc.adaptive_tcoeff_level_prediction_flag);
+        assert!(svc.slice_header_restriction_flag);
+        let vui = svc.vui_parameters_extension.unwrap();
+        assert_eq!(vui.len(), 1);
+        assert_eq!((vui[0].dependency_id, vui[0].quality_id, vui[0].temporal_id), (1, 0, 2));
+        let timing = vui[0].timing_info.as_ref().unwrap();
+        assert_eq!((timing.num_units_in_tick, timing.time_scale), (1001, 60000));
+        assert!(vui[0].low_delay_hrd_flag.is_none());
+
+        let mut ctx = Context::default();
+        ctx.put_subset_seq_param_set(SubsetSeqParameterSet::from_bytes(&data[..]).unwrap());
+        assert!(ctx.subset_sps_by_id(ParamSetId::from_u32(0).unwrap()).is_some());
+        assert!(ctx.sps_by_id(ParamSetId::from_u32(0).unwrap()).is_none());
+
+        assert!(matches!(SubsetSeqParameterSet::from_bytes(&data[..12]), Err(SubsetSpsError::RbspReaderError(_))));
+    }
+
+    #[test]
+    fn mvc() {
+        let data = hex!("76 00 1E 4B 2D 02 83 F2 A9 6B 8F C2 A5 8A 30 0F 7B DF 0A");
+        let subset = SubsetSeqParameterSet::from_bytes(&data[..]).unwrap();
+        assert_eq!(subset.id().id(), 1);
+        assert_eq!(subset.sps.pixel_dimensions().unwrap(), (320, 240));
+        let mvc = match subset.extension {
+            SubsetSpsExtension::Mvc(mvc) => mvc,
+            e => panic!("unexpected extension {:?}", e),
+        };
+        assert_eq!(mvc.views, [
+            MvcView { view_id: 0, anchor_refs_l0: vec![], anchor_refs_l1: vec![], non_anchor_refs_l0: vec![], non_anchor_refs_l1: vec![] },
+            MvcView { view_id: 1, anchor_refs_l0: vec![0], anchor_refs_l1: vec![], non_anchor_refs_l0: vec![0], non_anchor_refs_l1: vec![] },
+        ]);
+        assert_eq!(mvc.view(1).unwrap().anchor_refs_l0, [0]);
+        assert_eq!(mvc.level_values, [MvcLevelValue {
+            level_idc: 31,
+            applicable_ops: vec![MvcOperationPoint { temporal_id: 0, target_view_ids: vec![0, 1], num_views_minus1: 1 }],
+        }]);
+        let vui = mvc.vui_parameters_extension.unwrap();
+        assert_eq!(vui.len(), 1);
+        assert_eq!(vui[0].target_output_view_ids, [1]);
+        assert!(vui[0].nal_hrd_parameters.is_none());
+        let hrd = vui[0].vcl_hrd_parameters.as_ref().unwrap();
+        assert_eq!(hrd.time_offset_length, 24);
+        assert_eq!(vui[0].low_delay_hrd_flag, Some(false));
+        assert!(vui[0].pic_struct_present_flag);
+
+        // bit_equal_to_one is bit 62, following the seq_parameter_set_data()
+        let mut bad = data;
+        bad[7] &= 0b1111_1101;
+        assert!(matches!(SubsetSeqParameterSet::from_bytes(&bad[..]), Err(SubsetSpsError::InvalidBitEqualToOne)));
+    }
+}
@@ -0,0 +1,386 @@
+//! Derivation of picture order counts, which give the order in which pictures are output
+//! (_8.2.1_).
+//!
+//! A [PocState] is fed the first slice header of each picture in decoding order, and tracks the
+//! values carried from one picture to the next: the previous reference picture's
+//! `PicOrderCntMsb` and `pic_order_cnt_lsb` for `pic_order_cnt_type` 0, and the previous
+//! picture's `FrameNumOffset` and `frame_num` for types 1 and 2.  IDR pictures and
+//! `memory_management_control_operation` 5 reset this state.
+//!
+//! Gaps in `frame_num` (which require the decoder to infer "non-existing" frames) aren't
+//! modelled.
+
+use crate::nal::slice::{PicOrderCountLsb, PictureStructure, SliceHeader};
+use crate::nal::sps::{PicOrderCntType, SeqParameterSet};
+use std::convert::TryFrom;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PocError {
+    /// The slice header's picture order count syntax doesn't match the SPS's
+    /// `pic_order_cnt_type`, or the SPS lacks a valid _MaxFrameNum_, suggesting the header was
+    /// parsed with a different SPS.
+    MismatchedSyntax,
+    /// The picture order count doesn't fit in 32 bits, which a conforming stream never
+    /// requires.
+    Overflow,
+}
+
+/// The picture order counts of a frame or field, which has at least one of the two field order
+/// counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PicOrderCnt {
+    top: Option<i32>,
+    bottom: Option<i32>,
+}
+impl PicOrderCnt {
+    /// The counts of a frame or complementary field pair.
+    pub fn frame(top: i32, bottom: i32) -> Self {
+        PicOrderCnt { top: Some(top), bottom: Some(bottom) }
+    }
+
+    pub fn top_field(top: i32) -> Self {
+        PicOrderCnt { top: Some(top), bottom: None }
+    }
+
+    pub fn bottom_field(bottom: i32) -> Self {
+        PicOrderCnt { top: None, bottom: Some(bottom) }
+    }
+
+    /// The counts of whichever fields are given, or `None` if neither is.
+    pub fn from_fields(top: Option<i32>, bottom: Option<i32>) -> Option<Self> {
+        if top.is_none() && bottom.is_none() {
+            return None;
+        }
+        Some(PicOrderCnt { top, bottom })
+    }
+
+    /// _TopFieldOrderCnt_, absent for a bottom field.
+    pub fn top(&self) -> Option<i32> {
+        self.top
+    }
+
+    /// _BottomFieldOrderCnt_, absent for a top field.
+    pub fn bottom(&self) -> Option<i32> {
+        self.bottom
+    }
+
+    /// _PicOrderCnt( picX )_: the lesser of the field order counts of a frame, or the order
+    /// count of a field.
+    pub fn pic_order_cnt(&self) -> i32 {
+        match (self.top, self.bottom) {
+            (Some(top), Some(bottom)) => top.min(bottom),
+            (Some(top), None) => top,
+            (None, Some(bottom)) => bottom,
+            (None, None) => unreachable!("constructors ensure PicOrderCnt has a field"),
+        }
+    }
+
+    /// Applies `f` to each field order count present.
+    pub(crate) fn map(self, f: impl Fn(i32) -> i32) -> Self {
+        PicOrderCnt {
+            top: self.top.map(&f),
+            bottom: self.bottom.map(&f),
+        }
+    }
+}
+
+/// The properties of a picture which affect its picture order count, besides its POC syntax.
+struct Picture {
+    idr: bool,
+    /// Whether `nal_ref_idc` is non-zero.
+    reference: bool,
+    /// Whether the picture has a `memory_management_control_operation` of 5.
+    mmco5: bool,
+    structure: PictureStructure,
+}
+
+/// Tracks the state needed to derive the picture order count of each picture from its slice
+/// header.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PocState {
+    /// _prevPicOrderCntMsb_, for `pic_order_cnt_type` 0.
+    prev_pic_order_cnt_msb: i64,
+    /// _prevPicOrderCntLsb_, for `pic_order_cnt_type` 0.
+    prev_pic_order_cnt_lsb: i64,
+    /// _prevFrameNumOffset_, for `pic_order_cnt_type` 1 and 2.
+    prev_frame_num_offset: i64,
+    /// _prevFrameNum_, for `pic_order_cnt_type` 1 and 2.
+    prev_frame_num: u16,
+}
+impl PocState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Derives the picture order count of the picture with the given slice header, which must
+    /// be the first slice of each picture (frame or field) in decoding order, parsed with the
+    /// given SPS.  Updates the state for the next picture.
+    ///
+    /// The returned counts are those derived by _8.2.1.1_ to _8.2.1.3_.  When the picture has a
+    /// `memory_management_control_operation` of 5, later pictures are ordered relative to the
+    /// picture's counts reduced by its `pic_order_cnt()`, as the spec prescribes.
+    pub fn compute(&mut self, sps: &SeqParameterSet, header: &SliceHeader) -> Result<PicOrderCnt, PocError> {
+        let pic = Picture {
+            idr: header.idr_pic_id.is_some(),
+            reference: header.dec_ref_pic_marking.is_some(),
+            mmco5: header.dec_ref_pic_marking.as_ref().map(|m| m.has_mmco5()).unwrap_or(false),
+            structure: header.picture_structure(),
+        };
+        let Picture { idr, reference, mmco5, structure } = pic;
+        let (top, bottom) = match sps.pic_order_cnt {
+            PicOrderCntType::TypeZero { log2_max_pic_order_cnt_lsb_minus4 } => {
+                let (lsb, delta_pic_order_cnt_bottom) = match header.pic_order_cnt_lsb {
+                    Some(PicOrderCountLsb::Frame(lsb)) => (i64::from(lsb), 0),
+                    Some(PicOrderCountLsb::FieldsAbsolute { top, bottom_delta }) => {
+                        (i64::from(top), i64::from(bottom_delta) - i64::from(top))
+                    },
+                    _ => return Err(PocError::MismatchedSyntax),
+                };
+                self.type_zero(&pic, log2_max_pic_order_cnt_lsb_minus4, lsb, delta_pic_order_cnt_bottom)
+            },
+            PicOrderCntType::TypeOne { offset_for_non_ref_pic, offset_for_top_to_bottom_field, ref offsets_for_ref_frame, .. } => {
+                let delta_pic_order_cnt = match header.pic_order_cnt_lsb {
+                    None => [0, 0],
+                    Some(PicOrderCountLsb::FieldsDelta(d)) => [i64::from(d[0]), i64::from(d[1])],
+                    _ => return Err(PocError::MismatchedSyntax),
+                };
+                let frame_num_offset = self.frame_num_offset(sps, idr, header.frame_num)?;
+                let mut abs_frame_num = if offsets_for_ref_frame.is_empty() {
+                    0
+                } else {
+                    frame_num_offset + i64::from(header.frame_num)
+                };
+                if !reference && abs_frame_num > 0 {
+                    abs_frame_num -= 1;
+                }
+                let mut expected_pic_order_cnt = if abs_frame_num > 0 {
+                    let cycle_len = offsets_for_ref_frame.len() as i64;
+                    let pic_order_cnt_cycle_cnt = (abs_frame_num - 1) / cycle_len;
+                    let frame_num_in_pic_order_cnt_cycle = ((abs_frame_num - 1) % cycle_len) as usize;
+                    let expected_delta_per_pic_order_cnt_cycle = sps.pic_order_cnt.expected_delta_per_pic_order_cnt_cycle().unwrap();
+                    pic_order_cnt_cycle_cnt * expected_delta_per_pic_order_cnt_cycle
+                        + offsets_for_ref_frame[..=frame_num_in_pic_order_cnt_cycle].iter().map(|&o| i64::from(o)).sum::<i64>()
+                } else {
+                    0
+                };
+                if !reference {
+                    expected_pic_order_cnt += i64::from(offset_for_non_ref_pic);
+                }
+                let offset_for_top_to_bottom_field = i64::from(offset_for_top_to_bottom_field);
+                self.end_frame_num(mmco5, frame_num_offset, header.frame_num);
+                match structure {
+                    PictureStructure::Frame => {
+                        let top = expected_pic_order_cnt + delta_pic_order_cnt[0];
+                        (Some(top), Some(top + offset_for_top_to_bottom_field + delta_pic_order_cnt[1]))
+                    },
+                    PictureStructure::TopField => (Some(expected_pic_order_cnt + delta_pic_order_cnt[0]), None),
+                    PictureStructure::BottomField => {
+                        (None, Some(expected_pic_order_cnt + offset_for_top_to_bottom_field + delta_pic_order_cnt[0]))
+                    },
+                }
+            },
+            PicOrderCntType::TypeTwo => {
+                if header.pic_order_cnt_lsb.is_some() {
+                    return Err(PocError::MismatchedSyntax);
+                }
+                let frame_num_offset = self.frame_num_offset(sps, idr, header.frame_num)?;
+                let temp_pic_order_cnt = if idr {
+                    0
+                } else if !reference {
+                    2 * (frame_num_offset + i64::from(header.frame_num)) - 1
+                } else {
+                    2 * (frame_num_offset + i64::from(header.frame_num))
+                };
+                self.end_frame_num(mmco5, frame_num_offset, header.frame_num);
+                match structure {
+                    PictureStructure::Frame => (Some(temp_pic_order_cnt), Some(temp_pic_order_cnt)),
+                    PictureStructure::TopField => (Some(temp_pic_order_cnt), None),
+                    PictureStructure::BottomField => (None, Some(temp_pic_order_cnt)),
+                }
+            },
+        };
+        let to_i32 = |v: Option<i64>| v.map(|v| i32::try_from(v).map_err(|_| PocError::Overflow)).transpose();
+        Ok(PicOrderCnt::from_fields(to_i32(top)?, to_i32(bottom)?).expect("picture has a field"))
+    }
+
+    /// _8.2.1.1_, returning the top and bottom field order counts.
+    fn type_zero(&mut self, pic: &Picture, log2_max_pic_order_cnt_lsb_minus4: u8, lsb: i64, delta_pic_order_cnt_bottom: i64) -> (Option<i64>, Option<i64>) {
+        if pic.idr {
+            self.prev_pic_order_cnt_msb = 0;
+            self.prev_pic_order_cnt_lsb = 0;
+        }
+        let max_pic_order_cnt_lsb = 1i64 << (log2_max_pic_order_cnt_lsb_minus4 + 4);
+        let prev_lsb = self.prev_pic_order_cnt_lsb;
+        let pic_order_cnt_msb = if lsb < prev_lsb && prev_lsb - lsb >= max_pic_order_cnt_lsb / 2 {
+            self.prev_pic_order_cnt_msb + max_pic_order_cnt_lsb
+        } else if lsb > prev_lsb && lsb - prev_lsb > max_pic_order_cnt_lsb / 2 {
+            self.prev_pic_order_cnt_msb - max_pic_order_cnt_lsb
+        } else {
+            self.prev_pic_order_cnt_msb
+        };
+        let (top, bottom) = match pic.structure {
+            PictureStructure::Frame => {
+                let top = pic_order_cnt_msb + lsb;
+                (Some(top), Some(top + delta_pic_order_cnt_bottom))
+            },
+            PictureStructure::TopField => (Some(pic_order_cnt_msb + lsb), None),
+            PictureStructure::BottomField => (None, Some(pic_order_cnt_msb + lsb)),
+        };
+        if pic.reference {
+            if pic.mmco5 {
+                // the next picture uses the top field's order count after the reduction by
+                // tempPicOrderCnt, or 0 for a bottom field.
+                self.prev_pic_order_cnt_msb = 0;
+                self.prev_pic_order_cnt_lsb = match (top, bottom) {
+                    (Some(top), Some(bottom)) => top - top.min(bottom),
+                    _ => 0,
+                };
+            } else {
+                self.prev_pic_order_cnt_msb = pic_order_cnt_msb;
+                self.prev_pic_order_cnt_lsb = lsb;
+            }
+        }
+        (top, bottom)
+    }
+
+    /// _FrameNumOffset_, for `pic_order_cnt_type` 1 and 2.
+    fn frame_num_offset(&self, sps: &SeqParameterSet, idr: bool, frame_num: u16) -> Result<i64, PocError> {
+        let max_frame_num = sps.max_frame_num().ok_or(PocError::MismatchedSyntax)?;
+        Ok(if idr {
+            0
+        } else if self.prev_frame_num > frame_num {
+            self.prev_frame_num_offset + i64::from(max_frame_num)
+        } else {
+            self.prev_frame_num_offset
+        })
+    }
+
+    /// Records _prevFrameNumOffset_ and _prevFrameNum_ for the next picture, which are reset by
+    /// `memory_management_control_operation` 5.
+    fn end_frame_num(&mut self, mmco5: bool, frame_num_offset: i64, frame_num: u16) {
+        if mmco5 {
+            self.prev_frame_num_offset = 0;
+            self.prev_frame_num = 0;
+        } else {
+            self.prev_frame_num_offset = frame_num_offset;
+            self.prev_frame_num = frame_num;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nal::slice::{DecRefPicMarking, MemoryManagementControlOperation, PictureCoding};
+    use crate::nal::sps::{Level, Profile, SpsBuilder};
+
+    fn sps(pic_order_cnt: PicOrderCntType) -> SeqParameterSet {
+        let mut sps = SpsBuilder::new(Profile::Main, Level::L3)
+            .dimensions(640, 480)
+            .build()
+            .unwrap();
+        sps.log2_max_frame_num_minus4 = 0;
+        sps.pic_order_cnt = pic_order_cnt;
+        sps
+    }
+
+    /// A slice header with the given properties; `reference` of `None` means a non-reference
+    /// picture, and otherwise whether it has a `memory_management_control_operation` of 5.
+    fn header(idr: bool, reference: Option<bool>, frame_num: u16, picture_coding: PictureCoding, pic_order_cnt_lsb: Option<PicOrderCountLsb>) -> SliceHeader {
+        SliceHeader {
+            idr_pic_id: if idr { Some(0) } else { None },
+            pic_order_cnt_lsb,
+            dec_ref_pic_marking: match reference {
+                None => None,
+                Some(_) if idr => Some(DecRefPicMarking::Idr {
+                    no_output_of_prior_pics_flag: false,
+                    long_term_reference_flag: false,
+                }),
+                Some(true) => Some(DecRefPicMarking::Adaptive(vec![MemoryManagementControlOperation::AllRefPicturesUnused])),
+                Some(false) => Some(DecRefPicMarking::SlidingWindow),
+            },
+            ..SliceHeader::for_test(if idr { 7 } else { 5 }, frame_num, picture_coding)
+        }
+    }
+
+    #[test]
+    fn type_zero() {
+        // MaxPicOrderCntLsb is 16.
+        let sps = sps(PicOrderCntType::TypeZero { log2_max_pic_order_cnt_lsb_minus4: 0 });
+        let mut state = PocState::new();
+        let mut poc = |idr, reference, lsb| {
+            let h = header(idr, reference, 0, PictureCoding::Frame, Some(PicOrderCountLsb::Frame(lsb)));
+            state.compute(&sps, &h).unwrap().pic_order_cnt()
+        };
+        assert_eq!(poc(true, Some(false), 0), 0);
+        assert_eq!(poc(false, Some(false), 6), 6);
+        assert_eq!(poc(false, None, 2), 2);
+        assert_eq!(poc(false, Some(false), 12), 12);
+        // the lsb wraps forward...
+        assert_eq!(poc(false, Some(false), 2), 18);
+        // ...and non-reference pictures don't move the reference point.
+        assert_eq!(poc(false, None, 14), 14);
+        assert_eq!(poc(false, Some(false), 10), 26);
+        // memory_management_control_operation 5 resets the count after the picture.
+        assert_eq!(poc(false, Some(true), 14), 30);
+        assert_eq!(poc(false, Some(false), 4), 4);
+        assert_eq!(poc(true, Some(false), 8), 8);
+        assert_eq!(poc(false, Some(false), 6), 6);
+
+        // a frame with a separate bottom field count.
+        let h = header(false, None, 0, PictureCoding::Frame, Some(PicOrderCountLsb::FieldsAbsolute { top: 2, bottom_delta: 3 }));
+        assert_eq!(state.compute(&sps, &h), Ok(PicOrderCnt::frame(2, 3)));
+        let h = header(false, None, 0, PictureCoding::BottomField, Some(PicOrderCountLsb::Frame(3)));
+        assert_eq!(state.compute(&sps, &h), Ok(PicOrderCnt::bottom_field(3)));
+        let h = header(false, None, 0, PictureCoding::Frame, Some(PicOrderCountLsb::FieldsDelta([0, 0])));
+        assert_eq!(state.compute(&sps, &h), Err(PocError::MismatchedSyntax));
+    }
+
+    #[test]
+    fn type_one() {
+        let sps = sps(PicOrderCntType::TypeOne {
+            delta_pic_order_always_zero_flag: false,
+            offset_for_non_ref_pic: -2,
+            offset_for_top_to_bottom_field: 1,
+            offsets_for_ref_frame: vec![4, 2],
+        });
+        let mut state = PocState::new();
+        let mut poc = |idr, reference, frame_num, picture_coding, delta: [i32; 2]| {
+            let h = header(idr, reference, frame_num, picture_coding, Some(PicOrderCountLsb::FieldsDelta(delta)));
+            state.compute(&sps, &h).unwrap()
+        };
+        assert_eq!(poc(true, Some(false), 0, PictureCoding::Frame, [0, 0]), PicOrderCnt::frame(0, 1));
+        // absFrameNum 1 is the first of the cycle, with offset 4.
+        assert_eq!(poc(false, Some(false), 1, PictureCoding::Frame, [0, 0]), PicOrderCnt::frame(4, 5));
+        // a non-reference frame uses absFrameNum 1 and offset_for_non_ref_pic.
+        assert_eq!(poc(false, None, 2, PictureCoding::Frame, [1, 0]), PicOrderCnt::frame(3, 4));
+        // absFrameNum 2: 4 + 2.
+        assert_eq!(poc(false, Some(false), 2, PictureCoding::Frame, [0, -1]), PicOrderCnt::frame(6, 6));
+        // absFrameNum 3, the start of the second cycle: 6 + 4.
+        assert_eq!(poc(false, Some(false), 3, PictureCoding::TopField, [0, 0]), PicOrderCnt::top_field(10));
+        assert_eq!(poc(false, Some(false), 3, PictureCoding::BottomField, [0, 0]), PicOrderCnt::bottom_field(11));
+        // frame_num wraps at MaxFrameNum of 16, giving absFrameNum 17: 8 cycles of 6, plus 4.
+        assert_eq!(poc(false, Some(false), 15, PictureCoding::Frame, [0, 0]).pic_order_cnt(), 7 * 6 + 4);
+        assert_eq!(poc(false, Some(false), 1, PictureCoding::Frame, [0, 0]).pic_order_cnt(), 8 * 6 + 4);
+        // memory_management_control_operation 5 makes the next picture behave as after an IDR.
+        assert_eq!(poc(false, Some(true), 2, PictureCoding::Frame, [0, 0]).pic_order_cnt(), 8 * 6 + 6);
+        assert_eq!(poc(false, Some(false), 1, PictureCoding::Frame, [0, 0]), PicOrderCnt::frame(4, 5));
+    }
+
+    #[test]
+    fn type_two() {
+        let sps = sps(PicOrderCntType::TypeTwo);
+        let mut state = PocState::new();
+        let mut poc = |idr, reference, frame_num, picture_coding| {
+            state.compute(&sps, &header(idr, reference, frame_num, picture_coding, None)).unwrap()
+        };
+        assert_eq!(poc(true, Some(false), 0, PictureCoding::Frame), PicOrderCnt::frame(0, 0));
+        assert_eq!(poc(false, Some(false), 1, PictureCoding::Frame), PicOrderCnt::frame(2, 2));
+        assert_eq!(poc(false, None, 2, PictureCoding::Frame), PicOrderCnt::frame(3, 3));
+        assert_eq!(poc(false, Some(false), 2, PictureCoding::TopField), PicOrderCnt::top_field(4));
+        assert_eq!(poc(false, Some(false), 2, PictureCoding::BottomField), PicOrderCnt::bottom_field(4));
+        assert_eq!(poc(false, Some(false), 15, PictureCoding::Frame), PicOrderCnt::frame(30, 30));
+        assert_eq!(poc(false, Some(false), 0, PictureCoding::Frame), PicOrderCnt::frame(32, 32));
+        assert_eq!(poc(true, Some(false), 0, PictureCoding::Frame), PicOrderCnt::frame(0, 0));
+    }
+}
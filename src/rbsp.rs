@@ -21,9 +21,12 @@
 //! yield byte sequences where the encoding is removed (i.e. the decoder will replace instances of
 //! the sequence `0x00 0x00 0x03` with `0x00 0x00`).
 
-use bitstream_io::read::BitRead;
+use bitstream_io::read::BitRead as _;
 use std::borrow::Cow;
-use crate::nal::{NalHandler, NalHeader};
+use std::fmt;
+use std::io::Read as _;
+use crate::nal::{NalHandler, NalHeader, NalInterest};
+use crate::nal::sps::ChromaFormat;
 use crate::Context;
 
 #[derive(Debug)]
@@ -31,6 +34,32 @@ enum ParseState {
     Start,
     OneZero,
     TwoZero,
+    /// Just skipped an `emulation_prevention_three_byte`.
+    Three,
+    /// A forbidden byte sequence was found, or the inner handler returned `NalInterest::Stop`;
+    /// ignore the rest of this NAL unit.
+    Error,
+}
+
+/// Controls how [RbspDecoder] reacts to byte sequences which may not appear within a NAL unit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strictness {
+    /// Forbidden byte sequences are passed through to the inner handler unchanged.
+    Lenient,
+    /// The first forbidden byte sequence is recorded as an [RbspDecoderError] (see
+    /// [RbspDecoder::error]), and the remainder of the NAL unit is not passed to the inner
+    /// handler.
+    Strict,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RbspDecoderError {
+    /// One of the sequences `0x00 0x00 0x00`, `0x00 0x00 0x01` or `0x00 0x00 0x02` was found,
+    /// with the final byte at the given offset within the (escaped) NAL unit data.
+    ForbiddenSequence { offset: usize, byte: u8 },
+    /// An `emulation_prevention_three_byte` at the given offset was followed by a byte other than
+    /// `0x00`, `0x01`, `0x02` or `0x03`.
+    InvalidEmulationPrevention { offset: usize, byte: u8 },
 }
 
 /// Push parser which removes _emulation prevention_ as it calls
@@ -40,27 +69,53 @@ pub struct RbspDecoder<R>
         R: NalHandler
 {
     state: ParseState,
+    strictness: Strictness,
+    /// number of bytes of escaped input seen so far in the current NAL unit
+    offset: usize,
+    error: Option<RbspDecoderError>,
     nal_reader: R,
 }
 impl<R> RbspDecoder<R>
     where
         R: NalHandler
 {
+    /// Creates a decoder in [Strictness::Lenient] mode.
     pub fn new(nal_reader: R) -> Self {
+        Self::with_strictness(nal_reader, Strictness::Lenient)
+    }
+
+    pub fn with_strictness(nal_reader: R, strictness: Strictness) -> Self {
         RbspDecoder {
             state: ParseState::Start,
+            strictness,
+            offset: 0,
+            error: None,
             nal_reader,
         }
     }
 
+    /// In [Strictness::Strict] mode, returns the error (if any) found in the current NAL unit.
+    ///
+    /// The error is retained until the next call to `start()`.
+    pub fn error(&self) -> Option<&RbspDecoderError> {
+        self.error.as_ref()
+    }
+
+    fn fail(&mut self, error: RbspDecoderError) {
+        self.error = Some(error);
+        self.to(ParseState::Error);
+    }
+
     fn to(&mut self, new_state: ParseState) {
         self.state = new_state;
     }
 
-    fn emit(&mut self, ctx: &mut Context<R::Ctx>, buf: &[u8]) {
-        if !buf.is_empty() {
-            self.nal_reader.push(ctx, &buf)
+    fn emit(&mut self, ctx: &mut Context<R::Ctx>, buf: &[u8]) -> NalInterest {
+        if !buf.is_empty() && self.nal_reader.push(ctx, buf) == NalInterest::Stop {
+            self.to(ParseState::Error);
+            return NalInterest::Stop;
         }
+        NalInterest::Continue
     }
 
     pub fn into_handler(self) -> R {
@@ -75,16 +130,22 @@ impl<R> NalHandler for RbspDecoder<R>
 
     fn start(&mut self, ctx: &mut Context<Self::Ctx>, header: NalHeader) {
         self.state = ParseState::Start;
+        self.offset = 0;
+        self.error = None;
         self.nal_reader.start(ctx, header);
     }
 
-    fn push(&mut self, ctx: &mut Context<Self::Ctx>, mut buf: &[u8]) {
+    fn push(&mut self, ctx: &mut Context<Self::Ctx>, mut buf: &[u8]) -> NalInterest {
         // buf[0..i] hasn't yet been emitted and is RBSP (has no emulation_prevention_three_bytes).
         // self.state describes the state before buf[i].
         // buf[i..] has yet to be examined.
+        // base is the offset within the NAL unit of buf[0].
+        let mut base = self.offset;
+        self.offset += buf.len();
         let mut i = 0;
         while i < buf.len() {
             match self.state {
+                ParseState::Error => return NalInterest::Stop,
                 ParseState::Start => match memchr::memchr(0x00, &buf[i..]) {
                     Some(nonzero_len) => {
                         i += nonzero_len;
@@ -100,19 +161,34 @@ impl<R> NalHandler for RbspDecoder<R>
                     0x03 => {
                         // Found an emulation_prevention_three_byte; skip it.
                         let (rbsp, three_onward) = buf.split_at(i);
-                        self.emit(ctx, rbsp);
+                        if self.emit(ctx, rbsp) == NalInterest::Stop {
+                            return NalInterest::Stop;
+                        }
                         buf = &three_onward[1..];
+                        base += i + 1;
                         i = 0;
-                        // TODO: per spec, the next byte should be either 0x00, 0x1, 0x02 or
-                        // 0x03, but at the moment we assume this without checking for
-                        // correctness
-                        self.to(ParseState::Start);
+                        self.to(ParseState::Three);
                         continue; // don't increment i; buf[0] hasn't been examined yet.
                     },
                     // I see example PES packet payloads that end with 0x80 0x00 0x00 0x00,
                     // which triggered this error; guess the example is correct and this code
-                    // was wrong, but not sure why!
-                    // 0x00 => { self.err(b); },
+                    // was wrong, but not sure why!  Hence this is only checked in strict mode.
+                    b @ 0x00..=0x02 if self.strictness == Strictness::Strict => {
+                        self.emit(ctx, &buf[..i]);
+                        self.fail(RbspDecoderError::ForbiddenSequence { offset: base + i, byte: b });
+                        return NalInterest::Stop;
+                    },
+                    _ => self.to(ParseState::Start),
+                },
+                ParseState::Three => match buf[i] {
+                    // per spec, the byte following an emulation_prevention_three_byte should be
+                    // one of 0x00, 0x01, 0x02 or 0x03.
+                    b @ 0x04..=0xff if self.strictness == Strictness::Strict => {
+                        self.emit(ctx, &buf[..i]);
+                        self.fail(RbspDecoderError::InvalidEmulationPrevention { offset: base + i - 1, byte: b });
+                        return NalInterest::Stop;
+                    },
+                    0x00 => self.to(ParseState::OneZero),
                     _ => self.to(ParseState::Start),
                 },
             }
@@ -120,7 +196,10 @@ impl<R> NalHandler for RbspDecoder<R>
         }
 
         // buf is now entirely RBSP.
-        self.emit(ctx, buf);
+        if let ParseState::Error = self.state {
+            return NalInterest::Stop;
+        }
+        self.emit(ctx, buf)
     }
 
     /// To be invoked when calling code knows that the end of a sequence of NAL Unit data has been
@@ -133,6 +212,153 @@ impl<R> NalHandler for RbspDecoder<R>
         self.to(ParseState::Start);
         self.nal_reader.end(ctx);
     }
+
+    fn reset(&mut self, ctx: &mut Context<Self::Ctx>) {
+        self.to(ParseState::Start);
+        self.nal_reader.reset(ctx);
+    }
+}
+
+/// Push encoder which adds _emulation prevention_ as it calls an inner [NalHandler]; the inverse
+/// of [RbspDecoder].
+///
+/// Accepts RBSP data (without the NAL header byte), and forwards the escaped NAL unit payload.
+pub struct RbspEncoder<R>
+    where
+        R: NalHandler
+{
+    /// number of consecutive 0x00 bytes most recently forwarded (at most 2)
+    zeros: u8,
+    stopped: bool,
+    nal_reader: R,
+}
+impl<R> RbspEncoder<R>
+    where
+        R: NalHandler
+{
+    pub fn new(nal_reader: R) -> Self {
+        RbspEncoder {
+            zeros: 0,
+            stopped: false,
+            nal_reader,
+        }
+    }
+
+    fn emit(&mut self, ctx: &mut Context<R::Ctx>, buf: &[u8]) -> NalInterest {
+        if !buf.is_empty() && self.nal_reader.push(ctx, buf) == NalInterest::Stop {
+            self.stopped = true;
+            return NalInterest::Stop;
+        }
+        NalInterest::Continue
+    }
+
+    pub fn into_handler(self) -> R {
+        self.nal_reader
+    }
+}
+impl<R> NalHandler for RbspEncoder<R>
+    where
+        R: NalHandler
+{
+    type Ctx = R::Ctx;
+
+    fn start(&mut self, ctx: &mut Context<Self::Ctx>, header: NalHeader) {
+        self.zeros = 0;
+        self.stopped = false;
+        self.nal_reader.start(ctx, header);
+    }
+
+    fn push(&mut self, ctx: &mut Context<Self::Ctx>, buf: &[u8]) -> NalInterest {
+        if self.stopped {
+            return NalInterest::Stop;
+        }
+        // buf[start..i] has been examined but not yet emitted.
+        let mut start = 0;
+        let mut i = 0;
+        while i < buf.len() {
+            if self.zeros < 2 {
+                if buf[i] == 0x00 {
+                    self.zeros += 1;
+                    i += 1;
+                } else {
+                    self.zeros = 0;
+                    i += match memchr::memchr(0x00, &buf[i..]) {
+                        Some(nonzero_len) => nonzero_len,
+                        None => buf.len() - i,
+                    };
+                }
+                continue;
+            }
+            if buf[i] <= 0x03 {
+                if self.emit(ctx, &buf[start..i]) == NalInterest::Stop
+                    || self.emit(ctx, &[0x03]) == NalInterest::Stop {
+                    return NalInterest::Stop;
+                }
+                start = i;
+            }
+            self.zeros = 0;
+        }
+        self.emit(ctx, &buf[start..])
+    }
+
+    fn end(&mut self, ctx: &mut Context<Self::Ctx>) {
+        // The final byte of a NAL unit may not be 0x00 (as may happen with cabac_zero_words).
+        if self.zeros > 0 && !self.stopped {
+            self.emit(ctx, &[0x03]);
+        }
+        self.nal_reader.end(ctx);
+    }
+
+    fn reset(&mut self, ctx: &mut Context<Self::Ctx>) {
+        self.nal_reader.reset(ctx);
+    }
+}
+
+/// Adds _Emulation Prevention_ to the given _Raw Byte Sequence Payload_ (RBSP) of a single NAL
+/// unit; the inverse of [decode_nal].  Expects to be called without the NAL header byte.
+///
+/// As with [RbspEncoder], a final `0x03` is appended if the RBSP ends with a `0x00` byte (i.e.
+/// a `cabac_zero_word`).
+pub fn encode_nal(rbsp: &[u8]) -> Cow<'_, [u8]> {
+    let mut out: Option<Vec<u8>> = None;
+    // rbsp[start..i] has been examined but not yet copied to out.
+    let mut start = 0;
+    let mut zeros = 0;
+    for (i, &b) in rbsp.iter().enumerate() {
+        if zeros == 2 && b <= 0x03 {
+            let out = out.get_or_insert_with(|| Vec::with_capacity(rbsp.len() + 16));
+            out.extend_from_slice(&rbsp[start..i]);
+            out.push(0x03);
+            start = i;
+            zeros = 0;
+        }
+        if b == 0x00 {
+            zeros += 1;
+        } else {
+            zeros = 0;
+        }
+    }
+    if out.is_none() && zeros == 0 {
+        return Cow::Borrowed(rbsp);
+    }
+    let mut out = out.unwrap_or_else(|| Vec::with_capacity(rbsp.len() + 1));
+    out.extend_from_slice(&rbsp[start..]);
+    if zeros > 0 {
+        out.push(0x03);
+    }
+    Cow::Owned(out)
+}
+
+/// Rebuilds a whole NAL unit from its header and the RBSP delivered by [RbspDecoder], omitting
+/// any trailing zero bytes (which for a conforming byte stream are `trailing_zero_8bits` rather
+/// than part of the NAL unit).  Only suitable for NAL units which end with `rbsp_trailing_bits()`.
+pub(crate) fn reencode_nal(header: NalHeader, rbsp: &[u8]) -> Vec<u8> {
+    let len = rbsp.iter().rposition(|&b| b != 0x00).map_or(0, |i| i + 1);
+    let payload = encode_nal(&rbsp[..len]);
+    let mut nal = Vec::with_capacity(1 + payload.len());
+    nal.push(u8::from(header));
+    nal.extend_from_slice(&payload);
+    nal
 }
 
 /// Removes _Emulation Prevention_ from the given byte sequence of a single NAL unit, returning the
@@ -157,7 +383,7 @@ pub fn decode_nal<'a>(nal_unit: &'a [u8]) -> Cow<'a, [u8]> {
 
         fn start(&mut self, _ctx: &mut Context<Self::Ctx>, _header: NalHeader) {}
 
-        fn push(&mut self, _ctx: &mut Context<Self::Ctx>, buf: &[u8]) {
+        fn push(&mut self, _ctx: &mut Context<Self::Ctx>, buf: &[u8]) -> NalInterest {
             let dest = self.index..(self.index + buf.len());
 
             if &self.data[dest.clone()] != buf {
@@ -165,11 +391,14 @@ pub fn decode_nal<'a>(nal_unit: &'a [u8]) -> Cow<'a, [u8]> {
             }
 
             self.index += buf.len();
+            NalInterest::Continue
         }
 
         fn end(&mut self, _ctx: &mut Context<Self::Ctx>) {
-            if let Cow::Owned(vec) = &mut self.data {
-                vec.truncate(self.index);
+            match &mut self.data {
+                Cow::Owned(vec) => vec.truncate(self.index),
+                // a trailing emulation_prevention_three_byte needs no copy, but still must be removed
+                Cow::Borrowed(slice) => *slice = &slice[..self.index],
             }
         }
     }
@@ -185,6 +414,58 @@ pub fn decode_nal<'a>(nal_unit: &'a [u8]) -> Cow<'a, [u8]> {
     decoder.into_handler().data
 }
 
+/// Removes _Emulation Prevention_ from the given byte sequence of a single NAL unit, returning
+/// the RBSP as a sequence of slices borrowed from the input, split at each
+/// `emulation_prevention_three_byte`.
+///
+/// This avoids copying the NAL unit for consumers which can handle the RBSP in pieces.  Like
+/// [decode_nal], expects to be called without the NAL header byte.
+pub fn decode_nal_chunks(nal_unit: &[u8]) -> RbspChunks<'_> {
+    RbspChunks {
+        rest: nal_unit,
+        state: ParseState::Start,
+    }
+}
+
+/// Iterator returned by [decode_nal_chunks].
+pub struct RbspChunks<'a> {
+    rest: &'a [u8],
+    state: ParseState,
+}
+impl<'a> Iterator for RbspChunks<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        // Same as the lenient mode of RbspDecoder::push().
+        let mut i = 0;
+        while i < self.rest.len() {
+            match self.state {
+                ParseState::TwoZero if self.rest[i] == 0x03 => {
+                    let (chunk, three_onward) = self.rest.split_at(i);
+                    self.rest = &three_onward[1..];
+                    self.state = ParseState::Three;
+                    return Some(chunk);
+                },
+                ParseState::OneZero if self.rest[i] == 0x00 => self.state = ParseState::TwoZero,
+                ParseState::Three if self.rest[i] == 0x00 => self.state = ParseState::OneZero,
+                ParseState::Start | ParseState::Error => match memchr::memchr(0x00, &self.rest[i..]) {
+                    Some(nonzero_len) => {
+                        i += nonzero_len;
+                        self.state = ParseState::OneZero;
+                    },
+                    None => break,
+                },
+                _ => self.state = ParseState::Start,
+            }
+            i += 1;
+        }
+        Some(std::mem::take(&mut self.rest))
+    }
+}
+
 impl From<std::io::Error> for RbspBitReaderError {
     fn from(e: std::io::Error) -> Self {
         RbspBitReaderError::ReaderError(e)
@@ -198,15 +479,207 @@ pub enum RbspBitReaderError {
 
     /// An Exp-Golomb-coded syntax elements value has more than 32 bits.
     ExpGolombTooLarge(&'static str),
+
+    /// A mapped Exp-Golomb-coded syntax element had a `codeNum` with no entry in Table 9-4.
+    MappedExpGolombOutOfRange(&'static str, u32),
+
+    /// The end of the data was reached before a syntax element was completely read.
+    UnexpectedEnd,
+    /// The end of the data was reached before the named syntax element was completely read.
+    UnexpectedEndFor(&'static str),
+
+    /// A byte-oriented read of the named syntax element was attempted at a position which is
+    /// not byte-aligned.
+    NotByteAligned(&'static str),
+
+    /// The named bit of `rbsp_trailing_bits()` had the wrong value.
+    InvalidTrailingBits(&'static str),
+    /// A `cabac_zero_word` starting at the given byte offset had a value other than `0x0000`.
+    NonZeroCabacZeroWord { byte_offset: u64, value: u16 },
+}
+
+/// Where within nested syntax structures a parse error occurred.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorLocation {
+    scopes: Vec<&'static str>,
+    bit_offset: u64,
+}
+impl ErrorLocation {
+    pub fn new(scope: &'static str, bit_offset: u64) -> ErrorLocation {
+        ErrorLocation {
+            scopes: vec![scope],
+            bit_offset,
+        }
+    }
+
+    /// Records that this location is itself within the given (outer) scope.
+    pub fn within(mut self, scope: &'static str) -> ErrorLocation {
+        self.scopes.insert(0, scope);
+        self
+    }
+
+    /// The names of the enclosing syntax structures, outermost first (e.g.
+    /// `["sps", "vui", "nal_hrd"]`).
+    pub fn scopes(&self) -> &[&'static str] {
+        &self.scopes
+    }
+
+    /// The reader's bit position when the error was detected.
+    pub fn bit_offset(&self) -> u64 {
+        self.bit_offset
+    }
+}
+impl fmt::Display for ErrorLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at bit {}", self.scopes.join("."), self.bit_offset)
+    }
+}
+
+/// Implemented by error types which can be annotated with an [ErrorLocation].
+pub trait ErrorContext: Sized {
+    /// Annotates the error as having occurred within `scope`.  `bit_offset` should only be
+    /// recorded if no inner scope has already done so.
+    fn in_scope(self, scope: &'static str, bit_offset: u64) -> Self;
+}
+
+/// Runs `f`, annotating any error it returns with `scope` and the reader's position at the time
+/// of the error.
+pub fn scoped<R: BitRead + ?Sized, T, E: ErrorContext>(r: &mut R, scope: &'static str, f: impl FnOnce(&mut R) -> Result<T, E>) -> Result<T, E> {
+    f(r).map_err(|e| e.in_scope(scope, r.bit_position()))
+}
+
+/// Selects the column of Table 9-4 used when reading an `me(v)`-coded `coded_block_pattern`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodedBlockPatternMode {
+    /// The macroblock prediction mode is `Intra_4x4` or `Intra_8x8`.
+    Intra,
+    /// The macroblock prediction mode is `Pred_L0`, `Pred_L1`, `BiPred` or `Direct`.
+    Inter,
+}
+
+// Table 9-4 (a), for ChromaArrayType equal to 1 or 2, indexed by codeNum
+const CBP_INTRA: [u8; 48] = [
+    47, 31, 15,  0, 23, 27, 29, 30,  7, 11, 13, 14, 39, 43, 45, 46,
+    16,  3,  5, 10, 12, 19, 21, 26, 28, 35, 37, 42, 44,  1,  2,  4,
+     8, 17, 18, 20, 24,  6,  9, 22, 25, 32, 33, 34, 36, 40, 38, 41,
+];
+const CBP_INTER: [u8; 48] = [
+     0, 16,  1,  2,  4,  8, 32,  3,  5, 10, 12, 15, 47,  7, 11, 13,
+    14,  6,  9, 31, 35, 37, 42, 44, 33, 34, 36, 40, 39, 43, 45, 46,
+    17, 18, 20, 24, 19, 21, 26, 28, 23, 27, 29, 30, 22, 25, 38, 41,
+];
+// Table 9-4 (b), for ChromaArrayType equal to 0 or 3, indexed by codeNum
+const CBP_INTRA_NO_CHROMA: [u8; 16] = [15, 0, 7, 11, 13, 14, 3, 5, 10, 12, 1, 2, 4, 8, 6, 9];
+const CBP_INTER_NO_CHROMA: [u8; 16] = [0, 1, 2, 4, 8, 3, 5, 10, 12, 15, 7, 11, 13, 14, 6, 9];
+
+/// Reads H264 bitstream syntax elements from an RBSP.
+///
+/// Implemented by [RbspBitReader], and by [BitReaderSlice] which avoids the overhead of
+/// `std::io` when the whole RBSP is already in memory.  Parsers which are generic over this
+/// trait can use either.
+pub trait BitRead {
+    /// Reads an unsigned Exp-Golomb-coded `ue(v)` syntax element.
+    fn read_ue_named(&mut self, name: &'static str) -> Result<u32, RbspBitReaderError>;
+    /// Reads a signed Exp-Golomb-coded `se(v)` syntax element.
+    fn read_se_named(&mut self, name: &'static str) -> Result<i32, RbspBitReaderError>;
+    /// Reads a truncated Exp-Golomb-coded `te(v)` syntax element, given the maximum value the
+    /// element may take.
+    ///
+    /// Per section 9.1.1, when `max` is greater than `1` this is equivalent to `ue(v)`;
+    /// otherwise the value is coded as the inverse of a single bit.
+    fn read_te_named(&mut self, name: &'static str, max: u32) -> Result<u32, RbspBitReaderError> {
+        if max > 1 {
+            self.read_ue_named(name)
+        } else {
+            Ok(u32::from(!self.read_bool_named(name)?))
+        }
+    }
+    /// Reads a mapped Exp-Golomb-coded `me(v)` syntax element (i.e. `coded_block_pattern`),
+    /// translating the `codeNum` via Table 9-4.
+    ///
+    /// `chroma_array_type` is the _ChromaArrayType_ derived from the SPS (which is
+    /// `ChromaFormat::Monochrome` when `separate_colour_plane_flag` is set).
+    fn read_me_named(&mut self, name: &'static str, chroma_array_type: ChromaFormat, mode: CodedBlockPatternMode) -> Result<u8, RbspBitReaderError> {
+        let code_num = self.read_ue_named(name)?;
+        let table = match (chroma_array_type, mode) {
+            (ChromaFormat::YUV420, CodedBlockPatternMode::Intra) | (ChromaFormat::YUV422, CodedBlockPatternMode::Intra) => &CBP_INTRA[..],
+            (ChromaFormat::YUV420, CodedBlockPatternMode::Inter) | (ChromaFormat::YUV422, CodedBlockPatternMode::Inter) => &CBP_INTER[..],
+            (_, CodedBlockPatternMode::Intra) => &CBP_INTRA_NO_CHROMA[..],
+            (_, CodedBlockPatternMode::Inter) => &CBP_INTER_NO_CHROMA[..],
+        };
+        table.get(code_num as usize)
+            .copied()
+            .ok_or(RbspBitReaderError::MappedExpGolombOutOfRange(name, code_num))
+    }
+    fn read_bool(&mut self) -> Result<bool, RbspBitReaderError>;
+    fn read_bool_named(&mut self, name: &'static str) -> Result<bool, RbspBitReaderError>;
+    fn read_u8(&mut self, bit_count: u32) -> Result<u8, RbspBitReaderError>;
+    fn read_u16(&mut self, bit_count: u8) -> Result<u16, RbspBitReaderError>;
+    fn read_u32(&mut self, bit_count: u8) -> Result<u32, RbspBitReaderError>;
+    fn read_i32(&mut self, bit_count: u8) -> Result<i32, RbspBitReaderError>;
+    /// Returns true if positioned before the RBSP trailing bits.
+    ///
+    /// This matches the definition of `more_rbsp_data()` in Rec. ITU-T H.264
+    /// (03/2010) section 7.2.
+    fn has_more_rbsp_data(&mut self, name: &'static str) -> Result<bool, RbspBitReaderError>;
+    /// Fills `buf` with the next bytes of data, which must start at a byte-aligned position.
+    ///
+    /// Intended for byte-oriented payloads embedded in the bitstream, such as the
+    /// `user_data_payload_byte` values of SEI messages.
+    fn read_bytes_aligned(&mut self, buf: &mut [u8], name: &'static str) -> Result<(), RbspBitReaderError>;
+    /// Returns all remaining bytes of the data, which must start at a byte-aligned position.
+    ///
+    /// Note that no attempt is made to strip RBSP trailing bits, so this is most useful for
+    /// readers constructed over a single SEI payload or similar.
+    fn read_remaining_payload(&mut self, name: &'static str) -> Result<Vec<u8>, RbspBitReaderError>;
+    /// Reads `rbsp_slice_trailing_bits()`, validating the `rbsp_stop_one_bit`, alignment bits
+    /// and any following `cabac_zero_word`s, and returning the number of `cabac_zero_word`s.
+    ///
+    /// Must be called when positioned just after the end of `slice_data()`, and relies on
+    /// `approximate_bits_remaining()` being exact.
+    fn read_rbsp_slice_trailing_bits(&mut self) -> Result<u32, RbspBitReaderError> {
+        if !self.read_bool_named("rbsp_stop_one_bit")? {
+            return Err(RbspBitReaderError::InvalidTrailingBits("rbsp_stop_one_bit"));
+        }
+        while !self.is_byte_aligned() {
+            if self.read_bool_named("rbsp_alignment_zero_bit")? {
+                return Err(RbspBitReaderError::InvalidTrailingBits("rbsp_alignment_zero_bit"));
+            }
+        }
+        let mut cabac_zero_words = 0;
+        while self.approximate_bits_remaining() > 0 {
+            let byte_offset = self.bit_position() / 8;
+            if self.approximate_bits_remaining() < 16 {
+                return Err(RbspBitReaderError::UnexpectedEndFor("cabac_zero_word"));
+            }
+            let value = self.read_u16(16)?;
+            if value != 0 {
+                return Err(RbspBitReaderError::NonZeroCabacZeroWord { byte_offset, value });
+            }
+            cabac_zero_words += 1;
+        }
+        Ok(cabac_zero_words)
+    }
+    /// Returns true if the next bit to be read is the first bit of a byte.
+    fn is_byte_aligned(&self) -> bool;
+    /// Returns the number of bits read so far.
+    fn bit_position(&self) -> u64;
+    /// Returns the number of bits which remain to be read, including any RBSP trailing bits.
+    ///
+    /// This allows SEI payload parsers to implement `more_data_in_payload()`.  Implementations
+    /// over data whose length isn't known in advance may only be able to give an estimate.
+    fn approximate_bits_remaining(&self) -> u64;
 }
 
 pub struct RbspBitReader<'buf> {
     reader: bitstream_io::read::BitReader<std::io::Cursor<&'buf [u8]>, bitstream_io::BigEndian>,
+    len: usize,
 }
 impl<'buf> RbspBitReader<'buf> {
     pub fn new(buf: &'buf [u8]) -> Self {
         RbspBitReader {
             reader: bitstream_io::read::BitReader::new(std::io::Cursor::new(buf)),
+            len: buf.len(),
         }
     }
 
@@ -214,14 +687,14 @@ impl<'buf> RbspBitReader<'buf> {
         let count = count_zero_bits(&mut self.reader, name)?;
         if count > 0 {
             let val = self.read_u32(count)?;
-            Ok((1 << count) -1 + val)
+            exp_golomb_value(count.into(), val, name)
         } else {
             Ok(0)
         }
     }
 
     pub fn read_se_named(&mut self, name: &'static str) -> Result<i32, RbspBitReaderError> {
-        Ok(Self::golomb_to_signed(self.read_ue_named(name)?))
+        Ok(golomb_to_signed(self.read_ue_named(name)?))
     }
 
     pub fn read_bool(&mut self) -> Result<bool, RbspBitReaderError> {
@@ -268,12 +741,96 @@ impl<'buf> RbspBitReader<'buf> {
         }
     }
 
-    fn golomb_to_signed(val: u32) -> i32 {
-        let sign = (((val & 0x1) as i32) << 1) - 1;
-        ((val >> 1) as i32 + (val & 0x1) as i32) * sign
+    pub fn read_bytes_aligned(&mut self, buf: &mut [u8], name: &'static str) -> Result<(), RbspBitReaderError> {
+        if !self.reader.byte_aligned() {
+            return Err(RbspBitReaderError::NotByteAligned(name));
+        }
+        self.reader.read_bytes(buf).map_err(|e| match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => RbspBitReaderError::UnexpectedEndFor(name),
+            _ => RbspBitReaderError::ReaderErrorFor(name, e),
+        })
+    }
+
+    pub fn read_remaining_payload(&mut self, name: &'static str) -> Result<Vec<u8>, RbspBitReaderError> {
+        let mut buf = Vec::new();
+        self.reader.reader()
+            .ok_or(RbspBitReaderError::NotByteAligned(name))?
+            .read_to_end(&mut buf)
+            .map_err(|e| RbspBitReaderError::ReaderErrorFor(name, e))?;
+        Ok(buf)
+    }
+
+    pub fn is_byte_aligned(&self) -> bool {
+        self.reader.byte_aligned()
+    }
+
+    pub fn bit_position(&self) -> u64 {
+        // position_in_bits() needs a mutable reference, but cloning a Cursor over a slice is cheap
+        self.reader.clone()
+            .position_in_bits()
+            .expect("Cursor position should always be available")
+    }
+
+    pub fn approximate_bits_remaining(&self) -> u64 {
+        (self.len as u64 * 8).saturating_sub(self.bit_position())
     }
 }
-fn count_zero_bits<R: BitRead>(r: &mut R, name: &'static str) -> Result<u8, RbspBitReaderError> {
+impl<'buf> BitRead for RbspBitReader<'buf> {
+    fn read_ue_named(&mut self, name: &'static str) -> Result<u32, RbspBitReaderError> {
+        RbspBitReader::read_ue_named(self, name)
+    }
+    fn read_se_named(&mut self, name: &'static str) -> Result<i32, RbspBitReaderError> {
+        RbspBitReader::read_se_named(self, name)
+    }
+    fn read_bool(&mut self) -> Result<bool, RbspBitReaderError> {
+        RbspBitReader::read_bool(self)
+    }
+    fn read_bool_named(&mut self, name: &'static str) -> Result<bool, RbspBitReaderError> {
+        RbspBitReader::read_bool_named(self, name)
+    }
+    fn read_u8(&mut self, bit_count: u32) -> Result<u8, RbspBitReaderError> {
+        RbspBitReader::read_u8(self, bit_count)
+    }
+    fn read_u16(&mut self, bit_count: u8) -> Result<u16, RbspBitReaderError> {
+        RbspBitReader::read_u16(self, bit_count)
+    }
+    fn read_u32(&mut self, bit_count: u8) -> Result<u32, RbspBitReaderError> {
+        RbspBitReader::read_u32(self, bit_count)
+    }
+    fn read_i32(&mut self, bit_count: u8) -> Result<i32, RbspBitReaderError> {
+        RbspBitReader::read_i32(self, bit_count)
+    }
+    fn has_more_rbsp_data(&mut self, name: &'static str) -> Result<bool, RbspBitReaderError> {
+        RbspBitReader::has_more_rbsp_data(self, name)
+    }
+    fn read_bytes_aligned(&mut self, buf: &mut [u8], name: &'static str) -> Result<(), RbspBitReaderError> {
+        RbspBitReader::read_bytes_aligned(self, buf, name)
+    }
+    fn read_remaining_payload(&mut self, name: &'static str) -> Result<Vec<u8>, RbspBitReaderError> {
+        RbspBitReader::read_remaining_payload(self, name)
+    }
+    fn is_byte_aligned(&self) -> bool {
+        RbspBitReader::is_byte_aligned(self)
+    }
+    fn bit_position(&self) -> u64 {
+        RbspBitReader::bit_position(self)
+    }
+    fn approximate_bits_remaining(&self) -> u64 {
+        RbspBitReader::approximate_bits_remaining(self)
+    }
+}
+
+/// Combines the prefix length and suffix of a `ue(v)` element, where `count` is at most `31`.
+fn exp_golomb_value(count: u32, suffix: u32, name: &'static str) -> Result<u32, RbspBitReaderError> {
+    (1u32 << count).checked_sub(1)
+        .and_then(|base| base.checked_add(suffix))
+        .ok_or(RbspBitReaderError::ExpGolombTooLarge(name))
+}
+fn golomb_to_signed(val: u32) -> i32 {
+    let sign = (((val & 0x1) as i32) << 1) - 1;
+    ((val >> 1) as i32 + (val & 0x1) as i32) * sign
+}
+fn count_zero_bits<R: bitstream_io::read::BitRead>(r: &mut R, name: &'static str) -> Result<u8, RbspBitReaderError> {
     let mut count = 0;
     while !r.read_bit()? {
         count += 1;
@@ -284,6 +841,495 @@ fn count_zero_bits<R: BitRead>(r: &mut R, name: &'static str) -> Result<u8, Rbsp
     Ok(count)
 }
 
+/// A [BitRead] implementation over an in-memory RBSP, which avoids the overhead of `std::io`.
+pub struct BitReaderSlice<'buf> {
+    data: &'buf [u8],
+    /// index of the next bit to be read, counting from the most-significant bit of `data[0]`.
+    pos: usize,
+}
+impl<'buf> BitReaderSlice<'buf> {
+    pub fn new(data: &'buf [u8]) -> Self {
+        BitReaderSlice {
+            data,
+            pos: 0,
+        }
+    }
+
+    fn remaining_bits(&self) -> usize {
+        self.data.len() * 8 - self.pos
+    }
+
+    /// Returns the next (at least 57) bits as the most-significant bits of the result, padding
+    /// with zero bits beyond the end of the data.
+    fn peek_u64(&self) -> u64 {
+        let start = std::cmp::min(self.pos / 8, self.data.len());
+        let avail = &self.data[start..];
+        let n = std::cmp::min(avail.len(), 8);
+        let mut bytes = [0u8; 8];
+        bytes[..n].copy_from_slice(&avail[..n]);
+        u64::from_be_bytes(bytes) << (self.pos % 8)
+    }
+
+    fn read_bits(&mut self, bit_count: u32, name: Option<&'static str>) -> Result<u32, RbspBitReaderError> {
+        check_bit_count(bit_count, 32)?;
+        if bit_count as usize > self.remaining_bits() {
+            return Err(name.map_or(RbspBitReaderError::UnexpectedEnd, RbspBitReaderError::UnexpectedEndFor));
+        }
+        if bit_count == 0 {
+            return Ok(0);
+        }
+        let val = self.peek_u64() >> (64 - bit_count);
+        self.pos += bit_count as usize;
+        Ok(val as u32)
+    }
+}
+/// Rejects a read of more bits than the result type holds, as `RbspBitReader` does (through
+/// `bitstream_io`), rather than silently truncating the value.
+fn check_bit_count(bit_count: u32, max: u32) -> Result<(), RbspBitReaderError> {
+    if bit_count > max {
+        return Err(RbspBitReaderError::ReaderError(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "excessive bits for type read",
+        )));
+    }
+    Ok(())
+}
+
+impl<'buf> BitRead for BitReaderSlice<'buf> {
+    fn read_ue_named(&mut self, name: &'static str) -> Result<u32, RbspBitReaderError> {
+        let count = self.peek_u64().leading_zeros() as usize;
+        if count >= self.remaining_bits() {
+            return Err(RbspBitReaderError::UnexpectedEndFor(name));
+        }
+        if count > 31 {
+            return Err(RbspBitReaderError::ExpGolombTooLarge(name));
+        }
+        self.pos += count + 1;
+        if count > 0 {
+            let val = self.read_bits(count as u32, Some(name))?;
+            exp_golomb_value(count as u32, val, name)
+        } else {
+            Ok(0)
+        }
+    }
+
+    fn read_se_named(&mut self, name: &'static str) -> Result<i32, RbspBitReaderError> {
+        Ok(golomb_to_signed(self.read_ue_named(name)?))
+    }
+
+    fn read_bool(&mut self) -> Result<bool, RbspBitReaderError> {
+        self.read_bits(1, None).map(|v| v != 0)
+    }
+
+    fn read_bool_named(&mut self, name: &'static str) -> Result<bool, RbspBitReaderError> {
+        self.read_bits(1, Some(name)).map(|v| v != 0)
+    }
+
+    fn read_u8(&mut self, bit_count: u32) -> Result<u8, RbspBitReaderError> {
+        check_bit_count(bit_count, 8)?;
+        self.read_bits(bit_count, None).map(|v| v as u8)
+    }
+
+    fn read_u16(&mut self, bit_count: u8) -> Result<u16, RbspBitReaderError> {
+        check_bit_count(u32::from(bit_count), 16)?;
+        self.read_bits(u32::from(bit_count), None).map(|v| v as u16)
+    }
+
+    fn read_u32(&mut self, bit_count: u8) -> Result<u32, RbspBitReaderError> {
+        self.read_bits(u32::from(bit_count), None)
+    }
+
+    fn read_i32(&mut self, bit_count: u8) -> Result<i32, RbspBitReaderError> {
+        self.read_bits(u32::from(bit_count), None).map(|v| v as i32)
+    }
+
+    fn has_more_rbsp_data(&mut self, _name: &'static str) -> Result<bool, RbspBitReaderError> {
+        // The final 1 bit of the data is the rbsp_stop_one_bit, possibly followed by
+        // alignment_zero_bits and cabac_zero_words.
+        Ok(match self.data.iter().rposition(|&b| b != 0) {
+            Some(i) => {
+                let stop_bit = i * 8 + 7 - self.data[i].trailing_zeros() as usize;
+                self.pos < stop_bit
+            },
+            None => false,
+        })
+    }
+
+    fn read_bytes_aligned(&mut self, buf: &mut [u8], name: &'static str) -> Result<(), RbspBitReaderError> {
+        if !self.is_byte_aligned() {
+            return Err(RbspBitReaderError::NotByteAligned(name));
+        }
+        let start = self.pos / 8;
+        let src = self.data.get(start..start + buf.len())
+            .ok_or(RbspBitReaderError::UnexpectedEndFor(name))?;
+        buf.copy_from_slice(src);
+        self.pos += buf.len() * 8;
+        Ok(())
+    }
+
+    fn read_remaining_payload(&mut self, name: &'static str) -> Result<Vec<u8>, RbspBitReaderError> {
+        if !self.is_byte_aligned() {
+            return Err(RbspBitReaderError::NotByteAligned(name));
+        }
+        let rest = self.data[self.pos / 8..].to_vec();
+        self.pos = self.data.len() * 8;
+        Ok(rest)
+    }
+
+    fn is_byte_aligned(&self) -> bool {
+        self.pos & 0b111 == 0
+    }
+
+    fn bit_position(&self) -> u64 {
+        self.pos as u64
+    }
+
+    fn approximate_bits_remaining(&self) -> u64 {
+        self.remaining_bits() as u64
+    }
+}
+
+/// A [BitRead] implementation over a NAL unit's escaped payload (i.e. still containing
+/// emulation prevention bytes), which may be split across several chunks.
+///
+/// Emulation prevention bytes are removed lazily as the data is read, so no copy of the NAL unit
+/// is needed.  See [crate::nal::RefNal::reader].
+#[derive(Clone)]
+pub struct ChunkedBitReader<'a> {
+    cursor: EscapedCursor<'a>,
+    byte: u8,
+    bits_left: u32,
+    pos: u64,
+}
+impl<'a> ChunkedBitReader<'a> {
+    /// Creates a reader over the concatenation of `head` and each of `tail`.
+    pub fn new(head: &'a [u8], tail: &'a [&'a [u8]]) -> Self {
+        ChunkedBitReader {
+            cursor: EscapedCursor {
+                head,
+                tail,
+                zeros: 0,
+            },
+            byte: 0,
+            bits_left: 0,
+            pos: 0,
+        }
+    }
+
+    fn end_error(name: Option<&'static str>) -> RbspBitReaderError {
+        name.map_or(RbspBitReaderError::UnexpectedEnd, RbspBitReaderError::UnexpectedEndFor)
+    }
+
+    fn read_bit(&mut self, name: Option<&'static str>) -> Result<bool, RbspBitReaderError> {
+        if self.bits_left == 0 {
+            self.byte = self.cursor.next_byte().ok_or_else(|| Self::end_error(name))?;
+            self.bits_left = 8;
+        }
+        self.bits_left -= 1;
+        self.pos += 1;
+        Ok((self.byte >> self.bits_left) & 1 != 0)
+    }
+
+    fn read_bits(&mut self, bit_count: u32, name: Option<&'static str>) -> Result<u32, RbspBitReaderError> {
+        check_bit_count(bit_count, 32)?;
+        if bit_count <= self.bits_left {
+            self.bits_left -= bit_count;
+            self.pos += u64::from(bit_count);
+            return Ok((u32::from(self.byte) >> self.bits_left) & ((1 << bit_count) - 1));
+        }
+        // The rest of the current byte, then whole bytes, then the leading bits of a final byte.
+        // Bytes are taken through a copy of the cursor, so that a failed read leaves the
+        // position unchanged.
+        let mut cursor = self.cursor;
+        let mut val = u64::from(self.byte) & ((1 << self.bits_left) - 1);
+        let mut needed = bit_count - self.bits_left;
+        while needed >= 8 {
+            val = (val << 8) | u64::from(cursor.next_byte().ok_or_else(|| Self::end_error(name))?);
+            needed -= 8;
+        }
+        if needed > 0 {
+            let b = cursor.next_byte().ok_or_else(|| Self::end_error(name))?;
+            val = (val << needed) | u64::from(b >> (8 - needed));
+            self.byte = b;
+            self.bits_left = 8 - needed;
+        } else {
+            self.bits_left = 0;
+        }
+        self.cursor = cursor;
+        self.pos += u64::from(bit_count);
+        Ok(val as u32)
+    }
+}
+
+/// The position of a [ChunkedBitReader] within the escaped data, which is cheap to copy.
+#[derive(Clone, Copy)]
+struct EscapedCursor<'a> {
+    head: &'a [u8],
+    tail: &'a [&'a [u8]],
+    /// number of consecutive 0x00 bytes read from the escaped data (at most 2)
+    zeros: u8,
+}
+impl<'a> EscapedCursor<'a> {
+    /// Returns the next RBSP byte, skipping emulation prevention bytes in the same way as the
+    /// lenient mode of [RbspDecoder].
+    #[inline]
+    fn next_byte(&mut self) -> Option<u8> {
+        loop {
+            let b = match self.head.split_first() {
+                Some((&b, rest)) => {
+                    self.head = rest;
+                    b
+                },
+                None => {
+                    self.next_chunk()?;
+                    continue;
+                },
+            };
+            if self.zeros == 2 {
+                self.zeros = 0;
+                if b == 0x03 {
+                    continue;
+                }
+            } else if b == 0x00 {
+                self.zeros += 1;
+            } else {
+                self.zeros = 0;
+            }
+            return Some(b);
+        }
+    }
+
+    /// Moves on to the next chunk, returning `None` at the end of the data.
+    #[cold]
+    fn next_chunk(&mut self) -> Option<()> {
+        let (first, rest) = self.tail.split_first()?;
+        self.head = first;
+        self.tail = rest;
+        Some(())
+    }
+}
+impl<'a> BitRead for ChunkedBitReader<'a> {
+    fn read_ue_named(&mut self, name: &'static str) -> Result<u32, RbspBitReaderError> {
+        let mut count = 0;
+        while !self.read_bit(Some(name))? {
+            count += 1;
+            if count > 31 {
+                return Err(RbspBitReaderError::ExpGolombTooLarge(name));
+            }
+        }
+        if count > 0 {
+            let val = self.read_bits(count, Some(name))?;
+            exp_golomb_value(count, val, name)
+        } else {
+            Ok(0)
+        }
+    }
+
+    fn read_se_named(&mut self, name: &'static str) -> Result<i32, RbspBitReaderError> {
+        Ok(golomb_to_signed(self.read_ue_named(name)?))
+    }
+
+    fn read_bool(&mut self) -> Result<bool, RbspBitReaderError> {
+        self.read_bit(None)
+    }
+
+    fn read_bool_named(&mut self, name: &'static str) -> Result<bool, RbspBitReaderError> {
+        self.read_bit(Some(name))
+    }
+
+    fn read_u8(&mut self, bit_count: u32) -> Result<u8, RbspBitReaderError> {
+        check_bit_count(bit_count, 8)?;
+        self.read_bits(bit_count, None).map(|v| v as u8)
+    }
+
+    fn read_u16(&mut self, bit_count: u8) -> Result<u16, RbspBitReaderError> {
+        check_bit_count(u32::from(bit_count), 16)?;
+        self.read_bits(u32::from(bit_count), None).map(|v| v as u16)
+    }
+
+    fn read_u32(&mut self, bit_count: u8) -> Result<u32, RbspBitReaderError> {
+        self.read_bits(u32::from(bit_count), None)
+    }
+
+    fn read_i32(&mut self, bit_count: u8) -> Result<i32, RbspBitReaderError> {
+        self.read_bits(u32::from(bit_count), None).map(|v| v as i32)
+    }
+
+    fn has_more_rbsp_data(&mut self, _name: &'static str) -> Result<bool, RbspBitReaderError> {
+        // There's more data if any 1 bit follows the next 1 bit (the candidate rbsp_stop_one_bit).
+        let mut r = self.clone();
+        let mut found_stop_bit = false;
+        while let Ok(bit) = r.read_bit(None) {
+            if bit {
+                if found_stop_bit {
+                    return Ok(true);
+                }
+                found_stop_bit = true;
+            }
+        }
+        Ok(false)
+    }
+
+    fn read_bytes_aligned(&mut self, buf: &mut [u8], name: &'static str) -> Result<(), RbspBitReaderError> {
+        if !self.is_byte_aligned() {
+            return Err(RbspBitReaderError::NotByteAligned(name));
+        }
+        let mut cursor = self.cursor;
+        for b in buf.iter_mut() {
+            *b = cursor.next_byte().ok_or(RbspBitReaderError::UnexpectedEndFor(name))?;
+        }
+        self.cursor = cursor;
+        self.pos += buf.len() as u64 * 8;
+        Ok(())
+    }
+
+    fn read_remaining_payload(&mut self, name: &'static str) -> Result<Vec<u8>, RbspBitReaderError> {
+        if !self.is_byte_aligned() {
+            return Err(RbspBitReaderError::NotByteAligned(name));
+        }
+        let mut rest = Vec::new();
+        while let Some(b) = self.cursor.next_byte() {
+            rest.push(b);
+            self.pos += 8;
+        }
+        Ok(rest)
+    }
+
+    fn is_byte_aligned(&self) -> bool {
+        self.bits_left == 0
+    }
+
+    fn bit_position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Exact, but takes time proportional to the remaining data.
+    fn approximate_bits_remaining(&self) -> u64 {
+        let mut cursor = self.cursor;
+        let mut bytes = 0u64;
+        while cursor.next_byte().is_some() {
+            bytes += 1;
+        }
+        bytes * 8 + u64::from(self.bits_left)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BitWriterError {
+    /// The value of the named syntax element doesn't fit in the element's number of bits.
+    ValueTooLarge { name: &'static str, value: u64, bit_count: u32 },
+    /// The named syntax element would take a value outside the range allowed by its semantics
+    /// (for example a count derived from the length of a `Vec`).
+    ValueOutOfRange { name: &'static str, value: i64 },
+    /// The named syntax element was present where the parameter sets (or the syntax preceding
+    /// it) require it to be absent, or vice versa, or otherwise disagrees with them.
+    Inconsistent { name: &'static str },
+}
+
+/// Writes H264 bitstream syntax elements to an RBSP; the counterpart of [BitRead].
+///
+/// Implementations need only provide `write_bits` and `bit_position`.  Emulation prevention is
+/// not applied; see [encode_nal].
+pub trait BitWrite {
+    /// Appends the `bit_count` least-significant bits of `value`, most-significant first.
+    /// `bit_count` must be at most 64, and `value` must fit within it.
+    fn write_bits(&mut self, bit_count: u32, value: u64);
+    /// Returns the number of bits written so far.
+    fn bit_position(&self) -> u64;
+
+    /// Returns true if the next bit to be written is the first bit of a byte.
+    fn is_byte_aligned(&self) -> bool {
+        self.bit_position() & 7 == 0
+    }
+    /// Writes a single-bit flag.
+    fn write_bool_named(&mut self, _name: &'static str, value: bool) -> Result<(), BitWriterError> {
+        self.write_bits(1, u64::from(value));
+        Ok(())
+    }
+    /// Writes an unsigned fixed-length `u(n)` syntax element of `bit_count` bits (at most 32).
+    fn write_u_named(&mut self, name: &'static str, bit_count: u32, value: u32) -> Result<(), BitWriterError> {
+        debug_assert!(bit_count <= 32);
+        if bit_count < 32 && value >> bit_count != 0 {
+            return Err(BitWriterError::ValueTooLarge { name, value: u64::from(value), bit_count });
+        }
+        self.write_bits(bit_count, u64::from(value));
+        Ok(())
+    }
+    /// Writes an unsigned Exp-Golomb-coded `ue(v)` syntax element.  `u32::MAX` is out of range,
+    /// as its 32 leading zero bits are more than [BitRead::read_ue_named] accepts.
+    fn write_ue_named(&mut self, name: &'static str, value: u32) -> Result<(), BitWriterError> {
+        write_exp_golomb(self, name, i64::from(value), u64::from(value))
+    }
+    /// Writes a signed Exp-Golomb-coded `se(v)` syntax element.  `i32::MIN` is out of range, as
+    /// for `write_ue_named`.
+    fn write_se_named(&mut self, name: &'static str, value: i32) -> Result<(), BitWriterError> {
+        // _Table 9-3_: positive values map to odd codeNums, others to even.
+        let value = i64::from(value);
+        let code_num = if value > 0 { 2 * value - 1 } else { -2 * value };
+        write_exp_golomb(self, name, value, code_num as u64)
+    }
+    /// Writes `rbsp_trailing_bits()`: the `rbsp_stop_one_bit` followed by zero bits up to the
+    /// next byte boundary.
+    fn write_rbsp_trailing_bits(&mut self) -> Result<(), BitWriterError> {
+        self.write_bits(1, 1);
+        while !self.is_byte_aligned() {
+            self.write_bits(1, 0);
+        }
+        Ok(())
+    }
+}
+
+/// Writes the given codeNum, which readers accept only up to 2^32 - 2 (31 leading zero bits).
+fn write_exp_golomb<W: BitWrite + ?Sized>(w: &mut W, name: &'static str, value: i64, code_num: u64) -> Result<(), BitWriterError> {
+    if code_num > u64::from(u32::MAX - 1) {
+        return Err(BitWriterError::ValueOutOfRange { name, value });
+    }
+    let v = code_num + 1;
+    let len = 64 - v.leading_zeros();
+    w.write_bits(len - 1, 0);
+    w.write_bits(len, v);
+    Ok(())
+}
+
+/// A [BitWrite] implementation which appends to an in-memory buffer.
+#[derive(Debug, Default, Clone)]
+pub struct BitWriterVec {
+    data: Vec<u8>,
+    /// the number of bits written to the final byte of `data`, or `0` if it is complete.
+    bits_used: u32,
+}
+impl BitWriterVec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the data written, with any partial final byte padded with zero bits.
+    pub fn into_inner(self) -> Vec<u8> {
+        self.data
+    }
+}
+impl BitWrite for BitWriterVec {
+    fn write_bits(&mut self, mut bit_count: u32, value: u64) {
+        debug_assert!(bit_count <= 64);
+        debug_assert!(bit_count == 64 || value >> bit_count == 0);
+        while bit_count > 0 {
+            if self.bits_used == 0 {
+                self.data.push(0);
+            }
+            let n = std::cmp::min(bit_count, 8 - self.bits_used);
+            let bits = ((value >> (bit_count - n)) & ((1 << n) - 1)) as u8;
+            *self.data.last_mut().unwrap() |= bits << (8 - self.bits_used - n);
+            self.bits_used = (self.bits_used + n) % 8;
+            bit_count -= n;
+        }
+    }
+
+    fn bit_position(&self) -> u64 {
+        let len = self.data.len() as u64 * 8;
+        if self.bits_used == 0 { len } else { len - 8 + u64::from(self.bits_used) }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -295,6 +1341,8 @@ mod tests {
         started: bool,
         ended: bool,
         data: Vec<u8>,
+        /// once at least this many bytes have been received, return `NalInterest::Stop`.
+        limit: Option<usize>,
     }
     struct MockReader {
         state: Rc<RefCell<State>>
@@ -313,8 +1361,13 @@ mod tests {
             self.state.borrow_mut().started = true;
         }
 
-        fn push(&mut self, _ctx: &mut Context<Self::Ctx>, buf: &[u8]) {
-            self.state.borrow_mut().data.extend_from_slice(buf);
+        fn push(&mut self, _ctx: &mut Context<Self::Ctx>, buf: &[u8]) -> NalInterest {
+            let mut state = self.state.borrow_mut();
+            state.data.extend_from_slice(buf);
+            match state.limit {
+                Some(limit) if state.data.len() >= limit => NalInterest::Stop,
+                _ => NalInterest::Continue,
+            }
         }
 
         fn end(&mut self, _ctx: &mut Context<Self::Ctx>) {
@@ -332,6 +1385,7 @@ mod tests {
                 started: false,
                 ended: false,
                 data: Vec::new(),
+                limit: None,
             }));
             let mock = MockReader::new(Rc::clone(&state));
             let mut r = RbspDecoder::new(mock);
@@ -347,6 +1401,88 @@ mod tests {
         }
     }
 
+    #[test]
+    fn strict() {
+        let data = hex!("64 00 00 03 04 00 00 01 80");
+        for &strictness in &[Strictness::Lenient, Strictness::Strict] {
+            for i in 1..data.len()-1 {
+                let state = Rc::new(RefCell::new(State {
+                    started: false,
+                    ended: false,
+                    data: Vec::new(),
+                    limit: None,
+                }));
+                let mock = MockReader::new(Rc::clone(&state));
+                let mut r = RbspDecoder::with_strictness(mock, strictness);
+                let mut ctx = Context::default();
+                let (head, tail) = data.split_at(i);
+                r.start(&mut ctx, NalHeader::new(0x01).unwrap());
+                r.push(&mut ctx, head);
+                r.push(&mut ctx, tail);
+                r.end(&mut ctx);
+                let s = state.borrow();
+                assert!(s.ended);
+                if strictness == Strictness::Strict {
+                    assert_eq!(r.error(), Some(&RbspDecoderError::InvalidEmulationPrevention { offset: 3, byte: 0x04 }), "on split_at({})", i);
+                    assert_eq!(&s.data[..], &hex!("64 00 00")[..], "on split_at({})", i);
+                } else {
+                    assert_eq!(r.error(), None);
+                    assert_eq!(&s.data[..], &hex!("64 00 00 04 00 00 01 80")[..], "on split_at({})", i);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn strict_forbidden_sequence() {
+        let state = Rc::new(RefCell::new(State {
+            started: false,
+            ended: false,
+            data: Vec::new(),
+            limit: None,
+        }));
+        let mock = MockReader::new(Rc::clone(&state));
+        let mut r = RbspDecoder::with_strictness(mock, Strictness::Strict);
+        let mut ctx = Context::default();
+        r.start(&mut ctx, NalHeader::new(0x01).unwrap());
+        r.push(&mut ctx, &hex!("64 00 00 02 80")[..]);
+        r.end(&mut ctx);
+        assert_eq!(r.error(), Some(&RbspDecoderError::ForbiddenSequence { offset: 3, byte: 0x02 }));
+        assert_eq!(&state.borrow().data[..], &hex!("64 00 00")[..]);
+
+        // the error is cleared on the next NAL unit.
+        r.start(&mut ctx, NalHeader::new(0x01).unwrap());
+        r.push(&mut ctx, &hex!("80")[..]);
+        r.end(&mut ctx);
+        assert_eq!(r.error(), None);
+    }
+
+    #[test]
+    fn handler_stop() {
+        let data = hex!("64 00 00 03 01 02 03 04");
+        for i in 1..data.len()-1 {
+            let state = Rc::new(RefCell::new(State {
+                started: false,
+                ended: false,
+                data: Vec::new(),
+                limit: Some(3),
+            }));
+            let mock = MockReader::new(Rc::clone(&state));
+            let mut r = RbspDecoder::new(mock);
+            let mut ctx = Context::default();
+            let (head, tail) = data.split_at(i);
+            r.start(&mut ctx, NalHeader::new(0x01).unwrap());
+            let first = r.push(&mut ctx, head);
+            assert_eq!(r.push(&mut ctx, tail), NalInterest::Stop, "on split_at({})", i);
+            assert_eq!(first == NalInterest::Stop, i >= 3, "on split_at({})", i);
+            r.end(&mut ctx);
+            let s = state.borrow();
+            assert!(s.ended);
+            assert_eq!(&s.data[..], &hex!("64 00 00")[..], "on split_at({})", i);
+            assert_eq!(r.error(), None);
+        }
+    }
+
     #[test]
     fn decode_single_nal() {
         let data = hex!(
@@ -378,6 +1514,98 @@ mod tests {
         assert!(matches!(decoded, Cow::Borrowed(..)));
     }
 
+    #[test]
+    fn decode_chunks() {
+        let data = hex!(
+           "67 42 c0 15 d9 01 41 fb 01 6a 0c 02 0b
+            4a 00 00 03 00 02 00 00 03 00 79 1e 2c
+            5c 90");
+        let chunks: Vec<&[u8]> = decode_nal_chunks(&data).collect();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks.concat(), &decode_nal(&data)[..]);
+
+        for data in &[
+            &hex!("")[..],
+            &hex!("00 00 03")[..],
+            &hex!("00 00 03 00 00 03 00")[..],
+            &hex!("00 00 00 00 03 01 00 00 03")[..],
+            &hex!("64 00 0A AC 72 84 44 26 84 00 00 00 04 00 00 00 CA")[..],
+        ] {
+            let chunks: Vec<&[u8]> = decode_nal_chunks(data).collect();
+            assert_eq!(chunks.concat(), &decode_nal(data)[..], "for {:02x?}", data);
+        }
+    }
+
+    #[test]
+    fn bitreader_slice() {
+        let data = hex!("a6 42 98 e2 04 8a 57 c3 19 ee 5a 80 00 00");
+        let mut slow = RbspBitReader::new(&data[..]);
+        let mut fast = BitReaderSlice::new(&data[..]);
+        fn read_all<R: BitRead + ?Sized>(r: &mut R) -> Vec<i64> {
+            let mut v = vec![];
+            for i in 0..5 {
+                v.push(i64::from(r.read_ue_named("ue").unwrap()));
+                v.push(i64::from(r.read_se_named("se").unwrap()));
+                v.push(i64::from(r.read_bool().unwrap()));
+                v.push(i64::from(r.read_u8(i).unwrap()));
+            }
+            v.push(i64::from(r.read_u32(9).unwrap()));
+            v.push(i64::from(r.read_u16(3).unwrap()));
+            v.push(i64::from(r.has_more_rbsp_data("more").unwrap()));
+            v
+        }
+        assert_eq!(read_all(&mut slow), read_all(&mut fast));
+        while fast.read_bool().is_ok() {}
+        assert!(matches!(fast.read_u8(1), Err(RbspBitReaderError::UnexpectedEnd)));
+
+        let mut r = BitReaderSlice::new(&[0x00, 0x00, 0x00, 0x00, 0x01]);
+        assert!(matches!(r.read_ue_named("big"), Err(RbspBitReaderError::ExpGolombTooLarge("big"))));
+        let mut r = BitReaderSlice::new(&[0x00, 0x01]);
+        assert!(matches!(r.read_ue_named("short"), Err(RbspBitReaderError::UnexpectedEndFor("short"))));
+
+        // reads wider than the result type are rejected, as by RbspBitReader, and consume nothing.
+        let data = [0xff; 8];
+        let mut r = BitReaderSlice::new(&data[..]);
+        assert!(matches!(r.read_u8(9), Err(RbspBitReaderError::ReaderError(_))));
+        assert!(matches!(r.read_u16(17), Err(RbspBitReaderError::ReaderError(_))));
+        assert!(matches!(r.read_u32(33), Err(RbspBitReaderError::ReaderError(_))));
+        assert!(matches!(RbspBitReader::new(&data[..]).read_u8(9), Err(RbspBitReaderError::ReaderError(_))));
+        assert_eq!(r.bit_position(), 0);
+    }
+
+    #[test]
+    fn read_te() {
+        // 0b0 1 010 011 00100 0000
+        let mut r = BitReaderSlice::new(&[0x53, 0x20, 0x00]);
+        assert_eq!(r.read_te_named("te", 1).unwrap(), 1);
+        assert_eq!(r.read_te_named("te", 1).unwrap(), 0);
+        assert_eq!(r.read_te_named("te", 2).unwrap(), 1);
+        assert_eq!(r.read_te_named("te", 7).unwrap(), 2);
+        assert_eq!(r.read_te_named("te", 31).unwrap(), 3);
+    }
+
+    #[test]
+    fn read_me() {
+        // codeNum values 0, 3, 12, 47, 48
+        let data = hex!("90 68 30 06 30");
+        let read = |chroma_array_type, mode| {
+            let mut r = BitReaderSlice::new(&data[..]);
+            let mut v = vec![];
+            for _ in 0..4 {
+                v.push(r.read_me_named("cbp", chroma_array_type, mode).unwrap());
+            }
+            assert!(matches!(r.read_me_named("cbp", chroma_array_type, mode), Err(RbspBitReaderError::MappedExpGolombOutOfRange("cbp", 48))));
+            v
+        };
+        assert_eq!(read(ChromaFormat::YUV420, CodedBlockPatternMode::Intra), [47, 0, 39, 41]);
+        assert_eq!(read(ChromaFormat::YUV422, CodedBlockPatternMode::Inter), [0, 2, 47, 41]);
+        // codeNum values 0, 2, 16
+        let mut r = BitReaderSlice::new(&[0xb0, 0x8c]);
+        assert_eq!(r.read_me_named("cbp", ChromaFormat::Monochrome, CodedBlockPatternMode::Intra).unwrap(), 15);
+        assert_eq!(r.read_me_named("cbp", ChromaFormat::YUV444, CodedBlockPatternMode::Inter).unwrap(), 2);
+        assert!(matches!(r.read_me_named("cbp", ChromaFormat::YUV444, CodedBlockPatternMode::Inter), Err(RbspBitReaderError::MappedExpGolombOutOfRange("cbp", 16))));
+    }
+
     #[test]
     fn bitreader_has_more_data() {
         // Should work when the end bit is byte-aligned.
@@ -396,4 +1624,183 @@ mod tests {
         let mut reader = RbspBitReader::new(&[0x80, 0x00, 0x00]);
         assert!(!reader.has_more_rbsp_data("at end with cabac-zero-words").unwrap());
     }
+
+    #[test]
+    fn read_bytes() {
+        let data = hex!("81 02 03 04 05 06");
+        fn check<R: BitRead>(mut r: R) {
+            let mut buf = [0u8; 2];
+            assert!(r.is_byte_aligned());
+            assert!(r.read_bool().unwrap());
+            assert!(!r.is_byte_aligned());
+            assert_eq!((r.bit_position(), r.approximate_bits_remaining()), (1, 47));
+            assert!(matches!(r.read_bytes_aligned(&mut buf, "buf"), Err(RbspBitReaderError::NotByteAligned("buf"))));
+            assert!(matches!(r.read_remaining_payload("rest"), Err(RbspBitReaderError::NotByteAligned("rest"))));
+            assert_eq!(r.read_u8(7).unwrap(), 1);
+            r.read_bytes_aligned(&mut buf, "buf").unwrap();
+            assert_eq!(buf, [2, 3]);
+            assert_eq!(r.read_u8(8).unwrap(), 4);
+            assert_eq!((r.bit_position(), r.approximate_bits_remaining()), (32, 16));
+            assert_eq!(r.read_remaining_payload("rest").unwrap(), vec![5, 6]);
+            assert_eq!((r.bit_position(), r.approximate_bits_remaining()), (48, 0));
+            assert_eq!(r.read_remaining_payload("rest").unwrap(), vec![]);
+            assert!(matches!(r.read_bytes_aligned(&mut buf, "buf"), Err(RbspBitReaderError::UnexpectedEndFor("buf"))));
+        }
+        check(RbspBitReader::new(&data[..]));
+        check(BitReaderSlice::new(&data[..]));
+    }
+
+    #[test]
+    fn ue_extremes() {
+        fn check<R: BitRead>(mut max: R, mut too_large: R) {
+            assert_eq!(max.read_ue_named("max").unwrap(), u32::MAX - 1);
+            assert!(matches!(too_large.read_ue_named("too_large"), Err(RbspBitReaderError::ExpGolombTooLarge("too_large"))));
+        }
+        let max = hex!("00 00 00 01 FF FF FF FE");
+        let too_large = hex!("00 00 00 00 80 00 00 00 00");
+        check(RbspBitReader::new(&max[..]), RbspBitReader::new(&too_large[..]));
+        check(BitReaderSlice::new(&max[..]), BitReaderSlice::new(&too_large[..]));
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        for rbsp in &[
+            &hex!("")[..],
+            &hex!("4a 00 00 00 02 00 00 00 79 1e 2c 5c 90")[..],
+            &hex!("00 00 00 00 00 00")[..],
+            &hex!("00 00 01 00 00 02 00 00 03 00 00 04")[..],
+            &hex!("80 00 00")[..],
+        ] {
+            for i in 0..=rbsp.len() {
+                let state = Rc::new(RefCell::new(State {
+                    started: false,
+                    ended: false,
+                    data: Vec::new(),
+                    limit: None,
+                }));
+                let mut e = RbspEncoder::new(MockReader::new(Rc::clone(&state)));
+                let mut ctx = Context::default();
+                let (head, tail) = rbsp.split_at(i);
+                e.start(&mut ctx, NalHeader::new(0x01).unwrap());
+                e.push(&mut ctx, head);
+                e.push(&mut ctx, tail);
+                e.end(&mut ctx);
+                let s = state.borrow();
+                assert!(s.started && s.ended);
+                assert_eq!(&decode_nal(&s.data[..])[..], *rbsp, "{:02x?} split_at({})", rbsp, i);
+                // the output must never contain a forbidden three-byte sequence
+                assert!(!s.data.windows(3).any(|w| w[0] == 0 && w[1] == 0 && w[2] <= 2), "{:02x?}", s.data);
+                assert_eq!(&encode_nal(rbsp)[..], &s.data[..]);
+            }
+        }
+
+        let state = Rc::new(RefCell::new(State {
+            started: false,
+            ended: false,
+            data: Vec::new(),
+            limit: None,
+        }));
+        let mut e = RbspEncoder::new(MockReader::new(Rc::clone(&state)));
+        let mut ctx = Context::default();
+        e.start(&mut ctx, NalHeader::new(0x01).unwrap());
+        e.push(&mut ctx, &hex!("4a 00 00 00 02 00 00 00 79 1e 2c 5c 90")[..]);
+        e.end(&mut ctx);
+        assert_eq!(&state.borrow().data[..], &hex!("4a 00 00 03 00 02 00 00 03 00 79 1e 2c 5c 90")[..]);
+        assert!(matches!(encode_nal(&hex!("4a 00 00 04 00")[..]), Cow::Owned(_)));
+        assert!(matches!(encode_nal(&hex!("4a 00 00 04 80")[..]), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn slice_trailing_bits() {
+        fn check(data: &[u8], skip: u32) -> Result<u32, RbspBitReaderError> {
+            let mut r = BitReaderSlice::new(data);
+            r.read_u32(skip as u8).unwrap();
+            let slice = r.read_rbsp_slice_trailing_bits();
+            let mut r = RbspBitReader::new(data);
+            r.read_u32(skip as u8).unwrap();
+            let rbsp = BitRead::read_rbsp_slice_trailing_bits(&mut r);
+            assert_eq!(format!("{:?}", slice), format!("{:?}", rbsp));
+            slice
+        }
+        assert_eq!(check(&hex!("a8")[..], 4).unwrap(), 0);
+        assert_eq!(check(&hex!("a8 00 00 00 00")[..], 4).unwrap(), 2);
+        assert!(matches!(check(&hex!("a0")[..], 4), Err(RbspBitReaderError::InvalidTrailingBits("rbsp_stop_one_bit"))));
+        assert!(matches!(check(&hex!("a9")[..], 4), Err(RbspBitReaderError::InvalidTrailingBits("rbsp_alignment_zero_bit"))));
+        assert!(matches!(check(&hex!("a8 00 00 00 10")[..], 4), Err(RbspBitReaderError::NonZeroCabacZeroWord { byte_offset: 3, value: 0x0010 })));
+        assert!(matches!(check(&hex!("a8 00")[..], 4), Err(RbspBitReaderError::UnexpectedEndFor("cabac_zero_word"))));
+    }
+
+    #[test]
+    fn chunked_bit_reader() {
+        let data = hex!("67 64 00 00 03 01 ac 00 00 03 00 72 80");
+        let decoded = decode_nal(&data[..]);
+        for i in 0..data.len() {
+            for j in i..data.len() {
+                let tail = [&data[i..j], &data[j..]];
+                let mut c = ChunkedBitReader::new(&data[..i], &tail[..]);
+                let mut s = BitReaderSlice::new(&decoded[..]);
+                assert_eq!(c.approximate_bits_remaining(), s.approximate_bits_remaining());
+                assert_eq!(c.read_u8(3).unwrap(), s.read_u8(3).unwrap());
+                assert_eq!(c.read_ue_named("ue").unwrap(), s.read_ue_named("ue").unwrap());
+                assert_eq!(c.read_u32(20).unwrap(), s.read_u32(20).unwrap());
+                assert_eq!(c.bit_position(), s.bit_position());
+                assert_eq!(c.has_more_rbsp_data("more").unwrap(), s.has_more_rbsp_data("more").unwrap());
+                c.read_u8(4).unwrap();
+                assert_eq!(c.bit_position(), 32, "i={} j={}", i, j);
+                // a failed read leaves the position unchanged.
+                let mut d = c.clone();
+                assert_eq!(d.read_u32(32).unwrap(), u32::from_be_bytes([decoded[4], decoded[5], decoded[6], decoded[7]]));
+                assert!(matches!(d.read_u32(25), Err(RbspBitReaderError::UnexpectedEnd)));
+                assert_eq!(d.bit_position(), 64);
+                assert_eq!(d.read_u32(24).unwrap(), u32::from_be_bytes([0, decoded[8], decoded[9], decoded[10]]));
+                assert_eq!(c.read_remaining_payload("rest").unwrap(), &decoded[4..]);
+                assert!(!c.has_more_rbsp_data("more").unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn bit_writer() {
+        let mut w = BitWriterVec::new();
+        w.write_bool_named("a", true).unwrap();
+        w.write_u_named("b", 4, 0b0101).unwrap();
+        assert_eq!(
+            w.write_u_named("c", 2, 4),
+            Err(BitWriterError::ValueTooLarge { name: "c", value: 4, bit_count: 2 })
+        );
+        for &v in &[0, 1, 2, 7, 255, 65535, u32::MAX - 1] {
+            w.write_ue_named("ue", v).unwrap();
+        }
+        for &v in &[0, 1, -1, 127, -128, i32::MAX, i32::MIN + 1] {
+            w.write_se_named("se", v).unwrap();
+        }
+        // these would need codeNums of 2^32 - 1 and 2^32, which readers reject.
+        let position = w.bit_position();
+        assert_eq!(
+            w.write_ue_named("ue", u32::MAX),
+            Err(BitWriterError::ValueOutOfRange { name: "ue", value: i64::from(u32::MAX) })
+        );
+        assert_eq!(
+            w.write_se_named("se", i32::MIN),
+            Err(BitWriterError::ValueOutOfRange { name: "se", value: i64::from(i32::MIN) })
+        );
+        assert_eq!(w.bit_position(), position);
+        w.write_u_named("d", 32, 0xdead_beef).unwrap();
+        assert!(!w.is_byte_aligned());
+        w.write_rbsp_trailing_bits().unwrap();
+        assert!(w.is_byte_aligned());
+        let data = w.into_inner();
+
+        let mut r = BitReaderSlice::new(&data[..]);
+        assert!(r.read_bool().unwrap());
+        assert_eq!(r.read_u8(4).unwrap(), 0b0101);
+        for &v in &[0, 1, 2, 7, 255, 65535, u32::MAX - 1] {
+            assert_eq!(r.read_ue_named("ue").unwrap(), v);
+        }
+        for &v in &[0, 1, -1, 127, -128, i32::MAX, i32::MIN + 1] {
+            assert_eq!(r.read_se_named("se").unwrap(), v);
+        }
+        assert_eq!(r.read_u32(32).unwrap(), 0xdead_beef);
+        assert_eq!(r.read_rbsp_slice_trailing_bits().unwrap(), 0);
+    }
 }
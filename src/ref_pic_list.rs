@@ -0,0 +1,436 @@
+//! Construction of the reference picture lists of a P, SP or B slice (_8.2.4_): the initial
+//! ordering of the reference pictures in the decoded picture buffer, followed by the slice's
+//! `ref_pic_list_modification()` operations.
+//!
+//! The decoded picture buffer is described by a slice of [RefFrame]s, and list entries refer to
+//! frames by their index within it.  Both fields of a frame are assumed to share its marking as
+//! short-term or long-term.
+
+use crate::nal::pps::PicParameterSet;
+use crate::nal::slice::{ModificationOfPicNums, PictureStructure, SliceFamily, SliceHeader};
+use crate::nal::sps::SeqParameterSet;
+use crate::poc::PicOrderCnt;
+use std::cmp::Reverse;
+use std::iter;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefPicListError {
+    /// The slice header has no `ref_pic_list_modification()`, as for NAL unit types 20 and 21.
+    MissingModifications,
+    /// A modification of the given list (`0` or `1`) named a short-term picture number,
+    /// `picNumLX`, which no reference picture has.
+    NoShortTermPicture { list: u8, pic_num: i64 },
+    /// A modification of the given list named a `long_term_pic_num` which no reference picture
+    /// has.
+    NoLongTermPicture { list: u8, long_term_pic_num: u32 },
+}
+
+/// A frame, complementary field pair or unpaired field in the decoded picture buffer which is
+/// marked as used for reference.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefFrame {
+    pub frame_num: u16,
+    /// `LongTermFrameIdx` if marked as used for long-term reference; otherwise the frame is a
+    /// short-term reference.
+    pub long_term_frame_idx: Option<u32>,
+    /// The picture order count of the top field, if it is marked as used for reference.
+    pub top: Option<i32>,
+    /// The picture order count of the bottom field, if it is marked as used for reference.
+    pub bottom: Option<i32>,
+}
+impl RefFrame {
+    fn field(&self, parity: PictureStructure) -> Option<i32> {
+        match parity {
+            PictureStructure::TopField => self.top,
+            PictureStructure::BottomField => self.bottom,
+            PictureStructure::Frame => unreachable!("not a field"),
+        }
+    }
+
+    /// _PicOrderCnt( f )_, considering only the fields marked as used for reference.
+    fn pic_order_cnt(&self) -> i32 {
+        match (self.top, self.bottom) {
+            (Some(top), Some(bottom)) => top.min(bottom),
+            (top, bottom) => top.or(bottom).expect("reference frame has a reference field"),
+        }
+    }
+}
+
+/// An entry of a reference picture list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RefPic {
+    /// The index of the [RefFrame] within the decoded picture buffer given to [ref_pic_lists].
+    pub frame: usize,
+    /// `Frame` when the current picture is a frame; otherwise which field of the frame.
+    pub structure: PictureStructure,
+    pub long_term: bool,
+}
+
+/// _RefPicList0_ and _RefPicList1_, each with `num_ref_idx_lX_active_minus1 + 1` entries, of
+/// which those without a reference picture are `None`.  Both are empty for I and SI slices,
+/// and `l1` is empty for P and SP slices.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefPicLists {
+    pub l0: Vec<Option<RefPic>>,
+    pub l1: Vec<Option<RefPic>>,
+}
+
+/// Derives the reference picture lists of a slice with the given header, belonging to a picture
+/// with the given picture order count, from the reference pictures of the decoded picture
+/// buffer.
+///
+/// `dpb` should include all frames marked as used for reference when the slice is decoded,
+/// including the first field of the current frame when the slice belongs to its second field.
+pub fn ref_pic_lists(
+    sps: &SeqParameterSet,
+    pps: &PicParameterSet,
+    header: &SliceHeader,
+    pic_order_cnt: &PicOrderCnt,
+    dpb: &[RefFrame],
+) -> Result<RefPicLists, RefPicListError> {
+    let family = header.slice_type.family;
+    if family == SliceFamily::I || family == SliceFamily::SI {
+        return Ok(RefPicLists { l0: vec![], l1: vec![] });
+    }
+    let builder = Builder::new(sps, header, dpb);
+    let (init0, init1) = builder.initial_lists(family, pic_order_cnt.pic_order_cnt());
+    let modifications = header.ref_pic_list_modification.as_ref().ok_or(RefPicListError::MissingModifications)?;
+    let (num_ref_idx_l0_active_minus1, num_ref_idx_l1_active_minus1) = header.num_ref_idx_active_minus1(pps);
+    let l0 = builder.modify(init0, modifications.l0(), 0, num_ref_idx_l0_active_minus1)?;
+    let l1 = if family == SliceFamily::B {
+        builder.modify(init1, modifications.l1(), 1, num_ref_idx_l1_active_minus1)?
+    } else {
+        vec![]
+    };
+    Ok(RefPicLists { l0, l1 })
+}
+
+/// The state shared by the steps of list construction for one slice.
+struct Builder<'a> {
+    dpb: &'a [RefFrame],
+    structure: PictureStructure,
+    frame_num: u16,
+    max_frame_num: i64,
+}
+impl<'a> Builder<'a> {
+    fn new(sps: &SeqParameterSet, header: &SliceHeader, dpb: &'a [RefFrame]) -> Self {
+        Builder {
+            dpb,
+            structure: header.picture_structure(),
+            frame_num: header.frame_num,
+            // as in Dpb::push, only a constructed SPS lacks MaxFrameNum.
+            max_frame_num: sps.max_frame_num().map_or(1 << 16, i64::from),
+        }
+    }
+
+    fn is_field(&self) -> bool {
+        self.structure != PictureStructure::Frame
+    }
+
+    /// _FrameNumWrap_.
+    fn frame_num_wrap(&self, frame: &RefFrame) -> i64 {
+        if frame.frame_num > self.frame_num {
+            i64::from(frame.frame_num) - self.max_frame_num
+        } else {
+            i64::from(frame.frame_num)
+        }
+    }
+
+    /// _PicNum_ of a short-term entry, or _LongTermPicNum_ of a long-term one (_8.2.4.1_).
+    fn pic_num(&self, pic: &RefPic) -> i64 {
+        let frame = &self.dpb[pic.frame];
+        let num = match frame.long_term_frame_idx {
+            Some(long_term_frame_idx) => i64::from(long_term_frame_idx),
+            None => self.frame_num_wrap(frame),
+        };
+        if !self.is_field() {
+            num
+        } else if pic.structure == self.structure {
+            2 * num + 1
+        } else {
+            2 * num
+        }
+    }
+
+    /// The indices of the frames which may be referenced: for frames, those with both fields
+    /// marked, and for fields, those with either.
+    fn frames(&self, long_term: bool) -> Vec<usize> {
+        (0..self.dpb.len())
+            .filter(|&i| {
+                let f = &self.dpb[i];
+                let marked = if self.is_field() {
+                    f.top.is_some() || f.bottom.is_some()
+                } else {
+                    f.top.is_some() && f.bottom.is_some()
+                };
+                marked && f.long_term_frame_idx.is_some() == long_term
+            })
+            .collect()
+    }
+
+    /// The initial lists (_8.2.4.2_), before truncation to the number of active entries.
+    fn initial_lists(&self, family: SliceFamily, pic_order_cnt: i32) -> (Vec<RefPic>, Vec<RefPic>) {
+        let mut long_term = self.frames(true);
+        long_term.sort_by_key(|&i| self.dpb[i].long_term_frame_idx);
+        let long_term = self.entries(&long_term, true);
+        let mut short_term = self.frames(false);
+        if family != SliceFamily::B {
+            short_term.sort_by_key(|&i| Reverse(self.frame_num_wrap(&self.dpb[i])));
+            let mut l0 = self.entries(&short_term, false);
+            l0.extend(long_term);
+            return (l0, vec![]);
+        }
+        let (mut before, mut after): (Vec<usize>, Vec<usize>) = short_term
+            .into_iter()
+            .partition(|&i| self.dpb[i].pic_order_cnt() <= pic_order_cnt);
+        before.sort_by_key(|&i| Reverse(self.dpb[i].pic_order_cnt()));
+        after.sort_by_key(|&i| self.dpb[i].pic_order_cnt());
+        let short_term0: Vec<usize> = before.iter().chain(after.iter()).copied().collect();
+        let short_term1: Vec<usize> = after.iter().chain(before.iter()).copied().collect();
+        let mut l0 = self.entries(&short_term0, false);
+        l0.extend(long_term.iter().copied());
+        let mut l1 = self.entries(&short_term1, false);
+        l1.extend(long_term);
+        if l1.len() > 1 && l1 == l0 {
+            l1.swap(0, 1);
+        }
+        (l0, l1)
+    }
+
+    /// The entries for the given ordered frames: the frames themselves, or for fields, their
+    /// fields in alternating parity, starting with that of the current field (_8.2.4.2.5_).
+    fn entries(&self, frames: &[usize], long_term: bool) -> Vec<RefPic> {
+        let fields = |structure| {
+            frames
+                .iter()
+                .filter(move |&&i| structure == PictureStructure::Frame || self.dpb[i].field(structure).is_some())
+                .map(move |&frame| RefPic { frame, structure, long_term })
+        };
+        let opposite = match self.structure {
+            PictureStructure::Frame => return fields(PictureStructure::Frame).collect(),
+            PictureStructure::TopField => PictureStructure::BottomField,
+            PictureStructure::BottomField => PictureStructure::TopField,
+        };
+        let mut same = fields(self.structure);
+        let mut other = fields(opposite);
+        let mut entries = Vec::new();
+        loop {
+            match same.next() {
+                Some(e) => entries.push(e),
+                None => {
+                    entries.extend(other);
+                    return entries;
+                },
+            }
+            match other.next() {
+                Some(e) => entries.push(e),
+                None => {
+                    entries.extend(same);
+                    return entries;
+                },
+            }
+        }
+    }
+
+    /// Truncates or pads the initial list to its active entries, then applies the
+    /// modifications (_8.2.4.3_).
+    fn modify(
+        &self,
+        initial: Vec<RefPic>,
+        ops: &[ModificationOfPicNums],
+        list: u8,
+        num_ref_idx_active_minus1: u32,
+    ) -> Result<Vec<Option<RefPic>>, RefPicListError> {
+        let len = num_ref_idx_active_minus1 as usize + 1;
+        let mut entries: Vec<Option<RefPic>> = initial.into_iter().map(Some).chain(iter::repeat(None)).take(len).collect();
+        let (max_pic_num, curr_pic_num) = if self.is_field() {
+            (2 * self.max_frame_num, 2 * i64::from(self.frame_num) + 1)
+        } else {
+            (self.max_frame_num, i64::from(self.frame_num))
+        };
+        let mut pic_num_pred = curr_pic_num;
+        for (ref_idx, op) in ops.iter().enumerate() {
+            let pic = match *op {
+                ModificationOfPicNums::Subtract(abs_diff_pic_num_minus1) | ModificationOfPicNums::Add(abs_diff_pic_num_minus1) => {
+                    let abs_diff_pic_num = i64::from(abs_diff_pic_num_minus1) + 1;
+                    let mut pic_num_no_wrap = if let ModificationOfPicNums::Subtract(_) = op {
+                        pic_num_pred - abs_diff_pic_num
+                    } else {
+                        pic_num_pred + abs_diff_pic_num
+                    };
+                    if pic_num_no_wrap < 0 {
+                        pic_num_no_wrap += max_pic_num;
+                    } else if pic_num_no_wrap >= max_pic_num {
+                        pic_num_no_wrap -= max_pic_num;
+                    }
+                    pic_num_pred = pic_num_no_wrap;
+                    let pic_num = if pic_num_no_wrap > curr_pic_num {
+                        pic_num_no_wrap - max_pic_num
+                    } else {
+                        pic_num_no_wrap
+                    };
+                    self.find(false, pic_num).ok_or(RefPicListError::NoShortTermPicture { list, pic_num })?
+                },
+                ModificationOfPicNums::LongTermRef(long_term_pic_num) => self
+                    .find(true, i64::from(long_term_pic_num))
+                    .ok_or(RefPicListError::NoLongTermPicture { list, long_term_pic_num })?,
+            };
+            // insert the picture, removing its later entry (if any) so it appears only once.
+            entries.insert(ref_idx, Some(pic));
+            if let Some(i) = entries[ref_idx + 1..].iter().position(|&e| e == Some(pic)) {
+                entries.remove(ref_idx + 1 + i);
+            }
+            entries.truncate(len);
+        }
+        Ok(entries)
+    }
+
+    /// Finds the short-term picture with the given _PicNum_, or the long-term picture with the
+    /// given _LongTermPicNum_.
+    fn find(&self, long_term: bool, num: i64) -> Option<RefPic> {
+        let frames = self.frames(long_term);
+        let pics: Vec<RefPic> = if self.is_field() {
+            [PictureStructure::TopField, PictureStructure::BottomField]
+                .iter()
+                .flat_map(|&structure| {
+                    frames
+                        .iter()
+                        .filter(move |&&i| self.dpb[i].field(structure).is_some())
+                        .map(move |&frame| RefPic { frame, structure, long_term })
+                })
+                .collect()
+        } else {
+            self.entries(&frames, long_term)
+        };
+        pics.into_iter().find(|p| self.pic_num(p) == num)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nal::pps::PpsBuilder;
+    use crate::nal::slice::{DecRefPicMarking, NumRefIdxActive, PictureCoding, RefPicListModifications};
+    use crate::nal::sps::{Level, Profile, SpsBuilder};
+
+    fn frame(frame_num: u16, long_term_frame_idx: Option<u32>, top: Option<i32>, bottom: Option<i32>) -> RefFrame {
+        RefFrame { frame_num, long_term_frame_idx, top, bottom }
+    }
+
+    /// A slice header of the given type with `num_active` entries in each list.
+    fn header(slice_type: u32, frame_num: u16, picture_coding: PictureCoding, num_active: u32, l0: Vec<ModificationOfPicNums>) -> SliceHeader {
+        let header = SliceHeader::for_test(slice_type, frame_num, picture_coding);
+        let b = header.slice_type.family == SliceFamily::B;
+        SliceHeader {
+            num_ref_idx_active: Some(if b {
+                NumRefIdxActive::B {
+                    num_ref_idx_l0_active_minus1: num_active - 1,
+                    num_ref_idx_l1_active_minus1: num_active - 1,
+                }
+            } else {
+                NumRefIdxActive::P { num_ref_idx_l0_active_minus1: num_active - 1 }
+            }),
+            ref_pic_list_modification: Some(if b {
+                RefPicListModifications::B { ref_pic_list_modification_l0: l0, ref_pic_list_modification_l1: vec![] }
+            } else {
+                RefPicListModifications::P { ref_pic_list_modification_l0: l0 }
+            }),
+            dec_ref_pic_marking: Some(DecRefPicMarking::SlidingWindow),
+            ..header
+        }
+    }
+
+    /// The lists as `(frame, structure)` pairs, with `None` entries omitted.
+    fn summary(list: &[Option<RefPic>]) -> Vec<(usize, PictureStructure)> {
+        list.iter().flatten().map(|p| (p.frame, p.structure)).collect()
+    }
+
+    #[test]
+    fn frames() {
+        // max_frame_num of 16.
+        let sps = SpsBuilder::new(Profile::Main, Level::L3).dimensions(640, 480).build().unwrap();
+        let pps = PpsBuilder::for_sps(&sps).build().unwrap();
+        assert_eq!(sps.max_frame_num(), Some(16));
+        let poc = |p| PicOrderCnt::frame(p, p);
+        use PictureStructure::Frame;
+
+        // P: short-term frames by descending PicNum (with frame_num 15 wrapping to -1), then
+        // long-term ones by LongTermPicNum.  Frame 4 has only one reference field.
+        let dpb = [
+            frame(15, None, Some(0), Some(0)),
+            frame(0, None, Some(2), Some(2)),
+            frame(3, Some(1), Some(4), Some(4)),
+            frame(9, Some(0), Some(6), Some(6)),
+            frame(14, None, Some(8), None),
+        ];
+        let lists = ref_pic_lists(&sps, &pps, &header(0, 1, PictureCoding::Frame, 5, vec![]), &poc(10), &dpb).unwrap();
+        assert_eq!(summary(&lists.l0), vec![(1, Frame), (0, Frame), (3, Frame), (2, Frame)]);
+        assert_eq!(lists.l0.len(), 5);
+        assert!(lists.l0[4].is_none() && lists.l1.is_empty());
+        assert!(lists.l0[2].unwrap().long_term);
+
+        // moving frame_num 15 (PicNum -1 = 1 - 2) to the front, then long-term 1 second.
+        let ops = vec![ModificationOfPicNums::Subtract(1), ModificationOfPicNums::LongTermRef(1)];
+        let lists = ref_pic_lists(&sps, &pps, &header(0, 1, PictureCoding::Frame, 3, ops), &poc(10), &dpb).unwrap();
+        assert_eq!(summary(&lists.l0), vec![(0, Frame), (2, Frame), (1, Frame)]);
+        let ops = vec![ModificationOfPicNums::Add(0)];
+        assert_eq!(
+            ref_pic_lists(&sps, &pps, &header(0, 1, PictureCoding::Frame, 3, ops), &poc(10), &dpb),
+            Err(RefPicListError::NoShortTermPicture { list: 0, pic_num: -14 })
+        );
+
+        // B: by picture order count before and after the current picture's.
+        let dpb = [
+            frame(0, None, Some(0), Some(1)),
+            frame(1, None, Some(12), Some(13)),
+            frame(2, None, Some(4), Some(5)),
+            frame(3, Some(0), Some(16), Some(17)),
+        ];
+        let lists = ref_pic_lists(&sps, &pps, &header(1, 4, PictureCoding::Frame, 4, vec![]), &poc(8), &dpb).unwrap();
+        assert_eq!(summary(&lists.l0), vec![(2, Frame), (0, Frame), (1, Frame), (3, Frame)]);
+        assert_eq!(summary(&lists.l1), vec![(1, Frame), (2, Frame), (0, Frame), (3, Frame)]);
+
+        // identical lists have the first two entries of list 1 swapped.
+        let lists = ref_pic_lists(&sps, &pps, &header(1, 4, PictureCoding::Frame, 2, vec![]), &poc(20), &dpb[..3]).unwrap();
+        assert_eq!(summary(&lists.l0), vec![(1, Frame), (2, Frame)]);
+        assert_eq!(summary(&lists.l1), vec![(2, Frame), (1, Frame)]);
+
+        let i = ref_pic_lists(&sps, &pps, &header(2, 4, PictureCoding::Frame, 1, vec![]), &poc(8), &dpb).unwrap();
+        assert!(i.l0.is_empty() && i.l1.is_empty());
+    }
+
+    #[test]
+    fn fields() {
+        let sps = SpsBuilder::new(Profile::Main, Level::L3).dimensions(640, 480).build().unwrap();
+        let pps = PpsBuilder::for_sps(&sps).build().unwrap();
+        use PictureStructure::{BottomField as B, TopField as T};
+
+        // P: the bottom field of frame_num 2, whose top field is already decoded.  Fields
+        // alternate in parity, starting with the bottom, taking frames by descending
+        // FrameNumWrap.
+        let dpb = [
+            frame(0, None, Some(0), Some(1)),
+            frame(1, None, Some(4), Some(5)),
+            frame(2, None, Some(8), None),
+        ];
+        let poc = PicOrderCnt::bottom_field(9);
+        let lists = ref_pic_lists(&sps, &pps, &header(0, 2, PictureCoding::BottomField, 6, vec![]), &poc, &dpb).unwrap();
+        assert_eq!(summary(&lists.l0), vec![(1, B), (2, T), (0, B), (1, T), (0, T)]);
+
+        // CurrPicNum is 5; PicNum 4 is the opposite-parity field of frame_num 2.
+        let ops = vec![ModificationOfPicNums::Subtract(0)];
+        let lists = ref_pic_lists(&sps, &pps, &header(0, 2, PictureCoding::BottomField, 2, ops), &poc, &dpb).unwrap();
+        assert_eq!(summary(&lists.l0), vec![(2, T), (1, B)]);
+
+        // B: the top field of frame_num 3, between frames 0 and 1 in output order.
+        let dpb = [
+            frame(0, None, Some(0), Some(1)),
+            frame(1, None, Some(8), Some(9)),
+            frame(2, Some(0), Some(-4), Some(-3)),
+        ];
+        let poc = PicOrderCnt::top_field(4);
+        let lists = ref_pic_lists(&sps, &pps, &header(1, 3, PictureCoding::TopField, 6, vec![]), &poc, &dpb).unwrap();
+        assert_eq!(summary(&lists.l0), vec![(0, T), (0, B), (1, T), (1, B), (2, T), (2, B)]);
+        assert_eq!(summary(&lists.l1), vec![(1, T), (1, B), (0, T), (0, B), (2, T), (2, B)]);
+    }
+}
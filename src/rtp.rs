@@ -0,0 +1,496 @@
+//! Carriage of NAL units in the payloads of RTP packets, as specified by _RFC 6184_.
+//!
+//! Requires the `rtp` feature.  The [Depacketizer] passes each reassembled NAL unit to the same
+//! [NalReader] used with an [AnnexBReader](../annexb/struct.AnnexBReader.html), such as a
+//! [NalSwitch](../nal/struct.NalSwitch.html), and the [Packetizer] does the reverse.
+
+use crate::annexb::NalReader;
+use crate::Context;
+
+/// `nal_unit_type` values which RFC 6184 uses for its packet types, rather than for NAL units.
+const STAP_A: u8 = 24;
+const STAP_B: u8 = 25;
+const MTAP16: u8 = 26;
+const MTAP24: u8 = 27;
+const FU_A: u8 = 28;
+const FU_B: u8 = 29;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RtpError {
+    /// The RTP packet had no payload.
+    EmptyPayload,
+    /// The payload's type (`0`, `30` or `31`) is not defined by RFC 6184.
+    UnsupportedType(u8),
+    /// The packet's sequence number was earlier than that of a packet already received; the
+    /// packet was ignored.
+    OutOfOrder { expected: u16, actual: u16 },
+    /// An aggregation packet (STAP or MTAP) was too short for the header of its first unit, or
+    /// ended part way through the unit at the given offset.
+    TruncatedAggregate { offset: usize },
+    /// The aggregation unit at the given offset held a NAL unit of zero length.
+    EmptyAggregatedNal { offset: usize },
+    /// A fragmentation unit packet was too short to hold its headers and some data.
+    TruncatedFragment,
+    /// A fragmentation unit had both its start and end bits set.
+    FragmentStartAndEnd,
+    /// An FU-B packet (which may only carry the first fragment of a NAL unit) lacked the start
+    /// bit.
+    FuBWithoutStart,
+    /// A fragmentation unit continued a NAL unit whose first fragment was not received (typically
+    /// following packet loss); it was ignored.
+    FragmentWithoutStart,
+    /// A fragmented NAL unit was still incomplete when a packet other than a fragment of it
+    /// arrived.  The partial NAL unit was dropped (see [NalReader::reset]), and the new packet
+    /// was processed as usual.
+    IncompleteFragmentedNal,
+    /// A [Packetizer] was given a maximum payload size too small to hold a fragmentation unit.
+    InvalidMaxPayloadSize(usize),
+    /// A [Packetizer] was given a NAL unit of zero length.
+    EmptyNal,
+    /// In [PacketizationMode::SingleNal], a NAL unit was larger than the maximum payload size.
+    NalTooLarge { len: usize, max_payload_size: usize },
+}
+
+/// Told the number of packets found to be missing from the sequence; see
+/// [Depacketizer::set_loss_handler].
+pub type LossHandler<Ctx> = Box<dyn FnMut(&mut Context<Ctx>, u16)>;
+
+/// Reassembles NAL units from the payloads of the RTP packets of an H.264 stream, supporting the
+/// single NAL unit, STAP-A/B and MTAP16/24 aggregation, and FU-A/B fragmentation packet types.
+///
+/// Packets must be given in sequence number order (reordering is the job of a jitter buffer).
+/// NAL units are passed on in transmission order; for the interleaved packetization mode, any
+/// reordering by decoding order number is left to the `NalReader`.
+pub struct Depacketizer<R, Ctx>
+    where
+        R: NalReader<Ctx=Ctx>
+{
+    nal_reader: R,
+    loss_handler: Option<LossHandler<Ctx>>,
+    next_sequence_number: Option<u16>,
+    /// header byte of the fragmented NAL unit currently being reassembled
+    fragment_header: Option<u8>,
+}
+impl<R, Ctx> Depacketizer<R, Ctx>
+    where
+        R: NalReader<Ctx=Ctx>
+{
+    pub fn new(nal_reader: R) -> Self {
+        Depacketizer {
+            nal_reader,
+            loss_handler: None,
+            next_sequence_number: None,
+            fragment_header: None,
+        }
+    }
+
+    /// Sets a handler to be told whenever a gap in the packets' sequence numbers shows that
+    /// packets have been lost.
+    ///
+    /// Whether or not a handler is set, a fragmented NAL unit which was being reassembled when
+    /// the loss was detected is dropped (see [NalReader::reset]), along with any later fragments
+    /// of it.
+    pub fn set_loss_handler(&mut self, handler: Option<LossHandler<Ctx>>) {
+        self.loss_handler = handler;
+    }
+
+    /// Forgets the sequence number of the last packet, and drops any partially reassembled NAL
+    /// unit, as when the stream is restarted.
+    pub fn reset(&mut self, ctx: &mut Context<Ctx>) {
+        self.next_sequence_number = None;
+        self.drop_fragment(ctx);
+    }
+
+    /// Processes the payload of the RTP packet with the given sequence number, passing each NAL
+    /// unit completed by it to the `NalReader`.
+    ///
+    /// On error, any NAL units preceding the problem will already have been passed on, and the
+    /// rest of the packet is ignored.  Once an error handler has aborted parsing (see
+    /// [Context::report_error](../struct.Context.html#method.report_error)), the remaining NAL
+    /// units are ignored.
+    pub fn push(&mut self, ctx: &mut Context<Ctx>, sequence_number: u16, payload: &[u8]) -> Result<(), RtpError> {
+        if let Some(expected) = self.next_sequence_number {
+            let gap = sequence_number.wrapping_sub(expected);
+            if gap >= 0x8000 {
+                return Err(RtpError::OutOfOrder { expected, actual: sequence_number });
+            }
+            if gap > 0 {
+                self.drop_fragment(ctx);
+                if let Some(ref mut handler) = self.loss_handler {
+                    handler(ctx, gap);
+                }
+            }
+        }
+        self.next_sequence_number = Some(sequence_number.wrapping_add(1));
+
+        let indicator = *payload.first().ok_or(RtpError::EmptyPayload)?;
+        let payload_type = indicator & 0x1f;
+        let interrupted = match payload_type {
+            FU_A | FU_B => false,
+            _ => self.drop_fragment(ctx),
+        };
+        match payload_type {
+            1..=23 => self.single(ctx, payload),
+            STAP_A => self.aggregate(ctx, payload, 1, 0)?,
+            STAP_B => self.aggregate(ctx, payload, 3, 0)?,
+            MTAP16 => self.aggregate(ctx, payload, 3, 3)?,
+            MTAP24 => self.aggregate(ctx, payload, 3, 4)?,
+            FU_A | FU_B => self.fragment(ctx, payload)?,
+            _ => return Err(RtpError::UnsupportedType(payload_type)),
+        }
+        if interrupted {
+            Err(RtpError::IncompleteFragmentedNal)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Drops the partially reassembled NAL unit, if any, returning `true` if there was one.
+    fn drop_fragment(&mut self, ctx: &mut Context<Ctx>) -> bool {
+        if self.fragment_header.take().is_some() {
+            self.nal_reader.reset(ctx);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn single(&mut self, ctx: &mut Context<Ctx>, nal: &[u8]) {
+        if ctx.is_aborted() {
+            return;
+        }
+        self.nal_reader.start(ctx);
+        self.nal_reader.push(ctx, nal);
+        self.nal_reader.end(ctx);
+    }
+
+    /// Reads the aggregation units following the `header_len` bytes of packet header, each of
+    /// which has `extra_len` bytes (DOND and timestamp offset) between its size and its NAL unit.
+    fn aggregate(&mut self, ctx: &mut Context<Ctx>, payload: &[u8], header_len: usize, extra_len: usize) -> Result<(), RtpError> {
+        let mut offset = header_len;
+        if offset >= payload.len() {
+            return Err(RtpError::TruncatedAggregate { offset: 0 });
+        }
+        while offset < payload.len() {
+            let nal_offset = offset + 2 + extra_len;
+            if nal_offset > payload.len() {
+                return Err(RtpError::TruncatedAggregate { offset });
+            }
+            let len = usize::from(payload[offset]) << 8 | usize::from(payload[offset + 1]);
+            if len == 0 {
+                return Err(RtpError::EmptyAggregatedNal { offset });
+            }
+            let nal = payload.get(nal_offset..nal_offset + len)
+                .ok_or(RtpError::TruncatedAggregate { offset })?;
+            self.single(ctx, nal);
+            offset = nal_offset + len;
+        }
+        Ok(())
+    }
+
+    fn fragment(&mut self, ctx: &mut Context<Ctx>, payload: &[u8]) -> Result<(), RtpError> {
+        let is_fu_b = payload[0] & 0x1f == FU_B;
+        let data_offset = if is_fu_b { 4 } else { 2 };
+        if payload.len() <= data_offset {
+            return Err(RtpError::TruncatedFragment);
+        }
+        let fu_header = payload[1];
+        let start = fu_header & 0x80 != 0;
+        let end = fu_header & 0x40 != 0;
+        // the NAL header is reconstructed from the F and NRI bits of the FU indicator, and the
+        // type from the FU header
+        let nal_header = payload[0] & 0xe0 | fu_header & 0x1f;
+        if start && end {
+            self.drop_fragment(ctx);
+            return Err(RtpError::FragmentStartAndEnd);
+        }
+        if is_fu_b && !start {
+            self.drop_fragment(ctx);
+            return Err(RtpError::FuBWithoutStart);
+        }
+        let data = &payload[data_offset..];
+        if start {
+            let interrupted = self.drop_fragment(ctx);
+            if !ctx.is_aborted() {
+                self.nal_reader.start(ctx);
+                self.nal_reader.push(ctx, &[nal_header]);
+                self.nal_reader.push(ctx, data);
+                self.fragment_header = Some(nal_header);
+            }
+            return if interrupted { Err(RtpError::IncompleteFragmentedNal) } else { Ok(()) };
+        }
+        if self.fragment_header != Some(nal_header) {
+            self.drop_fragment(ctx);
+            return Err(RtpError::FragmentWithoutStart);
+        }
+        self.nal_reader.push(ctx, data);
+        if end {
+            self.fragment_header = None;
+            self.nal_reader.end(ctx);
+        }
+        Ok(())
+    }
+
+    pub fn nal_reader(&mut self) -> &mut R {
+        &mut self.nal_reader
+    }
+
+    pub fn into_nal_reader(self) -> R {
+        self.nal_reader
+    }
+}
+
+/// The `packetization-mode` of an RTP session, restricting the packet types which a [Packetizer]
+/// may produce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketizationMode {
+    /// `packetization-mode=0`: each packet holds a single NAL unit, so no NAL unit may be larger
+    /// than the maximum payload size.
+    SingleNal,
+    /// `packetization-mode=1`: small NAL units are aggregated into STAP-A packets, and large ones
+    /// fragmented into FU-A packets.
+    NonInterleaved,
+}
+
+/// Splits access units into RTP payloads of at most a given size, the inverse of [Depacketizer].
+///
+/// In [PacketizationMode::NonInterleaved], consecutive NAL units which fit together are sent as a
+/// single STAP-A packet, NAL units which fit alone as single NAL unit packets, and larger NAL
+/// units as a series of FU-A packets.  Aggregation never spans access units, since all packets of
+/// an aggregate share one RTP timestamp.
+pub struct Packetizer {
+    max_payload_size: usize,
+    mode: PacketizationMode,
+}
+impl Packetizer {
+    /// Creates a packetizer for payloads of at most `max_payload_size` bytes (typically the path
+    /// MTU less the IP, UDP and RTP headers), which must be at least 3 bytes.
+    pub fn new(max_payload_size: usize, mode: PacketizationMode) -> Result<Self, RtpError> {
+        if max_payload_size < 3 {
+            return Err(RtpError::InvalidMaxPayloadSize(max_payload_size));
+        }
+        Ok(Packetizer { max_payload_size, mode })
+    }
+
+    /// Returns the payloads of the RTP packets carrying the given access unit, each given as a
+    /// complete NAL unit (header byte followed by the escaped payload), in order.  The last
+    /// payload should be sent with the RTP marker bit set.
+    pub fn packetize(&self, nals: &[&[u8]]) -> Result<Vec<Vec<u8>>, RtpError> {
+        let mut payloads = vec![];
+        // run of NAL units which may be sent together
+        let mut pending: Vec<&[u8]> = vec![];
+        let mut pending_size = 1;  // STAP-A NAL header
+        for &nal in nals {
+            if nal.is_empty() {
+                return Err(RtpError::EmptyNal);
+            }
+            if nal.len() > self.max_payload_size {
+                if self.mode == PacketizationMode::SingleNal {
+                    return Err(RtpError::NalTooLarge { len: nal.len(), max_payload_size: self.max_payload_size });
+                }
+                Self::flush(&mut payloads, &mut pending);
+                pending_size = 1;
+                self.fragment(&mut payloads, nal);
+                continue;
+            }
+            let aggregated_size = pending_size + 2 + nal.len();
+            let can_aggregate = self.mode == PacketizationMode::NonInterleaved
+                && nal.len() <= usize::from(u16::MAX)
+                && aggregated_size <= self.max_payload_size;
+            if can_aggregate {
+                pending_size = aggregated_size;
+            } else {
+                Self::flush(&mut payloads, &mut pending);
+                pending_size = 1 + 2 + nal.len();
+            }
+            pending.push(nal);
+        }
+        Self::flush(&mut payloads, &mut pending);
+        Ok(payloads)
+    }
+
+    /// Emits the pending NAL units as a single NAL unit packet, or if there are several, a STAP-A.
+    fn flush(payloads: &mut Vec<Vec<u8>>, pending: &mut Vec<&[u8]>) {
+        match pending.len() {
+            0 => {},
+            1 => payloads.push(pending[0].to_vec()),
+            _ => {
+                // forbidden_zero_bit is set if set for any aggregated NAL unit, and NRI is the
+                // maximum of theirs
+                let f = pending.iter().fold(0, |f, nal| f | nal[0] & 0x80);
+                let nri = pending.iter().map(|nal| nal[0] & 0x60).max().unwrap_or(0);
+                let mut payload = vec![f | nri | STAP_A];
+                for nal in pending.iter() {
+                    payload.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+                    payload.extend_from_slice(nal);
+                }
+                payloads.push(payload);
+            },
+        }
+        pending.clear();
+    }
+
+    fn fragment(&self, payloads: &mut Vec<Vec<u8>>, nal: &[u8]) {
+        // the FU indicator takes the F and NRI bits of the NAL header, and the FU header its type
+        let indicator = nal[0] & 0xe0 | FU_A;
+        let nal_type = nal[0] & 0x1f;
+        let chunk_size = self.max_payload_size - 2;
+        let chunks = nal[1..].chunks(chunk_size);
+        let count = chunks.len();
+        for (i, chunk) in chunks.enumerate() {
+            let mut fu_header = nal_type;
+            if i == 0 {
+                fu_header |= 0x80;
+            }
+            if i == count - 1 {
+                fu_header |= 0x40;
+            }
+            let mut payload = Vec::with_capacity(2 + chunk.len());
+            payload.push(indicator);
+            payload.push(fu_header);
+            payload.extend_from_slice(chunk);
+            payloads.push(payload);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hex_literal::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[derive(Default)]
+    struct Collector {
+        current: Vec<u8>,
+        nals: Vec<Vec<u8>>,
+        resets: usize,
+    }
+    impl NalReader for Collector {
+        type Ctx = ();
+
+        fn start(&mut self, _ctx: &mut Context<Self::Ctx>) {
+            self.current.clear();
+        }
+
+        fn push(&mut self, _ctx: &mut Context<Self::Ctx>, buf: &[u8]) {
+            self.current.extend_from_slice(buf);
+        }
+
+        fn end(&mut self, _ctx: &mut Context<Self::Ctx>) {
+            self.nals.push(std::mem::take(&mut self.current));
+        }
+
+        fn reset(&mut self, _ctx: &mut Context<Self::Ctx>) {
+            self.current.clear();
+            self.resets += 1;
+        }
+    }
+
+    #[test]
+    fn packet_types() {
+        let mut ctx = Context::default();
+        let mut d = Depacketizer::new(Collector::default());
+        // single NAL unit
+        d.push(&mut ctx, 1, &hex!("09 F0")).unwrap();
+        // STAP-A of SPS and PPS
+        d.push(&mut ctx, 2, &hex!("78 0003 67 42 C0 0002 68 CE")).unwrap();
+        // FU-A in three fragments; NRI from the indicator, type from the FU header
+        d.push(&mut ctx, 3, &hex!("7C 85 88 84")).unwrap();
+        d.push(&mut ctx, 4, &hex!("7C 05 21")).unwrap();
+        d.push(&mut ctx, 5, &hex!("7C 45 A0")).unwrap();
+        // STAP-B (with DON), MTAP16 and MTAP24
+        d.push(&mut ctx, 6, &hex!("19 0001 0002 41 9A")).unwrap();
+        d.push(&mut ctx, 7, &hex!("1A 0002 0002 01 0010 41 9B")).unwrap();
+        d.push(&mut ctx, 8, &hex!("1B 0003 0002 01 000010 41 9C")).unwrap();
+        // FU-B, whose first fragment carries a DON
+        d.push(&mut ctx, 9, &hex!("3D 81 0004 9D")).unwrap();
+        d.push(&mut ctx, 10, &hex!("3C 41 01")).unwrap();
+        assert_eq!(d.nal_reader().nals, [
+            &hex!("09 F0")[..],
+            &hex!("67 42 C0"),
+            &hex!("68 CE"),
+            &hex!("65 88 84 21 A0"),
+            &hex!("41 9A"),
+            &hex!("41 9B"),
+            &hex!("41 9C"),
+            &hex!("21 9D 01"),
+        ]);
+
+        assert_eq!(d.push(&mut ctx, 11, &[]), Err(RtpError::EmptyPayload));
+        assert_eq!(d.push(&mut ctx, 12, &hex!("1E 00")), Err(RtpError::UnsupportedType(30)));
+        assert_eq!(d.push(&mut ctx, 13, &hex!("78 0003 67 42")), Err(RtpError::TruncatedAggregate { offset: 1 }));
+        assert_eq!(d.push(&mut ctx, 14, &hex!("78 0000")), Err(RtpError::EmptyAggregatedNal { offset: 1 }));
+        assert_eq!(d.push(&mut ctx, 15, &hex!("7C C5 00")), Err(RtpError::FragmentStartAndEnd));
+        assert_eq!(d.push(&mut ctx, 16, &hex!("7D 05 0000 00")), Err(RtpError::FuBWithoutStart));
+        assert_eq!(d.push(&mut ctx, 17, &hex!("7C 85")), Err(RtpError::TruncatedFragment));
+        assert_eq!(d.push(&mut ctx, 15, &hex!("09 F0")), Err(RtpError::OutOfOrder { expected: 18, actual: 15 }));
+        assert_eq!(d.nal_reader().nals.len(), 8);
+    }
+
+    #[test]
+    fn loss() {
+        let mut ctx = Context::default();
+        let mut d = Depacketizer::new(Collector::default());
+        let lost = Rc::new(Cell::new(0));
+        let lost2 = lost.clone();
+        d.set_loss_handler(Some(Box::new(move |_ctx, count| lost2.set(lost2.get() + count))));
+
+        // sequence numbers wrap around without loss,
+        d.push(&mut ctx, 0xffff, &hex!("7C 85 88")).unwrap();
+        d.push(&mut ctx, 0, &hex!("7C 45 84")).unwrap();
+        assert_eq!(lost.get(), 0);
+
+        // a gap drops the partial NAL unit, along with its remaining fragments,
+        d.push(&mut ctx, 1, &hex!("7C 85 88")).unwrap();
+        d.push(&mut ctx, 3, &hex!("7C 05 84")).unwrap_err();
+        assert_eq!(lost.get(), 1);
+        assert_eq!(d.push(&mut ctx, 4, &hex!("7C 45 84")), Err(RtpError::FragmentWithoutStart));
+
+        // as does a packet of another type arriving before the final fragment,
+        d.push(&mut ctx, 5, &hex!("7C 85 88")).unwrap();
+        assert_eq!(d.push(&mut ctx, 6, &hex!("09 F0")), Err(RtpError::IncompleteFragmentedNal));
+
+        let c = d.into_nal_reader();
+        assert_eq!(c.nals, [&hex!("65 88 84")[..], &hex!("09 F0")]);
+        assert_eq!(c.resets, 2);
+    }
+
+    #[test]
+    fn packetize() {
+        let sps = &hex!("67 42 C0 1E")[..];
+        let pps = &hex!("68 CE 3C 80")[..];
+        let idr: Vec<u8> = std::iter::once(0x65).chain(0..20).collect();
+        let sei = &hex!("06 05 01 80")[..];
+        let au = [sps, pps, &idr[..], sei];
+
+        let p = Packetizer::new(10, PacketizationMode::NonInterleaved).unwrap();
+        let payloads = p.packetize(&au).unwrap();
+        assert_eq!(payloads, [
+            &hex!("67 42 C0 1E")[..],
+            &hex!("68 CE 3C 80"),
+            &hex!("7C 85 00 01 02 03 04 05 06 07"),
+            &hex!("7C 05 08 09 0A 0B 0C 0D 0E 0F"),
+            &hex!("7C 45 10 11 12 13"),
+            &hex!("06 05 01 80"),
+        ]);
+        let mut ctx = Context::default();
+        let mut d = Depacketizer::new(Collector::default());
+        for (seq, payload) in payloads.iter().enumerate() {
+            assert!(payload.len() <= 10);
+            d.push(&mut ctx, seq as u16, payload).unwrap();
+        }
+        assert_eq!(d.nal_reader().nals, au);
+
+        // a STAP-A takes the highest NRI of its NAL units,
+        let p = Packetizer::new(100, PacketizationMode::NonInterleaved).unwrap();
+        assert_eq!(p.packetize(&[&hex!("09 F0"), &hex!("21 9A")]).unwrap(), [hex!("38 0002 09 F0 0002 21 9A")]);
+
+        let p = Packetizer::new(10, PacketizationMode::SingleNal).unwrap();
+        assert_eq!(p.packetize(&[sps, pps]).unwrap(), [sps, pps]);
+        assert_eq!(p.packetize(&au), Err(RtpError::NalTooLarge { len: 21, max_payload_size: 10 }));
+        assert_eq!(p.packetize(&[&[]]), Err(RtpError::EmptyNal));
+        assert!(matches!(Packetizer::new(2, PacketizationMode::SingleNal), Err(RtpError::InvalidMaxPayloadSize(2))));
+    }
+}
@@ -0,0 +1,275 @@
+//! Support for parameter sets delivered out-of-band, as in the `sprop-parameter-sets` parameter
+//! of an SDP description of an RTP stream (_RFC 6184_).  See
+//! [Context::put_sprop_parameter_sets](../struct.Context.html#method.put_sprop_parameter_sets).
+//!
+//! Also helpers for the other H.264 `a=fmtp` parameters of an SDP offer or answer; see
+//! [FmtpParams].
+
+use crate::nal::{pps, sps, NalHeaderError, UnitType};
+use std::fmt;
+use std::str::FromStr;
+
+/// A problem with one of the parameter sets passed to
+/// [Context::put_sprop_parameter_sets](../struct.Context.html#method.put_sprop_parameter_sets),
+/// [Context::put_param_set_nal](../struct.Context.html#method.put_param_set_nal),
+/// [SeqParameterSet::from_nal](../nal/sps/struct.SeqParameterSet.html#method.from_nal) or
+/// [PicParameterSet::from_nal](../nal/pps/struct.PicParameterSet.html#method.from_nal).
+#[derive(Debug)]
+pub enum SpropError {
+    /// The base64 encoding was invalid at the given character offset within the set.
+    Base64 { offset: usize },
+    /// The parameter set was empty.
+    Empty,
+    NalHeader(NalHeaderError),
+    /// The NAL unit was of a type other than SPS or PPS.
+    UnexpectedUnitType(UnitType),
+    Sps(sps::SpsError),
+    Pps(pps::PpsError),
+    /// A `profile-level-id` was not exactly six hexadecimal digits.
+    InvalidProfileLevelId,
+    /// A `packetization-mode` was other than `0`, `1` or `2`.
+    InvalidPacketizationMode,
+}
+
+/// Decodes standard (_RFC 4648_) base64, with or without trailing padding.
+pub(crate) fn decode_base64(input: &str) -> Result<Vec<u8>, SpropError> {
+    let input = input.trim_end_matches('=');
+    let mut out = Vec::with_capacity(input.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for (offset, c) in input.bytes().enumerate() {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return Err(SpropError::Base64 { offset }),
+        };
+        acc = acc << 6 | u32::from(v);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    // a single leftover character can't encode a whole byte, and the unused bits must be zero
+    if bits == 6 || acc != 0 {
+        return Err(SpropError::Base64 { offset: input.len() });
+    }
+    Ok(out)
+}
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes standard (_RFC 4648_) base64, with trailing padding.
+pub(crate) fn encode_base64(data: &[u8]) -> String {
+    let mut out = String::with_capacity((data.len() / 3 + 1) * 4);
+    for chunk in data.chunks(3) {
+        let v = chunk.iter().enumerate().fold(0u32, |v, (i, &b)| v | u32::from(b) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(char::from(BASE64_ALPHABET[(v >> (18 - 6 * i) & 0x3f) as usize]));
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Builds an SDP `sprop-parameter-sets` value from the given SPS and PPS NAL units (each header
+/// byte followed by the escaped payload): their base64 encodings, separated by commas.
+pub fn sprop_parameter_sets(nals: &[&[u8]]) -> String {
+    nals.iter()
+        .map(|nal| encode_base64(nal))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// The `profile-level-id` parameter: the `profile_idc`, constraint flags and `level_idc` of an
+/// SPS, written as six hexadecimal digits (e.g. `42e01f`).
+#[derive(Debug, Clone, Copy)]
+pub struct ProfileLevelId {
+    pub profile_idc: sps::ProfileIdc,
+    pub constraint_flags: sps::ConstraintFlags,
+    pub level_idc: u8,
+}
+impl ProfileLevelId {
+    pub fn from_sps(sps: &sps::SeqParameterSet) -> ProfileLevelId {
+        ProfileLevelId {
+            profile_idc: sps.profile_idc,
+            constraint_flags: sps.constraint_flags,
+            level_idc: sps.level_idc,
+        }
+    }
+
+    pub fn profile(&self) -> sps::Profile {
+        sps::Profile::from_profile_idc_and_constraint_flags(self.profile_idc, self.constraint_flags)
+    }
+
+    /// The level, which for `level_idc` `11` also depends on the profile and
+    /// `constraint_set3_flag` (level 1b).
+    pub fn level(&self) -> sps::Level {
+        sps::Level::from_profile_idc_constraint_flags_and_level_idc(
+            self.profile_idc, self.constraint_flags, self.level_idc)
+    }
+}
+impl PartialEq for ProfileLevelId {
+    fn eq(&self, other: &Self) -> bool {
+        self.profile_idc == other.profile_idc
+            && u8::from(self.constraint_flags) == u8::from(other.constraint_flags)
+            && self.level_idc == other.level_idc
+    }
+}
+impl Eq for ProfileLevelId {}
+impl fmt::Display for ProfileLevelId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02x}{:02x}{:02x}", u8::from(self.profile_idc), u8::from(self.constraint_flags), self.level_idc)
+    }
+}
+impl FromStr for ProfileLevelId {
+    type Err = SpropError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 6 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(SpropError::InvalidProfileLevelId);
+        }
+        let byte = |i: usize| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| SpropError::InvalidProfileLevelId);
+        Ok(ProfileLevelId {
+            profile_idc: byte(0)?.into(),
+            constraint_flags: byte(2)?.into(),
+            level_idc: byte(4)?,
+        })
+    }
+}
+
+/// The H.264 parameters of an SDP `a=fmtp` attribute, as defined by _RFC 6184_ section 8.1.
+///
+/// Parameters not represented here are ignored when parsing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FmtpParams {
+    pub profile_level_id: Option<ProfileLevelId>,
+    /// `0` (single NAL unit), `1` (non-interleaved) or `2` (interleaved); absent means `0`.
+    pub packetization_mode: Option<u8>,
+    /// The base64 parameter sets, which may be installed with
+    /// [Context::put_sprop_parameter_sets](../struct.Context.html#method.put_sprop_parameter_sets).
+    pub sprop_parameter_sets: Option<String>,
+}
+impl FmtpParams {
+    /// Describes a stream with the given SPS and PPS NAL units, taking the `profile-level-id`
+    /// from the (parsed) SPS.
+    pub fn from_param_sets(sps: &sps::SeqParameterSet, nals: &[&[u8]], packetization_mode: u8) -> FmtpParams {
+        FmtpParams {
+            profile_level_id: Some(ProfileLevelId::from_sps(sps)),
+            packetization_mode: Some(packetization_mode),
+            sprop_parameter_sets: Some(sprop_parameter_sets(nals)),
+        }
+    }
+
+    /// Parses the parameters following the format of an `a=fmtp` attribute: that is, given
+    /// `a=fmtp:96 packetization-mode=1;profile-level-id=42e01f`, the part after `96 `.
+    pub fn parse(params: &str) -> Result<FmtpParams, SpropError> {
+        let mut fmtp = FmtpParams::default();
+        for param in params.split(';') {
+            let (name, value) = match param.find('=') {
+                Some(i) => (param[..i].trim(), param[i + 1..].trim()),
+                None => continue,
+            };
+            if name.eq_ignore_ascii_case("profile-level-id") {
+                fmtp.profile_level_id = Some(value.parse()?);
+            } else if name.eq_ignore_ascii_case("packetization-mode") {
+                fmtp.packetization_mode = match value {
+                    "0" => Some(0),
+                    "1" => Some(1),
+                    "2" => Some(2),
+                    _ => return Err(SpropError::InvalidPacketizationMode),
+                };
+            } else if name.eq_ignore_ascii_case("sprop-parameter-sets") {
+                fmtp.sprop_parameter_sets = Some(value.to_string());
+            }
+        }
+        Ok(fmtp)
+    }
+}
+impl fmt::Display for FmtpParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut sep = "";
+        if let Some(mode) = self.packetization_mode {
+            write!(f, "packetization-mode={}", mode)?;
+            sep = ";";
+        }
+        if let Some(ref id) = self.profile_level_id {
+            write!(f, "{}profile-level-id={}", sep, id)?;
+            sep = ";";
+        }
+        if let Some(ref sets) = self.sprop_parameter_sets {
+            write!(f, "{}sprop-parameter-sets={}", sep, sets)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Context;
+    use crate::nal::pps::ParamSetId;
+
+    #[test]
+    fn base64() {
+        assert_eq!(decode_base64("").unwrap(), b"");
+        assert_eq!(decode_base64("Zg==").unwrap(), b"f");
+        assert_eq!(decode_base64("Zm8").unwrap(), b"fo");
+        assert_eq!(decode_base64("Zm9v").unwrap(), b"foo");
+        assert_eq!(decode_base64("Zm9vYg==").unwrap(), b"foob");
+        assert!(matches!(decode_base64("Zm9v!"), Err(SpropError::Base64 { offset: 4 })));
+        assert!(matches!(decode_base64("Zm9vY"), Err(SpropError::Base64 { offset: 5 })));
+        for data in &[&b""[..], b"f", b"fo", b"foo", b"foob", b"\xff\xfe\x00"] {
+            assert_eq!(decode_base64(&encode_base64(data)).unwrap(), *data);
+        }
+        assert_eq!(encode_base64(b"foob"), "Zm9vYg==");
+        assert_eq!(encode_base64(b"fo"), "Zm8=");
+    }
+
+    #[test]
+    fn fmtp() {
+        let sps_nal = decode_base64("Z0LAHrkQYf94CIAAAAMAgAAAGXEwBtYA2ve9wHwiEag=").unwrap();
+        let pps_nal = decode_base64("aN48gA==").unwrap();
+        let sps = sps::SeqParameterSet::from_bytes(&crate::rbsp::decode_nal(&sps_nal[1..])).unwrap();
+        let fmtp = FmtpParams::from_param_sets(&sps, &[&sps_nal, &pps_nal], 1);
+        let s = fmtp.to_string();
+        assert_eq!(s, "packetization-mode=1;profile-level-id=42c01e;\
+                       sprop-parameter-sets=Z0LAHrkQYf94CIAAAAMAgAAAGXEwBtYA2ve9wHwiEag=,aN48gA==");
+        assert_eq!(FmtpParams::parse(&s).unwrap(), fmtp);
+
+        let fmtp = FmtpParams::parse("Profile-Level-Id=42F00B; level-asymmetry-allowed=1 ;x").unwrap();
+        let id = fmtp.profile_level_id.unwrap();
+        assert_eq!(id.profile(), sps::Profile::ConstrainedBaseline);
+        assert_eq!(id.level(), sps::Level::L1_b);
+        assert_eq!(fmtp.packetization_mode, None);
+        assert!(matches!(FmtpParams::parse("profile-level-id=42e0"), Err(SpropError::InvalidProfileLevelId)));
+        assert!(matches!(FmtpParams::parse("profile-level-id=+42e01"), Err(SpropError::InvalidProfileLevelId)));
+        assert!(matches!(FmtpParams::parse("packetization-mode=3"), Err(SpropError::InvalidPacketizationMode)));
+    }
+
+    #[test]
+    fn sprop() {
+        let mut ctx = Context::default();
+        // PPS given first, and then an SPS, followed by a set which isn't a parameter set,
+        let results = ctx.put_sprop_parameter_sets("aN48gA==,Z0LAHrkQYf94CIAAAAMAgAAAGXEwBtYA2ve9wHwiEag=,CRA=");
+        assert!(matches!(results[0], Ok(UnitType::PicParameterSet)));
+        assert!(matches!(results[1], Ok(UnitType::SeqParameterSet)));
+        assert!(matches!(results[2], Err(SpropError::UnexpectedUnitType(UnitType::AccessUnitDelimiter))));
+        assert!(ctx.sps_by_id(ParamSetId::from_u32(0).unwrap()).is_some());
+        assert!(ctx.pps_by_id(ParamSetId::from_u32(0).unwrap()).is_some());
+
+        let mut ctx = Context::default();
+        let results = ctx.put_sprop_parameter_sets(" , Z/8=, aN48gA== ");
+        assert!(matches!(results[0], Err(SpropError::Empty)));
+        assert!(matches!(results[1], Err(SpropError::Sps(_))));
+        assert!(matches!(results[2], Err(SpropError::Pps(pps::PpsError::UnknownSeqParamSetId(_)))));
+        assert_eq!(ctx.sps().count(), 0);
+    }
+}